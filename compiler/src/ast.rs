@@ -1,10 +1,12 @@
-#![expect(dead_code, reason = "WIP")]
-
+use core::fmt;
 use std::rc::Rc;
 
 use derive_where::derive_where;
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+use crate::tokens::Position;
+use crate::types::Type;
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum BinaryOperator {
     And,
     Or,
@@ -22,10 +24,58 @@ pub enum BinaryOperator {
     Sub,
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+            BinaryOperator::Xor => "xor",
+            BinaryOperator::Le => "<=",
+            BinaryOperator::Lg => "<",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Ge => ">=",
+            BinaryOperator::Eq => "=",
+            BinaryOperator::Neq => "/=",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Mod => "%",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Identifier {
-    name: String,
-    id: Option<usize>,
+    pub name: String,
+    pub module: Option<String>, // `module.name` refers to a name from an imported module
+    /// What the name refers to once checked, see `types::TypedProgram`
+    pub id: Option<usize>,
+}
+
+impl Identifier {
+    /// The same name resolved to `id`
+    #[must_use]
+    pub fn with_id(&self, id: usize) -> Self {
+        Self {
+            id: Some(id),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            name,
+            module,
+            id: _,
+        } = self;
+        match module {
+            Some(module) => write!(f, "{module}.{name}"),
+            None => write!(f, "{name}"),
+        }
+    }
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -34,6 +84,21 @@ pub struct IntegerLiteral {
     value: i64, // Encloses sign and negation
 }
 
+impl IntegerLiteral {
+    #[must_use]
+    pub fn new(value: i64) -> Self {
+        Self {
+            repr: value.to_string(),
+            value,
+        }
+    }
+
+    #[must_use]
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+}
+
 #[derive(Debug)]
 #[derive_where(Hash, Eq, PartialEq)]
 pub struct RealLiteral {
@@ -42,7 +107,45 @@ pub struct RealLiteral {
     value: f64, // Encloses sign
 }
 
+impl RealLiteral {
+    #[must_use]
+    pub fn new(value: f64) -> Self {
+        Self {
+            repr: format!("{value:?}"),
+            value,
+        }
+    }
+
+    #[must_use]
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq)]
+pub struct StringLiteral {
+    value: String, // Escape sequences are replaced
+}
+
+impl StringLiteral {
+    #[must_use]
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum BoolLiteral {
     True,
     False,
@@ -67,6 +170,7 @@ pub enum Expression {
     IntegerLiteral(IntegerLiteral),
     RealLiteral(RealLiteral),
     BoolLiteral(BoolLiteral),
+    StringLiteral(StringLiteral),
     Call {
         callee: Identifier,
         args: Vec<Rc<Expression>>,
@@ -76,35 +180,166 @@ pub enum Expression {
         lhs: Rc<Expression>,
         rhs: Rc<Expression>,
     },
+    /// `-value` and `not value`, the sign of a literal is a part of it
+    Unop {
+        op: UnaryOperator,
+        value: Rc<Expression>,
+    },
     BoolToInt(Rc<Expression>),
     RealToInt(Rc<Expression>),
     IntToBool(Rc<Expression>), // It cannot be expressed as value != 0, since it shoould panic on value out of [0:1]
+    /// Explicit `t(value)`, `types::Aliases::convertible` lists the allowed ones.
+    /// The parser sees `alias(value)` as a call, the checker turns it into a conversion.
+    Conversion {
+        target: Rc<Type>,
+        value: Rc<Expression>,
+    },
 }
-#[expect(clippy::empty_structs_with_brackets, reason = "WIP")]
-pub struct SimpleDeclaration {
-    // TODO
+/// Text of the `---` comments directly preceding a declaration, one entry per line
+#[derive(Debug, Default, Hash, PartialEq, Eq)]
+pub struct Documentation {
+    lines: Vec<String>,
 }
 
+impl Documentation {
+    pub fn push_line(&mut self, line: &str) {
+        self.lines
+            .push(line.strip_prefix(' ').unwrap_or(line).to_owned());
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    #[must_use]
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+#[derive(Debug)]
+pub struct VariableDeclaration {
+    pub doc: Documentation,
+    /// Where the declaration starts, for diagnostics
+    pub position: Position,
+    pub name: Identifier,
+    pub t: Option<Rc<Type>>,
+    pub initializer: Option<Rc<Expression>>,
+}
+
+#[derive(Debug)]
+pub struct TypeDeclaration {
+    pub doc: Documentation,
+    pub position: Position,
+    pub name: Identifier,
+    pub t: Rc<Type>,
+}
+
+#[derive(Debug)]
+pub enum SimpleDeclaration {
+    Variable(VariableDeclaration),
+    Type(TypeDeclaration),
+}
+
+#[derive(Debug)]
+pub struct Parameter {
+    pub name: Identifier,
+    pub t: Rc<Type>,
+}
+
+#[derive(Debug)]
+pub enum RoutineBody {
+    Block(Block),
+    Expression(Rc<Expression>), // routine f() => expr;
+}
+
+#[derive(Debug)]
+pub struct RoutineDeclaration {
+    pub doc: Documentation,
+    pub position: Position,
+    pub name: Identifier,
+    pub parameters: Vec<Parameter>,
+    pub result: Option<Rc<Type>>,
+    pub body: Option<RoutineBody>, // Forward declarations have no body
+}
+
+/// `import module;` makes `module.name` available
+#[derive(Debug)]
+pub struct ImportDeclaration {
+    pub position: Position,
+    pub module: Identifier,
+}
+
+#[derive(Debug)]
+pub enum Declaration {
+    Import(ImportDeclaration),
+    Simple(SimpleDeclaration),
+    Routine(RoutineDeclaration),
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub declarations: Vec<Declaration>,
+}
+
+#[derive(Debug)]
 pub enum BlockElement {
-    Stmt(Rc<Statement>),
+    /// A statement with where it starts, the declarations know their positions
+    Stmt(Position, Rc<Statement>),
     Decl(Rc<SimpleDeclaration>),
 }
 
+#[derive(Debug, Default)]
 pub struct Block {
     elements: Vec<BlockElement>,
 }
 
+impl Block {
+    #[must_use]
+    pub fn new(elements: Vec<BlockElement>) -> Self {
+        Self { elements }
+    }
+
+    #[must_use]
+    pub fn elements(&self) -> &[BlockElement] {
+        &self.elements
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum LoopOrder {
     Direct,
     Reversed,
 }
 
+/// Constant a `case` branch is taken for
+#[derive(Debug)]
+pub enum CaseLabel {
+    Integer(IntegerLiteral),
+    Variant(Identifier),
+}
+
+/// `label, label is ... end;`
+#[derive(Debug)]
+pub struct CaseBranch {
+    pub labels: Vec<CaseLabel>,
+    pub body: Block,
+}
+
+#[derive(Debug)]
 pub enum Statement {
     Assignment {
-        lhs: Identifier,
+        lhs: Rc<LvalueExpression>,
         rhs: Rc<Expression>,
     },
+    /// `Expression::Call` of a routine for its effect, a result is dropped
+    Call {
+        call: Rc<Expression>,
+    },
+    /// `name: while ... loop ... end;`, the name is optional and is used by `break` and `continue`
     While {
+        label: Option<Identifier>,
         condition: Rc<Expression>,
         body: Block,
     },
@@ -115,6 +350,7 @@ pub enum Statement {
     },
     For {
         // It may be desugared into while
+        label: Option<Identifier>,
         identifier: Identifier,
         from: Rc<Expression>,
         to: Option<Rc<Expression>>,
@@ -124,7 +360,22 @@ pub enum Statement {
     Print {
         value: Rc<Expression>,
     },
-    Return {
+    /// `case value of branch... else ... end;`, labels are checked by `types::case_labels`
+    Case {
         value: Rc<Expression>,
+        branches: Vec<CaseBranch>,
+        otherwise: Option<Block>,
+    },
+    /// Procedures use `return;` without a value
+    Return {
+        value: Option<Rc<Expression>>,
+    },
+    /// Leaves the innermost loop or the enclosing one named `label`, see `flow::check_loop_exits`
+    Break {
+        label: Option<Identifier>,
+    },
+    /// Starts the next iteration of the innermost loop or the enclosing one named `label`
+    Continue {
+        label: Option<Identifier>,
     },
 }
@@ -0,0 +1,269 @@
+//! Prelude: routines implemented natively by the VM and available in every module
+
+use std::rc::Rc;
+
+use crate::types::{ArrayDescription, Type};
+
+#[cfg(test)]
+mod tests;
+
+/// Type of a built-in routine's parameter or result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Int,
+    Real,
+    Bool,
+    Str,
+    /// `integer` or `real`, the same one everywhere in the signature
+    Numeric,
+    /// Any array, the same one everywhere in the signature
+    Array,
+    /// Array of `integer` or `real`
+    NumericArray,
+    /// Element type of the array argument
+    Element,
+    /// Any type, the same one everywhere in the signature
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Sqrt,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Pow,
+    Sin,
+    Cos,
+    Tan,
+    Atan,
+    Exp,
+    Ln,
+    Min,
+    Max,
+    ToReal,    // The same as `Bytecode::IntToReal`
+    ToInteger, // The same as `Bytecode::RealToInt`
+    ToBoolean, // The same as `Bytecode::IntToBool`
+    Fill,
+    Copy, // Deep and of any type, it used to copy arrays only and shallowly
+    Sort,
+    Assert,
+    ReadInteger,
+    ReadReal,
+    ReadBoolean,
+    Length,
+    CharAt, // `s[i]` is sugar for `char_at(s, i)`
+    Equals, // Structural, `=` compares records and arrays by identity
+}
+
+impl Builtin {
+    pub const ALL: &[Builtin] = &[
+        Builtin::Sqrt,
+        Builtin::Abs,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Round,
+        Builtin::Pow,
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Atan,
+        Builtin::Exp,
+        Builtin::Ln,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::ToReal,
+        Builtin::ToInteger,
+        Builtin::ToBoolean,
+        Builtin::Fill,
+        Builtin::Copy,
+        Builtin::Sort,
+        Builtin::Assert,
+        Builtin::ReadInteger,
+        Builtin::ReadReal,
+        Builtin::ReadBoolean,
+        Builtin::Length,
+        Builtin::CharAt,
+        Builtin::Equals,
+    ];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Sqrt => "sqrt",
+            Builtin::Abs => "abs",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Round => "round",
+            Builtin::Pow => "pow",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Atan => "atan",
+            Builtin::Exp => "exp",
+            Builtin::Ln => "ln",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::ToReal => "to_real",
+            Builtin::ToInteger => "to_integer",
+            Builtin::ToBoolean => "to_boolean",
+            Builtin::Fill => "fill",
+            Builtin::Copy => "copy",
+            Builtin::Sort => "sort",
+            Builtin::Assert => "assert",
+            Builtin::ReadInteger => "read_integer",
+            Builtin::ReadReal => "read_real",
+            Builtin::ReadBoolean => "read_boolean",
+            Builtin::Length => "length",
+            Builtin::CharAt => "char_at",
+            Builtin::Equals => "equals",
+        }
+    }
+
+    #[must_use]
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    /// Id of the routine in `Bytecode::CallNative`
+    #[must_use]
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    #[must_use]
+    pub fn from_id(id: u32) -> Option<Builtin> {
+        Self::ALL.get(usize::try_from(id).ok()?).copied()
+    }
+
+    #[must_use]
+    pub fn arity(self) -> usize {
+        self.signature().0.len()
+    }
+
+    /// Parameters and result (if any)
+    fn signature(self) -> (&'static [Shape], Option<Shape>) {
+        match self {
+            Builtin::Sqrt
+            | Builtin::Sin
+            | Builtin::Cos
+            | Builtin::Tan
+            | Builtin::Atan
+            | Builtin::Exp
+            | Builtin::Ln => (&[Shape::Real], Some(Shape::Real)),
+            Builtin::Abs => (&[Shape::Numeric], Some(Shape::Numeric)),
+            Builtin::Floor | Builtin::Ceil | Builtin::Round | Builtin::ToInteger => {
+                (&[Shape::Real], Some(Shape::Int))
+            }
+            Builtin::Pow => (&[Shape::Real, Shape::Real], Some(Shape::Real)),
+            Builtin::Min | Builtin::Max => {
+                (&[Shape::Numeric, Shape::Numeric], Some(Shape::Numeric))
+            }
+            Builtin::ToReal => (&[Shape::Int], Some(Shape::Real)),
+            Builtin::ToBoolean => (&[Shape::Int], Some(Shape::Bool)),
+            Builtin::Fill => (&[Shape::Array, Shape::Element], None),
+            Builtin::Copy => (&[Shape::Any], Some(Shape::Any)),
+            Builtin::Sort => (&[Shape::NumericArray], None),
+            Builtin::Assert => (&[Shape::Bool], None),
+            // Read the next whitespace-separated word of the input
+            Builtin::ReadInteger => (&[], Some(Shape::Int)),
+            Builtin::ReadReal => (&[], Some(Shape::Real)),
+            Builtin::ReadBoolean => (&[], Some(Shape::Bool)),
+            // Strings are indexed by characters, from 1
+            Builtin::Length => (&[Shape::Str], Some(Shape::Int)),
+            Builtin::CharAt => (&[Shape::Str, Shape::Int], Some(Shape::Str)),
+            Builtin::Equals => (&[Shape::Any, Shape::Any], Some(Shape::Bool)),
+        }
+    }
+
+    /// Checks argument types of a call, returns the result type, if any
+    pub fn check_call(self, args: &[Rc<Type>]) -> Result<Option<Rc<Type>>, String> {
+        let (parameters, result) = self.signature();
+        if parameters.len() != args.len() {
+            return Err(format!(
+                "`{}` expects {} arguments, got {}",
+                self.name(),
+                parameters.len(),
+                args.len()
+            ));
+        }
+
+        let mut bindings = Bindings::default();
+        for (i, (&shape, arg)) in parameters.iter().zip(args).enumerate() {
+            if !bindings.unify(shape, arg) {
+                return Err(format!(
+                    "Argument {} of `{}` cannot be `{arg}`",
+                    i + 1,
+                    self.name()
+                ));
+            }
+        }
+        Ok(result.map(|shape| bindings.instantiate(shape)))
+    }
+}
+
+/// Types `Numeric`, `Array` and `Any` stand for in a particular call
+#[derive(Default)]
+struct Bindings {
+    numeric: Option<Rc<Type>>,
+    array: Option<Rc<Type>>,
+    any: Option<Rc<Type>>,
+}
+
+fn bind(binding: &mut Option<Rc<Type>>, t: &Rc<Type>) -> bool {
+    *binding.get_or_insert_with(|| Rc::clone(t)) == *t
+}
+
+impl Bindings {
+    fn unify(&mut self, shape: Shape, t: &Rc<Type>) -> bool {
+        match (shape, &**t) {
+            (Shape::Int, Type::Int)
+            | (Shape::Real, Type::Real)
+            | (Shape::Bool, Type::Bool)
+            | (Shape::Str, Type::String) => true,
+            (Shape::Numeric, Type::Int | Type::Real) => bind(&mut self.numeric, t),
+            (Shape::Array, Type::Array(_)) => bind(&mut self.array, t),
+            (Shape::Any, _) => bind(&mut self.any, t),
+            (Shape::NumericArray, Type::Array(ArrayDescription { t: element, .. })) => {
+                matches!(**element, Type::Int | Type::Real) && bind(&mut self.array, t)
+            }
+            (Shape::Element, _) => match self.array.as_deref() {
+                Some(Type::Array(ArrayDescription { t: element, .. })) => element == t,
+                _ => false,
+            },
+            (
+                Shape::Int
+                | Shape::Real
+                | Shape::Bool
+                | Shape::Str
+                | Shape::Numeric
+                | Shape::Array
+                | Shape::NumericArray,
+                _,
+            ) => false,
+        }
+    }
+
+    /// Only called after all the arguments are unified
+    fn instantiate(&self, shape: Shape) -> Rc<Type> {
+        match shape {
+            Shape::Int => Rc::new(Type::Int),
+            Shape::Real => Rc::new(Type::Real),
+            Shape::Bool => Rc::new(Type::Bool),
+            Shape::Str => Rc::new(Type::String),
+            Shape::Numeric => Rc::clone(self.numeric.as_ref().expect("Bound by an argument")),
+            Shape::Array | Shape::NumericArray => {
+                Rc::clone(self.array.as_ref().expect("Bound by an argument"))
+            }
+            Shape::Element => match self.array.as_deref() {
+                Some(Type::Array(ArrayDescription { t, .. })) => Rc::clone(t),
+                _ => unreachable!("Bound by an argument"),
+            },
+            Shape::Any => Rc::clone(self.any.as_ref().expect("Bound by an argument")),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::*;
+
+fn array_of(t: Type) -> Rc<Type> {
+    Rc::new(Type::Array(ArrayDescription {
+        t: Rc::new(t),
+        size: None,
+        length: None,
+    }))
+}
+
+#[test]
+fn ids_round_trip() {
+    for &builtin in Builtin::ALL {
+        assert_eq!(Builtin::from_id(builtin.id()), Some(builtin));
+        assert_eq!(Builtin::lookup(builtin.name()), Some(builtin));
+    }
+}
+
+#[test]
+fn numeric_result_follows_arguments() {
+    let int = Rc::new(Type::Int);
+    let real = Rc::new(Type::Real);
+    assert_eq!(
+        Builtin::Abs.check_call(&[Rc::clone(&int)]).ok(),
+        Some(Some(Rc::clone(&int)))
+    );
+    assert_eq!(
+        Builtin::Max
+            .check_call(&[Rc::clone(&real), Rc::clone(&real)])
+            .ok(),
+        Some(Some(Rc::clone(&real)))
+    );
+    assert_eq!(Builtin::Max.check_call(&[int, real]).ok(), None);
+}
+
+#[test]
+fn array_utilities() {
+    let reals = array_of(Type::Real);
+    assert_eq!(
+        Builtin::Copy.check_call(&[Rc::clone(&reals)]).ok(),
+        Some(Some(Rc::clone(&reals)))
+    );
+    assert_eq!(
+        Builtin::Fill
+            .check_call(&[Rc::clone(&reals), Rc::new(Type::Real)])
+            .ok(),
+        Some(None)
+    );
+    assert_eq!(
+        Builtin::Fill
+            .check_call(&[Rc::clone(&reals), Rc::new(Type::Bool)])
+            .ok(),
+        None
+    );
+    assert_eq!(Builtin::Sort.check_call(&[reals]).ok(), Some(None));
+    assert_eq!(Builtin::Sort.check_call(&[array_of(Type::Bool)]).ok(), None);
+}
+
+#[test]
+fn input() {
+    assert_eq!(
+        Builtin::ReadReal.check_call(&[]).ok(),
+        Some(Some(Rc::new(Type::Real)))
+    );
+    assert_eq!(
+        Builtin::ReadBoolean.check_call(&[Rc::new(Type::Bool)]).ok(),
+        None
+    );
+}
+
+#[test]
+fn arity() {
+    assert_eq!(Builtin::Pow.arity(), 2);
+    assert_eq!(Builtin::Pow.check_call(&[Rc::new(Type::Real)]).ok(), None);
+}
+
+#[test]
+fn strings() {
+    let string = Rc::new(Type::String);
+    assert_eq!(
+        Builtin::CharAt
+            .check_call(&[Rc::clone(&string), Rc::new(Type::Int)])
+            .ok(),
+        Some(Some(Rc::clone(&string)))
+    );
+    assert_eq!(
+        Builtin::Length.check_call(&[string]).ok(),
+        Some(Some(Rc::new(Type::Int)))
+    );
+    assert_eq!(
+        Builtin::Length.check_call(&[array_of(Type::Int)]).ok(),
+        None
+    );
+}
+
+#[test]
+fn copy_and_equals() {
+    let reals = array_of(Type::Real);
+    assert_eq!(
+        Builtin::Equals
+            .check_call(&[Rc::clone(&reals), Rc::clone(&reals)])
+            .ok(),
+        Some(Some(Rc::new(Type::Bool)))
+    );
+    assert_eq!(
+        Builtin::Equals
+            .check_call(&[Rc::clone(&reals), array_of(Type::Int)])
+            .ok(),
+        None
+    );
+    assert_eq!(
+        Builtin::Copy.check_call(&[Rc::new(Type::String)]).ok(),
+        Some(Some(Rc::new(Type::String)))
+    );
+}
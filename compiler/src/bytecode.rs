@@ -2,16 +2,29 @@
 
 use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
 
+pub mod linker;
+
 ///  Variable location and id
-enum Location {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
     Global(usize),
     Local(usize),
     Argument(usize),
 }
 
-struct TypeId(u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeId(pub u32);
+
+impl TypeId {
+    /// Primitives have the same ids in all modules, see `RTTI::primitives`
+    pub const INTEGER: TypeId = TypeId(0);
+    pub const REAL: TypeId = TypeId(1);
+    pub const BOOLEAN: TypeId = TypeId(2);
+    pub const STRING: TypeId = TypeId(3);
+}
 
-enum Bytecode {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bytecode {
     /// push int / bool onto stack
     IntConst {
         value: i64,
@@ -20,6 +33,10 @@ enum Bytecode {
     RealConst {
         value: f64,
     },
+    /// push a reference to `Module::strings[id]` onto stack
+    StringConst {
+        id: u32,
+    },
     /// push to stack
     Load {
         loc: Location,
@@ -94,6 +111,14 @@ enum Bytecode {
     Call {
         function_label: u64,
     },
+    /// call specified function in place of the running one, returning what it returns
+    TailCall {
+        function_label: u64,
+    },
+    /// call a built-in routine implemented by the VM, see `builtins::Builtin::id`
+    CallNative {
+        id: u32,
+    },
     /// Print a stack top and drop it
     Print {
         type_id: TypeId,
@@ -107,36 +132,131 @@ enum Bytecode {
     IntToReal, // All of it may be just a built-in call
 }
 
-struct RecordRTTI {
-    id: TypeId,
-    field_ids: Vec<TypeId>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordRTTI {
+    pub id: TypeId,
+    pub field_ids: Vec<TypeId>,
 }
 
-struct ArrayRTTI {
-    id: TypeId,
-    element_id: TypeId,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrayRTTI {
+    pub id: TypeId,
+    pub element_id: TypeId,
 }
 
-struct PrimitiveRTTI {
-    id: TypeId,
+/// Values are variant indices, names are kept for printing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumRTTI {
+    pub id: TypeId,
+    pub variants: Vec<String>,
 }
 
-enum RTTIElement {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimitiveRTTI {
+    pub id: TypeId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RTTIElement {
     Record(RecordRTTI),
     Array(ArrayRTTI),
+    Enum(EnumRTTI),
     Primitive(PrimitiveRTTI),
 }
 
-struct RTTI(Vec<RTTIElement>);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RTTI(pub Vec<RTTIElement>);
+
+impl RTTI {
+    /// Every module's RTTI starts with these, other types are numbered after them
+    #[must_use]
+    pub fn primitives() -> Self {
+        RTTI(
+            [
+                TypeId::INTEGER,
+                TypeId::REAL,
+                TypeId::BOOLEAN,
+                TypeId::STRING,
+            ]
+            .into_iter()
+            .map(|id| RTTIElement::Primitive(PrimitiveRTTI { id }))
+            .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn get(&self, TypeId(id): TypeId) -> Option<&RTTIElement> {
+        self.0.get(usize::try_from(id).ok()?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionCode {
+    Label(u64),
+    /// Built-in routine, see `builtins::Builtin::id`
+    Native(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionRecord {
+    pub name: String,
+    pub code: FunctionCode,
+    pub args: Vec<TypeId>,
+    pub result: TypeId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionTable(pub Vec<FunctionRecord>);
+
+/// Routine of a module initializing its globals, the VM runs the ones of every module
+/// before the first routine it calls, see `linker::link_ir` for the other backends
+pub const INIT: &str = "<init>";
 
-struct FunctionRecord {
-    name: String,
-    label_id: i64,
-    args: Vec<TypeId>,
-    result: TypeId,
+/// Routine of another module called from this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternFunction {
+    /// Qualified name, `module.routine`
+    pub name: String,
+    /// Label the module uses in `Call`s to that routine, it is never placed in the module's code
+    pub label_id: u64,
 }
 
-struct FunctionTable(Vec<FunctionRecord>);
+/// Named type or global variable of a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+    Type { name: String, type_id: TypeId },
+    Global { name: String, index: u32 },
+}
+
+impl Symbol {
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Symbol::Type { name, .. } | Symbol::Global { name, .. } => name,
+        }
+    }
+}
+
+/// Separately compiled module, see `linker` for merging these into a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub code: Vec<Bytecode>,
+    /// Label ids are `0..label_count`, including the ones from `externs`
+    pub label_count: u64,
+    pub functions: FunctionTable,
+    pub externs: Vec<ExternFunction>,
+    /// Top-level types and globals other modules may refer to, by their unqualified names
+    pub exports: Vec<Symbol>,
+    /// Types and globals of other modules, by their qualified names, `module.name`,
+    /// with the type ids and global indices this module uses for them
+    pub imports: Vec<Symbol>,
+    /// `TypeId`s are indices in it
+    pub rtti: RTTI,
+    /// String literals, allocated on the heap when the module is loaded
+    pub strings: Vec<String>,
+    pub global_count: u32,
+}
 
 struct MemorySpan {
     offset: u32,
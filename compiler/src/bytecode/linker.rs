@@ -0,0 +1,586 @@
+//! Merges separately compiled `Module`s into a single program
+
+use core::error::Error;
+use core::fmt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{
+    ArrayRTTI, Bytecode, EnumRTTI, FunctionCode, FunctionRecord, FunctionTable, INIT, Location,
+    Module, PrimitiveRTTI, RTTI, RTTIElement, RecordRTTI, Symbol, TypeId,
+};
+use crate::builtins::Builtin;
+use crate::{ir, registers};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    DuplicateModule { module: String },
+    DuplicateSymbol { symbol: String },
+    UndefinedSymbol { symbol: String, module: String },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateModule { module } => {
+                write!(f, "Module `{module}` is linked more than once")
+            }
+            LinkError::DuplicateSymbol { symbol } => {
+                write!(f, "Routine `{symbol}` is defined more than once")
+            }
+            LinkError::UndefinedSymbol { symbol, module } => {
+                write!(f, "`{symbol}` used in module `{module}` is not defined")
+            }
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Where the module's labels, types and globals start in the linked program
+#[derive(Debug, Clone, Copy, Default)]
+struct Offsets {
+    label: u64,
+    /// Added to the module's non-primitive types, primitives are shared by all modules
+    type_id: u32,
+    global: u32,
+    string: u32,
+}
+
+fn primitive_count() -> u32 {
+    u32::try_from(RTTI::primitives().0.len()).expect("There are just a few")
+}
+
+impl Offsets {
+    fn type_id(self, TypeId(id): TypeId) -> TypeId {
+        if id < primitive_count() {
+            TypeId(id)
+        } else {
+            TypeId(id + self.type_id)
+        }
+    }
+}
+
+/// How the code of a module changes in the linked program
+#[derive(Debug, Default)]
+struct Relocation {
+    offsets: Offsets,
+    /// The module's labels of other modules' routines, see `Module::externs`,
+    /// and their linked labels
+    labels: HashMap<u64, u64>,
+    /// The module's ids of other modules' types, see `Module::imports`, and their linked ids
+    type_ids: HashMap<TypeId, TypeId>,
+    /// The same for globals
+    globals: HashMap<usize, usize>,
+    /// The module's ids of host routines and their linked ids, see `Layout::natives`
+    natives: HashMap<u32, u32>,
+}
+
+impl Relocation {
+    fn type_id(&self, id: TypeId) -> TypeId {
+        self.type_ids
+            .get(&id)
+            .copied()
+            .unwrap_or_else(|| self.offsets.type_id(id))
+    }
+
+    fn global(&self, index: usize) -> usize {
+        self.globals
+            .get(&index)
+            .copied()
+            .unwrap_or(index + self.offsets.global as usize)
+    }
+
+    fn native(&self, id: u32) -> u32 {
+        self.natives.get(&id).copied().unwrap_or(id)
+    }
+
+    fn function_label(&self, label: u64) -> u64 {
+        self.labels
+            .get(&label)
+            .copied()
+            .unwrap_or(label + self.offsets.label)
+    }
+
+    /// Types of the arguments and the result, the code is left alone
+    fn signature(&self, function: FunctionRecord) -> FunctionRecord {
+        FunctionRecord {
+            args: function
+                .args
+                .into_iter()
+                .map(|id| self.type_id(id))
+                .collect(),
+            result: self.type_id(function.result),
+            ..function
+        }
+    }
+
+    fn location(&self, loc: Location) -> Location {
+        match loc {
+            Location::Global(index) => Location::Global(self.global(index)),
+            Location::Local(_) | Location::Argument(_) => loc,
+        }
+    }
+
+    /// Types of other modules keep their place, so their own ids are not resolved
+    fn rtti(&self, element: RTTIElement) -> RTTIElement {
+        let offsets = self.offsets;
+        match element {
+            RTTIElement::Record(RecordRTTI { id, field_ids }) => RTTIElement::Record(RecordRTTI {
+                id: offsets.type_id(id),
+                field_ids: field_ids.into_iter().map(|id| self.type_id(id)).collect(),
+            }),
+            RTTIElement::Array(ArrayRTTI { id, element_id }) => RTTIElement::Array(ArrayRTTI {
+                id: offsets.type_id(id),
+                element_id: self.type_id(element_id),
+            }),
+            RTTIElement::Enum(EnumRTTI { id, variants }) => RTTIElement::Enum(EnumRTTI {
+                id: offsets.type_id(id),
+                variants,
+            }),
+            RTTIElement::Primitive(PrimitiveRTTI { id }) => RTTIElement::Primitive(PrimitiveRTTI {
+                id: offsets.type_id(id),
+            }),
+        }
+    }
+
+    fn instruction(&self, instruction: Bytecode) -> Bytecode {
+        let label = |id: u64| id + self.offsets.label;
+        match instruction {
+            Bytecode::Load { loc } => Bytecode::Load {
+                loc: self.location(loc),
+            },
+            Bytecode::Store { loc } => Bytecode::Store {
+                loc: self.location(loc),
+            },
+            Bytecode::AddressOf { loc } => Bytecode::AddressOf {
+                loc: self.location(loc),
+            },
+            Bytecode::AllocRecord { type_id, size } => Bytecode::AllocRecord {
+                type_id: self.type_id(type_id),
+                size,
+            },
+            Bytecode::AllocArray { type_id, size } => Bytecode::AllocArray {
+                type_id: self.type_id(type_id),
+                size,
+            },
+            Bytecode::Print { type_id } => Bytecode::Print {
+                type_id: self.type_id(type_id),
+            },
+            Bytecode::StringConst { id } => Bytecode::StringConst {
+                id: id + self.offsets.string,
+            },
+            Bytecode::Label { id } => Bytecode::Label { id: label(id) },
+            Bytecode::Jump { label: id } => Bytecode::Jump { label: label(id) },
+            Bytecode::JumpZero { label: id } => Bytecode::JumpZero { label: label(id) },
+            Bytecode::JumpNotZero { label: id } => Bytecode::JumpNotZero { label: label(id) },
+            Bytecode::Call { function_label } => Bytecode::Call {
+                function_label: self.function_label(function_label),
+            },
+            Bytecode::TailCall { function_label } => Bytecode::TailCall {
+                function_label: self.function_label(function_label),
+            },
+            Bytecode::CallNative { id } => Bytecode::CallNative {
+                id: self.native(id),
+            },
+            Bytecode::IntConst { .. }
+            | Bytecode::RealConst { .. }
+            | Bytecode::Dup
+            | Bytecode::Drop
+            | Bytecode::Swap
+            | Bytecode::BinOp { .. }
+            | Bytecode::UnOp { .. }
+            | Bytecode::StoreAddress
+            | Bytecode::LoadAddress
+            | Bytecode::ArraySize
+            | Bytecode::ElementAddress
+            | Bytecode::FieldAddress { .. }
+            | Bytecode::Enter { .. }
+            | Bytecode::Ret
+            | Bytecode::Panic { .. }
+            | Bytecode::IntToBool
+            | Bytecode::RealToInt
+            | Bytecode::IntToReal => instruction,
+        }
+    }
+
+    /// Register code counterpart of `instruction`
+    fn register_instruction(&self, instruction: &mut registers::Instruction) {
+        use registers::Instruction;
+
+        match instruction {
+            Instruction::String { id, .. } => *id += self.offsets.string,
+            Instruction::LoadGlobal { index, .. } | Instruction::StoreGlobal { index, .. } => {
+                *index = self.global(*index);
+            }
+            Instruction::AllocRecord { type_id, .. }
+            | Instruction::AllocArray { type_id, .. }
+            | Instruction::Print { type_id, .. } => *type_id = self.type_id(*type_id),
+            Instruction::Call { function_label, .. } => {
+                *function_label = self.function_label(*function_label);
+            }
+            Instruction::CallNative { id, .. } => *id = self.native(*id),
+            Instruction::Int { .. }
+            | Instruction::Real { .. }
+            | Instruction::Move { .. }
+            | Instruction::Binary { .. }
+            | Instruction::Unary { .. }
+            | Instruction::IntToReal { .. }
+            | Instruction::RealToInt { .. }
+            | Instruction::IntToBool { .. }
+            | Instruction::ArraySize { .. }
+            | Instruction::LoadField { .. }
+            | Instruction::StoreField { .. }
+            | Instruction::LoadElement { .. }
+            | Instruction::StoreElement { .. }
+            | Instruction::Jump { .. }
+            | Instruction::JumpZero { .. }
+            | Instruction::Return { .. }
+            | Instruction::Panic { .. } => {}
+        }
+    }
+
+    /// IR counterpart of `instruction`
+    fn operation(&self, operation: &mut ir::Operation) {
+        use ir::Operation;
+
+        match operation {
+            Operation::String { id } => *id += self.offsets.string,
+            Operation::LoadGlobal { index } | Operation::StoreGlobal { index, .. } => {
+                *index = self.global(*index);
+            }
+            Operation::AllocRecord { type_id, .. }
+            | Operation::AllocArray { type_id, .. }
+            | Operation::Print { type_id, .. } => *type_id = self.type_id(*type_id),
+            Operation::Call { function_label, .. } => {
+                *function_label = self.function_label(*function_label);
+            }
+            Operation::CallNative { id, .. } => *id = self.native(*id),
+            Operation::Const(_)
+            | Operation::Param { .. }
+            | Operation::Copy(_)
+            | Operation::Phi(_)
+            | Operation::Binary { .. }
+            | Operation::Unary { .. }
+            | Operation::IntToReal(_)
+            | Operation::RealToInt(_)
+            | Operation::IntToBool(_)
+            | Operation::ArraySize(_)
+            | Operation::LoadField { .. }
+            | Operation::StoreField { .. }
+            | Operation::LoadElement { .. }
+            | Operation::StoreElement { .. } => {}
+        }
+    }
+}
+
+fn qualified_name(module: &str, name: &str) -> String {
+    format!("{module}.{name}")
+}
+
+/// Linked labels, ids and indices of what modules define, by their qualified names
+#[derive(Debug, Default)]
+struct Symbols {
+    routines: HashMap<String, u64>,
+    types: HashMap<String, TypeId>,
+    globals: HashMap<String, usize>,
+}
+
+impl Symbols {
+    /// Resolves what `module` uses from the modules linked before it
+    fn relocation(&self, module: &Module, offsets: Offsets) -> Result<Relocation, LinkError> {
+        let undefined = |symbol: &str| LinkError::UndefinedSymbol {
+            symbol: symbol.to_owned(),
+            module: module.name.clone(),
+        };
+        let mut relocation = Relocation {
+            offsets,
+            ..Relocation::default()
+        };
+        for function in &module.externs {
+            let &label = self
+                .routines
+                .get(&function.name)
+                .ok_or_else(|| undefined(&function.name))?;
+            let _: Option<u64> = relocation.labels.insert(function.label_id, label);
+        }
+        for symbol in &module.imports {
+            match symbol {
+                Symbol::Type { name, type_id } => {
+                    let &linked = self.types.get(name).ok_or_else(|| undefined(name))?;
+                    let _: Option<TypeId> = relocation.type_ids.insert(*type_id, linked);
+                }
+                Symbol::Global { name, index } => {
+                    let &linked = self.globals.get(name).ok_or_else(|| undefined(name))?;
+                    let _: Option<usize> = relocation.globals.insert(*index as usize, linked);
+                }
+            }
+        }
+        Ok(relocation)
+    }
+
+    /// Makes the exports of `module` available to the modules linked after it
+    fn export(&mut self, module: &Module, relocation: &Relocation) {
+        for symbol in &module.exports {
+            let name = qualified_name(&module.name, symbol.name());
+            match *symbol {
+                Symbol::Type { type_id, .. } => {
+                    let _: Option<TypeId> = self.types.insert(name, relocation.type_id(type_id));
+                }
+                Symbol::Global { index, .. } => {
+                    let _: Option<usize> =
+                        self.globals.insert(name, relocation.global(index as usize));
+                }
+            }
+        }
+    }
+}
+
+struct Layout {
+    /// How each module is relocated
+    relocations: Vec<Relocation>,
+    /// Built-ins and host routines by their linked ids, each one is listed once.
+    /// Modules number their host routines after the built-ins, the linked ids are unique.
+    natives: BTreeMap<u32, FunctionRecord>,
+    /// Where the program ends
+    end: Offsets,
+    /// Linked labels of the `INIT` routines, in the order of the modules
+    inits: Vec<u64>,
+    /// Linked label of `main` of the last module
+    main: Option<u64>,
+}
+
+fn layout(modules: &[Module]) -> Result<Layout, LinkError> {
+    let mut names = HashSet::new();
+    let mut symbols = Symbols::default();
+    let mut offsets = Vec::with_capacity(modules.len());
+    let mut next = Offsets::default();
+    let mut inits = Vec::new();
+    let mut natives = BTreeMap::new();
+    let mut hosts = HashMap::new();
+    let mut module_natives = Vec::with_capacity(modules.len());
+
+    // Natives by their linked ids, with the index of the first module declaring them
+    for (index, module) in modules.iter().enumerate() {
+        if !names.insert(module.name.as_str()) {
+            return Err(LinkError::DuplicateModule {
+                module: module.name.clone(),
+            });
+        }
+        let mut ids = HashMap::new();
+        for function in &module.functions.0 {
+            let label = match function.code {
+                FunctionCode::Label(label) => label,
+                // Built-ins are the same in all modules
+                FunctionCode::Native(id) if Builtin::from_id(id).is_some() => {
+                    let _: &mut (usize, FunctionRecord) = natives
+                        .entry(id)
+                        .or_insert_with(|| (index, function.clone()));
+                    continue;
+                }
+                FunctionCode::Native(id) => {
+                    let next_id = u32::try_from(Builtin::ALL.len() + hosts.len())
+                        .expect("No one has 4 billion routines");
+                    let linked = *hosts.entry(function.name.clone()).or_insert(next_id);
+                    let _: &mut (usize, FunctionRecord) =
+                        natives.entry(linked).or_insert_with(|| {
+                            let code = FunctionCode::Native(linked);
+                            (
+                                index,
+                                FunctionRecord {
+                                    code,
+                                    ..function.clone()
+                                },
+                            )
+                        });
+                    let _: Option<u32> = ids.insert(id, linked);
+                    continue;
+                }
+            };
+            if function.name == INIT {
+                inits.push(label + next.label);
+            }
+            let symbol = qualified_name(&module.name, &function.name);
+            if symbols
+                .routines
+                .insert(symbol.clone(), label + next.label)
+                .is_some()
+            {
+                return Err(LinkError::DuplicateSymbol { symbol });
+            }
+        }
+
+        offsets.push(next);
+        module_natives.push(ids);
+        next = Offsets {
+            label: next.label + module.label_count,
+            type_id: next.type_id
+                + u32::try_from(module.rtti.0.len()).expect("No one has 4 billion types")
+                - primitive_count(),
+            global: next.global + module.global_count,
+            string: next.string
+                + u32::try_from(module.strings.len()).expect("No one has 4 billion literals"),
+        };
+    }
+
+    // Modules only use the types and globals of the ones they import, which come first
+    let mut relocations = Vec::with_capacity(modules.len());
+    for ((module, offsets), natives) in modules.iter().zip(offsets).zip(module_natives) {
+        let relocation = Relocation {
+            natives,
+            ..symbols.relocation(module, offsets)?
+        };
+        symbols.export(module, &relocation);
+        relocations.push(relocation);
+    }
+    // Host routines may take and return types of the module declaring them
+    let natives = natives
+        .into_iter()
+        .map(|(id, (index, function))| (id, relocations[index].signature(function)))
+        .collect();
+    let main = modules
+        .last()
+        .and_then(|module| symbols.routines.get(&qualified_name(&module.name, "main")))
+        .copied();
+    Ok(Layout {
+        relocations,
+        natives,
+        end: next,
+        inits,
+        main,
+    })
+}
+
+/// Modules must be ordered so that every module comes after the ones it imports,
+/// the linked program is named after the last one. The `INIT` routines of the modules
+/// are in its function table in the same order, the VM runs them.
+///
+/// # Panics
+///
+/// If the program has more than `u32::MAX` types or string literals
+pub fn link(modules: Vec<Module>) -> Result<Module, LinkError> {
+    let Layout {
+        relocations,
+        natives,
+        end: next,
+        ..
+    } = layout(&modules)?;
+
+    let name = modules
+        .last()
+        .map(|module| module.name.clone())
+        .unwrap_or_default();
+    let rtti_primitives = RTTI::primitives().0.len();
+    let mut code = Vec::new();
+    let mut functions = Vec::new();
+    let mut rtti = RTTI::primitives().0;
+    let mut strings = Vec::new();
+
+    for (module, relocation) in modules.into_iter().zip(relocations) {
+        code.extend(
+            module
+                .code
+                .into_iter()
+                .map(|instruction| relocation.instruction(instruction)),
+        );
+        functions.extend(module.functions.0.into_iter().filter_map(|function| {
+            let FunctionCode::Label(label) = function.code else {
+                return None;
+            };
+            Some(relocation.signature(FunctionRecord {
+                name: qualified_name(&module.name, &function.name),
+                code: FunctionCode::Label(label + relocation.offsets.label),
+                ..function
+            }))
+        }));
+        rtti.extend(
+            module
+                .rtti
+                .0
+                .into_iter()
+                .skip(rtti_primitives)
+                .map(|element| relocation.rtti(element)),
+        );
+        strings.extend(module.strings);
+    }
+    functions.extend(natives.into_values());
+
+    Ok(Module {
+        name,
+        code,
+        label_count: next.label,
+        functions: FunctionTable(functions),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti: RTTI(rtti),
+        strings,
+        global_count: next.global,
+    })
+}
+
+/// Relocates the register code compiled along with each of `modules` the way `link` relocates
+/// their bytecode, so that it runs with the linked module, which has the `INIT` routines
+///
+/// # Panics
+///
+/// If the program has more than `u32::MAX` types or string literals
+pub fn link_registers(
+    modules: &[Module],
+    code: Vec<registers::Code>,
+) -> Result<registers::Code, LinkError> {
+    let Layout { relocations, .. } = layout(modules)?;
+    let mut routines = BTreeMap::new();
+    for (relocation, code) in relocations.iter().zip(code) {
+        for (label, mut routine) in code.0 {
+            for instruction in &mut routine.code {
+                relocation.register_instruction(instruction);
+            }
+            drop(routines.insert(label + relocation.offsets.label, routine));
+        }
+    }
+    Ok(registers::Code(routines))
+}
+
+/// Relocates the routines built along with each of `modules`, see `ir::build`,
+/// the way `link` relocates their bytecode. Without a VM to run the `INIT` routines,
+/// `main` of the last module starts with calls to them.
+///
+/// # Panics
+///
+/// If the program has more than `u32::MAX` types or string literals
+pub fn link_ir(
+    modules: &[Module],
+    routines: Vec<BTreeMap<u64, ir::Function>>,
+) -> Result<BTreeMap<u64, ir::Function>, LinkError> {
+    let Layout {
+        relocations,
+        inits,
+        main,
+        ..
+    } = layout(modules)?;
+    let mut linked = BTreeMap::new();
+    for (relocation, routines) in relocations.iter().zip(routines) {
+        for (label, mut function) in routines {
+            for block in &mut function.blocks {
+                for instruction in &mut block.instructions {
+                    relocation.operation(&mut instruction.operation);
+                }
+            }
+            drop(linked.insert(label + relocation.offsets.label, function));
+        }
+    }
+    if let Some(main) = main.and_then(|main| linked.get_mut(&main)) {
+        let calls = inits.iter().map(|&function_label| ir::Instruction {
+            result: None,
+            operation: ir::Operation::Call {
+                function_label,
+                args: Vec::new(),
+            },
+        });
+        drop(main.blocks[0].instructions.splice(0..0, calls));
+    }
+    Ok(linked)
+}
@@ -0,0 +1,537 @@
+use super::*;
+use crate::bytecode::{ExternFunction, INIT, Symbol};
+use crate::operators::SemanticBinaryOperator;
+
+fn with_primitives(elements: impl IntoIterator<Item = RTTIElement>) -> RTTI {
+    let mut rtti = RTTI::primitives();
+    rtti.0.extend(elements);
+    rtti
+}
+
+fn color(id: TypeId) -> RTTIElement {
+    RTTIElement::Enum(EnumRTTI {
+        id,
+        variants: vec!["red".to_owned(), "green".to_owned()],
+    })
+}
+
+/// `type point is record var x : real; var y : real; end;`
+/// `routine square(x : real) : real => x * x;`, with a global counting the calls
+fn geometry() -> Module {
+    Module {
+        name: "geometry".to_owned(),
+        code: vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 1, locals: 0 },
+            Bytecode::Load {
+                loc: Location::Global(0),
+            },
+            Bytecode::IntConst { value: 1 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntAdd,
+            },
+            Bytecode::Store {
+                loc: Location::Global(0),
+            },
+            Bytecode::Load {
+                loc: Location::Argument(0),
+            },
+            Bytecode::Dup,
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::RealMul,
+            },
+            Bytecode::Ret,
+        ],
+        label_count: 1,
+        functions: FunctionTable(vec![FunctionRecord {
+            name: "square".to_owned(),
+            code: FunctionCode::Label(0),
+            args: vec![TypeId::REAL],
+            result: TypeId::REAL,
+        }]),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti: with_primitives([RTTIElement::Record(RecordRTTI {
+            id: TypeId(4),
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        })]),
+        // Literals are kept even when no code refers to them
+        strings: vec!["unused".to_owned()],
+        global_count: 1,
+    }
+}
+
+/// `type color is enum red, green end;`
+/// `routine main() is var x is 2.0; print "x * x =", geometry.square(x); var a : array [2] integer; end;`
+fn main() -> Module {
+    Module {
+        name: "main".to_owned(),
+        code: vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 0, locals: 0 },
+            Bytecode::RealConst { value: 2.0 },
+            Bytecode::Store {
+                loc: Location::Global(0),
+            },
+            Bytecode::Load {
+                loc: Location::Global(0),
+            },
+            Bytecode::StringConst { id: 0 },
+            Bytecode::Print {
+                type_id: TypeId::STRING,
+            },
+            Bytecode::Call { function_label: 1 },
+            Bytecode::Print {
+                type_id: TypeId::REAL,
+            },
+            Bytecode::AllocArray {
+                type_id: TypeId(4),
+                size: 2,
+            },
+            Bytecode::Drop,
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Ret,
+        ],
+        label_count: 2,
+        functions: FunctionTable(vec![
+            FunctionRecord {
+                name: "main".to_owned(),
+                code: FunctionCode::Label(0),
+                args: Vec::new(),
+                result: TypeId::INTEGER,
+            },
+            FunctionRecord {
+                name: "sqrt".to_owned(),
+                code: FunctionCode::Native(0),
+                args: vec![TypeId::REAL],
+                result: TypeId::REAL,
+            },
+        ]),
+        externs: vec![ExternFunction {
+            name: "geometry.square".to_owned(),
+            label_id: 1,
+        }],
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti: with_primitives([
+            RTTIElement::Array(ArrayRTTI {
+                id: TypeId(4),
+                element_id: TypeId::INTEGER,
+            }),
+            color(TypeId(5)),
+        ]),
+        strings: vec!["x * x =".to_owned()],
+        global_count: 1,
+    }
+}
+
+#[test]
+fn relocates_modules() {
+    let linked = link(vec![geometry(), main()]).expect("Both modules are fine");
+
+    assert_eq!(linked.name, "main");
+    assert_eq!(linked.label_count, 3);
+    assert_eq!(linked.global_count, 2);
+    assert!(linked.externs.is_empty());
+    assert_eq!(linked.strings, ["unused", "x * x ="]);
+    assert_eq!(
+        linked.functions,
+        FunctionTable(vec![
+            FunctionRecord {
+                name: "geometry.square".to_owned(),
+                code: FunctionCode::Label(0),
+                args: vec![TypeId::REAL],
+                result: TypeId::REAL,
+            },
+            FunctionRecord {
+                name: "main.main".to_owned(),
+                code: FunctionCode::Label(1),
+                args: Vec::new(),
+                result: TypeId::INTEGER,
+            },
+            FunctionRecord {
+                name: "sqrt".to_owned(),
+                code: FunctionCode::Native(0),
+                args: vec![TypeId::REAL],
+                result: TypeId::REAL,
+            },
+        ])
+    );
+    assert_eq!(
+        linked.rtti,
+        RTTI(
+            RTTI::primitives()
+                .0
+                .into_iter()
+                .chain([
+                    RTTIElement::Record(RecordRTTI {
+                        id: TypeId(4),
+                        field_ids: vec![TypeId::REAL, TypeId::REAL],
+                    }),
+                    RTTIElement::Array(ArrayRTTI {
+                        id: TypeId(5),
+                        element_id: TypeId::INTEGER,
+                    }),
+                    color(TypeId(6)),
+                ])
+                .collect()
+        )
+    );
+    assert_eq!(linked.code[..10], geometry().code[..]);
+    assert_eq!(
+        linked.code[10..],
+        [
+            Bytecode::Label { id: 1 },
+            Bytecode::Enter { args: 0, locals: 0 },
+            Bytecode::RealConst { value: 2.0 },
+            Bytecode::Store {
+                loc: Location::Global(1),
+            },
+            Bytecode::Load {
+                loc: Location::Global(1),
+            },
+            Bytecode::StringConst { id: 1 },
+            Bytecode::Print {
+                type_id: TypeId::STRING,
+            },
+            Bytecode::Call { function_label: 0 },
+            Bytecode::Print {
+                type_id: TypeId::REAL,
+            },
+            Bytecode::AllocArray {
+                type_id: TypeId(5),
+                size: 2,
+            },
+            Bytecode::Drop,
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Ret,
+        ]
+    );
+}
+
+#[test]
+fn relocates_routines() {
+    use ir::{FunctionBuilder, IrType, Operation, Terminator};
+
+    // The start of `main` from `main()`
+    let mut builder = FunctionBuilder::new("main", Vec::new(), None);
+    let x = builder.value(IrType::Real, Operation::LoadGlobal { index: 0 });
+    let label = builder.value(IrType::Ref, Operation::String { id: 0 });
+    builder.effect(Operation::Print {
+        type_id: TypeId::STRING,
+        value: label,
+    });
+    let square = builder.value(
+        IrType::Real,
+        Operation::Call {
+            function_label: 1,
+            args: vec![x],
+        },
+    );
+    builder.effect(Operation::Print {
+        type_id: TypeId::REAL,
+        value: square,
+    });
+    builder.effect(Operation::AllocArray {
+        type_id: TypeId(4),
+        size: 2,
+    });
+    builder.terminate(Terminator::Return(None));
+
+    let linked = link_ir(
+        &[geometry(), main()],
+        vec![BTreeMap::new(), BTreeMap::from([(0, builder.finish())])],
+    )
+    .expect("Both modules are fine");
+    assert_eq!(linked.keys().collect::<Vec<_>>(), [&1]);
+    let operations: Vec<_> = linked[&1].blocks[0]
+        .instructions
+        .iter()
+        .map(|instruction| &instruction.operation)
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            &Operation::LoadGlobal { index: 1 },
+            &Operation::String { id: 1 },
+            &Operation::Print {
+                type_id: TypeId::STRING,
+                value: label,
+            },
+            &Operation::Call {
+                function_label: 0,
+                args: vec![x],
+            },
+            &Operation::Print {
+                type_id: TypeId::REAL,
+                value: square,
+            },
+            &Operation::AllocArray {
+                type_id: TypeId(5),
+                size: 2,
+            },
+        ]
+    );
+}
+
+#[test]
+fn undefined_symbol() {
+    assert_eq!(
+        link(vec![main()]),
+        Err(LinkError::UndefinedSymbol {
+            symbol: "geometry.square".to_owned(),
+            module: "main".to_owned(),
+        })
+    );
+}
+
+#[test]
+fn duplicate_module() {
+    assert_eq!(
+        link(vec![geometry(), geometry(), main()]),
+        Err(LinkError::DuplicateModule {
+            module: "geometry".to_owned(),
+        })
+    );
+}
+
+/// `geometry()` exporting `point` and its global, `calls`, which `INIT` initializes
+fn geometry_with_exports() -> Module {
+    let mut geometry = geometry();
+    geometry.code.extend([
+        Bytecode::Label { id: 1 },
+        Bytecode::Enter { args: 0, locals: 0 },
+        Bytecode::IntConst { value: 0 },
+        Bytecode::Ret,
+    ]);
+    geometry.label_count = 2;
+    geometry.functions.0.push(FunctionRecord {
+        name: INIT.to_owned(),
+        code: FunctionCode::Label(1),
+        args: Vec::new(),
+        result: TypeId::INTEGER,
+    });
+    geometry.exports = vec![
+        Symbol::Type {
+            name: "point".to_owned(),
+            type_id: TypeId(4),
+        },
+        Symbol::Global {
+            name: "calls".to_owned(),
+            index: 0,
+        },
+    ];
+    geometry
+}
+
+/// `routine main() is print geometry.calls; var p : geometry.point; end;`
+fn importer() -> Module {
+    Module {
+        name: "main".to_owned(),
+        code: vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 0, locals: 1 },
+            Bytecode::Load {
+                loc: Location::Global(0),
+            },
+            Bytecode::Print {
+                type_id: TypeId::INTEGER,
+            },
+            Bytecode::AllocRecord {
+                type_id: TypeId(4),
+                size: 2,
+            },
+            Bytecode::Store {
+                loc: Location::Local(0),
+            },
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Ret,
+        ],
+        label_count: 1,
+        functions: FunctionTable(vec![FunctionRecord {
+            name: "main".to_owned(),
+            code: FunctionCode::Label(0),
+            args: Vec::new(),
+            result: TypeId::INTEGER,
+        }]),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: vec![
+            Symbol::Type {
+                name: "geometry.point".to_owned(),
+                type_id: TypeId(4),
+            },
+            Symbol::Global {
+                name: "geometry.calls".to_owned(),
+                index: 0,
+            },
+        ],
+        // Its own copy of `geometry.point`
+        rtti: with_primitives([RTTIElement::Record(RecordRTTI {
+            id: TypeId(4),
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        })]),
+        strings: Vec::new(),
+        global_count: 1,
+    }
+}
+
+#[test]
+fn resolves_types_and_globals() {
+    let linked = link(vec![geometry_with_exports(), importer()]).expect("Both modules are fine");
+
+    assert_eq!(linked.global_count, 2);
+    assert_eq!(
+        linked.rtti.get(TypeId(5)),
+        Some(&RTTIElement::Record(RecordRTTI {
+            id: TypeId(5),
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        }))
+    );
+    assert_eq!(
+        linked.code[14..],
+        [
+            Bytecode::Label { id: 2 },
+            Bytecode::Enter { args: 0, locals: 1 },
+            Bytecode::Load {
+                loc: Location::Global(0),
+            },
+            Bytecode::Print {
+                type_id: TypeId::INTEGER,
+            },
+            Bytecode::AllocRecord {
+                type_id: TypeId(4),
+                size: 2,
+            },
+            Bytecode::Store {
+                loc: Location::Local(0),
+            },
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Ret,
+        ]
+    );
+    // The VM runs `INIT` of `geometry` before `main`
+    assert!(linked.functions.0.iter().any(|function| {
+        function.name == format!("geometry.{INIT}") && function.code == FunctionCode::Label(1)
+    }));
+    assert_eq!(
+        link(vec![geometry(), importer()]),
+        Err(LinkError::UndefinedSymbol {
+            symbol: "geometry.point".to_owned(),
+            module: "main".to_owned(),
+        })
+    );
+}
+
+/// A module named `name` whose `main` calls the natives it lists
+fn calling_natives(name: &str, natives: &[(&str, u32)]) -> Module {
+    let mut code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 0, locals: 0 },
+    ];
+    code.extend(natives.iter().flat_map(|&(_, id)| {
+        [
+            Bytecode::RealConst { value: 4.0 },
+            Bytecode::CallNative { id },
+            Bytecode::Drop,
+        ]
+    }));
+    code.extend([Bytecode::IntConst { value: 0 }, Bytecode::Ret]);
+    let mut functions = vec![FunctionRecord {
+        name: if name == "main" { "main" } else { "run" }.to_owned(),
+        code: FunctionCode::Label(0),
+        args: Vec::new(),
+        result: TypeId::INTEGER,
+    }];
+    functions.extend(natives.iter().map(|&(native, id)| FunctionRecord {
+        name: native.to_owned(),
+        code: FunctionCode::Native(id),
+        args: vec![TypeId::REAL],
+        result: TypeId::REAL,
+    }));
+    Module {
+        name: name.to_owned(),
+        code,
+        label_count: 1,
+        functions: FunctionTable(functions),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti: RTTI::primitives(),
+        strings: Vec::new(),
+        global_count: 0,
+    }
+}
+
+#[test]
+fn lists_natives_once() {
+    let host = u32::try_from(Builtin::ALL.len()).expect("A few built-ins");
+    let linked = link(vec![
+        calling_natives("numbers", &[("sqrt", 0), ("cube_root", host)]),
+        calling_natives(
+            "main",
+            &[("sqrt", 0), ("log", host), ("cube_root", host + 1)],
+        ),
+    ])
+    .expect("Both modules are fine");
+
+    let natives: Vec<_> = linked
+        .functions
+        .0
+        .iter()
+        .filter_map(|function| match function.code {
+            FunctionCode::Native(id) => Some((function.name.as_str(), id)),
+            FunctionCode::Label(_) => None,
+        })
+        .collect();
+    assert_eq!(
+        natives,
+        [("sqrt", 0), ("cube_root", host), ("log", host + 1)]
+    );
+    let calls: Vec<_> = linked
+        .code
+        .iter()
+        .filter_map(|instruction| {
+            if let Bytecode::CallNative { id } = *instruction {
+                Some(id)
+            } else {
+                None
+            }
+        })
+        .collect();
+    assert_eq!(calls, [0, host, 0, host + 1, host]);
+}
+
+/// `type shape is record var sides : integer; end;` and `routine draw(s : shape);`
+/// from the host, after `geometry()`
+#[test]
+fn relocates_host_signatures() {
+    let mut drawing = calling_natives("main", &[]);
+    drawing.rtti = with_primitives([RTTIElement::Record(RecordRTTI {
+        id: TypeId(4),
+        field_ids: vec![TypeId::INTEGER],
+    })]);
+    drawing.functions.0.push(FunctionRecord {
+        name: "draw".to_owned(),
+        code: FunctionCode::Native(u32::try_from(Builtin::ALL.len()).expect("A few built-ins")),
+        args: vec![TypeId(4)],
+        result: TypeId::INTEGER,
+    });
+    let linked = link(vec![geometry(), drawing]).expect("Both modules are fine");
+
+    let draw = linked
+        .functions
+        .0
+        .iter()
+        .find(|function| function.name == "draw")
+        .expect("Host routines are listed");
+    assert_eq!(draw.args, [TypeId(5)]);
+    assert_eq!(
+        linked.rtti.get(TypeId(5)),
+        Some(&RTTIElement::Record(RecordRTTI {
+            id: TypeId(5),
+            field_ids: vec![TypeId::INTEGER],
+        }))
+    );
+}
@@ -0,0 +1,746 @@
+//! Ahead-of-time translation of a linked program to C, for the system C compiler.
+//!
+//! Records become structs with a member per field, arrays and strings are heap blocks starting
+//! with their length. Every object starts with its `TypeId`, which the runtime in `cgen/runtime.c`
+//! uses for printing, `copy` and `equals`. Values of the IR become C variables,
+//! phis get another variable assigned on the edges to their block, like `registers::lower` does.
+
+use core::error::Error;
+use core::fmt::{self, Write as _};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::builtins::Builtin;
+use crate::bytecode::{
+    ArrayRTTI, EnumRTTI, FunctionCode, FunctionRecord, Module, PrimitiveRTTI, RTTIElement,
+    RecordRTTI, TypeId,
+};
+use crate::consteval::Constant;
+use crate::ir::{self, BlockId, IrType, Operation, Terminator, ValueId};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+#[cfg(test)]
+mod tests;
+
+/// Types of the runtime, the generated ones use them
+pub(crate) const RUNTIME_TYPES: &str = include_str!("cgen/runtime.h");
+/// Functions of the runtime, they use the generated `types`
+pub(crate) const RUNTIME: &str = include_str!("cgen/runtime.c");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgenError {
+    NoMain,
+    /// Host functions are registered with the `vm` crate, native code has none of them
+    HostFunction {
+        id: u32,
+    },
+}
+
+impl fmt::Display for CgenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgenError::NoMain => write!(f, "The program has no `main` routine"),
+            CgenError::HostFunction { id } => {
+                write!(f, "Host function {id} is not available in native code")
+            }
+        }
+    }
+}
+
+impl Error for CgenError {}
+
+fn c_type(t: IrType) -> &'static str {
+    match t {
+        IrType::Int | IrType::Bool => "int64_t",
+        IrType::Real => "double",
+        IrType::Ref => "void *",
+    }
+}
+
+/// Member of the runtime's `value` union holding values of type `t`
+fn member(t: IrType) -> char {
+    match t {
+        IrType::Int | IrType::Bool => 'i',
+        IrType::Real => 'r',
+        IrType::Ref => 'p',
+    }
+}
+
+fn field_type(module: &Module, id: TypeId) -> &'static str {
+    match module.rtti.get(id) {
+        Some(RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::REAL })) => "double",
+        Some(
+            RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::STRING })
+            | RTTIElement::Record(_)
+            | RTTIElement::Array(_),
+        ) => "void *",
+        Some(RTTIElement::Primitive(_) | RTTIElement::Enum(_)) | None => "int64_t",
+    }
+}
+
+/// Escapes everything but printable ASCII, `?` too so that there are no trigraphs
+fn literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(char::from(byte));
+            }
+            b' '..=b'~' => literal.push(char::from(byte)),
+            _ => write!(literal, "\\{byte:03o}").expect("Writing to a string won't fail"),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn real(value: f64) -> String {
+    if value.is_nan() {
+        "NAN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "INFINITY" } else { "-INFINITY" }.to_owned()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+fn int(value: i64) -> String {
+    if value == i64::MIN {
+        "INT64_MIN".to_owned()
+    } else {
+        format!("INT64_C({value})")
+    }
+}
+
+fn value(id: ValueId) -> String {
+    format!("v{}", id.0)
+}
+
+pub(crate) fn phis(block: &ir::Block) -> impl Iterator<Item = (ValueId, &[(BlockId, ValueId)])> {
+    block.instructions.iter().filter_map(|instruction| {
+        if let (Some((result, _)), Operation::Phi(incoming)) =
+            (instruction.result, &instruction.operation)
+        {
+            Some((result, incoming.as_slice()))
+        } else {
+            None
+        }
+    })
+}
+
+/// Linked names are qualified by their module
+fn unqualified<'a>(module: &str, name: &'a str) -> &'a str {
+    name.strip_prefix(module)
+        .and_then(|name| name.strip_prefix('.'))
+        .unwrap_or(name)
+}
+
+/// Routine records by their label
+fn signatures(module: &Module) -> HashMap<u64, &FunctionRecord> {
+    module
+        .functions
+        .0
+        .iter()
+        .filter_map(|function| match function.code {
+            FunctionCode::Label(label) => Some((label, function)),
+            FunctionCode::Native(_) => None,
+        })
+        .collect()
+}
+
+/// Types of the values referring to objects, by routine. The IR only knows they are references,
+/// fields of records are accessed through the struct of their type.
+fn object_types(
+    module: &Module,
+    routines: &BTreeMap<u64, ir::Function>,
+) -> HashMap<u64, HashMap<ValueId, TypeId>> {
+    let signatures = signatures(module);
+    let field = |record: TypeId, offset: u64| match module.rtti.get(record) {
+        Some(RTTIElement::Record(RecordRTTI { field_ids, .. })) => {
+            field_ids.get(usize::try_from(offset).ok()?).copied()
+        }
+        Some(RTTIElement::Array(_) | RTTIElement::Enum(_) | RTTIElement::Primitive(_)) | None => {
+            None
+        }
+    };
+    let element = |array: TypeId| match module.rtti.get(array) {
+        Some(&RTTIElement::Array(ArrayRTTI { element_id, .. })) => Some(element_id),
+        Some(RTTIElement::Record(_) | RTTIElement::Enum(_) | RTTIElement::Primitive(_)) | None => {
+            None
+        }
+    };
+    let mut globals = HashMap::new();
+    let mut types: HashMap<_, HashMap<_, _>> = routines
+        .keys()
+        .map(|&label| (label, HashMap::new()))
+        .collect();
+
+    // Values get their types from earlier ones, globals and phis from later ones too
+    loop {
+        let mut changed = false;
+        for (&label, function) in routines {
+            let values = types.get_mut(&label).expect("Every routine has types");
+            for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+                if let Operation::StoreGlobal { index, value } = instruction.operation
+                    && let Some(&t) = values.get(&value)
+                {
+                    changed |= globals.insert(index, t) != Some(t);
+                }
+                let Some((result, IrType::Ref)) = instruction.result else {
+                    continue;
+                };
+                if values.contains_key(&result) {
+                    continue;
+                }
+                let found = match instruction.operation {
+                    Operation::String { .. }
+                    | Operation::Binary {
+                        op: SemanticBinaryOperator::StringConcat,
+                        ..
+                    } => Some(TypeId::STRING),
+                    Operation::Param { index } => signatures
+                        .get(&label)
+                        .and_then(|function| function.args.get(index))
+                        .copied(),
+                    Operation::Copy(value) => values.get(&value).copied(),
+                    Operation::Phi(ref incoming) => incoming
+                        .iter()
+                        .find_map(|(_, value)| values.get(value))
+                        .copied(),
+                    Operation::AllocRecord { type_id, .. }
+                    | Operation::AllocArray { type_id, .. } => Some(type_id),
+                    Operation::LoadField { record, offset } => values
+                        .get(&record)
+                        .and_then(|&record| field(record, offset)),
+                    Operation::LoadElement { array, .. } => {
+                        values.get(&array).and_then(|&array| element(array))
+                    }
+                    Operation::Call { function_label, .. } => signatures
+                        .get(&function_label)
+                        .map(|function| function.result),
+                    Operation::LoadGlobal { index } => globals.get(&index).copied(),
+                    Operation::CallNative { id, ref args } => match Builtin::from_id(id) {
+                        Some(Builtin::Copy) => {
+                            args.first().and_then(|arg| values.get(arg)).copied()
+                        }
+                        Some(Builtin::CharAt) => Some(TypeId::STRING),
+                        _ => None,
+                    },
+                    Operation::Const(_)
+                    | Operation::Binary { .. }
+                    | Operation::Unary { .. }
+                    | Operation::IntToReal(_)
+                    | Operation::RealToInt(_)
+                    | Operation::IntToBool(_)
+                    | Operation::StoreGlobal { .. }
+                    | Operation::ArraySize(_)
+                    | Operation::StoreField { .. }
+                    | Operation::StoreElement { .. }
+                    | Operation::Print { .. } => None,
+                };
+                if let Some(t) = found {
+                    let _: Option<TypeId> = values.insert(result, t);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return types;
+        }
+    }
+}
+
+struct Translation<'a> {
+    module: &'a Module,
+    routines: &'a BTreeMap<u64, ir::Function>,
+    objects: HashMap<u64, HashMap<ValueId, TypeId>>,
+    out: String,
+}
+
+impl Translation<'_> {
+    /// Structs of the records and the table of all types for the runtime
+    fn types(&mut self) -> fmt::Result {
+        let mut table = Vec::with_capacity(self.module.rtti.0.len());
+        for (id, element) in self.module.rtti.0.iter().enumerate() {
+            let entry = match element {
+                RTTIElement::Primitive(PrimitiveRTTI { id }) => match *id {
+                    TypeId::REAL => "{.kind = KIND_REAL}".to_owned(),
+                    TypeId::BOOLEAN => "{.kind = KIND_BOOLEAN}".to_owned(),
+                    TypeId::STRING => "{.kind = KIND_STRING}".to_owned(),
+                    _ => "{.kind = KIND_INTEGER}".to_owned(),
+                },
+                RTTIElement::Enum(EnumRTTI { variants, .. }) => {
+                    let variants: Vec<_> =
+                        variants.iter().map(|variant| literal(variant)).collect();
+                    writeln!(
+                        self.out,
+                        "static const char *const variants_{id}[] = {{{}}};",
+                        variants.join(", ")
+                    )?;
+                    format!(
+                        "{{.kind = KIND_ENUM, .variant_count = {}, .variants = variants_{id}}}",
+                        variants.len()
+                    )
+                }
+                RTTIElement::Record(RecordRTTI { field_ids, .. }) => {
+                    writeln!(self.out, "struct record_{id} {{")?;
+                    writeln!(self.out, "    struct object header;")?;
+                    for (offset, &field) in field_ids.iter().enumerate() {
+                        writeln!(
+                            self.out,
+                            "    {} f{offset};",
+                            field_type(self.module, field)
+                        )?;
+                    }
+                    writeln!(self.out, "}};")?;
+                    let fields = if field_ids.is_empty() {
+                        "NULL".to_owned()
+                    } else {
+                        let fields: Vec<_> = field_ids
+                            .iter()
+                            .enumerate()
+                            .map(|(offset, field)| {
+                                format!("{{offsetof(struct record_{id}, f{offset}), {}}}", field.0)
+                            })
+                            .collect();
+                        writeln!(
+                            self.out,
+                            "static const struct field fields_{id}[] = {{{}}};",
+                            fields.join(", ")
+                        )?;
+                        format!("fields_{id}")
+                    };
+                    format!(
+                        "{{.kind = KIND_RECORD, .size = sizeof(struct record_{id}), .field_count = {}, .fields = {fields}}}",
+                        field_ids.len()
+                    )
+                }
+                RTTIElement::Array(ArrayRTTI { element_id, .. }) => {
+                    format!("{{.kind = KIND_ARRAY, .element = {}}}", element_id.0)
+                }
+            };
+            table.push(entry);
+        }
+        writeln!(self.out, "static const struct type types[] = {{")?;
+        for entry in table {
+            writeln!(self.out, "    {entry},")?;
+        }
+        writeln!(self.out, "}};")?;
+        writeln!(
+            self.out,
+            "static value globals[{}];",
+            self.module.global_count.max(1)
+        )?;
+        writeln!(
+            self.out,
+            "static void *strings[{}];",
+            self.module.strings.len().max(1)
+        )
+    }
+
+    fn prototype(label: u64, function: &ir::Function) -> String {
+        let args: Vec<_> = function
+            .args
+            .iter()
+            .enumerate()
+            .map(|(index, &t)| format!("{} a{index}", c_type(t)))
+            .collect();
+        format!(
+            "static {} r{label}({})",
+            function.result.map_or("void", c_type),
+            if args.is_empty() {
+                "void".to_owned()
+            } else {
+                args.join(", ")
+            }
+        )
+    }
+
+    /// Struct member of the field at `offset` of the record `record` refers to
+    fn field(&self, label: u64, record: ValueId, offset: u64) -> String {
+        let TypeId(id) = self.objects[&label]
+            .get(&record)
+            .copied()
+            .expect("Records of well-typed programs have a known type");
+        format!("((struct record_{id} *){})->f{offset}", value(record))
+    }
+
+    /// C expression computing `operation`, `types` has the types of the routine's values
+    fn expression(
+        &self,
+        label: u64,
+        types: &HashMap<ValueId, IrType>,
+        result: Option<IrType>,
+        operation: &Operation,
+    ) -> String {
+        use SemanticBinaryOperator as Op;
+
+        let boxed = |id: ValueId| format!("(value){{.{} = {}}}", member(types[&id]), value(id));
+        match *operation {
+            Operation::Const(Constant::Int(constant)) => int(constant),
+            Operation::Const(Constant::Real(constant)) => real(constant),
+            Operation::Const(Constant::Bool(constant)) => int(constant.into()),
+            Operation::String { id } => format!("strings[{id}]"),
+            Operation::Param { index } => format!("a{index}"),
+            Operation::Copy(id) => value(id),
+            Operation::Phi(_) => unreachable!("Phis are assigned on the edges"),
+            Operation::Binary { op, lhs, rhs } => {
+                let (lhs, rhs) = (value(lhs), value(rhs));
+                match op {
+                    Op::RealAdd => format!("{lhs} + {rhs}"),
+                    Op::RealSub => format!("{lhs} - {rhs}"),
+                    Op::RealMul => format!("{lhs} * {rhs}"),
+                    Op::RealDiv => format!("{lhs} / {rhs}"),
+                    Op::RealLe | Op::IntLe => format!("{lhs} <= {rhs}"),
+                    Op::RealLg | Op::IntLg => format!("{lhs} < {rhs}"),
+                    Op::RealGt | Op::IntGt => format!("{lhs} > {rhs}"),
+                    Op::RealGe | Op::IntGe => format!("{lhs} >= {rhs}"),
+                    // Also compares references by identity
+                    Op::RealEq | Op::IntEq => format!("{lhs} == {rhs}"),
+                    Op::RealNeq | Op::IntNeq | Op::BoolXor => format!("{lhs} != {rhs}"),
+                    Op::IntAdd => format!("rt_add({lhs}, {rhs})"),
+                    Op::IntSub => format!("rt_sub({lhs}, {rhs})"),
+                    Op::IntMul => format!("rt_mul({lhs}, {rhs})"),
+                    Op::IntDiv => format!("rt_div({lhs}, {rhs})"),
+                    Op::IntMod => format!("rt_mod({lhs}, {rhs})"),
+                    Op::BoolAnd => format!("{lhs} && {rhs}"),
+                    Op::BoolOr => format!("{lhs} || {rhs}"),
+                    Op::StringConcat => format!("rt_concat({lhs}, {rhs})"),
+                    Op::StringLe => format!("rt_compare({lhs}, {rhs}) <= 0"),
+                    Op::StringLg => format!("rt_compare({lhs}, {rhs}) < 0"),
+                    Op::StringGt => format!("rt_compare({lhs}, {rhs}) > 0"),
+                    Op::StringGe => format!("rt_compare({lhs}, {rhs}) >= 0"),
+                    Op::StringEq => format!("rt_compare({lhs}, {rhs}) == 0"),
+                    Op::StringNeq => format!("rt_compare({lhs}, {rhs}) != 0"),
+                }
+            }
+            Operation::Unary { op, value: id } => match op {
+                SemanticUnaryOperator::IntNeg => format!("rt_neg({})", value(id)),
+                SemanticUnaryOperator::RealNeg => format!("-{}", value(id)),
+                SemanticUnaryOperator::BoolNeg => format!("!{}", value(id)),
+            },
+            Operation::IntToReal(id) => format!("(double){}", value(id)),
+            Operation::RealToInt(id) => format!("rt_real_to_int({})", value(id)),
+            Operation::IntToBool(id) => format!("rt_to_boolean({})", value(id)),
+            Operation::LoadGlobal { index } => format!(
+                "globals[{index}].{}",
+                member(result.expect("Loads have a result"))
+            ),
+            Operation::StoreGlobal { index, value: id } => {
+                format!("globals[{index}].{} = {}", member(types[&id]), value(id))
+            }
+            Operation::AllocRecord { type_id, .. } => format!("rt_record({})", type_id.0),
+            Operation::AllocArray { type_id, size } => {
+                format!("rt_array({}, INT64_C({size}))", type_id.0)
+            }
+            Operation::ArraySize(array) => format!("((struct array *){})->length", value(array)),
+            Operation::LoadField { record, offset } => self.field(label, record, offset),
+            Operation::StoreField {
+                record,
+                offset,
+                value: id,
+            } => format!("{} = {}", self.field(label, record, offset), value(id)),
+            Operation::LoadElement { array, index } => format!(
+                "((struct array *){array})->elements[rt_index({array}, {})].{}",
+                value(index),
+                member(result.expect("Loads have a result")),
+                array = value(array),
+            ),
+            Operation::StoreElement {
+                array,
+                index,
+                value: id,
+            } => format!(
+                "((struct array *){array})->elements[rt_index({array}, {})] = {}",
+                value(index),
+                boxed(id),
+                array = value(array),
+            ),
+            Operation::Call {
+                function_label,
+                ref args,
+            } => {
+                let args: Vec<_> = args.iter().map(|&arg| value(arg)).collect();
+                format!("r{function_label}({})", args.join(", "))
+            }
+            Operation::CallNative { id, ref args } => {
+                let builtin = Builtin::from_id(id).expect("Host functions are rejected before");
+                native(builtin, args, types)
+            }
+            Operation::Print { type_id, value: id } => {
+                format!("rt_print({}, {})", boxed(id), type_id.0)
+            }
+        }
+    }
+
+    /// Whether `operation` is a call of a procedure, they have no result in C
+    fn is_procedure_call(&self, operation: &Operation) -> bool {
+        match *operation {
+            Operation::Call { function_label, .. } => self
+                .routines
+                .get(&function_label)
+                .is_some_and(|function| function.result.is_none()),
+            Operation::CallNative { id, .. } => matches!(
+                Builtin::from_id(id),
+                Some(Builtin::Fill | Builtin::Sort | Builtin::Assert)
+            ),
+            Operation::Const(_)
+            | Operation::String { .. }
+            | Operation::Param { .. }
+            | Operation::Copy(_)
+            | Operation::Phi(_)
+            | Operation::Binary { .. }
+            | Operation::Unary { .. }
+            | Operation::IntToReal(_)
+            | Operation::RealToInt(_)
+            | Operation::IntToBool(_)
+            | Operation::LoadGlobal { .. }
+            | Operation::StoreGlobal { .. }
+            | Operation::AllocRecord { .. }
+            | Operation::AllocArray { .. }
+            | Operation::ArraySize(_)
+            | Operation::LoadField { .. }
+            | Operation::StoreField { .. }
+            | Operation::LoadElement { .. }
+            | Operation::StoreElement { .. }
+            | Operation::Print { .. } => false,
+        }
+    }
+
+    /// Assigns the phis of `to` their values coming from `from`
+    fn phi_moves(&mut self, function: &ir::Function, from: BlockId, to: BlockId) -> fmt::Result {
+        for (result, incoming) in phis(function.block(to)) {
+            for &(predecessor, id) in incoming {
+                if predecessor == from {
+                    writeln!(self.out, "    p{} = {};", result.0, value(id))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn terminator(
+        &mut self,
+        function: &ir::Function,
+        block: BlockId,
+        terminator: Terminator,
+    ) -> fmt::Result {
+        match terminator {
+            Terminator::Jump(target) => {
+                self.phi_moves(function, block, target)?;
+                writeln!(self.out, "    goto {target};")
+            }
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                writeln!(self.out, "    if ({}) {{", value(condition))?;
+                self.phi_moves(function, block, then)?;
+                writeln!(self.out, "    goto {then};")?;
+                writeln!(self.out, "    }}")?;
+                self.phi_moves(function, block, otherwise)?;
+                writeln!(self.out, "    goto {otherwise};")
+            }
+            Terminator::Return(Some(id)) => writeln!(self.out, "    return {};", value(id)),
+            Terminator::Return(None) => writeln!(self.out, "    return;"),
+            Terminator::Panic { code } => {
+                writeln!(self.out, "    rt_fail(\"Panic with code {code}\");")
+            }
+        }
+    }
+
+    fn routine(&mut self, label: u64, function: &ir::Function) -> fmt::Result {
+        let types: HashMap<_, _> = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| instruction.result)
+            .collect();
+        let mut values: Vec<_> = types.iter().collect();
+        values.sort_by_key(|&(&id, _)| id);
+        let targets: BTreeSet<_> = function
+            .blocks
+            .iter()
+            .flat_map(|block| block.terminator.successors())
+            .collect();
+
+        writeln!(self.out, "/* {} */", function.name)?;
+        writeln!(self.out, "{} {{", Self::prototype(label, function))?;
+        for (&id, &t) in values {
+            writeln!(self.out, "    {} {};", c_type(t), value(id))?;
+        }
+        for block in &function.blocks {
+            for (result, _) in phis(block) {
+                writeln!(self.out, "    {} p{};", c_type(types[&result]), result.0)?;
+            }
+        }
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            if targets.contains(&id) {
+                writeln!(self.out, "{id}:")?;
+            }
+            for instruction in &block.instructions {
+                let operation = &instruction.operation;
+                match instruction.result {
+                    Some((result, _)) if matches!(operation, Operation::Phi(_)) => {
+                        writeln!(self.out, "    {} = p{};", value(result), result.0)?;
+                    }
+                    Some((result, _)) if self.is_procedure_call(operation) => {
+                        let call = self.expression(label, &types, None, operation);
+                        writeln!(self.out, "    {call};")?;
+                        writeln!(self.out, "    {} = 0;", value(result))?;
+                    }
+                    Some((result, t)) => {
+                        let expression = self.expression(label, &types, Some(t), operation);
+                        writeln!(self.out, "    {} = {expression};", value(result))?;
+                    }
+                    None => {
+                        let expression = self.expression(label, &types, None, operation);
+                        writeln!(self.out, "    {expression};")?;
+                    }
+                }
+            }
+            self.terminator(function, id, block.terminator)?;
+        }
+        writeln!(self.out, "}}")
+    }
+
+    fn program(&mut self, main: u64) -> fmt::Result {
+        writeln!(self.out, "/* Translated from `{}` */", self.module.name)?;
+        self.out.push_str(RUNTIME_TYPES);
+        writeln!(self.out)?;
+        self.types()?;
+        writeln!(self.out)?;
+        self.out.push_str(RUNTIME);
+        writeln!(self.out)?;
+        for (&label, function) in self.routines {
+            writeln!(self.out, "{};", Self::prototype(label, function))?;
+        }
+        for (&label, function) in self.routines {
+            writeln!(self.out)?;
+            self.routine(label, function)?;
+        }
+        writeln!(self.out)?;
+        writeln!(self.out, "int main(void) {{")?;
+        for (id, string) in self.module.strings.iter().enumerate() {
+            writeln!(
+                self.out,
+                "    strings[{id}] = rt_string({}, {});",
+                literal(string),
+                string.len()
+            )?;
+        }
+        writeln!(self.out, "    r{main}();")?;
+        writeln!(self.out, "    return 0;")?;
+        writeln!(self.out, "}}")
+    }
+}
+
+/// C call of the built-in, `types` has the types of the routine's values
+fn native(builtin: Builtin, args: &[ValueId], types: &HashMap<ValueId, IrType>) -> String {
+    let arg = |index: usize| value(args[index]);
+    let is_real = |index: usize| types[&args[index]] == IrType::Real;
+    let call = |f: &str| {
+        let args: Vec<_> = args.iter().map(|&arg| value(arg)).collect();
+        format!("{f}({})", args.join(", "))
+    };
+    match builtin {
+        Builtin::Sqrt => call("sqrt"),
+        Builtin::Sin => call("sin"),
+        Builtin::Cos => call("cos"),
+        Builtin::Tan => call("tan"),
+        Builtin::Atan => call("atan"),
+        Builtin::Exp => call("exp"),
+        Builtin::Ln => call("log"),
+        Builtin::Pow => call("pow"),
+        Builtin::Abs if is_real(0) => call("fabs"),
+        Builtin::Abs => call("rt_abs"),
+        Builtin::Floor => format!("rt_real_to_int(floor({}))", arg(0)),
+        Builtin::Ceil => format!("rt_real_to_int(ceil({}))", arg(0)),
+        Builtin::Round => format!("rt_real_to_int(round({}))", arg(0)),
+        Builtin::ToInteger => call("rt_real_to_int"),
+        Builtin::Min if is_real(0) => {
+            format!(
+                "rt_total_cmp({a}, {b}) < 0 ? {a} : {b}",
+                a = arg(0),
+                b = arg(1)
+            )
+        }
+        Builtin::Min => format!("{a} < {b} ? {a} : {b}", a = arg(0), b = arg(1)),
+        Builtin::Max if is_real(0) => {
+            format!(
+                "rt_total_cmp({a}, {b}) > 0 ? {a} : {b}",
+                a = arg(0),
+                b = arg(1)
+            )
+        }
+        Builtin::Max => format!("{a} > {b} ? {a} : {b}", a = arg(0), b = arg(1)),
+        Builtin::ToReal => format!("(double){}", arg(0)),
+        Builtin::ToBoolean => call("rt_to_boolean"),
+        Builtin::Fill => format!(
+            "rt_fill({}, (value){{.{} = {}}})",
+            arg(0),
+            member(types[&args[1]]),
+            arg(1)
+        ),
+        Builtin::Copy if types[&args[0]] == IrType::Ref => call("rt_copy"),
+        Builtin::Copy => arg(0),
+        Builtin::Sort => call("rt_sort"),
+        Builtin::Assert => call("rt_assert"),
+        Builtin::ReadInteger => call("rt_read_integer"),
+        Builtin::ReadReal => call("rt_read_real"),
+        Builtin::ReadBoolean => call("rt_read_boolean"),
+        Builtin::Length => call("rt_length"),
+        Builtin::CharAt => call("rt_char_at"),
+        Builtin::Equals if types[&args[0]] == IrType::Ref => call("rt_equals"),
+        Builtin::Equals => format!("{} == {}", arg(0), arg(1)),
+    }
+}
+
+/// Label of the `main` routine of a linked program, after checking that it only calls built-ins
+pub(crate) fn entry(
+    module: &Module,
+    routines: &BTreeMap<u64, ir::Function>,
+) -> Result<u64, CgenError> {
+    let main = signatures(module)
+        .into_iter()
+        .filter(|(_, function)| unqualified(&module.name, &function.name) == "main")
+        .map(|(label, _)| label)
+        .min()
+        .ok_or(CgenError::NoMain)?;
+    for instruction in routines
+        .values()
+        .flat_map(|function| &function.blocks)
+        .flat_map(|block| &block.instructions)
+    {
+        if let Operation::CallNative { id, .. } = instruction.operation
+            && Builtin::from_id(id).is_none()
+        {
+            return Err(CgenError::HostFunction { id });
+        }
+    }
+    Ok(main)
+}
+
+/// C source of the whole program, `module` and `routines` are linked, see `linker::link`
+/// and `linker::link_ir`. Built-ins behave and fail like the ones of the `vm` crate.
+///
+/// # Panics
+///
+/// If the type of a record can't be told, which the checker rules out
+pub fn translate(
+    module: &Module,
+    routines: &BTreeMap<u64, ir::Function>,
+) -> Result<String, CgenError> {
+    let main = entry(module, routines)?;
+    let mut translation = Translation {
+        module,
+        routines,
+        objects: object_types(module, routines),
+        out: String::new(),
+    };
+    translation
+        .program(main)
+        .expect("Writing to a string won't fail");
+    Ok(translation.out)
+}
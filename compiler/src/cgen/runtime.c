@@ -0,0 +1,734 @@
+/* Runtime of programs translated by `compiler::cgen`, after the generated `types`, and of the
+ * assembly of `compiler::x86_64`. Errors are reported like the `vm` crate does, on stderr,
+ * and end the program. */
+
+enum { TYPE_STRING = 3 };
+
+RT _Noreturn void rt_fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+RT void *rt_alloc(size_t size, int32_t type) {
+    struct object *object = calloc(1, size);
+    if (object == NULL) {
+        rt_fail("Out of memory");
+    }
+    object->type = type;
+    return object;
+}
+
+RT int rt_is_object(int32_t type) {
+    enum kind kind = types[type].kind;
+    return kind == KIND_STRING || kind == KIND_RECORD || kind == KIND_ARRAY;
+}
+
+/* Record fields have the C type of their own type */
+RT value rt_load(const char *slot, int32_t type) {
+    value result;
+    if (types[type].kind == KIND_REAL) {
+        memcpy(&result.r, slot, sizeof result.r);
+    } else if (rt_is_object(type)) {
+        memcpy(&result.p, slot, sizeof result.p);
+    } else {
+        memcpy(&result.i, slot, sizeof result.i);
+    }
+    return result;
+}
+
+RT void rt_store(char *slot, int32_t type, value v) {
+    if (types[type].kind == KIND_REAL) {
+        memcpy(slot, &v.r, sizeof v.r);
+    } else if (rt_is_object(type)) {
+        memcpy(slot, &v.p, sizeof v.p);
+    } else {
+        memcpy(slot, &v.i, sizeof v.i);
+    }
+}
+
+/* Strings */
+
+RT void *rt_string(const char *bytes, int64_t length) {
+    struct string *string = rt_alloc(sizeof(struct string) + (size_t)length, TYPE_STRING);
+    string->length = length;
+    if (length > 0) {
+        memcpy(string->bytes, bytes, (size_t)length);
+    }
+    return string;
+}
+
+RT void *rt_concat(const void *lhs, const void *rhs) {
+    const struct string *l = lhs, *r = rhs;
+    struct string *result = rt_string(l->bytes, l->length + r->length);
+    if (r->length > 0) {
+        memcpy(result->bytes + l->length, r->bytes, (size_t)r->length);
+    }
+    return result;
+}
+
+/* By bytes, like `str::cmp` */
+RT int rt_compare(const void *lhs, const void *rhs) {
+    const struct string *l = lhs, *r = rhs;
+    int64_t common = l->length < r->length ? l->length : r->length;
+    int order = common > 0 ? memcmp(l->bytes, r->bytes, (size_t)common) : 0;
+    if (order != 0) {
+        return order;
+    }
+    return (l->length > r->length) - (l->length < r->length);
+}
+
+RT int rt_is_char_start(char byte) {
+    return ((unsigned char)byte & 0xC0) != 0x80;
+}
+
+RT int64_t rt_length(const void *string) {
+    const struct string *s = string;
+    int64_t length = 0;
+    for (int64_t i = 0; i < s->length; i++) {
+        length += rt_is_char_start(s->bytes[i]);
+    }
+    return length;
+}
+
+RT void *rt_char_at(const void *string, int64_t index) {
+    const struct string *s = string;
+    int64_t seen = 0;
+    for (int64_t start = 0; start < s->length; start++) {
+        if (!rt_is_char_start(s->bytes[start])) {
+            continue;
+        }
+        seen++;
+        if (seen == index) {
+            int64_t end = start + 1;
+            while (end < s->length && !rt_is_char_start(s->bytes[end])) {
+                end++;
+            }
+            return rt_string(s->bytes + start, end - start);
+        }
+    }
+    rt_fail("Index %" PRId64 " is out of bounds 1..=%" PRId64, index, rt_length(string));
+}
+
+/* Records and arrays */
+
+/* Record types being allocated, innermost first, their fields are left empty */
+struct allocating {
+    int32_t type;
+    const struct allocating *outer;
+};
+
+RT void *rt_array_in(int32_t type, int64_t length, const struct allocating *allocating);
+RT void *rt_record_in(int32_t type, const struct allocating *allocating);
+
+RT value rt_default(int32_t type, const struct allocating *allocating) {
+    value result;
+    result.i = 0;
+    switch (types[type].kind) {
+    case KIND_REAL:
+        result.r = 0.0;
+        break;
+    case KIND_STRING:
+        result.p = rt_string("", 0);
+        break;
+    case KIND_RECORD:
+        result.p = NULL;
+        for (const struct allocating *outer = allocating; outer != NULL; outer = outer->outer) {
+            if (outer->type == type) {
+                return result;
+            }
+        }
+        result.p = rt_record_in(type, allocating);
+        break;
+    case KIND_ARRAY:
+        result.p = rt_array_in(type, 0, allocating);
+        break;
+    case KIND_INTEGER:
+    case KIND_BOOLEAN:
+    case KIND_ENUM:
+        break;
+    }
+    return result;
+}
+
+RT void *rt_record_in(int32_t type, const struct allocating *allocating) {
+    const struct type *t = &types[type];
+    struct allocating inner = {type, allocating};
+    char *record = rt_alloc(t->size, type);
+    for (int32_t i = 0; i < t->field_count; i++) {
+        const struct field *field = &t->fields[i];
+        rt_store(record + field->offset, field->type, rt_default(field->type, &inner));
+    }
+    return record;
+}
+
+RT void *rt_array_in(int32_t type, int64_t length, const struct allocating *allocating) {
+    if ((uint64_t)length > (SIZE_MAX - sizeof(struct array)) / sizeof(value)) {
+        rt_fail("Out of memory");
+    }
+    struct array *array =
+        rt_alloc(sizeof(struct array) + (size_t)length * sizeof(value), type);
+    array->length = length;
+    for (int64_t i = 0; i < length; i++) {
+        array->elements[i] = rt_default(types[type].element, allocating);
+    }
+    return array;
+}
+
+RT void *rt_record(int32_t type) {
+    return rt_record_in(type, NULL);
+}
+
+RT void *rt_array(int32_t type, int64_t length) {
+    return rt_array_in(type, length, NULL);
+}
+
+/* 0-based offset of the 1-based `index` */
+RT int64_t rt_index(const void *array, int64_t index) {
+    int64_t length = ((const struct array *)array)->length;
+    if (index < 1 || index > length) {
+        rt_fail("Index %" PRId64 " is out of bounds 1..=%" PRId64, index, length);
+    }
+    return index - 1;
+}
+
+/* Arithmetic */
+
+RT int64_t rt_add(int64_t lhs, int64_t rhs) {
+    if ((rhs > 0 && lhs > INT64_MAX - rhs) || (rhs < 0 && lhs < INT64_MIN - rhs)) {
+        rt_fail("Integer overflow");
+    }
+    return lhs + rhs;
+}
+
+RT int64_t rt_sub(int64_t lhs, int64_t rhs) {
+    if ((rhs < 0 && lhs > INT64_MAX + rhs) || (rhs > 0 && lhs < INT64_MIN + rhs)) {
+        rt_fail("Integer overflow");
+    }
+    return lhs - rhs;
+}
+
+RT int64_t rt_mul(int64_t lhs, int64_t rhs) {
+    int overflow;
+    if (lhs > 0) {
+        overflow = rhs > 0 ? lhs > INT64_MAX / rhs : rhs < INT64_MIN / lhs;
+    } else if (lhs < 0) {
+        overflow = rhs > 0 ? lhs < INT64_MIN / rhs : rhs < INT64_MAX / lhs;
+    } else {
+        overflow = 0;
+    }
+    if (overflow) {
+        rt_fail("Integer overflow");
+    }
+    return lhs * rhs;
+}
+
+RT void rt_check_division(int64_t lhs, int64_t rhs) {
+    if (rhs == 0) {
+        rt_fail("Division by zero");
+    }
+    if (lhs == INT64_MIN && rhs == -1) {
+        rt_fail("Integer overflow");
+    }
+}
+
+RT int64_t rt_div(int64_t lhs, int64_t rhs) {
+    rt_check_division(lhs, rhs);
+    return lhs / rhs;
+}
+
+RT int64_t rt_mod(int64_t lhs, int64_t rhs) {
+    rt_check_division(lhs, rhs);
+    return lhs % rhs;
+}
+
+RT int64_t rt_neg(int64_t value) {
+    if (value == INT64_MIN) {
+        rt_fail("Integer overflow");
+    }
+    return -value;
+}
+
+RT int64_t rt_abs(int64_t value) {
+    return value < 0 ? rt_neg(value) : value;
+}
+
+/* Like `f64::total_cmp` */
+RT int rt_total_cmp(double lhs, double rhs) {
+    int64_t l, r;
+    memcpy(&l, &lhs, sizeof l);
+    memcpy(&r, &rhs, sizeof r);
+    l ^= (int64_t)((uint64_t)(l >> 63) >> 1);
+    r ^= (int64_t)((uint64_t)(r >> 63) >> 1);
+    return (l > r) - (l < r);
+}
+
+/* Longest formatted real, "-0." with 323 zeros and 17 digits */
+#define REAL_LENGTH 352
+
+/* Like `Display` of an `f64`: the shortest digits reading back the same without an exponent,
+ * with ".0" after whole numbers */
+RT void rt_format_real(double value, char out[REAL_LENGTH]) {
+    if (isnan(value)) {
+        strcpy(out, "NaN");
+        return;
+    }
+    if (signbit(value)) {
+        *out++ = '-';
+        value = -value;
+    }
+    if (isinf(value)) {
+        strcpy(out, "inf");
+        return;
+    }
+    char scientific[32];
+    for (int precision = 0; precision <= 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision, value);
+        if (strtod(scientific, NULL) == value) {
+            break;
+        }
+    }
+    /* "d.ddde+XX" into digits and exponent */
+    char digits[32];
+    int count = 0;
+    char *exponent = strchr(scientific, 'e');
+    for (const char *c = scientific; c < exponent; c++) {
+        if (*c != '.') {
+            digits[count++] = *c;
+        }
+    }
+    int power = atoi(exponent + 1);
+    if (power < 0) {
+        *out++ = '0';
+        *out++ = '.';
+        for (int i = -1; i > power; i--) {
+            *out++ = '0';
+        }
+        memcpy(out, digits, (size_t)count);
+        out += count;
+    } else {
+        for (int i = 0; i <= power; i++) {
+            *out++ = i < count ? digits[i] : '0';
+        }
+        *out++ = '.';
+        if (count > power + 1) {
+            memcpy(out, digits + power + 1, (size_t)(count - power - 1));
+            out += count - power - 1;
+        } else {
+            *out++ = '0';
+        }
+    }
+    *out = '\0';
+}
+
+RT int64_t rt_real_to_int(double value) {
+    value = trunc(value);
+    if (!(value >= -9223372036854775808.0 && value < 9223372036854775808.0)) {
+        char formatted[REAL_LENGTH];
+        rt_format_real(value, formatted);
+        rt_fail("Invalid conversion: %s does not fit into an integer", formatted);
+    }
+    return (int64_t)value;
+}
+
+RT int64_t rt_to_boolean(int64_t value) {
+    if (value != 0 && value != 1) {
+        rt_fail("Invalid conversion: %" PRId64 " is not a boolean", value);
+    }
+    return value;
+}
+
+/* Built-ins */
+
+RT void rt_assert(int64_t condition) {
+    if (!condition) {
+        rt_fail("Assertion failed");
+    }
+}
+
+RT void rt_fill(void *array, value v) {
+    struct array *a = array;
+    for (int64_t i = 0; i < a->length; i++) {
+        a->elements[i] = v;
+    }
+}
+
+RT int rt_element_order(const value *lhs, const value *rhs, enum kind kind) {
+    switch (kind) {
+    case KIND_INTEGER:
+    case KIND_BOOLEAN:
+    case KIND_ENUM:
+        return (lhs->i > rhs->i) - (lhs->i < rhs->i);
+    case KIND_REAL:
+        return rt_total_cmp(lhs->r, rhs->r);
+    case KIND_STRING:
+    case KIND_RECORD:
+    case KIND_ARRAY:
+        break;
+    }
+    return 0;
+}
+
+/* Stable, numbers by value, other elements keep their order */
+RT void rt_sort(void *array) {
+    struct array *a = array;
+    enum kind kind = types[types[a->header.type].element].kind;
+    if (a->length < 2) {
+        return;
+    }
+    value *buffer = malloc((size_t)a->length * sizeof(value));
+    if (buffer == NULL) {
+        rt_fail("Out of memory");
+    }
+    for (int64_t width = 1; width < a->length; width *= 2) {
+        for (int64_t start = 0; start < a->length; start += 2 * width) {
+            int64_t middle = start + width < a->length ? start + width : a->length;
+            int64_t end = middle + width < a->length ? middle + width : a->length;
+            int64_t l = start, r = middle, out = start;
+            while (l < middle && r < end) {
+                if (rt_element_order(&a->elements[r], &a->elements[l], kind) < 0) {
+                    buffer[out++] = a->elements[r++];
+                } else {
+                    buffer[out++] = a->elements[l++];
+                }
+            }
+            while (l < middle) {
+                buffer[out++] = a->elements[l++];
+            }
+            while (r < end) {
+                buffer[out++] = a->elements[r++];
+            }
+        }
+        memcpy(a->elements, buffer, (size_t)a->length * sizeof(value));
+    }
+    free(buffer);
+}
+
+/* Pairs of objects, copies of originals or ones being compared */
+struct pairs {
+    const void **items;
+    size_t length;
+    size_t capacity;
+};
+
+RT const void *rt_find(const struct pairs *pairs, const void *first) {
+    for (size_t i = 0; i < pairs->length; i += 2) {
+        if (pairs->items[i] == first) {
+            return pairs->items[i + 1];
+        }
+    }
+    return NULL;
+}
+
+RT int rt_contains(const struct pairs *pairs, const void *first, const void *second) {
+    for (size_t i = 0; i < pairs->length; i += 2) {
+        if (pairs->items[i] == first && pairs->items[i + 1] == second) {
+            return 1;
+        }
+    }
+    return 0;
+}
+
+RT void rt_push(struct pairs *pairs, const void *first, const void *second) {
+    if (pairs->length + 2 > pairs->capacity) {
+        pairs->capacity = pairs->capacity == 0 ? 16 : 2 * pairs->capacity;
+        pairs->items = realloc(pairs->items, pairs->capacity * sizeof *pairs->items);
+        if (pairs->items == NULL) {
+            rt_fail("Out of memory");
+        }
+    }
+    pairs->items[pairs->length++] = first;
+    pairs->items[pairs->length++] = second;
+}
+
+RT void *rt_copy_object(void *object, struct pairs *copies);
+
+RT value rt_copy_value(value v, int32_t type, struct pairs *copies) {
+    enum kind kind = types[type].kind;
+    if ((kind == KIND_RECORD || kind == KIND_ARRAY) && v.p != NULL) {
+        v.p = rt_copy_object(v.p, copies);
+    }
+    return v;
+}
+
+RT void *rt_copy_object(void *object, struct pairs *copies) {
+    void *found = (void *)rt_find(copies, object);
+    if (found != NULL) {
+        return found;
+    }
+    int32_t type = ((struct object *)object)->type;
+    const struct type *t = &types[type];
+    if (t->kind == KIND_RECORD) {
+        char *copy = rt_alloc(t->size, type);
+        memcpy(copy, object, t->size);
+        /* Before the fields, so that cycles lead to it */
+        rt_push(copies, object, copy);
+        for (int32_t i = 0; i < t->field_count; i++) {
+            const struct field *field = &t->fields[i];
+            value v = rt_load(copy + field->offset, field->type);
+            rt_store(copy + field->offset, field->type, rt_copy_value(v, field->type, copies));
+        }
+        return copy;
+    }
+    if (t->kind == KIND_ARRAY) {
+        struct array *a = object;
+        size_t size = sizeof(struct array) + (size_t)a->length * sizeof(value);
+        struct array *copy = rt_alloc(size, type);
+        memcpy(copy, a, size);
+        rt_push(copies, object, copy);
+        for (int64_t i = 0; i < copy->length; i++) {
+            copy->elements[i] = rt_copy_value(copy->elements[i], t->element, copies);
+        }
+        return copy;
+    }
+    /* Strings are never changed, they are shared */
+    return object;
+}
+
+/* Deep, records and arrays are shared by assignment */
+RT void *rt_copy(void *object) {
+    struct pairs copies = {NULL, 0, 0};
+    void *copy = object == NULL ? NULL : rt_copy_object(object, &copies);
+    free(copies.items);
+    return copy;
+}
+
+RT int rt_equal_objects(const void *lhs, const void *rhs, struct pairs *assumed);
+
+RT int rt_equal_values(value lhs, value rhs, int32_t type, struct pairs *assumed) {
+    if (types[type].kind == KIND_REAL) {
+        return lhs.r == rhs.r;
+    }
+    if (rt_is_object(type)) {
+        return rt_equal_objects(lhs.p, rhs.p, assumed);
+    }
+    return lhs.i == rhs.i;
+}
+
+/* Pairs in `assumed` are being compared already, so cycles are taken as equal */
+RT int rt_equal_objects(const void *lhs, const void *rhs, struct pairs *assumed) {
+    if (lhs == rhs || (lhs != NULL && rhs != NULL && rt_contains(assumed, lhs, rhs))) {
+        return 1;
+    }
+    if (lhs == NULL || rhs == NULL) {
+        return 0;
+    }
+    rt_push(assumed, lhs, rhs);
+    int32_t type = ((const struct object *)lhs)->type;
+    const struct type *t = &types[type];
+    if (t->kind == KIND_STRING) {
+        return rt_compare(lhs, rhs) == 0;
+    }
+    if (t->kind == KIND_RECORD) {
+        for (int32_t i = 0; i < t->field_count; i++) {
+            const struct field *field = &t->fields[i];
+            value l = rt_load((const char *)lhs + field->offset, field->type);
+            value r = rt_load((const char *)rhs + field->offset, field->type);
+            if (!rt_equal_values(l, r, field->type, assumed)) {
+                return 0;
+            }
+        }
+        return 1;
+    }
+    const struct array *l = lhs, *r = rhs;
+    if (l->length != r->length) {
+        return 0;
+    }
+    for (int64_t i = 0; i < l->length; i++) {
+        if (!rt_equal_values(l->elements[i], r->elements[i], t->element, assumed)) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+RT int64_t rt_equals(const void *lhs, const void *rhs) {
+    struct pairs assumed = {NULL, 0, 0};
+    int equal = rt_equal_objects(lhs, rhs, &assumed);
+    free(assumed.items);
+    return equal;
+}
+
+/* Console */
+
+/* Next whitespace-separated word, NULL at the end of input */
+RT const char *rt_word(void) {
+    static char *word = NULL;
+    static size_t capacity = 0;
+    size_t length = 0;
+    int c;
+    do {
+        c = getchar();
+    } while (c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\f');
+    while (c != EOF && c != ' ' && c != '\t' && c != '\n' && c != '\r' && c != '\f') {
+        if (length + 2 > capacity) {
+            capacity = capacity == 0 ? 64 : 2 * capacity;
+            word = realloc(word, capacity);
+            if (word == NULL) {
+                rt_fail("Out of memory");
+            }
+        }
+        word[length++] = (char)c;
+        c = getchar();
+    }
+    if (length == 0) {
+        return NULL;
+    }
+    word[length] = '\0';
+    return word;
+}
+
+RT const char *rt_read(const char *expected) {
+    const char *word = rt_word();
+    if (word == NULL) {
+        rt_fail("Expected %s, found the end of input", expected);
+    }
+    return word;
+}
+
+RT _Noreturn void rt_invalid(const char *expected, const char *found) {
+    rt_fail("Expected %s, found \"%s\"", expected, found);
+}
+
+RT int64_t rt_read_integer(void) {
+    const char *word = rt_read("an integer");
+    const char *c = word;
+    int negative = *c == '-';
+    if (*c == '-' || *c == '+') {
+        c++;
+    }
+    if (*c == '\0') {
+        rt_invalid("an integer", word);
+    }
+    uint64_t limit = negative ? (uint64_t)INT64_MAX + 1 : (uint64_t)INT64_MAX;
+    uint64_t magnitude = 0;
+    for (; *c != '\0'; c++) {
+        if (*c < '0' || *c > '9') {
+            rt_invalid("an integer", word);
+        }
+        uint64_t digit = (uint64_t)(*c - '0');
+        if (magnitude > (limit - digit) / 10) {
+            rt_invalid("an integer", word);
+        }
+        magnitude = magnitude * 10 + digit;
+    }
+    if (negative) {
+        return magnitude == (uint64_t)INT64_MAX + 1 ? INT64_MIN : -(int64_t)magnitude;
+    }
+    return (int64_t)magnitude;
+}
+
+RT int rt_is_word(const char *c, const char *word) {
+    for (; *word != '\0'; c++, word++) {
+        if ((*c | 0x20) != *word) {
+            return 0;
+        }
+    }
+    return *c == '\0';
+}
+
+/* Decimal, with an optional exponent, or `inf`, `infinity` and `nan` like `f64::from_str` */
+RT double rt_read_real(void) {
+    const char *word = rt_read("a real");
+    const char *c = word;
+    if (*c == '-' || *c == '+') {
+        c++;
+    }
+    if (!rt_is_word(c, "inf") && !rt_is_word(c, "infinity") && !rt_is_word(c, "nan")) {
+        int digits = 0;
+        for (; *c >= '0' && *c <= '9'; c++) {
+            digits++;
+        }
+        if (*c == '.') {
+            for (c++; *c >= '0' && *c <= '9'; c++) {
+                digits++;
+            }
+        }
+        if (digits > 0 && (*c == 'e' || *c == 'E')) {
+            c++;
+            if (*c == '-' || *c == '+') {
+                c++;
+            }
+            digits = *c >= '0' && *c <= '9';
+            while (*c >= '0' && *c <= '9') {
+                c++;
+            }
+        }
+        if (digits == 0 || *c != '\0') {
+            rt_invalid("a real", word);
+        }
+    }
+    return strtod(word, NULL);
+}
+
+RT int64_t rt_read_boolean(void) {
+    const char *word = rt_read("a boolean");
+    if (strcmp(word, "true") == 0) {
+        return 1;
+    }
+    if (strcmp(word, "false") != 0) {
+        rt_invalid("a boolean", word);
+    }
+    return 0;
+}
+
+RT void rt_format(value v, int32_t type) {
+    const struct type *t = &types[type];
+    switch (t->kind) {
+    case KIND_INTEGER:
+        printf("%" PRId64, v.i);
+        break;
+    case KIND_REAL: {
+        char formatted[REAL_LENGTH];
+        rt_format_real(v.r, formatted);
+        fputs(formatted, stdout);
+        break;
+    }
+    case KIND_BOOLEAN:
+        fputs(v.i ? "true" : "false", stdout);
+        break;
+    case KIND_STRING: {
+        const struct string *s = v.p;
+        fwrite(s->bytes, 1, (size_t)s->length, stdout);
+        break;
+    }
+    case KIND_ENUM:
+        if (v.i < 0 || v.i >= t->variant_count) {
+            rt_fail("%" PRId64 " is not a variant of the enum", v.i);
+        }
+        fputs(t->variants[v.i], stdout);
+        break;
+    case KIND_ARRAY: {
+        const struct array *a = v.p;
+        putchar('[');
+        for (int64_t i = 0; i < a->length; i++) {
+            if (i > 0) {
+                fputs(", ", stdout);
+            }
+            rt_format(a->elements[i], t->element);
+        }
+        putchar(']');
+        break;
+    }
+    case KIND_RECORD:
+        putchar('{');
+        for (int32_t i = 0; i < t->field_count; i++) {
+            const struct field *field = &t->fields[i];
+            if (i > 0) {
+                fputs(", ", stdout);
+            }
+            rt_format(rt_load((const char *)v.p + field->offset, field->type), field->type);
+        }
+        putchar('}');
+        break;
+    }
+}
+
+RT void rt_print(value v, int32_t type) {
+    rt_format(v, type);
+    putchar('\n');
+}
@@ -0,0 +1,69 @@
+/* Types of the runtime of programs translated by `compiler::cgen`, the generated types follow */
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Linkage of the runtime's functions, the assembly of `compiler::x86_64` calls them */
+#ifndef RT
+#define RT static
+#endif
+
+/* Globals, array elements and `print` arguments, whatever their type */
+typedef union {
+    int64_t i;
+    double r;
+    void *p;
+} value;
+
+enum kind {
+    KIND_INTEGER,
+    KIND_REAL,
+    KIND_BOOLEAN,
+    KIND_STRING,
+    KIND_ENUM,
+    KIND_RECORD,
+    KIND_ARRAY,
+};
+
+struct field {
+    size_t offset;
+    int32_t type;
+};
+
+/* `types[id]` describes the type with `TypeId(id)` */
+struct type {
+    enum kind kind;
+    /* Records */
+    size_t size;
+    int32_t field_count;
+    const struct field *fields;
+    /* Arrays */
+    int32_t element;
+    /* Enums */
+    int32_t variant_count;
+    const char *const *variants;
+};
+
+/* Every object on the heap starts with it */
+struct object {
+    int32_t type;
+};
+
+struct string {
+    struct object header;
+    /* In bytes of UTF-8 */
+    int64_t length;
+    char bytes[];
+};
+
+struct array {
+    struct object header;
+    int64_t length;
+    value elements[];
+};
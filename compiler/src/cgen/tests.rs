@@ -0,0 +1,102 @@
+use std::fs;
+use std::process::Command;
+
+use super::*;
+use crate::ir::FunctionBuilder;
+use crate::test_support::{
+    Run, fails_at_run_time, module, print, routine, run_native, runs_the_corpus,
+};
+
+/// Compiles the translation with the system C compiler and runs it
+fn run(test: &str, module: &Module, routines: &BTreeMap<u64, ir::Function>, input: &str) -> Run {
+    let source = translate(module, routines).expect("The program is translatable");
+    run_native(&format!("cgen-{test}"), input, |dir, executable| {
+        let c = dir.join("program.c");
+        fs::write(&c, &source).expect("Temporary directory is writable");
+        let compiled = Command::new("cc")
+            .args(["-std=c11", "-O1", "-o"])
+            .arg(executable)
+            .arg(&c)
+            .arg("-lm")
+            .output()
+            .expect("The tests of the C backend need `cc`");
+        assert!(
+            compiled.status.success(),
+            "{}\n{source}",
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+    })
+}
+
+#[test]
+fn reals_are_printed_like_the_vm_does() {
+    let reals = [
+        25.0,
+        0.1,
+        1.0 / 3.0,
+        -0.0,
+        123_456.789,
+        1e15,
+        1e16,
+        1.5e-5,
+        1e-4,
+        2.5e-7,
+        1e100,
+        5e-324,
+        f64::MAX,
+        f64::INFINITY,
+        f64::NAN,
+    ];
+    let mut builder = FunctionBuilder::new("main", Vec::new(), None);
+    for real in reals {
+        let value = builder.value(IrType::Real, Operation::Const(Constant::Real(real)));
+        print(&mut builder, TypeId::REAL, value);
+    }
+    builder.terminate(Terminator::Return(None));
+    let routines = BTreeMap::from([(0, builder.finish())]);
+    let module = module(vec![routine("main", 0, &[], TypeId::INTEGER)], &[]);
+
+    let run = run("reals", &module, &routines, "");
+    // `Display` with `.0` after whole numbers
+    let expected: Vec<_> = reals
+        .iter()
+        .map(|real| match real.to_string() {
+            whole if real.is_finite() && !whole.contains('.') => format!("{whole}.0"),
+            formatted => formatted,
+        })
+        .collect();
+    assert_eq!(run.stdout.lines().collect::<Vec<_>>(), expected);
+    assert!(run.success);
+}
+
+#[test]
+fn corpus() {
+    runs_the_corpus(run);
+}
+
+#[test]
+fn runtime_errors() {
+    fails_at_run_time(run);
+}
+
+#[test]
+fn untranslatable_programs() {
+    let mut builder = FunctionBuilder::new("main", Vec::new(), None);
+    builder.effect(Operation::CallNative {
+        id: 1000,
+        args: Vec::new(),
+    });
+    builder.terminate(Terminator::Return(None));
+    let routines = BTreeMap::from([(0, builder.finish())]);
+    assert_eq!(
+        translate(&module(Vec::new(), &[]), &routines),
+        Err(CgenError::NoMain)
+    );
+    assert_eq!(
+        translate(
+            &module(vec![routine("main", 0, &[], TypeId::INTEGER)], &[]),
+            &routines
+        ),
+        Err(CgenError::HostFunction { id: 1000 })
+    );
+}
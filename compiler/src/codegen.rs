@@ -0,0 +1,814 @@
+//! Typed `ast` to stack `Bytecode`
+
+use std::rc::Rc;
+
+use crate::ast::{
+    BinaryOperator, Block, BlockElement, CaseLabel, Declaration, Expression, Identifier, LoopOrder,
+    LvalueExpression, RoutineBody, RoutineDeclaration, SimpleDeclaration, Statement,
+    TypeDeclaration, UnaryOperator, VariableDeclaration,
+};
+use crate::builtins::Builtin;
+use crate::bytecode::{
+    Bytecode, ExternFunction, FunctionCode, FunctionRecord, FunctionTable, INIT, Location, Module,
+    Symbol, TypeId,
+};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+use crate::types::{ArrayDescription, Type, TypedProgram};
+
+#[cfg(test)]
+mod tests;
+
+/// `Bytecode::Panic` code of a routine with a result that ends without `return`
+pub const MISSING_RETURN: u64 = 1;
+
+/// The module's RTTI starts with `RTTI::primitives`,
+/// routines of other modules are called through `Module::externs`.
+/// Calls in tail position are `Bytecode::TailCall`s, see `tail_calls`.
+///
+/// Globals are initialized in the order of their declarations by `INIT`, see `init_label`.
+/// Variables declared without an initializer get zeroes, empty strings, and records
+/// and arrays with their elements allocated.
+///
+/// # Panics
+///
+/// If the program was not checked
+#[must_use]
+pub fn compile(program: &TypedProgram) -> Module {
+    let init = init_label(program);
+    let mut generator = Generator {
+        program,
+        code: Vec::new(),
+        next_label: program.label_count + u64::from(init.is_some()),
+        loops: Loops::default(),
+    };
+    let globals = globals(program);
+    for declaration in &program.program.declarations {
+        if let Declaration::Routine(routine) = declaration
+            && routine.body.is_some()
+        {
+            generator.routine(routine);
+        }
+    }
+    if let Some(label) = init {
+        generator.init(label, &globals);
+    }
+
+    let mut functions: Vec<_> = program
+        .routines
+        .iter()
+        .filter(|routine| routine.module.is_none())
+        .map(|routine| FunctionRecord {
+            name: routine.name.clone(),
+            code: routine.code,
+            args: routine
+                .parameters
+                .iter()
+                .map(|t| program.type_id(t))
+                .collect(),
+            // Procedures return 0
+            result: routine
+                .result
+                .as_ref()
+                .map_or(TypeId::INTEGER, |t| program.type_id(t)),
+        })
+        .collect();
+    functions.extend(init.map(|label| FunctionRecord {
+        name: INIT.to_owned(),
+        code: FunctionCode::Label(label),
+        args: Vec::new(),
+        result: TypeId::INTEGER,
+    }));
+    let externs = program
+        .routines
+        .iter()
+        .filter_map(|routine| {
+            let (Some(module), FunctionCode::Label(label_id)) = (&routine.module, routine.code)
+            else {
+                return None;
+            };
+            Some(ExternFunction {
+                name: format!("{module}.{}", routine.name),
+                label_id,
+            })
+        })
+        .collect();
+    Module {
+        name: program.name.clone(),
+        code: generator.code,
+        label_count: generator.next_label,
+        functions: FunctionTable(functions),
+        externs,
+        exports: exports(program),
+        imports: program.imports.clone(),
+        rtti: program.rtti.clone(),
+        strings: program.strings.clone(),
+        global_count: program.global_count,
+    }
+}
+
+/// Declarations of the module's globals, in order
+pub(crate) fn globals(program: &TypedProgram) -> Vec<&VariableDeclaration> {
+    program
+        .program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Simple(SimpleDeclaration::Variable(variable)) => Some(variable),
+            Declaration::Simple(SimpleDeclaration::Type(_))
+            | Declaration::Routine(_)
+            | Declaration::Import(_) => None,
+        })
+        .collect()
+}
+
+/// Label of `INIT`, the first one after the entries of the routines, if the module has globals
+#[must_use]
+pub fn init_label(program: &TypedProgram) -> Option<u64> {
+    (!globals(program).is_empty()).then_some(program.label_count)
+}
+
+/// Top-level records, enums and globals, see `Module::exports`
+fn exports(program: &TypedProgram) -> Vec<Symbol> {
+    program
+        .program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Simple(SimpleDeclaration::Type(TypeDeclaration { name, t, .. })) => {
+                let is_declared = matches!(&*program.resolve(t), Type::Record(_) | Type::Enum(_));
+                is_declared.then(|| Symbol::Type {
+                    name: name.name.clone(),
+                    type_id: program.type_id(t),
+                })
+            }
+            Declaration::Simple(SimpleDeclaration::Variable(VariableDeclaration {
+                name, ..
+            })) => {
+                let Location::Global(index) =
+                    program.variables[name.id.expect("Resolved")].location
+                else {
+                    unreachable!("Top-level variables are globals")
+                };
+                Some(Symbol::Global {
+                    name: name.name.clone(),
+                    index: u32::try_from(index).expect("No one has 4 billion globals"),
+                })
+            }
+            Declaration::Routine(_) | Declaration::Import(_) => None,
+        })
+        .collect()
+}
+
+pub(crate) fn binary_operator(op: BinaryOperator, t: &Type) -> SemanticBinaryOperator {
+    use SemanticBinaryOperator as S;
+    match (op, t) {
+        (BinaryOperator::And, _) => S::BoolAnd,
+        (BinaryOperator::Or, _) => S::BoolOr,
+        (BinaryOperator::Xor, _) => S::BoolXor,
+        (BinaryOperator::Add, Type::String) => S::StringConcat,
+        (BinaryOperator::Le, Type::String) => S::StringLe,
+        (BinaryOperator::Lg, Type::String) => S::StringLg,
+        (BinaryOperator::Gt, Type::String) => S::StringGt,
+        (BinaryOperator::Ge, Type::String) => S::StringGe,
+        (BinaryOperator::Eq, Type::String) => S::StringEq,
+        (BinaryOperator::Neq, Type::String) => S::StringNeq,
+        (BinaryOperator::Add, Type::Real) => S::RealAdd,
+        (BinaryOperator::Sub, Type::Real) => S::RealSub,
+        (BinaryOperator::Mul, Type::Real) => S::RealMul,
+        (BinaryOperator::Div, Type::Real) => S::RealDiv,
+        (BinaryOperator::Le, Type::Real) => S::RealLe,
+        (BinaryOperator::Lg, Type::Real) => S::RealLg,
+        (BinaryOperator::Gt, Type::Real) => S::RealGt,
+        (BinaryOperator::Ge, Type::Real) => S::RealGe,
+        (BinaryOperator::Eq, Type::Real) => S::RealEq,
+        (BinaryOperator::Neq, Type::Real) => S::RealNeq,
+        // Booleans and enums are integers, references are compared by identity
+        (BinaryOperator::Add, _) => S::IntAdd,
+        (BinaryOperator::Sub, _) => S::IntSub,
+        (BinaryOperator::Mul, _) => S::IntMul,
+        (BinaryOperator::Div, _) => S::IntDiv,
+        (BinaryOperator::Mod, _) => S::IntMod,
+        (BinaryOperator::Le, _) => S::IntLe,
+        (BinaryOperator::Lg, _) => S::IntLg,
+        (BinaryOperator::Gt, _) => S::IntGt,
+        (BinaryOperator::Ge, _) => S::IntGe,
+        (BinaryOperator::Eq, _) => S::IntEq,
+        (BinaryOperator::Neq, _) => S::IntNeq,
+    }
+}
+
+pub(crate) fn unary_operator(op: UnaryOperator, t: &Type) -> SemanticUnaryOperator {
+    match (op, t) {
+        (UnaryOperator::Not, _) => SemanticUnaryOperator::BoolNeg,
+        (UnaryOperator::Neg, Type::Real) => SemanticUnaryOperator::RealNeg,
+        (UnaryOperator::Neg, _) => SemanticUnaryOperator::IntNeg,
+    }
+}
+
+struct Generator<'a> {
+    program: &'a TypedProgram,
+    code: Vec<Bytecode>,
+    next_label: u64,
+    loops: Loops<'a>,
+}
+
+impl<'a> Generator<'a> {
+    fn label(&mut self) -> u64 {
+        let label = self.next_label;
+        self.next_label += 1;
+        label
+    }
+
+    fn emit(&mut self, instruction: Bytecode) {
+        self.code.push(instruction);
+    }
+
+    fn location(&self, name: &Identifier) -> Location {
+        self.program.variables[name.id.expect("Resolved")].location
+    }
+
+    fn routine(&mut self, declaration: &'a RoutineDeclaration) {
+        let routine = &self.program.routines[declaration.name.id.expect("Resolved")];
+        let FunctionCode::Label(label) = routine.code else {
+            unreachable!("Routines with a body have a label")
+        };
+        let start = self.code.len();
+        self.emit(Bytecode::Label { id: label });
+        self.emit(Bytecode::Enter {
+            args: u16::try_from(routine.parameters.len()).expect("No one has 65536 parameters"),
+            locals: u16::try_from(routine.locals).expect("No one has 65536 locals"),
+        });
+        match declaration.body.as_ref().expect("Has a body") {
+            RoutineBody::Block(body) => {
+                self.block(body);
+                match routine.result {
+                    Some(_) => self.emit(Bytecode::Panic {
+                        code: MISSING_RETURN,
+                    }),
+                    None => self
+                        .code
+                        .extend([Bytecode::IntConst { value: 0 }, Bytecode::Ret]),
+                }
+            }
+            RoutineBody::Expression(value) => {
+                self.expression(value);
+                self.emit(Bytecode::Ret);
+            }
+        }
+        tail_calls(&mut self.code[start..]);
+    }
+
+    /// `INIT` of a module with globals
+    fn init(&mut self, label: u64, globals: &[&'a VariableDeclaration]) {
+        self.code.extend([
+            Bytecode::Label { id: label },
+            Bytecode::Enter { args: 0, locals: 0 },
+        ]);
+        for global in globals {
+            self.variable(global);
+        }
+        self.code
+            .extend([Bytecode::IntConst { value: 0 }, Bytecode::Ret]);
+    }
+
+    fn variable(&mut self, variable: &VariableDeclaration) {
+        match &variable.initializer {
+            Some(value) => self.expression(value),
+            None => self.default(variable.t.as_ref().expect("Checked"), &mut Vec::new()),
+        }
+        let loc = self.location(&variable.name);
+        self.emit(Bytecode::Store { loc });
+    }
+
+    /// Pushes the value of a variable declared without an initializer. The machine allocates
+    /// records with their nested records, their arrays are allocated here unless the record
+    /// contains itself, `records` are the ones being allocated.
+    fn default(&mut self, t: &Rc<Type>, records: &mut Vec<*const Type>) {
+        let program = self.program;
+        let t = program.resolve(t);
+        match &*t {
+            Type::Int | Type::Bool | Type::Enum(_) => self.emit(Bytecode::IntConst { value: 0 }),
+            Type::Real => self.emit(Bytecode::RealConst { value: 0.0 }),
+            Type::String => self.emit(Bytecode::StringConst {
+                id: program.string_id(""),
+            }),
+            Type::Array(ArrayDescription { length, .. }) => self.emit(Bytecode::AllocArray {
+                type_id: program.type_id(&t),
+                size: length.expect("Checked") as u64,
+            }),
+            Type::Record(record) => {
+                self.emit(Bytecode::AllocRecord {
+                    type_id: program.type_id(&t),
+                    size: record.fields.len() as u64,
+                });
+                records.push(Rc::as_ptr(&t));
+                for (offset, field) in record.fields.iter().enumerate() {
+                    let field_type = program.resolve(&field.t);
+                    let is_allocated = match &*field_type {
+                        Type::Array(array) => array.length.is_some(),
+                        Type::Record(_) => !records.contains(&Rc::as_ptr(&field_type)),
+                        Type::Int
+                        | Type::Real
+                        | Type::Bool
+                        | Type::String
+                        | Type::Alias(_)
+                        | Type::Enum(_) => false,
+                    };
+                    if is_allocated {
+                        self.emit(Bytecode::Dup);
+                        self.default(&field.t, records);
+                        self.code.extend([
+                            Bytecode::Swap,
+                            Bytecode::FieldAddress {
+                                field_offset: offset as u64,
+                            },
+                            Bytecode::StoreAddress,
+                        ]);
+                    }
+                }
+                let _: Option<*const Type> = records.pop();
+            }
+            Type::Alias(_) => unreachable!("Resolved"),
+        }
+    }
+
+    fn block(&mut self, block: &'a Block) {
+        for element in block.elements() {
+            match element {
+                BlockElement::Decl(declaration) => {
+                    if let SimpleDeclaration::Variable(variable) = &**declaration {
+                        self.variable(variable);
+                    }
+                }
+                BlockElement::Stmt(_, statement) => self.statement(statement),
+            }
+        }
+    }
+
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Assignment { lhs, rhs } => {
+                self.expression(rhs);
+                if let LvalueExpression::Identifier(name) = &**lhs {
+                    let loc = self.location(name);
+                    self.emit(Bytecode::Store { loc });
+                } else {
+                    self.address(lhs);
+                    self.emit(Bytecode::StoreAddress);
+                }
+            }
+            Statement::Call { call } => {
+                self.expression(call);
+                self.emit(Bytecode::Drop);
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                let (next, exit) = (self.label(), self.label());
+                self.emit(Bytecode::Label { id: next });
+                self.expression(condition);
+                self.emit(Bytecode::JumpZero { label: exit });
+                self.loop_body(label.as_ref(), body, next, exit);
+                self.code
+                    .extend([Bytecode::Jump { label: next }, Bytecode::Label { id: exit }]);
+            }
+            Statement::For {
+                label,
+                identifier,
+                from,
+                to,
+                order,
+                body,
+            } => {
+                let Location::Local(variable) = self.location(identifier) else {
+                    unreachable!("Loop variables are locals")
+                };
+                let reverse = matches!(order, LoopOrder::Reversed);
+                let (condition, next, exit) = (self.label(), self.label(), self.label());
+                let step = if reverse {
+                    SemanticBinaryOperator::IntSub
+                } else {
+                    SemanticBinaryOperator::IntAdd
+                };
+                let (variable, hidden, index) = (
+                    Location::Local(variable),
+                    Location::Local(variable + 1),
+                    Location::Local(variable + 2),
+                );
+                let counter = if let Some(to) = to {
+                    let (start, bound) = if reverse { (to, from) } else { (from, to) };
+                    self.expression(start);
+                    self.emit(Bytecode::Store { loc: variable });
+                    self.expression(bound);
+                    self.code.extend([
+                        Bytecode::Store { loc: hidden },
+                        Bytecode::Label { id: condition },
+                        Bytecode::Load { loc: variable },
+                        Bytecode::Load { loc: hidden },
+                        Bytecode::BinOp {
+                            op: if reverse {
+                                SemanticBinaryOperator::IntGe
+                            } else {
+                                SemanticBinaryOperator::IntLe
+                            },
+                        },
+                        Bytecode::JumpZero { label: exit },
+                    ]);
+                    variable
+                } else {
+                    self.expression(from);
+                    self.emit(Bytecode::Store { loc: hidden });
+                    if reverse {
+                        self.code
+                            .extend([Bytecode::Load { loc: hidden }, Bytecode::ArraySize]);
+                    } else {
+                        self.emit(Bytecode::IntConst { value: 1 });
+                    }
+                    self.code.extend([
+                        Bytecode::Store { loc: index },
+                        Bytecode::Label { id: condition },
+                        Bytecode::Load { loc: index },
+                    ]);
+                    if reverse {
+                        self.code.extend([
+                            Bytecode::IntConst { value: 1 },
+                            Bytecode::BinOp {
+                                op: SemanticBinaryOperator::IntGe,
+                            },
+                        ]);
+                    } else {
+                        self.code.extend([
+                            Bytecode::Load { loc: hidden },
+                            Bytecode::ArraySize,
+                            Bytecode::BinOp {
+                                op: SemanticBinaryOperator::IntLe,
+                            },
+                        ]);
+                    }
+                    self.code.extend([
+                        Bytecode::JumpZero { label: exit },
+                        Bytecode::Load { loc: hidden },
+                        Bytecode::Load { loc: index },
+                        Bytecode::ElementAddress,
+                        Bytecode::LoadAddress,
+                        Bytecode::Store { loc: variable },
+                    ]);
+                    index
+                };
+                self.loop_body(label.as_ref(), body, next, exit);
+                self.code.extend([
+                    Bytecode::Label { id: next },
+                    Bytecode::Load { loc: counter },
+                    Bytecode::IntConst { value: 1 },
+                    Bytecode::BinOp { op: step },
+                    Bytecode::Store { loc: counter },
+                    Bytecode::Jump { label: condition },
+                    Bytecode::Label { id: exit },
+                ]);
+            }
+            Statement::If {
+                condition,
+                on_true,
+                on_false,
+            } => {
+                let (otherwise, end) = (self.label(), self.label());
+                self.expression(condition);
+                self.emit(Bytecode::JumpZero { label: otherwise });
+                self.block(on_true);
+                self.code.extend([
+                    Bytecode::Jump { label: end },
+                    Bytecode::Label { id: otherwise },
+                ]);
+                if let Some(on_false) = on_false {
+                    self.block(on_false);
+                }
+                self.emit(Bytecode::Label { id: end });
+            }
+            Statement::Print { value } => {
+                let type_id = self.program.type_id(&self.program.type_of(value));
+                self.expression(value);
+                self.emit(Bytecode::Print { type_id });
+            }
+            Statement::Case {
+                value,
+                branches,
+                otherwise,
+            } => {
+                self.expression(value);
+                let labels: Vec<_> = branches
+                    .iter()
+                    .map(|branch| {
+                        let values = branch
+                            .labels
+                            .iter()
+                            .map(|label| match label {
+                                CaseLabel::Integer(literal) => literal.value(),
+                                CaseLabel::Variant(_) => unreachable!("Checked labels are values"),
+                            })
+                            .collect();
+                        (values, self.label())
+                    })
+                    .collect();
+                let (default, end) = (self.label(), self.label());
+                self.code.extend(case_dispatch(&labels, default));
+                for (branch, &(_, label)) in branches.iter().zip(&labels) {
+                    self.code
+                        .extend([Bytecode::Label { id: label }, Bytecode::Drop]);
+                    self.block(&branch.body);
+                    self.emit(Bytecode::Jump { label: end });
+                }
+                self.code
+                    .extend([Bytecode::Label { id: default }, Bytecode::Drop]);
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise);
+                }
+                self.emit(Bytecode::Label { id: end });
+            }
+            Statement::Return { value } => {
+                match value {
+                    Some(value) => self.expression(value),
+                    // Procedures return 0
+                    None => self.emit(Bytecode::IntConst { value: 0 }),
+                }
+                self.emit(Bytecode::Ret);
+            }
+            Statement::Break { label } => {
+                let jump = self.loops.break_jump(label.as_ref());
+                self.emit(jump.expect("Checked by `flow::check_loop_exits`"));
+            }
+            Statement::Continue { label } => {
+                let jump = self.loops.continue_jump(label.as_ref());
+                self.emit(jump.expect("Checked by `flow::check_loop_exits`"));
+            }
+        }
+    }
+
+    fn loop_body(&mut self, label: Option<&'a Identifier>, body: &'a Block, next: u64, exit: u64) {
+        self.loops.enter(label, next, exit);
+        self.block(body);
+        self.loops.leave();
+    }
+
+    /// Code of `f` run on its own
+    fn generated(&mut self, f: impl FnOnce(&mut Self)) -> Vec<Bytecode> {
+        let start = self.code.len();
+        f(self);
+        self.code.split_off(start)
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        let program = self.program;
+        match expression {
+            Expression::LvalueToRvalue(lvalue) => self.rvalue(lvalue),
+            Expression::IntegerLiteral(literal) => self.emit(Bytecode::IntConst {
+                value: literal.value(),
+            }),
+            Expression::RealLiteral(literal) => self.emit(Bytecode::RealConst {
+                value: literal.value(),
+            }),
+            Expression::BoolLiteral(literal) => self.emit(Bytecode::IntConst {
+                value: i64::from(*literal == crate::ast::BoolLiteral::True),
+            }),
+            Expression::StringLiteral(literal) => self.emit(Bytecode::StringConst {
+                id: program.string_id(literal.value()),
+            }),
+            Expression::Call { callee, args } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(match callee.id.map(|id| program.routines[id].code) {
+                    Some(FunctionCode::Label(function_label)) => Bytecode::Call { function_label },
+                    Some(FunctionCode::Native(id)) => Bytecode::CallNative { id },
+                    None => Bytecode::CallNative {
+                        id: Builtin::lookup(&callee.name).expect("Checked").id(),
+                    },
+                });
+            }
+            Expression::Binop { op, lhs, rhs } => {
+                if matches!(op, BinaryOperator::And | BinaryOperator::Or) {
+                    let lhs = self.generated(|generator| generator.expression(lhs));
+                    let rhs = self.generated(|generator| generator.expression(rhs));
+                    let end = self.label();
+                    let code = short_circuit(*op, lhs, rhs, end).expect("`and` and `or` do");
+                    self.code.extend(code);
+                    return;
+                }
+                let t = program.resolve(&program.type_of(lhs));
+                self.expression(lhs);
+                self.expression(rhs);
+                self.emit(Bytecode::BinOp {
+                    op: binary_operator(*op, &t),
+                });
+            }
+            Expression::Unop { op, value } => {
+                let t = program.resolve(&program.type_of(value));
+                self.expression(value);
+                self.emit(Bytecode::UnOp {
+                    op: unary_operator(*op, &t),
+                });
+            }
+            // Booleans are integers
+            Expression::BoolToInt(value) => self.expression(value),
+            Expression::RealToInt(value) => {
+                self.expression(value);
+                self.emit(Bytecode::RealToInt);
+            }
+            Expression::IntToBool(value) => {
+                self.expression(value);
+                self.emit(Bytecode::IntToBool);
+            }
+            Expression::Conversion { target, value } => {
+                let from = program.resolve(&program.type_of(value));
+                self.expression(value);
+                match (&*from, &*program.resolve(target)) {
+                    (Type::Int | Type::Bool, Type::Real) => self.emit(Bytecode::IntToReal),
+                    (Type::Real, Type::Int | Type::Enum(_)) => self.emit(Bytecode::RealToInt),
+                    (Type::Real, Type::Bool) => {
+                        self.code.extend([Bytecode::RealToInt, Bytecode::IntToBool]);
+                    }
+                    (Type::Int | Type::Enum(_), Type::Bool) => self.emit(Bytecode::IntToBool),
+                    // The same representation
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn rvalue(&mut self, lvalue: &LvalueExpression) {
+        match lvalue {
+            LvalueExpression::Identifier(name) => {
+                let loc = self.location(name);
+                self.emit(Bytecode::Load { loc });
+            }
+            LvalueExpression::Member {
+                lhs,
+                member_name: Identifier { id: None, .. },
+            } => {
+                self.rvalue(lhs);
+                self.emit(Bytecode::ArraySize);
+            }
+            LvalueExpression::Member { .. } | LvalueExpression::Index { .. } => {
+                self.address(lvalue);
+                self.emit(Bytecode::LoadAddress);
+            }
+        }
+    }
+
+    fn address(&mut self, lvalue: &LvalueExpression) {
+        match lvalue {
+            LvalueExpression::Identifier(name) => {
+                let loc = self.location(name);
+                self.emit(Bytecode::AddressOf { loc });
+            }
+            LvalueExpression::Member { lhs, member_name } => {
+                self.rvalue(lhs);
+                self.emit(Bytecode::FieldAddress {
+                    field_offset: member_name.id.expect("`length` is not assigned") as u64,
+                });
+            }
+            LvalueExpression::Index { lhs, index } => {
+                self.rvalue(lhs);
+                self.expression(index);
+                self.emit(Bytecode::ElementAddress);
+            }
+        }
+    }
+}
+
+/// Dispatch of `ast::Statement::Case` as a comparison chain: the value is on the stack top,
+/// `branches` pairs each branch's label values (see `types::case_labels`) with its label id.
+/// The value stays on the stack, so every branch and `otherwise` starts with `Bytecode::Drop`.
+#[must_use]
+pub fn case_dispatch(branches: &[(Vec<i64>, u64)], otherwise: u64) -> Vec<Bytecode> {
+    let mut code = Vec::new();
+    for (values, label) in branches {
+        for &value in values {
+            code.extend([
+                Bytecode::Dup,
+                Bytecode::IntConst { value },
+                Bytecode::BinOp {
+                    op: SemanticBinaryOperator::IntEq,
+                },
+                Bytecode::JumpNotZero { label: *label },
+            ]);
+        }
+    }
+    code.push(Bytecode::Jump { label: otherwise });
+    code
+}
+
+/// Lowers `lhs and rhs` and `lhs or rhs` so that `rhs` is evaluated only when `lhs`
+/// does not decide the result, which is then `lhs` itself. `end` is a fresh label id.
+/// `None` for the other operators, which evaluate both sides.
+#[must_use]
+pub fn short_circuit(
+    op: BinaryOperator,
+    lhs: Vec<Bytecode>,
+    rhs: Vec<Bytecode>,
+    end: u64,
+) -> Option<Vec<Bytecode>> {
+    let decided = match op {
+        BinaryOperator::And => Bytecode::JumpZero { label: end },
+        BinaryOperator::Or => Bytecode::JumpNotZero { label: end },
+        BinaryOperator::Xor
+        | BinaryOperator::Le
+        | BinaryOperator::Lg
+        | BinaryOperator::Gt
+        | BinaryOperator::Ge
+        | BinaryOperator::Eq
+        | BinaryOperator::Neq
+        | BinaryOperator::Mul
+        | BinaryOperator::Div
+        | BinaryOperator::Mod
+        | BinaryOperator::Add
+        | BinaryOperator::Sub => return None,
+    };
+    let mut code = lhs;
+    code.extend([Bytecode::Dup, decided, Bytecode::Drop]);
+    code.extend(rhs);
+    code.push(Bytecode::Label { id: end });
+    Some(code)
+}
+
+/// Turns the calls of a routine's code that are in tail position into `Bytecode::TailCall`s:
+/// the ones followed by `Ret` and the procedure calls followed by the `Drop` of their result,
+/// `IntConst 0` and `Ret`, since procedures return 0 too. Labels in between are skipped,
+/// the `Ret` stays for the jumps to them.
+pub fn tail_calls(code: &mut [Bytecode]) {
+    for position in 0..code.len() {
+        let Bytecode::Call { function_label } = code[position] else {
+            continue;
+        };
+        let mut rest = code[position + 1..]
+            .iter()
+            .filter(|instruction| !matches!(instruction, Bytecode::Label { .. }));
+        let tail = match rest.next() {
+            Some(Bytecode::Ret) => true,
+            Some(Bytecode::Drop) => {
+                rest.next() == Some(&Bytecode::IntConst { value: 0 })
+                    && rest.next() == Some(&Bytecode::Ret)
+            }
+            _ => false,
+        };
+        if tail {
+            code[position] = Bytecode::TailCall { function_label };
+        }
+    }
+}
+
+/// Jump targets of a loop being compiled
+#[derive(Debug, Clone, Copy)]
+struct LoopLabels<'a> {
+    name: Option<&'a str>,
+    /// Where `continue` goes: the condition of `while`, the step of `for`
+    next: u64,
+    /// Where `break` goes: right after the loop
+    exit: u64,
+}
+
+/// Loops enclosing the statement being compiled, innermost last
+#[derive(Debug, Default)]
+pub struct Loops<'a>(Vec<LoopLabels<'a>>);
+
+impl<'a> Loops<'a> {
+    pub fn enter(&mut self, name: Option<&'a Identifier>, next: u64, exit: u64) {
+        self.0.push(LoopLabels {
+            name: name.map(|name| name.name.as_str()),
+            next,
+            exit,
+        });
+    }
+
+    pub fn leave(&mut self) {
+        let _: Option<LoopLabels<'_>> = self.0.pop();
+    }
+
+    fn find(&self, label: Option<&Identifier>) -> Option<LoopLabels<'a>> {
+        match label {
+            None => self.0.last().copied(),
+            Some(label) => self
+                .0
+                .iter()
+                .rev()
+                .find(|labels| labels.name == Some(label.name.as_str()))
+                .copied(),
+        }
+    }
+
+    /// Lowers `ast::Statement::Break`, `None` for the ones `flow::check_loop_exits` rejects
+    #[must_use]
+    pub fn break_jump(&self, label: Option<&Identifier>) -> Option<Bytecode> {
+        self.find(label)
+            .map(|labels| Bytecode::Jump { label: labels.exit })
+    }
+
+    /// Lowers `ast::Statement::Continue`
+    #[must_use]
+    pub fn continue_jump(&self, label: Option<&Identifier>) -> Option<Bytecode> {
+        self.find(label)
+            .map(|labels| Bytecode::Jump { label: labels.next })
+    }
+}
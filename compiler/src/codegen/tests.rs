@@ -0,0 +1,105 @@
+use super::*;
+
+fn name(name: &str) -> Identifier {
+    Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }
+}
+
+#[test]
+fn case_chain() {
+    assert_eq!(
+        case_dispatch(&[(vec![1, 2], 10)], 11),
+        [
+            Bytecode::Dup,
+            Bytecode::IntConst { value: 1 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntEq,
+            },
+            Bytecode::JumpNotZero { label: 10 },
+            Bytecode::Dup,
+            Bytecode::IntConst { value: 2 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntEq,
+            },
+            Bytecode::JumpNotZero { label: 10 },
+            Bytecode::Jump { label: 11 },
+        ]
+    );
+}
+
+#[test]
+fn short_circuit_jumps_over_rhs() {
+    let lhs = vec![Bytecode::IntConst { value: 1 }];
+    let rhs = vec![Bytecode::Call { function_label: 0 }];
+    assert_eq!(
+        short_circuit(BinaryOperator::Or, lhs.clone(), rhs.clone(), 5),
+        Some(vec![
+            Bytecode::IntConst { value: 1 },
+            Bytecode::Dup,
+            Bytecode::JumpNotZero { label: 5 },
+            Bytecode::Drop,
+            Bytecode::Call { function_label: 0 },
+            Bytecode::Label { id: 5 },
+        ])
+    );
+    assert_eq!(short_circuit(BinaryOperator::Xor, lhs, rhs, 5), None);
+}
+
+/// `outer: while ... loop while ... loop ... end; end;`
+#[test]
+fn loop_exits() {
+    let outer = name("outer");
+    let mut loops = Loops::default();
+    assert_eq!(loops.break_jump(None), None);
+
+    loops.enter(Some(&outer), 0, 1);
+    loops.enter(None, 2, 3);
+    assert_eq!(loops.break_jump(None), Some(Bytecode::Jump { label: 3 }));
+    assert_eq!(loops.continue_jump(None), Some(Bytecode::Jump { label: 2 }));
+    assert_eq!(
+        loops.break_jump(Some(&outer)),
+        Some(Bytecode::Jump { label: 1 })
+    );
+    assert_eq!(
+        loops.continue_jump(Some(&outer)),
+        Some(Bytecode::Jump { label: 0 })
+    );
+    assert_eq!(loops.break_jump(Some(&name("inner"))), None);
+
+    loops.leave();
+    assert_eq!(loops.continue_jump(None), Some(Bytecode::Jump { label: 0 }));
+}
+
+#[test]
+fn calls_in_tail_position() {
+    let call = |function_label| Bytecode::Call { function_label };
+    let tail = |function_label| Bytecode::TailCall { function_label };
+    let mut code = [
+        call(0),
+        Bytecode::Label { id: 1 },
+        Bytecode::Ret,
+        call(1),
+        Bytecode::Drop,
+        Bytecode::IntConst { value: 0 },
+        Bytecode::Ret,
+        call(2),
+        Bytecode::Drop,
+        Bytecode::IntConst { value: 1 },
+        Bytecode::Ret,
+        call(3),
+        Bytecode::Print {
+            type_id: TypeId::INTEGER,
+        },
+        call(4),
+    ];
+    tail_calls(&mut code);
+    assert_eq!(code[0], tail(0));
+    assert_eq!(code[3], tail(1));
+    // Returns something else than the callee
+    assert_eq!(code[7], call(2));
+    assert_eq!(code[11], call(3));
+    assert_eq!(code[13], call(4));
+}
@@ -0,0 +1,274 @@
+//! Compile-time evaluation of expressions built from literals
+
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+use std::rc::Rc;
+
+use crate::ast::{
+    BinaryOperator, BoolLiteral, Expression, Identifier, IntegerLiteral, RealLiteral, UnaryOperator,
+};
+use crate::types::Type;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Real(f64),
+    Bool(bool),
+}
+
+impl Constant {
+    fn expression(self) -> Expression {
+        match self {
+            Self::Int(value) => Expression::IntegerLiteral(IntegerLiteral::new(value)),
+            Self::Real(value) => Expression::RealLiteral(RealLiteral::new(value)),
+            Self::Bool(true) => Expression::BoolLiteral(BoolLiteral::True),
+            Self::Bool(false) => Expression::BoolLiteral(BoolLiteral::False),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// Reads a variable, calls a routine or produces a string
+    NotConstant,
+    DivisionByZero,
+    IntegerOverflow,
+    /// Operands the checker would have rejected, or a conversion failing at runtime
+    Invalid(String),
+}
+
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConstant => write!(f, "Expression is not a constant"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::IntegerOverflow => write!(f, "Integer overflow"),
+            Self::Invalid(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl Error for EvaluationError {}
+
+fn truth(value: Constant) -> Result<bool, EvaluationError> {
+    match value {
+        Constant::Bool(value) => Ok(value),
+        Constant::Int(0) => Ok(false),
+        Constant::Int(1) => Ok(true),
+        Constant::Int(_) | Constant::Real(_) => Err(EvaluationError::Invalid(format!(
+            "{value:?} is not a boolean"
+        ))),
+    }
+}
+
+fn real(value: Constant) -> Result<f64, EvaluationError> {
+    match value {
+        #[expect(clippy::cast_precision_loss, reason = "Same as the VM's IntToReal")]
+        Constant::Int(value) => Ok(value as f64),
+        Constant::Real(value) => Ok(value),
+        Constant::Bool(_) => Err(EvaluationError::Invalid(format!(
+            "{value:?} is not a number"
+        ))),
+    }
+}
+
+fn integer(value: Constant) -> Result<i64, EvaluationError> {
+    match value {
+        Constant::Int(value) => Ok(value),
+        Constant::Real(_) | Constant::Bool(_) => Err(EvaluationError::Invalid(format!(
+            "{value:?} is not an integer"
+        ))),
+    }
+}
+
+/// Integer operands give an integer, otherwise both are taken as reals
+fn arithmetic(
+    lhs: Constant,
+    rhs: Constant,
+    int: fn(i64, i64) -> Result<i64, EvaluationError>,
+    real: fn(f64, f64) -> f64,
+) -> Result<Constant, EvaluationError> {
+    match (lhs, rhs) {
+        (Constant::Int(lhs), Constant::Int(rhs)) => int(lhs, rhs).map(Constant::Int),
+        _ => Ok(Constant::Real(real(self::real(lhs)?, self::real(rhs)?))),
+    }
+}
+
+fn checked(result: Option<i64>) -> Result<i64, EvaluationError> {
+    result.ok_or(EvaluationError::IntegerOverflow)
+}
+
+/// `/` rounds towards zero and `%` takes the sign of `lhs`, as in the VM
+fn division(lhs: i64, rhs: i64, f: fn(i64, i64) -> Option<i64>) -> Result<i64, EvaluationError> {
+    if rhs == 0 {
+        Err(EvaluationError::DivisionByZero)
+    } else {
+        checked(f(lhs, rhs))
+    }
+}
+
+/// `None` when a NaN is involved, it compares false to everything but with `/=`
+fn ordering(lhs: Constant, rhs: Constant) -> Result<Option<Ordering>, EvaluationError> {
+    Ok(match (lhs, rhs) {
+        (Constant::Int(lhs), Constant::Int(rhs)) => Some(lhs.cmp(&rhs)),
+        (Constant::Bool(lhs), Constant::Bool(rhs)) => Some(lhs.cmp(&rhs)),
+        _ => real(lhs)?.partial_cmp(&real(rhs)?),
+    })
+}
+
+/// `lhs op rhs`, booleans may be given as integers 0 and 1 like in the VM
+pub fn binary(
+    op: BinaryOperator,
+    lhs: Constant,
+    rhs: Constant,
+) -> Result<Constant, EvaluationError> {
+    let compare = |f: fn(Ordering) -> bool| {
+        ordering(lhs, rhs).map(|ordering| Constant::Bool(ordering.is_some_and(f)))
+    };
+    match op {
+        BinaryOperator::And => Ok(Constant::Bool(truth(lhs)? && truth(rhs)?)),
+        BinaryOperator::Or => Ok(Constant::Bool(truth(lhs)? || truth(rhs)?)),
+        BinaryOperator::Xor => Ok(Constant::Bool(truth(lhs)? != truth(rhs)?)),
+        BinaryOperator::Le => compare(Ordering::is_le),
+        BinaryOperator::Lg => compare(Ordering::is_lt),
+        BinaryOperator::Gt => compare(Ordering::is_gt),
+        BinaryOperator::Ge => compare(Ordering::is_ge),
+        BinaryOperator::Eq => compare(Ordering::is_eq),
+        BinaryOperator::Neq => {
+            ordering(lhs, rhs).map(|ordering| Constant::Bool(ordering != Some(Ordering::Equal)))
+        }
+        BinaryOperator::Mul => arithmetic(lhs, rhs, |l, r| checked(l.checked_mul(r)), |l, r| l * r),
+        BinaryOperator::Div => arithmetic(
+            lhs,
+            rhs,
+            |l, r| division(l, r, i64::checked_div),
+            |l, r| l / r,
+        ),
+        BinaryOperator::Mod => {
+            division(integer(lhs)?, integer(rhs)?, i64::checked_rem).map(Constant::Int)
+        }
+        BinaryOperator::Add => arithmetic(lhs, rhs, |l, r| checked(l.checked_add(r)), |l, r| l + r),
+        BinaryOperator::Sub => arithmetic(lhs, rhs, |l, r| checked(l.checked_sub(r)), |l, r| l - r),
+    }
+}
+
+/// `op value`, `not` takes integers 0 and 1 like `binary`
+pub fn unary(op: UnaryOperator, value: Constant) -> Result<Constant, EvaluationError> {
+    match (op, value) {
+        (UnaryOperator::Neg, Constant::Int(value)) => {
+            checked(value.checked_neg()).map(Constant::Int)
+        }
+        (UnaryOperator::Neg, Constant::Real(value)) => Ok(Constant::Real(-value)),
+        (UnaryOperator::Neg, Constant::Bool(_)) => Err(EvaluationError::Invalid(format!(
+            "{value:?} is not a number"
+        ))),
+        (UnaryOperator::Not, _) => truth(value).map(|value| Constant::Bool(!value)),
+    }
+}
+
+/// Truncates like the VM's `RealToInt`
+fn real_to_integer(value: Constant) -> Result<Constant, EvaluationError> {
+    let value = real(value)?.trunc();
+    #[expect(clippy::cast_precision_loss, reason = "Bounds are powers of two")]
+    if value.is_finite() && (i64::MIN as f64..-(i64::MIN as f64)).contains(&value) {
+        #[expect(clippy::cast_possible_truncation, reason = "Range is checked")]
+        Ok(Constant::Int(value as i64))
+    } else {
+        Err(EvaluationError::Invalid(format!(
+            "{value:?} does not fit into an integer"
+        )))
+    }
+}
+
+fn int_to_bool(value: Constant) -> Result<Constant, EvaluationError> {
+    integer(value).and_then(|value| truth(Constant::Int(value)).map(Constant::Bool))
+}
+
+/// Value of `expression` computed the same way the VM would
+pub fn evaluate(expression: &Expression) -> Result<Constant, EvaluationError> {
+    match expression {
+        Expression::IntegerLiteral(literal) => Ok(Constant::Int(literal.value())),
+        Expression::RealLiteral(literal) => Ok(Constant::Real(literal.value())),
+        Expression::BoolLiteral(literal) => Ok(Constant::Bool(*literal == BoolLiteral::True)),
+        Expression::Binop { op, lhs, rhs } => binary(*op, evaluate(lhs)?, evaluate(rhs)?),
+        Expression::Unop { op, value } => unary(*op, evaluate(value)?),
+        Expression::BoolToInt(value) => truth(evaluate(value)?).map(|b| Constant::Int(b.into())),
+        Expression::RealToInt(value) => real_to_integer(evaluate(value)?),
+        Expression::IntToBool(value) => int_to_bool(evaluate(value)?),
+        Expression::Conversion { target, value } => {
+            let value = evaluate(value)?;
+            match (&**target, value) {
+                (Type::Int, Constant::Int(_)) | (Type::Real, Constant::Real(_)) => Ok(value),
+                (Type::Int, Constant::Real(_)) => real_to_integer(value),
+                (Type::Int, Constant::Bool(value)) => Ok(Constant::Int(value.into())),
+                (Type::Real, Constant::Int(_)) => real(value).map(Constant::Real),
+                (Type::Bool, _) => int_to_bool(value),
+                // Aliases keep the representation, but the result is no longer a primitive literal
+                _ => Err(EvaluationError::NotConstant),
+            }
+        }
+        Expression::LvalueToRvalue(_) | Expression::StringLiteral(_) | Expression::Call { .. } => {
+            Err(EvaluationError::NotConstant)
+        }
+    }
+}
+
+/// Replaces the constant subexpressions of `expression` with literals.
+/// The ones that fail to evaluate are kept, so that the error is reported at runtime.
+#[must_use]
+pub fn fold(expression: &Rc<Expression>) -> Rc<Expression> {
+    if let Ok(value) = evaluate(expression) {
+        return Rc::new(value.expression());
+    }
+    let folded = |value: &Rc<Expression>| fold(value);
+    Rc::new(match &**expression {
+        Expression::Binop { op, lhs, rhs } => Expression::Binop {
+            op: *op,
+            lhs: folded(lhs),
+            rhs: folded(rhs),
+        },
+        Expression::Unop { op, value } => Expression::Unop {
+            op: *op,
+            value: folded(value),
+        },
+        Expression::Call { callee, args } => Expression::Call {
+            callee: Identifier {
+                name: callee.name.clone(),
+                module: callee.module.clone(),
+                id: callee.id,
+            },
+            args: args.iter().map(folded).collect(),
+        },
+        Expression::BoolToInt(value) => Expression::BoolToInt(folded(value)),
+        Expression::RealToInt(value) => Expression::RealToInt(folded(value)),
+        Expression::IntToBool(value) => Expression::IntToBool(folded(value)),
+        Expression::Conversion { target, value } => Expression::Conversion {
+            target: Rc::clone(target),
+            value: folded(value),
+        },
+        Expression::LvalueToRvalue(_)
+        | Expression::IntegerLiteral(_)
+        | Expression::RealLiteral(_)
+        | Expression::BoolLiteral(_)
+        | Expression::StringLiteral(_) => return Rc::clone(expression),
+    })
+}
+
+/// Number of elements of `array [size] t`, the size has to be a positive integer constant
+pub fn array_length(size: &Expression) -> Result<usize, String> {
+    match evaluate(size) {
+        Ok(Constant::Int(length)) => usize::try_from(length)
+            .ok()
+            .filter(|&length| length > 0)
+            .ok_or_else(|| format!("Array size {length} is not positive")),
+        Ok(value) => Err(format!("Array size {value:?} is not an integer")),
+        Err(EvaluationError::NotConstant) => {
+            Err("Array size has to be a compile-time constant".to_owned())
+        }
+        Err(e) => Err(format!("Array size cannot be evaluated: {e}")),
+    }
+}
@@ -0,0 +1,130 @@
+use super::*;
+use crate::ast::{Identifier, LvalueExpression};
+
+fn int(value: i64) -> Rc<Expression> {
+    Rc::new(Expression::IntegerLiteral(IntegerLiteral::new(value)))
+}
+
+fn real(value: f64) -> Rc<Expression> {
+    Rc::new(Expression::RealLiteral(RealLiteral::new(value)))
+}
+
+fn binop(op: BinaryOperator, lhs: Rc<Expression>, rhs: Rc<Expression>) -> Rc<Expression> {
+    Rc::new(Expression::Binop { op, lhs, rhs })
+}
+
+fn variable(name: &str) -> Rc<Expression> {
+    Rc::new(Expression::LvalueToRvalue(Rc::new(
+        LvalueExpression::Identifier(Identifier {
+            name: name.to_owned(),
+            module: None,
+            id: None,
+        }),
+    )))
+}
+
+#[test]
+fn arithmetic() {
+    let eval = |op, lhs, rhs| evaluate(&binop(op, lhs, rhs));
+    assert_eq!(
+        eval(BinaryOperator::Div, int(7), int(2)),
+        Ok(Constant::Int(3))
+    );
+    assert_eq!(
+        eval(BinaryOperator::Mod, int(-7), int(2)),
+        Ok(Constant::Int(-1))
+    );
+    assert_eq!(
+        eval(BinaryOperator::Div, int(7), real(2.0)),
+        Ok(Constant::Real(3.5))
+    );
+    assert_eq!(
+        eval(BinaryOperator::Div, int(1), int(0)),
+        Err(EvaluationError::DivisionByZero)
+    );
+    assert_eq!(
+        eval(BinaryOperator::Mul, int(i64::MAX), int(2)),
+        Err(EvaluationError::IntegerOverflow)
+    );
+    assert_eq!(
+        eval(
+            BinaryOperator::Lg,
+            binop(BinaryOperator::Add, int(1), int(2)),
+            real(3.5)
+        ),
+        Ok(Constant::Bool(true))
+    );
+    assert_eq!(
+        eval(BinaryOperator::Add, int(1), variable("n")),
+        Err(EvaluationError::NotConstant)
+    );
+}
+
+#[test]
+fn conversions() {
+    let truncated = Expression::RealToInt(real(-2.75));
+    assert_eq!(evaluate(&truncated), Ok(Constant::Int(-2)));
+    let conversion = |target, value| evaluate(&Expression::Conversion { target, value });
+    assert_eq!(
+        conversion(Rc::new(Type::Real), int(2)),
+        Ok(Constant::Real(2.0))
+    );
+    assert_eq!(
+        conversion(Rc::new(Type::Bool), int(1)),
+        Ok(Constant::Bool(true))
+    );
+    assert_eq!(
+        conversion(Rc::new(Type::Bool), int(2)),
+        Err(EvaluationError::Invalid(
+            "Int(2) is not a boolean".to_owned()
+        ))
+    );
+    assert_eq!(
+        evaluate(&Expression::RealToInt(real(f64::NAN))),
+        Err(EvaluationError::Invalid(
+            "NaN does not fit into an integer".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn folding_keeps_runtime_parts() {
+    // n + 2 * 3 and 1 / 0 stay
+    let sum = binop(
+        BinaryOperator::Add,
+        variable("n"),
+        binop(BinaryOperator::Mul, int(2), int(3)),
+    );
+    assert_eq!(
+        fold(&sum),
+        binop(BinaryOperator::Add, variable("n"), int(6))
+    );
+
+    let division = binop(BinaryOperator::Div, int(1), int(0));
+    assert_eq!(fold(&division), division);
+}
+
+#[test]
+fn array_lengths() {
+    let reason = |size: &Expression| array_length(size).err();
+    assert_eq!(
+        array_length(&binop(BinaryOperator::Add, int(1), int(2))).ok(),
+        Some(3)
+    );
+    assert_eq!(
+        reason(&binop(BinaryOperator::Sub, int(1), int(2))),
+        Some("Array size -1 is not positive".to_owned())
+    );
+    assert_eq!(
+        reason(&variable("n")),
+        Some("Array size has to be a compile-time constant".to_owned())
+    );
+    assert_eq!(
+        reason(&binop(BinaryOperator::Mod, int(4), int(0))),
+        Some("Array size cannot be evaluated: Division by zero".to_owned())
+    );
+    assert_eq!(
+        reason(&real(2.0)),
+        Some("Array size Real(2.0) is not an integer".to_owned())
+    );
+}
@@ -0,0 +1,364 @@
+//! Reference documentation for a program, rendered from the doc comments
+//! attached to its declarations
+
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use std::collections::HashSet;
+
+use crate::ast::{
+    Declaration, Documentation, Identifier, Program, RoutineDeclaration, SimpleDeclaration,
+    TypeDeclaration, VariableDeclaration,
+};
+use crate::consteval;
+use crate::types::Type;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("Unknown documentation format {s:?}")),
+        }
+    }
+}
+
+/// Renders a reference page listing types, routines and global variables of `program`
+#[must_use]
+#[expect(clippy::missing_panics_doc, reason = "Writing to a string won't fail")]
+pub fn render(program: &Program, title: &str, format: Format) -> String {
+    let mut types = Vec::new();
+    let mut routines: Vec<&RoutineDeclaration> = Vec::new();
+    let mut globals = Vec::new();
+    for declaration in &program.declarations {
+        match declaration {
+            Declaration::Simple(SimpleDeclaration::Type(t)) => types.push(t),
+            Declaration::Simple(SimpleDeclaration::Variable(v)) => globals.push(v),
+            Declaration::Routine(r) => {
+                match routines
+                    .iter()
+                    .position(|known| known.name.name == r.name.name)
+                {
+                    // Listed where it is declared first, as it is defined
+                    Some(index) if r.body.is_some() => routines[index] = r,
+                    Some(_) => {}
+                    None => routines.push(r),
+                }
+            }
+            Declaration::Import(_) => {}
+        }
+    }
+
+    let mut renderer = Renderer {
+        format,
+        type_names: types.iter().map(|t| t.name.name.as_str()).collect(),
+        out: String::new(),
+    };
+    renderer
+        .document(title, &types, &routines, &globals)
+        .expect("Writing to a string won't fail");
+    renderer.out
+}
+
+struct Renderer<'a> {
+    format: Format,
+    /// Types declared in the program, those get cross-linked
+    type_names: HashSet<&'a str>,
+    out: String,
+}
+
+fn anchor(kind: &str, name: &Identifier) -> String {
+    format!("{kind}-{}", name.name)
+}
+
+impl Renderer<'_> {
+    fn text(&mut self, text: &str) -> fmt::Result {
+        for ch in text.chars() {
+            match (self.format, ch) {
+                (Format::Markdown, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#') => {
+                    write!(self.out, "\\{ch}")?
+                }
+                (Format::Html, '&') => self.out.write_str("&amp;")?,
+                (Format::Html, '<') => self.out.write_str("&lt;")?,
+                (Format::Html, '>') => self.out.write_str("&gt;")?,
+                (Format::Html, '"') => self.out.write_str("&quot;")?,
+                (Format::Markdown | Format::Html, _) => self.out.write_char(ch)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn link(&mut self, anchor: &str, text: &str) -> fmt::Result {
+        match self.format {
+            Format::Markdown => {
+                self.out.write_char('[')?;
+                self.text(text)?;
+                write!(self.out, "](#{anchor})")
+            }
+            Format::Html => {
+                write!(self.out, "<a href=\"#{anchor}\">")?;
+                self.text(text)?;
+                self.out.write_str("</a>")
+            }
+        }
+    }
+
+    fn type_ref(&mut self, t: &Type) -> fmt::Result {
+        match t {
+            Type::Int => self.out.write_str("integer"),
+            Type::Real => self.out.write_str("real"),
+            Type::Bool => self.out.write_str("boolean"),
+            Type::String => self.out.write_str("string"),
+            Type::Alias(name)
+                if name.module.is_none() && self.type_names.contains(name.name.as_str()) =>
+            {
+                self.link(&anchor("type", name), &name.name)
+            }
+            Type::Alias(name) => self.text(&name.to_string()),
+            Type::Record(record) => {
+                self.out.write_str("record")?;
+                for field in &record.fields {
+                    self.out.write_str(" var ")?;
+                    self.text(&field.name.name)?;
+                    self.out.write_str(" : ")?;
+                    self.type_ref(&field.t)?;
+                    self.out.write_char(';')?;
+                }
+                self.out.write_str(" end")
+            }
+            Type::Enum(description) => {
+                self.out.write_str("enum ")?;
+                for (i, variant) in description.variants.iter().enumerate() {
+                    if i != 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    self.text(&variant.name)?;
+                }
+                self.out.write_str(" end")
+            }
+            Type::Array(array) => {
+                // Documented programs are not checked, so the size is evaluated here
+                let length = array.length.or_else(|| {
+                    let size = array.size.as_deref()?;
+                    consteval::array_length(size).ok()
+                });
+                match length {
+                    Some(length) => write!(self.out, "array [{length}] ")?,
+                    None => self.out.write_str("array [] ")?,
+                }
+                self.type_ref(&array.t)
+            }
+        }
+    }
+
+    fn begin_signature(&mut self) -> fmt::Result {
+        match self.format {
+            Format::Markdown => Ok(()),
+            Format::Html => self.out.write_str("<pre><code>"),
+        }
+    }
+
+    fn end_signature(&mut self) -> fmt::Result {
+        match self.format {
+            Format::Markdown => self.out.write_str("\n\n"),
+            Format::Html => self.out.write_str("</code></pre>\n"),
+        }
+    }
+
+    fn heading(&mut self, level: usize, anchor: Option<&str>, title: &str) -> fmt::Result {
+        match (self.format, anchor) {
+            (Format::Markdown, None) => write!(self.out, "{} ", "#".repeat(level))?,
+            // Renderers only keep inline HTML out of headings when it is a block of its own
+            (Format::Markdown, Some(anchor)) => write!(
+                self.out,
+                "<a id=\"{anchor}\"></a>\n\n{} ",
+                "#".repeat(level)
+            )?,
+            (Format::Html, None) => write!(self.out, "<h{level}>")?,
+            (Format::Html, Some(anchor)) => write!(self.out, "<h{level} id=\"{anchor}\">")?,
+        }
+        self.text(title)?;
+        match self.format {
+            Format::Markdown => self.out.write_str("\n\n"),
+            Format::Html => writeln!(self.out, "</h{level}>"),
+        }
+    }
+
+    fn documentation(&mut self, doc: &Documentation) -> fmt::Result {
+        if doc.is_empty() {
+            return Ok(());
+        }
+        match self.format {
+            // Doc comments are written in Markdown already
+            Format::Markdown => {
+                for line in doc.lines() {
+                    writeln!(self.out, "{line}")?;
+                }
+                self.out.write_char('\n')
+            }
+            Format::Html => {
+                self.out.write_str("<p>")?;
+                for (i, line) in doc.lines().iter().enumerate() {
+                    if i != 0 {
+                        self.out.write_char('\n')?;
+                    }
+                    self.text(line)?;
+                }
+                self.out.write_str("</p>\n")
+            }
+        }
+    }
+
+    fn type_declaration(&mut self, declaration: &TypeDeclaration) -> fmt::Result {
+        let TypeDeclaration {
+            doc,
+            position: _,
+            name,
+            t,
+        } = declaration;
+        self.heading(3, Some(&anchor("type", name)), &name.name)?;
+        self.begin_signature()?;
+        self.out.write_str("type ")?;
+        self.text(&name.name)?;
+        self.out.write_str(" is ")?;
+        match &**t {
+            // Fields are listed separately below
+            Type::Record(_) => self.out.write_str("record")?,
+            Type::Int
+            | Type::Real
+            | Type::Bool
+            | Type::String
+            | Type::Alias(_)
+            | Type::Array(_)
+            | Type::Enum(_) => self.type_ref(t)?,
+        }
+        self.end_signature()?;
+        self.documentation(doc)?;
+
+        let Type::Record(record) = &**t else {
+            return Ok(());
+        };
+        if self.format == Format::Html {
+            self.out.write_str("<ul>\n")?;
+        }
+        for field in &record.fields {
+            self.out.write_str(match self.format {
+                Format::Markdown => "- ",
+                Format::Html => "<li>",
+            })?;
+            self.text(&field.name.name)?;
+            self.out.write_str(" : ")?;
+            self.type_ref(&field.t)?;
+            self.out.write_str(match self.format {
+                Format::Markdown => "\n",
+                Format::Html => "</li>\n",
+            })?;
+        }
+        self.out.write_str(match self.format {
+            Format::Markdown => "\n",
+            Format::Html => "</ul>\n",
+        })
+    }
+
+    fn routine_declaration(&mut self, declaration: &RoutineDeclaration) -> fmt::Result {
+        let RoutineDeclaration {
+            doc,
+            position: _,
+            name,
+            parameters,
+            result,
+            body: _,
+        } = declaration;
+        self.heading(3, Some(&anchor("routine", name)), &name.name)?;
+        self.begin_signature()?;
+        self.out.write_str("routine ")?;
+        self.text(&name.name)?;
+        self.out.write_char('(')?;
+        for (i, parameter) in parameters.iter().enumerate() {
+            if i != 0 {
+                self.out.write_str(", ")?;
+            }
+            self.text(&parameter.name.name)?;
+            self.out.write_str(" : ")?;
+            self.type_ref(&parameter.t)?;
+        }
+        self.out.write_char(')')?;
+        if let Some(result) = result {
+            self.out.write_str(" : ")?;
+            self.type_ref(result)?;
+        }
+        self.end_signature()?;
+        self.documentation(doc)
+    }
+
+    fn variable_declaration(&mut self, declaration: &VariableDeclaration) -> fmt::Result {
+        let VariableDeclaration {
+            doc,
+            position: _,
+            name,
+            t,
+            initializer: _,
+        } = declaration;
+        self.heading(3, Some(&anchor("var", name)), &name.name)?;
+        self.begin_signature()?;
+        self.out.write_str("var ")?;
+        self.text(&name.name)?;
+        // Otherwise the type is inferred from the initializer
+        if let Some(t) = t {
+            self.out.write_str(" : ")?;
+            self.type_ref(t)?;
+        }
+        self.end_signature()?;
+        self.documentation(doc)
+    }
+
+    fn document(
+        &mut self,
+        title: &str,
+        types: &[&TypeDeclaration],
+        routines: &[&RoutineDeclaration],
+        globals: &[&VariableDeclaration],
+    ) -> fmt::Result {
+        if self.format == Format::Html {
+            self.out
+                .write_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>")?;
+            self.text(title)?;
+            self.out.write_str("</title>\n</head>\n<body>\n")?;
+        }
+        self.heading(1, None, title)?;
+
+        if !types.is_empty() {
+            self.heading(2, None, "Types")?;
+            for declaration in types {
+                self.type_declaration(declaration)?;
+            }
+        }
+        if !routines.is_empty() {
+            self.heading(2, None, "Routines")?;
+            for declaration in routines {
+                self.routine_declaration(declaration)?;
+            }
+        }
+        if !globals.is_empty() {
+            self.heading(2, None, "Global variables")?;
+            for declaration in globals {
+                self.variable_declaration(declaration)?;
+            }
+        }
+
+        if self.format == Format::Html {
+            self.out.write_str("</body>\n</html>\n")?;
+        }
+        Ok(())
+    }
+}
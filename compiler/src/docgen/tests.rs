@@ -0,0 +1,272 @@
+use std::rc::Rc;
+
+use expect_test::expect;
+
+use super::*;
+use crate::ast::Parameter;
+use crate::tokens::Position;
+use crate::types::{ArrayDescription, FieldDescription, RecordDeclaration};
+
+fn ident(name: &str) -> Identifier {
+    Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }
+}
+
+fn doc(lines: &[&str]) -> Documentation {
+    let mut doc = Documentation::default();
+    for line in lines {
+        doc.push_line(line);
+    }
+    doc
+}
+
+fn alias(name: &str) -> Rc<Type> {
+    Rc::new(Type::Alias(ident(name)))
+}
+
+/// Declarations of `tests/src/arrays_and_records.i` with some doc comments
+fn program() -> Program {
+    let point = TypeDeclaration {
+        doc: doc(&[" A point on a plane"]),
+        position: Position::begin(),
+        name: ident("point"),
+        t: Rc::new(Type::Record(RecordDeclaration {
+            fields: vec![
+                FieldDescription {
+                    name: ident("x"),
+                    t: Rc::new(Type::Real),
+                },
+                FieldDescription {
+                    name: ident("y"),
+                    t: Rc::new(Type::Real),
+                },
+            ],
+        })),
+    };
+    let triangle = TypeDeclaration {
+        doc: Documentation::default(),
+        position: Position::begin(),
+        name: ident("triangle"),
+        t: Rc::new(Type::Array(ArrayDescription {
+            t: alias("point"),
+            size: None,
+            length: Some(3),
+        })),
+    };
+    let eps = VariableDeclaration {
+        doc: doc(&[" Precision of <real> comparisons"]),
+        position: Position::begin(),
+        name: ident("EPS"),
+        t: None,
+        initializer: None,
+    };
+    let is_right = RoutineDeclaration {
+        doc: doc(&[
+            " Checks whether a triangle has a right angle,",
+            " *approximately*",
+        ]),
+        position: Position::begin(),
+        name: ident("is_right"),
+        parameters: vec![Parameter {
+            name: ident("t"),
+            t: alias("triangle"),
+        }],
+        result: Some(Rc::new(Type::Bool)),
+        body: None,
+    };
+    let sum = RoutineDeclaration {
+        doc: Documentation::default(),
+        position: Position::begin(),
+        name: ident("sum"),
+        parameters: vec![Parameter {
+            name: ident("values"),
+            t: Rc::new(Type::Array(ArrayDescription {
+                t: Rc::new(Type::Int),
+                size: None,
+                length: None,
+            })),
+        }],
+        result: Some(Rc::new(Type::Int)),
+        body: None,
+    };
+
+    Program {
+        declarations: vec![
+            Declaration::Simple(SimpleDeclaration::Type(point)),
+            Declaration::Simple(SimpleDeclaration::Type(triangle)),
+            Declaration::Simple(SimpleDeclaration::Variable(eps)),
+            Declaration::Routine(is_right),
+            Declaration::Routine(sum),
+        ],
+    }
+}
+
+#[test]
+fn markdown() {
+    expect![[r#"
+        # arrays\_and\_records
+
+        ## Types
+
+        <a id="type-point"></a>
+
+        ### point
+
+        type point is record
+
+        A point on a plane
+
+        - x : real
+        - y : real
+
+        <a id="type-triangle"></a>
+
+        ### triangle
+
+        type triangle is array [3] [point](#type-point)
+
+        ## Routines
+
+        <a id="routine-is_right"></a>
+
+        ### is\_right
+
+        routine is\_right(t : [triangle](#type-triangle)) : boolean
+
+        Checks whether a triangle has a right angle,
+        *approximately*
+
+        <a id="routine-sum"></a>
+
+        ### sum
+
+        routine sum(values : array [] integer) : integer
+
+        ## Global variables
+
+        <a id="var-EPS"></a>
+
+        ### EPS
+
+        var EPS
+
+        Precision of <real> comparisons
+
+    "#]]
+    .assert_eq(&render(&program(), "arrays_and_records", Format::Markdown));
+}
+
+#[test]
+fn html() {
+    expect![[r##"
+        <!DOCTYPE html>
+        <html>
+        <head>
+        <meta charset="utf-8">
+        <title>arrays_and_records</title>
+        </head>
+        <body>
+        <h1>arrays_and_records</h1>
+        <h2>Types</h2>
+        <h3 id="type-point">point</h3>
+        <pre><code>type point is record</code></pre>
+        <p>A point on a plane</p>
+        <ul>
+        <li>x : real</li>
+        <li>y : real</li>
+        </ul>
+        <h3 id="type-triangle">triangle</h3>
+        <pre><code>type triangle is array [3] <a href="#type-point">point</a></code></pre>
+        <h2>Routines</h2>
+        <h3 id="routine-is_right">is_right</h3>
+        <pre><code>routine is_right(t : <a href="#type-triangle">triangle</a>) : boolean</code></pre>
+        <p>Checks whether a triangle has a right angle,
+        *approximately*</p>
+        <h3 id="routine-sum">sum</h3>
+        <pre><code>routine sum(values : array [] integer) : integer</code></pre>
+        <h2>Global variables</h2>
+        <h3 id="var-EPS">EPS</h3>
+        <pre><code>var EPS</code></pre>
+        <p>Precision of &lt;real&gt; comparisons</p>
+        </body>
+        </html>
+    "##]]
+    .assert_eq(&render(&program(), "arrays_and_records", Format::Html));
+}
+
+/// Doc comments come from the source through the parser
+#[test]
+fn parsed_doc_comments() {
+    let source = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/src/comments.i"
+    ))
+    .expect("Test program exists");
+    let tokens: Vec<_> = crate::lexer::Lexer::from(source.as_str()).collect();
+    let program = crate::parser::parse(&tokens).expect("The program is valid");
+    expect![[r#"
+        # comments
+
+        ## Routines
+
+        <a id="routine-distance"></a>
+
+        ### distance
+
+        routine distance(a : real, b : real) : real
+
+        Distance between two points,
+        always non-negative
+
+        ## Global variables
+
+        <a id="var-SCALE"></a>
+
+        ### SCALE
+
+        var SCALE
+
+        Scale factor
+
+    "#]]
+    .assert_eq(&render(&program, "comments", Format::Markdown));
+}
+
+/// A routine declared ahead is listed once, where it is declared first, as it is defined
+#[test]
+fn forward_declarations() {
+    let source = "
+        routine half(x : integer) : real;
+        --- Called before it is defined
+        routine main() is print half(3); end;
+        --- Half of `x`
+        routine half(x : integer) : real => x / 2.0;
+    ";
+    let program = crate::parse(&crate::lex(source)).expect("The program is valid");
+    expect![[r#"
+        # halves
+
+        ## Routines
+
+        <a id="routine-half"></a>
+
+        ### half
+
+        routine half(x : integer) : real
+
+        Half of `x`
+
+        <a id="routine-main"></a>
+
+        ### main
+
+        routine main()
+
+        Called before it is defined
+
+    "#]]
+    .assert_eq(&render(&program, "halves", Format::Markdown));
+}
@@ -0,0 +1,498 @@
+//! Control flow checks of routine bodies
+
+use core::mem;
+use std::rc::Rc;
+
+use crate::ast::{
+    Block, BlockElement, BoolLiteral, CaseBranch, CaseLabel, Expression, Identifier,
+    LvalueExpression, RoutineBody, RoutineDeclaration, SimpleDeclaration, Statement,
+};
+use crate::tokens::Position;
+use crate::types::{Aliases, Type, TypeInferenceError};
+
+#[cfg(test)]
+mod tests;
+
+fn error(position: Position, reason: String) -> Result<(), TypeInferenceError> {
+    Err(TypeInferenceError { position, reason })
+}
+
+/// Checks that `break` and `continue` appear only inside loops and name enclosing ones
+pub fn check_loop_exits(body: &Block) -> Result<(), TypeInferenceError> {
+    block_loop_exits(body, &mut Vec::new())
+}
+
+/// `loops` has labels of the enclosing loops, innermost last
+fn block_loop_exits<'a>(
+    block: &'a Block,
+    loops: &mut Vec<Option<&'a str>>,
+) -> Result<(), TypeInferenceError> {
+    for element in block.elements() {
+        let &BlockElement::Stmt(position, ref statement) = element else {
+            continue;
+        };
+        match &**statement {
+            Statement::While { label, body, .. } | Statement::For { label, body, .. } => {
+                let label = label.as_ref().map(|label| label.name.as_str());
+                if let Some(name) = label
+                    && loops.contains(&label)
+                {
+                    return error(
+                        position,
+                        format!("Loop label `{name}` is already used by an enclosing loop"),
+                    );
+                }
+                loops.push(label);
+                block_loop_exits(body, loops)?;
+                let _: Option<Option<&str>> = loops.pop();
+            }
+            Statement::If {
+                on_true, on_false, ..
+            } => {
+                block_loop_exits(on_true, loops)?;
+                if let Some(on_false) = on_false {
+                    block_loop_exits(on_false, loops)?;
+                }
+            }
+            Statement::Case {
+                branches,
+                otherwise,
+                ..
+            } => {
+                for branch in branches {
+                    block_loop_exits(&branch.body, loops)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    block_loop_exits(otherwise, loops)?;
+                }
+            }
+            Statement::Break { label } => loop_exit(position, "break", label.as_ref(), loops)?,
+            Statement::Continue { label } => {
+                loop_exit(position, "continue", label.as_ref(), loops)?;
+            }
+            Statement::Assignment { .. }
+            | Statement::Call { .. }
+            | Statement::Print { .. }
+            | Statement::Return { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+fn loop_exit(
+    position: Position,
+    keyword: &str,
+    label: Option<&Identifier>,
+    loops: &[Option<&str>],
+) -> Result<(), TypeInferenceError> {
+    match label {
+        None if loops.is_empty() => error(position, format!("`{keyword}` outside of a loop")),
+        Some(label) if !loops.contains(&Some(label.name.as_str())) => error(
+            position,
+            format!("`{keyword} {label}` does not name an enclosing loop"),
+        ),
+        None | Some(_) => Ok(()),
+    }
+}
+
+/// What `check_routine` found, only the errors reject the program
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub errors: Vec<TypeInferenceError>,
+    pub warnings: Vec<TypeInferenceError>,
+}
+
+/// Reports routines with a result that can end without `return`, `return` with a value
+/// in procedures and local variables read before they are assigned, warns of unreachable code.
+///
+/// Variables of record, array and enum types, through `aliases` too, are initialized by their
+/// declaration, other ones are assigned by their initializer or an assignment. Branches of
+/// a `case` without `else` cover every value only when they name every variant of an enum
+/// declared in `aliases`, `while true` loops are left only by `break`.
+#[must_use]
+pub fn check_routine(routine: &RoutineDeclaration, aliases: &Aliases) -> Diagnostics {
+    let mut analysis = Analysis {
+        aliases,
+        routine: &routine.name.name,
+        has_result: routine.result.is_some(),
+        names: routine
+            .parameters
+            .iter()
+            .map(|parameter| parameter.name.name.as_str())
+            .collect(),
+        assigned: vec![true; routine.parameters.len()],
+        position: routine.position,
+        diagnostics: Diagnostics::default(),
+    };
+    let falls_off = match &routine.body {
+        Some(RoutineBody::Block(body)) => analysis.block(body),
+        Some(RoutineBody::Expression(value)) => {
+            analysis.expression(value);
+            false
+        }
+        None => false,
+    };
+    if falls_off && analysis.has_result {
+        analysis.position = routine.position;
+        analysis.report(format!(
+            "Routine `{}` may end without returning a value",
+            analysis.routine
+        ));
+    }
+    analysis.diagnostics
+}
+
+/// Records and arrays are allocated, enums start as their first variant.
+/// Unknown aliases are reported by the checker.
+fn is_initialized(t: &Rc<Type>, aliases: &Aliases) -> bool {
+    match aliases.resolve(t).as_deref() {
+        Ok(Type::Record(_) | Type::Array(_) | Type::Enum(_)) => true,
+        Ok(Type::Int | Type::Real | Type::Bool | Type::String | Type::Alias(_)) | Err(_) => false,
+    }
+}
+
+/// Whether `body` of the loop labeled `label` has a `break` leaving it.
+/// `nested` counts the loops inside it around `body`.
+fn breaks(body: &Block, label: Option<&Identifier>, nested: usize) -> bool {
+    body.elements().iter().any(|element| {
+        let BlockElement::Stmt(_, statement) = element else {
+            return false;
+        };
+        match &**statement {
+            Statement::Break { label: None } => nested == 0,
+            Statement::Break { label: Some(name) } => {
+                label.is_some_and(|label| label.name == name.name)
+            }
+            Statement::While { body, .. } | Statement::For { body, .. } => {
+                breaks(body, label, nested + 1)
+            }
+            Statement::If {
+                on_true, on_false, ..
+            } => {
+                breaks(on_true, label, nested)
+                    || on_false
+                        .as_ref()
+                        .is_some_and(|on_false| breaks(on_false, label, nested))
+            }
+            Statement::Case {
+                branches,
+                otherwise,
+                ..
+            } => {
+                branches
+                    .iter()
+                    .any(|branch| breaks(&branch.body, label, nested))
+                    || otherwise
+                        .as_ref()
+                        .is_some_and(|otherwise| breaks(otherwise, label, nested))
+            }
+            Statement::Assignment { .. }
+            | Statement::Call { .. }
+            | Statement::Print { .. }
+            | Statement::Return { .. }
+            | Statement::Continue { .. } => false,
+        }
+    })
+}
+
+struct Analysis<'a> {
+    aliases: &'a Aliases,
+    routine: &'a str,
+    has_result: bool,
+    /// Local variables and parameters in scope, innermost last
+    names: Vec<&'a str>,
+    /// Whether `names[i]` is definitely assigned at the current point
+    assigned: Vec<bool>,
+    /// Of the statement or the declaration being analysed
+    position: Position,
+    diagnostics: Diagnostics,
+}
+
+impl<'a> Analysis<'a> {
+    fn report(&mut self, reason: String) {
+        self.diagnostics.errors.push(TypeInferenceError {
+            position: self.position,
+            reason,
+        });
+    }
+
+    fn warn(&mut self, reason: String) {
+        self.diagnostics.warnings.push(TypeInferenceError {
+            position: self.position,
+            reason,
+        });
+    }
+
+    fn lookup(&self, name: &Identifier) -> Option<usize> {
+        if name.module.is_some() {
+            return None;
+        }
+        self.names.iter().rposition(|&known| known == name.name)
+    }
+
+    /// Returns whether control can reach the end of the block
+    fn block(&mut self, block: &'a Block) -> bool {
+        let scope = self.names.len();
+        let mut reachable = true;
+        for element in block.elements() {
+            self.position = match element {
+                &BlockElement::Stmt(position, _) => position,
+                BlockElement::Decl(declaration) => match &**declaration {
+                    SimpleDeclaration::Variable(variable) => variable.position,
+                    SimpleDeclaration::Type(declaration) => declaration.position,
+                },
+            };
+            if !reachable {
+                self.warn(format!("Unreachable code in routine `{}`", self.routine));
+                break;
+            }
+            match element {
+                BlockElement::Stmt(_, statement) => reachable = self.statement(statement),
+                BlockElement::Decl(declaration) => self.declaration(declaration),
+            }
+        }
+        self.names.truncate(scope);
+        self.assigned.truncate(scope);
+        reachable
+    }
+
+    fn declaration(&mut self, declaration: &'a SimpleDeclaration) {
+        let SimpleDeclaration::Variable(variable) = declaration else {
+            return;
+        };
+        if let Some(initializer) = &variable.initializer {
+            self.expression(initializer);
+        }
+        self.names.push(&variable.name.name);
+        self.assigned.push(
+            variable.initializer.is_some()
+                || variable
+                    .t
+                    .as_ref()
+                    .is_some_and(|t| is_initialized(t, self.aliases)),
+        );
+    }
+
+    /// Runs `first` and `second` from the same state, keeps what is assigned
+    /// on every path reaching the end, returns whether some path does
+    fn branches(
+        &mut self,
+        first: impl FnOnce(&mut Self) -> bool,
+        second: impl FnOnce(&mut Self) -> bool,
+    ) -> bool {
+        let before = self.assigned.clone();
+        let first_reachable = first(self);
+        let after_first = mem::replace(&mut self.assigned, before);
+        let second_reachable = second(self);
+        match (first_reachable, second_reachable) {
+            (true, true) => {
+                for (assigned, after_first) in self.assigned.iter_mut().zip(after_first) {
+                    *assigned &= after_first;
+                }
+            }
+            (true, false) => self.assigned = after_first,
+            (false, true | false) => {}
+        }
+        first_reachable || second_reachable
+    }
+
+    /// Loop bodies may run zero times, so they assign nothing for the code after the loop
+    fn loop_body(&mut self, body: &'a Block) {
+        let before = self.assigned.clone();
+        let _: bool = self.block(body);
+        self.assigned = before;
+    }
+
+    /// Returns whether control can reach the next statement
+    fn statement(&mut self, statement: &'a Statement) -> bool {
+        match statement {
+            Statement::Assignment { lhs, rhs } => {
+                self.expression(rhs);
+                match &**lhs {
+                    LvalueExpression::Identifier(name) => {
+                        if let Some(index) = self.lookup(name) {
+                            self.assigned[index] = true;
+                        }
+                    }
+                    // Stores into a field or an element read the variable holding the reference
+                    LvalueExpression::Member { .. } | LvalueExpression::Index { .. } => {
+                        self.lvalue(lhs);
+                    }
+                }
+                true
+            }
+            Statement::Call { call } => {
+                self.expression(call);
+                true
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                self.expression(condition);
+                self.loop_body(body);
+                !matches!(**condition, Expression::BoolLiteral(BoolLiteral::True))
+                    || breaks(body, label.as_ref(), 0)
+            }
+            Statement::For {
+                identifier,
+                from,
+                to,
+                body,
+                ..
+            } => {
+                self.expression(from);
+                if let Some(to) = to {
+                    self.expression(to);
+                }
+                self.names.push(&identifier.name);
+                self.assigned.push(true);
+                self.loop_body(body);
+                let _: Option<&str> = self.names.pop();
+                let _: Option<bool> = self.assigned.pop();
+                true
+            }
+            Statement::If {
+                condition,
+                on_true,
+                on_false,
+            } => {
+                self.expression(condition);
+                self.branches(
+                    |analysis| analysis.block(on_true),
+                    |analysis| {
+                        on_false
+                            .as_ref()
+                            .is_none_or(|on_false| analysis.block(on_false))
+                    },
+                )
+            }
+            Statement::Case {
+                value,
+                branches,
+                otherwise,
+            } => {
+                self.expression(value);
+                self.case(branches, otherwise.as_ref())
+            }
+            Statement::Print { value } => {
+                self.expression(value);
+                true
+            }
+            Statement::Return { value } => {
+                match (value, self.has_result) {
+                    (Some(value), true) => self.expression(value),
+                    (Some(value), false) => {
+                        self.expression(value);
+                        self.report(format!(
+                            "Procedure `{}` cannot return a value",
+                            self.routine
+                        ));
+                    }
+                    (None, true) => {
+                        self.report(format!("Routine `{}` has to return a value", self.routine))
+                    }
+                    (None, false) => {}
+                }
+                false
+            }
+            Statement::Break { .. } | Statement::Continue { .. } => false,
+        }
+    }
+
+    /// Whether `branches` name every variant of an enum
+    fn covers_enum(&self, branches: &[CaseBranch]) -> bool {
+        let variants: Vec<_> = branches
+            .iter()
+            .flat_map(|branch| &branch.labels)
+            .map_while(|label| match label {
+                CaseLabel::Variant(variant) => Some(variant.name.as_str()),
+                CaseLabel::Integer(_) => None,
+            })
+            .collect();
+        variants
+            .first()
+            .and_then(|&variant| self.aliases.enum_of(variant))
+            .is_some_and(|description| {
+                description
+                    .variants
+                    .iter()
+                    .all(|variant| variants.contains(&variant.name.as_str()))
+            })
+    }
+
+    /// Like `branches`, for any number of them
+    fn case(&mut self, branches: &'a [CaseBranch], otherwise: Option<&'a Block>) -> bool {
+        let exhaustive = otherwise.is_some() || self.covers_enum(branches);
+        let mut reachable = !exhaustive;
+        let mut assigned = None::<Vec<bool>>;
+        let before = self.assigned.clone();
+        for body in branches.iter().map(|branch| &branch.body).chain(otherwise) {
+            self.assigned.clone_from(&before);
+            if self.block(body) {
+                reachable = true;
+                assigned = Some(match assigned {
+                    None => self.assigned.clone(),
+                    Some(mut assigned) => {
+                        for (assigned, &now) in assigned.iter_mut().zip(&self.assigned) {
+                            *assigned &= now;
+                        }
+                        assigned
+                    }
+                });
+            }
+        }
+        self.assigned = match assigned {
+            Some(assigned) if exhaustive => assigned,
+            Some(_) | None => before,
+        };
+        reachable
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::LvalueToRvalue(lvalue) => self.lvalue(lvalue),
+            Expression::IntegerLiteral(_)
+            | Expression::RealLiteral(_)
+            | Expression::BoolLiteral(_)
+            | Expression::StringLiteral(_) => {}
+            Expression::Call { args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::Binop { lhs, rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::Unop { value: inner, .. }
+            | Expression::BoolToInt(inner)
+            | Expression::RealToInt(inner)
+            | Expression::IntToBool(inner)
+            | Expression::Conversion { value: inner, .. } => self.expression(inner),
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &LvalueExpression) {
+        match lvalue {
+            LvalueExpression::Identifier(name) => {
+                if let Some(index) = self.lookup(name)
+                    && !self.assigned[index]
+                {
+                    self.report(format!(
+                        "Variable `{name}` is used before being assigned in routine `{}`",
+                        self.routine
+                    ));
+                    // Reported once
+                    self.assigned[index] = true;
+                }
+            }
+            LvalueExpression::Member { lhs, .. } => self.lvalue(lhs),
+            LvalueExpression::Index { lhs, index } => {
+                self.lvalue(lhs);
+                self.expression(index);
+            }
+        }
+    }
+}
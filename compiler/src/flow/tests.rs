@@ -0,0 +1,287 @@
+use std::rc::Rc;
+
+use super::*;
+use crate::ast::{BoolLiteral, Documentation, IntegerLiteral, LoopOrder, VariableDeclaration};
+
+fn name(name: &str) -> Identifier {
+    Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }
+}
+
+fn block(statements: impl IntoIterator<Item = Statement>) -> Block {
+    Block::new(statements.into_iter().map(statement).collect())
+}
+
+fn while_loop(label: Option<&str>, body: Block) -> Statement {
+    Statement::While {
+        label: label.map(name),
+        condition: Rc::new(Expression::BoolLiteral(BoolLiteral::True)),
+        body,
+    }
+}
+
+/// `while c loop <body> end;`, which may not run
+fn while_c(body: Block) -> Statement {
+    Statement::While {
+        label: None,
+        condition: variable("c"),
+        body,
+    }
+}
+
+fn for_loop(label: Option<&str>, body: Block) -> Statement {
+    Statement::For {
+        label: label.map(name),
+        identifier: name("i"),
+        from: Rc::new(Expression::BoolLiteral(BoolLiteral::False)),
+        to: None,
+        order: LoopOrder::Direct,
+        body,
+    }
+}
+
+fn exit(keyword: &str, label: Option<&str>) -> Statement {
+    let label = label.map(name);
+    match keyword {
+        "break" => Statement::Break { label },
+        _ => Statement::Continue { label },
+    }
+}
+
+fn reason(body: &Block) -> Option<String> {
+    check_loop_exits(body).map_err(|e| e.reason).err()
+}
+
+/// `outer: while ... loop for ... loop if ... then continue outer; end; break; end; end;`
+#[test]
+fn exits_inside_loops() {
+    let body = block([while_loop(
+        Some("outer"),
+        block([for_loop(
+            None,
+            block([
+                Statement::If {
+                    condition: Rc::new(Expression::BoolLiteral(BoolLiteral::True)),
+                    on_true: block([exit("continue", Some("outer"))]),
+                    on_false: None,
+                },
+                exit("break", None),
+            ]),
+        )]),
+    )]);
+    assert_eq!(reason(&body), None);
+}
+
+#[test]
+fn exits_outside_loops() {
+    assert_eq!(
+        reason(&block([exit("break", None)])),
+        Some("`break` outside of a loop".to_owned())
+    );
+    assert_eq!(
+        reason(&block([
+            while_loop(Some("outer"), Block::default()),
+            while_loop(None, block([exit("continue", Some("outer"))])),
+        ])),
+        Some("`continue outer` does not name an enclosing loop".to_owned())
+    );
+    assert_eq!(
+        reason(&block([while_loop(
+            Some("outer"),
+            block([for_loop(Some("outer"), Block::default())]),
+        )])),
+        Some("Loop label `outer` is already used by an enclosing loop".to_owned())
+    );
+}
+
+fn variable(name: &str) -> Rc<Expression> {
+    Rc::new(Expression::LvalueToRvalue(Rc::new(
+        LvalueExpression::Identifier(self::name(name)),
+    )))
+}
+
+fn integer(value: i64) -> Rc<Expression> {
+    Rc::new(Expression::IntegerLiteral(IntegerLiteral::new(value)))
+}
+
+fn declare(name: &str, t: Type, initializer: Option<Rc<Expression>>) -> BlockElement {
+    BlockElement::Decl(Rc::new(SimpleDeclaration::Variable(VariableDeclaration {
+        doc: Documentation::default(),
+        position: Position::begin(),
+        name: self::name(name),
+        t: Some(Rc::new(t)),
+        initializer,
+    })))
+}
+
+fn statement(statement: Statement) -> BlockElement {
+    BlockElement::Stmt(Position::begin(), Rc::new(statement))
+}
+
+fn routine(result: Option<Type>, body: Vec<BlockElement>) -> RoutineDeclaration {
+    RoutineDeclaration {
+        doc: Documentation::default(),
+        position: Position::begin(),
+        name: name("f"),
+        parameters: Vec::new(),
+        result: result.map(Rc::new),
+        body: Some(RoutineBody::Block(Block::new(body))),
+    }
+}
+
+/// Reasons of the errors
+fn diagnostics(routine: &RoutineDeclaration) -> Vec<String> {
+    check_routine(routine, &Aliases::default())
+        .errors
+        .into_iter()
+        .map(|e| e.reason)
+        .collect()
+}
+
+fn warnings(routine: &RoutineDeclaration) -> Vec<String> {
+    check_routine(routine, &Aliases::default())
+        .warnings
+        .into_iter()
+        .map(|e| e.reason)
+        .collect()
+}
+
+fn ret(value: Option<Rc<Expression>>) -> BlockElement {
+    statement(Statement::Return { value })
+}
+
+/// `extra_return` and `no_return` of `invalid.i`
+#[test]
+fn returns() {
+    assert_eq!(
+        diagnostics(&routine(None, vec![ret(Some(integer(10)))])),
+        ["Procedure `f` cannot return a value"]
+    );
+    assert_eq!(
+        diagnostics(&routine(Some(Type::Real), Vec::new())),
+        ["Routine `f` may end without returning a value"]
+    );
+    assert_eq!(
+        diagnostics(&routine(Some(Type::Int), vec![ret(None)])),
+        ["Routine `f` has to return a value"]
+    );
+}
+
+/// `if c then return 1; else return 2; end;` returns on every path, `while c` may not run,
+/// `while true` ends only by `break`
+#[test]
+fn returns_on_every_path() {
+    let condition = Rc::new(Expression::BoolLiteral(BoolLiteral::True));
+    let both = Statement::If {
+        condition: Rc::clone(&condition),
+        on_true: Block::new(vec![ret(Some(integer(1)))]),
+        on_false: Some(Block::new(vec![ret(Some(integer(2)))])),
+    };
+    assert!(diagnostics(&routine(Some(Type::Int), vec![statement(both)])).is_empty());
+
+    let looping = while_c(Block::new(vec![ret(Some(integer(1)))]));
+    assert_eq!(
+        diagnostics(&routine(Some(Type::Int), vec![statement(looping)])),
+        ["Routine `f` may end without returning a value"]
+    );
+
+    let forever = while_loop(None, Block::new(vec![ret(Some(integer(1)))]));
+    assert!(diagnostics(&routine(Some(Type::Int), vec![statement(forever)])).is_empty());
+
+    // Only the `break` of the outer loop leaves it
+    let inner = while_c(Block::new(vec![statement(exit("break", None))]));
+    let forever = while_loop(
+        Some("outer"),
+        Block::new(vec![statement(inner), ret(Some(integer(1)))]),
+    );
+    assert!(diagnostics(&routine(Some(Type::Int), vec![statement(forever)])).is_empty());
+    let leaving = while_c(Block::new(vec![statement(exit("break", Some("outer")))]));
+    let forever = while_loop(
+        Some("outer"),
+        Block::new(vec![statement(leaving), ret(Some(integer(1)))]),
+    );
+    assert_eq!(
+        diagnostics(&routine(Some(Type::Int), vec![statement(forever)])),
+        ["Routine `f` may end without returning a value"]
+    );
+}
+
+/// Code after `return` or `break` is only warned of
+#[test]
+fn unreachable_code() {
+    let returned = routine(
+        None,
+        vec![
+            ret(None),
+            statement(Statement::Print { value: integer(1) }),
+            statement(Statement::Print { value: integer(2) }),
+        ],
+    );
+    assert!(diagnostics(&returned).is_empty());
+    assert_eq!(warnings(&returned), ["Unreachable code in routine `f`"]);
+    let body = Block::new(vec![
+        statement(exit("break", None)),
+        statement(Statement::Print { value: integer(1) }),
+    ]);
+    assert_eq!(
+        warnings(&routine(None, vec![statement(while_loop(None, body))])),
+        ["Unreachable code in routine `f`"]
+    );
+}
+
+/// `var i : real; var j : real; if c then j := 1; end; print j; i := j; print i;`, as `strange_cast` of `invalid.i`
+#[test]
+fn definite_assignment() {
+    let assign = |lhs: &str, rhs| {
+        statement(Statement::Assignment {
+            lhs: Rc::new(LvalueExpression::Identifier(name(lhs))),
+            rhs,
+        })
+    };
+    let body = vec![
+        declare("i", Type::Real, None),
+        declare("j", Type::Real, None),
+        declare("k", Type::Int, Some(integer(0))),
+        statement(Statement::If {
+            condition: variable("k"),
+            on_true: Block::new(vec![assign("j", integer(1))]),
+            on_false: None,
+        }),
+        statement(Statement::Print {
+            value: variable("j"),
+        }),
+        assign("i", variable("j")),
+        statement(Statement::Print {
+            value: variable("i"),
+        }),
+    ];
+    assert_eq!(
+        diagnostics(&routine(None, body)),
+        ["Variable `j` is used before being assigned in routine `f`"]
+    );
+}
+
+/// An inner `var x` assigned in its block does not assign the outer `x`
+#[test]
+fn shadowing() {
+    let inner = Block::new(vec![
+        declare("x", Type::Int, Some(integer(1))),
+        statement(Statement::Print {
+            value: variable("x"),
+        }),
+    ]);
+    let body = vec![
+        declare("x", Type::Int, None),
+        statement(while_c(inner)),
+        statement(Statement::Print {
+            value: variable("x"),
+        }),
+    ];
+    assert_eq!(
+        diagnostics(&routine(None, body)),
+        ["Variable `x` is used before being assigned in routine `f`"]
+    );
+}
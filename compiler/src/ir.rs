@@ -0,0 +1,674 @@
+//! Typed SSA intermediate representation between `types::TypedProgram` and `Bytecode`.
+//!
+//! A routine is a list of basic blocks, the first one is the entry. Each value is defined once
+//! by an instruction, values coming from several predecessors are merged by `Operation::Phi`s
+//! at the start of a block. `passes` optimize it, `lower` turns it into stack code.
+
+use core::fmt;
+use std::collections::BTreeMap;
+
+use crate::bytecode::TypeId;
+use crate::consteval::Constant;
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+use crate::types::TypedProgram;
+
+mod build;
+pub mod cfg;
+pub mod inline;
+pub mod lower;
+pub mod passes;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValueId(pub u32);
+
+impl fmt::Display for ValueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl BlockId {
+    #[must_use]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+/// Booleans are kept apart from integers, although both are `Bytecode::IntConst`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrType {
+    Int,
+    Real,
+    Bool,
+    /// Strings, records and arrays
+    Ref,
+}
+
+impl fmt::Display for IrType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Real => write!(f, "real"),
+            Self::Bool => write!(f, "bool"),
+            Self::Ref => write!(f, "ref"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Const(Constant),
+    /// `Module::strings[id]`
+    String {
+        id: u32,
+    },
+    Param {
+        index: usize,
+    },
+    Copy(ValueId),
+    /// The value coming from each predecessor
+    Phi(Vec<(BlockId, ValueId)>),
+    Binary {
+        op: SemanticBinaryOperator,
+        lhs: ValueId,
+        rhs: ValueId,
+    },
+    Unary {
+        op: SemanticUnaryOperator,
+        value: ValueId,
+    },
+    IntToReal(ValueId),
+    RealToInt(ValueId),
+    IntToBool(ValueId),
+    LoadGlobal {
+        index: usize,
+    },
+    StoreGlobal {
+        index: usize,
+        value: ValueId,
+    },
+    AllocRecord {
+        type_id: TypeId,
+        size: u64,
+    },
+    AllocArray {
+        type_id: TypeId,
+        size: u64,
+    },
+    ArraySize(ValueId),
+    LoadField {
+        record: ValueId,
+        offset: u64,
+    },
+    StoreField {
+        record: ValueId,
+        offset: u64,
+        value: ValueId,
+    },
+    /// Indices start at 1
+    LoadElement {
+        array: ValueId,
+        index: ValueId,
+    },
+    StoreElement {
+        array: ValueId,
+        index: ValueId,
+        value: ValueId,
+    },
+    Call {
+        function_label: u64,
+        args: Vec<ValueId>,
+    },
+    /// See `builtins::Builtin::id`
+    CallNative {
+        id: u32,
+        args: Vec<ValueId>,
+    },
+    Print {
+        type_id: TypeId,
+        value: ValueId,
+    },
+}
+
+impl Operation {
+    /// Values read by the operation, in the order they are pushed when lowered
+    #[must_use]
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Self::Const(_)
+            | Self::String { .. }
+            | Self::Param { .. }
+            | Self::LoadGlobal { .. }
+            | Self::AllocRecord { .. }
+            | Self::AllocArray { .. } => Vec::new(),
+            Self::Copy(value)
+            | Self::Unary { value, .. }
+            | Self::IntToReal(value)
+            | Self::RealToInt(value)
+            | Self::IntToBool(value)
+            | Self::StoreGlobal { value, .. }
+            | Self::ArraySize(value)
+            | Self::LoadField { record: value, .. }
+            | Self::Print { value, .. } => vec![*value],
+            Self::Phi(incoming) => incoming.iter().map(|&(_, value)| value).collect(),
+            Self::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Self::StoreField { record, value, .. } => vec![*value, *record],
+            Self::LoadElement { array, index } => vec![*array, *index],
+            Self::StoreElement {
+                array,
+                index,
+                value,
+            } => vec![*value, *array, *index],
+            Self::Call { args, .. } | Self::CallNative { args, .. } => args.clone(),
+        }
+    }
+
+    /// Replaces every read of a value with `f(value)`
+    pub fn map_operands(&mut self, mut f: impl FnMut(ValueId) -> ValueId) {
+        match self {
+            Self::Const(_)
+            | Self::String { .. }
+            | Self::Param { .. }
+            | Self::LoadGlobal { .. }
+            | Self::AllocRecord { .. }
+            | Self::AllocArray { .. } => {}
+            Self::Copy(value)
+            | Self::Unary { value, .. }
+            | Self::IntToReal(value)
+            | Self::RealToInt(value)
+            | Self::IntToBool(value)
+            | Self::StoreGlobal { value, .. }
+            | Self::ArraySize(value)
+            | Self::LoadField { record: value, .. }
+            | Self::Print { value, .. } => *value = f(*value),
+            Self::Phi(incoming) => {
+                for (_, value) in incoming {
+                    *value = f(*value);
+                }
+            }
+            Self::Binary { lhs, rhs, .. } => {
+                *lhs = f(*lhs);
+                *rhs = f(*rhs);
+            }
+            Self::StoreField { record, value, .. } => {
+                *record = f(*record);
+                *value = f(*value);
+            }
+            Self::LoadElement { array, index } => {
+                *array = f(*array);
+                *index = f(*index);
+            }
+            Self::StoreElement {
+                array,
+                index,
+                value,
+            } => {
+                *array = f(*array);
+                *index = f(*index);
+                *value = f(*value);
+            }
+            Self::Call { args, .. } | Self::CallNative { args, .. } => {
+                for arg in args {
+                    *arg = f(*arg);
+                }
+            }
+        }
+    }
+
+    /// The result depends on the operands only and computing it has no effect,
+    /// so equal operations give equal values. String concatenation allocates a new string,
+    /// which `IntEq` can tell apart from an equal one.
+    #[must_use]
+    pub fn is_pure(&self) -> bool {
+        match self {
+            Self::Binary { op, .. } => *op != SemanticBinaryOperator::StringConcat,
+            Self::Const(_)
+            | Self::String { .. }
+            | Self::Param { .. }
+            | Self::Copy(_)
+            | Self::Unary { .. }
+            | Self::IntToReal(_)
+            | Self::RealToInt(_)
+            | Self::IntToBool(_) => true,
+            Self::Phi(_)
+            | Self::LoadGlobal { .. }
+            | Self::StoreGlobal { .. }
+            | Self::AllocRecord { .. }
+            | Self::AllocArray { .. }
+            | Self::ArraySize(_)
+            | Self::LoadField { .. }
+            | Self::StoreField { .. }
+            | Self::LoadElement { .. }
+            | Self::StoreElement { .. }
+            | Self::Call { .. }
+            | Self::CallNative { .. }
+            | Self::Print { .. } => false,
+        }
+    }
+
+    /// May stop the program with a runtime error, like integer overflow does
+    #[must_use]
+    pub fn may_fail(&self) -> bool {
+        use SemanticBinaryOperator as Op;
+
+        match self {
+            Self::Binary { op, .. } => matches!(
+                op,
+                Op::IntAdd | Op::IntSub | Op::IntMul | Op::IntDiv | Op::IntMod
+            ),
+            Self::Unary { op, .. } => *op == SemanticUnaryOperator::IntNeg,
+            Self::RealToInt(_)
+            | Self::IntToBool(_)
+            | Self::LoadElement { .. }
+            | Self::StoreElement { .. }
+            | Self::Call { .. }
+            | Self::CallNative { .. } => true,
+            Self::Const(_)
+            | Self::String { .. }
+            | Self::Param { .. }
+            | Self::Copy(_)
+            | Self::Phi(_)
+            | Self::IntToReal(_)
+            | Self::LoadGlobal { .. }
+            | Self::StoreGlobal { .. }
+            | Self::AllocRecord { .. }
+            | Self::AllocArray { .. }
+            | Self::ArraySize(_)
+            | Self::LoadField { .. }
+            | Self::StoreField { .. }
+            | Self::Print { .. } => false,
+        }
+    }
+
+    /// Can be dropped when its result is unused
+    #[must_use]
+    pub fn is_removable(&self) -> bool {
+        !self.may_fail()
+            && !matches!(
+                self,
+                Self::StoreGlobal { .. } | Self::StoreField { .. } | Self::Print { .. }
+            )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |values: &[ValueId]| {
+            values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Self::Const(Constant::Int(value)) => write!(f, "const {value}"),
+            Self::Const(Constant::Real(value)) => write!(f, "const {value:?}"),
+            Self::Const(Constant::Bool(value)) => write!(f, "const {value}"),
+            Self::String { id } => write!(f, "string {id}"),
+            Self::Param { index } => write!(f, "param {index}"),
+            Self::Copy(value) => write!(f, "copy {value}"),
+            Self::Phi(incoming) => {
+                let incoming: Vec<_> = incoming
+                    .iter()
+                    .map(|(block, value)| format!("{block}: {value}"))
+                    .collect();
+                write!(f, "phi [{}]", incoming.join(", "))
+            }
+            Self::Binary { op, lhs, rhs } => write!(f, "{op:?} {lhs}, {rhs}"),
+            Self::Unary { op, value } => write!(f, "{op:?} {value}"),
+            Self::IntToReal(value) => write!(f, "IntToReal {value}"),
+            Self::RealToInt(value) => write!(f, "RealToInt {value}"),
+            Self::IntToBool(value) => write!(f, "IntToBool {value}"),
+            Self::LoadGlobal { index } => write!(f, "load @{index}"),
+            Self::StoreGlobal { index, value } => write!(f, "store @{index}, {value}"),
+            Self::AllocRecord { type_id, size } => {
+                write!(f, "record type {}, size {size}", type_id.0)
+            }
+            Self::AllocArray { type_id, size } => {
+                write!(f, "array type {}, size {size}", type_id.0)
+            }
+            Self::ArraySize(array) => write!(f, "size {array}"),
+            Self::LoadField { record, offset } => write!(f, "load {record}.{offset}"),
+            Self::StoreField {
+                record,
+                offset,
+                value,
+            } => write!(f, "store {record}.{offset}, {value}"),
+            Self::LoadElement { array, index } => write!(f, "load {array}[{index}]"),
+            Self::StoreElement {
+                array,
+                index,
+                value,
+            } => write!(f, "store {array}[{index}], {value}"),
+            Self::Call {
+                function_label,
+                args,
+            } => write!(f, "call L{function_label}({})", list(args)),
+            Self::CallNative { id, args } => write!(f, "native {id}({})", list(args)),
+            Self::Print { type_id, value } => write!(f, "print type {}, {value}", type_id.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    /// `None` for the ones executed for their effect only
+    pub result: Option<(ValueId, IrType)>,
+    pub operation: Operation,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.result {
+            Some((value, t)) => write!(f, "{value}: {t} = {}", self.operation),
+            None => write!(f, "{}", self.operation),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to `then` when `condition` is true
+    Branch {
+        condition: ValueId,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    /// Procedures return nothing
+    Return(Option<ValueId>),
+    Panic {
+        code: u64,
+    },
+}
+
+impl Terminator {
+    #[must_use]
+    pub fn successors(self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(target) => vec![target],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Self::Return(_) | Self::Panic { .. } => Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn operand(self) -> Option<ValueId> {
+        match self {
+            Self::Branch { condition, .. } => Some(condition),
+            Self::Return(value) => value,
+            Self::Jump(_) | Self::Panic { .. } => None,
+        }
+    }
+
+    pub fn map_operand(&mut self, f: impl FnOnce(ValueId) -> ValueId) {
+        match self {
+            Self::Branch { condition, .. } => *condition = f(*condition),
+            Self::Return(Some(value)) => *value = f(*value),
+            Self::Jump(_) | Self::Return(None) | Self::Panic { .. } => {}
+        }
+    }
+
+    pub fn map_successors(&mut self, mut f: impl FnMut(BlockId) -> BlockId) {
+        match self {
+            Self::Jump(target) => *target = f(*target),
+            Self::Branch {
+                then, otherwise, ..
+            } => {
+                *then = f(*then);
+                *otherwise = f(*otherwise);
+            }
+            Self::Return(_) | Self::Panic { .. } => {}
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jump {target}"),
+            Self::Branch {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "branch {condition}, {then}, {otherwise}"),
+            Self::Return(Some(value)) => write!(f, "return {value}"),
+            Self::Return(None) => write!(f, "return"),
+            Self::Panic { code } => write!(f, "panic {code}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    /// Phis come first
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<IrType>,
+    /// `None` for procedures
+    pub result: Option<IrType>,
+    /// `blocks[0]` is the entry
+    pub blocks: Vec<Block>,
+    /// Value ids are `0..value_count`
+    pub value_count: u32,
+}
+
+impl Function {
+    #[must_use]
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.index()]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.index()]
+    }
+
+    /// # Panics
+    ///
+    /// If the function has more than `u32::MAX` blocks
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> + use<> {
+        (0..u32::try_from(self.blocks.len()).expect("Block ids fit into u32")).map(BlockId)
+    }
+
+    /// How many times each value is read, indexed by `ValueId`
+    #[must_use]
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.value_count as usize];
+        for block in &self.blocks {
+            let operands = block
+                .instructions
+                .iter()
+                .flat_map(|instruction| instruction.operation.operands())
+                .chain(block.terminator.operand());
+            for ValueId(value) in operands {
+                counts[value as usize] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Replaces every read of a value with `f(value)`
+    pub fn map_operands(&mut self, mut f: impl FnMut(ValueId) -> ValueId) {
+        for block in &mut self.blocks {
+            for instruction in &mut block.instructions {
+                instruction.operation.map_operands(&mut f);
+            }
+            block.terminator.map_operand(&mut f);
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<_> = self.args.iter().map(ToString::to_string).collect();
+        write!(f, "routine {}({})", self.name, args.join(", "))?;
+        match self.result {
+            Some(t) => writeln!(f, " : {t}")?,
+            None => writeln!(f)?,
+        }
+        for (id, block) in self.block_ids().zip(&self.blocks) {
+            writeln!(f, "{id}:")?;
+            for instruction in &block.instructions {
+                writeln!(f, "  {instruction}")?;
+            }
+            writeln!(f, "  {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends instructions to the block it is positioned at,
+/// blocks are created with `Terminator::Return(None)` until `terminate` is called
+#[derive(Debug)]
+pub struct FunctionBuilder {
+    function: Function,
+    current: BlockId,
+}
+
+impl FunctionBuilder {
+    #[must_use]
+    pub fn new(name: &str, args: Vec<IrType>, result: Option<IrType>) -> Self {
+        Self {
+            function: Function {
+                name: name.to_owned(),
+                args,
+                result,
+                blocks: vec![Block {
+                    instructions: Vec::new(),
+                    terminator: Terminator::Return(None),
+                }],
+                value_count: 0,
+            },
+            current: BlockId(0),
+        }
+    }
+
+    /// Creates an empty block, the builder stays where it was
+    ///
+    /// # Panics
+    ///
+    /// If the function has more than `u32::MAX` blocks
+    pub fn block(&mut self) -> BlockId {
+        let id =
+            BlockId(u32::try_from(self.function.blocks.len()).expect("Block ids fit into u32"));
+        self.function.blocks.push(Block {
+            instructions: Vec::new(),
+            terminator: Terminator::Return(None),
+        });
+        id
+    }
+
+    pub fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    #[must_use]
+    pub fn current(&self) -> BlockId {
+        self.current
+    }
+
+    /// Appends an instruction producing a value of type `t`
+    pub fn value(&mut self, t: IrType, operation: Operation) -> ValueId {
+        let id = ValueId(self.function.value_count);
+        self.function.value_count += 1;
+        self.function
+            .block_mut(self.current)
+            .instructions
+            .push(Instruction {
+                result: Some((id, t)),
+                operation,
+            });
+        id
+    }
+
+    /// Adds a phi to the ones at the start of `block`, wherever the builder is
+    pub fn phi(&mut self, block: BlockId, t: IrType, incoming: Vec<(BlockId, ValueId)>) -> ValueId {
+        let id = ValueId(self.function.value_count);
+        self.function.value_count += 1;
+        let instructions = &mut self.function.block_mut(block).instructions;
+        let position = instructions
+            .iter()
+            .take_while(|instruction| matches!(instruction.operation, Operation::Phi(_)))
+            .count();
+        instructions.insert(
+            position,
+            Instruction {
+                result: Some((id, t)),
+                operation: Operation::Phi(incoming),
+            },
+        );
+        id
+    }
+
+    /// Replaces what the phi of `block` defining `value` merges
+    ///
+    /// # Panics
+    ///
+    /// If `block` has no such phi
+    pub fn set_incoming(
+        &mut self,
+        block: BlockId,
+        value: ValueId,
+        incoming: Vec<(BlockId, ValueId)>,
+    ) {
+        let phi = self
+            .function
+            .block_mut(block)
+            .instructions
+            .iter_mut()
+            .find(|instruction| {
+                instruction
+                    .result
+                    .is_some_and(|(result, _)| result == value)
+            })
+            .expect("The phi is in the block");
+        phi.operation = Operation::Phi(incoming);
+    }
+
+    /// Appends an instruction executed for its effect
+    pub fn effect(&mut self, operation: Operation) {
+        self.function
+            .block_mut(self.current)
+            .instructions
+            .push(Instruction {
+                result: None,
+                operation,
+            });
+    }
+
+    pub fn terminate(&mut self, terminator: Terminator) {
+        self.function.block_mut(self.current).terminator = terminator;
+    }
+
+    #[must_use]
+    pub fn finish(self) -> Function {
+        self.function
+    }
+}
+
+/// Builds the IR of every routine of a checked module, by the label of its entry,
+/// the way `codegen::compile` generates their bytecode. `INIT` is built too, see
+/// `codegen::init_label`.
+#[must_use]
+pub fn build(program: &TypedProgram) -> BTreeMap<u64, Function> {
+    build::routines(program)
+}
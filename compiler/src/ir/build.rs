@@ -0,0 +1,852 @@
+//! `types::TypedProgram` to `Function`s, statement by statement as `codegen` does.
+//!
+//! Locals and arguments live in values only: a read takes the value last written in its block,
+//! or merges the ones of the predecessors with a phi, as in "Simple and Efficient Construction
+//! of Static Single Assignment Form" (Braun et al.). Blocks are sealed once every predecessor
+//! is known, until then their phis wait for operands. Code after `return`, `break` and
+//! `continue` goes to blocks no one jumps to, which are dropped at the end.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use super::passes::unreachable_blocks;
+use super::{BlockId, Function, FunctionBuilder, IrType, Operation, Terminator, ValueId};
+use crate::ast::{
+    BinaryOperator, Block, BlockElement, CaseLabel, Declaration, Expression, Identifier, LoopOrder,
+    LvalueExpression, RoutineBody, RoutineDeclaration, SimpleDeclaration, Statement,
+    VariableDeclaration,
+};
+use crate::builtins::Builtin;
+use crate::bytecode::{FunctionCode, INIT, Location};
+use crate::codegen::{MISSING_RETURN, binary_operator, globals, init_label, unary_operator};
+use crate::consteval::Constant;
+use crate::operators::SemanticBinaryOperator;
+use crate::types::{ArrayDescription, Type, TypedProgram};
+
+/// See `ir::build`
+pub(super) fn routines(program: &TypedProgram) -> BTreeMap<u64, Function> {
+    let globals = globals(program);
+    let mut routines = BTreeMap::new();
+    for declaration in &program.program.declarations {
+        if let Declaration::Routine(routine) = declaration
+            && routine.body.is_some()
+        {
+            let (label, function) = routine_function(program, routine);
+            drop(routines.insert(label, function));
+        }
+    }
+    if let Some(label) = init_label(program) {
+        let mut builder = Builder::new(program, INIT, Vec::new(), None);
+        for global in &globals {
+            builder.variable(global);
+        }
+        builder.function.terminate(Terminator::Return(None));
+        drop(routines.insert(label, builder.finish()));
+    }
+    routines
+}
+
+fn routine_function<'a>(
+    program: &'a TypedProgram,
+    declaration: &'a RoutineDeclaration,
+) -> (u64, Function) {
+    let routine = &program.routines[declaration.name.id.expect("Resolved")];
+    let FunctionCode::Label(label) = routine.code else {
+        unreachable!("Routines with a body have a label")
+    };
+    let args: Vec<_> = routine
+        .parameters
+        .iter()
+        .map(|t| ir_type(program, t))
+        .collect();
+    let result = routine.result.as_ref().map(|t| ir_type(program, t));
+    let mut builder = Builder::new(program, &routine.name, args.clone(), result);
+    for (index, t) in args.into_iter().enumerate() {
+        let value = builder.function.value(t, Operation::Param { index });
+        builder.write(Location::Argument(index), t, value);
+    }
+    match declaration.body.as_ref().expect("Has a body") {
+        RoutineBody::Block(body) => {
+            builder.block(body);
+            builder.function.terminate(match result {
+                Some(_) => Terminator::Panic {
+                    code: MISSING_RETURN,
+                },
+                None => Terminator::Return(None),
+            });
+        }
+        RoutineBody::Expression(value) => {
+            let value = builder.expression(value);
+            builder.function.terminate(Terminator::Return(Some(value)));
+        }
+    }
+    (label, builder.finish())
+}
+
+/// Booleans stay apart from the integers and enums they are in the bytecode
+fn ir_type(program: &TypedProgram, t: &Rc<Type>) -> IrType {
+    match &*program.resolve(t) {
+        Type::Int | Type::Enum(_) => IrType::Int,
+        Type::Real => IrType::Real,
+        Type::Bool => IrType::Bool,
+        Type::String | Type::Record(_) | Type::Array(_) => IrType::Ref,
+        Type::Alias(_) => unreachable!("Resolved"),
+    }
+}
+
+/// Blocks `continue` and `break` of a loop being built go to
+#[derive(Debug, Clone, Copy)]
+struct LoopBlocks<'a> {
+    name: Option<&'a str>,
+    /// The condition of `while`, the step of `for`
+    next: BlockId,
+    /// Right after the loop
+    exit: BlockId,
+}
+
+struct Builder<'a> {
+    program: &'a TypedProgram,
+    function: FunctionBuilder,
+    /// The ones whose jumps are built so far, by `BlockId`
+    predecessors: Vec<Vec<BlockId>>,
+    sealed: Vec<bool>,
+    /// Phis of blocks that are not sealed yet, for reads of the locations
+    incomplete: HashMap<BlockId, Vec<(Location, ValueId)>>,
+    /// The value of each local and argument at the end of the blocks built so far
+    definitions: HashMap<(Location, BlockId), ValueId>,
+    types: HashMap<Location, IrType>,
+    /// Innermost last
+    loops: Vec<LoopBlocks<'a>>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(
+        program: &'a TypedProgram,
+        name: &str,
+        args: Vec<IrType>,
+        result: Option<IrType>,
+    ) -> Self {
+        Self {
+            program,
+            function: FunctionBuilder::new(name, args, result),
+            predecessors: vec![Vec::new()],
+            sealed: vec![true],
+            incomplete: HashMap::new(),
+            definitions: HashMap::new(),
+            types: HashMap::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    /// Drops the blocks no one jumps to and the phis merging a single value
+    fn finish(self) -> Function {
+        let mut function = self.function.finish();
+        let _removed = unreachable_blocks(&mut function);
+        trivial_phis(&mut function);
+        function
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.predecessors.push(Vec::new());
+        self.sealed.push(false);
+        self.function.block()
+    }
+
+    /// Code no one jumps to, after `return`, `break` or `continue`
+    fn unreachable(&mut self) {
+        let block = self.new_block();
+        self.seal(block);
+        self.function.switch_to(block);
+    }
+
+    /// Blocks that are not the entry and have no predecessors are unreachable,
+    /// their jumps do not count
+    fn is_reachable(&self) -> bool {
+        let current = self.function.current();
+        current == BlockId(0) || !self.predecessors[current.index()].is_empty()
+    }
+
+    fn jump(&mut self, target: BlockId) {
+        if self.is_reachable() {
+            self.predecessors[target.index()].push(self.function.current());
+        }
+        self.function.terminate(Terminator::Jump(target));
+    }
+
+    fn branch(&mut self, condition: ValueId, then: BlockId, otherwise: BlockId) {
+        if self.is_reachable() {
+            let current = self.function.current();
+            self.predecessors[then.index()].push(current);
+            self.predecessors[otherwise.index()].push(current);
+        }
+        self.function.terminate(Terminator::Branch {
+            condition,
+            then,
+            otherwise,
+        });
+    }
+
+    /// Every jump to `block` is built, the phis waiting for operands get them
+    fn seal(&mut self, block: BlockId) {
+        for (location, phi) in self.incomplete.remove(&block).unwrap_or_default() {
+            self.phi_operands(location, block, phi);
+        }
+        self.sealed[block.index()] = true;
+    }
+
+    fn write(&mut self, location: Location, t: IrType, value: ValueId) {
+        let _: Option<IrType> = self.types.insert(location, t);
+        let _: Option<ValueId> = self
+            .definitions
+            .insert((location, self.function.current()), value);
+    }
+
+    fn read(&mut self, location: Location, block: BlockId) -> ValueId {
+        if let Some(&value) = self.definitions.get(&(location, block)) {
+            return value;
+        }
+        let t = self.types[&location];
+        let predecessors = &self.predecessors[block.index()];
+        let value = if !self.sealed[block.index()] {
+            let phi = self.function.phi(block, t, Vec::new());
+            self.incomplete
+                .entry(block)
+                .or_default()
+                .push((location, phi));
+            phi
+        } else if let [predecessor] = predecessors[..] {
+            self.read(location, predecessor)
+        } else if predecessors.is_empty() {
+            // Read on no path that runs, like after an exhaustive `case`
+            self.function.phi(BlockId(0), t, Vec::new())
+        } else {
+            // Defined before the operands are read, as they may reach it through a loop
+            let phi = self.function.phi(block, t, Vec::new());
+            let _: Option<ValueId> = self.definitions.insert((location, block), phi);
+            self.phi_operands(location, block, phi);
+            phi
+        };
+        let _: Option<ValueId> = self.definitions.insert((location, block), value);
+        value
+    }
+
+    fn phi_operands(&mut self, location: Location, block: BlockId, phi: ValueId) {
+        let incoming = self.predecessors[block.index()]
+            .clone()
+            .into_iter()
+            .map(|predecessor| (predecessor, self.read(location, predecessor)))
+            .collect();
+        self.function.set_incoming(block, phi, incoming);
+    }
+
+    fn location(&self, name: &Identifier) -> Location {
+        self.program.variables[name.id.expect("Resolved")].location
+    }
+
+    fn variable_type(&self, name: &Identifier) -> IrType {
+        ir_type(
+            self.program,
+            &self.program.variables[name.id.expect("Resolved")].t,
+        )
+    }
+
+    fn load(&mut self, name: &Identifier) -> ValueId {
+        match self.location(name) {
+            Location::Global(index) => {
+                let t = self.variable_type(name);
+                self.function.value(t, Operation::LoadGlobal { index })
+            }
+            location @ (Location::Local(_) | Location::Argument(_)) => {
+                let current = self.function.current();
+                self.read(location, current)
+            }
+        }
+    }
+
+    fn store(&mut self, name: &Identifier, value: ValueId) {
+        match self.location(name) {
+            Location::Global(index) => {
+                self.function
+                    .effect(Operation::StoreGlobal { index, value });
+            }
+            location @ (Location::Local(_) | Location::Argument(_)) => {
+                let t = self.variable_type(name);
+                self.write(location, t, value);
+            }
+        }
+    }
+
+    fn constant(&mut self, constant: Constant) -> ValueId {
+        let t = match constant {
+            Constant::Int(_) => IrType::Int,
+            Constant::Real(_) => IrType::Real,
+            Constant::Bool(_) => IrType::Bool,
+        };
+        self.function.value(t, Operation::Const(constant))
+    }
+
+    fn variable(&mut self, variable: &VariableDeclaration) {
+        let value = match &variable.initializer {
+            Some(value) => self.expression(value),
+            None => self.default(variable.t.as_ref().expect("Checked"), &mut Vec::new()),
+        };
+        self.store(&variable.name, value);
+    }
+
+    /// See `codegen::Generator::default`
+    fn default(&mut self, t: &Rc<Type>, records: &mut Vec<*const Type>) -> ValueId {
+        let program = self.program;
+        let t = program.resolve(t);
+        match &*t {
+            Type::Int | Type::Enum(_) => self.constant(Constant::Int(0)),
+            Type::Bool => self.constant(Constant::Bool(false)),
+            Type::Real => self.constant(Constant::Real(0.0)),
+            Type::String => self.function.value(
+                IrType::Ref,
+                Operation::String {
+                    id: program.string_id(""),
+                },
+            ),
+            Type::Array(ArrayDescription { length, .. }) => self.function.value(
+                IrType::Ref,
+                Operation::AllocArray {
+                    type_id: program.type_id(&t),
+                    size: length.expect("Checked") as u64,
+                },
+            ),
+            Type::Record(record) => {
+                let value = self.function.value(
+                    IrType::Ref,
+                    Operation::AllocRecord {
+                        type_id: program.type_id(&t),
+                        size: record.fields.len() as u64,
+                    },
+                );
+                records.push(Rc::as_ptr(&t));
+                for (offset, field) in record.fields.iter().enumerate() {
+                    let field_type = program.resolve(&field.t);
+                    let is_allocated = match &*field_type {
+                        Type::Array(array) => array.length.is_some(),
+                        Type::Record(_) => !records.contains(&Rc::as_ptr(&field_type)),
+                        Type::Int
+                        | Type::Real
+                        | Type::Bool
+                        | Type::String
+                        | Type::Alias(_)
+                        | Type::Enum(_) => false,
+                    };
+                    if is_allocated {
+                        let field = self.default(&field.t, records);
+                        self.function.effect(Operation::StoreField {
+                            record: value,
+                            offset: offset as u64,
+                            value: field,
+                        });
+                    }
+                }
+                let _: Option<*const Type> = records.pop();
+                value
+            }
+            Type::Alias(_) => unreachable!("Resolved"),
+        }
+    }
+
+    fn block(&mut self, block: &'a Block) {
+        for element in block.elements() {
+            match element {
+                BlockElement::Decl(declaration) => {
+                    if let SimpleDeclaration::Variable(variable) = &**declaration {
+                        self.variable(variable);
+                    }
+                }
+                BlockElement::Stmt(_, statement) => self.statement(statement),
+            }
+        }
+    }
+
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Assignment { lhs, rhs } => {
+                let value = self.expression(rhs);
+                match &**lhs {
+                    LvalueExpression::Identifier(name) => self.store(name, value),
+                    LvalueExpression::Member { lhs, member_name } => {
+                        let record = self.rvalue(lhs);
+                        self.function.effect(Operation::StoreField {
+                            record,
+                            offset: member_name.id.expect("`length` is not assigned") as u64,
+                            value,
+                        });
+                    }
+                    LvalueExpression::Index { lhs, index } => {
+                        let array = self.rvalue(lhs);
+                        let index = self.expression(index);
+                        self.function.effect(Operation::StoreElement {
+                            array,
+                            index,
+                            value,
+                        });
+                    }
+                }
+            }
+            Statement::Call { call } => {
+                let Expression::Call { callee, args } = &**call else {
+                    unreachable!("Call statements are calls")
+                };
+                let _: Option<ValueId> = self.call(callee, args);
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                let header = self.new_block();
+                self.jump(header);
+                self.function.switch_to(header);
+                let condition = self.expression(condition);
+                let exit = self.enter_loop(condition);
+                self.loop_body(label.as_ref(), body, header, exit);
+                self.jump(header);
+                self.seal(header);
+                self.seal(exit);
+                self.function.switch_to(exit);
+            }
+            Statement::For {
+                label,
+                identifier,
+                from,
+                to,
+                order,
+                body,
+            } => {
+                let Location::Local(variable) = self.location(identifier) else {
+                    unreachable!("Loop variables are locals")
+                };
+                let reverse = matches!(order, LoopOrder::Reversed);
+                let (hidden, index) =
+                    (Location::Local(variable + 1), Location::Local(variable + 2));
+                let (header, next) = (self.new_block(), self.new_block());
+                let (counter, exit) = if let Some(to) = to {
+                    let (start, bound) = if reverse { (to, from) } else { (from, to) };
+                    let start = self.expression(start);
+                    self.store(identifier, start);
+                    let bound = self.expression(bound);
+                    self.write(hidden, IrType::Int, bound);
+                    self.jump(header);
+                    self.function.switch_to(header);
+                    let op = if reverse {
+                        SemanticBinaryOperator::IntGe
+                    } else {
+                        SemanticBinaryOperator::IntLe
+                    };
+                    let (lhs, rhs) = (self.load(identifier), self.read(hidden, header));
+                    let condition = self
+                        .function
+                        .value(IrType::Bool, Operation::Binary { op, lhs, rhs });
+                    (Location::Local(variable), self.enter_loop(condition))
+                } else {
+                    let array = self.expression(from);
+                    self.write(hidden, IrType::Ref, array);
+                    let start = if reverse {
+                        self.function
+                            .value(IrType::Int, Operation::ArraySize(array))
+                    } else {
+                        self.constant(Constant::Int(1))
+                    };
+                    self.write(index, IrType::Int, start);
+                    self.jump(header);
+                    self.function.switch_to(header);
+                    let lhs = self.read(index, header);
+                    let (op, rhs) = if reverse {
+                        (
+                            SemanticBinaryOperator::IntGe,
+                            self.constant(Constant::Int(1)),
+                        )
+                    } else {
+                        let array = self.read(hidden, header);
+                        let size = self
+                            .function
+                            .value(IrType::Int, Operation::ArraySize(array));
+                        (SemanticBinaryOperator::IntLe, size)
+                    };
+                    let condition = self
+                        .function
+                        .value(IrType::Bool, Operation::Binary { op, lhs, rhs });
+                    let exit = self.enter_loop(condition);
+                    let entry = self.function.current();
+                    let (array, index_value) = (self.read(hidden, entry), self.read(index, entry));
+                    let element = self.function.value(
+                        self.variable_type(identifier),
+                        Operation::LoadElement {
+                            array,
+                            index: index_value,
+                        },
+                    );
+                    self.store(identifier, element);
+                    (index, exit)
+                };
+                self.loop_body(label.as_ref(), body, next, exit);
+                self.jump(next);
+                self.seal(next);
+                self.function.switch_to(next);
+                let lhs = self.read(counter, next);
+                let rhs = self.constant(Constant::Int(1));
+                let op = if reverse {
+                    SemanticBinaryOperator::IntSub
+                } else {
+                    SemanticBinaryOperator::IntAdd
+                };
+                let stepped = self
+                    .function
+                    .value(IrType::Int, Operation::Binary { op, lhs, rhs });
+                self.write(counter, IrType::Int, stepped);
+                self.jump(header);
+                self.seal(header);
+                self.seal(exit);
+                self.function.switch_to(exit);
+            }
+            Statement::If {
+                condition,
+                on_true,
+                on_false,
+            } => {
+                let condition = self.expression(condition);
+                let (then, otherwise, end) = (self.new_block(), self.new_block(), self.new_block());
+                self.branch(condition, then, otherwise);
+                self.seal(then);
+                self.seal(otherwise);
+                self.function.switch_to(then);
+                self.block(on_true);
+                self.jump(end);
+                self.function.switch_to(otherwise);
+                if let Some(on_false) = on_false {
+                    self.block(on_false);
+                }
+                self.jump(end);
+                self.seal(end);
+                self.function.switch_to(end);
+            }
+            Statement::Print { value } => {
+                let type_id = self.program.type_id(&self.program.type_of(value));
+                let value = self.expression(value);
+                self.function.effect(Operation::Print { type_id, value });
+            }
+            Statement::Case {
+                value,
+                branches,
+                otherwise,
+            } => {
+                let value = self.expression(value);
+                let bodies: Vec<_> = branches.iter().map(|_| self.new_block()).collect();
+                let (default, end) = (self.new_block(), self.new_block());
+                for (branch, &body) in branches.iter().zip(&bodies) {
+                    for label in &branch.labels {
+                        let CaseLabel::Integer(literal) = label else {
+                            unreachable!("Checked labels are values")
+                        };
+                        let rhs = self.constant(Constant::Int(literal.value()));
+                        let condition = self.function.value(
+                            IrType::Bool,
+                            Operation::Binary {
+                                op: SemanticBinaryOperator::IntEq,
+                                lhs: value,
+                                rhs,
+                            },
+                        );
+                        let next = self.new_block();
+                        self.branch(condition, body, next);
+                        self.seal(next);
+                        self.function.switch_to(next);
+                    }
+                }
+                self.jump(default);
+                for (branch, body) in branches.iter().zip(bodies) {
+                    self.seal(body);
+                    self.function.switch_to(body);
+                    self.block(&branch.body);
+                    self.jump(end);
+                }
+                self.seal(default);
+                self.function.switch_to(default);
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise);
+                }
+                self.jump(end);
+                self.seal(end);
+                self.function.switch_to(end);
+            }
+            Statement::Return { value } => {
+                let value = value.as_ref().map(|value| self.expression(value));
+                self.function.terminate(Terminator::Return(value));
+                self.unreachable();
+            }
+            Statement::Break { label } => {
+                let target = self.find_loop(label.as_ref()).exit;
+                self.jump(target);
+                self.unreachable();
+            }
+            Statement::Continue { label } => {
+                let target = self.find_loop(label.as_ref()).next;
+                self.jump(target);
+                self.unreachable();
+            }
+        }
+    }
+
+    /// Goes to the body of a loop if `condition` holds, returns the block after the loop
+    fn enter_loop(&mut self, condition: ValueId) -> BlockId {
+        let (entry, exit) = (self.new_block(), self.new_block());
+        self.branch(condition, entry, exit);
+        self.seal(entry);
+        self.function.switch_to(entry);
+        exit
+    }
+
+    fn loop_body(
+        &mut self,
+        label: Option<&'a Identifier>,
+        body: &'a Block,
+        next: BlockId,
+        exit: BlockId,
+    ) {
+        self.loops.push(LoopBlocks {
+            name: label.map(|label| label.name.as_str()),
+            next,
+            exit,
+        });
+        self.block(body);
+        let _: Option<LoopBlocks<'_>> = self.loops.pop();
+    }
+
+    /// The loop `break` or `continue` with `label` leaves
+    fn find_loop(&self, label: Option<&Identifier>) -> LoopBlocks<'a> {
+        let found = match label {
+            None => self.loops.last(),
+            Some(label) => self
+                .loops
+                .iter()
+                .rev()
+                .find(|blocks| blocks.name == Some(label.name.as_str())),
+        };
+        *found.expect("Checked by `flow::check_loop_exits`")
+    }
+
+    fn expression(&mut self, expression: &Expression) -> ValueId {
+        let program = self.program;
+        match expression {
+            Expression::LvalueToRvalue(lvalue) => self.rvalue(lvalue),
+            Expression::IntegerLiteral(literal) => self.constant(Constant::Int(literal.value())),
+            Expression::RealLiteral(literal) => self.constant(Constant::Real(literal.value())),
+            Expression::BoolLiteral(literal) => {
+                self.constant(Constant::Bool(*literal == crate::ast::BoolLiteral::True))
+            }
+            Expression::StringLiteral(literal) => self.function.value(
+                IrType::Ref,
+                Operation::String {
+                    id: program.string_id(literal.value()),
+                },
+            ),
+            Expression::Call { callee, args } => self
+                .call(callee, args)
+                .expect("Procedures are not called for a value"),
+            Expression::Binop { op, lhs, rhs } => {
+                if matches!(op, BinaryOperator::And | BinaryOperator::Or) {
+                    return self.short_circuit(*op, lhs, rhs);
+                }
+                let t = program.resolve(&program.type_of(lhs));
+                let (lhs, rhs) = (self.expression(lhs), self.expression(rhs));
+                self.function.value(
+                    ir_type(program, &program.type_of(expression)),
+                    Operation::Binary {
+                        op: binary_operator(*op, &t),
+                        lhs,
+                        rhs,
+                    },
+                )
+            }
+            Expression::Unop { op, value } => {
+                let t = program.resolve(&program.type_of(value));
+                let value = self.expression(value);
+                self.function.value(
+                    ir_type(program, &program.type_of(expression)),
+                    Operation::Unary {
+                        op: unary_operator(*op, &t),
+                        value,
+                    },
+                )
+            }
+            Expression::BoolToInt(value) => {
+                let value = self.expression(value);
+                self.function.value(IrType::Int, Operation::Copy(value))
+            }
+            Expression::RealToInt(value) => {
+                let value = self.expression(value);
+                self.function
+                    .value(IrType::Int, Operation::RealToInt(value))
+            }
+            Expression::IntToBool(value) => {
+                let value = self.expression(value);
+                self.function
+                    .value(IrType::Bool, Operation::IntToBool(value))
+            }
+            Expression::Conversion { target, value } => {
+                let from = program.resolve(&program.type_of(value));
+                let value = self.expression(value);
+                match (&*from, &*program.resolve(target)) {
+                    (Type::Int | Type::Bool, Type::Real) => self
+                        .function
+                        .value(IrType::Real, Operation::IntToReal(value)),
+                    (Type::Real, Type::Int | Type::Enum(_)) => self
+                        .function
+                        .value(IrType::Int, Operation::RealToInt(value)),
+                    (Type::Real, Type::Bool) => {
+                        let value = self
+                            .function
+                            .value(IrType::Int, Operation::RealToInt(value));
+                        self.function
+                            .value(IrType::Bool, Operation::IntToBool(value))
+                    }
+                    (Type::Int | Type::Enum(_), Type::Bool) => self
+                        .function
+                        .value(IrType::Bool, Operation::IntToBool(value)),
+                    // The same representation, booleans become integers
+                    _ => {
+                        let t = ir_type(program, target);
+                        if t == ir_type(program, &from) {
+                            value
+                        } else {
+                            self.function.value(t, Operation::Copy(value))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// `rhs` is evaluated only when `lhs` does not decide the result, see `codegen::short_circuit`
+    fn short_circuit(&mut self, op: BinaryOperator, lhs: &Expression, rhs: &Expression) -> ValueId {
+        let lhs = self.expression(lhs);
+        let decided = self.function.current();
+        let (evaluated, end) = (self.new_block(), self.new_block());
+        if op == BinaryOperator::And {
+            self.branch(lhs, evaluated, end);
+        } else {
+            self.branch(lhs, end, evaluated);
+        }
+        self.seal(evaluated);
+        self.function.switch_to(evaluated);
+        let rhs = self.expression(rhs);
+        let evaluated = self.function.current();
+        self.jump(end);
+        self.seal(end);
+        self.function.switch_to(end);
+        let incoming = [(decided, lhs), (evaluated, rhs)]
+            .into_iter()
+            .filter(|(block, _)| self.predecessors[end.index()].contains(block))
+            .collect();
+        self.function.phi(end, IrType::Bool, incoming)
+    }
+
+    /// `None` for procedures
+    fn call(&mut self, callee: &Identifier, args: &[Rc<Expression>]) -> Option<ValueId> {
+        let program = self.program;
+        let values = args.iter().map(|arg| self.expression(arg)).collect();
+        let (operation, result) = if let Some(id) = callee.id {
+            let routine = &program.routines[id];
+            let operation = match routine.code {
+                FunctionCode::Label(function_label) => Operation::Call {
+                    function_label,
+                    args: values,
+                },
+                FunctionCode::Native(id) => Operation::CallNative { id, args: values },
+            };
+            (operation, routine.result.clone())
+        } else {
+            let builtin = Builtin::lookup(&callee.name).expect("Checked");
+            let types: Vec<_> = args.iter().map(|arg| program.type_of(arg)).collect();
+            let operation = Operation::CallNative {
+                id: builtin.id(),
+                args: values,
+            };
+            (operation, builtin.check_call(&types).expect("Checked"))
+        };
+        if let Some(t) = result {
+            return Some(self.function.value(ir_type(program, &t), operation));
+        }
+        self.function.effect(operation);
+        None
+    }
+
+    fn rvalue(&mut self, lvalue: &LvalueExpression) -> ValueId {
+        let program = self.program;
+        match lvalue {
+            LvalueExpression::Identifier(name) => self.load(name),
+            LvalueExpression::Member {
+                lhs,
+                member_name: Identifier { id: None, .. },
+            } => {
+                let array = self.rvalue(lhs);
+                self.function
+                    .value(IrType::Int, Operation::ArraySize(array))
+            }
+            LvalueExpression::Member {
+                lhs,
+                member_name: Identifier {
+                    id: Some(field), ..
+                },
+            } => {
+                let record = self.rvalue(lhs);
+                self.function.value(
+                    ir_type(program, &program.lvalue_type(lvalue)),
+                    Operation::LoadField {
+                        record,
+                        offset: *field as u64,
+                    },
+                )
+            }
+            LvalueExpression::Index { lhs, index } => {
+                let array = self.rvalue(lhs);
+                let index = self.expression(index);
+                self.function.value(
+                    ir_type(program, &program.lvalue_type(lvalue)),
+                    Operation::LoadElement { array, index },
+                )
+            }
+        }
+    }
+}
+
+/// Replaces the phis merging a single value, besides themselves, with the value
+fn trivial_phis(function: &mut Function) {
+    loop {
+        let trivial = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .find_map(|instruction| {
+                let (Some((result, _)), Operation::Phi(incoming)) =
+                    (instruction.result, &instruction.operation)
+                else {
+                    return None;
+                };
+                let mut values = incoming
+                    .iter()
+                    .map(|&(_, value)| value)
+                    .filter(|&value| value != result);
+                let first = values.next()?;
+                values
+                    .all(|value| value == first)
+                    .then_some((result, first))
+            });
+        let Some((phi, value)) = trivial else {
+            return;
+        };
+        for block in &mut function.blocks {
+            block
+                .instructions
+                .retain(|instruction| instruction.result.is_none_or(|(result, _)| result != phi));
+        }
+        function.map_operands(|operand| if operand == phi { value } else { operand });
+    }
+}
@@ -0,0 +1,191 @@
+//! Control flow graph of a `Function`: edges, dominators and natural loops
+
+use std::collections::BTreeSet;
+
+use super::{BlockId, Function};
+
+/// A header with the blocks that can reach a back edge to it without passing through it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: BlockId,
+    /// Includes the header
+    pub body: BTreeSet<BlockId>,
+}
+
+/// Only blocks reachable from the entry take part in it
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    reverse_postorder: Vec<BlockId>,
+    immediate_dominators: Vec<Option<BlockId>>,
+}
+
+impl Cfg {
+    #[must_use]
+    pub fn new(function: &Function) -> Self {
+        let successors: Vec<_> = function
+            .blocks
+            .iter()
+            .map(|block| block.terminator.successors())
+            .collect();
+        let reverse_postorder = reverse_postorder(&successors);
+        let mut predecessors = vec![Vec::new(); successors.len()];
+        for &block in &reverse_postorder {
+            for successor in &successors[block.index()] {
+                predecessors[successor.index()].push(block);
+            }
+        }
+        let mut cfg = Self {
+            successors,
+            predecessors,
+            reverse_postorder,
+            immediate_dominators: Vec::new(),
+        };
+        cfg.immediate_dominators = cfg.dominators();
+        cfg
+    }
+
+    #[must_use]
+    pub fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.successors[block.index()]
+    }
+
+    /// Reachable ones only, in reverse postorder
+    #[must_use]
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.predecessors[block.index()]
+    }
+
+    /// Reachable blocks, each one before its successors except along back edges
+    #[must_use]
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.reverse_postorder
+    }
+
+    #[must_use]
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        block == BlockId(0) || self.immediate_dominator(block).is_some()
+    }
+
+    /// `None` for the entry and for unreachable blocks
+    #[must_use]
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.immediate_dominators[block.index()]
+    }
+
+    /// Every path from the entry to `block` passes through `dominator`
+    #[must_use]
+    pub fn dominates(&self, dominator: BlockId, mut block: BlockId) -> bool {
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.immediate_dominator(block) {
+                Some(parent) => block = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Children of each block in the dominator tree, in reverse postorder
+    #[must_use]
+    pub fn dominator_tree(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![Vec::new(); self.successors.len()];
+        for &block in &self.reverse_postorder {
+            if let Some(parent) = self.immediate_dominator(block) {
+                children[parent.index()].push(block);
+            }
+        }
+        children
+    }
+
+    /// Natural loops, inner ones first. Back edges to the same header make one loop.
+    #[must_use]
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = Vec::new();
+        for &header in &self.reverse_postorder {
+            let latches: Vec<_> = self
+                .predecessors(header)
+                .iter()
+                .copied()
+                .filter(|&latch| self.dominates(header, latch))
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut body = BTreeSet::from([header]);
+            let mut worklist = latches;
+            while let Some(block) = worklist.pop() {
+                if body.insert(block) {
+                    worklist.extend_from_slice(self.predecessors(block));
+                }
+            }
+            loops.push(Loop { header, body });
+        }
+        loops.sort_by_key(|l| l.body.len());
+        loops
+    }
+
+    /// Cooper, Harvey and Kennedy's iterative algorithm
+    fn dominators(&self) -> Vec<Option<BlockId>> {
+        let mut order = vec![usize::MAX; self.successors.len()];
+        for (position, block) in self.reverse_postorder.iter().enumerate() {
+            order[block.index()] = position;
+        }
+        let mut dominators = vec![None; self.successors.len()];
+        dominators[0] = Some(BlockId(0));
+        let intersect = |dominators: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while order[a.index()] > order[b.index()] {
+                    a = dominators[a.index()].expect("Processed before");
+                }
+                while order[b.index()] > order[a.index()] {
+                    b = dominators[b.index()].expect("Processed before");
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in self.reverse_postorder.iter().skip(1) {
+                let dominator = self
+                    .predecessors(block)
+                    .iter()
+                    .copied()
+                    .filter(|predecessor| dominators[predecessor.index()].is_some())
+                    .reduce(|a, b| intersect(&dominators, a, b));
+                if dominator.is_some() && dominators[block.index()] != dominator {
+                    dominators[block.index()] = dominator;
+                    changed = true;
+                }
+            }
+        }
+        // The entry has no immediate dominator
+        dominators[0] = None;
+        dominators
+    }
+}
+
+fn reverse_postorder(successors: &[Vec<BlockId>]) -> Vec<BlockId> {
+    let mut visited = vec![false; successors.len()];
+    let mut postorder = Vec::with_capacity(successors.len());
+    // Blocks with the index of the next successor to visit
+    let mut stack = vec![(BlockId(0), 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.last_mut() {
+        if let Some(&successor) = successors[block.index()].get(*next) {
+            *next += 1;
+            if !visited[successor.index()] {
+                visited[successor.index()] = true;
+                stack.push((successor, 0));
+            }
+        } else {
+            postorder.push(*block);
+            let _: Option<(BlockId, usize)> = stack.pop();
+        }
+    }
+    postorder.reverse();
+    postorder
+}
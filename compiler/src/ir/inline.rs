@@ -0,0 +1,248 @@
+//! Replaces calls to small routines with a copy of their body. Routines calling themselves,
+//! even through others, are never inlined so that this terminates.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Block, BlockId, Function, Instruction, Operation, Terminator, ValueId};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest routine inlined by default, see `size`
+pub const THRESHOLD: usize = 12;
+
+/// Instructions of the routine, parameters are free once inlined
+#[must_use]
+pub fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| !matches!(instruction.operation, Operation::Param { .. }))
+        .count()
+}
+
+fn callees(function: &Function) -> impl Iterator<Item = u64> + '_ {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction.operation {
+            Operation::Call { function_label, .. } => Some(function_label),
+            Operation::Const(_)
+            | Operation::String { .. }
+            | Operation::Param { .. }
+            | Operation::Copy(_)
+            | Operation::Phi(_)
+            | Operation::Binary { .. }
+            | Operation::Unary { .. }
+            | Operation::IntToReal(_)
+            | Operation::RealToInt(_)
+            | Operation::IntToBool(_)
+            | Operation::LoadGlobal { .. }
+            | Operation::StoreGlobal { .. }
+            | Operation::AllocRecord { .. }
+            | Operation::AllocArray { .. }
+            | Operation::ArraySize(_)
+            | Operation::LoadField { .. }
+            | Operation::StoreField { .. }
+            | Operation::LoadElement { .. }
+            | Operation::StoreElement { .. }
+            | Operation::CallNative { .. }
+            | Operation::Print { .. } => None,
+        })
+}
+
+/// Whether the routine at `label` may call itself. Routines of other modules call none of ours.
+fn is_recursive(routines: &BTreeMap<u64, Function>, label: u64) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut pending: Vec<u64> = routines.get(&label).into_iter().flat_map(callees).collect();
+    while let Some(callee) = pending.pop() {
+        if callee == label {
+            return true;
+        }
+        if visited.insert(callee) {
+            pending.extend(routines.get(&callee).into_iter().flat_map(callees));
+        }
+    }
+    false
+}
+
+/// Replaces the call at `function.blocks[block].instructions[position]` with the blocks of `callee`.
+/// The instructions following the call move to a new block, starting with a phi of the returned values.
+fn inline_call(function: &mut Function, block: BlockId, position: usize, callee: &Function) {
+    let value_offset = function.value_count;
+    let block_offset = function.blocks.len();
+    let shift =
+        |id: BlockId| BlockId(id.0 + u32::try_from(block_offset).expect("Block ids fit into u32"));
+    let value = |id: ValueId| ValueId(id.0 + value_offset);
+    let continuation = shift(BlockId(
+        u32::try_from(callee.blocks.len()).expect("Block ids fit into u32"),
+    ));
+
+    let instructions = &mut function.block_mut(block).instructions;
+    let mut rest = instructions.split_off(position);
+    let call = rest.remove(0);
+    let Operation::Call { args, .. } = call.operation else {
+        unreachable!("Only calls are inlined");
+    };
+    let terminator = core::mem::replace(
+        &mut function.block_mut(block).terminator,
+        Terminator::Jump(shift(BlockId(0))),
+    );
+    // The successors are now reached from the end of the call
+    for successor in terminator.successors() {
+        for instruction in &mut function.block_mut(successor).instructions {
+            if let Operation::Phi(incoming) = &mut instruction.operation {
+                for (predecessor, _) in incoming {
+                    if *predecessor == block {
+                        *predecessor = continuation;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut returned = Vec::new();
+    for (id, callee_block) in callee.block_ids().zip(&callee.blocks) {
+        let mut instructions = Vec::with_capacity(callee_block.instructions.len());
+        for instruction in &callee_block.instructions {
+            let mut operation = instruction.operation.clone();
+            operation.map_operands(value);
+            match &mut operation {
+                Operation::Param { index } => operation = Operation::Copy(args[*index]),
+                Operation::Phi(incoming) => {
+                    for (predecessor, _) in incoming {
+                        *predecessor = shift(*predecessor);
+                    }
+                }
+                Operation::Const(_)
+                | Operation::String { .. }
+                | Operation::Copy(_)
+                | Operation::Binary { .. }
+                | Operation::Unary { .. }
+                | Operation::IntToReal(_)
+                | Operation::RealToInt(_)
+                | Operation::IntToBool(_)
+                | Operation::LoadGlobal { .. }
+                | Operation::StoreGlobal { .. }
+                | Operation::AllocRecord { .. }
+                | Operation::AllocArray { .. }
+                | Operation::ArraySize(_)
+                | Operation::LoadField { .. }
+                | Operation::StoreField { .. }
+                | Operation::LoadElement { .. }
+                | Operation::StoreElement { .. }
+                | Operation::Call { .. }
+                | Operation::CallNative { .. }
+                | Operation::Print { .. } => {}
+            }
+            instructions.push(Instruction {
+                result: instruction.result.map(|(result, t)| (value(result), t)),
+                operation,
+            });
+        }
+        let mut terminator = callee_block.terminator;
+        terminator.map_operand(value);
+        terminator.map_successors(shift);
+        if let Terminator::Return(result) = terminator {
+            returned.push((shift(id), result));
+            terminator = Terminator::Jump(continuation);
+        }
+        function.blocks.push(Block {
+            instructions,
+            terminator,
+        });
+    }
+    function.value_count += callee.value_count;
+
+    if let Some(result) = call.result {
+        let incoming = returned
+            .into_iter()
+            .map(|(block, value)| (block, value.expect("Routines with a result return one")))
+            .collect();
+        rest.insert(
+            0,
+            Instruction {
+                result: Some(result),
+                operation: Operation::Phi(incoming),
+            },
+        );
+    }
+    function.blocks.push(Block {
+        instructions: rest,
+        terminator,
+    });
+}
+
+/// Inlines every call of `function` to one of `candidates`, returns how many calls were replaced
+fn inline_calls(function: &mut Function, candidates: &BTreeMap<u64, Function>) -> usize {
+    let mut count = 0;
+    loop {
+        let call = function.block_ids().find_map(|block| {
+            function
+                .block(block)
+                .instructions
+                .iter()
+                .enumerate()
+                .find_map(|(position, instruction)| match instruction.operation {
+                    Operation::Call { function_label, .. } => {
+                        Some((block, position, candidates.get(&function_label)?))
+                    }
+                    Operation::Const(_)
+                    | Operation::String { .. }
+                    | Operation::Param { .. }
+                    | Operation::Copy(_)
+                    | Operation::Phi(_)
+                    | Operation::Binary { .. }
+                    | Operation::Unary { .. }
+                    | Operation::IntToReal(_)
+                    | Operation::RealToInt(_)
+                    | Operation::IntToBool(_)
+                    | Operation::LoadGlobal { .. }
+                    | Operation::StoreGlobal { .. }
+                    | Operation::AllocRecord { .. }
+                    | Operation::AllocArray { .. }
+                    | Operation::ArraySize(_)
+                    | Operation::LoadField { .. }
+                    | Operation::StoreField { .. }
+                    | Operation::LoadElement { .. }
+                    | Operation::StoreElement { .. }
+                    | Operation::CallNative { .. }
+                    | Operation::Print { .. } => None,
+                })
+        });
+        let Some((block, position, callee)) = call else {
+            return count;
+        };
+        inline_call(function, block, position, callee);
+        count += 1;
+    }
+}
+
+/// Inlines the calls to routines of at most `threshold` instructions which are not recursive,
+/// until none is left. Returns how many calls were replaced.
+///
+/// # Panics
+///
+/// If a routine with a result returns nothing
+pub fn inline(routines: &mut BTreeMap<u64, Function>, threshold: usize) -> usize {
+    let mut count = 0;
+    loop {
+        let candidates: BTreeMap<_, _> = routines
+            .iter()
+            .filter(|&(&label, function)| {
+                size(function) <= threshold && !is_recursive(routines, label)
+            })
+            .map(|(&label, function)| (label, function.clone()))
+            .collect();
+        let inlined: usize = routines
+            .values_mut()
+            .map(|function| inline_calls(function, &candidates))
+            .sum();
+        if inlined == 0 {
+            return count;
+        }
+        count += inlined;
+    }
+}
@@ -0,0 +1,126 @@
+use super::*;
+use crate::ir::{FunctionBuilder, IrType, passes};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+fn binary(op: SemanticBinaryOperator, lhs: ValueId, rhs: ValueId) -> Operation {
+    Operation::Binary { op, lhs, rhs }
+}
+
+fn int(value: i64) -> Operation {
+    Operation::Const(crate::consteval::Constant::Int(value))
+}
+
+/// `routine double(x : integer) : integer => x * 2;`
+fn double() -> Function {
+    let mut builder = FunctionBuilder::new("double", vec![IrType::Int], Some(IrType::Int));
+    let x = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let two = builder.value(IrType::Int, int(2));
+    let product = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntMul, x, two));
+    builder.terminate(Terminator::Return(Some(product)));
+    builder.finish()
+}
+
+/// `routine abs(x : integer) : integer is if x < 0 then return -x; end; return x; end;`
+fn abs() -> Function {
+    let mut builder = FunctionBuilder::new("abs", vec![IrType::Int], Some(IrType::Int));
+    let negative = builder.block();
+    let positive = builder.block();
+    let x = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let zero = builder.value(IrType::Int, int(0));
+    let less = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntLg, x, zero));
+    builder.terminate(Terminator::Branch {
+        condition: less,
+        then: negative,
+        otherwise: positive,
+    });
+    builder.switch_to(negative);
+    let negated = builder.value(
+        IrType::Int,
+        Operation::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            value: x,
+        },
+    );
+    builder.terminate(Terminator::Return(Some(negated)));
+    builder.switch_to(positive);
+    builder.terminate(Terminator::Return(Some(x)));
+    builder.finish()
+}
+
+/// `routine main(n : integer) : integer => callee(n) + 1;`
+fn main(callee: u64) -> Function {
+    let mut builder = FunctionBuilder::new("main", vec![IrType::Int], Some(IrType::Int));
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let result = builder.value(
+        IrType::Int,
+        Operation::Call {
+            function_label: callee,
+            args: vec![n],
+        },
+    );
+    let one = builder.value(IrType::Int, int(1));
+    let sum = builder.value(
+        IrType::Int,
+        binary(SemanticBinaryOperator::IntAdd, result, one),
+    );
+    builder.terminate(Terminator::Return(Some(sum)));
+    builder.finish()
+}
+
+#[test]
+fn small_routines_are_inlined() {
+    let mut routines = BTreeMap::from([(0, main(1)), (1, double())]);
+    assert_eq!(inline(&mut routines, THRESHOLD), 1);
+    assert_eq!(inline(&mut routines, THRESHOLD), 0);
+    let main = routines.get_mut(&0).expect("Routine is kept");
+    passes::optimize(main);
+    assert_eq!(
+        main.to_string(),
+        "\
+routine main(int) : int
+bb0:
+  %0: int = param 0
+  jump bb1
+bb1:
+  %5: int = const 2
+  %6: int = IntMul %0, %5
+  jump bb2
+bb2:
+  %2: int = const 1
+  %3: int = IntAdd %6, %2
+  return %3
+"
+    );
+    // The callee itself stays for other modules
+    assert_eq!(routines[&1], double());
+}
+
+#[test]
+fn returns_are_merged() {
+    let mut routines = BTreeMap::from([(0, main(1)), (1, abs())]);
+    assert_eq!(inline(&mut routines, THRESHOLD), 1);
+    let main = &routines[&0];
+    let continuation = main.block(BlockId(4));
+    assert_eq!(
+        continuation.instructions[0].operation,
+        Operation::Phi(vec![(BlockId(2), ValueId(7)), (BlockId(3), ValueId(4))]),
+        "{main}"
+    );
+    assert_eq!(
+        continuation.terminator,
+        Terminator::Return(Some(ValueId(3)))
+    );
+}
+
+#[test]
+fn recursive_and_large_routines_stay() {
+    assert_eq!(size(&double()), 2);
+    let mut routines = BTreeMap::from([(0, main(1)), (1, double())]);
+    assert_eq!(inline(&mut routines, 1), 0);
+
+    // `even` and `odd` call each other
+    let mut routines = BTreeMap::from([(0, main(1)), (1, main(0))]);
+    let before = routines.clone();
+    assert_eq!(inline(&mut routines, THRESHOLD), 0);
+    assert_eq!(routines, before);
+}
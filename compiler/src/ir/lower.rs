@@ -0,0 +1,254 @@
+//! `Function`s to stack `Bytecode`: every value read somewhere gets a local,
+//! phis are assigned at the end of each predecessor
+
+use std::collections::HashMap;
+
+use super::{BlockId, Function, Operation, Terminator, ValueId};
+use crate::bytecode::{Bytecode, Location};
+use crate::consteval::Constant;
+
+fn load(local: usize) -> Bytecode {
+    Bytecode::Load {
+        loc: Location::Local(local),
+    }
+}
+
+fn store(local: usize) -> Bytecode {
+    Bytecode::Store {
+        loc: Location::Local(local),
+    }
+}
+
+/// Leaves a value on the stack once lowered, even if it is unused
+fn pushes(operation: &Operation) -> bool {
+    match operation {
+        Operation::Phi(_)
+        | Operation::StoreGlobal { .. }
+        | Operation::StoreField { .. }
+        | Operation::StoreElement { .. }
+        | Operation::Print { .. } => false,
+        Operation::Const(_)
+        | Operation::String { .. }
+        | Operation::Param { .. }
+        | Operation::Copy(_)
+        | Operation::Binary { .. }
+        | Operation::Unary { .. }
+        | Operation::IntToReal(_)
+        | Operation::RealToInt(_)
+        | Operation::IntToBool(_)
+        | Operation::LoadGlobal { .. }
+        | Operation::AllocRecord { .. }
+        | Operation::AllocArray { .. }
+        | Operation::ArraySize(_)
+        | Operation::LoadField { .. }
+        | Operation::LoadElement { .. }
+        | Operation::Call { .. }
+        | Operation::CallNative { .. } => true,
+    }
+}
+
+struct Lowering<'a> {
+    function: &'a Function,
+    locals: HashMap<ValueId, usize>,
+    /// Label of `BlockId(0)`, the others follow it
+    first_block_label: u64,
+    labels: &'a mut u64,
+    code: Vec<Bytecode>,
+}
+
+impl Lowering<'_> {
+    fn block_label(&self, block: BlockId) -> u64 {
+        self.first_block_label + u64::from(block.0)
+    }
+
+    fn local(&self, value: ValueId) -> usize {
+        self.locals[&value]
+    }
+
+    /// Assigns the phis of `to` with the values coming from `from`,
+    /// all the values are read before the first phi is written
+    fn phi_copies(&mut self, from: BlockId, to: BlockId) {
+        let mut targets = Vec::new();
+        for instruction in &self.function.block(to).instructions {
+            let (Some((result, _)), Operation::Phi(incoming)) =
+                (instruction.result, &instruction.operation)
+            else {
+                continue;
+            };
+            let Some(&local) = self.locals.get(&result) else {
+                continue;
+            };
+            let (_, value) = incoming
+                .iter()
+                .find(|&&(predecessor, _)| predecessor == from)
+                .expect("Phis list every predecessor");
+            self.code.push(load(self.local(*value)));
+            targets.push(local);
+        }
+        self.code.extend(targets.into_iter().rev().map(store));
+    }
+
+    fn has_phi_copies(&self, to: BlockId) -> bool {
+        self.function
+            .block(to)
+            .instructions
+            .iter()
+            .any(|instruction| {
+                matches!(instruction.operation, Operation::Phi(_))
+                    && instruction
+                        .result
+                        .is_some_and(|(result, _)| self.locals.contains_key(&result))
+            })
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        if let Operation::Phi(_) = operation {
+            return;
+        }
+        for operand in operation.operands() {
+            self.code.push(load(self.local(operand)));
+        }
+        let tail: &[Bytecode] = match *operation {
+            Operation::Const(Constant::Int(value)) => &[Bytecode::IntConst { value }],
+            Operation::Const(Constant::Real(value)) => &[Bytecode::RealConst { value }],
+            Operation::Const(Constant::Bool(value)) => &[Bytecode::IntConst {
+                value: i64::from(value),
+            }],
+            Operation::String { id } => &[Bytecode::StringConst { id }],
+            Operation::Param { index } => &[Bytecode::Load {
+                loc: Location::Argument(index),
+            }],
+            Operation::Copy(_) | Operation::Phi(_) => &[],
+            Operation::Binary { op, .. } => &[Bytecode::BinOp { op }],
+            Operation::Unary { op, .. } => &[Bytecode::UnOp { op }],
+            Operation::IntToReal(_) => &[Bytecode::IntToReal],
+            Operation::RealToInt(_) => &[Bytecode::RealToInt],
+            Operation::IntToBool(_) => &[Bytecode::IntToBool],
+            Operation::LoadGlobal { index } => &[Bytecode::Load {
+                loc: Location::Global(index),
+            }],
+            Operation::StoreGlobal { index, .. } => &[Bytecode::Store {
+                loc: Location::Global(index),
+            }],
+            Operation::AllocRecord { type_id, size } => &[Bytecode::AllocRecord { type_id, size }],
+            Operation::AllocArray { type_id, size } => &[Bytecode::AllocArray { type_id, size }],
+            Operation::ArraySize(_) => &[Bytecode::ArraySize],
+            Operation::LoadField { offset, .. } => &[
+                Bytecode::FieldAddress {
+                    field_offset: offset,
+                },
+                Bytecode::LoadAddress,
+            ],
+            Operation::StoreField { offset, .. } => &[
+                Bytecode::FieldAddress {
+                    field_offset: offset,
+                },
+                Bytecode::StoreAddress,
+            ],
+            Operation::LoadElement { .. } => &[Bytecode::ElementAddress, Bytecode::LoadAddress],
+            Operation::StoreElement { .. } => &[Bytecode::ElementAddress, Bytecode::StoreAddress],
+            Operation::Call { function_label, .. } => &[Bytecode::Call { function_label }],
+            Operation::CallNative { id, .. } => &[Bytecode::CallNative { id }],
+            Operation::Print { type_id, .. } => &[Bytecode::Print { type_id }],
+        };
+        self.code.extend_from_slice(tail);
+    }
+
+    fn terminator(&mut self, block: BlockId, terminator: Terminator) {
+        match terminator {
+            Terminator::Jump(target) => {
+                self.phi_copies(block, target);
+                self.code.push(Bytecode::Jump {
+                    label: self.block_label(target),
+                });
+            }
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.code.push(load(self.local(condition)));
+                if self.has_phi_copies(then) || self.has_phi_copies(otherwise) {
+                    // The copies of each edge need their own place
+                    let edge = *self.labels;
+                    *self.labels += 1;
+                    self.code.push(Bytecode::JumpZero { label: edge });
+                    self.terminator(block, Terminator::Jump(then));
+                    self.code.push(Bytecode::Label { id: edge });
+                    self.terminator(block, Terminator::Jump(otherwise));
+                } else {
+                    self.code.extend([
+                        Bytecode::JumpZero {
+                            label: self.block_label(otherwise),
+                        },
+                        Bytecode::Jump {
+                            label: self.block_label(then),
+                        },
+                    ]);
+                }
+            }
+            Terminator::Return(value) => {
+                self.code.push(match value {
+                    Some(value) => load(self.local(value)),
+                    // The VM expects a value on the stack anyway
+                    None => Bytecode::IntConst { value: 0 },
+                });
+                self.code.push(Bytecode::Ret);
+            }
+            Terminator::Panic { code } => self.code.push(Bytecode::Panic { code }),
+        }
+    }
+}
+
+/// Code of `function` starting with `Bytecode::Label { id: function_label }`.
+/// Blocks and edges get fresh label ids starting at `*labels`, which is advanced past them.
+///
+/// # Panics
+///
+/// If the function has more than `u16::MAX` arguments or values
+#[must_use]
+pub fn to_bytecode(function: &Function, function_label: u64, labels: &mut u64) -> Vec<Bytecode> {
+    let uses = function.use_counts();
+    let mut locals = HashMap::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Some((result, _)) = instruction.result
+            && uses[result.0 as usize] > 0
+        {
+            let _: Option<usize> = locals.insert(result, locals.len());
+        }
+    }
+    let first_block_label = *labels;
+    *labels += u64::try_from(function.blocks.len()).expect("Block count fits into u64");
+    let enter = Bytecode::Enter {
+        args: u16::try_from(function.args.len()).expect("Argument count fits into u16"),
+        locals: u16::try_from(locals.len()).expect("Local count fits into u16"),
+    };
+    let mut lowering = Lowering {
+        function,
+        locals,
+        first_block_label,
+        labels,
+        code: vec![Bytecode::Label { id: function_label }, enter],
+    };
+
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        lowering.code.push(Bytecode::Label {
+            id: lowering.block_label(id),
+        });
+        for instruction in &block.instructions {
+            lowering.operation(&instruction.operation);
+            if !pushes(&instruction.operation) {
+                continue;
+            }
+            match instruction.result {
+                Some((result, _)) if lowering.locals.contains_key(&result) => {
+                    let local = lowering.local(result);
+                    lowering.code.push(store(local));
+                }
+                _ => lowering.code.push(Bytecode::Drop),
+            }
+        }
+        lowering.terminator(id, block.terminator);
+    }
+    lowering.code
+}
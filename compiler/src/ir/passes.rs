@@ -0,0 +1,323 @@
+//! Optimizations of `Function`s, each one tells whether it changed anything
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::cfg::{Cfg, Loop};
+use super::{BlockId, Function, Instruction, Operation, Terminator, ValueId};
+use crate::consteval::Constant;
+
+#[cfg(test)]
+mod tests;
+
+/// Runs all the passes until none of them changes anything
+pub fn optimize(function: &mut Function) {
+    while copy_propagation(function)
+        | common_subexpressions(function)
+        | loop_invariants(function)
+        | dead_code(function)
+    {}
+}
+
+/// Reads of copies and of phis merging a single value read the original value instead
+pub fn copy_propagation(function: &mut Function) -> bool {
+    let mut sources = HashMap::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        let Some((result, _)) = instruction.result else {
+            continue;
+        };
+        let source = match &instruction.operation {
+            Operation::Copy(source) => Some(*source),
+            // A phi may read itself along a back edge
+            Operation::Phi(incoming) => {
+                let mut values = incoming
+                    .iter()
+                    .map(|&(_, value)| value)
+                    .filter(|&value| value != result);
+                values
+                    .next()
+                    .filter(|&first| values.all(|value| value == first))
+            }
+            Operation::Const(_)
+            | Operation::String { .. }
+            | Operation::Param { .. }
+            | Operation::Binary { .. }
+            | Operation::Unary { .. }
+            | Operation::IntToReal(_)
+            | Operation::RealToInt(_)
+            | Operation::IntToBool(_)
+            | Operation::LoadGlobal { .. }
+            | Operation::StoreGlobal { .. }
+            | Operation::AllocRecord { .. }
+            | Operation::AllocArray { .. }
+            | Operation::ArraySize(_)
+            | Operation::LoadField { .. }
+            | Operation::StoreField { .. }
+            | Operation::LoadElement { .. }
+            | Operation::StoreElement { .. }
+            | Operation::Call { .. }
+            | Operation::CallNative { .. }
+            | Operation::Print { .. } => None,
+        };
+        if let Some(source) = source {
+            let _: Option<ValueId> = sources.insert(result, source);
+        }
+    }
+
+    let mut changed = false;
+    function.map_operands(|mut value| {
+        while let Some(&source) = sources.get(&value) {
+            value = source;
+            changed = true;
+        }
+        value
+    });
+    changed
+}
+
+/// `-0.0` and `0.0` are different values, unlike with `==`
+fn same(a: &Operation, b: &Operation) -> bool {
+    match (a, b) {
+        (Operation::Const(Constant::Real(a)), Operation::Const(Constant::Real(b))) => {
+            a.to_bits() == b.to_bits()
+        }
+        _ => a == b,
+    }
+}
+
+/// A pure operation repeated in a block dominated by its first occurrence
+/// becomes a copy of the first result
+pub fn common_subexpressions(function: &mut Function) -> bool {
+    let cfg = Cfg::new(function);
+    let tree = cfg.dominator_tree();
+    let mut available = Vec::new();
+    let mut changed = false;
+    // Blocks with the length of `available` to restore once their subtree is done
+    let mut stack = vec![(BlockId(0), None)];
+    while let Some((block, restore)) = stack.pop() {
+        if let Some(length) = restore {
+            available.truncate(length);
+            continue;
+        }
+        stack.push((block, Some(available.len())));
+        for instruction in &mut function.block_mut(block).instructions {
+            let operation = &mut instruction.operation;
+            let Some((result, _)) = instruction.result else {
+                continue;
+            };
+            if !operation.is_pure() || matches!(operation, Operation::Copy(_)) {
+                continue;
+            }
+            match available
+                .iter()
+                .find(|(known, _): &&(Operation, ValueId)| same(known, operation))
+            {
+                Some(&(_, value)) => {
+                    *operation = Operation::Copy(value);
+                    changed = true;
+                }
+                None => available.push((operation.clone(), result)),
+            }
+        }
+        stack.extend(tree[block.index()].iter().map(|&child| (child, None)));
+    }
+    changed
+}
+
+/// The only predecessor of the loop header from outside the loop, when it leads nowhere else
+fn preheader(function: &Function, cfg: &Cfg, l: &Loop) -> Option<BlockId> {
+    match *outside_predecessors(cfg, l).as_slice() {
+        [block] if function.block(block).terminator == Terminator::Jump(l.header) => Some(block),
+        _ => None,
+    }
+}
+
+fn outside_predecessors(cfg: &Cfg, l: &Loop) -> Vec<BlockId> {
+    cfg.predecessors(l.header)
+        .iter()
+        .copied()
+        .filter(|block| !l.body.contains(block))
+        .collect()
+}
+
+/// Puts a block between the header and its predecessors from outside the loop.
+/// Header phis get the values from these predecessors merged by a phi in the new block.
+fn insert_preheader(function: &mut Function, cfg: &Cfg, l: &Loop) {
+    let outside = outside_predecessors(cfg, l);
+    let preheader = BlockId(u32::try_from(function.blocks.len()).expect("Block ids fit into u32"));
+    let mut merged = Vec::new();
+    let mut value_count = function.value_count;
+    for instruction in &mut function.block_mut(l.header).instructions {
+        let (Some((_, t)), Operation::Phi(incoming)) =
+            (instruction.result, &mut instruction.operation)
+        else {
+            continue;
+        };
+        let (entering, looping): (Vec<_>, Vec<_>) = incoming
+            .drain(..)
+            .partition(|(block, _)| outside.contains(block));
+        let value = ValueId(value_count);
+        value_count += 1;
+        merged.push(Instruction {
+            result: Some((value, t)),
+            operation: Operation::Phi(entering),
+        });
+        *incoming = looping;
+        incoming.push((preheader, value));
+    }
+    function.value_count = value_count;
+    for &block in &outside {
+        function
+            .block_mut(block)
+            .terminator
+            .map_successors(|target| {
+                if target == l.header {
+                    preheader
+                } else {
+                    target
+                }
+            });
+    }
+    function.blocks.push(super::Block {
+        instructions: merged,
+        terminator: Terminator::Jump(l.header),
+    });
+}
+
+/// Pure operations of a loop that cannot fail and only read values computed before the loop
+/// are moved to its preheader, which is created when missing
+pub fn loop_invariants(function: &mut Function) -> bool {
+    let cfg = Cfg::new(function);
+    // A loop around the entry cannot be entered from outside
+    let loops: Vec<_> = cfg
+        .loops()
+        .into_iter()
+        .filter(|l| !outside_predecessors(&cfg, l).is_empty())
+        .collect();
+    let mut changed = false;
+    for l in &loops {
+        if preheader(function, &cfg, l).is_none() {
+            insert_preheader(function, &cfg, l);
+            changed = true;
+        }
+    }
+    if changed {
+        return true;
+    }
+
+    for l in &loops {
+        let Some(preheader) = preheader(function, &cfg, l) else {
+            continue;
+        };
+        let mut inside: BTreeSet<ValueId> = l
+            .body
+            .iter()
+            .flat_map(|&block| &function.block(block).instructions)
+            .filter_map(|instruction| Some(instruction.result?.0))
+            .collect();
+        let body: Vec<_> = cfg
+            .reverse_postorder()
+            .iter()
+            .copied()
+            .filter(|block| l.body.contains(block))
+            .collect();
+        for block in body {
+            let instructions = &mut function.block_mut(block).instructions;
+            let mut hoisted = Vec::new();
+            instructions.retain(|instruction| {
+                let Some((result, _)) = instruction.result else {
+                    return true;
+                };
+                let invariant = instruction.operation.is_pure()
+                    && !instruction.operation.may_fail()
+                    && instruction
+                        .operation
+                        .operands()
+                        .iter()
+                        .all(|operand| !inside.contains(operand));
+                if invariant {
+                    let _: bool = inside.remove(&result);
+                    hoisted.push(instruction.clone());
+                }
+                !invariant
+            });
+            changed |= !hoisted.is_empty();
+            function.block_mut(preheader).instructions.extend(hoisted);
+        }
+    }
+    changed
+}
+
+/// Drops unreachable blocks, renumbering the others
+pub(crate) fn unreachable_blocks(function: &mut Function) -> bool {
+    let cfg = Cfg::new(function);
+    let mut renumbered = Vec::with_capacity(function.blocks.len());
+    let mut next = 0;
+    for block in function.block_ids() {
+        renumbered.push(cfg.is_reachable(block).then(|| {
+            next += 1;
+            BlockId(next - 1)
+        }));
+    }
+    if renumbered.iter().all(Option::is_some) {
+        return false;
+    }
+
+    let blocks = core::mem::take(&mut function.blocks);
+    for (mut block, id) in blocks.into_iter().zip(&renumbered) {
+        if id.is_none() {
+            continue;
+        }
+        block
+            .terminator
+            .map_successors(|target| renumbered[target.index()].expect("Reachable"));
+        for instruction in &mut block.instructions {
+            if let Operation::Phi(incoming) = &mut instruction.operation {
+                incoming.retain(|(predecessor, _)| renumbered[predecessor.index()].is_some());
+                for (predecessor, _) in incoming {
+                    *predecessor = renumbered[predecessor.index()].expect("Retained");
+                }
+            }
+        }
+        function.blocks.push(block);
+    }
+    true
+}
+
+/// Removes unreachable blocks and the instructions computing unused values,
+/// unless they may fail or have other effects
+pub fn dead_code(function: &mut Function) -> bool {
+    let mut changed = unreachable_blocks(function);
+    loop {
+        let mut uses = vec![0_usize; function.value_count as usize];
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                let result = instruction.result.map(|(result, _)| result);
+                for operand in instruction.operation.operands() {
+                    // A phi reading itself along a back edge does not keep it alive
+                    if Some(operand) != result {
+                        uses[operand.0 as usize] += 1;
+                    }
+                }
+            }
+            if let Some(operand) = block.terminator.operand() {
+                uses[operand.0 as usize] += 1;
+            }
+        }
+
+        let mut removed = false;
+        for block in &mut function.blocks {
+            block.instructions.retain(|instruction| {
+                let dead = instruction
+                    .result
+                    .is_some_and(|(result, _)| uses[result.0 as usize] == 0)
+                    && instruction.operation.is_removable();
+                removed |= dead;
+                !dead
+            });
+        }
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
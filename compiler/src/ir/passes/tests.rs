@@ -0,0 +1,243 @@
+use super::*;
+use crate::ir::{FunctionBuilder, IrType};
+use crate::operators::SemanticBinaryOperator;
+
+fn binary(op: SemanticBinaryOperator, lhs: ValueId, rhs: ValueId) -> Operation {
+    Operation::Binary { op, lhs, rhs }
+}
+
+fn int(value: i64) -> Operation {
+    Operation::Const(Constant::Int(value))
+}
+
+#[test]
+fn copies_are_bypassed() {
+    let mut builder = FunctionBuilder::new("f", vec![IrType::Int], Some(IrType::Int));
+    let next = builder.block();
+    let x = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let y = builder.value(IrType::Int, Operation::Copy(x));
+    builder.terminate(Terminator::Jump(next));
+    builder.switch_to(next);
+    let z = builder.value(IrType::Int, Operation::Phi(vec![(BlockId(0), y)]));
+    let w = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntMul, z, y));
+    builder.terminate(Terminator::Return(Some(w)));
+    let mut function = builder.finish();
+
+    assert!(copy_propagation(&mut function));
+    assert_eq!(
+        function.block(next).instructions[1].operation,
+        binary(SemanticBinaryOperator::IntMul, x, x)
+    );
+    assert!(!copy_propagation(&mut function));
+    assert!(dead_code(&mut function));
+    assert_eq!(
+        function.to_string(),
+        "\
+routine f(int) : int
+bb0:
+  %0: int = param 0
+  jump bb1
+bb1:
+  %3: int = IntMul %0, %0
+  return %3
+"
+    );
+}
+
+/// `if c then print a * 2; else print a * 2; end; print a * 2;`
+#[test]
+fn dominating_expressions_are_reused() {
+    let mut builder = FunctionBuilder::new("f", vec![IrType::Bool, IrType::Int], None);
+    let then = builder.block();
+    let otherwise = builder.block();
+    let join = builder.block();
+    let c = builder.value(IrType::Bool, Operation::Param { index: 0 });
+    let a = builder.value(IrType::Int, Operation::Param { index: 1 });
+    builder.terminate(Terminator::Branch {
+        condition: c,
+        then,
+        otherwise,
+    });
+    for block in [then, otherwise, join] {
+        builder.switch_to(block);
+        let two = builder.value(IrType::Int, int(2));
+        let product = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntMul, a, two));
+        builder.effect(Operation::Print {
+            type_id: crate::bytecode::TypeId::INTEGER,
+            value: product,
+        });
+        if block != join {
+            builder.terminate(Terminator::Jump(join));
+        }
+    }
+    let mut function = builder.finish();
+    let before = function.clone();
+
+    // The branches do not dominate each other
+    assert!(!common_subexpressions(&mut function));
+    assert_eq!(function, before);
+
+    // Once the constant is known in the entry, the join can reuse the product computed there
+    builder = FunctionBuilder::new("g", vec![IrType::Int], None);
+    let a = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let two = builder.value(IrType::Int, int(2));
+    let _first = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntMul, a, two));
+    let again = builder.value(IrType::Int, int(2));
+    let second = builder.value(
+        IrType::Int,
+        binary(SemanticBinaryOperator::IntMul, a, again),
+    );
+    builder.effect(Operation::Print {
+        type_id: crate::bytecode::TypeId::INTEGER,
+        value: second,
+    });
+    let mut function = builder.finish();
+    optimize(&mut function);
+    assert_eq!(
+        function.to_string(),
+        "\
+routine g(int)
+bb0:
+  %0: int = param 0
+  %1: int = const 2
+  %2: int = IntMul %0, %1
+  print type 0, %2
+  return
+"
+    );
+}
+
+#[test]
+fn real_zeros_are_distinct() {
+    let mut builder = FunctionBuilder::new("f", Vec::new(), Some(IrType::Real));
+    let zero = builder.value(IrType::Real, Operation::Const(Constant::Real(0.0)));
+    let negative = builder.value(IrType::Real, Operation::Const(Constant::Real(-0.0)));
+    let sum = builder.value(
+        IrType::Real,
+        binary(SemanticBinaryOperator::RealDiv, zero, negative),
+    );
+    builder.terminate(Terminator::Return(Some(sum)));
+    assert!(!common_subexpressions(&mut builder.finish()));
+}
+
+/// `while i < n loop print real(n) * 2.0; i := i + 1; end;`
+fn invariant_loop(entry_branches: bool) -> Function {
+    let mut builder = FunctionBuilder::new("f", vec![IrType::Int], None);
+    let header = builder.block();
+    let body = builder.block();
+    let exit = builder.block();
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let start = builder.value(IrType::Int, int(0));
+    builder.terminate(if entry_branches {
+        // Both edges enter the loop
+        Terminator::Branch {
+            condition: n,
+            then: header,
+            otherwise: header,
+        }
+    } else {
+        Terminator::Jump(header)
+    });
+
+    builder.switch_to(body);
+    let real = builder.value(IrType::Real, Operation::IntToReal(n));
+    let two = builder.value(IrType::Real, Operation::Const(Constant::Real(2.0)));
+    let double = builder.value(
+        IrType::Real,
+        binary(SemanticBinaryOperator::RealMul, real, two),
+    );
+    builder.effect(Operation::Print {
+        type_id: crate::bytecode::TypeId::REAL,
+        value: double,
+    });
+    let one = builder.value(IrType::Int, int(1));
+    // Defined by the header phi below
+    let i = ValueId(7);
+    let next = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntAdd, i, one));
+    builder.terminate(Terminator::Jump(header));
+
+    builder.switch_to(header);
+    let incoming = if entry_branches {
+        vec![(BlockId(0), start), (BlockId(0), start), (body, next)]
+    } else {
+        vec![(BlockId(0), start), (body, next)]
+    };
+    assert_eq!(builder.value(IrType::Int, Operation::Phi(incoming)), i);
+    let more = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntLg, i, n));
+    builder.terminate(Terminator::Branch {
+        condition: more,
+        then: body,
+        otherwise: exit,
+    });
+    builder.finish()
+}
+
+#[test]
+fn invariants_leave_loops() {
+    let mut function = invariant_loop(false);
+    assert!(loop_invariants(&mut function));
+    assert!(!loop_invariants(&mut function));
+    let entry = &function.block(BlockId(0)).instructions;
+    // IntToReal, 2.0, the product and 1 are hoisted, `i + 1` may overflow
+    assert_eq!(entry.len(), 6);
+    assert_eq!(
+        entry[2].operation,
+        Operation::IntToReal(ValueId(0)),
+        "{function}"
+    );
+    let body = &function.block(BlockId(2)).instructions;
+    assert_eq!(body.len(), 2);
+    assert!(matches!(body[0].operation, Operation::Print { .. }));
+}
+
+#[test]
+fn preheaders_are_inserted() {
+    let mut function = invariant_loop(true);
+    assert!(loop_invariants(&mut function));
+    let preheader = BlockId(4);
+    assert_eq!(
+        function.block(BlockId(0)).terminator,
+        Terminator::Branch {
+            condition: ValueId(0),
+            then: preheader,
+            otherwise: preheader,
+        }
+    );
+    assert_eq!(
+        function.block(preheader).terminator,
+        Terminator::Jump(BlockId(1))
+    );
+    assert!(loop_invariants(&mut function));
+    assert_eq!(function.block(preheader).instructions.len(), 5);
+}
+
+#[test]
+fn effects_and_failures_stay() {
+    let mut builder = FunctionBuilder::new("f", vec![IrType::Int], None);
+    let dead = builder.block();
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let zero = builder.value(IrType::Int, int(0));
+    let _quotient = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntDiv, n, zero));
+    let _unused = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntEq, n, zero));
+    builder.effect(Operation::StoreGlobal { index: 0, value: n });
+    builder.switch_to(dead);
+    builder.effect(Operation::Print {
+        type_id: crate::bytecode::TypeId::INTEGER,
+        value: n,
+    });
+    let mut function = builder.finish();
+
+    assert!(dead_code(&mut function));
+    assert_eq!(
+        function.to_string(),
+        "\
+routine f(int)
+bb0:
+  %0: int = param 0
+  %1: int = const 0
+  %2: int = IntDiv %0, %1
+  store @0, %0
+  return
+"
+    );
+}
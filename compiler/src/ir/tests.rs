@@ -0,0 +1,374 @@
+use core::fmt::Write as _;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use expect_test::expect;
+
+use super::cfg::{Cfg, Loop};
+use super::*;
+use crate::bytecode::{Bytecode, Location};
+use crate::{Options, check, lex, parse};
+
+/// `routine sum(n : integer) : integer is var s is 0; for i in 1 .. n loop s := s + i; end; return s; end;`
+fn sum() -> Function {
+    let mut builder = FunctionBuilder::new("sum", vec![IrType::Int], Some(IrType::Int));
+    let header = builder.block();
+    let body = builder.block();
+    let exit = builder.block();
+
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let zero = builder.value(IrType::Int, Operation::Const(Constant::Int(0)));
+    let one = builder.value(IrType::Int, Operation::Const(Constant::Int(1)));
+    builder.terminate(Terminator::Jump(header));
+
+    // Defined by the header phis below
+    let s = ValueId(5);
+    let i = ValueId(6);
+    let next_s = ValueId(3);
+    let next_i = ValueId(4);
+    builder.switch_to(body);
+    assert_eq!(
+        builder.value(
+            IrType::Int,
+            Operation::Binary {
+                op: SemanticBinaryOperator::IntAdd,
+                lhs: s,
+                rhs: i,
+            },
+        ),
+        next_s
+    );
+    assert_eq!(
+        builder.value(
+            IrType::Int,
+            Operation::Binary {
+                op: SemanticBinaryOperator::IntAdd,
+                lhs: i,
+                rhs: one,
+            },
+        ),
+        next_i
+    );
+    builder.terminate(Terminator::Jump(header));
+
+    builder.switch_to(header);
+    assert_eq!(
+        builder.value(
+            IrType::Int,
+            Operation::Phi(vec![(BlockId(0), zero), (body, next_s)]),
+        ),
+        s
+    );
+    assert_eq!(
+        builder.value(
+            IrType::Int,
+            Operation::Phi(vec![(BlockId(0), one), (body, next_i)]),
+        ),
+        i
+    );
+    let more = builder.value(
+        IrType::Bool,
+        Operation::Binary {
+            op: SemanticBinaryOperator::IntLe,
+            lhs: i,
+            rhs: n,
+        },
+    );
+    builder.terminate(Terminator::Branch {
+        condition: more,
+        then: body,
+        otherwise: exit,
+    });
+
+    builder.switch_to(exit);
+    builder.terminate(Terminator::Return(Some(s)));
+    builder.finish()
+}
+
+#[test]
+fn textual_dump() {
+    assert_eq!(
+        sum().to_string(),
+        "\
+routine sum(int) : int
+bb0:
+  %0: int = param 0
+  %1: int = const 0
+  %2: int = const 1
+  jump bb1
+bb1:
+  %5: int = phi [bb0: %1, bb2: %3]
+  %6: int = phi [bb0: %2, bb2: %4]
+  %7: bool = IntLe %6, %0
+  branch %7, bb2, bb3
+bb2:
+  %3: int = IntAdd %5, %6
+  %4: int = IntAdd %6, %2
+  jump bb1
+bb3:
+  return %5
+"
+    );
+}
+
+#[test]
+fn dominators_and_loops() {
+    let cfg = Cfg::new(&sum());
+    assert_eq!(cfg.reverse_postorder(), [0, 1, 3, 2].map(BlockId));
+    assert_eq!(cfg.predecessors(BlockId(1)), [BlockId(0), BlockId(2)]);
+    assert_eq!(cfg.immediate_dominator(BlockId(0)), None);
+    for block in [2, 3] {
+        assert_eq!(cfg.immediate_dominator(BlockId(block)), Some(BlockId(1)));
+    }
+    assert!(cfg.dominates(BlockId(1), BlockId(2)));
+    assert!(!cfg.dominates(BlockId(2), BlockId(3)));
+    assert_eq!(
+        cfg.loops(),
+        [Loop {
+            header: BlockId(1),
+            body: [1, 2].map(BlockId).into(),
+        }]
+    );
+}
+
+#[test]
+fn unreachable_blocks_have_no_dominator() {
+    let mut builder = FunctionBuilder::new("f", Vec::new(), None);
+    let dead = builder.block();
+    builder.switch_to(dead);
+    builder.terminate(Terminator::Jump(BlockId(0)));
+    let cfg = Cfg::new(&builder.finish());
+    assert!(!cfg.is_reachable(dead));
+    assert!(cfg.predecessors(BlockId(0)).is_empty());
+    assert!(cfg.loops().is_empty());
+}
+
+#[test]
+fn phis_are_assigned_by_predecessors() {
+    let mut labels = 10;
+    let code = lower::to_bytecode(&sum(), 0, &mut labels);
+    assert_eq!(labels, 14);
+    let local = |index| Location::Local(index);
+    // Locals follow the order of blocks: n, 0, 1, s, i, i <= n, s + i, i + 1
+    assert_eq!(
+        code[..12],
+        [
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 1, locals: 8 },
+            Bytecode::Label { id: 10 },
+            Bytecode::Load {
+                loc: Location::Argument(0),
+            },
+            Bytecode::Store { loc: local(0) },
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Store { loc: local(1) },
+            Bytecode::IntConst { value: 1 },
+            Bytecode::Store { loc: local(2) },
+            // Both phis of the header at once
+            Bytecode::Load { loc: local(1) },
+            Bytecode::Load { loc: local(2) },
+            Bytecode::Store { loc: local(4) },
+        ]
+    );
+}
+
+fn built(source: &str) -> BTreeMap<u64, Function> {
+    let program = parse(&lex(source)).expect("Parses");
+    build(&check(program, &Options::default()).expect("Checks"))
+}
+
+fn dump(routines: &BTreeMap<u64, Function>) -> String {
+    let mut out = String::new();
+    for (label, function) in routines {
+        write!(out, "L{label} {function}").expect("Writing to a string won't fail");
+    }
+    out
+}
+
+/// `tests/src/while_loops.i`: the loop header merges `n` and `steps`, the `if` merges `n`
+#[test]
+fn built_from_source() {
+    let mut routines = built(include_str!("../../../tests/src/while_loops.i"));
+    expect![[r"
+        L0 routine collatz(int) : int
+        bb0:
+          %0: int = param 0
+          %1: int = const 0
+          jump bb1
+        bb1:
+          %2: int = phi [bb0: %0, bb6: %19]
+          %16: int = phi [bb0: %1, bb6: %18]
+          %3: int = const 1
+          %4: bool = IntNeq %2, %3
+          branch %4, bb2, bb3
+        bb2:
+          %5: int = const 2
+          %6: int = IntMod %2, %5
+          %7: int = const 0
+          %8: bool = IntEq %6, %7
+          branch %8, bb4, bb5
+        bb3:
+          return %16
+        bb4:
+          %9: int = const 2
+          %10: int = IntDiv %2, %9
+          jump bb6
+        bb5:
+          %11: int = const 3
+          %12: int = IntMul %11, %2
+          %13: int = const 1
+          %14: int = IntAdd %12, %13
+          jump bb6
+        bb6:
+          %19: int = phi [bb4: %10, bb5: %14]
+          %17: int = const 1
+          %18: int = IntAdd %16, %17
+          jump bb1
+        L1 routine main()
+        bb0:
+          %0: int = const 7
+          %1: int = call L0(%0)
+          print type 0, %1
+          return
+    "]]
+    .assert_eq(&dump(&routines));
+
+    routines.values_mut().for_each(passes::optimize);
+    expect![[r"
+        L0 routine collatz(int) : int
+        bb0:
+          %0: int = param 0
+          %1: int = const 0
+          %3: int = const 1
+          %5: int = const 2
+          %11: int = const 3
+          jump bb1
+        bb1:
+          %2: int = phi [bb0: %0, bb6: %19]
+          %16: int = phi [bb0: %1, bb6: %18]
+          %4: bool = IntNeq %2, %3
+          branch %4, bb2, bb3
+        bb2:
+          %6: int = IntMod %2, %5
+          %8: bool = IntEq %6, %1
+          branch %8, bb4, bb5
+        bb3:
+          return %16
+        bb4:
+          %10: int = IntDiv %2, %5
+          jump bb6
+        bb5:
+          %12: int = IntMul %11, %2
+          %14: int = IntAdd %12, %3
+          jump bb6
+        bb6:
+          %19: int = phi [bb4: %10, bb5: %14]
+          %18: int = IntAdd %16, %3
+          jump bb1
+        L1 routine main()
+        bb0:
+          %0: int = const 7
+          %1: int = call L0(%0)
+          print type 0, %1
+          return
+    "]]
+    .assert_eq(&dump(&routines));
+}
+
+/// Phis come first and list every predecessor once, the values read are defined once
+fn assert_well_formed(function: &Function) {
+    let cfg = Cfg::new(function);
+    let mut defined = HashSet::new();
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let Some((result, _)) = instruction.result {
+                assert!(
+                    defined.insert(result),
+                    "{result} is defined twice in {function}"
+                );
+            }
+        }
+    }
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        let phis = block
+            .instructions
+            .iter()
+            .take_while(|instruction| matches!(instruction.operation, Operation::Phi(_)))
+            .count();
+        for instruction in &block.instructions[phis..] {
+            assert!(
+                !matches!(instruction.operation, Operation::Phi(_)),
+                "Phis of {id} come first in {function}"
+            );
+        }
+        for instruction in &block.instructions[..phis] {
+            let Operation::Phi(incoming) = &instruction.operation else {
+                continue;
+            };
+            let mut from: Vec<_> = incoming.iter().map(|&(block, _)| block).collect();
+            from.sort();
+            let mut predecessors = cfg.predecessors(id).to_vec();
+            predecessors.sort();
+            // Entry phis merge nothing, they stand for values read on no path that runs
+            if id != BlockId(0) {
+                assert_eq!(from, predecessors, "{instruction} in {id} of {function}");
+            }
+        }
+        let operands = block
+            .instructions
+            .iter()
+            .flat_map(|instruction| instruction.operation.operands())
+            .chain(block.terminator.operand());
+        for operand in operands {
+            assert!(
+                defined.contains(&operand),
+                "{operand} is not defined in {function}"
+            );
+        }
+    }
+}
+
+/// Every program of `tests/src` that checks on its own is well formed before and after
+/// `passes::optimize`, and lowered
+#[test]
+fn corpus_is_well_formed() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/src");
+    let mut rejected = Vec::new();
+    for entry in fs::read_dir(dir).expect("Lists tests/src") {
+        let path = entry.expect("Lists tests/src").path();
+        let name = path
+            .file_stem()
+            .expect("Named")
+            .to_string_lossy()
+            .into_owned();
+        let source = fs::read_to_string(&path).expect("Reads the program");
+        let Ok(program) = parse(&lex(&source)) else {
+            rejected.push(name);
+            continue;
+        };
+        let Ok(program) = check(program, &Options::default()) else {
+            rejected.push(name);
+            continue;
+        };
+        let mut labels = program.label_count + 1;
+        for (label, mut function) in build(&program) {
+            assert_well_formed(&function);
+            passes::optimize(&mut function);
+            assert_well_formed(&function);
+            let code = lower::to_bytecode(&function, label, &mut labels);
+            assert_eq!(code.first(), Some(&Bytecode::Label { id: label }), "{name}");
+        }
+    }
+    rejected.sort();
+    // `imports` needs the modules it imports, the others are invalid on purpose
+    assert_eq!(
+        rejected,
+        [
+            "imports",
+            "invalid",
+            "lexer_invalid",
+            "unterminated_comment"
+        ]
+    );
+}
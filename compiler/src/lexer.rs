@@ -66,7 +66,7 @@ trait ImmutableIterator<'a>: Sized + Clone + From<&'a str> {
 }
 
 // TODO: rewrite with Chars<'a> and its .clone() method
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 struct IndexIterator<'a> {
     underlying: &'a str,
     index: usize,
@@ -153,6 +153,12 @@ fn name_disambiguation(lexeme: &str) -> TokenKind<'_> {
         "loop" => TokenKind::Keyword(Keyword::Loop),
         "reverse" => TokenKind::Keyword(Keyword::Reverse),
         "print" => TokenKind::Keyword(Keyword::Print),
+        "import" => TokenKind::Keyword(Keyword::Import),
+        "enum" => TokenKind::Keyword(Keyword::Enum),
+        "case" => TokenKind::Keyword(Keyword::Case),
+        "of" => TokenKind::Keyword(Keyword::Of),
+        "break" => TokenKind::Keyword(Keyword::Break),
+        "continue" => TokenKind::Keyword(Keyword::Continue),
         "and" => TokenKind::Operator(SyntacticOperator::And),
         "or" => TokenKind::Operator(SyntacticOperator::Or),
         "xor" => TokenKind::Operator(SyntacticOperator::Xor),
@@ -162,6 +168,7 @@ fn name_disambiguation(lexeme: &str) -> TokenKind<'_> {
         "integer" => TokenKind::BuiltinTypename(BuiltinTypename::Integer),
         "real" => TokenKind::BuiltinTypename(BuiltinTypename::Real),
         "boolean" => TokenKind::BuiltinTypename(BuiltinTypename::Boolean),
+        "string" => TokenKind::BuiltinTypename(BuiltinTypename::String),
         "NaN" => TokenKind::RealLiteral(RealLiteral { value: f64::NAN }),
     };
 
@@ -179,15 +186,110 @@ fn nominal_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexI
         .then(|| start.take_while_map(is_identifier_continue, name_disambiguation))
 }
 
-fn comment_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
+fn line_comment_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
     start.stars_with("--").map(|comment_start| {
-        comment_start.take_while_map(
-            |ch| ch != '\n',
-            |comment| TokenKind::Comment(Comment { value: comment }),
-        )
+        // `---` starts a doc comment, but `----` and longer are just separators
+        let doc_start = comment_start
+            .stars_with("-")
+            .filter(|it| it.next().is_none_or(|(ch, _)| ch != '-'));
+        match doc_start {
+            Some(doc_start) => doc_start.take_while_map(
+                |ch| ch != '\n',
+                |comment| TokenKind::DocComment(Comment { value: comment }),
+            ),
+            None => comment_start.take_while_map(
+                |ch| ch != '\n',
+                |comment| TokenKind::Comment(Comment { value: comment }),
+            ),
+        }
     })
 }
 
+/// Block comments are `/* ... */` and may be nested
+fn block_comment_token<'a>(
+    start: &IndexIterator<'a>,
+) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
+    let body_start = start.stars_with("/*")?;
+    let mut depth: usize = 1;
+    let mut it = body_start.clone();
+
+    loop {
+        if let Some(rest) = it.stars_with("*/") {
+            depth -= 1;
+            if depth == 0 {
+                let comment = ImmutableIterator::slice_to_str(&body_start, &it);
+                return Some((TokenKind::Comment(Comment { value: comment }), rest));
+            }
+            it = rest;
+        } else if let Some(rest) = it.stars_with("/*") {
+            depth += 1;
+            it = rest;
+        } else if let Some((_, rest)) = it.next() {
+            it = rest;
+        } else {
+            let problem = format!("Unterminated block comment starting at {}", start.position);
+            return Some((TokenKind::Invalid(InvalidToken { problem }), it));
+        }
+    }
+}
+
+fn comment_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
+    line_comment_token(start).or_else(|| block_comment_token(start))
+}
+
+const fn escaped_char(ch: char) -> Option<char> {
+    match ch {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '"' => Some(ch),
+        _ => None,
+    }
+}
+
+/// `"..."` with `\n`, `\t`, `\r`, `\0`, `\\` and `\"` escapes, it cannot span several lines
+fn string_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
+    let Some(('"', mut it)) = start.next() else {
+        return None;
+    };
+    let mut value = String::new();
+    let mut problem = None;
+
+    loop {
+        match it.next() {
+            Some(('"', rest)) => {
+                let kind = match problem {
+                    Some(problem) => TokenKind::Invalid(InvalidToken { problem }),
+                    None => TokenKind::StringLiteral(StringLiteral { value }),
+                };
+                return Some((kind, rest));
+            }
+            Some(('\\', rest)) => match rest.next() {
+                Some((ch, after)) if ch != '\n' => {
+                    match escaped_char(ch) {
+                        Some(ch) => value.push(ch),
+                        None if problem.is_none() => {
+                            problem = Some(format!("Unknown escape sequence `\\{ch}`"));
+                        }
+                        None => {}
+                    }
+                    it = after;
+                }
+                _ => it = rest,
+            },
+            Some((ch, rest)) if ch != '\n' => {
+                value.push(ch);
+                it = rest;
+            }
+            _ => {
+                let problem = format!("Unterminated string starting at {}", start.position);
+                return Some((TokenKind::Invalid(InvalidToken { problem }), it));
+            }
+        }
+    }
+}
+
 fn symbolic_token<'a>(start: &IndexIterator<'a>) -> Option<(TokenKind<'a>, IndexIterator<'a>)> {
     static KNOWN_TOKENS: &[(&str, TokenKind<'static>)] = &[
         (":=", TokenKind::Assignment),
@@ -283,6 +385,7 @@ fn numeric_token<'a>(
     }
 }
 
+#[derive(Debug)]
 pub struct Lexer<'src> {
     pos: IndexIterator<'src>,
     allow_sign: bool,
@@ -300,7 +403,7 @@ impl<'src> From<&'src str> for Lexer<'src> {
 impl Lexer<'_> {
     fn update_allow_sign(&mut self, token: &TokenKind<'_>) {
         self.allow_sign = match token {
-            TokenKind::Comment(_) => return,
+            TokenKind::Comment(_) | TokenKind::DocComment(_) => return,
 
             TokenKind::Assignment
             | TokenKind::LeftParenthesis
@@ -316,6 +419,7 @@ impl Lexer<'_> {
             | TokenKind::IntegerLiteral(_)
             | TokenKind::RealLiteral(_)
             | TokenKind::BoolLiteral(_)
+            | TokenKind::StringLiteral(_)
             | TokenKind::BuiltinTypename(_)
             | TokenKind::LeftBracket
             | TokenKind::RightParenthesis
@@ -333,6 +437,7 @@ impl<'src> Iterator for Lexer<'src> {
         let begin = self.pos.skip(char::is_whitespace);
         let (first_char, rest) = begin.next()?;
         let (kind, end) = comment_token(&begin)
+            .or_else(|| string_token(&begin))
             .or_else(|| nominal_token(&begin))
             .or_else(|| numeric_token(self.allow_sign, &begin))
             .or_else(|| symbolic_token(&begin))
@@ -36,26 +36,38 @@ macro_rules! tests {
 tests! [
     arithmetic_operations => "arithmetic_operations",
     arrays_and_records => "arrays_and_records",
+    comments => "comments",
     comparison_operators => "comparison_operators",
     complex_expressions => "complex_expressions",
     conditionals => "conditionals",
+    constant_sizes => "constant_sizes",
     deep_conditionals => "deep_conditionals",
+    enums => "enums",
     for_loops => "for_loops",
     function_parameters => "function_parameters",
     function_return => "function_return",
     identifiers => "identifiers",
+    imports => "imports",
     invalid => "invalid",
     lexer_invalid => "lexer_invalid",
     logical_operators => "logical_operators",
+    loop_exits => "loop_exits",
     nested_control => "nested_control",
+    nominal_aliases => "nominal_aliases",
     operator_precedence => "operator_precedence",
     parse_minus => "parse_minus",
+    read_input => "read_input",
     real_literals => "real_literals",
     records => "records",
     recursive_types => "recursive_types",
+    references => "references",
     shadow => "shadow",
+    short_circuit => "short_circuit",
+    strings => "strings",
     type_aliases => "type_aliases",
     type_conversions => "type_conversions",
+    unreachable_code => "unreachable_code",
+    unterminated_comment => "unterminated_comment",
     variable_declarations => "variable_declarations",
     while_loops => "while_loops",
 ];
@@ -0,0 +1,121 @@
+//! Compiler of `.i` programs to `bytecode::Module`s run by the `vm` crate.
+//!
+//! The phases can be driven one by one:
+//!
+//! ```text
+//! source --lex--> tokens --parse--> ast::Program --check--> types::TypedProgram --compile--> bytecode::Module --optimize--> bytecode::Module
+//! ```
+//!
+//! `lower_registers` is the alternative to `compile` for the register machine of the `vm` crate,
+//! `cgen::translate` turns the SSA form linked by `bytecode::linker::link_ir` into C,
+//! `x86_64::assemble` into assembly for x86-64 Linux and `wasm::compile` into WebAssembly.
+//!
+//! Modules of a program are compiled separately, see `modules::load` for finding them
+//! and `bytecode::linker::link` for merging the results.
+
+use std::collections::BTreeMap;
+
+pub mod ast;
+pub mod builtins;
+pub mod bytecode;
+pub mod cgen;
+pub mod codegen;
+pub mod consteval;
+pub mod docgen;
+pub mod flow;
+pub mod ir;
+pub mod lexer;
+pub mod modules;
+pub mod operators;
+pub mod optimizer;
+pub mod parser;
+pub mod registers;
+#[cfg(test)]
+mod test_support;
+pub mod tokens;
+pub mod types;
+pub mod wasm;
+pub mod x86_64;
+
+pub use crate::bytecode::Module;
+pub use crate::optimizer::OptLevel;
+pub use crate::parser::ParseError;
+pub use crate::types::{AliasSemantics, TypeErrors, TypeInferenceError, TypedProgram};
+
+/// Splits the source into tokens, including comments.
+/// Lexical errors are `tokens::TokenKind::Invalid` tokens, so this never fails.
+#[must_use]
+pub fn lex(source: &str) -> Vec<tokens::Token<'_>> {
+    lexer::Lexer::from(source).collect()
+}
+
+/// Builds the syntax tree of a single module, comments are skipped
+pub fn parse(tokens: &[tokens::Token<'_>]) -> Result<ast::Program, ParseError> {
+    parser::parse(tokens)
+}
+
+/// Settings of the whole pipeline, `Options::default()` is what the command line uses without flags
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub aliases: AliasSemantics,
+    pub opt_level: OptLevel,
+    /// Keeps every call, see `inline`
+    pub no_inline: bool,
+}
+
+/// Resolves names and infers types
+pub fn check(program: ast::Program, options: &Options) -> Result<TypedProgram, TypeErrors> {
+    types::check(program, options.aliases)
+}
+
+/// `check` of the module `name` of a program, it may import the `imported` modules,
+/// see `modules::load`
+pub fn check_module(
+    program: ast::Program,
+    name: &str,
+    imported: &[TypedProgram],
+    options: &Options,
+) -> Result<TypedProgram, TypeErrors> {
+    types::check_module(program, name, imported, options.aliases)
+}
+
+/// Generates the bytecode of a single module
+#[must_use]
+pub fn compile(program: &TypedProgram) -> Module {
+    codegen::compile(program)
+}
+
+/// Replaces the calls to small routines that are not recursive with their body,
+/// returns how many calls were replaced
+pub fn inline(routines: &mut BTreeMap<u64, ir::Function>, options: &Options) -> usize {
+    if options.no_inline {
+        return 0;
+    }
+    ir::inline::inline(routines, ir::inline::THRESHOLD)
+}
+
+/// Generates the register code of a single module from its SSA form, see `ir::build` and `inline`,
+/// optimized from `-O2`. It goes with the `Module` from `compile`, for the types, globals
+/// and strings.
+#[must_use]
+pub fn lower_registers(
+    routines: BTreeMap<u64, ir::Function>,
+    options: &Options,
+) -> registers::Code {
+    registers::Code(
+        routines
+            .into_iter()
+            .map(|(label, mut function)| {
+                if options.opt_level >= OptLevel::O2 {
+                    ir::passes::optimize(&mut function);
+                }
+                (label, registers::lower(&function))
+            })
+            .collect(),
+    )
+}
+
+/// Runs the peephole rules of `options.opt_level` over the module's code
+pub fn optimize(module: &mut Module, options: &Options) -> optimizer::Statistics {
+    optimizer::Optimizer::new(options.opt_level).run(&mut module.code)
+}
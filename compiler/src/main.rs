@@ -1,31 +1,365 @@
-#![expect(unreachable_pub, reason = "WIP")]
-
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
-use crate::lexer::Lexer;
-
-mod ast;
-mod bytecode;
-mod lexer;
-mod operators;
-mod tokens;
-mod types;
+use compiler::bytecode::linker::{link, link_ir, link_registers};
+use compiler::modules::{self, SearchPath};
+use compiler::{
+    OptLevel, Options, TypedProgram, cgen, check_module, compile, docgen, inline, ir, lex,
+    lower_registers, optimize, optimizer, parse, wasm, x86_64,
+};
+// Dependencies of the library
+#[cfg(test)]
+use corpus as _;
+use derive_where as _;
+#[cfg(test)]
+use expect_test as _;
+use phf as _;
+use unicode_ident as _;
 
 // TODO: create a Driver module
 
-#[expect(clippy::unwrap_used, reason = "WIP")]
+/// `compiler doc <file> [md|html]`
+fn doc(args: &[String]) -> ExitCode {
+    let Some(path) = args.first() else {
+        println!("No file provided");
+        return ExitCode::from(1);
+    };
+    let format = match args.get(1).map_or(Ok(docgen::Format::Html), |s| s.parse()) {
+        Ok(format) => format,
+        Err(e) => {
+            println!("{e}");
+            return ExitCode::from(1);
+        }
+    };
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Failed to read {path}: {e}");
+            return ExitCode::from(1);
+        }
+    };
+    let program = match parse(&lex(&source)) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("{path}:{e}");
+            return ExitCode::from(1);
+        }
+    };
+    let title = Path::new(path)
+        .file_stem()
+        .unwrap_or_else(|| OsStr::new(path))
+        .to_string_lossy();
+    print!("{}", docgen::render(&program, &title, format));
+    ExitCode::SUCCESS
+}
+
+/// `compiler [-I <dir>]... <file>`, dumps tokens of the file and of the modules it imports
+fn dump_tokens(args: &[String]) -> ExitCode {
+    let mut dirs = Vec::new();
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            let Some(dir) = args.next() else {
+                println!("No directory provided after -I");
+                return ExitCode::from(1);
+            };
+            dirs.push(PathBuf::from(dir));
+        } else {
+            file = Some(arg);
+        }
+    }
+    let Some(file) = file else {
+        println!("No file provided");
+        return ExitCode::from(1);
+    };
+
+    let modules = match modules::load(Path::new(file), &SearchPath::new(dirs)) {
+        Ok(modules) => modules,
+        Err(e) => {
+            println!("{e}");
+            return ExitCode::from(1);
+        }
+    };
+    for module in &modules {
+        if modules.len() > 1 {
+            println!("-- {} ({})", module.name, module.path.display());
+        }
+        for token in lex(&module.source) {
+            println!("{token}")
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Lists the routines of `program` in SSA form, optimized from `-O2`.
+/// Returns how many calls were inlined.
+fn print_ir(program: &TypedProgram, options: Options) -> usize {
+    let mut routines = ir::build(program);
+    let inlined = inline(&mut routines, &options);
+    for mut function in routines.into_values() {
+        if options.opt_level >= OptLevel::O2 {
+            ir::passes::optimize(&mut function);
+        }
+        print!("{function}");
+    }
+    inlined
+}
+
+/// What `compiler build` lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Bytecode,
+    Ir,
+    Registers,
+    C,
+    X86_64,
+    Wasm,
+    Wat,
+}
+
+/// `compiler build [-I <dir>]... [--aliases nominal|structural] [-O0|-O1|-O2] [--stats]
+/// [--emit=bytecode|ir|registers|c|x86-64|wasm|wat] [--no-inline] [-o <output>] <file>`, compiles the file with the modules it
+/// imports and lists the linked bytecode. `--stats` also lists how many times each optimization
+/// rule applied. `--emit=ir` lists the routines of each module in SSA form instead,
+/// where calls to small routines are inlined unless `--no-inline` is given, `--stats` counts them.
+/// `--emit=registers` lists the linked code of the register machine, inlined the same way.
+/// `--emit=c` prints the program translated to C, to be compiled with `cc -lm`.
+/// `--emit=x86-64` prints the program as assembly for Linux, or with `-o` builds the executable
+/// from it with `cc`. `--emit=wasm` writes the program as a WebAssembly module to `-o`,
+/// or to stdout, for `vm::wasm::run` to run, `--emit=wat` prints it in the text format.
+#[expect(clippy::too_many_lines, reason = "It is a single command line")]
+fn build(args: &[String]) -> ExitCode {
+    let mut dirs = Vec::new();
+    let mut options = Options::default();
+    let mut show_statistics = false;
+    let mut emit = Emit::Bytecode;
+    let mut file = None;
+    let mut executable = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" => {
+                let Some(dir) = args.next() else {
+                    println!("No directory provided after -I");
+                    return ExitCode::from(1);
+                };
+                dirs.push(PathBuf::from(dir));
+            }
+            "--aliases" => match args.next().map(|s| s.parse()) {
+                Some(Ok(aliases)) => options.aliases = aliases,
+                Some(Err(e)) => {
+                    println!("{e}");
+                    return ExitCode::from(1);
+                }
+                None => {
+                    println!("No alias semantics provided after --aliases");
+                    return ExitCode::from(1);
+                }
+            },
+            "--stats" => show_statistics = true,
+            "--emit=bytecode" => emit = Emit::Bytecode,
+            "--emit=ir" => emit = Emit::Ir,
+            "--emit=registers" => emit = Emit::Registers,
+            "--emit=c" => emit = Emit::C,
+            "--emit=x86-64" => emit = Emit::X86_64,
+            "--emit=wasm" => emit = Emit::Wasm,
+            "--emit=wat" => emit = Emit::Wat,
+            "-o" => {
+                let Some(path) = args.next() else {
+                    println!("No output provided after -o");
+                    return ExitCode::from(1);
+                };
+                executable = Some(PathBuf::from(path));
+            }
+            "--no-inline" => options.no_inline = true,
+            _ if arg.starts_with("--emit=") => {
+                println!("Unknown output \"{}\"", &arg["--emit=".len()..]);
+                return ExitCode::from(1);
+            }
+            _ => match arg.strip_prefix("-O").map(str::parse) {
+                Some(Ok(level)) => options.opt_level = level,
+                Some(Err(e)) => {
+                    println!("{e}");
+                    return ExitCode::from(1);
+                }
+                None => file = Some(arg),
+            },
+        }
+    }
+    let Some(file) = file else {
+        println!("No file provided");
+        return ExitCode::from(1);
+    };
+
+    let modules = match modules::load(Path::new(file), &SearchPath::new(dirs)) {
+        Ok(modules) => modules,
+        Err(e) => {
+            println!("{e}");
+            return ExitCode::from(1);
+        }
+    };
+    let mut checked = Vec::with_capacity(modules.len());
+    let mut compiled = Vec::with_capacity(modules.len());
+    let mut registers = Vec::new();
+    let mut routines = Vec::new();
+    let mut statistics = optimizer::Statistics::default();
+    let mut inlined = 0;
+    for module in modules {
+        let program = match check_module(module.program, &module.name, &checked, &options) {
+            Ok(program) => program,
+            Err(errors) => {
+                for error in &errors.0 {
+                    println!("{}:{error}", module.path.display());
+                }
+                return ExitCode::from(1);
+            }
+        };
+        for warning in &program.warnings {
+            eprintln!(
+                "{}:{}: warning: {}",
+                module.path.display(),
+                warning.position,
+                warning.reason
+            );
+        }
+        if emit == Emit::Ir {
+            inlined += print_ir(&program, options);
+            checked.push(program);
+            continue;
+        }
+        if emit == Emit::Registers {
+            let mut routines = ir::build(&program);
+            inlined += inline(&mut routines, &options);
+            registers.push(lower_registers(routines, &options));
+        }
+        if matches!(emit, Emit::C | Emit::X86_64 | Emit::Wasm | Emit::Wat) {
+            let mut functions = ir::build(&program);
+            inlined += inline(&mut functions, &options);
+            if options.opt_level >= OptLevel::O2 {
+                functions.values_mut().for_each(ir::passes::optimize);
+            }
+            routines.push(functions);
+        }
+        let mut module = compile(&program);
+        statistics.merge(&optimize(&mut module, &options));
+        compiled.push(module);
+        checked.push(program);
+    }
+    if emit == Emit::Ir {
+        if show_statistics {
+            println!("inline: {inlined}");
+        }
+        return ExitCode::SUCCESS;
+    }
+    if emit == Emit::Registers {
+        return match link_registers(&compiled, registers) {
+            Ok(code) => {
+                print!("{code}");
+                if show_statistics {
+                    println!("inline: {inlined}");
+                }
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                println!("{e}");
+                ExitCode::from(1)
+            }
+        };
+    }
+    if matches!(emit, Emit::C | Emit::X86_64 | Emit::Wasm | Emit::Wat) {
+        let routines = match link_ir(&compiled, routines) {
+            Ok(routines) => routines,
+            Err(e) => {
+                println!("{e}");
+                return ExitCode::from(1);
+            }
+        };
+        let program = match link(compiled) {
+            Ok(program) => program,
+            Err(e) => {
+                println!("{e}");
+                return ExitCode::from(1);
+            }
+        };
+        if emit == Emit::Wasm {
+            let written = wasm::compile(&program, &routines)
+                .map_err(|e| e.to_string())
+                .and_then(|module| {
+                    let binary = module.binary();
+                    match &executable {
+                        Some(path) => fs::write(path, binary),
+                        None => io::stdout().write_all(&binary),
+                    }
+                    .map_err(|e| e.to_string())
+                });
+            return match written {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    println!("{e}");
+                    ExitCode::from(1)
+                }
+            };
+        }
+        let translation = match emit {
+            Emit::C => cgen::translate(&program, &routines),
+            Emit::X86_64 => x86_64::assemble(&program, &routines),
+            Emit::Wat => wasm::compile(&program, &routines).map(|module| module.to_string()),
+            Emit::Bytecode | Emit::Ir | Emit::Registers | Emit::Wasm => {
+                unreachable!("Not translated to text")
+            }
+        }
+        .map_err(|e| e.to_string());
+        let built = match (translation, executable) {
+            (Ok(assembly), Some(executable)) if emit == Emit::X86_64 => {
+                x86_64::build_executable(&assembly, &executable).map_err(|e| e.to_string())
+            }
+            (Ok(source), _) => {
+                print!("{source}");
+                Ok(())
+            }
+            (Err(e), _) => Err(e),
+        };
+        return match built {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                println!("{e}");
+                ExitCode::from(1)
+            }
+        };
+    }
+    match link(compiled) {
+        Ok(program) => {
+            for instruction in &program.code {
+                println!("{instruction:?}");
+            }
+            if show_statistics {
+                print!("{statistics}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{e}");
+            ExitCode::from(1)
+        }
+    }
+}
+
 fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         println!("No file provided");
         return ExitCode::from(1);
     }
-
-    let source: String = fs::read_to_string(&args[1]).unwrap();
-    for token in Lexer::from(source.as_str()) {
-        println!("{token}")
+    match args[1].as_str() {
+        "doc" => return doc(&args[2..]),
+        "build" => return build(&args[2..]),
+        _ => {}
     }
-    ExitCode::SUCCESS
+    dump_tokens(&args[1..])
 }
@@ -0,0 +1,178 @@
+//! Locating imported modules and ordering them for separate compilation
+
+use core::error::Error;
+use core::fmt;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{Declaration, ImportDeclaration, Program};
+use crate::parser::ParseError;
+
+#[cfg(test)]
+mod tests;
+
+pub const EXTENSION: &str = "i";
+
+/// Directories searched for `<module>.i` files, in order
+#[derive(Debug, Clone, Default)]
+pub struct SearchPath {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    #[must_use]
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Self { dirs }
+    }
+
+    /// The directory of the importing module is searched first
+    pub fn find(&self, module: &str, importer: &Path) -> Option<PathBuf> {
+        let file = format!("{module}.{EXTENSION}");
+        importer
+            .parent()
+            .into_iter()
+            .chain(self.dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceModule {
+    pub name: String,
+    pub path: PathBuf,
+    pub source: String,
+    pub program: Program,
+}
+
+#[derive(Debug)]
+pub enum ModuleError {
+    NotFound { module: String, importer: PathBuf },
+    Cycle { modules: Vec<String> },
+    Io { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, error: ParseError },
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::NotFound { module, importer } => write!(
+                f,
+                "Module `{module}` imported from {} is not found",
+                importer.display()
+            ),
+            ModuleError::Cycle { modules } => {
+                write!(f, "Import cycle: {}", modules.join(" -> "))
+            }
+            ModuleError::Io { path, error } => {
+                write!(f, "Failed to read {}: {error}", path.display())
+            }
+            ModuleError::Parse { path, error } => write!(f, "{}:{error}", path.display()),
+        }
+    }
+}
+
+impl Error for ModuleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ModuleError::Io { error, .. } => Some(error),
+            ModuleError::Parse { error, .. } => Some(error),
+            ModuleError::NotFound { .. } | ModuleError::Cycle { .. } => None,
+        }
+    }
+}
+
+/// Modules named in `import module;` declarations
+#[must_use]
+pub fn imports(program: &Program) -> Vec<&str> {
+    program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Import(ImportDeclaration { module, .. }) => Some(module.name.as_str()),
+            Declaration::Simple(_) | Declaration::Routine(_) => None,
+        })
+        .collect()
+}
+
+fn module_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    InProgress,
+    Done,
+}
+
+struct Loader<'a> {
+    search_path: &'a SearchPath,
+    states: HashMap<String, State>,
+    /// Modules being loaded, each one imports the next
+    stack: Vec<String>,
+    loaded: Vec<SourceModule>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self, name: String, path: PathBuf) -> Result<(), ModuleError> {
+        match self.states.get(&name) {
+            Some(State::Done) => return Ok(()),
+            Some(State::InProgress) => {
+                let start = self.stack.iter().position(|module| *module == name);
+                let mut modules = self.stack[start.unwrap_or_default()..].to_vec();
+                modules.push(name);
+                return Err(ModuleError::Cycle { modules });
+            }
+            None => {}
+        }
+
+        let source = fs::read_to_string(&path).map_err(|error| ModuleError::Io {
+            path: path.clone(),
+            error,
+        })?;
+        let program = crate::parse(&crate::lex(&source)).map_err(|error| ModuleError::Parse {
+            path: path.clone(),
+            error,
+        })?;
+        let _: Option<State> = self.states.insert(name.clone(), State::InProgress);
+        self.stack.push(name.clone());
+
+        for module in imports(&program) {
+            let dependency =
+                self.search_path
+                    .find(module, &path)
+                    .ok_or_else(|| ModuleError::NotFound {
+                        module: module.to_owned(),
+                        importer: path.clone(),
+                    })?;
+            self.load(module.to_owned(), dependency)?;
+        }
+
+        drop(self.stack.pop());
+        let _: Option<State> = self.states.insert(name.clone(), State::Done);
+        self.loaded.push(SourceModule {
+            name,
+            path,
+            source,
+            program,
+        });
+        Ok(())
+    }
+}
+
+/// Reads and parses `root` and all the modules it imports, transitively.
+/// Every module comes after the ones it imports, so `root` is the last one.
+pub fn load(root: &Path, search_path: &SearchPath) -> Result<Vec<SourceModule>, ModuleError> {
+    let mut loader = Loader {
+        search_path,
+        states: HashMap::new(),
+        stack: Vec::new(),
+        loaded: Vec::new(),
+    };
+    loader.load(module_name(root), root.to_owned())?;
+    Ok(loader.loaded)
+}
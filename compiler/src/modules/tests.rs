@@ -0,0 +1,51 @@
+use super::*;
+
+fn modules_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/modules")
+}
+
+fn load_names(root: &str) -> Result<Vec<String>, ModuleError> {
+    let modules = load(&modules_dir().join(root), &SearchPath::default())?;
+    Ok(modules.into_iter().map(|module| module.name).collect())
+}
+
+#[test]
+fn finds_imports() {
+    let src = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/src/imports.i"
+    ));
+    let program = crate::parse(&crate::lex(src)).expect("Parses");
+    assert_eq!(imports(&program), ["geometry"]);
+}
+
+#[test]
+fn dependencies_go_first() {
+    assert_eq!(
+        load_names("main.i").expect("All modules are there"),
+        ["numbers", "geometry", "main"]
+    );
+}
+
+#[test]
+fn search_path() {
+    let search_path = SearchPath::new(vec![modules_dir()]);
+    let importer = Path::new("somewhere/else.i");
+    assert_eq!(
+        search_path.find("numbers", importer),
+        Some(modules_dir().join("numbers.i"))
+    );
+    assert_eq!(search_path.find("missing", importer), None);
+}
+
+#[test]
+fn import_cycle() {
+    let result = load_names("cycle_a.i");
+    assert!(
+        matches!(
+            &result,
+            Err(ModuleError::Cycle { modules }) if *modules == ["cycle_a", "cycle_b", "cycle_a"]
+        ),
+        "Expected an import cycle, got {result:?}"
+    );
+}
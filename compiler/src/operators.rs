@@ -1,6 +1,4 @@
-#![expect(dead_code, reason = "WIP")]
-
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum SyntacticOperator {
     Add, // Either binary or unary one
     Sub, // Either binary or unary one
@@ -19,7 +17,7 @@ pub enum SyntacticOperator {
     Neg,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum SemanticBinaryOperator {
     RealAdd,
     RealSub,
@@ -42,13 +40,23 @@ pub enum SemanticBinaryOperator {
     IntGe,
     IntEq,
     IntNeq,
+    /// Evaluates both sides, `and` of the source is lowered by `codegen::short_circuit`
     BoolAnd,
     BoolXor,
+    /// Evaluates both sides, `or` of the source is lowered by `codegen::short_circuit`
     BoolOr,
+    /// `+` on strings
+    StringConcat,
+    /// Strings are compared by contents, lexicographically
+    StringLe,
+    StringLg,
+    StringGt,
+    StringGe,
+    StringEq,
+    StringNeq,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-#[expect(clippy::enum_variant_names, reason = "TODO")]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum SemanticUnaryOperator {
     IntNeg,
     RealNeg,
@@ -0,0 +1,452 @@
+//! Peephole optimization of `Bytecode`: rules rewrite short runs of adjacent instructions
+//! until none of them matches anymore.
+//!
+//! Jumps only target `Bytecode::Label`s, so a run without labels after its first instruction
+//! is always entered from the top and can be replaced by anything with the same effect.
+
+use core::fmt;
+use core::str::FromStr;
+use std::collections::BTreeMap;
+
+use crate::ast::BinaryOperator;
+use crate::bytecode::Bytecode;
+use crate::consteval::{self, Constant};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Code is kept as generated
+    #[default]
+    O0,
+    /// Removes redundant instructions
+    O1,
+    /// Also evaluates constant operations and branches, removes unreachable code
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::O0),
+            "1" => Ok(Self::O1),
+            "2" => Ok(Self::O2),
+            _ => Err(format!("Unknown optimization level {s:?}")),
+        }
+    }
+}
+
+/// `matched` first instructions of the code are replaced with `replacement`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub matched: usize,
+    pub replacement: Vec<Bytecode>,
+}
+
+impl Rewrite {
+    #[must_use]
+    pub fn new(matched: usize, replacement: impl Into<Vec<Bytecode>>) -> Self {
+        Self {
+            matched,
+            replacement: replacement.into(),
+        }
+    }
+}
+
+/// A rewrite must not match its own replacement, otherwise `Optimizer::run` never stops
+pub trait Rule: fmt::Debug {
+    /// Key of the rule in `Statistics`
+    fn name(&self) -> &'static str;
+
+    /// Rewrite of the instructions at the start of `code`, if the rule applies there
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite>;
+}
+
+/// How many times each rule has been applied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics(BTreeMap<&'static str, usize>);
+
+impl Statistics {
+    #[must_use]
+    pub fn count(&self, rule: &str) -> usize {
+        self.0.get(rule).copied().unwrap_or(0)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (&rule, &count) in &other.0 {
+            *self.0.entry(rule).or_default() += count;
+        }
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (rule, count) in &self.0 {
+            writeln!(f, "{rule}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Optimizer {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Optimizer {
+    /// The built-in rules of `level`
+    #[must_use]
+    pub fn new(level: OptLevel) -> Self {
+        let mut optimizer = Self::default();
+        if level >= OptLevel::O1 {
+            optimizer = optimizer
+                .with_rule(StoreLoad)
+                .with_rule(NeutralOperand)
+                .with_rule(JumpToNext)
+                .with_rule(PushDrop);
+        }
+        if level >= OptLevel::O2 {
+            optimizer = optimizer
+                .with_rule(FoldConstants)
+                .with_rule(ConstantBranch)
+                .with_rule(Unreachable);
+        }
+        optimizer
+    }
+
+    /// Rules are tried in the order they are added
+    #[must_use]
+    pub fn with_rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Rewrites `code` in passes until a pass changes nothing
+    pub fn run(&self, code: &mut Vec<Bytecode>) -> Statistics {
+        let mut statistics = Statistics::default();
+        loop {
+            let mut changed = false;
+            let mut optimized = Vec::with_capacity(code.len());
+            let mut rest = code.as_slice();
+            while let Some((&first, tail)) = rest.split_first() {
+                let rewrite = self
+                    .rules
+                    .iter()
+                    .find_map(|rule| Some((rule.name(), rule.rewrite(rest)?)));
+                if let Some((
+                    name,
+                    Rewrite {
+                        matched,
+                        replacement,
+                    },
+                )) = rewrite
+                {
+                    *statistics.0.entry(name).or_default() += 1;
+                    optimized.extend(replacement);
+                    rest = &rest[matched..];
+                    changed = true;
+                } else {
+                    optimized.push(first);
+                    rest = tail;
+                }
+            }
+            *code = optimized;
+            if !changed {
+                return statistics;
+            }
+        }
+    }
+}
+
+/// `Store x; Load x` to `Dup; Store x`
+#[derive(Debug, Clone, Copy)]
+pub struct StoreLoad;
+
+impl Rule for StoreLoad {
+    fn name(&self) -> &'static str {
+        "store-load"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        match *code {
+            [Bytecode::Store { loc }, Bytecode::Load { loc: loaded }, ..] if loc == loaded => {
+                Some(Rewrite::new(2, [Bytecode::Dup, Bytecode::Store { loc }]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Adding or subtracting integer 0, multiplying or dividing by integer 1
+#[derive(Debug, Clone, Copy)]
+pub struct NeutralOperand;
+
+impl Rule for NeutralOperand {
+    fn name(&self) -> &'static str {
+        "neutral-operand"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        use SemanticBinaryOperator as Op;
+
+        match code {
+            [
+                Bytecode::IntConst { value: 0 },
+                Bytecode::BinOp {
+                    op: Op::IntAdd | Op::IntSub,
+                },
+                ..,
+            ]
+            | [
+                Bytecode::IntConst { value: 1 },
+                Bytecode::BinOp {
+                    op: Op::IntMul | Op::IntDiv,
+                },
+                ..,
+            ] => Some(Rewrite::new(2, [])),
+            _ => None,
+        }
+    }
+}
+
+/// A jump to the label right after it
+#[derive(Debug, Clone, Copy)]
+pub struct JumpToNext;
+
+impl Rule for JumpToNext {
+    fn name(&self) -> &'static str {
+        "jump-to-next"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        let (label, condition) = match *code {
+            [Bytecode::Jump { label }, ..] => (label, None),
+            [
+                Bytecode::JumpZero { label } | Bytecode::JumpNotZero { label },
+                ..,
+            ] => (label, Some(Bytecode::Drop)),
+            _ => return None,
+        };
+        match code.get(1) {
+            // The label may be targeted by other jumps
+            Some(&next @ Bytecode::Label { id }) if id == label => Some(Rewrite::new(
+                2,
+                condition.into_iter().chain([next]).collect::<Vec<_>>(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// `Drop` of a value pushed without side effects, `Dup; Drop` included
+#[derive(Debug, Clone, Copy)]
+pub struct PushDrop;
+
+impl Rule for PushDrop {
+    fn name(&self) -> &'static str {
+        "push-drop"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        match code {
+            [
+                Bytecode::Dup
+                | Bytecode::IntConst { .. }
+                | Bytecode::RealConst { .. }
+                | Bytecode::StringConst { .. }
+                | Bytecode::Load { .. }
+                | Bytecode::AddressOf { .. },
+                Bytecode::Drop,
+                ..,
+            ] => Some(Rewrite::new(2, [])),
+            _ => None,
+        }
+    }
+}
+
+fn constant(instruction: Bytecode) -> Option<Constant> {
+    if let Bytecode::IntConst { value } = instruction {
+        Some(Constant::Int(value))
+    } else if let Bytecode::RealConst { value } = instruction {
+        Some(Constant::Real(value))
+    } else {
+        None
+    }
+}
+
+fn push(value: Constant) -> Bytecode {
+    match value {
+        Constant::Int(value) => Bytecode::IntConst { value },
+        Constant::Real(value) => Bytecode::RealConst { value },
+        Constant::Bool(value) => Bytecode::IntConst {
+            value: value.into(),
+        },
+    }
+}
+
+/// `None` for string operators and for operands of the wrong kind, which fail at runtime
+fn fold(op: SemanticBinaryOperator, lhs: Constant, rhs: Constant) -> Option<Constant> {
+    use SemanticBinaryOperator as Op;
+
+    let (op, real) = match op {
+        Op::RealAdd => (BinaryOperator::Add, true),
+        Op::RealSub => (BinaryOperator::Sub, true),
+        Op::RealMul => (BinaryOperator::Mul, true),
+        Op::RealDiv => (BinaryOperator::Div, true),
+        Op::RealLe => (BinaryOperator::Le, true),
+        Op::RealLg => (BinaryOperator::Lg, true),
+        Op::RealGt => (BinaryOperator::Gt, true),
+        Op::RealGe => (BinaryOperator::Ge, true),
+        Op::RealEq => (BinaryOperator::Eq, true),
+        Op::RealNeq => (BinaryOperator::Neq, true),
+        Op::IntAdd => (BinaryOperator::Add, false),
+        Op::IntSub => (BinaryOperator::Sub, false),
+        Op::IntMul => (BinaryOperator::Mul, false),
+        Op::IntDiv => (BinaryOperator::Div, false),
+        Op::IntMod => (BinaryOperator::Mod, false),
+        Op::IntLe => (BinaryOperator::Le, false),
+        Op::IntLg => (BinaryOperator::Lg, false),
+        Op::IntGt => (BinaryOperator::Gt, false),
+        Op::IntGe => (BinaryOperator::Ge, false),
+        Op::IntEq => (BinaryOperator::Eq, false),
+        Op::IntNeq => (BinaryOperator::Neq, false),
+        Op::BoolAnd => (BinaryOperator::And, false),
+        Op::BoolXor => (BinaryOperator::Xor, false),
+        Op::BoolOr => (BinaryOperator::Or, false),
+        Op::StringConcat
+        | Op::StringLe
+        | Op::StringLg
+        | Op::StringGt
+        | Op::StringGe
+        | Op::StringEq
+        | Op::StringNeq => return None,
+    };
+    let operand = |value| {
+        matches!(
+            (value, real),
+            (Constant::Real(_), true) | (Constant::Int(_), false)
+        )
+    };
+    if operand(lhs) && operand(rhs) {
+        consteval::binary(op, lhs, rhs).ok()
+    } else {
+        None
+    }
+}
+
+fn fold_unary(op: SemanticUnaryOperator, value: Constant) -> Option<Constant> {
+    match (op, value) {
+        (SemanticUnaryOperator::IntNeg, Constant::Int(value)) => {
+            value.checked_neg().map(Constant::Int)
+        }
+        (SemanticUnaryOperator::RealNeg, Constant::Real(value)) => Some(Constant::Real(-value)),
+        (SemanticUnaryOperator::BoolNeg, Constant::Int(value @ (0 | 1))) => {
+            Some(Constant::Bool(value == 0))
+        }
+        _ => None,
+    }
+}
+
+/// Operators and `IntToReal` applied to constants.
+/// Operations that would fail, like division by zero, are left to fail at runtime.
+#[derive(Debug, Clone, Copy)]
+pub struct FoldConstants;
+
+impl Rule for FoldConstants {
+    fn name(&self) -> &'static str {
+        "fold-constants"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        let binary = || match *code {
+            [lhs, rhs, Bytecode::BinOp { op }, ..] => Some(Rewrite::new(
+                3,
+                [push(fold(op, constant(lhs)?, constant(rhs)?)?)],
+            )),
+            _ => None,
+        };
+        let unary = || {
+            let folded = match *code {
+                [value, Bytecode::UnOp { op }, ..] => fold_unary(op, constant(value)?)?,
+                #[expect(clippy::cast_precision_loss, reason = "Same as the VM's IntToReal")]
+                [Bytecode::IntConst { value }, Bytecode::IntToReal, ..] => {
+                    Constant::Real(value as f64)
+                }
+                _ => return None,
+            };
+            Some(Rewrite::new(2, [push(folded)]))
+        };
+        binary().or_else(unary)
+    }
+}
+
+/// Conditional jump on a constant, taken or not
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantBranch;
+
+impl Rule for ConstantBranch {
+    fn name(&self) -> &'static str {
+        "constant-branch"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        let (taken, label) = match *code {
+            [
+                Bytecode::IntConst { value },
+                Bytecode::JumpZero { label },
+                ..,
+            ] => (value == 0, label),
+            [
+                Bytecode::IntConst { value },
+                Bytecode::JumpNotZero { label },
+                ..,
+            ] => (value != 0, label),
+            _ => return None,
+        };
+        if taken {
+            Some(Rewrite::new(2, [Bytecode::Jump { label }]))
+        } else {
+            Some(Rewrite::new(2, []))
+        }
+    }
+}
+
+/// Instructions after `Jump`, `Ret`, `TailCall` or `Panic` up to the next label
+#[derive(Debug, Clone, Copy)]
+pub struct Unreachable;
+
+impl Rule for Unreachable {
+    fn name(&self) -> &'static str {
+        "unreachable"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        let (
+            &transfer @ (Bytecode::Jump { .. }
+            | Bytecode::Ret
+            | Bytecode::TailCall { .. }
+            | Bytecode::Panic { .. }),
+            rest,
+        ) = code.split_first()?
+        else {
+            return None;
+        };
+        let dead = rest
+            .iter()
+            .take_while(|instruction| !matches!(instruction, Bytecode::Label { .. }))
+            .count();
+        (dead > 0).then(|| Rewrite {
+            matched: 1 + dead,
+            replacement: vec![transfer],
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use super::*;
+use crate::bytecode::{Location, TypeId};
+
+fn int(value: i64) -> Bytecode {
+    Bytecode::IntConst { value }
+}
+
+fn binop(op: SemanticBinaryOperator) -> Bytecode {
+    Bytecode::BinOp { op }
+}
+
+fn optimized(level: OptLevel, mut code: Vec<Bytecode>) -> (Vec<Bytecode>, Statistics) {
+    let statistics = Optimizer::new(level).run(&mut code);
+    (code, statistics)
+}
+
+#[test]
+fn redundant_instructions() {
+    let local = Location::Local(0);
+    let code = vec![
+        Bytecode::Store { loc: local },
+        Bytecode::Load { loc: local },
+        int(0),
+        binop(SemanticBinaryOperator::IntAdd),
+        Bytecode::Dup,
+        Bytecode::Drop,
+        Bytecode::Jump { label: 1 },
+        Bytecode::Label { id: 1 },
+        Bytecode::Ret,
+    ];
+    let (code, statistics) = optimized(OptLevel::O1, code);
+    assert_eq!(
+        code,
+        [
+            Bytecode::Dup,
+            Bytecode::Store { loc: local },
+            Bytecode::Label { id: 1 },
+            Bytecode::Ret,
+        ]
+    );
+    assert_eq!(
+        statistics.to_string(),
+        "jump-to-next: 1\nneutral-operand: 1\npush-drop: 1\nstore-load: 1\n"
+    );
+}
+
+#[test]
+fn o0_keeps_code() {
+    let code = vec![Bytecode::Dup, Bytecode::Drop];
+    let (kept, statistics) = optimized(OptLevel::O0, code.clone());
+    assert_eq!(kept, code);
+    assert!(statistics.is_empty());
+}
+
+#[test]
+fn constants_cascade() {
+    // if 2 * 3 + 1 > 6 then print 1.5 * 2; end
+    let code = vec![
+        int(2),
+        int(3),
+        binop(SemanticBinaryOperator::IntMul),
+        int(1),
+        binop(SemanticBinaryOperator::IntAdd),
+        int(6),
+        binop(SemanticBinaryOperator::IntGt),
+        Bytecode::JumpZero { label: 1 },
+        Bytecode::RealConst { value: 1.5 },
+        int(2),
+        Bytecode::IntToReal,
+        binop(SemanticBinaryOperator::RealMul),
+        Bytecode::Print {
+            type_id: TypeId::REAL,
+        },
+        Bytecode::Label { id: 1 },
+    ];
+    let (code, statistics) = optimized(OptLevel::O2, code);
+    assert_eq!(
+        code,
+        [
+            Bytecode::RealConst { value: 3.0 },
+            Bytecode::Print {
+                type_id: TypeId::REAL,
+            },
+            Bytecode::Label { id: 1 },
+        ]
+    );
+    assert_eq!(statistics.count("fold-constants"), 5);
+    assert_eq!(statistics.count("constant-branch"), 1);
+}
+
+#[test]
+fn failing_operations_stay() {
+    let code = vec![
+        int(1),
+        int(0),
+        binop(SemanticBinaryOperator::IntDiv),
+        int(i64::MIN),
+        Bytecode::UnOp {
+            op: SemanticUnaryOperator::IntNeg,
+        },
+        int(1),
+        Bytecode::RealConst { value: 1.0 },
+        binop(SemanticBinaryOperator::IntAdd),
+    ];
+    let (kept, statistics) = optimized(OptLevel::O2, code.clone());
+    assert_eq!(kept, code);
+    assert!(statistics.is_empty());
+}
+
+#[test]
+fn unreachable_code() {
+    let code = vec![
+        Bytecode::Jump { label: 2 },
+        Bytecode::Label { id: 1 },
+        int(1),
+        Bytecode::Ret,
+        int(2),
+        Bytecode::Print {
+            type_id: TypeId::INTEGER,
+        },
+        Bytecode::Label { id: 2 },
+        Bytecode::Panic { code: 1 },
+        Bytecode::Ret,
+    ];
+    let (code, statistics) = optimized(OptLevel::O2, code);
+    assert_eq!(
+        code,
+        [
+            Bytecode::Jump { label: 2 },
+            Bytecode::Label { id: 1 },
+            int(1),
+            Bytecode::Ret,
+            Bytecode::Label { id: 2 },
+            Bytecode::Panic { code: 1 },
+        ]
+    );
+    assert_eq!(statistics.count("unreachable"), 2);
+}
+
+/// `Swap; Swap` is not a built-in rule
+#[derive(Debug)]
+struct DoubleSwap;
+
+impl Rule for DoubleSwap {
+    fn name(&self) -> &'static str {
+        "double-swap"
+    }
+
+    fn rewrite(&self, code: &[Bytecode]) -> Option<Rewrite> {
+        matches!(code, [Bytecode::Swap, Bytecode::Swap, ..]).then(|| Rewrite::new(2, []))
+    }
+}
+
+#[test]
+fn custom_rules() {
+    let mut code = vec![
+        Bytecode::Swap,
+        Bytecode::Swap,
+        Bytecode::Dup,
+        Bytecode::Drop,
+    ];
+    let statistics = Optimizer::default().with_rule(DoubleSwap).run(&mut code);
+    assert_eq!(code, [Bytecode::Dup, Bytecode::Drop]);
+    assert_eq!(statistics.count("double-swap"), 1);
+    assert_eq!(statistics.count("push-drop"), 0);
+
+    let mut total = Statistics::default();
+    total.merge(&statistics);
+    total.merge(&statistics);
+    assert_eq!(total.count("double-swap"), 2);
+}
+
+#[test]
+fn levels_from_flags() {
+    assert_eq!("2".parse(), Ok(OptLevel::O2));
+    assert_eq!(
+        "3".parse::<OptLevel>(),
+        Err("Unknown optimization level \"3\"".to_owned())
+    );
+}
@@ -0,0 +1,775 @@
+//! Tokens to `ast`
+
+use core::error::Error;
+use core::fmt;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::{
+    BinaryOperator, Block, BlockElement, BoolLiteral, CaseBranch, CaseLabel, Declaration,
+    Documentation, Expression, Identifier, ImportDeclaration, IntegerLiteral, LoopOrder,
+    LvalueExpression, Parameter, Program, RealLiteral, RoutineBody, RoutineDeclaration,
+    SimpleDeclaration, Statement, StringLiteral, TypeDeclaration, UnaryOperator,
+    VariableDeclaration,
+};
+use crate::operators::SyntacticOperator;
+use crate::tokens::{self, BuiltinTypename, Keyword, Position, Token, TokenKind};
+use crate::types::{ArrayDescription, EnumDescription, FieldDescription, RecordDeclaration, Type};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: Position,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.reason)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Comments are skipped, doc comments go to the declaration after them,
+/// the first invalid token is the error
+pub fn parse(tokens: &[Token<'_>]) -> Result<Program, ParseError> {
+    let mut significant = Vec::with_capacity(tokens.len());
+    let mut docs = HashMap::new();
+    let mut doc = Documentation::default();
+    for token in tokens {
+        match &token.kind {
+            TokenKind::Comment(_) => {}
+            TokenKind::DocComment(comment) => doc.push_line(comment.value),
+            TokenKind::Invalid(invalid) => {
+                return Err(ParseError {
+                    position: token.extent.start,
+                    reason: invalid.problem.clone(),
+                });
+            }
+            TokenKind::Identifier(_)
+            | TokenKind::Keyword(_)
+            | TokenKind::IntegerLiteral(_)
+            | TokenKind::RealLiteral(_)
+            | TokenKind::BoolLiteral(_)
+            | TokenKind::StringLiteral(_)
+            | TokenKind::BuiltinTypename(_)
+            | TokenKind::Operator(_)
+            | TokenKind::LeftBracket
+            | TokenKind::RightBracket
+            | TokenKind::LeftParenthesis
+            | TokenKind::RightParenthesis
+            | TokenKind::RightArrow
+            | TokenKind::Assignment
+            | TokenKind::RangeSymbol
+            | TokenKind::Dot
+            | TokenKind::Comma
+            | TokenKind::Semicolon
+            | TokenKind::Colon => {
+                if !doc.is_empty() {
+                    drop(docs.insert(significant.len(), core::mem::take(&mut doc)));
+                }
+                significant.push(token);
+            }
+        }
+    }
+    let mut parser = Parser {
+        end: tokens
+            .last()
+            .map_or_else(Position::begin, |token| token.extent.end),
+        tokens: significant,
+        next: 0,
+        docs,
+    };
+    parser.program()
+}
+
+fn name(name: &str) -> Identifier {
+    Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }
+}
+
+/// Text of a token for error messages
+fn describe(kind: Option<&TokenKind<'_>>) -> String {
+    match kind {
+        Some(kind) => kind.to_string(),
+        None => "the end of the file".to_owned(),
+    }
+}
+
+struct Parser<'t, 'src> {
+    tokens: Vec<&'t Token<'src>>,
+    /// Index of the first token not consumed yet
+    next: usize,
+    /// Where the last token ends
+    end: Position,
+    /// Doc comments by the index of the token they precede
+    docs: HashMap<usize, Documentation>,
+}
+
+impl<'src> Parser<'_, 'src> {
+    fn peek(&self) -> Option<&TokenKind<'src>> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&TokenKind<'src>> {
+        self.tokens.get(self.next + offset).map(|token| &token.kind)
+    }
+
+    /// Doc comments before the next token, declarations take them before their first token
+    fn doc(&mut self) -> Documentation {
+        self.docs.remove(&self.next).unwrap_or_default()
+    }
+
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |token| token.extent.start)
+    }
+
+    fn error<T>(&self, reason: String) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position(),
+            reason,
+        })
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        self.error(format!(
+            "Expected {expected}, found {}",
+            describe(self.peek())
+        ))
+    }
+
+    fn eat(&mut self, kind: &TokenKind<'_>) -> bool {
+        if self.peek() == Some(kind) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind<'_>) -> Result<(), ParseError> {
+        if self.eat(kind) {
+            Ok(())
+        } else {
+            self.unexpected(&kind.to_string())
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> bool {
+        self.eat(&TokenKind::Keyword(keyword))
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), ParseError> {
+        self.expect(&TokenKind::Keyword(keyword))
+    }
+
+    fn identifier(&mut self) -> Result<Identifier, ParseError> {
+        match self.peek() {
+            Some(&TokenKind::Identifier(tokens::Identifier { name: value })) => {
+                self.next += 1;
+                Ok(name(value))
+            }
+            _ => self.unexpected("an identifier"),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let mut declarations = Vec::new();
+        while self.peek().is_some() {
+            declarations.push(self.declaration()?);
+        }
+        Ok(Program { declarations })
+    }
+
+    fn declaration(&mut self) -> Result<Declaration, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Import)) => {
+                let position = self.position();
+                self.next += 1;
+                let module = self.identifier()?;
+                self.expect(&TokenKind::Semicolon)?;
+                Ok(Declaration::Import(ImportDeclaration { position, module }))
+            }
+            Some(TokenKind::Keyword(Keyword::Routine)) => self.routine().map(Declaration::Routine),
+            Some(TokenKind::Keyword(Keyword::Var | Keyword::Type)) => {
+                self.simple_declaration().map(Declaration::Simple)
+            }
+            _ => self.unexpected("a declaration"),
+        }
+    }
+
+    /// `var` or `type` declaration with its `;`
+    fn simple_declaration(&mut self) -> Result<SimpleDeclaration, ParseError> {
+        let doc = self.doc();
+        let position = self.position();
+        let declaration = if self.eat_keyword(Keyword::Type) {
+            let name = self.identifier()?;
+            self.expect_keyword(Keyword::Is)?;
+            SimpleDeclaration::Type(TypeDeclaration {
+                doc,
+                position,
+                name,
+                t: self.type_()?,
+            })
+        } else {
+            SimpleDeclaration::Variable(VariableDeclaration {
+                doc,
+                ..self.variable()?
+            })
+        };
+        self.expect(&TokenKind::Semicolon)?;
+        Ok(declaration)
+    }
+
+    /// `var name : t is initializer`, either the type or the initializer may be missing
+    fn variable(&mut self) -> Result<VariableDeclaration, ParseError> {
+        let position = self.position();
+        self.expect_keyword(Keyword::Var)?;
+        let name = self.identifier()?;
+        let t = if self.eat(&TokenKind::Colon) {
+            Some(self.type_()?)
+        } else {
+            None
+        };
+        let initializer = if self.eat_keyword(Keyword::Is) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        if t.is_none() && initializer.is_none() {
+            return self.unexpected("`:` with a type or `is` with an initializer");
+        }
+        Ok(VariableDeclaration {
+            doc: Documentation::default(),
+            position,
+            name,
+            t,
+            initializer,
+        })
+    }
+
+    fn routine(&mut self) -> Result<RoutineDeclaration, ParseError> {
+        let doc = self.doc();
+        let position = self.position();
+        self.expect_keyword(Keyword::Routine)?;
+        let name = self.identifier()?;
+        self.expect(&TokenKind::LeftParenthesis)?;
+        let mut parameters = Vec::new();
+        if !self.eat(&TokenKind::RightParenthesis) {
+            loop {
+                let name = self.identifier()?;
+                self.expect(&TokenKind::Colon)?;
+                parameters.push(Parameter {
+                    name,
+                    t: self.type_()?,
+                });
+                if self.eat(&TokenKind::RightParenthesis) {
+                    break;
+                }
+                self.expect(&TokenKind::Comma)?;
+            }
+        }
+        let result = if self.eat(&TokenKind::Colon) {
+            Some(self.type_()?)
+        } else {
+            None
+        };
+        let body = if self.eat_keyword(Keyword::Is) {
+            let body = self.block()?;
+            self.expect_keyword(Keyword::End)?;
+            Some(RoutineBody::Block(body))
+        } else if self.eat(&TokenKind::RightArrow) {
+            Some(RoutineBody::Expression(self.expression()?))
+        } else {
+            None
+        };
+        self.expect(&TokenKind::Semicolon)?;
+        Ok(RoutineDeclaration {
+            doc,
+            position,
+            name,
+            parameters,
+            result,
+            body,
+        })
+    }
+
+    fn type_(&mut self) -> Result<Rc<Type>, ParseError> {
+        let t = match self.peek() {
+            Some(TokenKind::BuiltinTypename(typename)) => {
+                let t = match typename {
+                    BuiltinTypename::Integer => Type::Int,
+                    BuiltinTypename::Real => Type::Real,
+                    BuiltinTypename::Boolean => Type::Bool,
+                    BuiltinTypename::String => Type::String,
+                };
+                self.next += 1;
+                t
+            }
+            Some(TokenKind::Identifier(_)) => Type::Alias(self.qualified_name()?),
+            Some(TokenKind::Keyword(Keyword::Record)) => {
+                self.next += 1;
+                let mut fields = Vec::new();
+                while !self.eat_keyword(Keyword::End) {
+                    let VariableDeclaration {
+                        name,
+                        t,
+                        initializer,
+                        ..
+                    } = self.variable()?;
+                    let (Some(t), None) = (t, initializer) else {
+                        return self
+                            .error(format!("Field `{name}` needs a type and no initializer"));
+                    };
+                    self.expect(&TokenKind::Semicolon)?;
+                    fields.push(FieldDescription { name, t });
+                }
+                Type::Record(RecordDeclaration { fields })
+            }
+            Some(TokenKind::Keyword(Keyword::Array)) => {
+                self.next += 1;
+                self.expect(&TokenKind::LeftBracket)?;
+                let size = if self.eat(&TokenKind::RightBracket) {
+                    None
+                } else {
+                    let size = self.expression()?;
+                    self.expect(&TokenKind::RightBracket)?;
+                    Some(size)
+                };
+                Type::Array(ArrayDescription {
+                    t: self.type_()?,
+                    size,
+                    length: None,
+                })
+            }
+            Some(TokenKind::Keyword(Keyword::Enum)) => {
+                self.next += 1;
+                let mut variants = vec![self.identifier()?];
+                while self.eat(&TokenKind::Comma) {
+                    variants.push(self.identifier()?);
+                }
+                self.expect_keyword(Keyword::End)?;
+                Type::Enum(EnumDescription { variants })
+            }
+            _ => return self.unexpected("a type"),
+        };
+        Ok(Rc::new(t))
+    }
+
+    /// `name` or `module.name`
+    fn qualified_name(&mut self) -> Result<Identifier, ParseError> {
+        let first = self.identifier()?;
+        if self.peek() == Some(&TokenKind::Dot)
+            && matches!(self.peek_at(1), Some(TokenKind::Identifier(_)))
+        {
+            self.next += 1;
+            let mut qualified = self.identifier()?;
+            qualified.module = Some(first.name);
+            return Ok(qualified);
+        }
+        Ok(first)
+    }
+
+    /// Declarations and statements up to `end` or `else`, which is not consumed
+    fn block(&mut self) -> Result<Block, ParseError> {
+        let mut elements = Vec::new();
+        loop {
+            match self.peek() {
+                Some(TokenKind::Keyword(Keyword::End | Keyword::Else)) | None => break,
+                Some(TokenKind::Keyword(Keyword::Var | Keyword::Type)) => {
+                    elements.push(BlockElement::Decl(Rc::new(self.simple_declaration()?)));
+                }
+                Some(_) => {
+                    let position = self.position();
+                    for statement in self.statement()? {
+                        elements.push(BlockElement::Stmt(position, Rc::new(statement)));
+                    }
+                }
+            }
+        }
+        Ok(Block::new(elements))
+    }
+
+    /// A statement with its `;`, `print a, b` is a `print` of each value
+    fn statement(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let statement = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::While)) => self.while_loop(None)?,
+            Some(TokenKind::Keyword(Keyword::For)) => self.for_loop(None)?,
+            Some(TokenKind::Identifier(_)) if self.peek_at(1) == Some(&TokenKind::Colon) => {
+                let label = self.identifier()?;
+                self.next += 1;
+                match self.peek() {
+                    Some(TokenKind::Keyword(Keyword::While)) => self.while_loop(Some(label))?,
+                    Some(TokenKind::Keyword(Keyword::For)) => self.for_loop(Some(label))?,
+                    _ => return self.unexpected("a loop after its label"),
+                }
+            }
+            Some(TokenKind::Keyword(Keyword::If)) => self.if_statement()?,
+            Some(TokenKind::Keyword(Keyword::Case)) => self.case()?,
+            Some(TokenKind::Keyword(Keyword::Print)) => {
+                self.next += 1;
+                let mut statements = vec![Statement::Print {
+                    value: self.expression()?,
+                }];
+                while self.eat(&TokenKind::Comma) {
+                    statements.push(Statement::Print {
+                        value: self.expression()?,
+                    });
+                }
+                self.expect(&TokenKind::Semicolon)?;
+                return Ok(statements);
+            }
+            // Not reserved by the lexer
+            Some(TokenKind::Identifier(tokens::Identifier { name: "return" })) => {
+                self.next += 1;
+                let value = if self.peek() == Some(&TokenKind::Semicolon) {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                Statement::Return { value }
+            }
+            Some(TokenKind::Keyword(Keyword::Break)) => {
+                self.next += 1;
+                Statement::Break {
+                    label: self.loop_label()?,
+                }
+            }
+            Some(TokenKind::Keyword(Keyword::Continue)) => {
+                self.next += 1;
+                Statement::Continue {
+                    label: self.loop_label()?,
+                }
+            }
+            Some(TokenKind::Identifier(_)) => match self.name_expression()? {
+                call @ Expression::Call { .. } => Statement::Call {
+                    call: Rc::new(call),
+                },
+                Expression::LvalueToRvalue(lhs) => {
+                    self.expect(&TokenKind::Assignment)?;
+                    Statement::Assignment {
+                        lhs,
+                        rhs: self.expression()?,
+                    }
+                }
+                Expression::IntegerLiteral(_)
+                | Expression::RealLiteral(_)
+                | Expression::BoolLiteral(_)
+                | Expression::StringLiteral(_)
+                | Expression::Binop { .. }
+                | Expression::Unop { .. }
+                | Expression::BoolToInt(_)
+                | Expression::RealToInt(_)
+                | Expression::IntToBool(_)
+                | Expression::Conversion { .. } => {
+                    unreachable!("Names are calls or lvalues")
+                }
+            },
+            _ => return self.unexpected("a statement"),
+        };
+        self.expect(&TokenKind::Semicolon)?;
+        Ok(vec![statement])
+    }
+
+    fn loop_label(&mut self) -> Result<Option<Identifier>, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Identifier(_)) => self.identifier().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn while_loop(&mut self, label: Option<Identifier>) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::While)?;
+        let condition = self.expression()?;
+        self.expect_keyword(Keyword::Loop)?;
+        let body = self.block()?;
+        self.expect_keyword(Keyword::End)?;
+        Ok(Statement::While {
+            label,
+            condition,
+            body,
+        })
+    }
+
+    /// `for i in from .. to [reverse] loop`, or `for x in array [..] [reverse] loop`
+    fn for_loop(&mut self, label: Option<Identifier>) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::For)?;
+        let identifier = self.identifier()?;
+        self.expect_keyword(Keyword::In)?;
+        let from = self.expression()?;
+        let to = if self.eat(&TokenKind::RangeSymbol)
+            && !matches!(
+                self.peek(),
+                Some(TokenKind::Keyword(Keyword::Reverse | Keyword::Loop))
+            ) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        let order = if self.eat_keyword(Keyword::Reverse) {
+            LoopOrder::Reversed
+        } else {
+            LoopOrder::Direct
+        };
+        self.expect_keyword(Keyword::Loop)?;
+        let body = self.block()?;
+        self.expect_keyword(Keyword::End)?;
+        Ok(Statement::For {
+            label,
+            identifier,
+            from,
+            to,
+            order,
+            body,
+        })
+    }
+
+    fn if_statement(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::If)?;
+        let condition = self.expression()?;
+        self.expect_keyword(Keyword::Then)?;
+        let on_true = self.block()?;
+        let on_false = if self.eat_keyword(Keyword::Else) {
+            Some(self.block()?)
+        } else {
+            None
+        };
+        self.expect_keyword(Keyword::End)?;
+        Ok(Statement::If {
+            condition,
+            on_true,
+            on_false,
+        })
+    }
+
+    /// `case value of label, label is ... end; ... else ... end`
+    fn case(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::Case)?;
+        let value = self.expression()?;
+        self.expect_keyword(Keyword::Of)?;
+        let mut branches = Vec::new();
+        while !matches!(
+            self.peek(),
+            Some(TokenKind::Keyword(Keyword::Else | Keyword::End))
+        ) {
+            let mut labels = vec![self.case_label()?];
+            while self.eat(&TokenKind::Comma) {
+                labels.push(self.case_label()?);
+            }
+            self.expect_keyword(Keyword::Is)?;
+            let body = self.block()?;
+            self.expect_keyword(Keyword::End)?;
+            self.expect(&TokenKind::Semicolon)?;
+            branches.push(CaseBranch { labels, body });
+        }
+        let otherwise = if self.eat_keyword(Keyword::Else) {
+            Some(self.block()?)
+        } else {
+            None
+        };
+        self.expect_keyword(Keyword::End)?;
+        Ok(Statement::Case {
+            value,
+            branches,
+            otherwise,
+        })
+    }
+
+    fn case_label(&mut self) -> Result<CaseLabel, ParseError> {
+        match self.peek() {
+            Some(&TokenKind::IntegerLiteral(tokens::IntegerLiteral { value })) => {
+                self.next += 1;
+                Ok(CaseLabel::Integer(IntegerLiteral::new(value)))
+            }
+            Some(TokenKind::Identifier(_)) => self.identifier().map(CaseLabel::Variant),
+            _ => self.unexpected("an integer or an enum variant"),
+        }
+    }
+
+    /// Operators of the same precedence are left-associative:
+    /// `and`, `or` and `xor` bind the loosest, then comparisons, sums, products
+    /// and finally the prefix `-`, `+` and `not`
+    fn expression(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let mut lhs = self.relation()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Operator(SyntacticOperator::And)) => BinaryOperator::And,
+                Some(TokenKind::Operator(SyntacticOperator::Or)) => BinaryOperator::Or,
+                Some(TokenKind::Operator(SyntacticOperator::Xor)) => BinaryOperator::Xor,
+                _ => return Ok(lhs),
+            };
+            self.next += 1;
+            let rhs = self.relation()?;
+            lhs = Rc::new(Expression::Binop { op, lhs, rhs });
+        }
+    }
+
+    /// A single comparison, `a < b < c` is an error
+    fn relation(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let lhs = self.sum()?;
+        let op = match self.peek() {
+            Some(TokenKind::Operator(SyntacticOperator::Lt)) => BinaryOperator::Lg,
+            Some(TokenKind::Operator(SyntacticOperator::Le)) => BinaryOperator::Le,
+            Some(TokenKind::Operator(SyntacticOperator::Gt)) => BinaryOperator::Gt,
+            Some(TokenKind::Operator(SyntacticOperator::Ge)) => BinaryOperator::Ge,
+            Some(TokenKind::Operator(SyntacticOperator::Eq)) => BinaryOperator::Eq,
+            Some(TokenKind::Operator(SyntacticOperator::Neq)) => BinaryOperator::Neq,
+            _ => return Ok(lhs),
+        };
+        self.next += 1;
+        let rhs = self.sum()?;
+        Ok(Rc::new(Expression::Binop { op, lhs, rhs }))
+    }
+
+    fn sum(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Operator(SyntacticOperator::Add)) => BinaryOperator::Add,
+                Some(TokenKind::Operator(SyntacticOperator::Sub)) => BinaryOperator::Sub,
+                // The lexer takes the sign of `a[1] -1` for the literal's, it is added then
+                Some(TokenKind::IntegerLiteral(_) | TokenKind::RealLiteral(_))
+                    if self.tokens[self.next].lexeme.starts_with(['-', '+']) =>
+                {
+                    let rhs = self.product()?;
+                    lhs = Rc::new(Expression::Binop {
+                        op: BinaryOperator::Add,
+                        lhs,
+                        rhs,
+                    });
+                    continue;
+                }
+                _ => return Ok(lhs),
+            };
+            self.next += 1;
+            let rhs = self.product()?;
+            lhs = Rc::new(Expression::Binop { op, lhs, rhs });
+        }
+    }
+
+    fn product(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Operator(SyntacticOperator::Mul)) => BinaryOperator::Mul,
+                Some(TokenKind::Operator(SyntacticOperator::Div)) => BinaryOperator::Div,
+                Some(TokenKind::Operator(SyntacticOperator::Mod)) => BinaryOperator::Mod,
+                _ => return Ok(lhs),
+            };
+            self.next += 1;
+            let rhs = self.unary()?;
+            lhs = Rc::new(Expression::Binop { op, lhs, rhs });
+        }
+    }
+
+    fn unary(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let op = match self.peek() {
+            Some(TokenKind::Operator(SyntacticOperator::Sub)) => UnaryOperator::Neg,
+            Some(TokenKind::Operator(SyntacticOperator::Neg)) => UnaryOperator::Not,
+            Some(TokenKind::Operator(SyntacticOperator::Add)) => {
+                self.next += 1;
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.next += 1;
+        let value = self.unary()?;
+        Ok(Rc::new(Expression::Unop { op, value }))
+    }
+
+    fn primary(&mut self) -> Result<Rc<Expression>, ParseError> {
+        let expression = match self.peek() {
+            Some(&TokenKind::IntegerLiteral(tokens::IntegerLiteral { value })) => {
+                self.next += 1;
+                Expression::IntegerLiteral(IntegerLiteral::new(value))
+            }
+            Some(&TokenKind::RealLiteral(tokens::RealLiteral { value })) => {
+                self.next += 1;
+                Expression::RealLiteral(RealLiteral::new(value))
+            }
+            Some(&TokenKind::BoolLiteral(tokens::BoolLiteral { value })) => {
+                self.next += 1;
+                Expression::BoolLiteral(if value {
+                    BoolLiteral::True
+                } else {
+                    BoolLiteral::False
+                })
+            }
+            Some(TokenKind::StringLiteral(tokens::StringLiteral { value })) => {
+                let literal = StringLiteral::new(value.clone());
+                self.next += 1;
+                Expression::StringLiteral(literal)
+            }
+            Some(TokenKind::LeftParenthesis) => {
+                self.next += 1;
+                let value = self.expression()?;
+                self.expect(&TokenKind::RightParenthesis)?;
+                return Ok(value);
+            }
+            // `integer(value)` and the like
+            Some(TokenKind::BuiltinTypename(_)) => {
+                let target = self.type_()?;
+                self.expect(&TokenKind::LeftParenthesis)?;
+                let value = self.expression()?;
+                self.expect(&TokenKind::RightParenthesis)?;
+                Expression::Conversion { target, value }
+            }
+            Some(TokenKind::Identifier(_)) => self.name_expression()?,
+            _ => return self.unexpected("an expression"),
+        };
+        Ok(Rc::new(expression))
+    }
+
+    /// A call, `routine(args)` or `module.routine(args)`, or a variable with its fields
+    /// and elements, `name.field[index]`
+    fn name_expression(&mut self) -> Result<Expression, ParseError> {
+        let is_qualified_call = self.peek_at(1) == Some(&TokenKind::Dot)
+            && matches!(self.peek_at(2), Some(TokenKind::Identifier(_)))
+            && self.peek_at(3) == Some(&TokenKind::LeftParenthesis);
+        let callee = if is_qualified_call {
+            self.qualified_name()?
+        } else {
+            self.identifier()?
+        };
+        if self.eat(&TokenKind::LeftParenthesis) {
+            let mut args = Vec::new();
+            if !self.eat(&TokenKind::RightParenthesis) {
+                loop {
+                    args.push(self.expression()?);
+                    if self.eat(&TokenKind::RightParenthesis) {
+                        break;
+                    }
+                    self.expect(&TokenKind::Comma)?;
+                }
+            }
+            return Ok(Expression::Call { callee, args });
+        }
+
+        let mut lvalue = LvalueExpression::Identifier(callee);
+        loop {
+            if self.eat(&TokenKind::Dot) {
+                lvalue = LvalueExpression::Member {
+                    lhs: Rc::new(lvalue),
+                    member_name: self.identifier()?,
+                };
+            } else if self.eat(&TokenKind::LeftBracket) {
+                let index = self.expression()?;
+                self.expect(&TokenKind::RightBracket)?;
+                lvalue = LvalueExpression::Index {
+                    lhs: Rc::new(lvalue),
+                    index,
+                };
+            } else {
+                return Ok(Expression::LvalueToRvalue(Rc::new(lvalue)));
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use super::*;
+use crate::lexer::Lexer;
+
+fn parse_source(src: &str) -> Result<Program, ParseError> {
+    let tokens: Vec<_> = Lexer::from(src).collect();
+    parse(&tokens)
+}
+
+fn error(src: &str) -> Option<String> {
+    parse_source(src).err().map(|e| e.to_string())
+}
+
+/// The statements of `routine main() is <body> end;`
+fn statements(body: &str) -> Vec<Rc<Statement>> {
+    let Ok(program) = parse_source(&format!("routine main() is {body} end;")) else {
+        return Vec::new();
+    };
+    let [
+        Declaration::Routine(RoutineDeclaration {
+            body: Some(RoutineBody::Block(block)),
+            ..
+        }),
+    ] = &program.declarations[..]
+    else {
+        return Vec::new();
+    };
+    block
+        .elements()
+        .iter()
+        .filter_map(|element| match element {
+            BlockElement::Stmt(_, statement) => Some(Rc::clone(statement)),
+            BlockElement::Decl(_) => None,
+        })
+        .collect()
+}
+
+/// The value of `print <expression>;`
+fn expression(expression: &str) -> Option<Rc<Expression>> {
+    match statements(&format!("print {expression};")).as_slice() {
+        [statement] => match &**statement {
+            Statement::Print { value } => Some(Rc::clone(value)),
+            Statement::Assignment { .. }
+            | Statement::Call { .. }
+            | Statement::While { .. }
+            | Statement::If { .. }
+            | Statement::For { .. }
+            | Statement::Case { .. }
+            | Statement::Return { .. }
+            | Statement::Break { .. }
+            | Statement::Continue { .. } => None,
+        },
+        _ => None,
+    }
+}
+
+fn int(value: i64) -> Rc<Expression> {
+    Rc::new(Expression::IntegerLiteral(IntegerLiteral::new(value)))
+}
+
+fn binop(op: BinaryOperator, lhs: Rc<Expression>, rhs: Rc<Expression>) -> Rc<Expression> {
+    Rc::new(Expression::Binop { op, lhs, rhs })
+}
+
+fn variable(name: &str) -> Rc<LvalueExpression> {
+    Rc::new(LvalueExpression::Identifier(Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }))
+}
+
+macro_rules! parses {
+    ($($name:ident),+,) => {
+        $(
+            #[test]
+            fn $name() {
+                let src = include_str!(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/../tests/src/",
+                    stringify!($name), ".i"
+                ));
+                assert_eq!(parse_source(src).err(), None);
+            }
+        )+
+    };
+}
+
+parses![
+    arithmetic_operations,
+    arrays_and_records,
+    comments,
+    comparison_operators,
+    complex_expressions,
+    conditionals,
+    constant_sizes,
+    deep_conditionals,
+    enums,
+    for_loops,
+    function_parameters,
+    function_return,
+    identifiers,
+    imports,
+    invalid,
+    logical_operators,
+    loop_exits,
+    nested_control,
+    nominal_aliases,
+    operator_precedence,
+    parse_minus,
+    read_input,
+    real_literals,
+    records,
+    recursive_types,
+    references,
+    shadow,
+    short_circuit,
+    strings,
+    type_aliases,
+    type_conversions,
+    variable_declarations,
+    while_loops,
+];
+
+#[test]
+fn precedence() {
+    let sum = binop(
+        BinaryOperator::Add,
+        int(1),
+        binop(BinaryOperator::Mul, int(2), int(3)),
+    );
+    assert_eq!(
+        expression("1 + 2 * 3 < 4 or true"),
+        Some(binop(
+            BinaryOperator::Or,
+            binop(BinaryOperator::Lg, sum, int(4)),
+            Rc::new(Expression::BoolLiteral(BoolLiteral::True)),
+        ))
+    );
+    assert_eq!(
+        expression("(1 - 2) - 3"),
+        expression("1 - 2 - 3"),
+        "Operators are left-associative"
+    );
+}
+
+/// `a[1] -1` is lexed as `]` and the literal `-1`
+#[test]
+fn signed_literal_in_sum() {
+    let element = Rc::new(Expression::LvalueToRvalue(Rc::new(
+        LvalueExpression::Index {
+            lhs: variable("a"),
+            index: int(1),
+        },
+    )));
+    assert_eq!(
+        expression("a[1] -1"),
+        Some(binop(BinaryOperator::Add, element, int(-1)))
+    );
+}
+
+#[test]
+fn unary_operators() {
+    let a = Rc::new(Expression::LvalueToRvalue(variable("a")));
+    assert_eq!(
+        expression("not a"),
+        Some(Rc::new(Expression::Unop {
+            op: UnaryOperator::Not,
+            value: Rc::clone(&a),
+        }))
+    );
+    assert_eq!(
+        expression("-(a)"),
+        Some(Rc::new(Expression::Unop {
+            op: UnaryOperator::Neg,
+            value: a,
+        }))
+    );
+}
+
+#[test]
+fn statements_of_a_body() {
+    let body = statements("print a, b; x.y[1] := 2; f(x); return;");
+    assert!(matches!(
+        body.iter()
+            .map(|statement| &**statement)
+            .collect::<Vec<_>>()[..],
+        [
+            Statement::Print { .. },
+            Statement::Print { .. },
+            Statement::Assignment { .. },
+            Statement::Call { .. },
+            Statement::Return { value: None },
+        ]
+    ));
+}
+
+#[test]
+fn forward_declaration() {
+    let program = parse_source("routine f(a : integer) : integer; routine g() => f(1);");
+    assert!(matches!(
+        program.as_ref().map(|program| &program.declarations[..]),
+        Ok([
+            Declaration::Routine(RoutineDeclaration { body: None, .. }),
+            Declaration::Routine(RoutineDeclaration {
+                body: Some(RoutineBody::Expression(_)),
+                result: None,
+                ..
+            }),
+        ])
+    ));
+}
+
+/// `---` lines of a declaration, in order
+fn docs(declaration: &Declaration) -> &[String] {
+    match declaration {
+        Declaration::Routine(RoutineDeclaration { doc, .. })
+        | Declaration::Simple(
+            SimpleDeclaration::Type(TypeDeclaration { doc, .. })
+            | SimpleDeclaration::Variable(VariableDeclaration { doc, .. }),
+        ) => doc.lines(),
+        Declaration::Import(_) => &[],
+    }
+}
+
+#[test]
+fn doc_comments() {
+    let source = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/src/comments.i"
+    ))
+    .expect("Test program exists");
+    let program = parse_source(&source).expect("The program is valid");
+    assert_eq!(
+        program.declarations.iter().map(docs).collect::<Vec<_>>(),
+        [
+            &["Distance between two points,", "always non-negative"][..],
+            &["Scale factor"],
+        ]
+    );
+
+    let program = parse_source(
+        "--- A point
+        type point is record var x : real; end;
+        routine main() is
+          --- Not a declaration of the program
+          print 1;
+        end;
+        -- Plain comments are not documentation
+        var count is 0;",
+    )
+    .expect("The program is valid");
+    assert_eq!(
+        program.declarations.iter().map(docs).collect::<Vec<_>>(),
+        [&["A point"][..], &[], &[]]
+    );
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        error("routine main() is print 1 end;").as_deref(),
+        Some("1:26: Expected SEMICOLON, found KEYWORD(End)")
+    );
+    assert_eq!(
+        error("var x;").as_deref(),
+        Some("1:5: Expected `:` with a type or `is` with an initializer, found SEMICOLON")
+    );
+    assert_eq!(
+        error("routine main() is 1 := 2; end;").as_deref(),
+        Some("1:18: Expected a statement, found INTEGER LITERAL(1)")
+    );
+}
@@ -0,0 +1,514 @@
+//! Register-based alternative to the stack `Bytecode`, lowered from `ir::Function`s.
+//!
+//! Every frame has its own registers: the arguments come first, the values of the routine
+//! follow. Instructions name the registers they read and write, so there is nothing like
+//! `Dup` or `Swap`, and jumps go to indices in the code of the routine.
+//!
+//! `cargo x bench` compares the instructions executed and the time taken by the two machines
+//! on the programs of `tests/run` at `-O2`.
+
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::bytecode::TypeId;
+use crate::consteval::Constant;
+use crate::ir::{self, BlockId, Operation, Terminator, ValueId};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Register(pub u32);
+
+impl Register {
+    #[must_use]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
+
+/// Booleans are integers 0 and 1, like on the stack machine
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Int {
+        dst: Register,
+        value: i64,
+    },
+    Real {
+        dst: Register,
+        value: f64,
+    },
+    /// reference to `Module::strings[id]`
+    String {
+        dst: Register,
+        id: u32,
+    },
+    Move {
+        dst: Register,
+        src: Register,
+    },
+    Binary {
+        op: SemanticBinaryOperator,
+        dst: Register,
+        lhs: Register,
+        rhs: Register,
+    },
+    Unary {
+        op: SemanticUnaryOperator,
+        dst: Register,
+        src: Register,
+    },
+    IntToReal {
+        dst: Register,
+        src: Register,
+    },
+    RealToInt {
+        dst: Register,
+        src: Register,
+    },
+    IntToBool {
+        dst: Register,
+        src: Register,
+    },
+    LoadGlobal {
+        dst: Register,
+        index: usize,
+    },
+    StoreGlobal {
+        index: usize,
+        src: Register,
+    },
+    AllocRecord {
+        dst: Register,
+        type_id: TypeId,
+    },
+    AllocArray {
+        dst: Register,
+        type_id: TypeId,
+        size: u64,
+    },
+    ArraySize {
+        dst: Register,
+        array: Register,
+    },
+    LoadField {
+        dst: Register,
+        record: Register,
+        offset: u64,
+    },
+    StoreField {
+        record: Register,
+        offset: u64,
+        src: Register,
+    },
+    /// `index` is 1-based
+    LoadElement {
+        dst: Register,
+        array: Register,
+        index: Register,
+    },
+    StoreElement {
+        array: Register,
+        index: Register,
+        src: Register,
+    },
+    /// `dst` is `None` when the result is unused
+    Call {
+        dst: Option<Register>,
+        function_label: u64,
+        args: Vec<Register>,
+    },
+    /// See `builtins::Builtin::id`
+    CallNative {
+        dst: Option<Register>,
+        id: u32,
+        args: Vec<Register>,
+    },
+    Print {
+        type_id: TypeId,
+        src: Register,
+    },
+    Jump {
+        target: usize,
+    },
+    JumpZero {
+        condition: Register,
+        target: usize,
+    },
+    /// Procedures return integer 0
+    Return {
+        src: Option<Register>,
+    },
+    Panic {
+        code: u64,
+    },
+}
+
+fn list(registers: &[Register]) -> String {
+    let registers: Vec<_> = registers.iter().map(ToString::to_string).collect();
+    registers.join(", ")
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let call = |f: &mut fmt::Formatter<'_>, dst: &Option<Register>| match dst {
+            Some(dst) => write!(f, "{dst} = "),
+            None => Ok(()),
+        };
+        match self {
+            Self::Int { dst, value } => write!(f, "{dst} = int {value}"),
+            Self::Real { dst, value } => write!(f, "{dst} = real {value:?}"),
+            Self::String { dst, id } => write!(f, "{dst} = string {id}"),
+            Self::Move { dst, src } => write!(f, "{dst} = {src}"),
+            Self::Binary { op, dst, lhs, rhs } => write!(f, "{dst} = {op:?} {lhs}, {rhs}"),
+            Self::Unary { op, dst, src } => write!(f, "{dst} = {op:?} {src}"),
+            Self::IntToReal { dst, src } => write!(f, "{dst} = IntToReal {src}"),
+            Self::RealToInt { dst, src } => write!(f, "{dst} = RealToInt {src}"),
+            Self::IntToBool { dst, src } => write!(f, "{dst} = IntToBool {src}"),
+            Self::LoadGlobal { dst, index } => write!(f, "{dst} = @{index}"),
+            Self::StoreGlobal { index, src } => write!(f, "@{index} = {src}"),
+            Self::AllocRecord { dst, type_id } => write!(f, "{dst} = record type {}", type_id.0),
+            Self::AllocArray { dst, type_id, size } => {
+                write!(f, "{dst} = array type {} [{size}]", type_id.0)
+            }
+            Self::ArraySize { dst, array } => write!(f, "{dst} = size {array}"),
+            Self::LoadField {
+                dst,
+                record,
+                offset,
+            } => write!(f, "{dst} = {record}.{offset}"),
+            Self::StoreField {
+                record,
+                offset,
+                src,
+            } => write!(f, "{record}.{offset} = {src}"),
+            Self::LoadElement { dst, array, index } => write!(f, "{dst} = {array}[{index}]"),
+            Self::StoreElement { array, index, src } => write!(f, "{array}[{index}] = {src}"),
+            Self::Call {
+                dst,
+                function_label,
+                args,
+            } => {
+                call(f, dst)?;
+                write!(f, "call L{function_label}({})", list(args))
+            }
+            Self::CallNative { dst, id, args } => {
+                call(f, dst)?;
+                write!(f, "native {id}({})", list(args))
+            }
+            Self::Print { type_id, src } => write!(f, "print type {}, {src}", type_id.0),
+            Self::Jump { target } => write!(f, "jump {target}"),
+            Self::JumpZero { condition, target } => write!(f, "jump {target} unless {condition}"),
+            Self::Return { src: Some(src) } => write!(f, "return {src}"),
+            Self::Return { src: None } => write!(f, "return"),
+            Self::Panic { code } => write!(f, "panic {code}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routine {
+    pub name: String,
+    /// Registers `0..args` hold the arguments
+    pub args: u32,
+    pub registers: u32,
+    pub code: Vec<Instruction>,
+}
+
+impl fmt::Display for Routine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "routine {} (args {}, registers {})",
+            self.name, self.args, self.registers
+        )?;
+        for (index, instruction) in self.code.iter().enumerate() {
+            writeln!(f, "{index:>4}: {instruction}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Register code of a module's routines by the label of their entry. Types, globals and strings
+/// are the ones of the `bytecode::Module` compiled from the same program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Code(pub BTreeMap<u64, Routine>);
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (label, routine) in &self.0 {
+            write!(f, "{label}: {routine}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Jump targets are labels until the code is complete:
+/// blocks first, then the edges needing moves of their own
+struct Lowering<'a> {
+    function: &'a ir::Function,
+    args: u32,
+    /// Where each phi gets its value on the edges to its block
+    incoming: HashMap<ValueId, Register>,
+    code: Vec<Instruction>,
+    /// Index in `code` of each label
+    positions: Vec<usize>,
+}
+
+impl Lowering<'_> {
+    fn register(&self, value: ValueId) -> Register {
+        Register(self.args + value.0)
+    }
+
+    fn edge(&mut self) -> usize {
+        self.positions.push(usize::MAX);
+        self.positions.len() - 1
+    }
+
+    /// Moves the values of the phis of `to` coming from `from` to where they read them
+    fn phi_moves(&mut self, from: BlockId, to: BlockId) {
+        for instruction in &self.function.block(to).instructions {
+            let (Some((result, _)), Operation::Phi(incoming)) =
+                (instruction.result, &instruction.operation)
+            else {
+                continue;
+            };
+            for &(predecessor, value) in incoming {
+                if predecessor == from {
+                    self.code.push(Instruction::Move {
+                        dst: self.incoming[&result],
+                        src: self.register(value),
+                    });
+                }
+            }
+        }
+    }
+
+    fn has_phis(&self, block: BlockId) -> bool {
+        self.function
+            .block(block)
+            .instructions
+            .iter()
+            .any(|instruction| matches!(instruction.operation, Operation::Phi(_)))
+    }
+
+    /// Jumps to `to`, unless it is the next block
+    fn jump(&mut self, from: BlockId, to: BlockId) {
+        self.phi_moves(from, to);
+        if to.0 != from.0 + 1 {
+            self.code.push(Instruction::Jump { target: to.index() });
+        }
+    }
+
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn operation(&mut self, result: Option<ValueId>, operation: &Operation) {
+        let register = |value| self.register(value);
+        let dst = || register(result.expect("Operations with a value have a result"));
+        let instruction = match *operation {
+            Operation::Const(Constant::Int(value)) => Instruction::Int { dst: dst(), value },
+            Operation::Const(Constant::Bool(value)) => Instruction::Int {
+                dst: dst(),
+                value: i64::from(value),
+            },
+            Operation::Const(Constant::Real(value)) => Instruction::Real { dst: dst(), value },
+            Operation::String { id } => Instruction::String { dst: dst(), id },
+            Operation::Param { index } => Instruction::Move {
+                dst: dst(),
+                src: Register(u32::try_from(index).expect("Arguments fit into u32")),
+            },
+            Operation::Copy(value) => Instruction::Move {
+                dst: dst(),
+                src: register(value),
+            },
+            Operation::Phi(_) => {
+                let result = result.expect("Phis have a result");
+                Instruction::Move {
+                    dst: register(result),
+                    src: self.incoming[&result],
+                }
+            }
+            Operation::Binary { op, lhs, rhs } => Instruction::Binary {
+                op,
+                dst: dst(),
+                lhs: register(lhs),
+                rhs: register(rhs),
+            },
+            Operation::Unary { op, value } => Instruction::Unary {
+                op,
+                dst: dst(),
+                src: register(value),
+            },
+            Operation::IntToReal(value) => Instruction::IntToReal {
+                dst: dst(),
+                src: register(value),
+            },
+            Operation::RealToInt(value) => Instruction::RealToInt {
+                dst: dst(),
+                src: register(value),
+            },
+            Operation::IntToBool(value) => Instruction::IntToBool {
+                dst: dst(),
+                src: register(value),
+            },
+            Operation::LoadGlobal { index } => Instruction::LoadGlobal { dst: dst(), index },
+            Operation::StoreGlobal { index, value } => Instruction::StoreGlobal {
+                index,
+                src: register(value),
+            },
+            Operation::AllocRecord { type_id, .. } => Instruction::AllocRecord {
+                dst: dst(),
+                type_id,
+            },
+            Operation::AllocArray { type_id, size } => Instruction::AllocArray {
+                dst: dst(),
+                type_id,
+                size,
+            },
+            Operation::ArraySize(array) => Instruction::ArraySize {
+                dst: dst(),
+                array: register(array),
+            },
+            Operation::LoadField { record, offset } => Instruction::LoadField {
+                dst: dst(),
+                record: register(record),
+                offset,
+            },
+            Operation::StoreField {
+                record,
+                offset,
+                value,
+            } => Instruction::StoreField {
+                record: register(record),
+                offset,
+                src: register(value),
+            },
+            Operation::LoadElement { array, index } => Instruction::LoadElement {
+                dst: dst(),
+                array: register(array),
+                index: register(index),
+            },
+            Operation::StoreElement {
+                array,
+                index,
+                value,
+            } => Instruction::StoreElement {
+                array: register(array),
+                index: register(index),
+                src: register(value),
+            },
+            Operation::Call {
+                function_label,
+                ref args,
+            } => Instruction::Call {
+                dst: result.map(register),
+                function_label,
+                args: args.iter().map(|&arg| register(arg)).collect(),
+            },
+            Operation::CallNative { id, ref args } => Instruction::CallNative {
+                dst: result.map(register),
+                id,
+                args: args.iter().map(|&arg| register(arg)).collect(),
+            },
+            Operation::Print { type_id, value } => Instruction::Print {
+                type_id,
+                src: register(value),
+            },
+        };
+        self.code.push(instruction);
+    }
+
+    fn terminator(&mut self, block: BlockId, terminator: Terminator) {
+        match terminator {
+            Terminator::Jump(target) => self.jump(block, target),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.register(condition);
+                if self.has_phis(otherwise) {
+                    let edge = self.edge();
+                    self.code.push(Instruction::JumpZero {
+                        condition,
+                        target: edge,
+                    });
+                    self.phi_moves(block, then);
+                    self.code.push(Instruction::Jump {
+                        target: then.index(),
+                    });
+                    self.positions[edge] = self.code.len();
+                    self.jump(block, otherwise);
+                } else {
+                    self.code.push(Instruction::JumpZero {
+                        condition,
+                        target: otherwise.index(),
+                    });
+                    self.jump(block, then);
+                }
+            }
+            Terminator::Return(value) => self.code.push(Instruction::Return {
+                src: value.map(|value| self.register(value)),
+            }),
+            Terminator::Panic { code } => self.code.push(Instruction::Panic { code }),
+        }
+    }
+}
+
+/// Every value gets a register of its own, phis get another one written on the edges
+/// to their block and read at its start, so that the edges can write them in any order
+///
+/// # Panics
+///
+/// If the function has more than `u32::MAX` arguments and values
+#[must_use]
+pub fn lower(function: &ir::Function) -> Routine {
+    let args = u32::try_from(function.args.len()).expect("Arguments fit into u32");
+    let mut registers = args + function.value_count;
+    let mut incoming = HashMap::new();
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let (Some((result, _)), Operation::Phi(_)) = (instruction.result, &instruction.operation)
+        {
+            let _: Option<Register> = incoming.insert(result, Register(registers));
+            registers += 1;
+        }
+    }
+    let mut lowering = Lowering {
+        function,
+        args,
+        incoming,
+        code: Vec::new(),
+        positions: vec![usize::MAX; function.blocks.len()],
+    };
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        lowering.positions[id.index()] = lowering.code.len();
+        for instruction in &block.instructions {
+            let result = instruction.result.map(|(result, _)| result);
+            lowering.operation(result, &instruction.operation);
+        }
+        lowering.terminator(id, block.terminator);
+    }
+
+    let Lowering {
+        mut code,
+        positions,
+        ..
+    } = lowering;
+    for instruction in &mut code {
+        if let Instruction::Jump { target } | Instruction::JumpZero { target, .. } = instruction {
+            *target = positions[*target];
+        }
+    }
+    Routine {
+        name: function.name.clone(),
+        args,
+        registers,
+        code,
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+use crate::ir::{FunctionBuilder, IrType};
+
+/// `routine max(a : integer, b : integer) : integer is if a < b then a := b; end; return a; end;`
+fn max() -> ir::Function {
+    let mut builder = FunctionBuilder::new("max", vec![IrType::Int; 2], Some(IrType::Int));
+    let then = builder.block();
+    let join = builder.block();
+    let a = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let b = builder.value(IrType::Int, Operation::Param { index: 1 });
+    let less = builder.value(
+        IrType::Bool,
+        Operation::Binary {
+            op: SemanticBinaryOperator::IntLg,
+            lhs: a,
+            rhs: b,
+        },
+    );
+    builder.terminate(Terminator::Branch {
+        condition: less,
+        then,
+        otherwise: join,
+    });
+    builder.switch_to(then);
+    builder.terminate(Terminator::Jump(join));
+    builder.switch_to(join);
+    let result = builder.value(
+        IrType::Int,
+        Operation::Phi(vec![(BlockId(0), a), (then, b)]),
+    );
+    builder.terminate(Terminator::Return(Some(result)));
+    builder.finish()
+}
+
+#[test]
+fn phis_read_their_own_register() {
+    assert_eq!(
+        lower(&max()).to_string(),
+        "\
+routine max (args 2, registers 7)
+   0: r2 = r0
+   1: r3 = r1
+   2: r4 = IntLg r2, r3
+   3: jump 5 unless r4
+   4: jump 7
+   5: r6 = r2
+   6: jump 8
+   7: r6 = r3
+   8: r5 = r6
+   9: return r5
+"
+    );
+}
+
+#[test]
+fn unused_call_results_are_not_written() {
+    let mut builder = FunctionBuilder::new("f", Vec::new(), None);
+    builder.effect(Operation::Call {
+        function_label: 3,
+        args: Vec::new(),
+    });
+    let routine = lower(&builder.finish());
+    assert_eq!(
+        routine.code,
+        [
+            Instruction::Call {
+                dst: None,
+                function_label: 3,
+                args: Vec::new(),
+            },
+            Instruction::Return { src: None },
+        ]
+    );
+    assert_eq!(routine.registers, 0);
+}
@@ -0,0 +1,226 @@
+//! Modules and routines shared by the tests of the backends, with the harness of the
+//! backends building executables
+
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::{env, fs};
+
+use crate::bytecode::linker::{link, link_ir};
+use crate::bytecode::{
+    ArrayRTTI, EnumRTTI, FunctionCode, FunctionRecord, FunctionTable, Module, RTTI, RTTIElement,
+    RecordRTTI, TypeId,
+};
+use crate::consteval::Constant;
+use crate::ir::{self, FunctionBuilder, IrType, Operation, ValueId};
+use crate::modules::{self, SearchPath};
+use crate::operators::SemanticBinaryOperator;
+use crate::{Options, TypedProgram};
+
+/// `record x : real; y : real end`
+pub(crate) const POINT: TypeId = TypeId(4);
+/// `array of integer`
+pub(crate) const INTEGERS: TypeId = TypeId(5);
+/// `enum red, green end`
+pub(crate) const COLOR: TypeId = TypeId(6);
+/// `array of POINT`
+pub(crate) const POINTS: TypeId = TypeId(7);
+
+/// The entry of routine `name` of module `main` in the function table
+#[must_use]
+pub(crate) fn routine(name: &str, label: u64, args: &[TypeId], result: TypeId) -> FunctionRecord {
+    FunctionRecord {
+        name: format!("main.{name}"),
+        code: FunctionCode::Label(label),
+        args: args.to_vec(),
+        result,
+    }
+}
+
+/// Module `main` with the types above and a global, the code is in the SSA routines
+///
+/// # Panics
+///
+/// On more routines than labels
+#[must_use]
+pub(crate) fn module(functions: Vec<FunctionRecord>, strings: &[&str]) -> Module {
+    let mut rtti = RTTI::primitives();
+    rtti.0.extend([
+        RTTIElement::Record(RecordRTTI {
+            id: POINT,
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        }),
+        RTTIElement::Array(ArrayRTTI {
+            id: INTEGERS,
+            element_id: TypeId::INTEGER,
+        }),
+        RTTIElement::Enum(EnumRTTI {
+            id: COLOR,
+            variants: vec!["red".to_owned(), "green".to_owned()],
+        }),
+        RTTIElement::Array(ArrayRTTI {
+            id: POINTS,
+            element_id: POINT,
+        }),
+    ]);
+    Module {
+        name: "main".to_owned(),
+        code: Vec::new(),
+        label_count: u64::try_from(functions.len()).expect("Few routines"),
+        functions: FunctionTable(functions),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti,
+        strings: strings.iter().map(|&s| s.to_owned()).collect(),
+        global_count: 1,
+    }
+}
+
+pub(crate) fn int(builder: &mut FunctionBuilder, value: i64) -> ValueId {
+    builder.value(IrType::Int, Operation::Const(Constant::Int(value)))
+}
+
+pub(crate) fn binary(
+    builder: &mut FunctionBuilder,
+    t: IrType,
+    op: SemanticBinaryOperator,
+    lhs: ValueId,
+    rhs: ValueId,
+) -> ValueId {
+    builder.value(t, Operation::Binary { op, lhs, rhs })
+}
+
+pub(crate) fn print(builder: &mut FunctionBuilder, type_id: TypeId, value: ValueId) {
+    builder.effect(Operation::Print { type_id, value });
+}
+
+/// Checks the module at `path` with the ones it imports and links their SSA routines
+///
+/// # Panics
+///
+/// If it is not a valid program
+#[must_use]
+pub(crate) fn built(path: &Path) -> (Module, BTreeMap<u64, ir::Function>) {
+    let mut checked = Vec::new();
+    for module in modules::load(path, &SearchPath::default()).expect("Modules are there") {
+        let program =
+            crate::check_module(module.program, &module.name, &checked, &Options::default())
+                .expect("Type checks");
+        checked.push(program);
+    }
+    build(&checked)
+}
+
+/// `built` of a single module
+///
+/// # Panics
+///
+/// If it is not a valid program
+#[must_use]
+pub(crate) fn built_source(source: &str) -> (Module, BTreeMap<u64, ir::Function>) {
+    let program = crate::parse(&crate::lex(source)).expect("Parses");
+    build(&[crate::check(program, &Options::default()).expect("Type checks")])
+}
+
+fn build(checked: &[TypedProgram]) -> (Module, BTreeMap<u64, ir::Function>) {
+    let units: Vec<_> = checked.iter().map(crate::compile).collect();
+    let routines = link_ir(&units, checked.iter().map(ir::build).collect()).expect("Links");
+    (link(units).expect("Links"), routines)
+}
+
+pub(crate) struct Run {
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+    pub(crate) success: bool,
+}
+
+/// Builds `program` in a temporary directory named after the test with `build`, which gets
+/// the directory and the path of the executable, and runs it on `input`
+///
+/// # Panics
+///
+/// If the program can't be run
+pub(crate) fn run_native(test: &str, input: &str, build: impl FnOnce(&Path, &Path)) -> Run {
+    let dir = env::temp_dir().join(format!("{test}-{}", process::id()));
+    fs::create_dir_all(&dir).expect("Temporary directory is writable");
+    let executable = dir.join("program");
+    build(&dir, &executable);
+    let mut child = Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("The program was just built");
+    child
+        .stdin
+        .take()
+        .expect("Input is piped")
+        .write_all(input.as_bytes())
+        .expect("The program reads its input");
+    let output = child.wait_with_output().expect("The program ends");
+    fs::remove_dir_all(&dir).expect("Temporary directory is writable");
+    Run {
+        stdout: String::from_utf8(output.stdout).expect("Output is UTF-8"),
+        stderr: String::from_utf8(output.stderr).expect("Output is UTF-8"),
+        success: output.status.success(),
+    }
+}
+
+/// Runs the programs of `tests/run` with `run`, which gets the name, the program and the input
+///
+/// # Panics
+///
+/// If one prints something else than expected or fails
+pub(crate) fn runs_the_corpus(
+    run: impl Fn(&str, &Module, &BTreeMap<u64, ir::Function>, &str) -> Run,
+) {
+    for corpus::Program {
+        name,
+        source,
+        input,
+        expected,
+    } in corpus::programs()
+    {
+        let (module, routines) = built(&source);
+        let run = run(&name, &module, &routines, &input);
+        assert_eq!(run.stdout, expected, "{name}");
+        assert!(run.success, "{name}: {}", run.stderr);
+    }
+}
+
+/// Runs programs failing on bad input and out of bounds indices with `run`, the string has
+/// a trigraph of C and escapes of the assembler
+///
+/// # Panics
+///
+/// If one doesn't print what it does on the stack machine before failing
+pub(crate) fn fails_at_run_time(
+    run: impl Fn(&str, &Module, &BTreeMap<u64, ir::Function>, &str) -> Run,
+) {
+    for (name, source, input, stdout, stderr) in [
+        (
+            "input",
+            "routine main() is print read_integer(); print read_integer(); end;",
+            "  100\n2.5",
+            "100\n",
+            "Expected an integer, found \"2.5\"\n",
+        ),
+        (
+            "bounds",
+            r#"routine main() is
+                 var a : array [3] integer; a[3] := 7; print "??/ \\ é"; print a[3]; print a[4];
+               end;"#,
+            "",
+            "??/ \\ é\n7\n",
+            "Index 4 is out of bounds 1..=3\n",
+        ),
+    ] {
+        let (module, routines) = built_source(source);
+        let run = run(name, &module, &routines, input);
+        assert_eq!(run.stdout, stdout, "{name}");
+        assert_eq!(run.stderr, stderr, "{name}");
+        assert!(!run.success, "{name}");
+    }
+}
@@ -4,7 +4,7 @@ use crate::operators::SyntacticOperator;
 
 // Token types
 
-#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone, Copy)]
 pub enum Keyword {
     Var,
     Type,
@@ -22,6 +22,12 @@ pub enum Keyword {
     Loop,
     Reverse,
     Print,
+    Import,
+    Enum,
+    Case,
+    Of,
+    Break,
+    Continue,
 }
 
 #[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
@@ -29,26 +35,33 @@ pub struct Identifier<'a> {
     pub name: &'a str,
 }
 
-#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone, Copy)]
 pub struct IntegerLiteral {
     pub value: i64,
 }
 
-#[derive(PartialEq, fmt::Debug, Clone)]
+#[derive(PartialEq, fmt::Debug, Clone, Copy)]
 pub struct RealLiteral {
     pub value: f64,
 }
 
-#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone, Copy)]
 pub struct BoolLiteral {
     pub value: bool,
 }
 
+/// Escape sequences are already replaced
 #[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
+pub struct StringLiteral {
+    pub value: String,
+}
+
+#[derive(PartialEq, Eq, Hash, fmt::Debug, Clone, Copy)]
 pub enum BuiltinTypename {
     Integer,
     Real,
     Boolean,
+    String,
 }
 
 #[derive(PartialEq, Eq, Hash, fmt::Debug, Clone)]
@@ -67,24 +80,32 @@ impl fmt::Display for Comment<'_> {
 
         let Self { value } = self;
         let comment: &str = value;
+        // Block comments may span several lines, keep the output on one
         if comment.len() <= MAX_LEN {
-            write!(f, "{comment}")
+            write!(f, "{}", comment.escape_debug())
         } else {
-            write!(f, "{} …", &comment[..comment.floor_char_boundary(MAX_LEN)])
+            write!(
+                f,
+                "{} …",
+                comment[..comment.floor_char_boundary(MAX_LEN)].escape_debug()
+            )
         }
     }
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, fmt::Debug, Clone)]
 pub enum TokenKind<'a> {
     Identifier(Identifier<'a>),
     Keyword(Keyword),
     IntegerLiteral(IntegerLiteral),
     RealLiteral(RealLiteral),
     BoolLiteral(BoolLiteral),
+    StringLiteral(StringLiteral),
     BuiltinTypename(BuiltinTypename),
     Operator(SyntacticOperator),
     Comment(Comment<'a>),
+    /// `---` comment documenting the declaration that follows it
+    DocComment(Comment<'a>),
     Invalid(InvalidToken),
     LeftBracket,
     RightBracket,
@@ -113,11 +134,15 @@ impl fmt::Display for TokenKind<'_> {
             TokenKind::BoolLiteral(BoolLiteral { value }) => {
                 write!(f, "BOOLEAN LITERAL({value})")
             }
+            TokenKind::StringLiteral(StringLiteral { value }) => {
+                write!(f, "STRING LITERAL({value:?})")
+            }
             TokenKind::BuiltinTypename(builtin_typename) => {
                 write!(f, "TYPENAME({builtin_typename:?})")
             }
             TokenKind::Operator(operator) => write!(f, "OPERATOR({operator:?})"),
             TokenKind::Comment(comment) => write!(f, "COMMENT({comment})"),
+            TokenKind::DocComment(comment) => write!(f, "DOC COMMENT({comment})"),
             TokenKind::Invalid(InvalidToken { problem }) => write!(f, "INVALID({problem})"),
             TokenKind::LeftBracket => write!(f, "LEFT BRACKET"),
             TokenKind::RightBracket => write!(f, "RIGHT BRACKET"),
@@ -136,17 +161,19 @@ impl fmt::Display for TokenKind<'_> {
 
 // Token description
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
 }
 
 impl Position {
+    #[must_use]
     pub fn begin() -> Self {
         Position { line: 1, column: 0 }
     }
 
+    #[must_use]
     pub fn advance(self, is_newline: bool) -> Self {
         if is_newline {
             Position {
@@ -169,6 +196,7 @@ impl fmt::Display for Position {
     }
 }
 
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Extent {
     pub start: Position,
     pub end: Position,
@@ -181,6 +209,7 @@ impl fmt::Display for Extent {
     }
 }
 
+#[derive(fmt::Debug, Clone)]
 pub struct Token<'a> {
     pub extent: Extent,
     pub lexeme: &'a str,
@@ -1,64 +1,544 @@
-#![expect(dead_code, reason = "WIP")]
-
-use crate::ast::{Expression, Identifier};
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+use crate::ast::{
+    BinaryOperator, CaseBranch, CaseLabel, Expression, Identifier, LvalueExpression, Program,
+    UnaryOperator,
+};
+use crate::builtins::Builtin;
+use crate::bytecode::{FunctionCode, Location, RTTI, Symbol, TypeId};
+use crate::consteval;
+use crate::tokens::Position;
+
+mod checker;
+#[cfg(test)]
+mod tests;
+
 #[derive(Debug, Hash, PartialEq, Eq)]
-struct FieldDescription {
-    name: Identifier,
-    t: Rc<Type>,
+pub struct FieldDescription {
+    pub name: Identifier,
+    pub t: Rc<Type>,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
-struct RecordDeclaration {
-    fields: Vec<FieldDescription>,
+pub struct RecordDeclaration {
+    pub fields: Vec<FieldDescription>,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
-struct ArrayDescription {
-    t: Rc<Type>,
-    length: Option<usize>,
+pub struct ArrayDescription {
+    pub t: Rc<Type>,
+    /// The size as written, `None` for parameters taking arrays of any size.
+    /// The checker replaces it with `length`.
+    pub size: Option<Rc<Expression>>,
+    /// Evaluated from `size` by `consteval::array_length`, `None` until checked
+    pub length: Option<usize>,
 }
 
+/// `enum first, second, ... end`, values are variant indices starting from 0
 #[derive(Debug, Hash, PartialEq, Eq)]
-enum Type {
+pub struct EnumDescription {
+    pub variants: Vec<Identifier>,
+}
+
+impl EnumDescription {
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<i64> {
+        let index = self
+            .variants
+            .iter()
+            .position(|variant| variant.name == name)?;
+        i64::try_from(index).ok()
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub enum Type {
     Int,
     Real,
     Bool,
+    /// Immutable, stored on the heap like records and arrays
+    String,
     Alias(Identifier),
     Record(RecordDeclaration),
     Array(ArrayDescription),
+    Enum(EnumDescription),
+}
+
+/// As it is written in the source, `array [3] integer`
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => f.write_str("integer"),
+            Type::Real => f.write_str("real"),
+            Type::Bool => f.write_str("boolean"),
+            Type::String => f.write_str("string"),
+            Type::Alias(name) => write!(f, "{name}"),
+            Type::Record(record) => {
+                f.write_str("record")?;
+                for field in &record.fields {
+                    write!(f, " var {} : {};", field.name, field.t)?;
+                }
+                f.write_str(" end")
+            }
+            Type::Array(array) => {
+                let length = array.length.or_else(|| {
+                    let size = array.size.as_deref()?;
+                    consteval::array_length(size).ok()
+                });
+                match length {
+                    Some(length) => write!(f, "array [{length}] {}", array.t),
+                    None => write!(f, "array [] {}", array.t),
+                }
+            }
+            Type::Enum(description) => {
+                f.write_str("enum ")?;
+                for (i, variant) in description.variants.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{variant}")?;
+                }
+                f.write_str(" end")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TypeInferenceError {
+    /// Where the statement or the declaration with the error starts
+    pub position: Position,
+    pub reason: String,
+}
+
+impl fmt::Display for TypeInferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.reason)
+    }
+}
+
+impl Error for TypeInferenceError {}
+
+/// Why a module is rejected, in the order the errors were found.
+/// Flow errors of a routine do not stop the checking of the next ones.
+#[derive(Debug)]
+pub struct TypeErrors(pub Vec<TypeInferenceError>);
+
+impl fmt::Display for TypeErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, error) in self.0.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
 }
 
-fn is_primitive(t: &Type) -> bool {
-    match &t {
-        Type::Int | Type::Real | Type::Bool => true,
-        Type::Alias(_) | Type::Record(_) | Type::Array(_) => false,
+impl Error for TypeErrors {}
+
+/// How `type name is t;` relates `name` to `t`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AliasSemantics {
+    /// Every alias is a distinct type, values change it only through explicit conversions
+    Nominal,
+    /// Aliases are other names of their types, as `tests/src/type_aliases.i` expects
+    #[default]
+    Structural,
+}
+
+impl FromStr for AliasSemantics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nominal" => Ok(AliasSemantics::Nominal),
+            "structural" => Ok(AliasSemantics::Structural),
+            _ => Err(format!("Unknown alias semantics {s:?}")),
+        }
+    }
+}
+
+/// Type declarations visible in a module, by name
+#[derive(Debug, Default)]
+pub struct Aliases(HashMap<String, Rc<Type>>);
+
+impl Aliases {
+    pub fn declare(&mut self, name: &Identifier, t: Rc<Type>) -> Result<(), String> {
+        if self.0.insert(name.to_string(), t).is_some() {
+            return Err(format!("Type `{name}` is declared more than once"));
+        }
+        Ok(())
+    }
+
+    /// The declared enum with `variant`, variants are unique across enums
+    #[must_use]
+    pub fn enum_of(&self, variant: &str) -> Option<&EnumDescription> {
+        self.0.values().find_map(|t| match &**t {
+            Type::Enum(description)
+                if description
+                    .variants
+                    .iter()
+                    .any(|known| known.name == variant) =>
+            {
+                Some(description)
+            }
+            Type::Int
+            | Type::Real
+            | Type::Bool
+            | Type::String
+            | Type::Alias(_)
+            | Type::Record(_)
+            | Type::Array(_)
+            | Type::Enum(_) => None,
+        })
+    }
+
+    /// Follows aliases until a type which is not one
+    pub fn resolve(&self, t: &Rc<Type>) -> Result<Rc<Type>, String> {
+        let mut t = Rc::clone(t);
+        let mut seen = HashSet::new();
+        while let Type::Alias(name) = &*t {
+            let name = name.to_string();
+            let Some(aliased) = self.0.get(&name) else {
+                return Err(format!("Unknown type `{name}`"));
+            };
+            if !seen.insert(name) {
+                return Err(format!("Type `{t}` is defined through itself"));
+            }
+            t = Rc::clone(aliased);
+        }
+        Ok(t)
+    }
+
+    /// Whether a value of type `from` can be assigned or passed where `to` is expected as is
+    pub fn assignable(
+        &self,
+        from: &Rc<Type>,
+        to: &Rc<Type>,
+        semantics: AliasSemantics,
+    ) -> Result<bool, String> {
+        match semantics {
+            AliasSemantics::Nominal => Ok(from == to),
+            AliasSemantics::Structural => Ok(self.resolve(from)? == self.resolve(to)?),
+        }
+    }
+
+    /// Whether `to(value)` is allowed for a value of type `from`: between an alias and its type
+    /// (or another alias of it), between numbers and booleans, and from enums to integers
+    pub fn convertible(&self, from: &Rc<Type>, to: &Rc<Type>) -> Result<bool, String> {
+        let from = self.resolve(from)?;
+        let to = self.resolve(to)?;
+        Ok(from == to
+            || matches!(
+                (&*from, &*to),
+                (Type::Int, Type::Real | Type::Bool)
+                    | (Type::Real | Type::Bool | Type::Enum(_), Type::Int)
+            ))
     }
 }
 
-struct TypeInferenceError {
-    reason: String,
+/// Variable of a checked module, the `Identifier::id` of its name and uses
+/// is its index in `TypedProgram::variables`
+#[derive(Debug)]
+pub struct Variable {
+    pub name: String,
+    /// Declared or inferred from the initializer
+    pub t: Rc<Type>,
+    pub location: Location,
 }
 
-fn infer(expr: &Expression) -> Result<Rc<Type>, TypeInferenceError> {
-    match &expr {
-        Expression::IntegerLiteral(_) => Ok(Rc::new(Type::Int)),
-        Expression::RealLiteral(_) => Ok(Rc::new(Type::Real)),
-        Expression::BoolLiteral(_) => Ok(Rc::new(Type::Bool)),
-        Expression::Call { .. } => todo!("No context lookup yet"),
-        Expression::LvalueToRvalue(..) => todo!("No context lookup yet"),
-        Expression::Binop { .. } => todo!("Tricky type conversions"),
-        Expression::BoolToInt(inner) => ensure(inner, &Type::Bool).map(|()| Rc::new(Type::Int)),
-        Expression::RealToInt(inner) => ensure(inner, &Type::Real).map(|()| Rc::new(Type::Int)),
-        Expression::IntToBool(inner) => ensure(inner, &Type::Int).map(|()| Rc::new(Type::Bool)),
+/// Routine of a checked module, the `Identifier::id` of its name and callees
+/// is its index in `TypedProgram::routines`. Built-ins have no id.
+#[derive(Debug)]
+pub struct Routine {
+    pub name: String,
+    /// The imported module defining it, `None` for the routines of this one
+    pub module: Option<String>,
+    pub parameters: Vec<Rc<Type>>,
+    /// `None` for procedures
+    pub result: Option<Rc<Type>>,
+    /// The label of its entry, or a host function for the ones declared without a body,
+    /// numbered after the built-ins
+    pub code: FunctionCode,
+    /// Locals of its body, the hidden ones of `for` loops included
+    pub locals: usize,
+}
+
+/// What `TypedProgram::type_id` looks types up by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TypeKey {
+    /// Records and enums are told apart by their declarations
+    Declared(*const Type),
+    /// Arrays by the type of their elements
+    Array(TypeId),
+}
+
+/// Program with every `Identifier::id` resolved and implicit conversions made explicit.
+///
+/// Variables declared without a type get the inferred one, routines declared with `=>`
+/// and without a result type get the type of their expression, array types get their `length`
+/// and `case` labels are the integer values of the variants.
+/// Each `for` loop has two hidden locals right after its variable: the upper bound of a range,
+/// or the array and the index of the element.
+#[derive(Debug)]
+pub struct TypedProgram {
+    pub program: Program,
+    /// Of the module
+    pub name: String,
+    /// The globals of imported modules it uses are numbered after its own
+    pub variables: Vec<Variable>,
+    /// The routines of imported modules are called through the labels after its own
+    pub routines: Vec<Routine>,
+    /// Types and globals of imported modules, see `bytecode::Module::imports`
+    pub imports: Vec<Symbol>,
+    /// Type names are unique in a module, the local ones included.
+    /// The ones of imported modules are qualified, `module.name`.
+    pub aliases: Aliases,
+    /// Types of every variable, parameter and result, see `type_id`
+    pub rtti: RTTI,
+    /// String literals, see `string_id`
+    pub strings: Vec<String>,
+    pub global_count: u32,
+    /// Labels `0..label_count` are the entries of the routines
+    pub label_count: u64,
+    /// Of the routines, like unreachable code, the program runs anyway
+    pub warnings: Vec<TypeInferenceError>,
+    type_ids: HashMap<TypeKey, TypeId>,
+    /// Keeps the types of `TypeKey::Declared` alive, so that their addresses are not reused
+    #[expect(dead_code, reason = "Owns the types only")]
+    declared: Vec<Rc<Type>>,
+}
+
+impl TypedProgram {
+    /// Follows aliases
+    ///
+    /// # Panics
+    ///
+    /// If `t` was not checked
+    #[must_use]
+    pub fn resolve(&self, t: &Rc<Type>) -> Rc<Type> {
+        self.aliases.resolve(t).expect("Checked")
+    }
+
+    /// # Panics
+    ///
+    /// If `t` is not a type of the checked program
+    #[must_use]
+    pub fn type_id(&self, t: &Rc<Type>) -> TypeId {
+        let t = self.resolve(t);
+        let key = match &*t {
+            Type::Int => return TypeId::INTEGER,
+            Type::Real => return TypeId::REAL,
+            Type::Bool => return TypeId::BOOLEAN,
+            Type::String => return TypeId::STRING,
+            Type::Array(array) => TypeKey::Array(self.type_id(&array.t)),
+            Type::Record(_) | Type::Enum(_) | Type::Alias(_) => TypeKey::Declared(Rc::as_ptr(&t)),
+        };
+        *self.type_ids.get(&key).expect("Every type is registered")
+    }
+
+    /// Index of a literal in `strings`
+    ///
+    /// # Panics
+    ///
+    /// If the literal is not in the checked program
+    #[must_use]
+    pub fn string_id(&self, value: &str) -> u32 {
+        let id = self
+            .strings
+            .iter()
+            .position(|string| string == value)
+            .expect("Every literal is collected");
+        u32::try_from(id).expect("No one has 4 billion literals")
+    }
+
+    /// Type of a checked expression, aliases are kept
+    ///
+    /// # Panics
+    ///
+    /// If the expression was not checked
+    #[must_use]
+    pub fn type_of(&self, expression: &Expression) -> Rc<Type> {
+        match expression {
+            Expression::LvalueToRvalue(lvalue) => self.lvalue_type(lvalue),
+            Expression::IntegerLiteral(_) | Expression::BoolToInt(_) | Expression::RealToInt(_) => {
+                Rc::new(Type::Int)
+            }
+            Expression::RealLiteral(_) => Rc::new(Type::Real),
+            Expression::BoolLiteral(_) | Expression::IntToBool(_) => Rc::new(Type::Bool),
+            Expression::StringLiteral(_) => Rc::new(Type::String),
+            Expression::Call { callee, args } => {
+                if let Some(id) = callee.id {
+                    return Rc::clone(
+                        self.routines[id]
+                            .result
+                            .as_ref()
+                            .expect("Procedures are not called for a value"),
+                    );
+                }
+                let args = args.iter().map(|arg| self.type_of(arg)).collect::<Vec<_>>();
+                Builtin::lookup(&callee.name)
+                    .and_then(|builtin| builtin.check_call(&args).ok().flatten())
+                    .expect("Built-ins are checked")
+            }
+            Expression::Binop { op, lhs, .. } => match op {
+                BinaryOperator::And
+                | BinaryOperator::Or
+                | BinaryOperator::Xor
+                | BinaryOperator::Le
+                | BinaryOperator::Lg
+                | BinaryOperator::Gt
+                | BinaryOperator::Ge
+                | BinaryOperator::Eq
+                | BinaryOperator::Neq => Rc::new(Type::Bool),
+                BinaryOperator::Mul
+                | BinaryOperator::Div
+                | BinaryOperator::Mod
+                | BinaryOperator::Add
+                | BinaryOperator::Sub => self.resolve(&self.type_of(lhs)),
+            },
+            Expression::Unop { op, value } => match op {
+                UnaryOperator::Neg => self.resolve(&self.type_of(value)),
+                UnaryOperator::Not => Rc::new(Type::Bool),
+            },
+            Expression::Conversion { target, .. } => Rc::clone(target),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If the lvalue was not checked
+    #[must_use]
+    pub fn lvalue_type(&self, lvalue: &LvalueExpression) -> Rc<Type> {
+        match lvalue {
+            LvalueExpression::Identifier(name) => {
+                Rc::clone(&self.variables[name.id.expect("Resolved")].t)
+            }
+            LvalueExpression::Member { lhs, member_name } => {
+                let Some(field) = member_name.id else {
+                    // `length` of an array
+                    return Rc::new(Type::Int);
+                };
+                match &*self.resolve(&self.lvalue_type(lhs)) {
+                    Type::Record(record) => Rc::clone(&record.fields[field].t),
+                    Type::Int
+                    | Type::Real
+                    | Type::Bool
+                    | Type::String
+                    | Type::Alias(_)
+                    | Type::Array(_)
+                    | Type::Enum(_) => unreachable!("Fields belong to records"),
+                }
+            }
+            LvalueExpression::Index { lhs, .. } => match &*self.resolve(&self.lvalue_type(lhs)) {
+                Type::Array(array) => Rc::clone(&array.t),
+                Type::Int
+                | Type::Real
+                | Type::Bool
+                | Type::String
+                | Type::Alias(_)
+                | Type::Record(_)
+                | Type::Enum(_) => unreachable!("Strings are indexed by `char_at` calls"),
+            },
+        }
     }
 }
 
-fn ensure(expr: &Expression, t: &Type) -> Result<(), TypeInferenceError> {
-    todo!("Check if {expr:?} : {t:?}")
+/// Resolves names and infers types of a module, see `TypedProgram`
+pub fn check(program: Program, aliases: AliasSemantics) -> Result<TypedProgram, TypeErrors> {
+    check_module(program, "program", &[], aliases)
 }
 
-fn convert(expr: &Expression, source_type: &Type, dest_type: &Type) -> Rc<Expression> {
-    todo!("Convert {expr:?} from {source_type:?} to {dest_type:?}")
+/// `check` of the module `name`, which may import the `imported` ones
+pub fn check_module(
+    program: Program,
+    name: &str,
+    imported: &[TypedProgram],
+    aliases: AliasSemantics,
+) -> Result<TypedProgram, TypeErrors> {
+    checker::check(program, name, imported, aliases)
+}
+
+/// Values of each branch's labels in a `case` on a value of type `t` (aliases resolved).
+/// Labels must be distinct, a `case` on an enum must handle every variant unless it has `else`.
+pub fn case_labels(
+    t: &Type,
+    branches: &[CaseBranch],
+    has_otherwise: bool,
+) -> Result<Vec<Vec<i64>>, String> {
+    let error = |reason: String| Err(reason);
+    let mut seen = HashSet::new();
+    let mut values = Vec::with_capacity(branches.len());
+    for branch in branches {
+        let mut branch_values = Vec::with_capacity(branch.labels.len());
+        for label in &branch.labels {
+            let value = match (t, label) {
+                (Type::Int, CaseLabel::Integer(literal)) => literal.value(),
+                (Type::Enum(description), CaseLabel::Variant(name)) => {
+                    match description.value(&name.name) {
+                        Some(value) if name.module.is_none() => value,
+                        _ => return error(format!("`{name}` is not a variant of the enum")),
+                    }
+                }
+                (Type::Int, CaseLabel::Variant(name)) => {
+                    return error(format!("Variant `{name}` cannot label an integer case"));
+                }
+                (Type::Enum(_), CaseLabel::Integer(literal)) => {
+                    return error(format!(
+                        "Integer {} cannot label an enum case",
+                        literal.value()
+                    ));
+                }
+                (
+                    Type::Real
+                    | Type::Bool
+                    | Type::String
+                    | Type::Alias(_)
+                    | Type::Record(_)
+                    | Type::Array(_),
+                    _,
+                ) => return error(format!("Cannot use `case` on `{t}`")),
+            };
+            if !seen.insert(value) {
+                return error(format!(
+                    "Label {} is used more than once",
+                    label_text(label)
+                ));
+            }
+            branch_values.push(value);
+        }
+        values.push(branch_values);
+    }
+
+    if let Type::Enum(description) = t
+        && !has_otherwise
+    {
+        let missing = description
+            .variants
+            .iter()
+            .filter(|variant| {
+                description
+                    .value(&variant.name)
+                    .is_some_and(|v| !seen.contains(&v))
+            })
+            .map(|variant| format!("`{}`", variant.name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return error(format!("Variants {} are not handled", missing.join(", ")));
+        }
+    }
+    Ok(values)
+}
+
+fn label_text(label: &CaseLabel) -> String {
+    match label {
+        CaseLabel::Integer(literal) => literal.value().to_string(),
+        CaseLabel::Variant(name) => format!("`{name}`"),
+    }
 }
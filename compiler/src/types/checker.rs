@@ -0,0 +1,1443 @@
+//! Name resolution and type checking, see `super::check`
+
+use core::mem;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::{
+    AliasSemantics, Aliases, ArrayDescription, EnumDescription, FieldDescription,
+    RecordDeclaration, Routine, Type, TypeErrors, TypeInferenceError, TypeKey, TypedProgram,
+    Variable, case_labels,
+};
+use crate::ast::{
+    BinaryOperator, Block, BlockElement, CaseBranch, CaseLabel, Declaration, Expression,
+    Identifier, ImportDeclaration, IntegerLiteral, LvalueExpression, Parameter, Program,
+    RoutineBody, RoutineDeclaration, SimpleDeclaration, Statement, TypeDeclaration, UnaryOperator,
+    VariableDeclaration,
+};
+use crate::builtins::Builtin;
+use crate::bytecode::{
+    ArrayRTTI, EnumRTTI, FunctionCode, Location, PrimitiveRTTI, RTTI, RTTIElement, RecordRTTI,
+    Symbol, TypeId,
+};
+use crate::consteval;
+use crate::flow;
+use crate::tokens::Position;
+
+fn rc<T>(value: T) -> Rc<T> {
+    Rc::new(value)
+}
+
+/// `Alias(name)` of a type declared in `module` as `Alias(module.name)`, for the modules importing it
+fn qualify(t: &Rc<Type>, module: &str) -> Rc<Type> {
+    match &**t {
+        Type::Alias(name) if name.module.is_none() => rc(Type::Alias(Identifier {
+            name: name.name.clone(),
+            module: Some(module.to_owned()),
+            id: None,
+        })),
+        Type::Record(RecordDeclaration { fields }) => rc(Type::Record(RecordDeclaration {
+            fields: fields
+                .iter()
+                .map(|field| FieldDescription {
+                    name: field.name.clone(),
+                    t: qualify(&field.t, module),
+                })
+                .collect(),
+        })),
+        Type::Array(ArrayDescription {
+            t: element,
+            size,
+            length,
+        }) => rc(Type::Array(ArrayDescription {
+            t: qualify(element, module),
+            size: size.clone(),
+            length: *length,
+        })),
+        Type::Int | Type::Real | Type::Bool | Type::String | Type::Alias(_) | Type::Enum(_) => {
+            Rc::clone(t)
+        }
+    }
+}
+
+pub(super) fn check(
+    program: Program,
+    name: &str,
+    imported: &[TypedProgram],
+    semantics: AliasSemantics,
+) -> Result<TypedProgram, TypeErrors> {
+    let mut checker = Checker {
+        semantics,
+        aliases: Aliases::default(),
+        variants: HashMap::new(),
+        variables: Vec::new(),
+        scope: Vec::new(),
+        modules: HashSet::new(),
+        globals: HashMap::new(),
+        routines: Vec::new(),
+        routine_ids: HashMap::new(),
+        inferred: HashSet::new(),
+        rtti: RTTI::primitives().0,
+        type_ids: HashMap::new(),
+        declared: Vec::new(),
+        strings: Vec::new(),
+        global_count: 0,
+        routine: None,
+        position: Position::begin(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let declarations = checker.declarations(program, name, imported);
+    let mut errors = mem::take(&mut checker.errors);
+    let (resolved, label_count) = match declarations {
+        Ok(declarations) if errors.is_empty() => declarations,
+        Ok(_) => return Err(TypeErrors(errors)),
+        Err(error) => {
+            errors.push(error);
+            return Err(TypeErrors(errors));
+        }
+    };
+
+    let imports = checker.imports();
+    let Checker {
+        aliases,
+        variables,
+        routines,
+        rtti,
+        type_ids,
+        declared,
+        strings,
+        global_count,
+        warnings,
+        ..
+    } = checker;
+    Ok(TypedProgram {
+        program: Program {
+            declarations: resolved,
+        },
+        name: name.to_owned(),
+        variables,
+        routines,
+        imports,
+        aliases,
+        rtti: RTTI(rtti),
+        strings,
+        global_count,
+        label_count,
+        warnings,
+        type_ids,
+        declared,
+    })
+}
+
+/// State of the routine being checked
+#[derive(Debug)]
+struct RoutineScope {
+    result: Option<Rc<Type>>,
+    locals: usize,
+}
+
+#[derive(Debug)]
+struct Checker {
+    semantics: AliasSemantics,
+    aliases: Aliases,
+    /// Enum variants by name, with their enum and value
+    variants: HashMap<String, (Rc<Type>, i64)>,
+    variables: Vec<Variable>,
+    /// Names of the variables in scope with their ids, innermost last
+    scope: Vec<(String, usize)>,
+    /// Names of the imported modules
+    modules: HashSet<String>,
+    /// Ids of the globals of imported modules, by their qualified names
+    globals: HashMap<String, usize>,
+    routines: Vec<Routine>,
+    /// By the names they are called with, qualified for the ones of imported modules
+    routine_ids: HashMap<String, usize>,
+    /// Routines declared with `=>` and no result type whose body is not checked yet
+    inferred: HashSet<usize>,
+    rtti: Vec<RTTIElement>,
+    type_ids: HashMap<TypeKey, TypeId>,
+    declared: Vec<Rc<Type>>,
+    strings: Vec<String>,
+    global_count: u32,
+    routine: Option<RoutineScope>,
+    /// Of the statement or the declaration being checked, errors are reported there
+    position: Position,
+    /// Flow errors and warnings of the routines checked so far, see `flow::check_routine`
+    errors: Vec<TypeInferenceError>,
+    warnings: Vec<TypeInferenceError>,
+}
+
+impl Checker {
+    /// Checks the declarations of the module `name` in order, returns them resolved
+    /// with the number of labels of its routines
+    fn declarations(
+        &mut self,
+        program: Program,
+        name: &str,
+        imported: &[TypedProgram],
+    ) -> Result<(Vec<Declaration>, u64), TypeInferenceError> {
+        let Program { declarations } = program;
+
+        for declaration in &declarations {
+            if let Declaration::Import(ImportDeclaration { position, module }) = declaration {
+                self.position = *position;
+                let Some(module) = imported.iter().find(|known| known.name == module.name) else {
+                    return self.error(format!("Module `{module}` is not checked before `{name}`"));
+                };
+                self.import(module)?;
+            }
+        }
+
+        // Types and routines may be used before they are declared, variables may not
+        let mut types = Vec::new();
+        for declaration in &declarations {
+            if let Declaration::Simple(SimpleDeclaration::Type(declaration)) = declaration {
+                types.push((declaration.position, self.declare_type(declaration)?));
+            }
+        }
+        for (position, t) in &types {
+            self.position = *position;
+            let _: TypeId = self.register(t)?;
+        }
+        for declaration in &declarations {
+            if let Declaration::Routine(routine) = declaration {
+                self.declare_routine(routine)?;
+            }
+        }
+        let mut label_count = 0;
+        for routine in &mut self.routines {
+            if let FunctionCode::Label(label) = &mut routine.code {
+                *label = label_count;
+                label_count += 1;
+            }
+        }
+
+        let mut types = types.into_iter().map(|(_, t)| t);
+        let mut resolved = Vec::with_capacity(declarations.len());
+        for declaration in declarations {
+            resolved.push(match declaration {
+                Declaration::Import(import) => Declaration::Import(import),
+                Declaration::Simple(SimpleDeclaration::Type(declaration)) => {
+                    Declaration::Simple(SimpleDeclaration::Type(TypeDeclaration {
+                        t: types.next().expect("Declared above"),
+                        ..declaration
+                    }))
+                }
+                Declaration::Simple(SimpleDeclaration::Variable(variable)) => {
+                    Declaration::Simple(SimpleDeclaration::Variable(self.variable(variable, true)?))
+                }
+                Declaration::Routine(routine) => Declaration::Routine(self.routine(routine)?),
+            });
+        }
+        Ok((resolved, label_count))
+    }
+
+    fn at(&self, reason: String) -> TypeInferenceError {
+        TypeInferenceError {
+            position: self.position,
+            reason,
+        }
+    }
+
+    fn error<T>(&self, reason: String) -> Result<T, TypeInferenceError> {
+        Err(self.at(reason))
+    }
+
+    fn resolve(&self, t: &Rc<Type>) -> Result<Rc<Type>, TypeInferenceError> {
+        self.aliases.resolve(t).map_err(|reason| self.at(reason))
+    }
+
+    /// Makes the top-level types, routines and globals of `module` available
+    /// by their qualified names, `module.name`
+    fn import(&mut self, module: &TypedProgram) -> Result<(), TypeInferenceError> {
+        let qualified = |name: &Identifier| Identifier {
+            name: name.name.clone(),
+            module: Some(module.name.clone()),
+            id: None,
+        };
+        if !self.modules.insert(module.name.clone()) {
+            return self.error(format!(
+                "Module `{}` is imported more than once",
+                module.name
+            ));
+        }
+        // The types of its imports its own types are made of
+        #[expect(
+            clippy::iter_over_hash_type,
+            reason = "Names are inserted in any order"
+        )]
+        for (name, t) in &module.aliases.0 {
+            if name.contains('.') && !self.aliases.0.contains_key(name) {
+                drop(self.aliases.0.insert(name.clone(), Rc::clone(t)));
+            }
+        }
+        for declaration in &module.program.declarations {
+            match declaration {
+                Declaration::Simple(SimpleDeclaration::Type(TypeDeclaration {
+                    name, t, ..
+                })) => {
+                    let t = qualify(t, &module.name);
+                    if let Type::Enum(description) = &*t {
+                        for (value, variant) in (0..).zip(&description.variants) {
+                            drop(
+                                self.variants
+                                    .insert(qualified(variant).to_string(), (Rc::clone(&t), value)),
+                            );
+                        }
+                    }
+                    self.aliases
+                        .declare(&qualified(name), t)
+                        .map_err(|reason| self.at(reason))?;
+                }
+                Declaration::Simple(SimpleDeclaration::Variable(VariableDeclaration {
+                    name,
+                    ..
+                })) => {
+                    let variable = &module.variables[name.id.expect("Checked")];
+                    self.global_count += 1;
+                    let _: Option<usize> = self
+                        .globals
+                        .insert(qualified(name).to_string(), self.variables.len());
+                    self.variables.push(Variable {
+                        name: qualified(name).to_string(),
+                        t: qualify(&variable.t, &module.name),
+                        location: Location::Global(self.global_count as usize - 1),
+                    });
+                }
+                Declaration::Routine(RoutineDeclaration {
+                    name,
+                    body: Some(_),
+                    ..
+                }) => {
+                    let routine = &module.routines[name.id.expect("Checked")];
+                    let _: Option<usize> = self
+                        .routine_ids
+                        .insert(qualified(name).to_string(), self.routines.len());
+                    self.routines.push(Routine {
+                        name: routine.name.clone(),
+                        module: Some(module.name.clone()),
+                        parameters: routine
+                            .parameters
+                            .iter()
+                            .map(|t| qualify(t, &module.name))
+                            .collect(),
+                        result: routine.result.as_ref().map(|t| qualify(t, &module.name)),
+                        // Labels are given once every routine is known
+                        code: FunctionCode::Label(0),
+                        locals: 0,
+                    });
+                }
+                // Host routines are provided to the program importing them
+                Declaration::Routine(RoutineDeclaration { body: None, .. })
+                | Declaration::Import(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Types and globals of the imported modules the module uses, see `bytecode::Module::imports`
+    fn imports(&self) -> Vec<Symbol> {
+        let mut imports: Vec<_> = self
+            .aliases
+            .0
+            .iter()
+            .filter(|(name, _)| name.contains('.'))
+            .filter_map(|(name, t)| {
+                let t = self.resolve(t).ok()?;
+                let type_id = self.type_ids.get(&TypeKey::Declared(Rc::as_ptr(&t)))?;
+                Some(Symbol::Type {
+                    name: name.clone(),
+                    type_id: *type_id,
+                })
+            })
+            .chain(self.globals.iter().map(|(name, &id)| {
+                let Location::Global(index) = self.variables[id].location else {
+                    unreachable!("Imported variables are globals")
+                };
+                Symbol::Global {
+                    name: name.clone(),
+                    index: u32::try_from(index).expect("No one has 4 billion globals"),
+                }
+            }))
+            .collect();
+        imports.sort_by(|a, b| a.name().cmp(b.name()));
+        imports
+    }
+
+    fn declare_type(
+        &mut self,
+        declaration: &TypeDeclaration,
+    ) -> Result<Rc<Type>, TypeInferenceError> {
+        self.position = declaration.position;
+        let t = self.rebuild(&declaration.t)?;
+        self.aliases
+            .declare(&declaration.name, Rc::clone(&t))
+            .map_err(|reason| self.at(reason))?;
+        Ok(t)
+    }
+
+    /// `t` with array lengths evaluated, the variants of its enums are declared
+    fn rebuild(&mut self, t: &Rc<Type>) -> Result<Rc<Type>, TypeInferenceError> {
+        match &**t {
+            Type::Int | Type::Real | Type::Bool | Type::String | Type::Alias(_) => Ok(Rc::clone(t)),
+            Type::Enum(EnumDescription { variants }) => {
+                for (value, variant) in (0..).zip(variants) {
+                    let known = self
+                        .variants
+                        .insert(variant.name.clone(), (Rc::clone(t), value));
+                    match known {
+                        Some((known, _)) if Rc::ptr_eq(&known, t) => {
+                            return self
+                                .error(format!("Variant `{variant}` is declared more than once"));
+                        }
+                        Some(_) => {
+                            return self.error(format!(
+                                "Variant `{variant}` is declared by more than one enum"
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+                Ok(Rc::clone(t))
+            }
+            Type::Record(RecordDeclaration { fields }) => {
+                let mut rebuilt = Vec::<FieldDescription>::with_capacity(fields.len());
+                for field in fields {
+                    if rebuilt
+                        .iter()
+                        .any(|known| known.name.name == field.name.name)
+                    {
+                        return self
+                            .error(format!("Field `{}` is declared more than once", field.name));
+                    }
+                    rebuilt.push(FieldDescription {
+                        name: field.name.clone(),
+                        t: self.rebuild(&field.t)?,
+                    });
+                }
+                Ok(rc(Type::Record(RecordDeclaration { fields: rebuilt })))
+            }
+            Type::Array(ArrayDescription {
+                t: element, size, ..
+            }) => {
+                let length = match size {
+                    Some(size) => {
+                        Some(consteval::array_length(size).map_err(|reason| self.at(reason))?)
+                    }
+                    None => None,
+                };
+                Ok(rc(Type::Array(ArrayDescription {
+                    t: self.rebuild(element)?,
+                    size: size.clone(),
+                    length,
+                })))
+            }
+        }
+    }
+
+    /// Gives `t` and the types it is made of a `TypeId`
+    fn register(&mut self, t: &Rc<Type>) -> Result<TypeId, TypeInferenceError> {
+        let t = self.resolve(t)?;
+        let key = match &*t {
+            Type::Int => return Ok(TypeId::INTEGER),
+            Type::Real => return Ok(TypeId::REAL),
+            Type::Bool => return Ok(TypeId::BOOLEAN),
+            Type::String => return Ok(TypeId::STRING),
+            Type::Array(array) => TypeKey::Array(self.register(&array.t)?),
+            Type::Record(_) | Type::Enum(_) | Type::Alias(_) => TypeKey::Declared(Rc::as_ptr(&t)),
+        };
+        if let Some(&id) = self.type_ids.get(&key) {
+            return Ok(id);
+        }
+
+        let id = TypeId(u32::try_from(self.rtti.len()).expect("No one has 4 billion types"));
+        // Recursive records refer to themselves, so the id is known before the fields
+        let _: Option<TypeId> = self.type_ids.insert(key, id);
+        self.rtti.push(RTTIElement::Primitive(PrimitiveRTTI { id }));
+        let element = match &*t {
+            Type::Array(array) => RTTIElement::Array(ArrayRTTI {
+                id,
+                element_id: self.register(&array.t)?,
+            }),
+            Type::Enum(description) => RTTIElement::Enum(EnumRTTI {
+                id,
+                variants: description
+                    .variants
+                    .iter()
+                    .map(|variant| variant.name.clone())
+                    .collect(),
+            }),
+            Type::Record(record) => RTTIElement::Record(RecordRTTI {
+                id,
+                field_ids: record
+                    .fields
+                    .iter()
+                    .map(|field| self.register(&field.t))
+                    .collect::<Result<_, _>>()?,
+            }),
+            Type::Int | Type::Real | Type::Bool | Type::String | Type::Alias(_) => {
+                unreachable!("Returned above")
+            }
+        };
+        self.rtti[id.0 as usize] = element;
+        if matches!(key, TypeKey::Declared(_)) {
+            self.declared.push(t);
+        }
+        Ok(id)
+    }
+
+    /// A type written in a declaration
+    fn declared_type(&mut self, t: &Rc<Type>) -> Result<Rc<Type>, TypeInferenceError> {
+        let t = self.rebuild(t)?;
+        let _: TypeId = self.register(&t)?;
+        Ok(t)
+    }
+
+    fn declare_routine(
+        &mut self,
+        declaration: &RoutineDeclaration,
+    ) -> Result<(), TypeInferenceError> {
+        let RoutineDeclaration {
+            position,
+            name,
+            parameters,
+            result,
+            body,
+            ..
+        } = declaration;
+        self.position = *position;
+        if name.module.is_some() {
+            return self.error(format!(
+                "Routine `{name}` of another module cannot be declared"
+            ));
+        }
+        let parameters = parameters
+            .iter()
+            .map(|parameter| self.declared_type(&parameter.t))
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = result
+            .as_ref()
+            .map(|result| self.declared_type(result))
+            .transpose()?;
+        // Labels are given once every routine is known
+        let code = match body {
+            Some(_) => FunctionCode::Label(0),
+            None => FunctionCode::Native(0),
+        };
+
+        let is_inferred = result.is_none() && matches!(body, Some(RoutineBody::Expression(_)));
+
+        let Some(&id) = self.routine_ids.get(&name.name) else {
+            if is_inferred {
+                let _: bool = self.inferred.insert(self.routines.len());
+            }
+            let _: Option<usize> = self
+                .routine_ids
+                .insert(name.name.clone(), self.routines.len());
+            self.routines.push(Routine {
+                name: name.name.clone(),
+                module: None,
+                parameters,
+                result,
+                code,
+                locals: 0,
+            });
+            return Ok(());
+        };
+
+        // A forward declaration and the definition
+        if is_inferred {
+            let _: bool = self.inferred.insert(id);
+        }
+        let known = &mut self.routines[id];
+        if known.parameters != parameters || known.result != result {
+            return self.error(format!(
+                "Declarations of routine `{name}` have different signatures"
+            ));
+        }
+        match (known.code, code) {
+            (FunctionCode::Label(_), FunctionCode::Label(_)) => {
+                self.error(format!("Routine `{name}` is defined more than once"))
+            }
+            (FunctionCode::Native(_), FunctionCode::Label(_)) => {
+                known.code = code;
+                Ok(())
+            }
+            (_, FunctionCode::Native(_)) => Ok(()),
+        }
+    }
+
+    /// Routines without a definition are provided by the embedding program, see `vm::host`
+    fn host_id(&self, routine: usize) -> u32 {
+        let hosts = self.routines[..routine]
+            .iter()
+            .filter(|routine| matches!(routine.code, FunctionCode::Native(_)))
+            .count();
+        u32::try_from(Builtin::ALL.len() + hosts).expect("No one has 4 billion routines")
+    }
+
+    fn declare_variable(&mut self, name: &Identifier, t: Rc<Type>) -> Identifier {
+        let location = if let Some(routine) = &mut self.routine {
+            routine.locals += 1;
+            Location::Local(routine.locals - 1)
+        } else {
+            self.global_count += 1;
+            Location::Global(self.global_count as usize - 1)
+        };
+        let id = self.variables.len();
+        self.variables.push(Variable {
+            name: name.name.clone(),
+            t,
+            location,
+        });
+        self.scope.push((name.name.clone(), id));
+        name.with_id(id)
+    }
+
+    fn variable(
+        &mut self,
+        variable: VariableDeclaration,
+        is_global: bool,
+    ) -> Result<VariableDeclaration, TypeInferenceError> {
+        let VariableDeclaration {
+            doc,
+            position,
+            name,
+            t,
+            initializer,
+        } = variable;
+        self.position = position;
+        if name.module.is_some() {
+            return self.error(format!(
+                "Variable `{name}` of another module cannot be declared"
+            ));
+        }
+        let (t, initializer) = match (t, initializer) {
+            (Some(t), Some(initializer)) => {
+                let t = self.declared_type(&t)?;
+                let (value, value_type) = self.expression(&initializer)?;
+                let value = self.coerce(value, &value_type, &t)?;
+                (t, Some(value))
+            }
+            (Some(t), None) => (self.declared_type(&t)?, None),
+            (None, Some(initializer)) => {
+                let (value, t) = self.expression(&initializer)?;
+                (t, Some(value))
+            }
+            (None, None) => unreachable!("The parser requires one of them"),
+        };
+        if initializer.is_none() {
+            match &*self.resolve(&t)? {
+                Type::Array(ArrayDescription { length: None, .. }) => {
+                    return self.error(format!("Variable `{name}` needs the size of its array"));
+                }
+                // The default value
+                Type::String => self.string(""),
+                Type::Int
+                | Type::Real
+                | Type::Bool
+                | Type::Alias(_)
+                | Type::Record(_)
+                | Type::Array(_)
+                | Type::Enum(_) => {}
+            }
+        }
+        debug_assert_eq!(is_global, self.routine.is_none());
+        let name = self.declare_variable(&name, Rc::clone(&t));
+        Ok(VariableDeclaration {
+            doc,
+            position,
+            name,
+            t: Some(t),
+            initializer,
+        })
+    }
+
+    fn string(&mut self, value: &str) {
+        if !self.strings.iter().any(|string| string == value) {
+            self.strings.push(value.to_owned());
+        }
+    }
+
+    fn routine(
+        &mut self,
+        mut declaration: RoutineDeclaration,
+    ) -> Result<RoutineDeclaration, TypeInferenceError> {
+        let doc = mem::take(&mut declaration.doc);
+        let position = declaration.position;
+        self.position = position;
+        let name = declaration.name.clone();
+        let id = self.routine_ids[&name.name];
+        let Some(body) = &declaration.body else {
+            let RoutineDeclaration {
+                parameters, result, ..
+            } = declaration;
+            let code = self.routines[id].code;
+            if let FunctionCode::Native(_) = code {
+                self.routines[id].code = FunctionCode::Native(self.host_id(id));
+            }
+            return Ok(RoutineDeclaration {
+                doc,
+                position,
+                name: name.with_id(id),
+                parameters,
+                result,
+                body: None,
+            });
+        };
+
+        let scope = self.scope.len();
+        self.routine = Some(RoutineScope {
+            result: self.routines[id].result.clone(),
+            locals: 0,
+        });
+        let mut checked_parameters = Vec::with_capacity(declaration.parameters.len());
+        for (index, Parameter { name, .. }) in declaration.parameters.iter().enumerate() {
+            if checked_parameters
+                .iter()
+                .any(|known: &Parameter| known.name.name == name.name)
+            {
+                return self.error(format!("Parameter `{name}` is declared more than once"));
+            }
+            let t = Rc::clone(&self.routines[id].parameters[index]);
+            let variable = self.variables.len();
+            self.variables.push(Variable {
+                name: name.name.clone(),
+                t: Rc::clone(&t),
+                location: Location::Argument(index),
+            });
+            self.scope.push((name.name.clone(), variable));
+            checked_parameters.push(Parameter {
+                name: name.clone().with_id(variable),
+                t,
+            });
+        }
+
+        let body = match body {
+            RoutineBody::Block(block) => {
+                flow::check_loop_exits(block)?;
+                RoutineBody::Block(self.block(block)?)
+            }
+            RoutineBody::Expression(value) => {
+                let (value, t) = self.expression(value)?;
+                let value = if let Some(result) = self.routines[id].result.clone() {
+                    self.coerce(value, &t, &result)?
+                } else {
+                    let _: TypeId = self.register(&t)?;
+                    let _: bool = self.inferred.remove(&id);
+                    self.routines[id].result = Some(t);
+                    value
+                };
+                RoutineBody::Expression(value)
+            }
+        };
+
+        self.scope.truncate(scope);
+        let routine = self.routine.take().expect("Set above");
+        self.routines[id].locals = routine.locals;
+        // Before checking, `case` labels are still variants, local types are declared by now
+        // Flow errors do not stop the checking of the next routines
+        let flow::Diagnostics { errors, warnings } =
+            flow::check_routine(&declaration, &self.aliases);
+        self.errors.extend(errors);
+        self.warnings.extend(warnings);
+        Ok(RoutineDeclaration {
+            doc,
+            position,
+            name: name.with_id(id),
+            parameters: checked_parameters,
+            result: self.routines[id].result.clone(),
+            body: Some(body),
+        })
+    }
+
+    fn block(&mut self, block: &Block) -> Result<Block, TypeInferenceError> {
+        let scope = self.scope.len();
+        let position = self.position;
+        let mut elements = Vec::with_capacity(block.elements().len());
+        for element in block.elements() {
+            match element {
+                BlockElement::Decl(declaration) => {
+                    let declaration = match &**declaration {
+                        SimpleDeclaration::Type(declaration) => {
+                            let t = self.declare_type(declaration)?;
+                            let _: TypeId = self.register(&t)?;
+                            SimpleDeclaration::Type(TypeDeclaration {
+                                doc: Default::default(),
+                                position: declaration.position,
+                                name: declaration.name.clone(),
+                                t,
+                            })
+                        }
+                        SimpleDeclaration::Variable(variable) => {
+                            let variable = VariableDeclaration {
+                                doc: Default::default(),
+                                position: variable.position,
+                                name: variable.name.clone(),
+                                t: variable.t.clone(),
+                                initializer: variable.initializer.clone(),
+                            };
+                            SimpleDeclaration::Variable(self.variable(variable, false)?)
+                        }
+                    };
+                    elements.push(BlockElement::Decl(rc(declaration)));
+                }
+                &BlockElement::Stmt(position, ref statement) => {
+                    self.position = position;
+                    let statement = self.statement(statement)?;
+                    elements.push(BlockElement::Stmt(position, rc(statement)));
+                }
+            }
+        }
+        self.scope.truncate(scope);
+        self.position = position;
+        Ok(Block::new(elements))
+    }
+
+    fn routine_scope(&self) -> &RoutineScope {
+        self.routine.as_ref().expect("Statements are in routines")
+    }
+
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn statement(&mut self, statement: &Statement) -> Result<Statement, TypeInferenceError> {
+        Ok(match statement {
+            Statement::Assignment { lhs, rhs } => {
+                let (lhs, t) = self.lvalue(lhs)?;
+                if let LvalueExpression::Member {
+                    member_name: Identifier { id: None, .. },
+                    ..
+                } = &*lhs
+                {
+                    return self.error("The length of an array cannot be assigned".to_owned());
+                }
+                let (rhs, rhs_type) = self.expression(rhs)?;
+                Statement::Assignment {
+                    rhs: self.coerce(rhs, &rhs_type, &t)?,
+                    lhs,
+                }
+            }
+            Statement::Call { call } => {
+                let Expression::Call { callee, args } = &**call else {
+                    unreachable!("The parser makes calls only")
+                };
+                let (call, _) = self.call(callee, args, true)?;
+                Statement::Call { call }
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => Statement::While {
+                label: label.clone(),
+                condition: self.condition(condition)?,
+                body: self.block(body)?,
+            },
+            Statement::For {
+                label,
+                identifier,
+                from,
+                to,
+                order,
+                body,
+            } => {
+                let (from, from_type) = self.expression(from)?;
+                let int = rc(Type::Int);
+                let (from, to, t) = match to {
+                    Some(to) => {
+                        let (to, to_type) = self.expression(to)?;
+                        (
+                            self.coerce(from, &from_type, &int)?,
+                            Some(self.coerce(to, &to_type, &int)?),
+                            int,
+                        )
+                    }
+                    None => match &*self.resolve(&from_type)? {
+                        Type::Array(array) => (from, None, Rc::clone(&array.t)),
+                        Type::Int
+                        | Type::Real
+                        | Type::Bool
+                        | Type::String
+                        | Type::Alias(_)
+                        | Type::Record(_)
+                        | Type::Enum(_) => {
+                            return self.error(format!(
+                                "Loop over `{from_type}` needs the end of its range"
+                            ));
+                        }
+                    },
+                };
+                let scope = self.scope.len();
+                let identifier = self.declare_variable(identifier, t);
+                let routine = self.routine.as_mut().expect("Loops are in routines");
+                routine.locals += 2;
+                let body = self.block(body)?;
+                self.scope.truncate(scope);
+                Statement::For {
+                    label: label.clone(),
+                    identifier,
+                    from,
+                    to,
+                    order: *order,
+                    body,
+                }
+            }
+            Statement::If {
+                condition,
+                on_true,
+                on_false,
+            } => Statement::If {
+                condition: self.condition(condition)?,
+                on_true: self.block(on_true)?,
+                on_false: on_false
+                    .as_ref()
+                    .map(|block| self.block(block))
+                    .transpose()?,
+            },
+            Statement::Print { value } => Statement::Print {
+                value: self.expression(value)?.0,
+            },
+            Statement::Case {
+                value,
+                branches,
+                otherwise,
+            } => {
+                let (value, t) = self.expression(value)?;
+                let values = case_labels(&*self.resolve(&t)?, branches, otherwise.is_some())
+                    .map_err(|reason| self.at(reason))?;
+                let mut checked = Vec::with_capacity(branches.len());
+                for (branch, values) in branches.iter().zip(values) {
+                    checked.push(CaseBranch {
+                        labels: values
+                            .into_iter()
+                            .map(|value| CaseLabel::Integer(IntegerLiteral::new(value)))
+                            .collect(),
+                        body: self.block(&branch.body)?,
+                    });
+                }
+                Statement::Case {
+                    value,
+                    branches: checked,
+                    otherwise: otherwise
+                        .as_ref()
+                        .map(|block| self.block(block))
+                        .transpose()?,
+                }
+            }
+            // `return` without a value in a function or with one in a procedure is reported
+            // by `flow::check_routine`, along with the other flow errors of the routine
+            Statement::Return { value } => match (value, self.routine_scope().result.clone()) {
+                (Some(value), Some(result)) => {
+                    let (value, t) = self.expression(value)?;
+                    Statement::Return {
+                        value: Some(self.coerce(value, &t, &result)?),
+                    }
+                }
+                (Some(value), None) => Statement::Return {
+                    value: Some(self.expression(value)?.0),
+                },
+                (None, None | Some(_)) => Statement::Return { value: None },
+            },
+            Statement::Break { label } => Statement::Break {
+                label: label.clone(),
+            },
+            Statement::Continue { label } => Statement::Continue {
+                label: label.clone(),
+            },
+        })
+    }
+
+    /// Of `if` and `while`
+    fn condition(&mut self, condition: &Expression) -> Result<Rc<Expression>, TypeInferenceError> {
+        let (condition, t) = self.expression(condition)?;
+        self.truth(condition, &t)
+    }
+
+    /// `value` as a boolean, integers are converted
+    fn truth(
+        &self,
+        value: Rc<Expression>,
+        t: &Rc<Type>,
+    ) -> Result<Rc<Expression>, TypeInferenceError> {
+        match &*self.resolve(t)? {
+            Type::Bool => Ok(value),
+            Type::Int => Ok(rc(Expression::IntToBool(value))),
+            Type::Real
+            | Type::String
+            | Type::Alias(_)
+            | Type::Record(_)
+            | Type::Array(_)
+            | Type::Enum(_) => self.error(format!("`{t}` is not a boolean")),
+        }
+    }
+
+    /// Whether a value of type `from` can be stored where `to` is expected as is:
+    /// arrays of any size go where the size is not given
+    fn assignable(&self, from: &Rc<Type>, to: &Rc<Type>) -> Result<bool, TypeInferenceError> {
+        if self
+            .aliases
+            .assignable(from, to, self.semantics)
+            .map_err(|reason| self.at(reason))?
+        {
+            return Ok(true);
+        }
+        match (&*self.resolve(from)?, &*self.resolve(to)?) {
+            (Type::Array(from), Type::Array(to)) => {
+                Ok(to.length.is_none_or(|length| from.length == Some(length))
+                    && self.assignable(&from.t, &to.t)?
+                    && self.assignable(&to.t, &from.t)?)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// `value` of type `from` where `to` is expected, converted implicitly between
+    /// integers, reals and booleans. Reals are truncated, integers other than 0 and 1
+    /// are a runtime error as booleans.
+    fn coerce(
+        &self,
+        value: Rc<Expression>,
+        from: &Rc<Type>,
+        to: &Rc<Type>,
+    ) -> Result<Rc<Expression>, TypeInferenceError> {
+        if self.assignable(from, to)? {
+            return Ok(value);
+        }
+        let is_alias = |t: &Type| matches!(t, Type::Alias(_));
+        let nominal = self.semantics == AliasSemantics::Nominal && (is_alias(from) || is_alias(to));
+        let real = || rc(Type::Real);
+        Ok(rc(match (&*self.resolve(from)?, &*self.resolve(to)?) {
+            (Type::Int, Type::Real) if !nominal => Expression::Conversion {
+                target: real(),
+                value,
+            },
+            (Type::Real, Type::Int) if !nominal => Expression::RealToInt(value),
+            (Type::Bool, Type::Int) if !nominal => Expression::BoolToInt(value),
+            (Type::Int, Type::Bool) if !nominal => Expression::IntToBool(value),
+            (Type::Bool, Type::Real) if !nominal => Expression::Conversion {
+                target: real(),
+                value: rc(Expression::BoolToInt(value)),
+            },
+            _ => return self.error(format!("Cannot use `{from}` as `{to}`")),
+        }))
+    }
+
+    /// The checked expression with its type
+    fn expression(
+        &mut self,
+        expression: &Expression,
+    ) -> Result<(Rc<Expression>, Rc<Type>), TypeInferenceError> {
+        let (expression, t) = match expression {
+            Expression::LvalueToRvalue(lvalue) => return self.rvalue(lvalue),
+            Expression::IntegerLiteral(literal) => (
+                Expression::IntegerLiteral(IntegerLiteral::new(literal.value())),
+                Type::Int,
+            ),
+            Expression::RealLiteral(literal) => (
+                Expression::RealLiteral(crate::ast::RealLiteral::new(literal.value())),
+                Type::Real,
+            ),
+            Expression::BoolLiteral(literal) => (Expression::BoolLiteral(*literal), Type::Bool),
+            Expression::StringLiteral(literal) => {
+                self.string(literal.value());
+                (
+                    Expression::StringLiteral(crate::ast::StringLiteral::new(
+                        literal.value().to_owned(),
+                    )),
+                    Type::String,
+                )
+            }
+            Expression::Call { callee, args } => {
+                let (call, t) = self.call(callee, args, false)?;
+                return Ok((call, t.expect("Only procedures have no result")));
+            }
+            Expression::Binop { op, lhs, rhs } => return self.binary(*op, lhs, rhs),
+            Expression::Unop { op, value } => {
+                let (value, t) = self.expression(value)?;
+                match op {
+                    UnaryOperator::Not => (
+                        Expression::Unop {
+                            op: *op,
+                            value: self.truth(value, &t)?,
+                        },
+                        Type::Bool,
+                    ),
+                    UnaryOperator::Neg => match &*self.resolve(&t)? {
+                        Type::Int | Type::Real => {
+                            let t = self.resolve(&t)?;
+                            return Ok((rc(Expression::Unop { op: *op, value }), t));
+                        }
+                        Type::Bool
+                        | Type::String
+                        | Type::Alias(_)
+                        | Type::Record(_)
+                        | Type::Array(_)
+                        | Type::Enum(_) => return self.error(format!("Cannot negate `{t}`")),
+                    },
+                }
+            }
+            Expression::BoolToInt(value) => (
+                Expression::BoolToInt(self.typed(value, &Type::Bool)?),
+                Type::Int,
+            ),
+            Expression::RealToInt(value) => (
+                Expression::RealToInt(self.typed(value, &Type::Real)?),
+                Type::Int,
+            ),
+            Expression::IntToBool(value) => (
+                Expression::IntToBool(self.typed(value, &Type::Int)?),
+                Type::Bool,
+            ),
+            Expression::Conversion { target, value } => {
+                let target = self.declared_type(target)?;
+                let (value, t) = self.expression(value)?;
+                return self.conversion(target, value, &t);
+            }
+        };
+        Ok((rc(expression), rc(t)))
+    }
+
+    fn typed(
+        &mut self,
+        value: &Expression,
+        t: &Type,
+    ) -> Result<Rc<Expression>, TypeInferenceError> {
+        let (value, value_type) = self.expression(value)?;
+        if *self.resolve(&value_type)? != *t {
+            return self.error(format!("Expected `{t}`, found `{value_type}`"));
+        }
+        Ok(value)
+    }
+
+    /// `target(value)`, see `Aliases::convertible`
+    fn conversion(
+        &self,
+        target: Rc<Type>,
+        value: Rc<Expression>,
+        t: &Rc<Type>,
+    ) -> Result<(Rc<Expression>, Rc<Type>), TypeInferenceError> {
+        if !self
+            .aliases
+            .convertible(t, &target)
+            .map_err(|reason| self.at(reason))?
+        {
+            return self.error(format!("Cannot convert `{t}` to `{target}`"));
+        }
+        Ok((
+            rc(Expression::Conversion {
+                target: Rc::clone(&target),
+                value,
+            }),
+            target,
+        ))
+    }
+
+    /// Calls of routines, built-ins and conversions to named types, `name(value)`.
+    /// The result type is `None` for procedures, which are called by statements only.
+    fn call(
+        &mut self,
+        callee: &Identifier,
+        args: &[Rc<Expression>],
+        is_statement: bool,
+    ) -> Result<(Rc<Expression>, Option<Rc<Type>>), TypeInferenceError> {
+        if let Some(module) = &callee.module
+            && !self.modules.contains(module)
+        {
+            return self.error(format!("Module `{module}` is not imported"));
+        }
+        let mut checked = Vec::with_capacity(args.len());
+        let mut types = Vec::with_capacity(args.len());
+        for arg in args {
+            let (arg, t) = self.expression(arg)?;
+            checked.push(arg);
+            types.push(t);
+        }
+
+        let (callee, result) = if let Some(&id) = self.routine_ids.get(&callee.to_string()) {
+            let routine = &self.routines[id];
+            if routine.parameters.len() != args.len() {
+                return self.error(format!(
+                    "`{callee}` expects {} arguments, got {}",
+                    routine.parameters.len(),
+                    args.len()
+                ));
+            }
+            let parameters = routine.parameters.clone();
+            let result = routine.result.clone();
+            if self.inferred.contains(&id) {
+                return self.error(format!(
+                    "The result of `{callee}` is inferred from its body, it cannot be called before"
+                ));
+            }
+            checked = checked
+                .into_iter()
+                .zip(&types)
+                .zip(&parameters)
+                .map(|((arg, t), parameter)| self.coerce(arg, t, parameter))
+                .collect::<Result<_, _>>()?;
+            (callee.with_id(id), result)
+        } else if self.aliases.0.contains_key(&callee.to_string()) {
+            let ([value], [t]) = (&checked[..], &types[..]) else {
+                return self.error(format!("Conversion to `{callee}` takes a single value"));
+            };
+            let (value, t) = (Rc::clone(value), Rc::clone(t));
+            let target = rc(Type::Alias(callee.clone()));
+            let (conversion, t) = self.conversion(target, value, &t)?;
+            return Ok((conversion, Some(t)));
+        } else if let Some(builtin) = Builtin::lookup(&callee.name)
+            && callee.module.is_none()
+        {
+            let result = builtin
+                .check_call(&types)
+                .map_err(|reason| self.at(reason))?;
+            (callee.clone(), result)
+        } else {
+            return self.error(format!("Unknown routine `{callee}`"));
+        };
+
+        if result.is_none() && !is_statement {
+            return self.error(format!("`{callee}` does not return a value"));
+        }
+        Ok((
+            rc(Expression::Call {
+                callee,
+                args: checked,
+            }),
+            result,
+        ))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOperator,
+        lhs: &Expression,
+        rhs: &Expression,
+    ) -> Result<(Rc<Expression>, Rc<Type>), TypeInferenceError> {
+        let (lhs, lhs_type) = self.expression(lhs)?;
+        let (rhs, rhs_type) = self.expression(rhs)?;
+        let (l, r) = (self.resolve(&lhs_type)?, self.resolve(&rhs_type)?);
+        let mismatch = || {
+            self.error(format!(
+                "Operator `{op}` cannot be applied to `{lhs_type}` and `{rhs_type}`"
+            ))
+        };
+        let real = || rc(Type::Real);
+        let to_real = |value| {
+            rc(Expression::Conversion {
+                target: real(),
+                value,
+            })
+        };
+        // Integers meeting reals become reals
+        let numeric = |lhs, rhs| match (&*l, &*r) {
+            (Type::Int, Type::Int) => Some((lhs, rhs, rc(Type::Int))),
+            (Type::Real, Type::Real) => Some((lhs, rhs, real())),
+            (Type::Int, Type::Real) => Some((to_real(lhs), rhs, real())),
+            (Type::Real, Type::Int) => Some((lhs, to_real(rhs), real())),
+            _ => None,
+        };
+        let bool = rc(Type::Bool);
+
+        let (lhs, rhs, t) = match op {
+            BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor => (
+                self.truth(lhs, &lhs_type)?,
+                self.truth(rhs, &rhs_type)?,
+                bool,
+            ),
+            BinaryOperator::Add if *l == Type::String && *r == Type::String => {
+                (lhs, rhs, rc(Type::String))
+            }
+            BinaryOperator::Add
+            | BinaryOperator::Sub
+            | BinaryOperator::Mul
+            | BinaryOperator::Div => match numeric(lhs, rhs) {
+                Some(operands) => operands,
+                None => return mismatch(),
+            },
+            BinaryOperator::Mod => match (&*l, &*r) {
+                (Type::Int, Type::Int) => (lhs, rhs, rc(Type::Int)),
+                _ => return mismatch(),
+            },
+            BinaryOperator::Le
+            | BinaryOperator::Lg
+            | BinaryOperator::Gt
+            | BinaryOperator::Ge
+            | BinaryOperator::Eq
+            | BinaryOperator::Neq => {
+                let is_equality = matches!(op, BinaryOperator::Eq | BinaryOperator::Neq);
+                match (&*l, &*r) {
+                    (Type::Int | Type::Real, Type::Int | Type::Real) => {
+                        let (lhs, rhs, _) = numeric(lhs, rhs).expect("Both are numbers");
+                        (lhs, rhs, bool)
+                    }
+                    (Type::Bool, Type::Bool) | (Type::String, Type::String) => (lhs, rhs, bool),
+                    (Type::Enum(_), Type::Enum(_)) if l == r => (lhs, rhs, bool),
+                    // References are compared by identity, see `equals`
+                    (Type::Record(_) | Type::Array(_), Type::Record(_) | Type::Array(_))
+                        if is_equality
+                            && (self.assignable(&lhs_type, &rhs_type)?
+                                || self.assignable(&rhs_type, &lhs_type)?) =>
+                    {
+                        (lhs, rhs, bool)
+                    }
+                    _ => return mismatch(),
+                }
+            }
+        };
+        Ok((rc(Expression::Binop { op, lhs, rhs }), t))
+    }
+
+    fn lookup(&self, name: &Identifier) -> Option<usize> {
+        if name.module.is_some() {
+            return self.globals.get(&name.to_string()).copied();
+        }
+        self.scope
+            .iter()
+            .rev()
+            .find(|(known, _)| *known == name.name)
+            .map(|&(_, id)| id)
+    }
+
+    /// Variables, their fields and elements, `length` of arrays, characters of strings
+    /// and enum variants
+    fn rvalue(
+        &mut self,
+        lvalue: &LvalueExpression,
+    ) -> Result<(Rc<Expression>, Rc<Type>), TypeInferenceError> {
+        if let Some(name) = self.qualified(lvalue) {
+            return self.rvalue(&LvalueExpression::Identifier(name));
+        }
+        match lvalue {
+            LvalueExpression::Identifier(name) if self.lookup(name).is_none() => {
+                let Some((t, value)) = self.variants.get(&name.to_string()) else {
+                    return self.error(format!("Unknown variable `{name}`"));
+                };
+                let t = Rc::clone(t);
+                Ok((
+                    rc(Expression::Conversion {
+                        target: Rc::clone(&t),
+                        value: rc(Expression::IntegerLiteral(IntegerLiteral::new(*value))),
+                    }),
+                    t,
+                ))
+            }
+            LvalueExpression::Index { lhs, index } => {
+                let (array, t) = self.lvalue(lhs)?;
+                if *self.resolve(&t)? != Type::String {
+                    let (lvalue, t) = self.lvalue(lvalue)?;
+                    return Ok((rc(Expression::LvalueToRvalue(lvalue)), t));
+                }
+                let (index, index_type) = self.expression(index)?;
+                let index = self.coerce(index, &index_type, &rc(Type::Int))?;
+                Ok((
+                    rc(Expression::Call {
+                        callee: Identifier {
+                            name: Builtin::CharAt.name().to_owned(),
+                            module: None,
+                            id: None,
+                        },
+                        args: vec![rc(Expression::LvalueToRvalue(array)), index],
+                    }),
+                    rc(Type::String),
+                ))
+            }
+            LvalueExpression::Identifier(_) | LvalueExpression::Member { .. } => {
+                let (lvalue, t) = self.lvalue(lvalue)?;
+                Ok((rc(Expression::LvalueToRvalue(lvalue)), t))
+            }
+        }
+    }
+
+    /// `module.name` is parsed as a field of `module` unless it is called
+    fn qualified(&self, lvalue: &LvalueExpression) -> Option<Identifier> {
+        let LvalueExpression::Member { lhs, member_name } = lvalue else {
+            return None;
+        };
+        let LvalueExpression::Identifier(module) = &**lhs else {
+            return None;
+        };
+        (module.module.is_none()
+            && self.lookup(module).is_none()
+            && self.modules.contains(&module.name))
+        .then(|| Identifier {
+            name: member_name.name.clone(),
+            module: Some(module.name.clone()),
+            id: None,
+        })
+    }
+
+    /// The `id` of a field is its index, `length` of an array has no id
+    fn lvalue(
+        &mut self,
+        lvalue: &LvalueExpression,
+    ) -> Result<(Rc<LvalueExpression>, Rc<Type>), TypeInferenceError> {
+        if let Some(name) = self.qualified(lvalue) {
+            return self.lvalue(&LvalueExpression::Identifier(name));
+        }
+        let (lvalue, t) = match lvalue {
+            LvalueExpression::Identifier(name) => {
+                let Some(id) = self.lookup(name) else {
+                    return self.error(format!("Unknown variable `{name}`"));
+                };
+                (
+                    LvalueExpression::Identifier(name.with_id(id)),
+                    Rc::clone(&self.variables[id].t),
+                )
+            }
+            LvalueExpression::Member { lhs, member_name } => {
+                let (lhs, t) = self.lvalue(lhs)?;
+                match &*self.resolve(&t)? {
+                    Type::Record(record) => {
+                        let Some(field) = record
+                            .fields
+                            .iter()
+                            .position(|field| field.name.name == member_name.name)
+                        else {
+                            return self.error(format!("`{t}` has no field `{member_name}`"));
+                        };
+                        let field_type = Rc::clone(&record.fields[field].t);
+                        (
+                            LvalueExpression::Member {
+                                lhs,
+                                member_name: member_name.with_id(field),
+                            },
+                            field_type,
+                        )
+                    }
+                    Type::Array(_) if member_name.name == "length" => (
+                        LvalueExpression::Member {
+                            lhs,
+                            member_name: member_name.clone(),
+                        },
+                        rc(Type::Int),
+                    ),
+                    Type::Int
+                    | Type::Real
+                    | Type::Bool
+                    | Type::String
+                    | Type::Alias(_)
+                    | Type::Array(_)
+                    | Type::Enum(_) => {
+                        return self.error(format!("`{t}` has no field `{member_name}`"));
+                    }
+                }
+            }
+            LvalueExpression::Index { lhs, index } => {
+                let (lhs, t) = self.lvalue(lhs)?;
+                let Type::Array(array) = &*self.resolve(&t)? else {
+                    return self.error(format!("`{t}` cannot be indexed"));
+                };
+                let element = Rc::clone(&array.t);
+                let (index, index_type) = self.expression(index)?;
+                (
+                    LvalueExpression::Index {
+                        lhs,
+                        index: self.coerce(index, &index_type, &rc(Type::Int))?,
+                    },
+                    element,
+                )
+            }
+        };
+        Ok((rc(lvalue), t))
+    }
+}
@@ -0,0 +1,495 @@
+use super::*;
+use crate::ast::{Block, IntegerLiteral};
+
+fn name(name: &str) -> Identifier {
+    Identifier {
+        name: name.to_owned(),
+        module: None,
+        id: None,
+    }
+}
+
+/// `enum idle, running, done end`
+fn state() -> Type {
+    Type::Enum(EnumDescription {
+        variants: ["idle", "running", "done"].map(name).into(),
+    })
+}
+
+fn branch(labels: Vec<CaseLabel>) -> CaseBranch {
+    CaseBranch {
+        labels,
+        body: Block::default(),
+    }
+}
+
+fn variants(names: &[&str]) -> CaseBranch {
+    branch(
+        names
+            .iter()
+            .map(|&variant| CaseLabel::Variant(name(variant)))
+            .collect(),
+    )
+}
+
+fn integers(values: &[i64]) -> CaseBranch {
+    branch(
+        values
+            .iter()
+            .map(|&value| CaseLabel::Integer(IntegerLiteral::new(value)))
+            .collect(),
+    )
+}
+
+#[test]
+fn enum_case_is_exhaustive() {
+    let branches = [variants(&["done"]), variants(&["idle", "running"])];
+    assert_eq!(
+        case_labels(&state(), &branches, false).ok(),
+        Some(vec![vec![2], vec![0, 1]])
+    );
+
+    let partial = [variants(&["running"])];
+    assert_eq!(
+        case_labels(&state(), &partial, false).err(),
+        Some("Variants `idle`, `done` are not handled".to_owned())
+    );
+    assert_eq!(
+        case_labels(&state(), &partial, true).ok(),
+        Some(vec![vec![1]])
+    );
+}
+
+#[test]
+fn invalid_labels() {
+    let reason = |t: &Type, branches: &[CaseBranch]| case_labels(t, branches, true).err();
+    assert_eq!(
+        reason(&state(), &[variants(&["idle"]), variants(&["idle"])]),
+        Some("Label `idle` is used more than once".to_owned())
+    );
+    assert_eq!(
+        reason(&state(), &[variants(&["stopped"])]),
+        Some("`stopped` is not a variant of the enum".to_owned())
+    );
+    assert_eq!(
+        reason(&state(), &[integers(&[0])]),
+        Some("Integer 0 cannot label an enum case".to_owned())
+    );
+    assert_eq!(
+        reason(&Type::Int, &[integers(&[1, -1]), integers(&[3, 1])]),
+        Some("Label 1 is used more than once".to_owned())
+    );
+    assert!(reason(&Type::Real, &[integers(&[1])]).is_some());
+}
+
+#[test]
+fn integer_case_needs_no_else() {
+    assert_eq!(
+        case_labels(&Type::Int, &[integers(&[-1, 7])], false).ok(),
+        Some(vec![vec![-1, 7]])
+    );
+}
+
+fn alias(name: &str) -> Rc<Type> {
+    Rc::new(Type::Alias(self::name(name)))
+}
+
+/// `type kilometers is real; type miles is real; type distance is kilometers;`
+fn distances() -> Aliases {
+    let mut aliases = Aliases::default();
+    for (name, t) in [
+        ("kilometers", Rc::new(Type::Real)),
+        ("miles", Rc::new(Type::Real)),
+        ("distance", alias("kilometers")),
+    ] {
+        aliases
+            .declare(&self::name(name), t)
+            .expect("Declared once");
+    }
+    aliases
+}
+
+#[test]
+fn nominal_aliases() {
+    let aliases = distances();
+    let assignable = |from: &str, to: &str, semantics| {
+        aliases.assignable(&alias(from), &alias(to), semantics).ok()
+    };
+    assert_eq!(
+        assignable("kilometers", "kilometers", AliasSemantics::Nominal),
+        Some(true)
+    );
+    assert_eq!(
+        assignable("kilometers", "miles", AliasSemantics::Nominal),
+        Some(false)
+    );
+    assert_eq!(
+        assignable("kilometers", "miles", AliasSemantics::Structural),
+        Some(true)
+    );
+    assert_eq!(
+        assignable("distance", "kilometers", AliasSemantics::Nominal),
+        Some(false)
+    );
+    assert_eq!(
+        aliases
+            .assignable(
+                &Rc::new(Type::Real),
+                &alias("miles"),
+                AliasSemantics::Nominal
+            )
+            .ok(),
+        Some(false)
+    );
+    assert_eq!(
+        aliases
+            .assignable(
+                &alias("parsecs"),
+                &alias("miles"),
+                AliasSemantics::Structural
+            )
+            .err(),
+        Some("Unknown type `parsecs`".to_owned())
+    );
+}
+
+#[test]
+fn explicit_conversions() {
+    let aliases = distances();
+    let convertible = |from: &Rc<Type>, to: &Rc<Type>| aliases.convertible(from, to).ok();
+    let real = Rc::new(Type::Real);
+    assert_eq!(
+        convertible(&alias("kilometers"), &alias("miles")),
+        Some(true)
+    );
+    assert_eq!(convertible(&alias("distance"), &real), Some(true));
+    assert_eq!(convertible(&real, &Rc::new(Type::Int)), Some(true));
+    assert_eq!(
+        convertible(&alias("miles"), &Rc::new(Type::Int)),
+        Some(true)
+    );
+    assert_eq!(convertible(&Rc::new(Type::Bool), &real), Some(false));
+    assert_eq!(
+        convertible(&Rc::new(Type::Int), &Rc::new(state())),
+        Some(false)
+    );
+    assert_eq!(
+        convertible(&Rc::new(state()), &Rc::new(Type::Int)),
+        Some(true)
+    );
+}
+
+#[test]
+fn alias_semantics_flag() {
+    assert_eq!("structural".parse(), Ok(AliasSemantics::Structural));
+    assert_eq!(AliasSemantics::default(), AliasSemantics::Structural);
+    assert_eq!(
+        "newtype".parse::<AliasSemantics>(),
+        Err("Unknown alias semantics \"newtype\"".to_owned())
+    );
+}
+
+fn check_with(src: &str, semantics: AliasSemantics) -> Result<TypedProgram, TypeErrors> {
+    let program = crate::parse(&crate::lex(src)).map_err(|e| {
+        TypeErrors(vec![TypeInferenceError {
+            position: e.position,
+            reason: e.reason,
+        }])
+    })?;
+    check(program, semantics)
+}
+
+fn check_source(src: &str) -> Result<TypedProgram, TypeErrors> {
+    check_with(src, AliasSemantics::default())
+}
+
+/// Reasons of all the errors, one per line
+fn reasons(errors: TypeErrors) -> String {
+    let reasons: Vec<_> = errors.0.into_iter().map(|e| e.reason).collect();
+    reasons.join("\n")
+}
+
+fn check_error(src: &str) -> Option<String> {
+    check_source(src).err().map(reasons)
+}
+
+#[test]
+fn checked_program() {
+    let program = check_source(
+        "type point is record var x : real; var y : real; end;
+         var origin : point;
+         routine half(a : integer) => a / 2.0;
+         routine main() is
+           var i is 1;
+           for k in 1 .. 3 loop print half(k) + i; end;
+           print origin.x;
+         end;",
+    );
+    let program = program.map_err(reasons);
+    let program = program.as_ref().map_err(String::as_str);
+    let routines = program.map(|program| {
+        program
+            .routines
+            .iter()
+            .map(|routine| {
+                (
+                    routine.name.as_str(),
+                    routine.result.clone(),
+                    routine.locals,
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    // `k` and the bound of the loop are the hidden locals after `i`
+    assert_eq!(
+        routines,
+        Ok(vec![
+            ("half", Some(Rc::new(Type::Real)), 0),
+            ("main", None, 4),
+        ])
+    );
+    assert_eq!(program.map(|program| program.global_count), Ok(1));
+}
+
+#[test]
+fn rejected_programs() {
+    assert_eq!(
+        check_error("routine main() is print x; end;").as_deref(),
+        Some("Unknown variable `x`")
+    );
+    assert_eq!(
+        check_error("routine f(a : integer) => a; routine main() is print f(1, 2); end;")
+            .as_deref(),
+        Some("`f` expects 1 arguments, got 2")
+    );
+    assert_eq!(
+        check_error("routine main() is var s is \"a\"; s := 1; end;").as_deref(),
+        Some("Cannot use `integer` as `string`")
+    );
+    assert_eq!(
+        check_error("routine main() is print g(); end; routine g() => 1;").as_deref(),
+        Some("The result of `g` is inferred from its body, it cannot be called before")
+    );
+    assert_eq!(
+        check_error("routine main() is var a : array [] integer; end;").as_deref(),
+        Some("Variable `a` needs the size of its array")
+    );
+}
+
+/// At the innermost statement, types are written as in the source
+#[test]
+fn error_positions() {
+    let error = check_source(
+        "routine main() is
+           var a : array [3] integer;
+           if true then
+             a := 1;
+           end;
+         end;",
+    )
+    .err()
+    .map(|e| e.to_string());
+    assert_eq!(
+        error.as_deref(),
+        Some("4:13: Cannot use `integer` as `array [3] integer`")
+    );
+    assert_eq!(
+        check_source("type km is real;\nvar d : km is \"far\";")
+            .err()
+            .map(|e| e.to_string())
+            .as_deref(),
+        Some("2:0: Cannot use `string` as `km`")
+    );
+}
+
+#[test]
+fn alias_semantics_of_programs() {
+    let type_aliases = include_str!("../../../tests/src/type_aliases.i");
+    let nominal_aliases = include_str!("../../../tests/src/nominal_aliases.i");
+    let error = |src, semantics| check_with(src, semantics).err().map(|e| e.to_string());
+    assert_eq!(error(type_aliases, AliasSemantics::Structural), None);
+    assert_eq!(
+        error(type_aliases, AliasSemantics::Nominal).as_deref(),
+        Some("4:0: Cannot use `kilometers` as `miles`")
+    );
+    assert_eq!(error(nominal_aliases, AliasSemantics::Structural), None);
+    assert_eq!(error(nominal_aliases, AliasSemantics::Nominal), None);
+}
+
+/// `target(value)` is checked with `Aliases::convertible`
+#[test]
+fn conversions_in_programs() {
+    assert_eq!(
+        check_error("routine main() is print real(\"1\"); end;").as_deref(),
+        Some("Cannot convert `string` to `real`")
+    );
+    assert_eq!(
+        check_error(
+            "type point is record var x : real; end;
+             routine main() is var p : point; print integer(p); end;"
+        )
+        .as_deref(),
+        Some("Cannot convert `point` to `integer`")
+    );
+    assert_eq!(
+        check_error("type km is real; routine main() is print km(1); end;"),
+        None
+    );
+}
+
+#[test]
+fn duplicate_variants() {
+    assert_eq!(
+        check_error("type state is enum idle, done, idle end;").as_deref(),
+        Some("Variant `idle` is declared more than once")
+    );
+    assert_eq!(
+        check_error("type state is enum idle, done end; type job is enum queued, done end;")
+            .as_deref(),
+        Some("Variant `done` is declared by more than one enum")
+    );
+}
+
+#[test]
+fn misplaced_loop_exits() {
+    assert_eq!(
+        check_error("routine main() is break; end;").as_deref(),
+        Some("`break` outside of a loop")
+    );
+    assert_eq!(
+        check_error("routine main() is while true loop continue outer; end; end;").as_deref(),
+        Some("`continue outer` does not name an enclosing loop")
+    );
+}
+
+/// `tests/src/invalid.i` is rejected for each of its routines, unreachable code is only warned of
+#[test]
+fn control_flow() {
+    let source = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/src/invalid.i"
+    ))
+    .expect("Test program exists");
+    assert_eq!(
+        check_source(&source)
+            .err()
+            .map(|e| e.to_string())
+            .as_deref(),
+        Some(
+            "2:2: Procedure `extra_return` cannot return a value\n\
+             5:0: Routine `no_return` may end without returning a value\n\
+             15:2: Variable `j` is used before being assigned in routine `strange_cast`\n\
+             12:0: Routine `strange_cast` may end without returning a value"
+        )
+    );
+    let program = check_source("routine main() is return; print 1; end;");
+    let warnings =
+        program.map(|program| program.warnings.iter().map(ToString::to_string).collect());
+    assert_eq!(
+        warnings.ok(),
+        Some(vec!["1:26: Unreachable code in routine `main`".to_owned()])
+    );
+    assert_eq!(
+        check_error("routine no_return() : real is print 1; end;").as_deref(),
+        Some("Routine `no_return` may end without returning a value")
+    );
+    assert_eq!(
+        check_error(
+            "type a is real; type b is real;
+             routine strange_cast() is var i : a; var j : b; i := j; end;"
+        )
+        .as_deref(),
+        Some("Variable `j` is used before being assigned in routine `strange_cast`")
+    );
+    // Records are allocated by their declaration, through aliases too
+    assert_eq!(
+        check_error(
+            "type point is record var x : real; end;
+             type position is point;
+             routine origin() : position is var result : position; return result; end;
+             routine forever() : integer is while true loop return 1; end; end;"
+        ),
+        None
+    );
+    // A `case` naming every variant returns on every path, one of integers may not
+    let case = |value: &str, labels: &str| {
+        check_error(&format!(
+            "type state is enum idle, done end;
+             routine next(s : state) : state is
+               var i is 1;
+               case {value} of {labels} is return done; end; end;
+             end;"
+        ))
+    };
+    assert_eq!(case("s", "idle, done"), None);
+    assert_eq!(
+        case("i", "1, 2").as_deref(),
+        Some("Routine `next` may end without returning a value")
+    );
+}
+
+#[test]
+fn imported_modules() {
+    let parse = |src: &str| crate::parse(&crate::lex(src)).expect("Parses");
+    let geometry = check_module(
+        parse(
+            "type point is record var x : real; var y : real; end;
+             var calls is 0;
+             routine norm(p : point) : real is calls := calls + 1; return p.x * p.x; end;",
+        ),
+        "geometry",
+        &[],
+        AliasSemantics::default(),
+    )
+    .expect("Type checks");
+    let imported = [geometry];
+    let main = |src| {
+        check_module(parse(src), "main", &imported, AliasSemantics::default()).map_err(reasons)
+    };
+
+    let program = main(
+        "import geometry;
+         routine main() is var p : geometry.point; print geometry.norm(p), geometry.calls; end;",
+    );
+    let program = program.as_ref().map_err(String::as_str);
+    assert_eq!(
+        program.map(|program| program.imports.clone()),
+        Ok(vec![
+            Symbol::Global {
+                name: "geometry.calls".to_owned(),
+                index: 0,
+            },
+            Symbol::Type {
+                name: "geometry.point".to_owned(),
+                type_id: TypeId(4),
+            },
+        ])
+    );
+    assert_eq!(
+        program.map(|program| {
+            program
+                .routines
+                .iter()
+                .map(|routine| (routine.name.as_str(), routine.module.as_deref()))
+                .collect::<Vec<_>>()
+        }),
+        Ok(vec![("norm", Some("geometry")), ("main", None)])
+    );
+
+    assert_eq!(
+        main("routine main() is print geometry.calls; end;").err(),
+        Some("Unknown variable `geometry`".to_owned())
+    );
+    assert_eq!(
+        main("routine main() is print geometry.norm(1); end;").err(),
+        Some("Module `geometry` is not imported".to_owned())
+    );
+    assert_eq!(
+        main("import shapes; routine main() is end;").err(),
+        Some("Module `shapes` is not checked before `main`".to_owned())
+    );
+    assert_eq!(
+        main("import geometry; routine main() is print geometry.area(1); end;").err(),
+        Some("Unknown routine `geometry.area`".to_owned())
+    );
+}
@@ -0,0 +1,983 @@
+//! WebAssembly modules, binary or text, for the runtime of `vm::wasm` to run.
+//!
+//! Integers and booleans are `i64`, reals `f64` and references `i32` addresses into the linear
+//! memory, which starts with a header: the address of its first free byte, where the host
+//! allocates objects from, and the table of types the host prints and copies by, see `Kind`.
+//! The globals and the strings follow. Objects start with their `TypeId`, then come the length
+//! of arrays and strings and their elements or bytes, or the fields of records. `print`,
+//! allocation and most built-ins are imported from the host, failures go to its `fail`.
+//!
+//! Every value of the IR has a local of its routine. Blocks are cases of a `br_table` in a loop,
+//! jumps set the next one and go back to the start of the loop, unless it is the next one in
+//! order, which is just fallen into.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::builtins::Builtin;
+use crate::bytecode::{
+    ArrayRTTI, EnumRTTI, Module, PrimitiveRTTI, RTTIElement, RecordRTTI, TypeId,
+};
+use crate::cgen::{self, CgenError};
+use crate::consteval::Constant;
+use crate::ir::{self, BlockId, IrType, Operation, Terminator, ValueId};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+use self::module::{Callee, Function, Instruction, Plain};
+pub use self::module::{Import, Signature, ValType, Wasm};
+
+mod module;
+#[cfg(test)]
+mod tests;
+
+/// Address of the first free byte of memory, the host moves it on when allocating
+pub const HEAP_POINTER: u32 = 8;
+/// Address of the number of types, their descriptions follow from `TYPES`
+pub const TYPE_COUNT: u32 = 12;
+pub const TYPES: u32 = 16;
+/// Bytes of a description: its `Kind` and up to three more words
+pub const TYPE_SIZE: u32 = 16;
+
+/// Offset of the length of arrays and strings, after the `TypeId`
+pub const LENGTH: u32 = 4;
+/// Offset of the fields of records, the elements of arrays and the bytes of strings
+pub const CONTENTS: u32 = 8;
+
+/// First word of a type description, the meaning of the next ones depends on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Integer,
+    Real,
+    Boolean,
+    String,
+    /// Number of variants, address of the addresses of their names as strings
+    Enum,
+    /// Number of fields, address of their `TypeId`s
+    Record,
+    /// `TypeId` of the elements
+    Array,
+}
+
+impl Kind {
+    const ALL: [Kind; 7] = [
+        Kind::Integer,
+        Kind::Real,
+        Kind::Boolean,
+        Kind::String,
+        Kind::Enum,
+        Kind::Record,
+        Kind::Array,
+    ];
+
+    #[must_use]
+    pub fn from_code(code: u32) -> Option<Kind> {
+        Kind::ALL.get(code as usize).copied()
+    }
+
+    fn code(self) -> u32 {
+        (0..)
+            .zip(Kind::ALL)
+            .find_map(|(code, kind)| (kind == self).then_some(code))
+            .expect("Every kind is listed")
+    }
+}
+
+/// Why the program stops, the first argument of the imported `fail`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IntegerOverflow,
+    DivisionByZero,
+    /// With the index and the length
+    IndexOutOfBounds,
+    /// With the code
+    Panic,
+}
+
+impl Fault {
+    const ALL: [Fault; 4] = [
+        Fault::IntegerOverflow,
+        Fault::DivisionByZero,
+        Fault::IndexOutOfBounds,
+        Fault::Panic,
+    ];
+
+    #[must_use]
+    pub fn from_code(code: i32) -> Option<Fault> {
+        usize::try_from(code)
+            .ok()
+            .and_then(|index| Fault::ALL.get(index))
+            .copied()
+    }
+
+    fn code(self) -> i32 {
+        (0..)
+            .zip(Fault::ALL)
+            .find_map(|(code, fault)| (fault == self).then_some(code))
+            .expect("Every fault is listed")
+    }
+}
+
+/// Functions of the module before the routines, the arithmetic of the `vm` crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Helper {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Neg,
+    /// Address of an element less `CONTENTS`, failing out of bounds
+    Element,
+}
+
+impl Helper {
+    const ALL: [Helper; 7] = [
+        Helper::Add,
+        Helper::Sub,
+        Helper::Mul,
+        Helper::Div,
+        Helper::Mod,
+        Helper::Neg,
+        Helper::Element,
+    ];
+
+    fn callee(self) -> Callee {
+        let index = Helper::ALL
+            .iter()
+            .position(|&helper| helper == self)
+            .expect("Every helper is listed");
+        Callee::Function(u32::try_from(index).expect("Few helpers"))
+    }
+}
+
+fn valtype(t: IrType) -> ValType {
+    match t {
+        IrType::Int | IrType::Bool => ValType::I64,
+        IrType::Real => ValType::F64,
+        IrType::Ref => ValType::I32,
+    }
+}
+
+fn signature(params: &[ValType], results: &[ValType]) -> Signature {
+    Signature {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    }
+}
+
+/// Initial contents of memory from `HEAP_POINTER`
+struct Data(Vec<u8>);
+
+impl Data {
+    fn address(&self) -> u32 {
+        HEAP_POINTER + u32::try_from(self.0.len()).expect("Data fits into memory")
+    }
+
+    fn align(&mut self) {
+        while !self.0.len().is_multiple_of(8) {
+            self.0.push(0);
+        }
+    }
+
+    fn word(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn set(&mut self, address: u32, value: u32) {
+        let offset = (address - HEAP_POINTER) as usize;
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// A string object, returns its address
+    fn string(&mut self, s: &str) -> u32 {
+        self.align();
+        let address = self.address();
+        self.word(TypeId::STRING.0);
+        self.word(u32::try_from(s.len()).expect("Strings fit into memory"));
+        self.0.extend_from_slice(s.as_bytes());
+        address
+    }
+}
+
+/// Locals of a routine: its parameters, then one for each value, for each phi and the next block
+struct Frame {
+    args: u32,
+    types: HashMap<ValueId, IrType>,
+    /// Phis are assigned on the edges to their block, then copied to their value
+    phis: HashMap<ValueId, u32>,
+    value_count: u32,
+    block_count: u32,
+}
+
+impl Frame {
+    fn new(function: &ir::Function) -> Self {
+        let types = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| instruction.result)
+            .collect();
+        let phis = function
+            .blocks
+            .iter()
+            .flat_map(cgen::phis)
+            .zip(0..)
+            .map(|((result, _), index)| (result, index))
+            .collect();
+        Self {
+            args: u32::try_from(function.args.len()).expect("Routines take few arguments"),
+            types,
+            phis,
+            value_count: function.value_count,
+            block_count: u32::try_from(function.blocks.len()).expect("Routines have few blocks"),
+        }
+    }
+
+    fn local(&self, id: ValueId) -> u32 {
+        self.args + id.0
+    }
+
+    fn phi(&self, id: ValueId) -> u32 {
+        self.args + self.value_count + self.phis[&id]
+    }
+
+    fn block(&self) -> u32 {
+        self.args + self.value_count + u32::try_from(self.phis.len()).expect("Few phis")
+    }
+
+    fn locals(&self) -> Vec<ValType> {
+        let values = (0..self.value_count).map(|id| {
+            self.types
+                .get(&ValueId(id))
+                .map_or(ValType::I64, |&t| valtype(t))
+        });
+        let mut phis: Vec<_> = self.phis.iter().map(|(&id, &index)| (index, id)).collect();
+        phis.sort_unstable();
+        let phis = phis.into_iter().map(|(_, id)| valtype(self.types[&id]));
+        values.chain(phis).chain([ValType::I32]).collect()
+    }
+
+    fn valtype(&self, id: ValueId) -> ValType {
+        valtype(self.types[&id])
+    }
+}
+
+struct Lowering<'a> {
+    routines: &'a BTreeMap<u64, ir::Function>,
+    imports: Vec<Import>,
+    /// Function indices of the routines
+    functions: HashMap<u64, u32>,
+    globals: u32,
+    strings: Vec<u32>,
+    code: Vec<Instruction>,
+}
+
+impl Lowering<'_> {
+    fn emit(&mut self, instruction: Instruction) {
+        self.code.push(instruction);
+    }
+
+    /// Offset of the global from address 0
+    fn global(&self, index: usize) -> u32 {
+        self.globals + 8 * u32::try_from(index).expect("Few globals")
+    }
+
+    fn plain(&mut self, plain: Plain) {
+        self.code.push(Instruction::Plain(plain));
+    }
+
+    fn get(&mut self, frame: &Frame, id: ValueId) {
+        self.emit(Instruction::LocalGet(frame.local(id)));
+    }
+
+    /// Calls the host's `name`, importing it on the first call
+    fn import(&mut self, name: &'static str, params: &[ValType], results: &[ValType]) {
+        let index = self
+            .imports
+            .iter()
+            .position(|import| import.name == name)
+            .unwrap_or_else(|| {
+                self.imports.push(Import {
+                    name,
+                    signature: signature(params, results),
+                });
+                self.imports.len() - 1
+            });
+        let index = u32::try_from(index).expect("Few imports");
+        self.emit(Instruction::Call(Callee::Import(index)));
+    }
+
+    /// The value as the bits of an `i64`, for the host's `print` and `fill`
+    fn bits(&mut self, frame: &Frame, id: ValueId) {
+        self.get(frame, id);
+        match frame.types[&id] {
+            IrType::Int | IrType::Bool => {}
+            IrType::Real => self.plain(Plain::I64ReinterpretF64),
+            IrType::Ref => self.plain(Plain::I64ExtendI32U),
+        }
+    }
+
+    /// `plain` on both operands, a comparison giving a boolean
+    fn compare(&mut self, frame: &Frame, lhs: ValueId, rhs: ValueId, plain: Plain) {
+        self.get(frame, lhs);
+        self.get(frame, rhs);
+        self.plain(plain);
+        self.plain(Plain::I64ExtendI32U);
+    }
+
+    fn arithmetic(&mut self, frame: &Frame, lhs: ValueId, rhs: ValueId, plain: Plain) {
+        self.get(frame, lhs);
+        self.get(frame, rhs);
+        self.plain(plain);
+    }
+
+    fn checked(&mut self, frame: &Frame, lhs: ValueId, rhs: ValueId, helper: Helper) {
+        self.get(frame, lhs);
+        self.get(frame, rhs);
+        self.emit(Instruction::Call(helper.callee()));
+    }
+
+    fn binary(&mut self, frame: &Frame, op: SemanticBinaryOperator, lhs: ValueId, rhs: ValueId) {
+        use SemanticBinaryOperator as Op;
+
+        let refs = frame.types[&lhs] == IrType::Ref;
+        let (comparison, strings) = match op {
+            Op::RealAdd => return self.arithmetic(frame, lhs, rhs, Plain::F64Add),
+            Op::RealSub => return self.arithmetic(frame, lhs, rhs, Plain::F64Sub),
+            Op::RealMul => return self.arithmetic(frame, lhs, rhs, Plain::F64Mul),
+            Op::RealDiv => return self.arithmetic(frame, lhs, rhs, Plain::F64Div),
+            Op::RealLe => (Plain::F64Le, false),
+            Op::RealLg => (Plain::F64Lt, false),
+            Op::RealGt => (Plain::F64Gt, false),
+            Op::RealGe => (Plain::F64Ge, false),
+            Op::RealEq => (Plain::F64Eq, false),
+            Op::RealNeq => (Plain::F64Ne, false),
+            Op::IntLe => (Plain::I64LeS, false),
+            Op::IntLg => (Plain::I64LtS, false),
+            Op::IntGt => (Plain::I64GtS, false),
+            Op::IntGe => (Plain::I64GeS, false),
+            // Also compares references by identity
+            Op::IntEq if refs => (Plain::I32Eq, false),
+            Op::IntEq => (Plain::I64Eq, false),
+            Op::IntNeq if refs => (Plain::I32Ne, false),
+            Op::IntNeq | Op::BoolXor => (Plain::I64Ne, false),
+            Op::IntAdd => return self.checked(frame, lhs, rhs, Helper::Add),
+            Op::IntSub => return self.checked(frame, lhs, rhs, Helper::Sub),
+            Op::IntMul => return self.checked(frame, lhs, rhs, Helper::Mul),
+            Op::IntDiv => return self.checked(frame, lhs, rhs, Helper::Div),
+            Op::IntMod => return self.checked(frame, lhs, rhs, Helper::Mod),
+            Op::BoolAnd => return self.arithmetic(frame, lhs, rhs, Plain::I64And),
+            Op::BoolOr => return self.arithmetic(frame, lhs, rhs, Plain::I64Or),
+            Op::StringConcat => {
+                self.get(frame, lhs);
+                self.get(frame, rhs);
+                return self.import("concat", &[ValType::I32; 2], &[ValType::I32]);
+            }
+            Op::StringLe => (Plain::I32LeS, true),
+            Op::StringLg => (Plain::I32LtS, true),
+            Op::StringGt => (Plain::I32GtS, true),
+            Op::StringGe => (Plain::I32GeS, true),
+            Op::StringEq => (Plain::I32Eq, true),
+            Op::StringNeq => (Plain::I32Ne, true),
+        };
+        if strings {
+            // The host compares the strings to -1, 0 or 1, which is compared to 0
+            self.get(frame, lhs);
+            self.get(frame, rhs);
+            self.import("compare", &[ValType::I32; 2], &[ValType::I32]);
+            self.emit(Instruction::I32Const(0));
+            self.plain(comparison);
+            self.plain(Plain::I64ExtendI32U);
+        } else {
+            self.compare(frame, lhs, rhs, comparison);
+        }
+    }
+
+    /// Built-ins depending on the types of their arguments which are not just called with them,
+    /// returns whether they leave a result on the stack, `None` for the other ones
+    fn special_native(
+        &mut self,
+        frame: &Frame,
+        builtin: Builtin,
+        args: &[ValueId],
+    ) -> Option<bool> {
+        let is_real = |index: usize| frame.types[&args[index]] == IrType::Real;
+        let is_ref = |index: usize| frame.types[&args[index]] == IrType::Ref;
+        match builtin {
+            Builtin::Fill => {
+                self.get(frame, args[0]);
+                self.bits(frame, args[1]);
+                self.import(builtin.name(), &[ValType::I32, ValType::I64], &[]);
+                Some(false)
+            }
+            Builtin::Min | Builtin::Max if !is_real(0) => {
+                // The first one when it compares as wanted to the second one
+                let wanted = if builtin == Builtin::Min {
+                    Plain::I64LtS
+                } else {
+                    Plain::I64GtS
+                };
+                self.get(frame, args[0]);
+                self.get(frame, args[1]);
+                self.arithmetic(frame, args[0], args[1], wanted);
+                self.plain(Plain::Select);
+                Some(true)
+            }
+            Builtin::Equals if !is_ref(0) => {
+                let equal = if is_real(0) {
+                    Plain::F64Eq
+                } else {
+                    Plain::I64Eq
+                };
+                self.compare(frame, args[0], args[1], equal);
+                Some(true)
+            }
+            Builtin::Copy if !is_ref(0) => {
+                self.get(frame, args[0]);
+                Some(true)
+            }
+            Builtin::Sqrt
+            | Builtin::Abs
+            | Builtin::Floor
+            | Builtin::Ceil
+            | Builtin::Round
+            | Builtin::Pow
+            | Builtin::Sin
+            | Builtin::Cos
+            | Builtin::Tan
+            | Builtin::Atan
+            | Builtin::Exp
+            | Builtin::Ln
+            | Builtin::Min
+            | Builtin::Max
+            | Builtin::ToReal
+            | Builtin::ToInteger
+            | Builtin::ToBoolean
+            | Builtin::Copy
+            | Builtin::Sort
+            | Builtin::Assert
+            | Builtin::ReadInteger
+            | Builtin::ReadReal
+            | Builtin::ReadBoolean
+            | Builtin::Length
+            | Builtin::CharAt
+            | Builtin::Equals => None,
+        }
+    }
+
+    /// Call of the built-in, returns whether it leaves a result on the stack
+    fn native(&mut self, frame: &Frame, builtin: Builtin, args: &[ValueId]) -> bool {
+        use ValType::{F64, I32, I64};
+
+        if let Some(pushed) = self.special_native(frame, builtin, args) {
+            return pushed;
+        }
+        let name = builtin.name();
+        for &arg in args {
+            self.get(frame, arg);
+        }
+        match builtin {
+            Builtin::Sqrt => self.plain(Plain::F64Sqrt),
+            Builtin::Sin
+            | Builtin::Cos
+            | Builtin::Tan
+            | Builtin::Atan
+            | Builtin::Exp
+            | Builtin::Ln => self.import(name, &[F64], &[F64]),
+            Builtin::Pow | Builtin::Min | Builtin::Max => self.import(name, &[F64; 2], &[F64]),
+            Builtin::Abs if frame.types[&args[0]] == IrType::Real => self.plain(Plain::F64Abs),
+            Builtin::Abs | Builtin::ToBoolean => self.import(name, &[I64], &[I64]),
+            Builtin::Floor | Builtin::Ceil => {
+                let rounding = if builtin == Builtin::Floor {
+                    Plain::F64Floor
+                } else {
+                    Plain::F64Ceil
+                };
+                self.plain(rounding);
+                self.import(Builtin::ToInteger.name(), &[F64], &[I64]);
+            }
+            Builtin::Round | Builtin::ToInteger => self.import(name, &[F64], &[I64]),
+            Builtin::ToReal => self.plain(Plain::F64ConvertI64S),
+            Builtin::Copy => self.import(name, &[I32], &[I32]),
+            Builtin::Sort => {
+                self.import(name, &[I32], &[]);
+                return false;
+            }
+            Builtin::Assert => {
+                self.import(name, &[I64], &[]);
+                return false;
+            }
+            Builtin::ReadInteger | Builtin::ReadBoolean => self.import(name, &[], &[I64]),
+            Builtin::ReadReal => self.import(name, &[], &[F64]),
+            Builtin::Length => self.import(name, &[I32], &[I64]),
+            Builtin::CharAt => self.import(name, &[I32, I64], &[I32]),
+            Builtin::Equals => self.import(name, &[I32; 2], &[I64]),
+            Builtin::Fill => unreachable!("Not called with its arguments"),
+        }
+        true
+    }
+
+    /// Leaves the address of the element less `CONTENTS` on the stack
+    fn element(&mut self, frame: &Frame, array: ValueId, index: ValueId) {
+        self.get(frame, array);
+        self.get(frame, index);
+        self.emit(Instruction::Call(Helper::Element.callee()));
+    }
+
+    /// Code of `operation`, returns whether it leaves a result on the stack
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn operation(&mut self, frame: &Frame, result: Option<ValueId>, operation: &Operation) -> bool {
+        match *operation {
+            Operation::Const(Constant::Int(constant)) => self.emit(Instruction::I64Const(constant)),
+            Operation::Const(Constant::Real(constant)) => {
+                self.emit(Instruction::F64Const(constant));
+            }
+            Operation::Const(Constant::Bool(constant)) => {
+                self.emit(Instruction::I64Const(constant.into()));
+            }
+            Operation::String { id } => {
+                let address = self.strings[id as usize];
+                self.emit(Instruction::I32Const(
+                    i32::try_from(address).expect("Data fits into memory"),
+                ));
+            }
+            Operation::Param { index } => {
+                self.emit(Instruction::LocalGet(
+                    u32::try_from(index).expect("Routines take few arguments"),
+                ));
+            }
+            Operation::Copy(id) => self.get(frame, id),
+            Operation::Phi(_) => {
+                let result = result.expect("Phis have a result");
+                self.emit(Instruction::LocalGet(frame.phi(result)));
+            }
+            Operation::Binary { op, lhs, rhs } => self.binary(frame, op, lhs, rhs),
+            Operation::Unary { op, value } => match op {
+                SemanticUnaryOperator::IntNeg => {
+                    self.get(frame, value);
+                    self.emit(Instruction::Call(Helper::Neg.callee()));
+                }
+                SemanticUnaryOperator::RealNeg => {
+                    self.get(frame, value);
+                    self.plain(Plain::F64Neg);
+                }
+                SemanticUnaryOperator::BoolNeg => {
+                    self.get(frame, value);
+                    self.emit(Instruction::I64Const(1));
+                    self.plain(Plain::I64Xor);
+                }
+            },
+            Operation::IntToReal(id) => return self.native(frame, Builtin::ToReal, &[id]),
+            Operation::RealToInt(id) => return self.native(frame, Builtin::ToInteger, &[id]),
+            Operation::IntToBool(id) => return self.native(frame, Builtin::ToBoolean, &[id]),
+            Operation::LoadGlobal { index } => {
+                let result = result.expect("Loads have a result");
+                self.emit(Instruction::I32Const(0));
+                self.emit(Instruction::Load(frame.valtype(result), self.global(index)));
+            }
+            Operation::StoreGlobal { index, value } => {
+                self.emit(Instruction::I32Const(0));
+                self.get(frame, value);
+                self.emit(Instruction::Store(frame.valtype(value), self.global(index)));
+                return false;
+            }
+            Operation::AllocRecord { type_id, .. } => {
+                self.emit(Instruction::I32Const(
+                    i32::try_from(type_id.0).expect("Few types"),
+                ));
+                self.import("record", &[ValType::I32], &[ValType::I32]);
+            }
+            Operation::AllocArray { type_id, size } => {
+                let size = i64::try_from(size).expect("Array sizes are checked by the compiler");
+                self.emit(Instruction::I32Const(
+                    i32::try_from(type_id.0).expect("Few types"),
+                ));
+                self.emit(Instruction::I64Const(size));
+                self.import("array", &[ValType::I32, ValType::I64], &[ValType::I32]);
+            }
+            Operation::ArraySize(array) => {
+                self.get(frame, array);
+                self.emit(Instruction::Load(ValType::I32, LENGTH));
+                self.plain(Plain::I64ExtendI32U);
+            }
+            Operation::LoadField { record, offset } => {
+                let result = result.expect("Loads have a result");
+                let offset = u32::try_from(offset).expect("Records have few fields");
+                self.get(frame, record);
+                self.emit(Instruction::Load(
+                    frame.valtype(result),
+                    CONTENTS + 8 * offset,
+                ));
+            }
+            Operation::StoreField {
+                record,
+                offset,
+                value,
+            } => {
+                let offset = u32::try_from(offset).expect("Records have few fields");
+                self.get(frame, record);
+                self.get(frame, value);
+                self.emit(Instruction::Store(
+                    frame.valtype(value),
+                    CONTENTS + 8 * offset,
+                ));
+                return false;
+            }
+            Operation::LoadElement { array, index } => {
+                let result = result.expect("Loads have a result");
+                self.element(frame, array, index);
+                self.emit(Instruction::Load(frame.valtype(result), CONTENTS));
+            }
+            Operation::StoreElement {
+                array,
+                index,
+                value,
+            } => {
+                self.element(frame, array, index);
+                self.get(frame, value);
+                self.emit(Instruction::Store(frame.valtype(value), CONTENTS));
+                return false;
+            }
+            Operation::Call {
+                function_label,
+                ref args,
+            } => {
+                for &arg in args {
+                    self.get(frame, arg);
+                }
+                self.emit(Instruction::Call(Callee::Function(
+                    self.functions[&function_label],
+                )));
+                return self.routines[&function_label].result.is_some();
+            }
+            Operation::CallNative { id, ref args } => {
+                let builtin = Builtin::from_id(id).expect("Host functions are rejected before");
+                return self.native(frame, builtin, args);
+            }
+            Operation::Print { type_id, value } => {
+                self.bits(frame, value);
+                self.emit(Instruction::I32Const(
+                    i32::try_from(type_id.0).expect("Few types"),
+                ));
+                self.import("print", &[ValType::I64, ValType::I32], &[]);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// `fail` is always imported, first
+const FAIL: Callee = Callee::Import(0);
+
+/// Calls `fail` with `fault` and the values left by `a` and `b`, it doesn't return
+fn fail(fault: Fault, a: Instruction, b: Instruction) -> [Instruction; 5] {
+    [
+        Instruction::I32Const(fault.code()),
+        a,
+        b,
+        Instruction::Call(FAIL),
+        Instruction::Plain(Plain::Unreachable),
+    ]
+}
+
+/// Code of the helper, checking its operands like the `vm` crate does
+fn helper(helper: Helper) -> Function {
+    use Instruction::{End, I32Const, I64Const, If, Load, LocalGet, LocalSet};
+    use ValType::{I32, I64};
+
+    let p = Instruction::Plain;
+    let overflow = || fail(Fault::IntegerOverflow, I64Const(0), I64Const(0));
+    let (name, params, results, locals, body): (_, _, _, _, Vec<Instruction>) = match helper {
+        // Overflown when the result has another sign than both operands
+        Helper::Add => ("add", vec![I64, I64], I64, vec![I64], {
+            let mut body = vec![LocalGet(0), LocalGet(1), p(Plain::I64Add), LocalSet(2)];
+            body.extend([LocalGet(0), LocalGet(2), p(Plain::I64Xor)]);
+            body.extend([LocalGet(1), LocalGet(2), p(Plain::I64Xor), p(Plain::I64And)]);
+            body.extend([I64Const(0), p(Plain::I64LtS), If]);
+            body.extend(overflow());
+            body.extend([End, LocalGet(2)]);
+            body
+        }),
+        // Overflown when the operands have different signs and the result not the first one's
+        Helper::Sub => ("sub", vec![I64, I64], I64, vec![I64], {
+            let mut body = vec![LocalGet(0), LocalGet(1), p(Plain::I64Sub), LocalSet(2)];
+            body.extend([LocalGet(0), LocalGet(1), p(Plain::I64Xor)]);
+            body.extend([LocalGet(0), LocalGet(2), p(Plain::I64Xor), p(Plain::I64And)]);
+            body.extend([I64Const(0), p(Plain::I64LtS), If]);
+            body.extend(overflow());
+            body.extend([End, LocalGet(2)]);
+            body
+        }),
+        // Overflown when dividing the result by one operand doesn't give the other one
+        Helper::Mul => ("mul", vec![I64, I64], I64, vec![I64], {
+            let mut body = vec![LocalGet(0), I64Const(-1), p(Plain::I64Eq), If];
+            body.extend([LocalGet(1), I64Const(i64::MIN), p(Plain::I64Eq), If]);
+            body.extend(overflow());
+            body.extend([
+                End,
+                I64Const(0),
+                LocalGet(1),
+                p(Plain::I64Sub),
+                p(Plain::Return),
+            ]);
+            body.extend([End, LocalGet(0), LocalGet(1), p(Plain::I64Mul), LocalSet(2)]);
+            body.extend([LocalGet(0), p(Plain::I64Eqz), p(Plain::I32Eqz), If]);
+            body.extend([LocalGet(2), LocalGet(0), p(Plain::I64DivS)]);
+            body.extend([LocalGet(1), p(Plain::I64Ne), If]);
+            body.extend(overflow());
+            body.extend([End, End, LocalGet(2)]);
+            body
+        }),
+        Helper::Div | Helper::Mod => {
+            let (name, operation) = if helper == Helper::Div {
+                ("div", Plain::I64DivS)
+            } else {
+                ("mod", Plain::I64RemS)
+            };
+            let mut body = vec![LocalGet(1), p(Plain::I64Eqz), If];
+            body.extend(fail(Fault::DivisionByZero, I64Const(0), I64Const(0)));
+            body.extend([End, LocalGet(0), I64Const(i64::MIN), p(Plain::I64Eq)]);
+            body.extend([
+                LocalGet(1),
+                I64Const(-1),
+                p(Plain::I64Eq),
+                p(Plain::I32And),
+                If,
+            ]);
+            body.extend(overflow());
+            body.extend([End, LocalGet(0), LocalGet(1), p(operation)]);
+            (name, vec![I64, I64], I64, Vec::new(), body)
+        }
+        Helper::Neg => ("neg", vec![I64], I64, Vec::new(), {
+            let mut body = vec![LocalGet(0), I64Const(i64::MIN), p(Plain::I64Eq), If];
+            body.extend(overflow());
+            body.extend([End, I64Const(0), LocalGet(0), p(Plain::I64Sub)]);
+            body
+        }),
+        // `array + 8 * index - 8`, elements are numbered from 1
+        Helper::Element => ("element", vec![I32, I64], I32, vec![I64], {
+            let mut body = vec![LocalGet(0), Load(I32, LENGTH), p(Plain::I64ExtendI32U)];
+            body.extend([LocalSet(2), LocalGet(1), I64Const(1), p(Plain::I64LtS)]);
+            body.extend([
+                LocalGet(1),
+                LocalGet(2),
+                p(Plain::I64GtS),
+                p(Plain::I32Or),
+                If,
+            ]);
+            body.extend(fail(Fault::IndexOutOfBounds, LocalGet(1), LocalGet(2)));
+            body.extend([End, LocalGet(0), LocalGet(1), p(Plain::I32WrapI64)]);
+            body.extend([I32Const(8), p(Plain::I32Mul), p(Plain::I32Add)]);
+            body.extend([I32Const(-8), p(Plain::I32Add)]);
+            body
+        }),
+    };
+    Function {
+        name: name.to_owned(),
+        signature: signature(&params, &[results]),
+        locals,
+        body,
+        export: None,
+    }
+}
+
+impl Lowering<'_> {
+    /// Assigns the phis of `to` their values coming from `from`
+    fn phi_moves(&mut self, frame: &Frame, function: &ir::Function, from: BlockId, to: BlockId) {
+        for (result, incoming) in cgen::phis(function.block(to)) {
+            for &(predecessor, id) in incoming {
+                if predecessor == from {
+                    self.get(frame, id);
+                    self.emit(Instruction::LocalSet(frame.phi(result)));
+                }
+            }
+        }
+    }
+
+    /// Goes from the code of `from`, nested `depth` deep in it, to `to`
+    fn jump(
+        &mut self,
+        frame: &Frame,
+        function: &ir::Function,
+        (from, to): (BlockId, BlockId),
+        depth: u32,
+    ) {
+        self.phi_moves(frame, function, from, to);
+        // The code of the next block follows
+        if to.0 != from.0 + 1 {
+            self.emit(Instruction::I32Const(
+                i32::try_from(to.0).expect("Routines have few blocks"),
+            ));
+            self.emit(Instruction::LocalSet(frame.block()));
+            self.emit(Instruction::Br(frame.block_count - 1 - from.0 + depth));
+        }
+    }
+
+    fn terminator(
+        &mut self,
+        frame: &Frame,
+        function: &ir::Function,
+        block: BlockId,
+        terminator: Terminator,
+    ) {
+        match terminator {
+            Terminator::Jump(target) => self.jump(frame, function, (block, target), 0),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.get(frame, condition);
+                self.plain(Plain::I32WrapI64);
+                self.emit(Instruction::If);
+                self.jump(frame, function, (block, then), 1);
+                self.emit(Instruction::Else);
+                self.jump(frame, function, (block, otherwise), 1);
+                self.emit(Instruction::End);
+            }
+            Terminator::Return(value) => {
+                if let Some(id) = value {
+                    self.get(frame, id);
+                }
+                self.plain(Plain::Return);
+            }
+            Terminator::Panic { code } => self.code.extend(fail(
+                Fault::Panic,
+                Instruction::I64Const(code.cast_signed()),
+                Instruction::I64Const(0),
+            )),
+        }
+    }
+
+    fn routine(&mut self, label: u64, function: &ir::Function, main: u64) -> Function {
+        let frame = Frame::new(function);
+        self.emit(Instruction::Loop);
+        for _ in &function.blocks {
+            self.emit(Instruction::Block);
+        }
+        self.emit(Instruction::LocalGet(frame.block()));
+        self.emit(Instruction::BrTable((0..frame.block_count).collect(), 0));
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            self.emit(Instruction::End);
+            for instruction in &block.instructions {
+                let result = instruction.result.map(|(result, _)| result);
+                let pushed = self.operation(&frame, result, &instruction.operation);
+                match (result, pushed) {
+                    (Some(result), true) => self.emit(Instruction::LocalSet(frame.local(result))),
+                    // Procedures give 0 like they do in the `vm` crate
+                    (Some(result), false) => {
+                        self.emit(Instruction::I64Const(0));
+                        self.emit(Instruction::LocalSet(frame.local(result)));
+                    }
+                    (None, true) => self.plain(Plain::Drop),
+                    (None, false) => {}
+                }
+            }
+            self.terminator(&frame, function, id, block.terminator);
+        }
+        self.emit(Instruction::End);
+        self.plain(Plain::Unreachable);
+        let params: Vec<_> = function.args.iter().map(|&t| valtype(t)).collect();
+        let results: Vec<_> = function.result.map(valtype).into_iter().collect();
+        Function {
+            name: format!("r{label}"),
+            signature: signature(&params, &results),
+            locals: frame.locals(),
+            body: core::mem::take(&mut self.code),
+            export: (label == main).then_some("main"),
+        }
+    }
+}
+
+/// The header, the globals, the strings of `module` and the names of its enums,
+/// returns the address of the globals and of the strings
+fn data(module: &Module) -> (Data, u32, Vec<u32>) {
+    let mut data = Data(Vec::new());
+    // The heap pointer is set at the end
+    data.word(0);
+    let type_count = u32::try_from(module.rtti.0.len()).expect("Few types");
+    data.word(type_count);
+    data.0
+        .resize(data.0.len() + (TYPE_SIZE * type_count) as usize, 0);
+    data.align();
+    let globals = data.address();
+    data.0
+        .resize(data.0.len() + 8 * module.global_count as usize, 0);
+    for (description, element) in (TYPES..).step_by(TYPE_SIZE as usize).zip(&module.rtti.0) {
+        let (kind, words) = match element {
+            RTTIElement::Primitive(PrimitiveRTTI { id }) => match *id {
+                TypeId::REAL => (Kind::Real, [0, 0]),
+                TypeId::BOOLEAN => (Kind::Boolean, [0, 0]),
+                TypeId::STRING => (Kind::String, [0, 0]),
+                _ => (Kind::Integer, [0, 0]),
+            },
+            RTTIElement::Enum(EnumRTTI { variants, .. }) => {
+                let names: Vec<_> = variants
+                    .iter()
+                    .map(|variant| data.string(variant))
+                    .collect();
+                data.align();
+                let table = data.address();
+                for name in names {
+                    data.word(name);
+                }
+                let count = u32::try_from(variants.len()).expect("Enums have few variants");
+                (Kind::Enum, [count, table])
+            }
+            RTTIElement::Record(RecordRTTI { field_ids, .. }) => {
+                let table = data.address();
+                for field in field_ids {
+                    data.word(field.0);
+                }
+                let count = u32::try_from(field_ids.len()).expect("Records have few fields");
+                (Kind::Record, [count, table])
+            }
+            RTTIElement::Array(ArrayRTTI { element_id, .. }) => (Kind::Array, [element_id.0, 0]),
+        };
+        data.set(description, kind.code());
+        data.set(description + 4, words[0]);
+        data.set(description + 8, words[1]);
+    }
+    let strings = module
+        .strings
+        .iter()
+        .map(|string| data.string(string))
+        .collect();
+    data.align();
+    let heap = data.address();
+    data.set(HEAP_POINTER, heap);
+    (data, globals, strings)
+}
+
+/// Module of the whole program, `module` and `routines` are linked, see `linker::link`
+/// and `linker::link_ir`. Its `main` export runs the `main` routine.
+///
+/// # Panics
+///
+/// If the size of an array doesn't fit into `i64`, which the checker rules out
+pub fn compile(module: &Module, routines: &BTreeMap<u64, ir::Function>) -> Result<Wasm, CgenError> {
+    let main = cgen::entry(module, routines)?;
+    let (data, globals, strings) = data(module);
+    let helpers = u32::try_from(Helper::ALL.len()).expect("Few helpers");
+    let mut lowering = Lowering {
+        routines,
+        imports: vec![Import {
+            name: "fail",
+            signature: signature(&[ValType::I32, ValType::I64, ValType::I64], &[]),
+        }],
+        functions: routines.keys().copied().zip(helpers..).collect(),
+        globals,
+        strings,
+        code: Vec::new(),
+    };
+    let mut functions: Vec<_> = Helper::ALL.into_iter().map(helper).collect();
+    for (&label, function) in routines {
+        functions.push(lowering.routine(label, function, main));
+    }
+    Ok(Wasm {
+        imports: lowering.imports,
+        functions,
+        data: data.0,
+        data_start: HEAP_POINTER,
+    })
+}
@@ -0,0 +1,624 @@
+//! WebAssembly modules as `wasm::compile` builds them, encoded as binary or printed as text
+
+use core::fmt::{self, Write as _};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+            ValType::F64 => 0x7C,
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+            ValType::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.params.is_empty() {
+            write!(f, " (param")?;
+            for param in &self.params {
+                write!(f, " {param}")?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(result) = self.results.first() {
+            write!(f, " (result {result})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Function of the host, from the `env` module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub name: &'static str,
+    pub signature: Signature,
+}
+
+/// What a call goes to, functions are numbered after the imports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Callee {
+    Import(u32),
+    Function(u32),
+}
+
+/// Instructions without immediates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Plain {
+    Unreachable,
+    Return,
+    Drop,
+    Select,
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32GtS,
+    I32LeS,
+    I32GeS,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64GtS,
+    I64LeS,
+    I64GeS,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I32Add,
+    I32Mul,
+    I32And,
+    I32Or,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I64Or,
+    I64Xor,
+    F64Abs,
+    F64Neg,
+    F64Ceil,
+    F64Floor,
+    F64Sqrt,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    I32WrapI64,
+    I64ExtendI32U,
+    F64ConvertI64S,
+    I64ReinterpretF64,
+}
+
+impl Plain {
+    fn opcode(self) -> u8 {
+        match self {
+            Plain::Unreachable => 0x00,
+            Plain::Return => 0x0F,
+            Plain::Drop => 0x1A,
+            Plain::Select => 0x1B,
+            Plain::I32Eqz => 0x45,
+            Plain::I32Eq => 0x46,
+            Plain::I32Ne => 0x47,
+            Plain::I32LtS => 0x48,
+            Plain::I32GtS => 0x4A,
+            Plain::I32LeS => 0x4C,
+            Plain::I32GeS => 0x4E,
+            Plain::I64Eqz => 0x50,
+            Plain::I64Eq => 0x51,
+            Plain::I64Ne => 0x52,
+            Plain::I64LtS => 0x53,
+            Plain::I64GtS => 0x55,
+            Plain::I64LeS => 0x57,
+            Plain::I64GeS => 0x59,
+            Plain::F64Eq => 0x61,
+            Plain::F64Ne => 0x62,
+            Plain::F64Lt => 0x63,
+            Plain::F64Gt => 0x64,
+            Plain::F64Le => 0x65,
+            Plain::F64Ge => 0x66,
+            Plain::I32Add => 0x6A,
+            Plain::I32Mul => 0x6C,
+            Plain::I32And => 0x71,
+            Plain::I32Or => 0x72,
+            Plain::I64Add => 0x7C,
+            Plain::I64Sub => 0x7D,
+            Plain::I64Mul => 0x7E,
+            Plain::I64DivS => 0x7F,
+            Plain::I64RemS => 0x81,
+            Plain::I64And => 0x83,
+            Plain::I64Or => 0x84,
+            Plain::I64Xor => 0x85,
+            Plain::F64Abs => 0x99,
+            Plain::F64Neg => 0x9A,
+            Plain::F64Ceil => 0x9B,
+            Plain::F64Floor => 0x9C,
+            Plain::F64Sqrt => 0x9F,
+            Plain::F64Add => 0xA0,
+            Plain::F64Sub => 0xA1,
+            Plain::F64Mul => 0xA2,
+            Plain::F64Div => 0xA3,
+            Plain::I32WrapI64 => 0xA7,
+            Plain::I64ExtendI32U => 0xAD,
+            Plain::F64ConvertI64S => 0xB9,
+            Plain::I64ReinterpretF64 => 0xBD,
+        }
+    }
+
+    /// Name in the text format
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Plain::Unreachable => "unreachable",
+            Plain::Return => "return",
+            Plain::Drop => "drop",
+            Plain::Select => "select",
+            Plain::I32Eqz => "i32.eqz",
+            Plain::I32Eq => "i32.eq",
+            Plain::I32Ne => "i32.ne",
+            Plain::I32LtS => "i32.lt_s",
+            Plain::I32GtS => "i32.gt_s",
+            Plain::I32LeS => "i32.le_s",
+            Plain::I32GeS => "i32.ge_s",
+            Plain::I64Eqz => "i64.eqz",
+            Plain::I64Eq => "i64.eq",
+            Plain::I64Ne => "i64.ne",
+            Plain::I64LtS => "i64.lt_s",
+            Plain::I64GtS => "i64.gt_s",
+            Plain::I64LeS => "i64.le_s",
+            Plain::I64GeS => "i64.ge_s",
+            Plain::F64Eq => "f64.eq",
+            Plain::F64Ne => "f64.ne",
+            Plain::F64Lt => "f64.lt",
+            Plain::F64Gt => "f64.gt",
+            Plain::F64Le => "f64.le",
+            Plain::F64Ge => "f64.ge",
+            Plain::I32Add => "i32.add",
+            Plain::I32Mul => "i32.mul",
+            Plain::I32And => "i32.and",
+            Plain::I32Or => "i32.or",
+            Plain::I64Add => "i64.add",
+            Plain::I64Sub => "i64.sub",
+            Plain::I64Mul => "i64.mul",
+            Plain::I64DivS => "i64.div_s",
+            Plain::I64RemS => "i64.rem_s",
+            Plain::I64And => "i64.and",
+            Plain::I64Or => "i64.or",
+            Plain::I64Xor => "i64.xor",
+            Plain::F64Abs => "f64.abs",
+            Plain::F64Neg => "f64.neg",
+            Plain::F64Ceil => "f64.ceil",
+            Plain::F64Floor => "f64.floor",
+            Plain::F64Sqrt => "f64.sqrt",
+            Plain::F64Add => "f64.add",
+            Plain::F64Sub => "f64.sub",
+            Plain::F64Mul => "f64.mul",
+            Plain::F64Div => "f64.div",
+            Plain::I32WrapI64 => "i32.wrap_i64",
+            Plain::I64ExtendI32U => "i64.extend_i32_u",
+            Plain::F64ConvertI64S => "f64.convert_i64_s",
+            Plain::I64ReinterpretF64 => "i64.reinterpret_f64",
+        }
+    }
+}
+
+/// Instructions in use, blocks have no parameters and no results
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Instruction {
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+    Call(Callee),
+    LocalGet(u32),
+    LocalSet(u32),
+    /// With the offset
+    Load(ValType, u32),
+    Store(ValType, u32),
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    Plain(Plain),
+}
+
+impl Instruction {
+    fn opcode(&self) -> u8 {
+        match *self {
+            Instruction::Block => 0x02,
+            Instruction::Loop => 0x03,
+            Instruction::If => 0x04,
+            Instruction::Else => 0x05,
+            Instruction::End => 0x0B,
+            Instruction::Br(_) => 0x0C,
+            Instruction::BrTable(..) => 0x0E,
+            Instruction::Call(_) => 0x10,
+            Instruction::LocalGet(_) => 0x20,
+            Instruction::LocalSet(_) => 0x21,
+            Instruction::Load(ValType::I32, _) => 0x28,
+            Instruction::Load(ValType::I64, _) => 0x29,
+            Instruction::Load(ValType::F64, _) => 0x2B,
+            Instruction::Store(ValType::I32, _) => 0x36,
+            Instruction::Store(ValType::I64, _) => 0x37,
+            Instruction::Store(ValType::F64, _) => 0x39,
+            Instruction::I32Const(_) => 0x41,
+            Instruction::I64Const(_) => 0x42,
+            Instruction::F64Const(_) => 0x44,
+            Instruction::Plain(plain) => plain.opcode(),
+        }
+    }
+
+    /// Name in the text format
+    fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Block => "block",
+            Instruction::Loop => "loop",
+            Instruction::If => "if",
+            Instruction::Else => "else",
+            Instruction::End => "end",
+            Instruction::Br(_) => "br",
+            Instruction::BrTable(..) => "br_table",
+            Instruction::Call(_) => "call",
+            Instruction::LocalGet(_) => "local.get",
+            Instruction::LocalSet(_) => "local.set",
+            Instruction::Load(ValType::I32, _) => "i32.load",
+            Instruction::Load(ValType::I64, _) => "i64.load",
+            Instruction::Load(ValType::F64, _) => "f64.load",
+            Instruction::Store(ValType::I32, _) => "i32.store",
+            Instruction::Store(ValType::I64, _) => "i64.store",
+            Instruction::Store(ValType::F64, _) => "f64.store",
+            Instruction::I32Const(_) => "i32.const",
+            Instruction::I64Const(_) => "i64.const",
+            Instruction::F64Const(_) => "f64.const",
+            Instruction::Plain(plain) => plain.mnemonic(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    /// Without the `$`
+    pub(crate) name: String,
+    pub(crate) signature: Signature,
+    pub(crate) locals: Vec<ValType>,
+    /// Without the final `end`
+    pub(crate) body: Vec<Instruction>,
+    pub(crate) export: Option<&'static str>,
+}
+
+/// A whole program: its routines, the runtime functions it imports and its memory,
+/// exported as `memory` together with `main`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wasm {
+    pub imports: Vec<Import>,
+    pub(crate) functions: Vec<Function>,
+    /// Initial contents of memory, from `data_start`
+    pub(crate) data: Vec<u8>,
+    pub(crate) data_start: u32,
+}
+
+const PAGE: u32 = 64 * 1024;
+
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = u8::try_from(value & 0x7F).expect("Masked to 7 bits");
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = u8::try_from(value & 0x7F).expect("Masked to 7 bits");
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn length(out: &mut Vec<u8>, length: usize) {
+    unsigned(out, u64::try_from(length).expect("Lengths fit into u64"));
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    length(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    length(out, contents.len());
+    out.extend_from_slice(contents);
+}
+
+/// `f64.const` operand, `{:?}` of finite reals reads back the same
+fn real(value: f64) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        format!("{sign}nan:0x{:x}", value.to_bits() & ((1 << 52) - 1))
+    } else if value.is_infinite() {
+        format!("{sign}inf")
+    } else {
+        format!("{value:?}")
+    }
+}
+
+impl Wasm {
+    fn pages(&self) -> u32 {
+        let end = self.data_start + u32::try_from(self.data.len()).expect("Data fits into memory");
+        end.div_ceil(PAGE) + 1
+    }
+
+    fn callee(&self, callee: Callee) -> u32 {
+        match callee {
+            Callee::Import(index) => index,
+            Callee::Function(index) => {
+                u32::try_from(self.imports.len()).expect("Few imports") + index
+            }
+        }
+    }
+
+    fn callee_name(&self, callee: Callee) -> &str {
+        match callee {
+            Callee::Import(index) => self.imports[index as usize].name,
+            Callee::Function(index) => &self.functions[index as usize].name,
+        }
+    }
+
+    fn instruction(&self, out: &mut Vec<u8>, instruction: &Instruction) {
+        out.push(instruction.opcode());
+        match *instruction {
+            Instruction::Block | Instruction::Loop | Instruction::If => out.push(0x40),
+            Instruction::Br(depth)
+            | Instruction::LocalGet(depth)
+            | Instruction::LocalSet(depth) => {
+                unsigned(out, depth.into());
+            }
+            Instruction::BrTable(ref targets, default) => {
+                length(out, targets.len());
+                for &target in targets {
+                    unsigned(out, target.into());
+                }
+                unsigned(out, default.into());
+            }
+            Instruction::Call(callee) => unsigned(out, self.callee(callee).into()),
+            Instruction::Load(t, offset) | Instruction::Store(t, offset) => {
+                unsigned(out, if t == ValType::I32 { 2 } else { 3 });
+                unsigned(out, offset.into());
+            }
+            Instruction::I32Const(value) => signed(out, value.into()),
+            Instruction::I64Const(value) => signed(out, value),
+            Instruction::F64Const(value) => out.extend_from_slice(&value.to_le_bytes()),
+            Instruction::Else | Instruction::End | Instruction::Plain(_) => {}
+        }
+    }
+
+    /// The module in the binary format
+    ///
+    /// # Panics
+    ///
+    /// If a function has more than `u64::MAX` locals, which no routine has
+    #[must_use]
+    pub fn binary(&self) -> Vec<u8> {
+        let mut signatures: Vec<&Signature> = Vec::new();
+        let all = self
+            .imports
+            .iter()
+            .map(|import| &import.signature)
+            .chain(self.functions.iter().map(|function| &function.signature));
+        for signature in all {
+            if !signatures.contains(&signature) {
+                signatures.push(signature);
+            }
+        }
+        let index = |signature: &Signature| {
+            let position = signatures
+                .iter()
+                .position(|&other| other == signature)
+                .expect("Every signature is listed");
+            u64::try_from(position).expect("Few signatures")
+        };
+
+        let mut out = b"\0asm".to_vec();
+        out.extend_from_slice(&1_u32.to_le_bytes());
+
+        let mut types = Vec::new();
+        length(&mut types, signatures.len());
+        for signature in &signatures {
+            types.push(0x60);
+            for list in [&signature.params, &signature.results] {
+                length(&mut types, list.len());
+                types.extend(list.iter().map(|t| t.code()));
+            }
+        }
+        section(&mut out, 1, &types);
+
+        let mut imports = Vec::new();
+        length(&mut imports, self.imports.len());
+        for import in &self.imports {
+            name(&mut imports, "env");
+            name(&mut imports, import.name);
+            imports.push(0x00);
+            unsigned(&mut imports, index(&import.signature));
+        }
+        section(&mut out, 2, &imports);
+
+        let mut functions = Vec::new();
+        length(&mut functions, self.functions.len());
+        for function in &self.functions {
+            unsigned(&mut functions, index(&function.signature));
+        }
+        section(&mut out, 3, &functions);
+
+        let mut memory = vec![1, 0x00];
+        unsigned(&mut memory, self.pages().into());
+        section(&mut out, 5, &memory);
+
+        let mut exports = Vec::new();
+        let exported: Vec<_> = (0_u32..)
+            .zip(&self.functions)
+            .filter_map(|(index, function)| function.export.map(|export| (export, index)))
+            .collect();
+        length(&mut exports, exported.len() + 1);
+        name(&mut exports, "memory");
+        exports.extend_from_slice(&[0x02, 0x00]);
+        for (export, index) in exported {
+            name(&mut exports, export);
+            exports.push(0x00);
+            unsigned(&mut exports, self.callee(Callee::Function(index)).into());
+        }
+        section(&mut out, 7, &exports);
+
+        let mut code = Vec::new();
+        length(&mut code, self.functions.len());
+        for function in &self.functions {
+            let mut body = Vec::new();
+            length(&mut body, function.locals.len());
+            for local in &function.locals {
+                body.push(1);
+                body.push(local.code());
+            }
+            for instruction in &function.body {
+                self.instruction(&mut body, instruction);
+            }
+            body.push(Instruction::End.opcode());
+            length(&mut code, body.len());
+            code.extend_from_slice(&body);
+        }
+        section(&mut out, 10, &code);
+
+        let mut data = vec![1, 0x00, Instruction::I32Const(0).opcode()];
+        signed(&mut data, self.data_start.into());
+        data.push(Instruction::End.opcode());
+        length(&mut data, self.data.len());
+        data.extend_from_slice(&self.data);
+        section(&mut out, 11, &data);
+        out
+    }
+}
+
+/// The module in the text format
+impl fmt::Display for Wasm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "(module")?;
+        for import in &self.imports {
+            writeln!(
+                f,
+                "  (import \"env\" \"{name}\" (func ${name}{}))",
+                import.signature,
+                name = import.name
+            )?;
+        }
+        writeln!(f, "  (memory (export \"memory\") {})", self.pages())?;
+        let mut data = String::new();
+        for &byte in &self.data {
+            if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+                data.push(char::from(byte));
+            } else {
+                write!(data, "\\{byte:02x}")?;
+            }
+        }
+        writeln!(f, "  (data (i32.const {}) \"{data}\")", self.data_start)?;
+        for function in &self.functions {
+            write!(f, "  (func ${}", function.name)?;
+            if let Some(export) = function.export {
+                write!(f, " (export \"{export}\")")?;
+            }
+            write!(f, "{}", function.signature)?;
+            if !function.locals.is_empty() {
+                write!(f, " (local")?;
+                for local in &function.locals {
+                    write!(f, " {local}")?;
+                }
+                write!(f, ")")?;
+            }
+            writeln!(f)?;
+            let mut depth = 2;
+            for instruction in &function.body {
+                if matches!(instruction, Instruction::End | Instruction::Else) {
+                    depth -= 1;
+                }
+                write!(
+                    f,
+                    "{:width$}{}",
+                    "",
+                    instruction.mnemonic(),
+                    width = depth * 2
+                )?;
+                match *instruction {
+                    Instruction::Br(value)
+                    | Instruction::LocalGet(value)
+                    | Instruction::LocalSet(value) => write!(f, " {value}")?,
+                    Instruction::BrTable(ref targets, default) => {
+                        for target in targets {
+                            write!(f, " {target}")?;
+                        }
+                        write!(f, " {default}")?;
+                    }
+                    Instruction::Call(callee) => write!(f, " ${}", self.callee_name(callee))?,
+                    Instruction::Load(_, offset) | Instruction::Store(_, offset) => {
+                        if offset > 0 {
+                            write!(f, " offset={offset}")?;
+                        }
+                    }
+                    Instruction::I32Const(value) => write!(f, " {value}")?,
+                    Instruction::I64Const(value) => write!(f, " {value}")?,
+                    Instruction::F64Const(value) => write!(f, " {}", real(value))?,
+                    Instruction::Block
+                    | Instruction::Loop
+                    | Instruction::If
+                    | Instruction::Else
+                    | Instruction::End
+                    | Instruction::Plain(_) => {}
+                }
+                writeln!(f)?;
+                if matches!(
+                    instruction,
+                    Instruction::Block | Instruction::Loop | Instruction::If | Instruction::Else
+                ) {
+                    depth += 1;
+                }
+            }
+            writeln!(f, "  )")?;
+        }
+        writeln!(f, ")")
+    }
+}
@@ -0,0 +1,160 @@
+use expect_test::expect;
+
+use super::*;
+use crate::ir::FunctionBuilder;
+use crate::test_support::{self, int, module, print, routine};
+
+/// `routine difference(a : integer, b : integer) : integer is return a - b; end;`
+/// `routine main() is print difference(3, 10); print "hi"; end;`
+fn program() -> (Module, BTreeMap<u64, ir::Function>) {
+    let mut builder = FunctionBuilder::new(
+        "difference",
+        vec![IrType::Int, IrType::Int],
+        Some(IrType::Int),
+    );
+    let lhs = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let rhs = builder.value(IrType::Int, Operation::Param { index: 1 });
+    let difference = test_support::binary(
+        &mut builder,
+        IrType::Int,
+        SemanticBinaryOperator::IntSub,
+        lhs,
+        rhs,
+    );
+    builder.terminate(Terminator::Return(Some(difference)));
+    let difference = builder.finish();
+
+    let mut builder = FunctionBuilder::new("main", Vec::new(), None);
+    let three = int(&mut builder, 3);
+    let ten = int(&mut builder, 10);
+    let value = builder.value(
+        IrType::Int,
+        Operation::Call {
+            function_label: 1,
+            args: vec![three, ten],
+        },
+    );
+    print(&mut builder, TypeId::INTEGER, value);
+    let greeting = builder.value(IrType::Ref, Operation::String { id: 0 });
+    print(&mut builder, TypeId::STRING, greeting);
+    builder.terminate(Terminator::Return(None));
+    let routines = BTreeMap::from([(0, builder.finish()), (1, difference)]);
+    let module = module(
+        vec![
+            routine("main", 0, &[], TypeId::INTEGER),
+            routine(
+                "difference",
+                1,
+                &[TypeId::INTEGER, TypeId::INTEGER],
+                TypeId::INTEGER,
+            ),
+        ],
+        &["hi"],
+    );
+    (module, routines)
+}
+
+#[test]
+fn text() {
+    let (module, routines) = program();
+    let wat = compile(&module, &routines)
+        .expect("There is a main")
+        .to_string();
+    assert!(wat.starts_with(
+        "(module\n  (import \"env\" \"fail\" (func $fail (param i32 i64 i64)))\n  \
+         (import \"env\" \"print\" (func $print (param i64 i32)))\n  (memory (export \"memory\") 2)\n"
+    ));
+    let (_, routines) = wat.split_once("  (func $r0").expect("main is there");
+    expect![[r#"
+         (export "main") (local i64 i64 i64 i32 i32)
+            loop
+              block
+                local.get 4
+                br_table 0 0
+              end
+              i64.const 3
+              local.set 0
+              i64.const 10
+              local.set 1
+              local.get 0
+              local.get 1
+              call $r1
+              local.set 2
+              local.get 2
+              i32.const 0
+              call $print
+              i32.const 200
+              local.set 3
+              local.get 3
+              i64.extend_i32_u
+              i32.const 3
+              call $print
+              return
+            end
+            unreachable
+          )
+          (func $r1 (param i64 i64) (result i64) (local i64 i64 i64 i32)
+            loop
+              block
+                local.get 5
+                br_table 0 0
+              end
+              local.get 0
+              local.set 2
+              local.get 1
+              local.set 3
+              local.get 2
+              local.get 3
+              call $sub
+              local.set 4
+              local.get 4
+              return
+            end
+            unreachable
+          )
+        )
+    "#]]
+    .assert_eq(routines);
+}
+
+/// Ids of the sections of `binary` after checking the header and that they end with it
+fn sections(binary: &[u8]) -> Vec<u8> {
+    let (header, mut rest) = binary.split_at(8);
+    assert_eq!(header, b"\0asm\x01\0\0\0");
+    let mut ids = Vec::new();
+    while let Some((&id, tail)) = rest.split_first() {
+        let mut size = 0;
+        let mut shift = 0;
+        let mut tail = tail;
+        loop {
+            let (&byte, next) = tail.split_first().expect("Sizes are complete");
+            tail = next;
+            size |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        ids.push(id);
+        rest = tail.get(size..).expect("Sections fit into the module");
+    }
+    ids
+}
+
+#[test]
+fn binary() {
+    let (module, routines) = program();
+    let wasm = compile(&module, &routines).expect("There is a main");
+    // Type, import, function, memory, export, code and data
+    assert_eq!(sections(&wasm.binary()), [1, 2, 3, 5, 7, 10, 11]);
+}
+
+#[test]
+fn no_main() {
+    let (mut module, routines) = program();
+    let _main = module.functions.0.remove(0);
+    assert!(matches!(
+        compile(&module, &routines),
+        Err(CgenError::NoMain)
+    ));
+}
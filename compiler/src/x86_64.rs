@@ -0,0 +1,759 @@
+//! Native code for x86-64 Linux: assembly for the GNU assembler in AT&T syntax, built into an
+//! executable together with the runtime of `cgen` by the system C compiler, see `build_executable`.
+//!
+//! Every value of the IR has a stack slot in the frame of its routine, operations load their
+//! operands into registers and store their result back. Routines take their arguments on the
+//! stack and return in `%rax`, reals as their bits, the runtime is called following the System V
+//! ABI. Objects are laid out like the structs of the runtime: their `TypeId` padded to 8 bytes,
+//! then the fields of records, or the length and the elements of arrays and strings.
+
+use core::fmt::{self, Write as _};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::process::Command;
+use std::{env, fs, io, process};
+
+use crate::builtins::Builtin;
+use crate::bytecode::{
+    ArrayRTTI, EnumRTTI, Module, PrimitiveRTTI, RTTIElement, RecordRTTI, TypeId,
+};
+use crate::cgen::{self, CgenError};
+use crate::consteval::Constant;
+use crate::ir::{self, BlockId, IrType, Operation, Terminator, ValueId};
+use crate::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+#[cfg(test)]
+mod tests;
+
+/// Registers of the integer arguments of System V calls
+const ARGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+/// Registers of the floating point arguments of System V calls used by the runtime
+const REAL_ARGS: [&str; 2] = ["%xmm0", "%xmm1"];
+
+/// Offset of the first field of records, after the type
+const FIELDS: u64 = 8;
+/// Offset of the length of arrays and strings
+const LENGTH: u64 = 8;
+/// Offset of the elements of arrays and the bytes of strings
+const ELEMENTS: u64 = 16;
+
+/// Argument of a call into the runtime
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// Passed by its type, reals in `%xmm` registers
+    Value(ValueId),
+    /// Bits of the value in a general purpose register, for the runtime's `value` union
+    Bits(ValueId),
+    Int(i64),
+}
+
+/// How a runtime function returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Returns {
+    Nothing,
+    /// C's `int`, sign extended
+    Int32,
+    Int64,
+    Real,
+}
+
+/// C source of the runtime, with its functions visible to the assembly
+#[must_use]
+pub fn runtime() -> String {
+    format!(
+        "#define RT\n{}\nextern const struct type types[];\n\n{}",
+        cgen::RUNTIME_TYPES,
+        cgen::RUNTIME
+    )
+}
+
+/// Escapes everything but printable ASCII for `.ascii`
+fn ascii(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(char::from(byte));
+            }
+            b' '..=b'~' => literal.push(char::from(byte)),
+            _ => write!(literal, "\\{byte:03o}").expect("Writing to a string won't fail"),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Value of `enum kind` of the runtime
+fn kind(element: &RTTIElement) -> u32 {
+    match *element {
+        RTTIElement::Primitive(PrimitiveRTTI { id }) => match id {
+            TypeId::REAL => 1,
+            TypeId::BOOLEAN => 2,
+            TypeId::STRING => 3,
+            _ => 0,
+        },
+        RTTIElement::Enum(_) => 4,
+        RTTIElement::Record(_) => 5,
+        RTTIElement::Array(_) => 6,
+    }
+}
+
+/// Stack slot of a value below `%rbp`
+fn slot(id: ValueId) -> String {
+    format!("-{}(%rbp)", (id.0 as usize + 1) * 8)
+}
+
+/// Stack slots of the values of a routine
+struct Frame {
+    label: u64,
+    types: HashMap<ValueId, IrType>,
+    /// Phis are assigned on the edges to their block, then copied to their value
+    phis: HashMap<ValueId, usize>,
+    value_count: usize,
+}
+
+impl Frame {
+    fn new(label: u64, function: &ir::Function) -> Self {
+        let types = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| instruction.result)
+            .collect();
+        let phis = function
+            .blocks
+            .iter()
+            .flat_map(cgen::phis)
+            .enumerate()
+            .map(|(index, (result, _))| (result, index))
+            .collect();
+        Self {
+            label,
+            types,
+            phis,
+            value_count: function.value_count as usize,
+        }
+    }
+
+    /// Bytes below `%rbp`, keeping `%rsp` aligned to 16 bytes
+    fn size(&self) -> usize {
+        (self.value_count + self.phis.len()).div_ceil(2) * 16
+    }
+
+    fn phi(&self, id: ValueId) -> String {
+        format!("-{}(%rbp)", (self.value_count + self.phis[&id] + 1) * 8)
+    }
+
+    fn block(&self, block: BlockId) -> String {
+        format!(".L{}_{block}", self.label)
+    }
+
+    fn overflow(&self) -> String {
+        format!(".L{}_overflow", self.label)
+    }
+}
+
+struct Assembly<'a> {
+    module: &'a Module,
+    routines: &'a BTreeMap<u64, ir::Function>,
+    out: String,
+}
+
+impl Assembly<'_> {
+    fn line(&mut self, line: fmt::Arguments<'_>) -> fmt::Result {
+        writeln!(self.out, "    {line}")
+    }
+
+    fn load(&mut self, id: ValueId, register: &str) -> fmt::Result {
+        self.line(format_args!("movq {}, {register}", slot(id)))
+    }
+
+    /// Calls `function` of the runtime or the C library, the result is left in `%rax`
+    fn call(
+        &mut self,
+        frame: &Frame,
+        function: &str,
+        args: &[Arg],
+        returns: Returns,
+    ) -> fmt::Result {
+        let (mut ints, mut reals) = (ARGS.iter(), REAL_ARGS.iter());
+        for &arg in args {
+            match arg {
+                Arg::Value(id) if frame.types[&id] == IrType::Real => {
+                    let register = reals.next().expect("Runtime functions take few reals");
+                    self.line(format_args!("movsd {}, {register}", slot(id)))?;
+                }
+                Arg::Value(id) | Arg::Bits(id) => {
+                    let register = ints.next().expect("Runtime functions take few arguments");
+                    self.load(id, register)?;
+                }
+                Arg::Int(value) => {
+                    let register = ints.next().expect("Runtime functions take few arguments");
+                    self.line(format_args!("movq ${value}, {register}"))?;
+                }
+            }
+        }
+        self.line(format_args!("call {function}@PLT"))?;
+        match returns {
+            // Procedures give 0 like they do in the `vm` crate
+            Returns::Nothing => self.line(format_args!("xorl %eax, %eax")),
+            Returns::Int32 => self.line(format_args!("movslq %eax, %rax")),
+            Returns::Int64 => Ok(()),
+            Returns::Real => self.line(format_args!("movq %xmm0, %rax")),
+        }
+    }
+
+    /// Sets `%rax` to the flag `condition` of the last comparison
+    fn set(&mut self, condition: &str) -> fmt::Result {
+        self.line(format_args!("set{condition} %al"))?;
+        self.line(format_args!("movzbl %al, %eax"))
+    }
+
+    fn compare_ints(&mut self, lhs: ValueId, rhs: ValueId, condition: &str) -> fmt::Result {
+        self.load(lhs, "%rax")?;
+        self.line(format_args!("cmpq {}, %rax", slot(rhs)))?;
+        self.set(condition)
+    }
+
+    /// Compares `first` with `second`, false when one of them is NaN but for `ne`
+    fn compare_reals(&mut self, first: ValueId, second: ValueId, condition: &str) -> fmt::Result {
+        self.line(format_args!("movsd {}, %xmm0", slot(first)))?;
+        self.line(format_args!("ucomisd {}, %xmm0", slot(second)))?;
+        // Unordered operands set the zero, parity and carry flags
+        match condition {
+            "e" => {
+                self.line(format_args!("sete %al"))?;
+                self.line(format_args!("setnp %cl"))?;
+                self.line(format_args!("andb %cl, %al"))?;
+                self.line(format_args!("movzbl %al, %eax"))
+            }
+            "ne" => {
+                self.line(format_args!("setne %al"))?;
+                self.line(format_args!("setp %cl"))?;
+                self.line(format_args!("orb %cl, %al"))?;
+                self.line(format_args!("movzbl %al, %eax"))
+            }
+            _ => self.set(condition),
+        }
+    }
+
+    fn real_arithmetic(&mut self, lhs: ValueId, rhs: ValueId, instruction: &str) -> fmt::Result {
+        self.line(format_args!("movsd {}, %xmm0", slot(lhs)))?;
+        self.line(format_args!("{instruction} {}, %xmm0", slot(rhs)))?;
+        self.line(format_args!("movq %xmm0, %rax"))
+    }
+
+    /// Integer arithmetic failing on overflow like the `vm` crate does
+    fn checked(
+        &mut self,
+        frame: &Frame,
+        lhs: ValueId,
+        rhs: ValueId,
+        instruction: &str,
+    ) -> fmt::Result {
+        self.load(lhs, "%rax")?;
+        self.line(format_args!("{instruction} {}, %rax", slot(rhs)))?;
+        self.line(format_args!("jo {}", frame.overflow()))
+    }
+
+    /// Leaves the 0-based index of the element of `array` in `%rax` and the array in `%rcx`
+    fn element(&mut self, frame: &Frame, array: ValueId, index: ValueId) -> fmt::Result {
+        self.call(
+            frame,
+            "rt_index",
+            &[Arg::Value(array), Arg::Value(index)],
+            Returns::Int64,
+        )?;
+        self.load(array, "%rcx")
+    }
+
+    fn binary(
+        &mut self,
+        frame: &Frame,
+        op: SemanticBinaryOperator,
+        lhs: ValueId,
+        rhs: ValueId,
+    ) -> fmt::Result {
+        use SemanticBinaryOperator as Op;
+
+        let string_comparison = |condition| (condition, [Arg::Value(lhs), Arg::Value(rhs)]);
+        let (condition, args) = match op {
+            Op::RealAdd => return self.real_arithmetic(lhs, rhs, "addsd"),
+            Op::RealSub => return self.real_arithmetic(lhs, rhs, "subsd"),
+            Op::RealMul => return self.real_arithmetic(lhs, rhs, "mulsd"),
+            Op::RealDiv => return self.real_arithmetic(lhs, rhs, "divsd"),
+            // Only `above` conditions are false for unordered operands
+            Op::RealLe => return self.compare_reals(rhs, lhs, "ae"),
+            Op::RealLg => return self.compare_reals(rhs, lhs, "a"),
+            Op::RealGt => return self.compare_reals(lhs, rhs, "a"),
+            Op::RealGe => return self.compare_reals(lhs, rhs, "ae"),
+            Op::RealEq => return self.compare_reals(lhs, rhs, "e"),
+            Op::RealNeq => return self.compare_reals(lhs, rhs, "ne"),
+            Op::IntLe => return self.compare_ints(lhs, rhs, "le"),
+            Op::IntLg => return self.compare_ints(lhs, rhs, "l"),
+            Op::IntGt => return self.compare_ints(lhs, rhs, "g"),
+            Op::IntGe => return self.compare_ints(lhs, rhs, "ge"),
+            // Also compares references by identity
+            Op::IntEq => return self.compare_ints(lhs, rhs, "e"),
+            Op::IntNeq | Op::BoolXor => return self.compare_ints(lhs, rhs, "ne"),
+            Op::IntAdd => return self.checked(frame, lhs, rhs, "addq"),
+            Op::IntSub => return self.checked(frame, lhs, rhs, "subq"),
+            Op::IntMul => return self.checked(frame, lhs, rhs, "imulq"),
+            Op::IntDiv | Op::IntMod => {
+                let function = if op == Op::IntDiv { "rt_div" } else { "rt_mod" };
+                let args = [Arg::Value(lhs), Arg::Value(rhs)];
+                return self.call(frame, function, &args, Returns::Int64);
+            }
+            Op::BoolAnd | Op::BoolOr => {
+                let instruction = if op == Op::BoolAnd { "andq" } else { "orq" };
+                self.load(lhs, "%rax")?;
+                return self.line(format_args!("{instruction} {}, %rax", slot(rhs)));
+            }
+            Op::StringConcat => {
+                let args = [Arg::Value(lhs), Arg::Value(rhs)];
+                return self.call(frame, "rt_concat", &args, Returns::Int64);
+            }
+            Op::StringLe => string_comparison("le"),
+            Op::StringLg => string_comparison("l"),
+            Op::StringGt => string_comparison("g"),
+            Op::StringGe => string_comparison("ge"),
+            Op::StringEq => string_comparison("e"),
+            Op::StringNeq => string_comparison("ne"),
+        };
+        self.call(frame, "rt_compare", &args, Returns::Int32)?;
+        self.line(format_args!("cmpq $0, %rax"))?;
+        self.set(condition)
+    }
+
+    /// Rounds with `function` of the C library and converts the result to an integer
+    fn rounded(&mut self, frame: &Frame, function: &str, args: &[Arg]) -> fmt::Result {
+        // The result stays in `%xmm0` for the conversion
+        self.call(frame, function, args, Returns::Real)?;
+        self.line(format_args!("call rt_real_to_int@PLT"))
+    }
+
+    /// Call of the built-in, the result is left in `%rax`
+    fn native(&mut self, frame: &Frame, builtin: Builtin, args: &[ValueId]) -> fmt::Result {
+        let values: Vec<_> = args.iter().map(|&arg| Arg::Value(arg)).collect();
+        let is_real = |index: usize| frame.types[&args[index]] == IrType::Real;
+        let is_ref = |index: usize| frame.types[&args[index]] == IrType::Ref;
+        match builtin {
+            Builtin::Sqrt => self.call(frame, "sqrt", &values, Returns::Real),
+            Builtin::Sin => self.call(frame, "sin", &values, Returns::Real),
+            Builtin::Cos => self.call(frame, "cos", &values, Returns::Real),
+            Builtin::Tan => self.call(frame, "tan", &values, Returns::Real),
+            Builtin::Atan => self.call(frame, "atan", &values, Returns::Real),
+            Builtin::Exp => self.call(frame, "exp", &values, Returns::Real),
+            Builtin::Ln => self.call(frame, "log", &values, Returns::Real),
+            Builtin::Pow => self.call(frame, "pow", &values, Returns::Real),
+            Builtin::Abs if is_real(0) => {
+                self.load(args[0], "%rax")?;
+                self.line(format_args!("btrq $63, %rax"))
+            }
+            Builtin::Abs => self.call(frame, "rt_abs", &values, Returns::Int64),
+            Builtin::Floor => self.rounded(frame, "floor", &values),
+            Builtin::Ceil => self.rounded(frame, "ceil", &values),
+            Builtin::Round => self.rounded(frame, "round", &values),
+            Builtin::ToInteger => self.call(frame, "rt_real_to_int", &values, Returns::Int64),
+            Builtin::Min | Builtin::Max => {
+                let condition = if builtin == Builtin::Min { "l" } else { "g" };
+                if is_real(0) {
+                    self.call(frame, "rt_total_cmp", &values, Returns::Int32)?;
+                    self.line(format_args!("movq %rax, %rdx"))?;
+                }
+                self.load(args[1], "%rax")?;
+                self.load(args[0], "%rcx")?;
+                if is_real(0) {
+                    self.line(format_args!("cmpq $0, %rdx"))?;
+                } else {
+                    self.line(format_args!("cmpq %rax, %rcx"))?;
+                }
+                // The first one when it compares as `condition` to the second one
+                self.line(format_args!("cmov{condition}q %rcx, %rax"))
+            }
+            Builtin::ToReal => {
+                self.line(format_args!("cvtsi2sdq {}, %xmm0", slot(args[0])))?;
+                self.line(format_args!("movq %xmm0, %rax"))
+            }
+            Builtin::ToBoolean => self.call(frame, "rt_to_boolean", &values, Returns::Int64),
+            Builtin::Fill => {
+                let args = [Arg::Value(args[0]), Arg::Bits(args[1])];
+                self.call(frame, "rt_fill", &args, Returns::Nothing)
+            }
+            Builtin::Copy if is_ref(0) => self.call(frame, "rt_copy", &values, Returns::Int64),
+            Builtin::Copy => self.load(args[0], "%rax"),
+            Builtin::Sort => self.call(frame, "rt_sort", &values, Returns::Nothing),
+            Builtin::Assert => self.call(frame, "rt_assert", &values, Returns::Nothing),
+            Builtin::ReadInteger => self.call(frame, "rt_read_integer", &[], Returns::Int64),
+            Builtin::ReadReal => self.call(frame, "rt_read_real", &[], Returns::Real),
+            Builtin::ReadBoolean => self.call(frame, "rt_read_boolean", &[], Returns::Int64),
+            Builtin::Length => self.call(frame, "rt_length", &values, Returns::Int64),
+            Builtin::CharAt => self.call(frame, "rt_char_at", &values, Returns::Int64),
+            Builtin::Equals if is_ref(0) => self.call(frame, "rt_equals", &values, Returns::Int64),
+            Builtin::Equals if is_real(0) => self.compare_reals(args[0], args[1], "e"),
+            Builtin::Equals => self.compare_ints(args[0], args[1], "e"),
+        }
+    }
+
+    /// Code of `operation`, its result is left in `%rax`
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn operation(
+        &mut self,
+        frame: &Frame,
+        result: Option<ValueId>,
+        operation: &Operation,
+    ) -> fmt::Result {
+        match *operation {
+            Operation::Const(Constant::Int(constant)) => {
+                self.line(format_args!("movabsq ${constant}, %rax"))
+            }
+            Operation::Const(Constant::Real(constant)) => {
+                self.line(format_args!("movabsq ${}, %rax", constant.to_bits()))
+            }
+            Operation::Const(Constant::Bool(constant)) => {
+                self.line(format_args!("movq ${}, %rax", i64::from(constant)))
+            }
+            Operation::String { id } => self.line(format_args!("leaq string_{id}(%rip), %rax")),
+            Operation::Param { index } => {
+                self.line(format_args!("movq {}(%rbp), %rax", 16 + index * 8))
+            }
+            Operation::Copy(id) => self.load(id, "%rax"),
+            Operation::Phi(_) => {
+                let result = result.expect("Phis have a result");
+                self.line(format_args!("movq {}, %rax", frame.phi(result)))
+            }
+            Operation::Binary { op, lhs, rhs } => self.binary(frame, op, lhs, rhs),
+            Operation::Unary { op, value } => {
+                self.load(value, "%rax")?;
+                match op {
+                    SemanticUnaryOperator::IntNeg => {
+                        self.line(format_args!("negq %rax"))?;
+                        self.line(format_args!("jo {}", frame.overflow()))
+                    }
+                    SemanticUnaryOperator::RealNeg => self.line(format_args!("btcq $63, %rax")),
+                    SemanticUnaryOperator::BoolNeg => self.line(format_args!("xorq $1, %rax")),
+                }
+            }
+            Operation::IntToReal(id) => self.native(frame, Builtin::ToReal, &[id]),
+            Operation::RealToInt(id) => self.native(frame, Builtin::ToInteger, &[id]),
+            Operation::IntToBool(id) => self.native(frame, Builtin::ToBoolean, &[id]),
+            Operation::LoadGlobal { index } => {
+                self.line(format_args!("movq globals+{}(%rip), %rax", index * 8))
+            }
+            Operation::StoreGlobal { index, value } => {
+                self.load(value, "%rax")?;
+                self.line(format_args!("movq %rax, globals+{}(%rip)", index * 8))
+            }
+            Operation::AllocRecord { type_id, .. } => {
+                let args = [Arg::Int(type_id.0.into())];
+                self.call(frame, "rt_record", &args, Returns::Int64)
+            }
+            Operation::AllocArray { type_id, size } => {
+                let size = i64::try_from(size).expect("Array sizes are checked by the compiler");
+                let args = [Arg::Int(type_id.0.into()), Arg::Int(size)];
+                self.call(frame, "rt_array", &args, Returns::Int64)
+            }
+            Operation::ArraySize(array) => {
+                self.load(array, "%rax")?;
+                self.line(format_args!("movq {LENGTH}(%rax), %rax"))
+            }
+            Operation::LoadField { record, offset } => {
+                self.load(record, "%rax")?;
+                self.line(format_args!("movq {}(%rax), %rax", FIELDS + offset * 8))
+            }
+            Operation::StoreField {
+                record,
+                offset,
+                value,
+            } => {
+                self.load(record, "%rax")?;
+                self.load(value, "%rcx")?;
+                self.line(format_args!("movq %rcx, {}(%rax)", FIELDS + offset * 8))
+            }
+            Operation::LoadElement { array, index } => {
+                self.element(frame, array, index)?;
+                self.line(format_args!("movq {ELEMENTS}(%rcx,%rax,8), %rax"))
+            }
+            Operation::StoreElement {
+                array,
+                index,
+                value,
+            } => {
+                self.element(frame, array, index)?;
+                self.load(value, "%rdx")?;
+                self.line(format_args!("movq %rdx, {ELEMENTS}(%rcx,%rax,8)"))
+            }
+            Operation::Call {
+                function_label,
+                ref args,
+            } => {
+                let space = args.len().div_ceil(2) * 16;
+                if space > 0 {
+                    self.line(format_args!("subq ${space}, %rsp"))?;
+                }
+                for (index, &arg) in args.iter().enumerate() {
+                    self.load(arg, "%rax")?;
+                    self.line(format_args!("movq %rax, {}(%rsp)", index * 8))?;
+                }
+                self.line(format_args!("call r{function_label}"))?;
+                if space > 0 {
+                    self.line(format_args!("addq ${space}, %rsp"))?;
+                }
+                Ok(())
+            }
+            Operation::CallNative { id, ref args } => {
+                let builtin = Builtin::from_id(id).expect("Host functions are rejected before");
+                self.native(frame, builtin, args)
+            }
+            Operation::Print { type_id, value } => {
+                let args = [Arg::Bits(value), Arg::Int(type_id.0.into())];
+                self.call(frame, "rt_print", &args, Returns::Nothing)
+            }
+        }
+    }
+
+    /// Assigns the phis of `to` their values coming from `from`
+    fn phi_moves(
+        &mut self,
+        frame: &Frame,
+        function: &ir::Function,
+        from: BlockId,
+        to: BlockId,
+    ) -> fmt::Result {
+        for (result, incoming) in cgen::phis(function.block(to)) {
+            for &(predecessor, id) in incoming {
+                if predecessor == from {
+                    self.load(id, "%rax")?;
+                    self.line(format_args!("movq %rax, {}", frame.phi(result)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn terminator(
+        &mut self,
+        frame: &Frame,
+        function: &ir::Function,
+        block: BlockId,
+        terminator: Terminator,
+    ) -> fmt::Result {
+        match terminator {
+            Terminator::Jump(target) => {
+                self.phi_moves(frame, function, block, target)?;
+                self.line(format_args!("jmp {}", frame.block(target)))
+            }
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let skip = format!("{}_else", frame.block(block));
+                self.line(format_args!("cmpq $0, {}", slot(condition)))?;
+                self.line(format_args!("je {skip}"))?;
+                self.phi_moves(frame, function, block, then)?;
+                self.line(format_args!("jmp {}", frame.block(then)))?;
+                writeln!(self.out, "{skip}:")?;
+                self.phi_moves(frame, function, block, otherwise)?;
+                self.line(format_args!("jmp {}", frame.block(otherwise)))
+            }
+            Terminator::Return(value) => {
+                match value {
+                    Some(id) => self.load(id, "%rax")?,
+                    None => self.line(format_args!("xorl %eax, %eax"))?,
+                }
+                self.line(format_args!("leave"))?;
+                self.line(format_args!("ret"))
+            }
+            Terminator::Panic { code } => {
+                let message = format!("{}_panic", frame.block(block));
+                self.line(format_args!("leaq {message}(%rip), %rdi"))?;
+                self.line(format_args!("xorl %eax, %eax"))?;
+                self.line(format_args!("call rt_fail@PLT"))?;
+                self.line(format_args!(".section .rodata"))?;
+                writeln!(self.out, "{message}:")?;
+                self.line(format_args!(".asciz \"Panic with code {code}\""))?;
+                self.line(format_args!(".text"))
+            }
+        }
+    }
+
+    fn routine(&mut self, label: u64, function: &ir::Function) -> fmt::Result {
+        let frame = Frame::new(label, function);
+        writeln!(self.out)?;
+        writeln!(self.out, "# {}", function.name)?;
+        writeln!(self.out, "r{label}:")?;
+        self.line(format_args!("pushq %rbp"))?;
+        self.line(format_args!("movq %rsp, %rbp"))?;
+        self.line(format_args!("subq ${}, %rsp", frame.size()))?;
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            writeln!(self.out, "{}:", frame.block(id))?;
+            for instruction in &block.instructions {
+                let result = instruction.result.map(|(result, _)| result);
+                self.operation(&frame, result, &instruction.operation)?;
+                if let Some(result) = result {
+                    self.line(format_args!("movq %rax, {}", slot(result)))?;
+                }
+            }
+            self.terminator(&frame, function, id, block.terminator)?;
+        }
+        writeln!(self.out, "{}:", frame.overflow())?;
+        self.line(format_args!("leaq overflow(%rip), %rdi"))?;
+        self.line(format_args!("xorl %eax, %eax"))?;
+        self.line(format_args!("call rt_fail@PLT"))
+    }
+
+    /// The table of all types for the runtime, see `struct type` in `cgen/runtime.h`
+    fn types(&mut self) -> fmt::Result {
+        for (id, element) in self.module.rtti.0.iter().enumerate() {
+            match element {
+                RTTIElement::Enum(EnumRTTI { variants, .. }) => {
+                    for (index, variant) in variants.iter().enumerate() {
+                        writeln!(self.out, "variant_{id}_{index}:")?;
+                        self.line(format_args!(".asciz {}", ascii(variant)))?;
+                    }
+                    self.line(format_args!(".balign 8"))?;
+                    writeln!(self.out, "variants_{id}:")?;
+                    for index in 0..variants.len() {
+                        self.line(format_args!(".quad variant_{id}_{index}"))?;
+                    }
+                }
+                RTTIElement::Record(RecordRTTI { field_ids, .. }) => {
+                    self.line(format_args!(".balign 8"))?;
+                    writeln!(self.out, "fields_{id}:")?;
+                    for (offset, field) in (FIELDS..).step_by(8).zip(field_ids) {
+                        self.line(format_args!(".quad {offset}"))?;
+                        self.line(format_args!(".long {}, 0", field.0))?;
+                    }
+                }
+                RTTIElement::Primitive(_) | RTTIElement::Array(_) => {}
+            }
+        }
+        self.line(format_args!(".balign 8"))?;
+        self.line(format_args!(".globl types"))?;
+        writeln!(self.out, "types:")?;
+        for (id, element) in self.module.rtti.0.iter().enumerate() {
+            let (size, field_count, fields) = match element {
+                RTTIElement::Record(RecordRTTI { field_ids, .. }) => (
+                    FIELDS + 8 * u64::try_from(field_ids.len()).expect("Records have few fields"),
+                    field_ids.len(),
+                    format!("fields_{id}"),
+                ),
+                RTTIElement::Primitive(_) | RTTIElement::Enum(_) | RTTIElement::Array(_) => {
+                    (0, 0, "0".to_owned())
+                }
+            };
+            let element_id = match *element {
+                RTTIElement::Array(ArrayRTTI { element_id, .. }) => element_id.0,
+                RTTIElement::Primitive(_) | RTTIElement::Enum(_) | RTTIElement::Record(_) => 0,
+            };
+            let (variant_count, variants) = match element {
+                RTTIElement::Enum(EnumRTTI { variants, .. }) => {
+                    (variants.len(), format!("variants_{id}"))
+                }
+                RTTIElement::Primitive(_) | RTTIElement::Record(_) | RTTIElement::Array(_) => {
+                    (0, "0".to_owned())
+                }
+            };
+            self.line(format_args!(".long {}, 0", kind(element)))?;
+            self.line(format_args!(".quad {size}"))?;
+            self.line(format_args!(".long {field_count}, 0"))?;
+            self.line(format_args!(".quad {fields}"))?;
+            self.line(format_args!(".long {element_id}, {variant_count}"))?;
+            self.line(format_args!(".quad {variants}"))?;
+        }
+        Ok(())
+    }
+
+    fn program(&mut self, main: u64) -> fmt::Result {
+        writeln!(self.out, "# Compiled from `{}`", self.module.name)?;
+        self.line(format_args!(".section .note.GNU-stack,\"\",@progbits"))?;
+        self.line(format_args!(".section .rodata"))?;
+        writeln!(self.out, "overflow:")?;
+        self.line(format_args!(".asciz \"Integer overflow\""))?;
+        // Strings are never changed, they are objects of the runtime
+        for (id, string) in self.module.strings.iter().enumerate() {
+            self.line(format_args!(".balign 8"))?;
+            writeln!(self.out, "string_{id}:")?;
+            self.line(format_args!(".long {}, 0", TypeId::STRING.0))?;
+            self.line(format_args!(".quad {}", string.len()))?;
+            self.line(format_args!(".ascii {}", ascii(string)))?;
+        }
+        // The table has addresses for the loader to relocate
+        self.line(format_args!(".data"))?;
+        self.types()?;
+        self.line(format_args!(".bss"))?;
+        self.line(format_args!(".balign 8"))?;
+        writeln!(self.out, "globals:")?;
+        self.line(format_args!(
+            ".zero {}",
+            self.module.global_count.max(1) * 8
+        ))?;
+
+        self.line(format_args!(".text"))?;
+        self.line(format_args!(".globl main"))?;
+        writeln!(self.out, "main:")?;
+        self.line(format_args!("pushq %rbp"))?;
+        self.line(format_args!("movq %rsp, %rbp"))?;
+        self.line(format_args!("call r{main}"))?;
+        self.line(format_args!("xorl %eax, %eax"))?;
+        self.line(format_args!("popq %rbp"))?;
+        self.line(format_args!("ret"))?;
+        for (&label, function) in self.routines {
+            self.routine(label, function)?;
+        }
+        Ok(())
+    }
+}
+
+/// Assembly of the whole program, `module` and `routines` are linked, see `linker::link`
+/// and `linker::link_ir`. Built-ins behave and fail like the ones of the `vm` crate.
+///
+/// # Panics
+///
+/// If the size of an array doesn't fit into `i64`, which the checker rules out
+pub fn assemble(
+    module: &Module,
+    routines: &BTreeMap<u64, ir::Function>,
+) -> Result<String, CgenError> {
+    let main = cgen::entry(module, routines)?;
+    let mut assembly = Assembly {
+        module,
+        routines,
+        out: String::new(),
+    };
+    assembly
+        .program(main)
+        .expect("Writing to a string won't fail");
+    Ok(assembly.out)
+}
+
+/// Builds `executable` from the output of `assemble` and the `runtime` with `cc`
+///
+/// # Errors
+///
+/// If the files can't be written or `cc` fails, with its diagnostics
+pub fn build_executable(assembly: &str, executable: &Path) -> io::Result<()> {
+    let dir = env::temp_dir().join(format!("x86_64-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let (program, runtime_c) = (dir.join("program.s"), dir.join("runtime.c"));
+    fs::write(&program, assembly)?;
+    fs::write(&runtime_c, runtime())?;
+    let output = Command::new("cc")
+        .args(["-std=c11", "-O2", "-o"])
+        .arg(executable)
+        .arg(&program)
+        .arg(&runtime_c)
+        .arg("-lm")
+        .output();
+    fs::remove_dir_all(&dir)?;
+    let output = output?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ))
+    }
+}
@@ -0,0 +1,70 @@
+use super::*;
+use crate::ir::FunctionBuilder;
+use crate::test_support::{
+    Run, binary, fails_at_run_time, int, module, print, routine, run_native, runs_the_corpus,
+};
+
+/// Builds the program with the system toolchain and runs it
+fn run(test: &str, module: &Module, routines: &BTreeMap<u64, ir::Function>, input: &str) -> Run {
+    let assembly = assemble(module, routines).expect("The program only calls built-ins");
+    run_native(&format!("x86_64-{test}"), input, |_, executable| {
+        let built = build_executable(&assembly, executable);
+        assert!(
+            built.is_ok(),
+            "The tests of the x86-64 backend need `cc`: {built:?}\n{assembly}"
+        );
+    })
+}
+
+#[test]
+#[cfg_attr(
+    not(all(target_arch = "x86_64", target_os = "linux")),
+    ignore = "The executables only run on x86-64 Linux"
+)]
+fn corpus() {
+    runs_the_corpus(run);
+}
+
+#[test]
+#[cfg_attr(
+    not(all(target_arch = "x86_64", target_os = "linux")),
+    ignore = "The executables only run on x86-64 Linux"
+)]
+fn runtime_errors() {
+    fails_at_run_time(run);
+}
+
+#[test]
+#[cfg_attr(
+    not(all(target_arch = "x86_64", target_os = "linux")),
+    ignore = "The executables only run on x86-64 Linux"
+)]
+fn integer_overflow() {
+    let mut builder = FunctionBuilder::new("main", Vec::new(), None);
+    let max = int(&mut builder, i64::MAX);
+    let negated = builder.value(
+        IrType::Int,
+        Operation::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            value: max,
+        },
+    );
+    print(&mut builder, TypeId::INTEGER, negated);
+    let one = int(&mut builder, 1);
+    let overflown = binary(
+        &mut builder,
+        IrType::Int,
+        SemanticBinaryOperator::IntAdd,
+        max,
+        one,
+    );
+    print(&mut builder, TypeId::INTEGER, overflown);
+    builder.terminate(Terminator::Return(None));
+    let routines = BTreeMap::from([(0, builder.finish())]);
+    let module = module(vec![routine("main", 0, &[], TypeId::INTEGER)], &[]);
+
+    let run = run("overflow", &module, &routines, "");
+    assert_eq!(run.stdout, "-9223372036854775807\n");
+    assert_eq!(run.stderr, "Integer overflow\n");
+    assert!(!run.success);
+}
@@ -0,0 +1,50 @@
+//! The programs of `tests/run` with what they read and print, for the tests of the backends
+//! in the crates of the workspace. Only a dev-dependency, nothing ships with it.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Program {
+    pub name: String,
+    /// `tests/src/<name>.i`
+    pub source: PathBuf,
+    /// `tests/input/<name>.txt`, empty without one
+    pub input: String,
+    /// `tests/run/<name>.stdout`
+    pub expected: String,
+}
+
+/// The programs of `tests/run` by name
+///
+/// # Panics
+///
+/// If the directory can't be read
+#[must_use]
+pub fn programs() -> Vec<Program> {
+    let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+    let mut programs: Vec<_> = fs::read_dir(tests.join("run"))
+        .expect("The corpus is there")
+        .map(|entry| {
+            let path = entry.expect("The corpus is readable").path();
+            let name = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .expect("Names are UTF-8")
+                .to_owned();
+            let input = fs::read_to_string(tests.join("input").join(format!("{name}.txt")))
+                .unwrap_or_default();
+            let expected = fs::read_to_string(&path).expect("The corpus is readable");
+            let source = tests.join("src").join(format!("{name}.i"));
+            Program {
+                name,
+                source,
+                input,
+                expected,
+            }
+        })
+        .collect();
+    programs.sort();
+    programs
+}
@@ -0,0 +1,14 @@
+import numbers;
+
+type point is record
+  var x : real;
+  var y : real;
+end;
+
+var calls is 0;
+var unit : point;
+
+routine squared_distance(from : point, to : point) : real is
+  calls := calls + 1;
+  return numbers.square(from.x - to.x) + numbers.square(from.y - to.y);
+end;
@@ -0,0 +1,10 @@
+import geometry;
+import numbers; -- already imported by geometry
+
+routine main() is
+  var origin : geometry.point;
+  geometry.unit.x := 3.0;
+  geometry.unit.y := 4.0;
+  print numbers.square(geometry.squared_distance(origin, geometry.unit));
+  print geometry.calls;
+end;
@@ -0,0 +1 @@
+routine square(x : real) => x * x;
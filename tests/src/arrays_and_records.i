@@ -1,11 +1,11 @@
 type point is record 
-  var x is real;
-  car y is real;
+  var x : real;
+  var y : real;
 end;
 
 type triangle is array [1 + 2] point;   
 
-var EPS = 0.0000001;
+var EPS is 0.0000001;
 
 routine approximately_eq(a : real, b : real, eps : real) => (a - b) * (a - b) < eps * eps;
 
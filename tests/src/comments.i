@@ -0,0 +1,14 @@
+/* Block comments may span
+   several lines */
+
+--- Distance between two points,
+--- always non-negative
+routine distance(a : real, b : real) : real is
+  /* outer /* nested */ still outer */
+  if a > b then return a - b; else return b - a; end;
+end;
+
+---------------- not a doc comment
+
+--- Scale factor
+var SCALE is 2/*inline*/ * 3;
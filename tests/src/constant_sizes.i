@@ -0,0 +1,11 @@
+type row is array [2 * 4 - 3] integer;
+type flags is array [(7 - 1) / 2] boolean;
+
+routine main() is
+  var r : row;
+  var f : flags;
+  r[5] := 10 / 4 + 7 % 4;
+  f[3] := 1 < 2 and not (2.5 > 3);
+  print r[5];
+  print f[3];
+end;
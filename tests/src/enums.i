@@ -0,0 +1,28 @@
+type state is enum idle, running, done end;
+
+routine next(s : state) : state is
+  case s of
+    idle is return running; end;
+    running is return done; end;
+    done is return idle; end;
+  end;
+end;
+
+routine describe(code : integer) is
+  case code of
+    0, 1 is print "small"; end;
+    -1 is print "negative"; end;
+  else
+    print "other";
+  end;
+end;
+
+routine main() is
+  var s : state;
+  for i in 1..4 loop
+    print s;
+    s := next(s);
+  end;
+  describe(1);
+  describe(42);
+end;
@@ -2,8 +2,8 @@ routine array_length (a : array [] integer) : integer;
 routine bubble_sort (a : array [] integer, length : integer);
 
 routine sort_and_print_reversed_array(a : array [] integer) is
-  bubble_sort(a, a.length)
-  for elem in a ..  reversed loop 
+  bubble_sort(a, a.length);
+  for elem in a .. reverse loop 
     print elem;
   end;
 end;
@@ -17,7 +17,7 @@ routine array_length (a : array [] integer) : integer is
 end;
 
 routine bubble_sort (a : array [] integer, length : integer) is 
-  for i in 1 .. n loop
+  for i in 1 .. length loop
     for j in 2 .. i loop 
       if a[j - 1] > a[j] then
         var t is a[j];
@@ -26,10 +26,10 @@ routine bubble_sort (a : array [] integer, length : integer) is
       end;
     end; 
   end;
-end.
+end;
 
 routine countdown (n: integer) is 
-  for i in n .. 0 reversed loop 
+  for i in 0 .. n reverse loop 
     print i;
   end;
 end;
@@ -42,12 +42,12 @@ end;
 
 routine main() is
   var arr : array [5] integer; 
-  arr[1] = 3;
-  arr[2] = 5;
-  arr[3] = 1;
-  arr[4] = 2;
-  arr[5] = 4;
-  sort_and_print_reversed_array(arr, arr.length);
+  arr[1] := 3;
+  arr[2] := 5;
+  arr[3] := 1;
+  arr[4] := 2;
+  arr[5] := 4;
+  sort_and_print_reversed_array(arr);
   count(3);
   countdown(5);
 end;
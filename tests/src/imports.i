@@ -0,0 +1,7 @@
+import geometry;
+/* import hidden; */
+
+routine main() is
+  var origin : geometry.point;
+  print geometry.squared_distance(origin, origin);
+end;
@@ -1,3 +1,5 @@
 routine main() is
   var 🐈 is 123456789012345678901234567890;
+  print "bad \q escape";
+  print "unterminated
 end;
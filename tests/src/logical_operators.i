@@ -18,6 +18,5 @@ routine main() is
   
   print not a;
   print not b;
-  print not x;
   print not y;
 end;
@@ -0,0 +1,16 @@
+routine main() is
+  var i is 0;
+  outer: while i < 10 loop
+    i := i + 1;
+    if i % 2 = 0 then continue; end;
+    for j in 1..i loop
+      if j = 3 then continue outer; end;
+      if i * j > 20 then break outer; end;
+      print i * j;
+    end;
+  end;
+  for k in 1..100 loop
+    if k > 2 then break; end;
+    print k;
+  end;
+end;
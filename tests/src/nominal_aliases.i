@@ -0,0 +1,10 @@
+type kilometers is real;
+type miles is real;
+
+routine to_miles(value : kilometers) : miles => miles(real(value) * 0.621371);
+
+routine main() is
+  var dist : kilometers is kilometers(10.0);
+  print to_miles(dist);
+  print integer(real(dist));
+end;
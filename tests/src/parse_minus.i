@@ -2,4 +2,4 @@ var a is -1;
 var b is -1 - -2;
 var c is -1 - (-2);
 var d is (-5) + (-7 + -8);
-var e = - -7; -- --7 is comment :)
+var e is - -7; -- --7 is comment :)
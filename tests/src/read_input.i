@@ -0,0 +1,10 @@
+--- Sums `count` reals read after the count itself
+routine main() is
+  var count is read_integer();
+  var sum is 0.0;
+  for i in 1 .. count loop
+    sum := sum + read_real();
+  end;
+  print sum;
+  print read_boolean() and sum > 10.0;
+end;
@@ -1,6 +1,6 @@
 type point is record -- reprsents a point
-  var x is real;
-  car y is real;
+  var x : real;
+  var y : real;
 end;
 
 routine point_of (x : real, y : real) : point is 
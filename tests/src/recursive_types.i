@@ -1,6 +1,6 @@
 type linked_list is record 
   var data : integer;
-  var tail : linked_list
+  var tail : linked_list;
 end;
 
 routine linked_list_of(data: integer, tail : linked_list) : linked_list is 
@@ -28,7 +28,7 @@ routine length(l : linked_list ) : integer is
   return result;
 end;
 
-routine reverse(l : linked_list) : linked_list is
+routine reversed(l : linked_list) : linked_list is
   var result is empty();
 
   while is_empty(l) /= true loop
@@ -0,0 +1,19 @@
+type point is record var x : integer; var y : integer; end;
+
+routine move(p : point) is
+  p.x := p.x + 1;
+end;
+
+routine main() is
+  var a : point;
+  var b is a;
+  var c is copy(a);
+  move(b);
+  print a.x;
+  print c.x;
+  print a = b;
+  print a = c;
+  print equals(a, c);
+  c.x := 1;
+  print equals(a, c);
+end;
@@ -0,0 +1,11 @@
+routine loud(b : boolean) : boolean is
+  print b;
+  return b;
+end;
+
+routine main() is
+  print false and loud(true);
+  print true and loud(false);
+  print true or loud(true);
+  print false or loud(false);
+end;
@@ -0,0 +1,13 @@
+var GREETING is "Hello, \"world\"!\n";
+
+routine shout(s : string) : string => s + "!";
+
+routine main() is
+  var name : string;
+  name := "tab\there";
+  print GREETING;
+  print shout(name);
+  print length(name);
+  print name[1] = "t";
+  print "abc" < "abd";
+end;
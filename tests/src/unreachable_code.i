@@ -0,0 +1,11 @@
+routine f(x : integer) : integer is
+  while true loop
+    return x;
+    print 7;
+  end;
+end;
+routine main() is
+  print f(3);
+  return;
+  print 2;
+end;
@@ -0,0 +1,4 @@
+var x is 1;
+/* outer
+  /* inner */
+var y is 2;
@@ -0,0 +1,124 @@
+//! Input of `read_*` built-ins and output of `print`
+
+use core::cell::RefCell;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+use std::io::{self, BufRead, BufReader, Write};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    EndOfInput {
+        expected: &'static str,
+    },
+    Invalid {
+        expected: &'static str,
+        found: String,
+    },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::EndOfInput { expected } => {
+                write!(f, "Expected {expected}, found the end of input")
+            }
+            InputError::Invalid { expected, found } => {
+                write!(f, "Expected {expected}, found {found:?}")
+            }
+        }
+    }
+}
+
+impl Error for InputError {}
+
+pub(crate) struct Console {
+    pub(crate) input: Box<dyn BufRead>,
+    pub(crate) output: Box<dyn Write>,
+}
+
+impl fmt::Debug for Console {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Console").finish_non_exhaustive()
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+        }
+    }
+}
+
+impl Console {
+    /// Next whitespace-separated word, `None` at the end of input
+    fn word(&mut self) -> io::Result<Option<String>> {
+        let mut word = Vec::new();
+        loop {
+            let buffer = self.input.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            let skipped = if word.is_empty() {
+                buffer
+                    .iter()
+                    .take_while(|byte| byte.is_ascii_whitespace())
+                    .count()
+            } else {
+                0
+            };
+            let taken = buffer[skipped..]
+                .iter()
+                .take_while(|byte| !byte.is_ascii_whitespace())
+                .count();
+            word.extend_from_slice(&buffer[skipped..skipped + taken]);
+            let done = skipped + taken < buffer.len() && !word.is_empty();
+            self.input.consume(skipped + taken);
+            if done {
+                break;
+            }
+        }
+        Ok((!word.is_empty()).then(|| String::from_utf8_lossy(&word).into_owned()))
+    }
+
+    /// Reads `expected` (e.g. "an integer") parsed as `T`
+    pub(crate) fn read<T: FromStr>(
+        &mut self,
+        expected: &'static str,
+    ) -> io::Result<Result<T, InputError>> {
+        Ok(match self.word()? {
+            None => Err(InputError::EndOfInput { expected }),
+            Some(word) => match word.parse() {
+                Ok(value) => Ok(value),
+                Err(_) => Err(InputError::Invalid {
+                    expected,
+                    found: word,
+                }),
+            },
+        })
+    }
+}
+
+/// In-memory output, stays readable after a clone is passed to `Vm::set_output`
+#[derive(Debug, Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    #[must_use]
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
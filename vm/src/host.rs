@@ -0,0 +1,267 @@
+//! Routines provided by the embedding Rust program and callable from `.i` code
+
+use core::error::Error;
+use core::fmt;
+
+pub use compiler::bytecode::TypeId;
+use compiler::bytecode::{EnumRTTI, RTTI, RTTIElement, RecordRTTI};
+
+use crate::value::{ConversionError, FromValue, Heap, Object, ObjectRef, ToValue, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// Objects are checked by their shape, their contents are the host's business.
+/// Records may be null, since recursive ones are until assigned.
+pub(crate) fn accepts(t: TypeId, value: Value, heap: &Heap, rtti: &RTTI) -> bool {
+    match (rtti.get(t), value) {
+        (Some(RTTIElement::Primitive(primitive)), value) => {
+            matches!(
+                (primitive.id, value),
+                (TypeId::INTEGER, Value::Int(_))
+                    | (TypeId::REAL, Value::Real(_))
+                    | (TypeId::BOOLEAN, Value::Bool(_))
+            ) || primitive.id == TypeId::STRING
+                && matches!(value, Value::Ref(string)
+                    if string != ObjectRef::NULL && matches!(heap.get(string), Object::String(_)))
+        }
+        (Some(RTTIElement::Enum(EnumRTTI { variants, .. })), Value::Int(index)) => {
+            usize::try_from(index).is_ok_and(|index| index < variants.len())
+        }
+        (Some(RTTIElement::Record(_)), Value::Ref(ObjectRef::NULL)) => true,
+        (Some(RTTIElement::Array(_)), Value::Ref(ObjectRef::NULL)) => false,
+        (Some(RTTIElement::Record(RecordRTTI { field_ids, .. })), Value::Ref(record)) => {
+            matches!(heap.get(record), Object::Record(fields) if fields.len() == field_ids.len())
+        }
+        (Some(RTTIElement::Array(_)), Value::Ref(array)) => {
+            matches!(heap.get(array), Object::Array(_))
+        }
+        (
+            Some(RTTIElement::Enum(_) | RTTIElement::Record(_) | RTTIElement::Array(_)),
+            Value::Real(_) | Value::Bool(_) | Value::Ref(_),
+        )
+        | (Some(RTTIElement::Record(_) | RTTIElement::Array(_)), Value::Int(_))
+        | (None, _) => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub args: Vec<TypeId>,
+    pub result: Option<TypeId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostError {
+    AlreadyRegistered {
+        name: String,
+    },
+    NotFound {
+        name: String,
+    },
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    ArgumentType {
+        index: usize,
+        expected: TypeId,
+    },
+    /// The callback returned something else than its signature promised
+    ResultType {
+        expected: Option<TypeId>,
+    },
+    Conversion(ConversionError),
+    /// The host function itself failed, that is a runtime panic for the program
+    Failed(String),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::AlreadyRegistered { name } => {
+                write!(f, "Host function `{name}` is already registered")
+            }
+            HostError::NotFound { name } => write!(f, "No host function `{name}`"),
+            HostError::ArgumentCount { expected, found } => {
+                write!(f, "Expected {expected} arguments, found {found}")
+            }
+            HostError::ArgumentType { index, expected } => {
+                write!(f, "Argument {index} should be of type {expected:?}")
+            }
+            HostError::ResultType {
+                expected: Some(expected),
+            } => write!(f, "Result should be of type {expected:?}"),
+            HostError::ResultType { expected: None } => write!(f, "Expected no result"),
+            HostError::Conversion(e) => write!(f, "{e}"),
+            HostError::Failed(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl Error for HostError {}
+
+impl From<ConversionError> for HostError {
+    fn from(e: ConversionError) -> Self {
+        HostError::Conversion(e)
+    }
+}
+
+type Callback = Box<dyn Fn(&mut Heap, &[Value]) -> Result<Option<Value>, HostError>>;
+
+/// What a host function can return
+pub trait HostResult {
+    fn into_result(self, heap: &mut Heap) -> Result<Option<Value>, HostError>;
+}
+
+impl HostResult for () {
+    fn into_result(self, _heap: &mut Heap) -> Result<Option<Value>, HostError> {
+        Ok(None)
+    }
+}
+
+impl<T: ToValue> HostResult for T {
+    fn into_result(self, heap: &mut Heap) -> Result<Option<Value>, HostError> {
+        Ok(Some(self.to_value(heap)))
+    }
+}
+
+impl<T: HostResult> HostResult for Result<T, HostError> {
+    fn into_result(self, heap: &mut Heap) -> Result<Option<Value>, HostError> {
+        self?.into_result(heap)
+    }
+}
+
+/// Rust closures taking arguments convertible with `FromValue`
+pub trait IntoHostFunction<Args> {
+    fn into_callback(self) -> Callback;
+}
+
+/// Marks host functions working with `Value`s directly
+#[derive(Debug, Clone, Copy)]
+pub struct Untyped;
+
+impl<F> IntoHostFunction<Untyped> for F
+where
+    F: Fn(&mut Heap, &[Value]) -> Result<Option<Value>, HostError> + 'static,
+{
+    fn into_callback(self) -> Callback {
+        Box::new(self)
+    }
+}
+
+macro_rules! typed_host_function {
+    ($(($($arg:ident $value:ident),*)),+ $(,)?) => {
+        $(
+            impl<F, R, $($arg),*> IntoHostFunction<($($arg,)*)> for F
+            where
+                F: Fn($($arg),*) -> R + 'static,
+                R: HostResult,
+                $($arg: FromValue,)*
+            {
+                fn into_callback(self) -> Callback {
+                    Box::new(move |heap, args| {
+                        let &[$($value),*] = args else {
+                            unreachable!("Argument count is checked by the caller");
+                        };
+                        self($($arg::from_value($value, heap)?),*).into_result(heap)
+                    })
+                }
+            }
+        )+
+    };
+}
+
+typed_host_function! {
+    (),
+    (A a),
+    (A a, B b),
+    (A a, B b, C c),
+    (A a, B b, C c, D d),
+}
+
+pub struct HostFunction {
+    pub name: String,
+    pub signature: Signature,
+    callback: Callback,
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("name", &self.name)
+            .field("signature", &self.signature)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HostFunction {
+    /// Checks arguments against the signature, so that the callback does not have to,
+    /// and the result, so that the program does not have to.
+    /// Type ids of the signature are the ones of `rtti`.
+    pub fn call(
+        &self,
+        heap: &mut Heap,
+        rtti: &RTTI,
+        args: &[Value],
+    ) -> Result<Option<Value>, HostError> {
+        let Signature {
+            args: arg_types,
+            result,
+        } = &self.signature;
+        if arg_types.len() != args.len() {
+            return Err(HostError::ArgumentCount {
+                expected: arg_types.len(),
+                found: args.len(),
+            });
+        }
+        for (index, (&expected, &arg)) in arg_types.iter().zip(args).enumerate() {
+            if !accepts(expected, arg, heap, rtti) {
+                return Err(HostError::ArgumentType { index, expected });
+            }
+        }
+        let value = (self.callback)(heap, args)?;
+        match (*result, value) {
+            (None, None) => Ok(None),
+            (Some(expected), Some(value)) if accepts(expected, value, heap, rtti) => {
+                Ok(Some(value))
+            }
+            (expected, _) => Err(HostError::ResultType { expected }),
+        }
+    }
+}
+
+/// Host functions by name, `Vm::load` resolves native routines past the built-ins to them
+#[derive(Debug, Default)]
+pub struct HostFunctions(Vec<HostFunction>);
+
+impl HostFunctions {
+    pub fn register<Args>(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        function: impl IntoHostFunction<Args>,
+    ) -> Result<usize, HostError> {
+        if self.lookup(name).is_some() {
+            return Err(HostError::AlreadyRegistered {
+                name: name.to_owned(),
+            });
+        }
+        self.0.push(HostFunction {
+            name: name.to_owned(),
+            signature,
+            callback: function.into_callback(),
+        });
+        Ok(self.0.len() - 1)
+    }
+
+    #[must_use]
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|function| function.name == name)
+    }
+
+    #[must_use]
+    pub fn get(&self, id: usize) -> Option<&HostFunction> {
+        self.0.get(id)
+    }
+}
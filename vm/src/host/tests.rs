@@ -0,0 +1,209 @@
+use super::*;
+use crate::Vm;
+use crate::test_support::compiled;
+use crate::value::Object;
+
+fn signature(args: &[TypeId], result: Option<TypeId>) -> Signature {
+    Signature {
+        args: args.to_vec(),
+        result,
+    }
+}
+
+/// Records and arrays are numbered after the primitives, as in `TYPES`
+const POINT: TypeId = TypeId(4);
+const INTEGERS: TypeId = TypeId(5);
+
+/// Host functions see the types of the loaded program
+const TYPES: &str = "
+    type point is record var x : real; var y : real; end;
+    type integers is array [] integer;
+    routine main() is end;
+";
+
+#[test]
+fn primitives() {
+    let mut vm = Vm::new();
+    vm.register(
+        "hypot",
+        signature(&[TypeId::REAL, TypeId::REAL], Some(TypeId::REAL)),
+        |a: f64, b: f64| a.hypot(b),
+    )
+    .expect("Not registered yet");
+
+    assert_eq!(
+        vm.call_host("hypot", &[Value::Real(3.0), Value::Real(4.0)]),
+        Ok(Some(Value::Real(5.0)))
+    );
+    assert_eq!(
+        vm.call_host("hypot", &[Value::Real(3.0), Value::Int(4)]),
+        Err(HostError::ArgumentType {
+            index: 1,
+            expected: TypeId::REAL
+        })
+    );
+    assert_eq!(
+        vm.call_host("hypot", &[Value::Real(3.0)]),
+        Err(HostError::ArgumentCount {
+            expected: 2,
+            found: 1
+        })
+    );
+}
+
+#[test]
+fn strings() {
+    let mut vm = Vm::new();
+    vm.register(
+        "greet",
+        signature(&[TypeId::STRING], Some(TypeId::STRING)),
+        |name: String| format!("Hello, {name}!"),
+    )
+    .expect("Not registered yet");
+
+    let name = "world".to_value(vm.heap());
+    let Ok(Some(greeting)) = vm.call_host("greet", &[name]) else {
+        unreachable!("greet returns a string")
+    };
+    assert_eq!(
+        String::from_value(greeting, vm.heap()),
+        Ok("Hello, world!".to_owned())
+    );
+    assert_eq!(
+        vm.call_host("greet", &[Value::Int(1)]),
+        Err(HostError::ArgumentType {
+            index: 0,
+            expected: TypeId::STRING
+        })
+    );
+}
+
+#[test]
+fn arrays_and_records() {
+    let mut vm = Vm::new();
+    vm.load(compiled(TYPES))
+        .expect("No host functions are used");
+    vm.register(
+        "sum",
+        signature(&[INTEGERS], Some(TypeId::INTEGER)),
+        |values: Vec<i64>| values.iter().sum::<i64>(),
+    )
+    .expect("Not registered yet");
+    vm.register("origin", signature(&[], Some(POINT)), || (0.0_f64, 0.0_f64))
+        .expect("Not registered yet");
+
+    let values = vec![1_i64, 2, 3].to_value(vm.heap());
+    assert_eq!(vm.call_host("sum", &[values]), Ok(Some(Value::Int(6))));
+
+    let Ok(Some(Value::Ref(origin))) = vm.call_host("origin", &[]) else {
+        unreachable!("origin returns a record")
+    };
+    assert_eq!(
+        vm.heap().get(origin),
+        &Object::Record(vec![Value::Real(0.0), Value::Real(0.0)])
+    );
+    assert_eq!(
+        <(f64, f64)>::from_value(Value::Ref(origin), vm.heap()),
+        Ok((0.0, 0.0))
+    );
+    assert_eq!(
+        <Vec<f64>>::from_value(Value::Ref(origin), vm.heap()),
+        Err(ConversionError::UnexpectedObject { expected: "array" })
+    );
+    assert_eq!(
+        vm.call_host("sum", &[Value::Ref(origin)]),
+        Err(HostError::ArgumentType {
+            index: 0,
+            expected: INTEGERS
+        })
+    );
+}
+
+#[test]
+fn results() {
+    let mut vm = Vm::new();
+    vm.load(compiled(TYPES))
+        .expect("No host functions are used");
+    vm.register("line", signature(&[], Some(POINT)), || {
+        (0.0_f64, 0.0_f64, 0.0_f64)
+    })
+    .expect("Not registered yet");
+    vm.register("count", signature(&[], Some(TypeId::INTEGER)), || 1.0_f64)
+        .expect("Not registered yet");
+    vm.register("log", signature(&[TypeId::STRING], None), |_: String| 1_i64)
+        .expect("Not registered yet");
+
+    assert_eq!(
+        vm.call_host("line", &[]),
+        Err(HostError::ResultType {
+            expected: Some(POINT)
+        })
+    );
+    assert_eq!(
+        vm.call_host("count", &[]),
+        Err(HostError::ResultType {
+            expected: Some(TypeId::INTEGER)
+        })
+    );
+    let message = "done".to_value(vm.heap());
+    assert_eq!(
+        vm.call_host("log", &[message]),
+        Err(HostError::ResultType { expected: None })
+    );
+}
+
+/// Without a program only the primitives have types
+#[test]
+fn unknown_types() {
+    let mut vm = Vm::new();
+    vm.register(
+        "sum",
+        signature(&[INTEGERS], Some(TypeId::INTEGER)),
+        |values: Vec<i64>| values.iter().sum::<i64>(),
+    )
+    .expect("Not registered yet");
+
+    let values = vec![1_i64, 2, 3].to_value(vm.heap());
+    assert_eq!(
+        vm.call_host("sum", &[values]),
+        Err(HostError::ArgumentType {
+            index: 0,
+            expected: INTEGERS
+        })
+    );
+}
+
+#[test]
+fn failures() {
+    let mut vm = Vm::new();
+    vm.register(
+        "checked_div",
+        signature(&[TypeId::INTEGER, TypeId::INTEGER], Some(TypeId::INTEGER)),
+        |a: i64, b: i64| {
+            a.checked_div(b)
+                .ok_or_else(|| HostError::Failed("Division by zero".to_owned()))
+        },
+    )
+    .expect("Not registered yet");
+
+    assert_eq!(
+        vm.call_host("checked_div", &[Value::Int(1), Value::Int(0)]),
+        Err(HostError::Failed("Division by zero".to_owned()))
+    );
+    assert_eq!(
+        vm.register(
+            "checked_div",
+            signature(&[], None),
+            |_: &mut Heap, _: &[Value]| Ok(None)
+        ),
+        Err(HostError::AlreadyRegistered {
+            name: "checked_div".to_owned()
+        })
+    );
+    assert_eq!(
+        vm.call_host("missing", &[]),
+        Err(HostError::NotFound {
+            name: "missing".to_owned()
+        })
+    );
+}
@@ -0,0 +1,913 @@
+//! Stack machine executing `Bytecode`, `registers` runs the register code instead
+//!
+//! Booleans are integers 0 and 1 here, as `Bytecode::IntConst` pushes both,
+//! `Value::Bool` only appears when values are passed to or from the embedding program.
+
+use core::error::Error;
+use core::fmt;
+use std::io::{self, Write as _};
+
+use compiler::builtins::Builtin;
+use compiler::bytecode::{
+    ArrayRTTI, Bytecode, EnumRTTI, Location, PrimitiveRTTI, RTTIElement, RecordRTTI, TypeId,
+};
+use compiler::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+
+use crate::console::{Console, InputError};
+use crate::host::{HostError, HostFunctions};
+use crate::natives;
+use crate::profile::Profile;
+use crate::program::Program;
+use crate::value::{Heap, Object, ObjectRef, Value};
+
+mod registers;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// `Bytecode::Panic`
+    Panic {
+        code: u64,
+    },
+    IndexOutOfBounds {
+        index: i64,
+        length: usize,
+    },
+    DivisionByZero,
+    IntegerOverflow,
+    /// A recursive record field that was never assigned is used
+    NullReference,
+    /// More than `MAX_DEPTH` routines are running
+    StackOverflow,
+    InvalidConversion(String),
+    AssertionFailed,
+    Input(InputError),
+    Host(HostError),
+    Io(io::Error),
+    NotLoaded,
+    UnknownRoutine {
+        name: String,
+    },
+    /// `Vm::call` got more or fewer arguments than the routine takes
+    ArgumentCount {
+        routine: String,
+        expected: usize,
+        found: usize,
+    },
+    /// `Vm::call` got an argument of another type than the parameter, `index` counts from 0
+    ArgumentType {
+        routine: String,
+        index: usize,
+        expected: TypeId,
+    },
+    /// The code does something the compiler never generates
+    Malformed(String),
+    /// `wasm::run` could not load or finish the module
+    Wasm(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::Panic { code } => write!(f, "Panic with code {code}"),
+            RuntimeError::IndexOutOfBounds { index, length } => {
+                write!(f, "Index {index} is out of bounds 1..={length}")
+            }
+            RuntimeError::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeError::IntegerOverflow => write!(f, "Integer overflow"),
+            RuntimeError::NullReference => write!(f, "Null reference"),
+            RuntimeError::StackOverflow => write!(f, "Stack overflow"),
+            RuntimeError::InvalidConversion(reason) => write!(f, "Invalid conversion: {reason}"),
+            RuntimeError::AssertionFailed => write!(f, "Assertion failed"),
+            RuntimeError::Input(e) => write!(f, "{e}"),
+            RuntimeError::Host(e) => write!(f, "{e}"),
+            RuntimeError::Io(e) => write!(f, "{e}"),
+            RuntimeError::NotLoaded => write!(f, "No program is loaded"),
+            RuntimeError::UnknownRoutine { name } => write!(f, "No routine `{name}`"),
+            RuntimeError::ArgumentCount {
+                routine,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Routine `{routine}` takes {expected} arguments, found {found}"
+            ),
+            RuntimeError::ArgumentType {
+                routine,
+                index,
+                expected,
+            } => write!(
+                f,
+                "Argument {index} of routine `{routine}` should be of type {expected:?}"
+            ),
+            RuntimeError::Malformed(reason) => write!(f, "Malformed bytecode: {reason}"),
+            RuntimeError::Wasm(reason) => write!(f, "WebAssembly: {reason}"),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+impl From<HostError> for RuntimeError {
+    fn from(e: HostError) -> Self {
+        RuntimeError::Host(e)
+    }
+}
+
+impl From<InputError> for RuntimeError {
+    fn from(e: InputError) -> Self {
+        RuntimeError::Input(e)
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> Self {
+        RuntimeError::Io(e)
+    }
+}
+
+fn malformed<T>(reason: &str) -> Result<T, RuntimeError> {
+    Err(RuntimeError::Malformed(reason.to_owned()))
+}
+
+pub(crate) fn int(value: Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(value) => Ok(value),
+        Value::Real(_) | Value::Bool(_) | Value::Ref(_) => malformed("Expected an integer"),
+    }
+}
+
+pub(crate) fn real(value: Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Real(value) => Ok(value),
+        Value::Int(_) | Value::Bool(_) | Value::Ref(_) => malformed("Expected a real"),
+    }
+}
+
+/// Conditional jumps treat any non-zero integer as true
+pub(crate) fn truth(value: Value) -> Result<bool, RuntimeError> {
+    match value {
+        Value::Int(value) => Ok(value != 0),
+        Value::Bool(value) => Ok(value),
+        Value::Real(_) | Value::Ref(_) => malformed("Expected a boolean"),
+    }
+}
+
+/// Like `Display` of an `f64`, the shortest digits reading back the same without an exponent,
+/// with `.0` after whole numbers: `25.0`, `0.00000000000001`
+pub(crate) fn format_real(value: f64) -> String {
+    let mut formatted = value.to_string();
+    if value.is_finite() && !formatted.contains('.') {
+        formatted.push_str(".0");
+    }
+    formatted
+}
+
+/// Truncates towards zero
+pub(crate) fn real_to_int(value: f64) -> Result<i64, RuntimeError> {
+    let value = value.trunc();
+    #[expect(clippy::cast_precision_loss, reason = "Bounds are powers of two")]
+    if value.is_finite() && (i64::MIN as f64..-(i64::MIN as f64)).contains(&value) {
+        #[expect(clippy::cast_possible_truncation, reason = "Range is checked")]
+        Ok(value as i64)
+    } else {
+        Err(RuntimeError::InvalidConversion(format!(
+            "{} does not fit into an integer",
+            format_real(value)
+        )))
+    }
+}
+
+pub(crate) fn string(heap: &Heap, value: Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::Ref(ObjectRef::NULL) => Err(RuntimeError::NullReference),
+        Value::Ref(object) => match heap.get(object) {
+            Object::String(string) => Ok(string),
+            Object::Array(_) | Object::Record(_) => malformed("Expected a string"),
+        },
+        Value::Int(_) | Value::Real(_) | Value::Bool(_) => malformed("Expected a string"),
+    }
+}
+
+/// Booleans of the embedding program become integers
+pub(crate) fn to_machine(value: Value) -> Value {
+    match value {
+        Value::Bool(value) => Value::Int(value.into()),
+        Value::Int(_) | Value::Real(_) | Value::Ref(_) => value,
+    }
+}
+
+/// And back, knowing the type
+pub(crate) fn from_machine(value: Value, type_id: TypeId) -> Value {
+    match (value, type_id) {
+        (Value::Int(value), TypeId::BOOLEAN) => Value::Bool(value != 0),
+        _ => value,
+    }
+}
+
+/// String operators read `heap`, concatenation allocates the result there
+fn binary(
+    op: SemanticBinaryOperator,
+    lhs: Value,
+    rhs: Value,
+    heap: &mut Heap,
+) -> Result<Value, RuntimeError> {
+    use SemanticBinaryOperator as Op;
+
+    let bool = |value: bool| Ok(Value::Int(value.into()));
+    let ints = || Ok::<_, RuntimeError>((int(lhs)?, int(rhs)?));
+    let reals = || Ok::<_, RuntimeError>((real(lhs)?, real(rhs)?));
+    let strings = |heap: &Heap| Ok::<_, RuntimeError>(string(heap, lhs)?.cmp(string(heap, rhs)?));
+    let checked = |result: Option<i64>| result.map(Value::Int).ok_or(RuntimeError::IntegerOverflow);
+    let division = |f: fn(i64, i64) -> Option<i64>| {
+        let (lhs, rhs) = ints()?;
+        if rhs == 0 {
+            Err(RuntimeError::DivisionByZero)
+        } else {
+            checked(f(lhs, rhs))
+        }
+    };
+
+    match op {
+        Op::RealAdd => reals().map(|(lhs, rhs)| Value::Real(lhs + rhs)),
+        Op::RealSub => reals().map(|(lhs, rhs)| Value::Real(lhs - rhs)),
+        Op::RealMul => reals().map(|(lhs, rhs)| Value::Real(lhs * rhs)),
+        Op::RealDiv => reals().map(|(lhs, rhs)| Value::Real(lhs / rhs)),
+        Op::RealLe => reals().and_then(|(lhs, rhs)| bool(lhs <= rhs)),
+        Op::RealLg => reals().and_then(|(lhs, rhs)| bool(lhs < rhs)),
+        Op::RealGt => reals().and_then(|(lhs, rhs)| bool(lhs > rhs)),
+        Op::RealGe => reals().and_then(|(lhs, rhs)| bool(lhs >= rhs)),
+        #[expect(clippy::float_cmp, reason = "It is what the program asks for")]
+        Op::RealEq => reals().and_then(|(lhs, rhs)| bool(lhs == rhs)),
+        #[expect(clippy::float_cmp, reason = "It is what the program asks for")]
+        Op::RealNeq => reals().and_then(|(lhs, rhs)| bool(lhs != rhs)),
+        Op::IntAdd => ints().and_then(|(lhs, rhs)| checked(lhs.checked_add(rhs))),
+        Op::IntSub => ints().and_then(|(lhs, rhs)| checked(lhs.checked_sub(rhs))),
+        Op::IntMul => ints().and_then(|(lhs, rhs)| checked(lhs.checked_mul(rhs))),
+        Op::IntDiv => division(i64::checked_div),
+        Op::IntMod => division(i64::checked_rem),
+        Op::IntLe => ints().and_then(|(lhs, rhs)| bool(lhs <= rhs)),
+        Op::IntLg => ints().and_then(|(lhs, rhs)| bool(lhs < rhs)),
+        Op::IntGt => ints().and_then(|(lhs, rhs)| bool(lhs > rhs)),
+        Op::IntGe => ints().and_then(|(lhs, rhs)| bool(lhs >= rhs)),
+        // Also compares references by identity
+        Op::IntEq => bool(lhs == rhs),
+        Op::IntNeq => bool(lhs != rhs),
+        Op::BoolAnd => bool(truth(lhs)? && truth(rhs)?),
+        Op::BoolXor => bool(truth(lhs)? != truth(rhs)?),
+        Op::BoolOr => bool(truth(lhs)? || truth(rhs)?),
+        Op::StringConcat => {
+            let result = [string(heap, lhs)?, string(heap, rhs)?].concat();
+            Ok(Value::Ref(heap.alloc(Object::String(result))))
+        }
+        Op::StringLe => bool(strings(heap)?.is_le()),
+        Op::StringLg => bool(strings(heap)?.is_lt()),
+        Op::StringGt => bool(strings(heap)?.is_gt()),
+        Op::StringGe => bool(strings(heap)?.is_ge()),
+        Op::StringEq => bool(strings(heap)?.is_eq()),
+        Op::StringNeq => bool(strings(heap)?.is_ne()),
+    }
+}
+
+/// 0-based offset of the 1-based `index` of an array
+fn element_offset(index: i64, length: usize) -> Result<usize, RuntimeError> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| index.checked_sub(1))
+        .filter(|&offset| offset < length)
+        .ok_or(RuntimeError::IndexOutOfBounds { index, length })
+}
+
+fn unary(op: SemanticUnaryOperator, value: Value) -> Result<Value, RuntimeError> {
+    match op {
+        SemanticUnaryOperator::IntNeg => int(value)?
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or(RuntimeError::IntegerOverflow),
+        SemanticUnaryOperator::RealNeg => Ok(Value::Real(-real(value)?)),
+        SemanticUnaryOperator::BoolNeg => Ok(Value::Int((!truth(value)?).into())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Address {
+    Global(usize),
+    Stack(usize),
+    /// 0-based
+    Element(ObjectRef, usize),
+    Field(ObjectRef, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Value(Value),
+    Address(Address),
+}
+
+#[derive(Debug)]
+struct Frame {
+    /// `None` for the routine called by the embedding program
+    return_pc: Option<usize>,
+    /// Stack index of the first argument
+    base: usize,
+    args: usize,
+    /// `base` of the frame a `Bytecode::TailCall` replaced,
+    /// the callee's `Enter` moves its arguments there
+    replaced: Option<usize>,
+}
+
+/// Routines running at once, tail calls do not count
+pub const MAX_DEPTH: usize = 100_000;
+
+/// State of a single `Vm::call`
+pub(crate) struct Machine<'a> {
+    pub(crate) program: &'a Program,
+    pub(crate) heap: &'a mut Heap,
+    pub(crate) host_functions: &'a HostFunctions,
+    pub(crate) globals: &'a mut [Value],
+    pub(crate) console: &'a mut Console,
+    stack: Vec<Slot>,
+    frames: Vec<Frame>,
+    /// Instructions run so far
+    pub(crate) executed: u64,
+    /// Routines running outside the loop of the current `run_registers`,
+    /// in the loops below it and in native code
+    pub(crate) depth: usize,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Option<&'a mut crate::jit::Jit>,
+    /// Of the stack machine
+    pub(crate) profile: Option<&'a mut Profile>,
+}
+
+impl<'a> Machine<'a> {
+    pub(crate) fn new(
+        program: &'a Program,
+        heap: &'a mut Heap,
+        host_functions: &'a HostFunctions,
+        globals: &'a mut [Value],
+        console: &'a mut Console,
+    ) -> Self {
+        Self {
+            program,
+            heap,
+            host_functions,
+            globals,
+            console,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            executed: 0,
+            depth: 0,
+            #[cfg(feature = "jit")]
+            jit: None,
+            profile: None,
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(Slot::Value(value));
+    }
+
+    fn pop_slot(&mut self) -> Result<Slot, RuntimeError> {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        if self.stack.len() <= base {
+            return malformed("Stack underflow");
+        }
+        self.stack
+            .pop()
+            .ok_or_else(|| RuntimeError::Malformed("Stack underflow".to_owned()))
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.pop_slot()? {
+            Slot::Value(value) => Ok(value),
+            Slot::Address(_) => malformed("Expected a value, found an address"),
+        }
+    }
+
+    fn pop_address(&mut self) -> Result<Address, RuntimeError> {
+        match self.pop_slot()? {
+            Slot::Address(address) => Ok(address),
+            Slot::Value(_) => malformed("Expected an address, found a value"),
+        }
+    }
+
+    fn pop_ref(&mut self) -> Result<ObjectRef, RuntimeError> {
+        match self.pop()? {
+            Value::Ref(ObjectRef::NULL) => Err(RuntimeError::NullReference),
+            Value::Ref(object) => Ok(object),
+            Value::Int(_) | Value::Real(_) | Value::Bool(_) => malformed("Expected a reference"),
+        }
+    }
+
+    fn frame(&self) -> Result<&Frame, RuntimeError> {
+        self.frames
+            .last()
+            .ok_or_else(|| RuntimeError::Malformed("No routine is running".to_owned()))
+    }
+
+    fn address(&self, loc: Location) -> Result<Address, RuntimeError> {
+        match loc {
+            Location::Global(index) if index < self.globals.len() => Ok(Address::Global(index)),
+            Location::Global(_) => malformed("No such global"),
+            Location::Argument(index) => {
+                let frame = self.frame()?;
+                if index < frame.args {
+                    Ok(Address::Stack(frame.base + index))
+                } else {
+                    malformed("No such argument")
+                }
+            }
+            Location::Local(index) => {
+                let frame = self.frame()?;
+                let index = frame.base + frame.args + index;
+                if index < self.stack.len() {
+                    Ok(Address::Stack(index))
+                } else {
+                    malformed("No such local")
+                }
+            }
+        }
+    }
+
+    fn read(&self, address: Address) -> Result<Value, RuntimeError> {
+        match address {
+            Address::Global(index) => Ok(self.globals[index]),
+            Address::Stack(index) => match self.stack[index] {
+                Slot::Value(value) => Ok(value),
+                Slot::Address(_) => malformed("Variables hold values"),
+            },
+            Address::Element(object, index) | Address::Field(object, index) => {
+                match self.heap.get(object) {
+                    Object::Array(values) | Object::Record(values) => Ok(values[index]),
+                    Object::String(_) => malformed("Strings are not addressable"),
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, address: Address, value: Value) -> Result<(), RuntimeError> {
+        match address {
+            Address::Global(index) => self.globals[index] = value,
+            Address::Stack(index) => self.stack[index] = Slot::Value(value),
+            Address::Element(object, index) | Address::Field(object, index) => {
+                match self.heap.get_mut(object) {
+                    Object::Array(values) | Object::Record(values) => values[index] = value,
+                    Object::String(_) => return malformed("Strings are not addressable"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Called between instructions of the outermost loop, where the stack, the globals
+    /// and the `registers` of that loop hold every value in use
+    pub(crate) fn collect_garbage(&mut self, registers: &[Value]) {
+        let stack = self.stack.iter().filter_map(|slot| match *slot {
+            Slot::Value(value) => Some(value),
+            Slot::Address(Address::Element(object, _) | Address::Field(object, _)) => {
+                Some(Value::Ref(object))
+            }
+            Slot::Address(Address::Global(_) | Address::Stack(_)) => None,
+        });
+        let strings = self.program.strings().iter().copied().map(Value::Ref);
+        let _: usize = self.heap.collect(
+            stack
+                .chain(self.globals.iter().copied())
+                .chain(registers.iter().copied())
+                .chain(strings),
+        );
+    }
+
+    fn rtti(&self, type_id: TypeId) -> Result<&'a RTTIElement, RuntimeError> {
+        self.program
+            .module
+            .rtti
+            .get(type_id)
+            .ok_or_else(|| RuntimeError::Malformed(format!("No type with id {}", type_id.0)))
+    }
+
+    /// Primitives are zero or empty strings, enums are their first variant,
+    /// nested records are allocated unless the type is recursive, then they are null,
+    /// nested arrays are empty, since RTTI does not know their sizes
+    fn default_value(
+        &mut self,
+        type_id: TypeId,
+        allocating: &mut Vec<TypeId>,
+    ) -> Result<Value, RuntimeError> {
+        match *self.rtti(type_id)? {
+            RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::REAL }) => Ok(Value::Real(0.0)),
+            RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::STRING }) => {
+                Ok(Value::Ref(self.heap.alloc(Object::String(String::new()))))
+            }
+            RTTIElement::Primitive(_) | RTTIElement::Enum(_) => Ok(Value::Int(0)),
+            RTTIElement::Record(_) if allocating.contains(&type_id) => {
+                Ok(Value::Ref(ObjectRef::NULL))
+            }
+            RTTIElement::Record(_) => self.alloc_record(type_id, allocating),
+            RTTIElement::Array(_) => self.alloc_array(type_id, 0, allocating),
+        }
+    }
+
+    pub(crate) fn alloc_record(
+        &mut self,
+        type_id: TypeId,
+        allocating: &mut Vec<TypeId>,
+    ) -> Result<Value, RuntimeError> {
+        let RTTIElement::Record(RecordRTTI { field_ids, .. }) = self.rtti(type_id)? else {
+            return malformed("Expected a record type");
+        };
+        if let Some(profile) = self.profile.as_deref_mut() {
+            profile.allocation(type_id);
+        }
+        allocating.push(type_id);
+        let fields = field_ids
+            .iter()
+            .map(|&field| self.default_value(field, allocating))
+            .collect::<Result<_, _>>()?;
+        let _: Option<TypeId> = allocating.pop();
+        Ok(Value::Ref(
+            self.heap.alloc_typed(Object::Record(fields), type_id),
+        ))
+    }
+
+    pub(crate) fn alloc_array(
+        &mut self,
+        type_id: TypeId,
+        size: u64,
+        allocating: &mut Vec<TypeId>,
+    ) -> Result<Value, RuntimeError> {
+        let RTTIElement::Array(ArrayRTTI { element_id, .. }) = *self.rtti(type_id)? else {
+            return malformed("Expected an array type");
+        };
+        if let Some(profile) = self.profile.as_deref_mut() {
+            profile.allocation(type_id);
+        }
+        let elements = (0..size)
+            .map(|_| self.default_value(element_id, allocating))
+            .collect::<Result<_, _>>()?;
+        Ok(Value::Ref(
+            self.heap.alloc_typed(Object::Array(elements), type_id),
+        ))
+    }
+
+    fn format(&self, value: Value, type_id: TypeId, out: &mut String) -> Result<(), RuntimeError> {
+        match (self.rtti(type_id)?, value) {
+            (
+                RTTIElement::Primitive(PrimitiveRTTI {
+                    id: TypeId::INTEGER,
+                }),
+                Value::Int(value),
+            ) => {
+                out.push_str(&value.to_string());
+            }
+            (RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::REAL }), Value::Real(value)) => {
+                out.push_str(&format_real(value));
+            }
+            (
+                RTTIElement::Primitive(PrimitiveRTTI {
+                    id: TypeId::BOOLEAN,
+                }),
+                value,
+            ) => {
+                out.push_str(if truth(value)? { "true" } else { "false" });
+            }
+            (RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::STRING }), value) => {
+                out.push_str(string(self.heap, value)?);
+            }
+            (RTTIElement::Enum(EnumRTTI { variants, .. }), Value::Int(value)) => {
+                let variant = usize::try_from(value)
+                    .ok()
+                    .and_then(|index| variants.get(index))
+                    .ok_or_else(|| {
+                        RuntimeError::Malformed(format!("{value} is not a variant of the enum"))
+                    })?;
+                out.push_str(variant);
+            }
+            (RTTIElement::Array(_) | RTTIElement::Record(_), Value::Ref(ObjectRef::NULL)) => {
+                return Err(RuntimeError::NullReference);
+            }
+            (&RTTIElement::Array(ArrayRTTI { element_id, .. }), Value::Ref(object)) => {
+                let Object::Array(elements) = self.heap.get(object) else {
+                    return malformed("Expected an array");
+                };
+                out.push('[');
+                for (i, &element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.format(element, element_id, out)?;
+                }
+                out.push(']');
+            }
+            (RTTIElement::Record(RecordRTTI { field_ids, .. }), Value::Ref(object)) => {
+                let Object::Record(fields) = self.heap.get(object) else {
+                    return malformed("Expected a record");
+                };
+                out.push('{');
+                for (i, (&field, &field_id)) in fields.iter().zip(field_ids).enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.format(field, field_id, out)?;
+                }
+                out.push('}');
+            }
+            _ => return malformed("Value does not match its type"),
+        }
+        Ok(())
+    }
+
+    fn call_native(&mut self, id: u32) -> Result<Value, RuntimeError> {
+        let arity = match Builtin::from_id(id) {
+            Some(builtin) => builtin.arity(),
+            None => self
+                .host_functions
+                .get(self.program.host(id))
+                .expect("Resolved when loaded")
+                .signature
+                .args
+                .len(),
+        };
+        let args = self.pop_args(arity)?;
+        self.native(id, args)
+    }
+
+    /// `args` are in the order of parameters
+    pub(crate) fn native(&mut self, id: u32, mut args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if let Some(builtin) = Builtin::from_id(id) {
+            return natives::call(
+                builtin,
+                self.heap,
+                &self.program.module.rtti,
+                self.console,
+                &args,
+            );
+        }
+
+        let function = self
+            .host_functions
+            .get(self.program.host(id))
+            .expect("Resolved when loaded");
+        if args.len() != function.signature.args.len() {
+            return malformed("Wrong number of arguments");
+        }
+        for (arg, &type_id) in args.iter_mut().zip(&function.signature.args) {
+            *arg = from_machine(*arg, type_id);
+        }
+        let result = function.call(self.heap, &self.program.module.rtti, &args)?;
+        Ok(result.map_or(Value::Int(0), to_machine))
+    }
+
+    /// In the order of parameters
+    fn pop_args(&mut self, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        let mut args = (0..count)
+            .map(|_| self.pop())
+            .collect::<Result<Vec<_>, _>>()?;
+        args.reverse();
+        Ok(args)
+    }
+
+    fn jump(&self, label: u64) -> usize {
+        self.program.label(label)
+    }
+
+    /// Where jumping to `label` from before `pc` goes, jumps back are loops for the profile
+    fn jump_from(&mut self, pc: usize, label: u64) -> usize {
+        let target = self.jump(label);
+        if target < pc
+            && let Some(profile) = self.profile.as_deref_mut()
+        {
+            profile.trip(label);
+        }
+        target
+    }
+
+    /// Runs the routine starting at `label` until it returns
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    pub(crate) fn run(&mut self, label: u64, args: &[Value]) -> Result<Value, RuntimeError> {
+        for &arg in args {
+            self.push(to_machine(arg));
+        }
+        self.frames.push(Frame {
+            return_pc: None,
+            base: self.stack.len(),
+            args: 0,
+            replaced: None,
+        });
+        let program = self.program;
+        let code = &program.module.code;
+        let mut pc = self.jump(label);
+        if let Some(profile) = self.profile.as_deref_mut() {
+            profile.enter(label);
+        }
+
+        loop {
+            if self.heap.should_collect() {
+                self.collect_garbage(&[]);
+            }
+            let Some(&instruction) = code.get(pc) else {
+                return malformed("Execution went past the end of the code");
+            };
+            pc += 1;
+            self.executed += 1;
+            if let Some(profile) = self.profile.as_deref_mut() {
+                profile.instruction();
+            }
+
+            match instruction {
+                Bytecode::IntConst { value } => self.push(Value::Int(value)),
+                Bytecode::RealConst { value } => self.push(Value::Real(value)),
+                Bytecode::StringConst { id } => self.push(Value::Ref(program.string(id))),
+                Bytecode::Load { loc } => {
+                    let value = self.read(self.address(loc)?)?;
+                    self.push(value);
+                }
+                Bytecode::Store { loc } => {
+                    let value = self.pop()?;
+                    self.write(self.address(loc)?, value)?;
+                }
+                Bytecode::AddressOf { loc } => {
+                    let address = self.address(loc)?;
+                    self.stack.push(Slot::Address(address));
+                }
+                Bytecode::Dup => {
+                    let slot = self.pop_slot()?;
+                    self.stack.extend([slot, slot]);
+                }
+                Bytecode::Drop => drop(self.pop_slot()?),
+                Bytecode::Swap => {
+                    let top = self.pop_slot()?;
+                    let second = self.pop_slot()?;
+                    self.stack.extend([top, second]);
+                }
+                Bytecode::BinOp { op } => {
+                    let rhs = self.pop()?;
+                    let lhs = self.pop()?;
+                    let result = binary(op, lhs, rhs, self.heap)?;
+                    self.push(result);
+                }
+                Bytecode::UnOp { op } => {
+                    let value = self.pop()?;
+                    self.push(unary(op, value)?);
+                }
+                Bytecode::StoreAddress => {
+                    let address = self.pop_address()?;
+                    let value = self.pop()?;
+                    self.write(address, value)?;
+                }
+                Bytecode::LoadAddress => {
+                    let address = self.pop_address()?;
+                    let value = self.read(address)?;
+                    self.push(value);
+                }
+                Bytecode::AllocRecord { type_id, size: _ } => {
+                    let record = self.alloc_record(type_id, &mut Vec::new())?;
+                    self.push(record);
+                }
+                Bytecode::AllocArray { type_id, size } => {
+                    let array = self.alloc_array(type_id, size, &mut Vec::new())?;
+                    self.push(array);
+                }
+                Bytecode::ArraySize => {
+                    let array = self.pop_ref()?;
+                    let Object::Array(elements) = self.heap.get(array) else {
+                        return malformed("Expected an array");
+                    };
+                    let size = i64::try_from(elements.len()).expect("Arrays fit into memory");
+                    self.push(Value::Int(size));
+                }
+                Bytecode::ElementAddress => {
+                    let index = int(self.pop()?)?;
+                    let array = self.pop_ref()?;
+                    let Object::Array(elements) = self.heap.get(array) else {
+                        return malformed("Expected an array");
+                    };
+                    let offset = element_offset(index, elements.len())?;
+                    self.stack
+                        .push(Slot::Address(Address::Element(array, offset)));
+                }
+                Bytecode::FieldAddress { field_offset } => {
+                    let record = self.pop_ref()?;
+                    let Object::Record(fields) = self.heap.get(record) else {
+                        return malformed("Expected a record");
+                    };
+                    let offset = usize::try_from(field_offset)
+                        .ok()
+                        .filter(|&offset| offset < fields.len())
+                        .ok_or_else(|| RuntimeError::Malformed("No such field".to_owned()))?;
+                    self.stack
+                        .push(Slot::Address(Address::Field(record, offset)));
+                }
+                Bytecode::Label { .. } => {}
+                Bytecode::Jump { label } => pc = self.jump_from(pc, label),
+                Bytecode::JumpZero { label } => {
+                    if !truth(self.pop()?)? {
+                        pc = self.jump_from(pc, label);
+                    }
+                }
+                Bytecode::JumpNotZero { label } => {
+                    if truth(self.pop()?)? {
+                        pc = self.jump_from(pc, label);
+                    }
+                }
+                Bytecode::Enter { args, locals } => {
+                    let args = usize::from(args);
+                    let frame = self.frames.last_mut().expect("Pushed by the call");
+                    frame.base = frame
+                        .base
+                        .checked_sub(args)
+                        .ok_or_else(|| RuntimeError::Malformed("Stack underflow".to_owned()))?;
+                    frame.args = args;
+                    if let Some(start) = frame.replaced.take() {
+                        if start > frame.base {
+                            return malformed("Tail call below its frame");
+                        }
+                        drop(self.stack.drain(start..frame.base));
+                        frame.base = start;
+                    }
+                    self.stack
+                        .extend((0..locals).map(|_| Slot::Value(Value::Int(0))));
+                }
+                Bytecode::Ret => {
+                    let value = self.pop()?;
+                    if let Some(profile) = self.profile.as_deref_mut() {
+                        profile.leave();
+                    }
+                    let frame = self.frames.pop().expect("Pushed by the call");
+                    self.stack.truncate(frame.base);
+                    match frame.return_pc {
+                        Some(return_pc) => {
+                            self.push(value);
+                            pc = return_pc;
+                        }
+                        None => return Ok(value),
+                    }
+                }
+                Bytecode::Call { function_label } => {
+                    if self.frames.len() >= MAX_DEPTH {
+                        return Err(RuntimeError::StackOverflow);
+                    }
+                    self.frames.push(Frame {
+                        return_pc: Some(pc),
+                        base: self.stack.len(),
+                        args: 0,
+                        replaced: None,
+                    });
+                    pc = self.jump(function_label);
+                    if let Some(profile) = self.profile.as_deref_mut() {
+                        profile.enter(function_label);
+                    }
+                }
+                Bytecode::TailCall { function_label } => {
+                    let frame = self.frames.last_mut().expect("Pushed by the call");
+                    frame.replaced = Some(frame.base);
+                    frame.base = self.stack.len();
+                    frame.args = 0;
+                    pc = self.jump(function_label);
+                    if let Some(profile) = self.profile.as_deref_mut() {
+                        profile.leave();
+                        profile.enter(function_label);
+                    }
+                }
+                Bytecode::CallNative { id } => {
+                    let result = self.call_native(id)?;
+                    self.push(result);
+                }
+                Bytecode::Print { type_id } => {
+                    let value = self.pop()?;
+                    let mut line = String::new();
+                    self.format(value, type_id, &mut line)?;
+                    writeln!(self.console.output, "{line}")?;
+                }
+                Bytecode::Panic { code } => return Err(RuntimeError::Panic { code }),
+                Bytecode::IntToBool => {
+                    let value = self.pop()?;
+                    let result = natives::call(
+                        Builtin::ToBoolean,
+                        self.heap,
+                        &self.program.module.rtti,
+                        self.console,
+                        &[value],
+                    )?;
+                    self.push(result);
+                }
+                Bytecode::RealToInt => {
+                    let value = real_to_int(real(self.pop()?)?)?;
+                    self.push(Value::Int(value));
+                }
+                Bytecode::IntToReal => {
+                    let value = self.pop()?;
+                    let result = natives::call(
+                        Builtin::ToReal,
+                        self.heap,
+                        &self.program.module.rtti,
+                        self.console,
+                        &[value],
+                    )?;
+                    self.push(result);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Register machine executing `compiler::registers` code with the heap, globals and natives
+//! of the stack machine. Each frame is a window of `Routine::registers` values.
+
+use std::io::Write as _;
+
+use compiler::builtins::Builtin;
+use compiler::registers::{Code, Instruction, Register, Routine};
+
+use super::{
+    MAX_DEPTH, Machine, RuntimeError, binary, element_offset, int, malformed, real, real_to_int,
+    to_machine, truth, unary,
+};
+use crate::natives;
+use crate::value::{Object, ObjectRef, Value};
+
+#[derive(Debug)]
+struct Frame<'c> {
+    routine: &'c Routine,
+    /// Where the caller continues
+    pc: usize,
+    base: usize,
+    /// Where the caller wants the result
+    dst: Option<Register>,
+    /// Of the caller
+    #[cfg(feature = "jit")]
+    label: u64,
+}
+
+fn get(frame: &[Value], register: Register) -> Result<Value, RuntimeError> {
+    frame
+        .get(register.index())
+        .copied()
+        .ok_or_else(|| RuntimeError::Malformed(format!("No register {register}")))
+}
+
+fn set(frame: &mut [Value], register: Register, value: Value) -> Result<(), RuntimeError> {
+    let slot = frame
+        .get_mut(register.index())
+        .ok_or_else(|| RuntimeError::Malformed(format!("No register {register}")))?;
+    *slot = value;
+    Ok(())
+}
+
+/// The record or array `value` refers to, `reason` if it is no reference
+fn object(value: Value, reason: &str) -> Result<ObjectRef, RuntimeError> {
+    match value {
+        Value::Ref(ObjectRef::NULL) => Err(RuntimeError::NullReference),
+        Value::Ref(object) => Ok(object),
+        Value::Int(_) | Value::Real(_) | Value::Bool(_) => malformed(reason),
+    }
+}
+
+fn lookup(code: &Code, label: u64) -> Result<&Routine, RuntimeError> {
+    code.0
+        .get(&label)
+        .ok_or_else(|| RuntimeError::Malformed(format!("No routine at label {label}")))
+}
+
+/// Appends the frame of `routine` to `registers`, starting with `args`
+fn enter(
+    registers: &mut Vec<Value>,
+    routine: &Routine,
+    args: &[Value],
+) -> Result<(), RuntimeError> {
+    if args.len() != routine.args as usize {
+        return malformed("Wrong number of arguments");
+    }
+    let base = registers.len();
+    registers.extend_from_slice(args);
+    registers.resize(base + routine.registers as usize, Value::Int(0));
+    Ok(())
+}
+
+impl Machine<'_> {
+    pub(crate) fn field(&self, record: Value, offset: u64) -> Result<(Value, usize), RuntimeError> {
+        let object = object(record, "Expected a record")?;
+        let Object::Record(fields) = self.heap.get(object) else {
+            return malformed("Expected a record");
+        };
+        usize::try_from(offset)
+            .ok()
+            .filter(|&offset| offset < fields.len())
+            .map(|offset| (record, offset))
+            .ok_or_else(|| RuntimeError::Malformed("No such field".to_owned()))
+    }
+
+    pub(crate) fn element(
+        &self,
+        array: Value,
+        index: Value,
+    ) -> Result<(Value, usize), RuntimeError> {
+        let object = object(array, "Expected an array")?;
+        let Object::Array(elements) = self.heap.get(object) else {
+            return malformed("Expected an array");
+        };
+        Ok((array, element_offset(int(index)?, elements.len())?))
+    }
+
+    /// Values of records and arrays, `object` is checked by `field` or `element`
+    pub(crate) fn slot(&mut self, object: Value, offset: usize) -> &mut Value {
+        let Value::Ref(object) = object else {
+            unreachable!("Checked before")
+        };
+        match self.heap.get_mut(object) {
+            Object::Array(values) | Object::Record(values) => &mut values[offset],
+            Object::String(_) => unreachable!("Checked before"),
+        }
+    }
+
+    pub(crate) fn array_size(&self, array: Value) -> Result<Value, RuntimeError> {
+        let array = object(array, "Expected an array")?;
+        let Object::Array(elements) = self.heap.get(array) else {
+            return malformed("Expected an array");
+        };
+        let size = i64::try_from(elements.len()).expect("Arrays fit into memory");
+        Ok(Value::Int(size))
+    }
+
+    /// Runs the routine starting at `label` until it returns. With the JIT, hot routines
+    /// and loops run natively, see `crate::jit`.
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    pub(crate) fn run_registers(
+        &mut self,
+        code: &Code,
+        label: u64,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let mut registers = Vec::new();
+        let mut frames: Vec<Frame<'_>> = Vec::new();
+        let mut routine = lookup(code, label)?;
+        let args: Vec<_> = args.iter().copied().map(to_machine).collect();
+        #[cfg(feature = "jit")]
+        if let Some(value) = self.jitted(code, label, 0, &args, 0)? {
+            return Ok(value);
+        }
+        #[cfg(feature = "jit")]
+        let mut label = label;
+        enter(&mut registers, routine, &args)?;
+        let mut base = 0;
+        let mut pc = 0;
+        // Of the routine that ran last
+        let mut returning = None;
+
+        loop {
+            if let Some(value) = returning.take() {
+                registers.truncate(base);
+                let Some(caller) = frames.pop() else {
+                    return Ok(value);
+                };
+                routine = caller.routine;
+                pc = caller.pc;
+                base = caller.base;
+                #[cfg(feature = "jit")]
+                {
+                    label = caller.label;
+                }
+                if let Some(dst) = caller.dst {
+                    set(&mut registers[base..], dst, value)?;
+                }
+            }
+            // Loops run by native code have values of their callers in native frames
+            if self.depth == 0 && self.heap.should_collect() {
+                self.collect_garbage(&registers);
+            }
+            let Some(instruction) = routine.code.get(pc) else {
+                return malformed("Execution went past the end of the routine");
+            };
+            #[cfg(feature = "jit")]
+            let at = pc;
+            pc += 1;
+            self.executed += 1;
+            let frame = &mut registers[base..];
+
+            match *instruction {
+                Instruction::Int { dst, value } => set(frame, dst, Value::Int(value))?,
+                Instruction::Real { dst, value } => set(frame, dst, Value::Real(value))?,
+                Instruction::String { dst, id } => {
+                    set(frame, dst, Value::Ref(self.program.string(id)))?;
+                }
+                Instruction::Move { dst, src } => set(frame, dst, get(frame, src)?)?,
+                Instruction::Binary { op, dst, lhs, rhs } => {
+                    let result = binary(op, get(frame, lhs)?, get(frame, rhs)?, self.heap)?;
+                    set(frame, dst, result)?;
+                }
+                Instruction::Unary { op, dst, src } => {
+                    set(frame, dst, unary(op, get(frame, src)?)?)?;
+                }
+                Instruction::IntToReal { dst, src } => {
+                    let value = get(frame, src)?;
+                    let result = natives::call(
+                        Builtin::ToReal,
+                        self.heap,
+                        &self.program.module.rtti,
+                        self.console,
+                        &[value],
+                    )?;
+                    set(frame, dst, result)?;
+                }
+                Instruction::RealToInt { dst, src } => {
+                    let value = real_to_int(real(get(frame, src)?)?)?;
+                    set(frame, dst, Value::Int(value))?;
+                }
+                Instruction::IntToBool { dst, src } => {
+                    let value = get(frame, src)?;
+                    let result = natives::call(
+                        Builtin::ToBoolean,
+                        self.heap,
+                        &self.program.module.rtti,
+                        self.console,
+                        &[value],
+                    )?;
+                    set(frame, dst, result)?;
+                }
+                Instruction::LoadGlobal { dst, index } => {
+                    let Some(&value) = self.globals.get(index) else {
+                        return malformed("No such global");
+                    };
+                    set(frame, dst, value)?;
+                }
+                Instruction::StoreGlobal { index, src } => {
+                    let value = get(frame, src)?;
+                    let Some(global) = self.globals.get_mut(index) else {
+                        return malformed("No such global");
+                    };
+                    *global = value;
+                }
+                Instruction::AllocRecord { dst, type_id } => {
+                    let record = self.alloc_record(type_id, &mut Vec::new())?;
+                    set(&mut registers[base..], dst, record)?;
+                }
+                Instruction::AllocArray { dst, type_id, size } => {
+                    let array = self.alloc_array(type_id, size, &mut Vec::new())?;
+                    set(&mut registers[base..], dst, array)?;
+                }
+                Instruction::ArraySize { dst, array } => {
+                    let size = self.array_size(get(frame, array)?)?;
+                    set(&mut registers[base..], dst, size)?;
+                }
+                Instruction::LoadField {
+                    dst,
+                    record,
+                    offset,
+                } => {
+                    let (record, offset) = self.field(get(frame, record)?, offset)?;
+                    let value = *self.slot(record, offset);
+                    set(&mut registers[base..], dst, value)?;
+                }
+                Instruction::StoreField {
+                    record,
+                    offset,
+                    src,
+                } => {
+                    let value = get(frame, src)?;
+                    let (record, offset) = self.field(get(frame, record)?, offset)?;
+                    *self.slot(record, offset) = value;
+                }
+                Instruction::LoadElement { dst, array, index } => {
+                    let (array, offset) = self.element(get(frame, array)?, get(frame, index)?)?;
+                    let value = *self.slot(array, offset);
+                    set(&mut registers[base..], dst, value)?;
+                }
+                Instruction::StoreElement { array, index, src } => {
+                    let value = get(frame, src)?;
+                    let (array, offset) = self.element(get(frame, array)?, get(frame, index)?)?;
+                    *self.slot(array, offset) = value;
+                }
+                Instruction::Call {
+                    dst,
+                    function_label,
+                    ref args,
+                } => {
+                    if frames.len() + self.depth >= MAX_DEPTH {
+                        return Err(RuntimeError::StackOverflow);
+                    }
+                    let args = args
+                        .iter()
+                        .map(|&arg| get(frame, arg))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    #[cfg(feature = "jit")]
+                    if let Some(value) =
+                        self.jitted(code, function_label, 0, &args, frames.len() + 1)?
+                    {
+                        if let Some(dst) = dst {
+                            set(&mut registers[base..], dst, value)?;
+                        }
+                        continue;
+                    }
+                    let callee = lookup(code, function_label)?;
+                    frames.push(Frame {
+                        routine,
+                        pc,
+                        base,
+                        dst,
+                        #[cfg(feature = "jit")]
+                        label,
+                    });
+                    base = registers.len();
+                    enter(&mut registers, callee, &args)?;
+                    routine = callee;
+                    pc = 0;
+                    #[cfg(feature = "jit")]
+                    {
+                        label = function_label;
+                    }
+                }
+                Instruction::CallNative { dst, id, ref args } => {
+                    let args = args
+                        .iter()
+                        .map(|&arg| get(frame, arg))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let result = self.native(id, args)?;
+                    if let Some(dst) = dst {
+                        set(&mut registers[base..], dst, result)?;
+                    }
+                }
+                Instruction::Print { type_id, src } => {
+                    let mut line = String::new();
+                    self.format(get(frame, src)?, type_id, &mut line)?;
+                    writeln!(self.console.output, "{line}")?;
+                }
+                Instruction::Jump { target } => pc = target,
+                Instruction::JumpZero { condition, target } => {
+                    if !truth(get(frame, condition)?)? {
+                        pc = target;
+                    }
+                }
+                Instruction::Return { src } => {
+                    returning = Some(src.map_or(Ok(Value::Int(0)), |src| get(frame, src))?);
+                }
+                Instruction::Panic { code } => return Err(RuntimeError::Panic { code }),
+            }
+
+            // A loop went round, the rest of the routine may run natively
+            #[cfg(feature = "jit")]
+            if matches!(
+                instruction,
+                Instruction::Jump { .. } | Instruction::JumpZero { .. }
+            ) && pc <= at
+            {
+                returning = self.jitted(code, label, pc, &registers[base..], frames.len())?;
+            }
+        }
+    }
+}
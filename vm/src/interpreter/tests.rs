@@ -0,0 +1,1047 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use compiler::ast::BinaryOperator;
+use compiler::bytecode::{FunctionCode, FunctionRecord, FunctionTable, Module, RTTI};
+use compiler::codegen::{case_dispatch, short_circuit, tail_calls};
+use compiler::consteval::Constant;
+use compiler::ir;
+use compiler::optimizer::{OptLevel, Optimizer};
+use compiler::registers::{self, Code};
+
+use super::*;
+use crate::Vm;
+use crate::console::Capture;
+use crate::host::Signature;
+use crate::test_support::{compiled, linked, output};
+use crate::value::ToValue;
+
+const POINT: TypeId = TypeId(4);
+const POINTS: TypeId = TypeId(5);
+/// `enum idle, running, done end`
+const STATE: TypeId = TypeId(6);
+
+fn routine(name: &str, label: u64, args: &[TypeId], result: TypeId) -> FunctionRecord {
+    FunctionRecord {
+        name: name.to_owned(),
+        code: FunctionCode::Label(label),
+        args: args.to_vec(),
+        result,
+    }
+}
+
+fn module(code: Vec<Bytecode>, functions: Vec<FunctionRecord>) -> Module {
+    with_strings(code, functions, Vec::new())
+}
+
+fn with_strings(
+    code: Vec<Bytecode>,
+    functions: Vec<FunctionRecord>,
+    strings: Vec<String>,
+) -> Module {
+    let mut rtti = RTTI::primitives();
+    rtti.0.extend([
+        RTTIElement::Record(RecordRTTI {
+            id: POINT,
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        }),
+        RTTIElement::Array(ArrayRTTI {
+            id: POINTS,
+            element_id: POINT,
+        }),
+        RTTIElement::Enum(EnumRTTI {
+            id: STATE,
+            variants: ["idle", "running", "done"].map(str::to_owned).into(),
+        }),
+    ]);
+    Module {
+        name: "test".to_owned(),
+        label_count: code
+            .iter()
+            .filter(|instruction| matches!(instruction, Bytecode::Label { .. }))
+            .count() as u64,
+        code,
+        functions: FunctionTable(functions),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti,
+        strings,
+        global_count: 0,
+    }
+}
+
+fn load(module: Module) -> Vm {
+    let mut vm = Vm::new();
+    vm.load(module).expect("Module is well-formed");
+    vm
+}
+
+fn arg(index: usize) -> Bytecode {
+    Bytecode::Load {
+        loc: Location::Argument(index),
+    }
+}
+
+/// `routine fact(n : integer) : integer is if n <= 1 then return 1; end; return n * fact(n - 1); end;`
+#[test]
+fn recursion() {
+    let mut vm = load(module(
+        vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 1, locals: 0 },
+            arg(0),
+            Bytecode::IntConst { value: 1 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntLe,
+            },
+            Bytecode::JumpZero { label: 1 },
+            Bytecode::IntConst { value: 1 },
+            Bytecode::Ret,
+            Bytecode::Label { id: 1 },
+            arg(0),
+            arg(0),
+            Bytecode::IntConst { value: 1 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntSub,
+            },
+            Bytecode::Call { function_label: 0 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntMul,
+            },
+            Bytecode::Ret,
+        ],
+        vec![routine("fact", 0, &[TypeId::INTEGER], TypeId::INTEGER)],
+    ));
+
+    assert_eq!(
+        vm.call("fact", &[Value::Int(10)]).ok(),
+        Some(Value::Int(3_628_800))
+    );
+    assert_eq!(
+        vm.call("test.fact", &[Value::Int(1)]).ok(),
+        Some(Value::Int(1))
+    );
+    assert!(matches!(
+        vm.call("fact", &[Value::Int(30)]),
+        Err(RuntimeError::IntegerOverflow)
+    ));
+    assert!(matches!(
+        vm.call("main", &[]),
+        Err(RuntimeError::UnknownRoutine { .. })
+    ));
+}
+
+/// `routine y(i : integer) : real is var t : array [3] point; t[2].y := 4.0; return t[i].y; end;`
+#[test]
+fn records_and_arrays() {
+    let element = |field_offset| {
+        [
+            Bytecode::ElementAddress,
+            Bytecode::LoadAddress,
+            Bytecode::FieldAddress { field_offset },
+        ]
+    };
+    let t = Bytecode::Load {
+        loc: Location::Local(0),
+    };
+    let mut code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 1, locals: 1 },
+        Bytecode::AllocArray {
+            type_id: POINTS,
+            size: 3,
+        },
+        Bytecode::Store {
+            loc: Location::Local(0),
+        },
+        Bytecode::RealConst { value: 4.0 },
+        t,
+        Bytecode::IntConst { value: 2 },
+    ];
+    code.extend(element(1));
+    code.extend([Bytecode::StoreAddress, t, arg(0)]);
+    code.extend(element(1));
+    code.extend([Bytecode::LoadAddress, Bytecode::Ret]);
+    let mut vm = load(module(
+        code,
+        vec![routine("y", 0, &[TypeId::INTEGER], TypeId::REAL)],
+    ));
+
+    assert_eq!(vm.call("y", &[Value::Int(2)]).ok(), Some(Value::Real(4.0)));
+    assert_eq!(vm.call("y", &[Value::Int(3)]).ok(), Some(Value::Real(0.0)));
+    assert!(matches!(
+        vm.call("y", &[Value::Int(4)]),
+        Err(RuntimeError::IndexOutOfBounds {
+            index: 4,
+            length: 3
+        })
+    ));
+}
+
+/// `routine is_even(n : integer) : boolean => parity(max(n, 0));` with `parity` from the host
+#[test]
+fn natives() {
+    let parity = 1000;
+    let program = module(
+        vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 1, locals: 0 },
+            arg(0),
+            Bytecode::IntConst { value: 0 },
+            Bytecode::CallNative {
+                id: Builtin::Max.id(),
+            },
+            Bytecode::CallNative { id: parity },
+            Bytecode::Ret,
+        ],
+        vec![
+            routine("is_even", 0, &[TypeId::INTEGER], TypeId::BOOLEAN),
+            FunctionRecord {
+                name: "parity".to_owned(),
+                code: FunctionCode::Native(parity),
+                args: vec![TypeId::INTEGER],
+                result: TypeId::BOOLEAN,
+            },
+        ],
+    );
+
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.load(program.clone()),
+        Err(crate::LoadError::UnresolvedHostFunction {
+            name: "parity".to_owned()
+        })
+    );
+    let mut mismatched = Vm::new();
+    mismatched
+        .register(
+            "parity",
+            Signature {
+                args: vec![TypeId::REAL],
+                result: Some(TypeId::BOOLEAN),
+            },
+            |x: f64| x.rem_euclid(2.0) == 0.0,
+        )
+        .expect("Not registered yet");
+    assert_eq!(
+        mismatched.load(program.clone()),
+        Err(crate::LoadError::HostSignature {
+            name: "parity".to_owned()
+        })
+    );
+    vm.register(
+        "parity",
+        Signature {
+            args: vec![TypeId::INTEGER],
+            result: Some(TypeId::BOOLEAN),
+        },
+        |n: i64| n % 2 == 0,
+    )
+    .expect("Not registered yet");
+    vm.load(program).expect("Host function is registered");
+
+    assert_eq!(
+        vm.call("is_even", &[Value::Int(4)]).ok(),
+        Some(Value::Bool(true))
+    );
+    assert_eq!(
+        vm.call("is_even", &[Value::Int(-3)]).ok(),
+        Some(Value::Bool(true))
+    );
+    assert_eq!(
+        vm.call("is_even", &[Value::Int(3)]).ok(),
+        Some(Value::Bool(false))
+    );
+}
+
+#[test]
+fn malformed_code() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.load(module(vec![Bytecode::Jump { label: 7 }], Vec::new())),
+        Err(crate::LoadError::UndefinedLabel { label: 7 })
+    );
+    assert!(matches!(vm.run(), Err(RuntimeError::NotLoaded)));
+
+    let mut vm = load(module(
+        vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 0, locals: 0 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntAdd,
+            },
+            Bytecode::Ret,
+        ],
+        vec![routine("main", 0, &[], TypeId::INTEGER)],
+    ));
+    assert!(matches!(vm.run(), Err(RuntimeError::Malformed(_))));
+}
+
+/// `routine main() is print read_integer() + read_integer(); print read_boolean(); print read_real(); end;`
+fn echo() -> Module {
+    let read = |builtin: Builtin| Bytecode::CallNative { id: builtin.id() };
+    let print = |type_id| Bytecode::Print { type_id };
+    module(
+        vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 0, locals: 0 },
+            read(Builtin::ReadInteger),
+            read(Builtin::ReadInteger),
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::IntAdd,
+            },
+            print(TypeId::INTEGER),
+            read(Builtin::ReadBoolean),
+            print(TypeId::BOOLEAN),
+            read(Builtin::ReadReal),
+            print(TypeId::REAL),
+            Bytecode::IntConst { value: 0 },
+            Bytecode::Ret,
+        ],
+        vec![routine("main", 0, &[], TypeId::INTEGER)],
+    )
+}
+
+#[test]
+fn console() {
+    let mut vm = load(echo());
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    vm.set_input(&b"2 40\n  true\t25"[..]);
+    vm.run().expect("Input is fine");
+    assert_eq!(output.contents(), b"42\ntrue\n25.0\n");
+
+    vm.set_input(&b"2 two"[..]);
+    assert!(matches!(
+        vm.run(),
+        Err(RuntimeError::Input(InputError::Invalid {
+            expected: "an integer",
+            found,
+        })) if found == "two"
+    ));
+    vm.set_input(&b"2 2 false"[..]);
+    assert!(matches!(
+        vm.run(),
+        Err(RuntimeError::Input(InputError::EndOfInput {
+            expected: "a real"
+        }))
+    ));
+}
+
+/// `routine greet(name : string) : integer is var s is "Hi, " + name; print s; print s[5] < "a"; return length(s); end;`
+#[test]
+fn strings() {
+    let s = Bytecode::Load {
+        loc: Location::Local(0),
+    };
+    let mut vm = load(with_strings(
+        vec![
+            Bytecode::Label { id: 0 },
+            Bytecode::Enter { args: 1, locals: 1 },
+            Bytecode::StringConst { id: 0 },
+            arg(0),
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::StringConcat,
+            },
+            Bytecode::Store {
+                loc: Location::Local(0),
+            },
+            s,
+            Bytecode::Print {
+                type_id: TypeId::STRING,
+            },
+            s,
+            Bytecode::IntConst { value: 5 },
+            Bytecode::CallNative {
+                id: Builtin::CharAt.id(),
+            },
+            Bytecode::StringConst { id: 1 },
+            Bytecode::BinOp {
+                op: SemanticBinaryOperator::StringLg,
+            },
+            Bytecode::Print {
+                type_id: TypeId::BOOLEAN,
+            },
+            s,
+            Bytecode::CallNative {
+                id: Builtin::Length.id(),
+            },
+            Bytecode::Ret,
+        ],
+        vec![routine("greet", 0, &[TypeId::STRING], TypeId::INTEGER)],
+        vec!["Hi, ".to_owned(), "a".to_owned()],
+    ));
+    let output = Capture::default();
+    vm.set_output(output.clone());
+
+    let name = "Ann".to_value(vm.heap());
+    assert_eq!(vm.call("greet", &[name]).ok(), Some(Value::Int(7)));
+    let name = "ёж".to_value(vm.heap());
+    assert_eq!(vm.call("greet", &[name]).ok(), Some(Value::Int(6)));
+    let empty = "".to_value(vm.heap());
+    assert!(matches!(
+        vm.call("greet", &[empty]),
+        Err(RuntimeError::IndexOutOfBounds {
+            index: 5,
+            length: 4
+        })
+    ));
+    assert_eq!(
+        output.contents(),
+        "Hi, Ann\ntrue\nHi, ёж\nfalse\nHi, \n".as_bytes()
+    );
+
+    assert_eq!(
+        Vm::new().load(with_strings(
+            vec![Bytecode::StringConst { id: 2 }],
+            Vec::new(),
+            Vec::new()
+        )),
+        Err(crate::LoadError::UndefinedString { id: 2 })
+    );
+}
+
+/// `routine next(s : state) : state is print s; case s of idle is return running; end; done is ... else return done; end; end;`
+#[test]
+fn enum_case() {
+    let mut code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 1, locals: 0 },
+        arg(0),
+        Bytecode::Print { type_id: STATE },
+        arg(0),
+    ];
+    code.extend(case_dispatch(&[(vec![0], 1), (vec![2], 2)], 3));
+    for (label, value) in [(1, 1), (2, 0), (3, 2)] {
+        code.extend([
+            Bytecode::Label { id: label },
+            Bytecode::Drop,
+            Bytecode::IntConst { value },
+            Bytecode::Ret,
+        ]);
+    }
+    let mut vm = load(module(code, vec![routine("next", 0, &[STATE], STATE)]));
+    let output = Capture::default();
+    vm.set_output(output.clone());
+
+    let mut state = Value::Int(0);
+    for _ in 0..4 {
+        state = vm.call("next", &[state]).expect("Variants are valid");
+    }
+    assert_eq!(state, Value::Int(1));
+    assert_eq!(output.contents(), b"idle\nrunning\ndone\nidle\n");
+    assert!(matches!(
+        vm.call("next", &[Value::Int(3)]),
+        Err(RuntimeError::ArgumentType { index: 0, .. })
+    ));
+}
+
+/// `routine loud(b : boolean) : boolean is print b; return b; end;`
+/// `routine main() is print false and loud(true); print true and loud(false);`
+/// `print true or loud(true); print false or loud(false); end;`
+#[test]
+fn short_circuit_skips_side_effects() {
+    let mut code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 1, locals: 0 },
+        arg(0),
+        Bytecode::Print {
+            type_id: TypeId::BOOLEAN,
+        },
+        arg(0),
+        Bytecode::Ret,
+        Bytecode::Label { id: 1 },
+        Bytecode::Enter { args: 0, locals: 0 },
+    ];
+    let operands = [
+        (BinaryOperator::And, 0, 1),
+        (BinaryOperator::And, 1, 0),
+        (BinaryOperator::Or, 1, 1),
+        (BinaryOperator::Or, 0, 0),
+    ];
+    for (end, (op, lhs, rhs)) in (2..).zip(operands) {
+        let lhs = vec![Bytecode::IntConst { value: lhs }];
+        let rhs = vec![
+            Bytecode::IntConst { value: rhs },
+            Bytecode::Call { function_label: 0 },
+        ];
+        code.extend(short_circuit(op, lhs, rhs, end).expect("Logical operator"));
+        code.push(Bytecode::Print {
+            type_id: TypeId::BOOLEAN,
+        });
+    }
+    code.extend([Bytecode::IntConst { value: 0 }, Bytecode::Ret]);
+    let mut vm = load(module(
+        code,
+        vec![
+            routine("loud", 0, &[TypeId::BOOLEAN], TypeId::BOOLEAN),
+            routine("main", 1, &[], TypeId::INTEGER),
+        ],
+    ));
+    let output = Capture::default();
+    vm.set_output(output.clone());
+
+    vm.run().expect("Well-formed");
+    // `loud` only runs for the second and the fourth operators
+    assert_eq!(
+        output.contents(),
+        b"false\nfalse\nfalse\ntrue\nfalse\nfalse\n"
+    );
+}
+
+/// `routine main() is var x is 2 * 3 + 0; print x; if x > 5 and true then print x * 1.5; end; end;`
+#[test]
+fn optimization_levels_agree() {
+    let x = Location::Local(0);
+    let mut code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 0, locals: 1 },
+        Bytecode::IntConst { value: 2 },
+        Bytecode::IntConst { value: 3 },
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::IntMul,
+        },
+        Bytecode::IntConst { value: 0 },
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::IntAdd,
+        },
+        Bytecode::Store { loc: x },
+        Bytecode::Load { loc: x },
+        Bytecode::Print {
+            type_id: TypeId::INTEGER,
+        },
+    ];
+    let condition = vec![
+        Bytecode::Load { loc: x },
+        Bytecode::IntConst { value: 5 },
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::IntGt,
+        },
+    ];
+    code.extend(
+        short_circuit(
+            BinaryOperator::And,
+            condition,
+            vec![Bytecode::IntConst { value: 1 }],
+            1,
+        )
+        .expect("Logical operator"),
+    );
+    code.extend([
+        Bytecode::JumpZero { label: 2 },
+        Bytecode::Load { loc: x },
+        Bytecode::IntToReal,
+        Bytecode::RealConst { value: 1.5 },
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::RealMul,
+        },
+        Bytecode::Print {
+            type_id: TypeId::REAL,
+        },
+        Bytecode::Jump { label: 2 },
+        Bytecode::Label { id: 2 },
+        Bytecode::IntConst { value: 0 },
+        Bytecode::Ret,
+        Bytecode::Print {
+            type_id: TypeId::INTEGER,
+        },
+    ]);
+
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let mut program = module(code.clone(), vec![routine("main", 0, &[], TypeId::INTEGER)]);
+        let statistics = Optimizer::new(opt_level).run(&mut program.code);
+        assert_eq!(statistics.is_empty(), opt_level == OptLevel::O0);
+        let mut vm = load(program);
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        vm.run().expect("Well-formed");
+        assert_eq!(output.contents(), b"6\n9.0\n", "{opt_level:?}");
+    }
+}
+
+/// `routine fib(n : integer) : integer is var a is 0; var b is 1;`
+/// `for i in 1 .. n loop print real(n) * 2.0; a, b := b, a + b; end; return a; end;`
+fn fib() -> ir::Function {
+    use ir::{BlockId, IrType, Operation, Terminator, ValueId};
+
+    let int = |value| Operation::Const(Constant::Int(value));
+    let binary = |op, lhs, rhs| Operation::Binary { op, lhs, rhs };
+    let mut builder = ir::FunctionBuilder::new("fib", vec![IrType::Int], Some(IrType::Int));
+    let header = builder.block();
+    let body = builder.block();
+    let exit = builder.block();
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let zero = builder.value(IrType::Int, int(0));
+    let one = builder.value(IrType::Int, int(1));
+    builder.terminate(Terminator::Jump(header));
+
+    // Defined by the header phis below
+    let (a, b, i) = (ValueId(9), ValueId(10), ValueId(11));
+    builder.switch_to(body);
+    let real = builder.value(IrType::Real, Operation::IntToReal(n));
+    let two = builder.value(IrType::Real, Operation::Const(Constant::Real(2.0)));
+    let double = builder.value(
+        IrType::Real,
+        binary(SemanticBinaryOperator::RealMul, real, two),
+    );
+    builder.effect(Operation::Print {
+        type_id: TypeId::REAL,
+        value: double,
+    });
+    let sum = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntAdd, a, b));
+    let step = builder.value(IrType::Int, int(1));
+    let next = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntAdd, i, step));
+    builder.terminate(Terminator::Jump(header));
+
+    builder.switch_to(header);
+    // `a` gets the old `b`, so the phis have to be assigned at once
+    for (value, start, step) in [(a, zero, b), (b, one, sum), (i, one, next)] {
+        let phi = Operation::Phi(vec![(BlockId(0), start), (body, step)]);
+        assert_eq!(builder.value(IrType::Int, phi), value);
+    }
+    let more = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntLe, i, n));
+    builder.terminate(Terminator::Branch {
+        condition: more,
+        then: body,
+        otherwise: exit,
+    });
+    builder.switch_to(exit);
+    builder.terminate(Terminator::Return(Some(a)));
+    builder.finish()
+}
+
+#[test]
+fn lowered_ir() {
+    let original = fib();
+    let mut optimized = original.clone();
+    ir::passes::optimize(&mut optimized);
+    assert_ne!(optimized, original);
+
+    for function in [original, optimized] {
+        let mut labels = 1;
+        let code = ir::lower::to_bytecode(&function, 0, &mut labels);
+        let mut program = module(
+            code,
+            vec![routine("fib", 0, &[TypeId::INTEGER], TypeId::INTEGER)],
+        );
+        program.label_count = labels;
+        let mut vm = load(program);
+        let output = Capture::default();
+        vm.set_output(output.clone());
+        assert_eq!(
+            vm.call("fib", &[Value::Int(3)]).ok(),
+            Some(Value::Int(2)),
+            "{function}"
+        );
+        assert_eq!(output.contents(), b"6.0\n6.0\n6.0\n");
+        assert_eq!(vm.call("fib", &[Value::Int(10)]).ok(), Some(Value::Int(55)));
+    }
+}
+
+/// `routine abs(x : integer) : integer is if x < 0 then return -x; end; return x; end;`
+/// `routine total(n : integer) : integer is var s is 0; for i in -n .. n loop s := s + abs(i); end; return s; end;`
+fn abs_total() -> BTreeMap<u64, ir::Function> {
+    use ir::{BlockId, IrType, Operation, Terminator, ValueId};
+
+    let int = |value| Operation::Const(Constant::Int(value));
+    let binary = |op, lhs, rhs| Operation::Binary { op, lhs, rhs };
+    let mut builder = ir::FunctionBuilder::new("abs", vec![IrType::Int], Some(IrType::Int));
+    let negative = builder.block();
+    let positive = builder.block();
+    let x = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let zero = builder.value(IrType::Int, int(0));
+    let less = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntLg, x, zero));
+    builder.terminate(Terminator::Branch {
+        condition: less,
+        then: negative,
+        otherwise: positive,
+    });
+    builder.switch_to(negative);
+    let negated = builder.value(
+        IrType::Int,
+        Operation::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            value: x,
+        },
+    );
+    builder.terminate(Terminator::Return(Some(negated)));
+    builder.switch_to(positive);
+    builder.terminate(Terminator::Return(Some(x)));
+    let abs = builder.finish();
+
+    let mut builder = ir::FunctionBuilder::new("total", vec![IrType::Int], Some(IrType::Int));
+    let header = builder.block();
+    let body = builder.block();
+    let exit = builder.block();
+    let n = builder.value(IrType::Int, Operation::Param { index: 0 });
+    let start = builder.value(
+        IrType::Int,
+        Operation::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            value: n,
+        },
+    );
+    let zero = builder.value(IrType::Int, int(0));
+    builder.terminate(Terminator::Jump(header));
+
+    // Defined by the header phis below
+    let (i, s) = (ValueId(7), ValueId(8));
+    builder.switch_to(body);
+    let call = Operation::Call {
+        function_label: 1,
+        args: vec![i],
+    };
+    let distance = builder.value(IrType::Int, call);
+    let sum = builder.value(
+        IrType::Int,
+        binary(SemanticBinaryOperator::IntAdd, s, distance),
+    );
+    let one = builder.value(IrType::Int, int(1));
+    let next = builder.value(IrType::Int, binary(SemanticBinaryOperator::IntAdd, i, one));
+    builder.terminate(Terminator::Jump(header));
+
+    builder.switch_to(header);
+    for (value, entry, step) in [(i, start, next), (s, zero, sum)] {
+        let phi = Operation::Phi(vec![(BlockId(0), entry), (body, step)]);
+        assert_eq!(builder.value(IrType::Int, phi), value);
+    }
+    let more = builder.value(IrType::Bool, binary(SemanticBinaryOperator::IntLe, i, n));
+    builder.terminate(Terminator::Branch {
+        condition: more,
+        then: body,
+        otherwise: exit,
+    });
+    builder.switch_to(exit);
+    builder.terminate(Terminator::Return(Some(s)));
+    BTreeMap::from([(0, builder.finish()), (1, abs)])
+}
+
+#[test]
+fn inlined_calls() {
+    let mut inlined = abs_total();
+    assert_eq!(ir::inline::inline(&mut inlined, ir::inline::THRESHOLD), 1);
+    let mut optimized = inlined.clone();
+    for function in optimized.values_mut() {
+        ir::passes::optimize(function);
+    }
+
+    for routines in [abs_total(), inlined, optimized] {
+        let mut labels = 2;
+        let code = routines
+            .iter()
+            .flat_map(|(&label, function)| ir::lower::to_bytecode(function, label, &mut labels))
+            .collect();
+        let mut program = module(
+            code,
+            vec![
+                routine("total", 0, &[TypeId::INTEGER], TypeId::INTEGER),
+                routine("abs", 1, &[TypeId::INTEGER], TypeId::INTEGER),
+            ],
+        );
+        program.label_count = labels;
+        let mut vm = load(program);
+        assert_eq!(
+            vm.call("total", &[Value::Int(3)]).ok(),
+            Some(Value::Int(12)),
+            "{}",
+            routines[&0]
+        );
+    }
+}
+
+#[test]
+fn register_machine() {
+    let mut routines = abs_total();
+    for function in routines.values_mut() {
+        ir::passes::optimize(function);
+    }
+    let mut labels = 2;
+    let code = routines
+        .iter()
+        .flat_map(|(&label, function)| ir::lower::to_bytecode(function, label, &mut labels))
+        .collect();
+    let mut program = module(
+        code,
+        vec![
+            routine("total", 0, &[TypeId::INTEGER], TypeId::INTEGER),
+            routine("abs", 1, &[TypeId::INTEGER], TypeId::INTEGER),
+        ],
+    );
+    program.label_count = labels;
+    let mut vm = load(program);
+    assert_eq!(
+        vm.call("total", &[Value::Int(10)]).ok(),
+        Some(Value::Int(110))
+    );
+    let stack = vm.executed();
+
+    let code = Code(
+        routines
+            .iter()
+            .map(|(&label, function)| (label, registers::lower(function)))
+            .collect(),
+    );
+    assert_eq!(vm.load_registers(code).err(), None);
+    assert_eq!(
+        vm.call("total", &[Value::Int(10)]).ok(),
+        Some(Value::Int(110))
+    );
+    let executed = vm.executed() - stack;
+    assert!(executed < stack / 2, "{executed} of {stack}");
+
+    // `total` is missing
+    let code = Code(BTreeMap::from([(1, registers::lower(&routines[&1]))]));
+    assert_eq!(
+        vm.load_registers(code).err(),
+        Some(crate::LoadError::UndefinedLabel { label: 0 })
+    );
+
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.load_registers(Code::default()).err(),
+        Some(crate::LoadError::NotLoaded)
+    );
+}
+
+/// `routine count(n : integer, s : integer) : integer is`
+/// `if n = 0 then return id(s); end; return count(n - 1, s + n); end;`
+/// `routine id(x : integer) : integer is var y is x; return y; end;`
+fn count() -> Vec<Bytecode> {
+    vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 2, locals: 0 },
+        arg(0),
+        Bytecode::JumpNotZero { label: 1 },
+        arg(1),
+        Bytecode::Call { function_label: 2 },
+        Bytecode::Ret,
+        Bytecode::Label { id: 1 },
+        arg(0),
+        Bytecode::IntConst { value: 1 },
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::IntSub,
+        },
+        arg(1),
+        arg(0),
+        Bytecode::BinOp {
+            op: SemanticBinaryOperator::IntAdd,
+        },
+        Bytecode::Call { function_label: 0 },
+        Bytecode::Ret,
+        Bytecode::Label { id: 2 },
+        Bytecode::Enter { args: 1, locals: 1 },
+        arg(0),
+        Bytecode::Store {
+            loc: Location::Local(0),
+        },
+        Bytecode::Load {
+            loc: Location::Local(0),
+        },
+        Bytecode::Ret,
+    ]
+}
+
+#[test]
+fn tail_calls_reuse_frames() {
+    let functions = || {
+        vec![
+            routine(
+                "count",
+                0,
+                &[TypeId::INTEGER, TypeId::INTEGER],
+                TypeId::INTEGER,
+            ),
+            routine("id", 2, &[TypeId::INTEGER], TypeId::INTEGER),
+        ]
+    };
+    let depth = i64::try_from(MAX_DEPTH).expect("Depth fits into i64");
+    let args = [Value::Int(depth), Value::Int(0)];
+
+    let mut vm = load(module(count(), functions()));
+    assert_eq!(
+        vm.call("count", &[Value::Int(3), Value::Int(0)]).ok(),
+        Some(Value::Int(6))
+    );
+    assert!(matches!(
+        vm.call("count", &args),
+        Err(RuntimeError::StackOverflow)
+    ));
+
+    let mut code = count();
+    tail_calls(&mut code);
+    assert_eq!(code[5], Bytecode::TailCall { function_label: 2 });
+    let mut vm = load(module(code, functions()));
+    assert_eq!(
+        vm.call("count", &args).ok(),
+        Some(Value::Int(depth * (depth + 1) / 2))
+    );
+}
+
+fn program_output(name: &str) -> String {
+    let path = format!("{}/../tests/src/{name}.i", env!("CARGO_MANIFEST_DIR"));
+    output(compiled(
+        &std::fs::read_to_string(path).expect("Test program exists"),
+    ))
+}
+
+/// `case` of an enum and of integers with `else`
+#[test]
+fn compiled_case() {
+    assert_eq!(
+        program_output("enums"),
+        "idle\nrunning\ndone\nidle\nsmall\nother\n"
+    );
+}
+
+/// `break` and `continue` of the innermost and of a labeled loop
+#[test]
+fn compiled_loop_exits() {
+    assert_eq!(
+        program_output("loop_exits"),
+        "1\n3\n6\n5\n10\n7\n14\n9\n18\n1\n2\n"
+    );
+}
+
+/// `and` and `or` do not evaluate their right operand when the left one decides
+#[test]
+fn compiled_short_circuit() {
+    assert_eq!(
+        program_output("short_circuit"),
+        "false\nfalse\nfalse\ntrue\nfalse\nfalse\n"
+    );
+}
+
+/// `tests/modules/main.i` uses a type, routines and globals of the modules it imports
+#[test]
+fn compiled_modules() {
+    let main = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/modules/main.i");
+    assert_eq!(output(linked(&main)), "625.0\n1\n");
+}
+
+/// Routines of the program come before the built-ins of the same name
+#[test]
+fn routines_shadow_builtins() {
+    let module = compiled(
+        "routine sqrt(x : real) : real => x * 2.0;
+        routine main() is print sqrt(16.0); end;",
+    );
+    assert_eq!(output(module), "32.0\n");
+}
+
+/// Strings made in a loop are collected, the ones still referenced are not
+#[test]
+fn garbage_collection() {
+    let mut vm = Vm::new();
+    vm.load(compiled(
+        r#"
+        var kept : string;
+        routine main() is
+          kept := "kept" + "!";
+          var s is "";
+          for i in 1 .. 10000 loop
+            s := "x" + "y";
+          end;
+          print kept + s;
+        end;
+        "#,
+    ))
+    .expect("Module is well-formed");
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    vm.run().expect("Runs");
+
+    assert_eq!(output.contents(), b"kept!xy\n");
+    assert!(
+        vm.heap().live() < 2000,
+        "{} objects alive",
+        vm.heap().live()
+    );
+}
+
+/// Recursive fields start null, `equals` compares them, using them fails
+#[test]
+fn null_references() {
+    let source = "
+        type list is record var data : integer; var tail : list; end;
+        var first : list;
+        var second : list;
+        routine main() is
+          print equals(first, second);
+          second.tail := first;
+          print equals(first, second);
+          print second.tail.data;
+          print first.tail.data;
+        end;
+    ";
+    let mut vm = Vm::new();
+    vm.load(compiled(source)).expect("Module is well-formed");
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    assert!(matches!(vm.run(), Err(RuntimeError::NullReference)));
+    assert_eq!(output.contents(), b"true\nfalse\n0\n");
+}
+
+/// `Vm::call` checks the arguments against the routine, and initializes the globals first
+#[test]
+fn calls_from_the_host() {
+    let mut vm = Vm::new();
+    vm.load(compiled(
+        "
+        var squares : array [3] integer;
+        var base is 2;
+        routine square(i : integer) : integer is
+          squares[i] := (base + i) * (base + i);
+          return squares[i];
+        end;
+        routine main() is print square(1); end;
+        ",
+    ))
+    .expect("Module is well-formed");
+
+    assert!(matches!(
+        vm.call("square", &[]),
+        Err(RuntimeError::ArgumentCount {
+            expected: 1,
+            found: 0,
+            ..
+        })
+    ));
+    assert!(matches!(
+        vm.call("square", &[Value::Real(1.0)]),
+        Err(RuntimeError::ArgumentType {
+            index: 0,
+            expected: TypeId::INTEGER,
+            ..
+        })
+    ));
+    assert!(matches!(
+        vm.call("square", &[Value::Int(3)]),
+        Ok(Value::Int(25))
+    ));
+}
+
+/// `sort` orders integers and reals, an array mixing them is malformed
+#[test]
+fn sort_checks_elements() {
+    let mut heap = Heap::default();
+    let mut sort = |elements: Vec<Value>| {
+        let array = heap.alloc(Object::Array(elements));
+        let result = natives::call(
+            Builtin::Sort,
+            &mut heap,
+            &RTTI::primitives(),
+            &mut Console::default(),
+            &[Value::Ref(array)],
+        );
+        result.map(|_| heap.get(array).clone())
+    };
+    assert!(matches!(
+        sort(vec![Value::Real(2.5), Value::Real(-1.0)]),
+        Ok(Object::Array(sorted)) if sorted == [Value::Real(-1.0), Value::Real(2.5)]
+    ));
+    assert!(matches!(
+        sort(vec![Value::Real(2.5), Value::Int(1)]),
+        Err(RuntimeError::Malformed(_))
+    ));
+}
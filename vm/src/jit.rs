@@ -0,0 +1,1146 @@
+//! Tier of the register machine compiling hot routines to native code with Cranelift.
+//!
+//! Routines are counted whenever they are called and whenever they jump back to a loop head,
+//! at `Jit::threshold` they are compiled. Calls then run the native code, and a loop the
+//! interpreter is in continues natively from its head. Routines with strings or `print`
+//! stay interpreted, so does everything on machines Cranelift does not support.
+//!
+//! Every register is a pair of variables: the bits of its value and a tag telling integers,
+//! reals and references apart, which the heap, the globals and the interpreter need. Frames
+//! are passed as arrays of `Slot`s. The heap, globals, built-ins and calls are reached through
+//! helpers, which keep failures in the `Context` and make the native code return non-zero.
+
+use core::{fmt, mem, slice};
+use std::collections::{BTreeSet, HashMap};
+
+use compiler::builtins::Builtin;
+use compiler::bytecode::TypeId;
+use compiler::operators::{SemanticBinaryOperator, SemanticUnaryOperator};
+use compiler::registers::{Code, Instruction, Register, Routine};
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    self, AbiParam, InstBuilder as _, JumpTableData, MemFlags, StackSlotData, StackSlotKind, types,
+};
+use cranelift_codegen::settings::{self, Configurable as _};
+use cranelift_frontend::{FuncInstBuilder, FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module as _, default_libcall_names};
+
+use crate::interpreter::{MAX_DEPTH, Machine, RuntimeError, real_to_int};
+use crate::value::{ObjectRef, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// Calls and loop iterations after which a routine is compiled
+pub const THRESHOLD: u64 = 1000;
+
+/// Native routines running at once, deeper calls are interpreted so that the stack of the
+/// embedding program holds. Each one also takes a `run_registers` and a helper, which are
+/// over 16 KiB in debug builds.
+const NATIVE_DEPTH: usize = 32;
+
+const INT: i64 = 0;
+const REAL: i64 = 1;
+const REF: i64 = 2;
+
+/// Register of a frame passed to or from native code
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct Slot {
+    bits: i64,
+    tag: i64,
+}
+
+/// Bytes of a `Slot` and the offset of its tag
+const SLOT: i32 = 16;
+const TAG: i32 = 8;
+
+impl From<Value> for Slot {
+    fn from(value: Value) -> Self {
+        let (bits, tag) = match value {
+            Value::Int(value) => (value, INT),
+            Value::Bool(value) => (value.into(), INT),
+            Value::Real(value) => (value.to_bits().cast_signed(), REAL),
+            Value::Ref(ObjectRef::NULL) => (-1, REF),
+            Value::Ref(object) => (
+                i64::try_from(object.index()).expect("Objects fit into memory"),
+                REF,
+            ),
+        };
+        Self { bits, tag }
+    }
+}
+
+impl Slot {
+    fn value(self) -> Value {
+        match self.tag {
+            REAL => Value::Real(f64::from_bits(self.bits.cast_unsigned())),
+            // Null references are negative
+            REF => Value::Ref(
+                usize::try_from(self.bits).map_or(ObjectRef::NULL, ObjectRef::from_index),
+            ),
+            _ => Value::Int(self.bits),
+        }
+    }
+}
+
+/// What helpers reach through the first argument of native code
+struct Context<'m, 'a> {
+    machine: &'m mut Machine<'a>,
+    code: &'m Code,
+    failure: Option<RuntimeError>,
+}
+
+/// `status = routine(context, frame, entry, result)`. Entry 0 starts the routine with its
+/// arguments in `frame`, the other ones continue at `State::Compiled::heads` with all
+/// of its registers there.
+type Native = unsafe extern "C" fn(*mut Context<'_, '_>, *const Slot, u64, *mut Slot) -> u32;
+
+/// `status = helper(context, operand, args, count, result)`, `operand` is a label, an index
+/// or an id, depending on the helper
+type Helper = unsafe extern "C" fn(*mut Context<'_, '_>, u64, *const Slot, u64, *mut Slot) -> u32;
+
+/// Runs `f` with the context and the arguments native code passes to a helper,
+/// writing its value to `result` and giving the status
+///
+/// # Safety
+///
+/// The pointers are the ones native code passes: its context, `count` slots and one more
+unsafe fn helper(
+    context: *mut Context<'_, '_>,
+    args: *const Slot,
+    count: u64,
+    result: *mut Slot,
+    f: impl FnOnce(&mut Context<'_, '_>, &[Slot]) -> Result<Value, RuntimeError>,
+) -> u32 {
+    let count = usize::try_from(count).expect("Few arguments");
+    // SAFETY: native code passes its context, its scratch slots and its result slot
+    let (context, args, result) = unsafe {
+        (
+            &mut *context,
+            slice::from_raw_parts(args, count),
+            &mut *result,
+        )
+    };
+    match f(context, args) {
+        Ok(value) => {
+            *result = Slot::from(value);
+            0
+        }
+        Err(e) => {
+            context.failure = Some(e);
+            1
+        }
+    }
+}
+
+/// Declares a `Helper` named `$name` running `$body` with the context, the operand
+/// and the arguments
+macro_rules! helpers {
+    ($(
+        $(#[$doc:meta])*
+        fn $name:ident($context:ident, $operand:ident, $args:ident) $body:block
+    )*) => {$(
+        $(#[$doc])*
+        unsafe extern "C" fn $name(
+            context: *mut Context<'_, '_>,
+            $operand: u64,
+            args: *const Slot,
+            count: u64,
+            result: *mut Slot,
+        ) -> u32 {
+            // SAFETY: only native code calls helpers
+            unsafe { helper(context, args, count, result, |$context, $args| $body) }
+        }
+    )*};
+}
+
+/// Failures native code finds itself, the operand of `fault`
+const OVERFLOW: i64 = 0;
+const DIVISION_BY_ZERO: i64 = 1;
+const PANIC: i64 = 2;
+
+fn index(operand: u64) -> Result<usize, RuntimeError> {
+    usize::try_from(operand).map_err(|e| RuntimeError::Malformed(e.to_string()))
+}
+
+fn type_id(operand: u64) -> Result<TypeId, RuntimeError> {
+    u32::try_from(operand)
+        .map(TypeId)
+        .map_err(|e| RuntimeError::Malformed(e.to_string()))
+}
+
+helpers! {
+    /// The argument is the code of a panic
+    fn fault(_context, fault, args) {
+        Err(match fault {
+            0 => RuntimeError::IntegerOverflow,
+            1 => RuntimeError::DivisionByZero,
+            _ => RuntimeError::Panic {
+                code: args[0].bits.cast_unsigned(),
+            },
+        })
+    }
+
+    fn to_integer(_context, _operand, args) {
+        let Value::Real(value) = args[0].value() else {
+            return Err(RuntimeError::Malformed("Expected a real".to_owned()));
+        };
+        real_to_int(value).map(Value::Int)
+    }
+
+    fn load_global(context, index, _args) {
+        context
+            .machine
+            .globals
+            .get(self::index(index)?)
+            .copied()
+            .ok_or_else(|| RuntimeError::Malformed("No such global".to_owned()))
+    }
+
+    fn store_global(context, index, args) {
+        let global = context
+            .machine
+            .globals
+            .get_mut(self::index(index)?)
+            .ok_or_else(|| RuntimeError::Malformed("No such global".to_owned()))?;
+        *global = args[0].value();
+        Ok(Value::Int(0))
+    }
+
+    fn alloc_record(context, type_id, _args) {
+        context
+            .machine
+            .alloc_record(self::type_id(type_id)?, &mut Vec::new())
+    }
+
+    /// The argument is the size
+    fn alloc_array(context, type_id, args) {
+        let size = args[0].bits.cast_unsigned();
+        context
+            .machine
+            .alloc_array(self::type_id(type_id)?, size, &mut Vec::new())
+    }
+
+    fn array_size(context, _operand, args) {
+        context.machine.array_size(args[0].value())
+    }
+
+    fn load_field(context, offset, args) {
+        let (record, offset) = context.machine.field(args[0].value(), offset)?;
+        Ok(*context.machine.slot(record, offset))
+    }
+
+    fn store_field(context, offset, args) {
+        let (record, offset) = context.machine.field(args[0].value(), offset)?;
+        *context.machine.slot(record, offset) = args[1].value();
+        Ok(Value::Int(0))
+    }
+
+    fn load_element(context, _operand, args) {
+        let (array, offset) = context
+            .machine
+            .element(args[0].value(), args[1].value())?;
+        Ok(*context.machine.slot(array, offset))
+    }
+
+    fn store_element(context, _operand, args) {
+        let (array, offset) = context
+            .machine
+            .element(args[0].value(), args[1].value())?;
+        *context.machine.slot(array, offset) = args[2].value();
+        Ok(Value::Int(0))
+    }
+
+    /// Calls the routine at the label natively or in the interpreter
+    fn call(context, label, args) {
+        if context.machine.depth > MAX_DEPTH {
+            return Err(RuntimeError::StackOverflow);
+        }
+        let args: Vec<_> = args.iter().map(|slot| slot.value()).collect();
+        context.machine.run_registers(context.code, label, &args)
+    }
+
+    fn native(context, id, args) {
+        let args = args.iter().map(|slot| slot.value()).collect();
+        let id = u32::try_from(id).map_err(|e| RuntimeError::Malformed(e.to_string()))?;
+        context.machine.native(id, args)
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    /// Calls and loop iterations so far
+    Counting(u64),
+    Compiled {
+        native: Native,
+        /// Loop heads, entries `1..`
+        heads: Vec<usize>,
+    },
+    /// Not supported, or compiling failed
+    Interpreted,
+}
+
+/// Native code of the routines of the loaded register code
+pub(crate) struct Jit {
+    pub(crate) threshold: u64,
+    /// Made when the first routine is compiled
+    module: Option<JITModule>,
+    routines: HashMap<u64, State>,
+    /// Native routines running
+    running: usize,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self {
+            threshold: THRESHOLD,
+            module: None,
+            routines: HashMap::new(),
+            running: 0,
+        }
+    }
+}
+
+impl fmt::Debug for Jit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jit")
+            .field("threshold", &self.threshold)
+            .field("routines", &self.routines)
+            .field("running", &self.running)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: native code only runs while a `Machine` borrows the `Jit`,
+            // and its function pointers go away with it
+            unsafe {
+                module.free_memory();
+            }
+        }
+    }
+}
+
+impl Jit {
+    /// Forgets the native code, keeping the threshold
+    pub(crate) fn reset(&mut self) {
+        *self = Self {
+            threshold: self.threshold,
+            module: None,
+            routines: HashMap::new(),
+            running: 0,
+        };
+    }
+
+    /// Routines compiled to native code
+    pub(crate) fn compiled(&self) -> usize {
+        self.routines
+            .values()
+            .filter(|state| matches!(state, State::Compiled { .. }))
+            .count()
+    }
+
+    /// Counts the routine at `label` as entered at `pc`, compiling it when it gets hot.
+    /// The native code and its entry for `pc`, if it is compiled and may run now.
+    fn enter(&mut self, code: &Code, label: u64, pc: usize) -> Option<(Native, u64)> {
+        if self.running >= NATIVE_DEPTH {
+            return None;
+        }
+        let state = self.routines.entry(label).or_insert(State::Counting(0));
+        if let State::Counting(count) = state {
+            *count += 1;
+            if *count >= self.threshold {
+                *state = code
+                    .0
+                    .get(&label)
+                    .filter(|routine| supported(routine))
+                    .and_then(|routine| compile(&mut self.module, routine))
+                    .unwrap_or(State::Interpreted);
+            }
+        }
+        match state {
+            State::Compiled { native, heads } if pc == 0 => Some((*native, 0)),
+            State::Compiled { native, heads } => {
+                let head = heads.iter().position(|&head| head == pc)?;
+                Some((*native, u64::try_from(head + 1).expect("Few heads")))
+            }
+            State::Counting(_) | State::Interpreted => None,
+        }
+    }
+}
+
+impl Machine<'_> {
+    /// Runs the routine at `label` natively from `pc` if it is hot, `frame` holds its
+    /// arguments, or all of its registers when continuing a loop. `running` routines of
+    /// the interpreter are below it. `None` when it is to be interpreted.
+    pub(crate) fn jitted(
+        &mut self,
+        code: &Code,
+        label: u64,
+        pc: usize,
+        frame: &[Value],
+        running: usize,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let Some(jit) = self.jit.as_deref_mut() else {
+            return Ok(None);
+        };
+        let Some((native, entry)) = jit.enter(code, label, pc) else {
+            return Ok(None);
+        };
+        jit.running += 1;
+        let slots: Vec<_> = frame.iter().map(|&value| Slot::from(value)).collect();
+        let mut result = Slot::default();
+        self.depth += running + 1;
+        let mut context = Context {
+            machine: self,
+            code,
+            failure: None,
+        };
+        // SAFETY: `native` was compiled from `code` with the signature of `Native`,
+        // `slots` hold the registers `entry` loads
+        let status = unsafe { native(&raw mut context, slots.as_ptr(), entry, &raw mut result) };
+        let failure = context.failure.take();
+        self.depth -= running + 1;
+        if let Some(jit) = self.jit.as_deref_mut() {
+            jit.running -= 1;
+        }
+        match (status, failure) {
+            (0, _) => Ok(Some(result.value())),
+            (_, Some(e)) => Err(e),
+            (_, None) => Err(RuntimeError::Malformed(
+                "Native code failed without a reason".to_owned(),
+            )),
+        }
+    }
+}
+
+fn is_string(op: SemanticBinaryOperator) -> bool {
+    use SemanticBinaryOperator as Op;
+
+    matches!(
+        op,
+        Op::StringConcat
+            | Op::StringLe
+            | Op::StringLg
+            | Op::StringGt
+            | Op::StringGe
+            | Op::StringEq
+            | Op::StringNeq
+    )
+}
+
+/// Strings and printing are left to the interpreter, and so is code which runs off its end
+fn supported(routine: &Routine) -> bool {
+    let length = routine.code.len();
+    let instructions_supported =
+        routine
+            .code
+            .iter()
+            .enumerate()
+            .all(|(pc, instruction)| match *instruction {
+                Instruction::String { .. } | Instruction::Print { .. } => false,
+                Instruction::Binary { op, .. } => !is_string(op),
+                Instruction::Jump { target } => target < length,
+                Instruction::JumpZero { target, .. } => target < length && pc + 1 < length,
+                Instruction::Int { .. }
+                | Instruction::Real { .. }
+                | Instruction::Move { .. }
+                | Instruction::Unary { .. }
+                | Instruction::IntToReal { .. }
+                | Instruction::RealToInt { .. }
+                | Instruction::IntToBool { .. }
+                | Instruction::LoadGlobal { .. }
+                | Instruction::StoreGlobal { .. }
+                | Instruction::AllocRecord { .. }
+                | Instruction::AllocArray { .. }
+                | Instruction::ArraySize { .. }
+                | Instruction::LoadField { .. }
+                | Instruction::StoreField { .. }
+                | Instruction::LoadElement { .. }
+                | Instruction::StoreElement { .. }
+                | Instruction::Call { .. }
+                | Instruction::CallNative { .. }
+                | Instruction::Return { .. }
+                | Instruction::Panic { .. } => true,
+            });
+    instructions_supported
+        && matches!(
+            routine.code.last(),
+            Some(Instruction::Jump { .. } | Instruction::Return { .. } | Instruction::Panic { .. })
+        )
+}
+
+/// Native code of the host, `None` if Cranelift doesn't support it
+fn module() -> Option<JITModule> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    Some(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+/// Where `instruction` may jump to
+fn target(instruction: &Instruction) -> Option<usize> {
+    match *instruction {
+        Instruction::Jump { target } | Instruction::JumpZero { target, .. } => Some(target),
+        Instruction::Int { .. }
+        | Instruction::Real { .. }
+        | Instruction::String { .. }
+        | Instruction::Move { .. }
+        | Instruction::Binary { .. }
+        | Instruction::Unary { .. }
+        | Instruction::IntToReal { .. }
+        | Instruction::RealToInt { .. }
+        | Instruction::IntToBool { .. }
+        | Instruction::LoadGlobal { .. }
+        | Instruction::StoreGlobal { .. }
+        | Instruction::AllocRecord { .. }
+        | Instruction::AllocArray { .. }
+        | Instruction::ArraySize { .. }
+        | Instruction::LoadField { .. }
+        | Instruction::StoreField { .. }
+        | Instruction::LoadElement { .. }
+        | Instruction::StoreElement { .. }
+        | Instruction::Call { .. }
+        | Instruction::CallNative { .. }
+        | Instruction::Print { .. }
+        | Instruction::Return { .. }
+        | Instruction::Panic { .. } => None,
+    }
+}
+
+/// Loop heads: targets of jumps back
+fn heads(routine: &Routine) -> Vec<usize> {
+    let heads: BTreeSet<_> = routine
+        .code
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instruction)| target(instruction).filter(|&target| target <= pc))
+        .collect();
+    heads.into_iter().collect()
+}
+
+fn compile(module: &mut Option<JITModule>, routine: &Routine) -> Option<State> {
+    if module.is_none() {
+        *module = self::module();
+    }
+    let module = module.as_mut()?;
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    let signature = &mut context.func.signature;
+    signature.params.extend([
+        AbiParam::new(pointer),
+        AbiParam::new(pointer),
+        AbiParam::new(types::I64),
+        AbiParam::new(pointer),
+    ]);
+    signature.returns.push(AbiParam::new(types::I32));
+    let mut helper = module.make_signature();
+    helper.params.extend([
+        AbiParam::new(pointer),
+        AbiParam::new(types::I64),
+        AbiParam::new(pointer),
+        AbiParam::new(types::I64),
+        AbiParam::new(pointer),
+    ]);
+    helper.returns.push(AbiParam::new(types::I32));
+
+    let heads = heads(routine);
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let helper = builder.import_signature(helper);
+    Translation::new(&mut builder, routine, pointer, helper).translate(routine, &heads);
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+    // SAFETY: the function was compiled with the signature of `Native`
+    let native = unsafe { mem::transmute::<*const u8, Native>(code) };
+    Some(State::Compiled { native, heads })
+}
+
+/// Cranelift IR of a routine being built
+struct Translation<'b, 'f> {
+    builder: &'b mut FunctionBuilder<'f>,
+    pointer: ir::Type,
+    helper: ir::SigRef,
+    /// Parameters of the native routine
+    context: ir::Value,
+    frame: ir::Value,
+    entry: ir::Value,
+    result: ir::Value,
+    /// Arguments of helpers and what they give
+    scratch: ir::StackSlot,
+    output: ir::StackSlot,
+    /// Blocks starting at these instructions
+    blocks: HashMap<usize, ir::Block>,
+    /// Returning the status of a failed helper
+    failed: ir::Block,
+    overflow: ir::Block,
+    division_by_zero: ir::Block,
+}
+
+fn bits(register: Register) -> Variable {
+    Variable::from_u32(2 * register.0)
+}
+
+fn tag(register: Register) -> Variable {
+    Variable::from_u32(2 * register.0 + 1)
+}
+
+fn offset(index: usize) -> i32 {
+    i32::try_from(index).expect("Few registers") * SLOT
+}
+
+impl<'b, 'f> Translation<'b, 'f> {
+    fn new(
+        builder: &'b mut FunctionBuilder<'f>,
+        routine: &Routine,
+        pointer: ir::Type,
+        helper: ir::SigRef,
+    ) -> Self {
+        for register in 0..routine.registers {
+            builder.declare_var(bits(Register(register)), types::I64);
+            builder.declare_var(tag(Register(register)), types::I64);
+        }
+        let arguments = routine
+            .code
+            .iter()
+            .map(|instruction| {
+                if let Instruction::Call { args, .. } | Instruction::CallNative { args, .. } =
+                    instruction
+                {
+                    args.len()
+                } else {
+                    0
+                }
+            })
+            .max()
+            .unwrap_or_default()
+            .max(3);
+        let scratch = builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            u32::try_from(offset(arguments)).expect("Few arguments"),
+            3,
+        ));
+        let output = builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            SLOT.cast_unsigned(),
+            3,
+        ));
+        let start = builder.create_block();
+        builder.append_block_params_for_function_params(start);
+        let &[context, frame, entry, result] = builder.block_params(start) else {
+            unreachable!("Native routines have four parameters")
+        };
+        builder.switch_to_block(start);
+        let mut leaders = BTreeSet::from([0]);
+        for (pc, instruction) in routine.code.iter().enumerate() {
+            if let Some(target) = target(instruction) {
+                leaders.extend([target, pc + 1]);
+            } else if matches!(
+                instruction,
+                Instruction::Return { .. } | Instruction::Panic { .. }
+            ) {
+                let _: bool = leaders.insert(pc + 1);
+            }
+        }
+        let blocks = leaders
+            .into_iter()
+            .filter(|&pc| pc < routine.code.len())
+            .map(|pc| (pc, builder.create_block()))
+            .collect();
+        let failed = builder.create_block();
+        let overflow = builder.create_block();
+        let division_by_zero = builder.create_block();
+        for block in [failed, overflow, division_by_zero] {
+            builder.set_cold_block(block);
+        }
+        Self {
+            builder,
+            pointer,
+            helper,
+            context,
+            frame,
+            entry,
+            result,
+            scratch,
+            output,
+            blocks,
+            failed,
+            overflow,
+            division_by_zero,
+        }
+    }
+
+    fn int(&mut self, value: i64) -> ir::Value {
+        self.builder.ins().iconst(types::I64, value)
+    }
+
+    fn bits(&mut self, register: Register) -> ir::Value {
+        self.builder.use_var(bits(register))
+    }
+
+    fn real(&mut self, register: Register) -> ir::Value {
+        let bits = self.bits(register);
+        self.builder
+            .ins()
+            .bitcast(types::F64, MemFlags::new(), bits)
+    }
+
+    /// Non-zero integers are true, gives `i8` 0 or 1
+    fn truth(&mut self, register: Register) -> ir::Value {
+        let bits = self.bits(register);
+        self.builder.ins().icmp_imm(IntCC::NotEqual, bits, 0)
+    }
+
+    fn set(&mut self, register: Register, bits: ir::Value, tag: i64) {
+        let tag = self.int(tag);
+        self.builder.def_var(self::bits(register), bits);
+        self.builder.def_var(self::tag(register), tag);
+    }
+
+    fn set_real(&mut self, register: Register, value: ir::Value) {
+        let bits = self
+            .builder
+            .ins()
+            .bitcast(types::I64, MemFlags::new(), value);
+        self.set(register, bits, REAL);
+    }
+
+    /// `i8` 0 or 1
+    fn set_bool(&mut self, register: Register, value: ir::Value) {
+        let bits = self.builder.ins().uextend(types::I64, value);
+        self.set(register, bits, INT);
+    }
+
+    fn mov(&mut self, dst: Register, src: Register) {
+        let bits = self.bits(src);
+        let tag = self.builder.use_var(tag(src));
+        self.builder.def_var(self::bits(dst), bits);
+        self.builder.def_var(self::tag(dst), tag);
+    }
+
+    /// Loads `registers` from the frame, the other ones are integer 0
+    fn load_frame(&mut self, loaded: u32, registers: u32) {
+        for register in 0..registers {
+            let register = Register(register);
+            if register.0 < loaded {
+                let offset = offset(register.index());
+                let flags = MemFlags::trusted();
+                let ins = self.builder.ins();
+                let bits = ins.load(types::I64, flags, self.frame, offset);
+                let tag = self
+                    .builder
+                    .ins()
+                    .load(types::I64, flags, self.frame, offset + TAG);
+                self.builder.def_var(self::bits(register), bits);
+                self.builder.def_var(self::tag(register), tag);
+            } else {
+                let zero = self.int(0);
+                self.set(register, zero, INT);
+            }
+        }
+    }
+
+    /// Goes on in a new block if `condition` is zero, otherwise goes to `block`
+    fn unless(&mut self, condition: ir::Value, block: ir::Block) {
+        let next = self.builder.create_block();
+        let _: ir::Inst = self.builder.ins().brif(condition, block, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    /// Calls `helper` with the values and tags of `args`, `dst` gets its result
+    fn call(
+        &mut self,
+        helper: Helper,
+        operand: i64,
+        args: &[(ir::Value, ir::Value)],
+        dst: Option<Register>,
+    ) {
+        for (index, &(bits, tag)) in args.iter().enumerate() {
+            let offset = offset(index);
+            let _: ir::Inst = self.builder.ins().stack_store(bits, self.scratch, offset);
+            let _: ir::Inst = self
+                .builder
+                .ins()
+                .stack_store(tag, self.scratch, offset + TAG);
+        }
+        let address = i64::try_from(helper as usize).expect("Addresses fit into i64");
+        let callee = self.int(address);
+        let operand = self.int(operand);
+        let args_address = self.builder.ins().stack_addr(self.pointer, self.scratch, 0);
+        let count = self.int(i64::try_from(args.len()).expect("Few arguments"));
+        let output = self.builder.ins().stack_addr(self.pointer, self.output, 0);
+        let call = self.builder.ins().call_indirect(
+            self.helper,
+            callee,
+            &[self.context, operand, args_address, count, output],
+        );
+        let status = self.builder.inst_results(call)[0];
+        self.unless(status, self.failed);
+        if let Some(dst) = dst {
+            let bits = self.builder.ins().stack_load(types::I64, self.output, 0);
+            let tag = self.builder.ins().stack_load(types::I64, self.output, TAG);
+            self.builder.def_var(self::bits(dst), bits);
+            self.builder.def_var(self::tag(dst), tag);
+        }
+    }
+
+    fn arg(&mut self, register: Register) -> (ir::Value, ir::Value) {
+        (self.bits(register), self.builder.use_var(tag(register)))
+    }
+
+    fn args(&mut self, registers: &[Register]) -> Vec<(ir::Value, ir::Value)> {
+        registers
+            .iter()
+            .map(|&register| self.arg(register))
+            .collect()
+    }
+
+    /// Calls `fault` and returns its status
+    fn fault(&mut self, fault: i64, code: i64) {
+        let code = self.int(code);
+        let tag = self.int(INT);
+        self.call(self::fault, fault, &[(code, tag)], None);
+        // `fault` always fails
+        let status = self.builder.ins().iconst(types::I32, 1);
+        let _: ir::Inst = self.builder.ins().return_(&[status]);
+    }
+
+    /// `dst = f(lhs, rhs)` of reals
+    fn reals(
+        &mut self,
+        dst: Register,
+        (lhs, rhs): (Register, Register),
+        f: impl FnOnce(FuncInstBuilder<'_, 'f>, ir::Value, ir::Value) -> ir::Value,
+    ) {
+        let (lhs, rhs) = (self.real(lhs), self.real(rhs));
+        let result = f(self.builder.ins(), lhs, rhs);
+        self.set_real(dst, result);
+    }
+
+    /// `dst = f(lhs, rhs)` of integers, failing with `overflow` when `f` says it overflows
+    fn checked(
+        &mut self,
+        dst: Register,
+        (lhs, rhs): (Register, Register),
+        f: impl FnOnce(FuncInstBuilder<'_, 'f>, ir::Value, ir::Value) -> (ir::Value, ir::Value),
+    ) {
+        let (lhs, rhs) = (self.bits(lhs), self.bits(rhs));
+        let (result, overflown) = f(self.builder.ins(), lhs, rhs);
+        self.unless(overflown, self.overflow);
+        self.set(dst, result, INT);
+    }
+
+    /// `dst = f(lhs, rhs)` of integers, `rhs` is checked for zero and `MIN / -1` overflowing
+    fn division(
+        &mut self,
+        dst: Register,
+        (lhs, rhs): (Register, Register),
+        f: impl FnOnce(FuncInstBuilder<'_, 'f>, ir::Value, ir::Value) -> ir::Value,
+    ) {
+        let (lhs, rhs) = (self.bits(lhs), self.bits(rhs));
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+        self.unless(zero, self.division_by_zero);
+        let min = self.builder.ins().icmp_imm(IntCC::Equal, lhs, i64::MIN);
+        let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+        let overflown = self.builder.ins().band(min, minus_one);
+        self.unless(overflown, self.overflow);
+        let result = f(self.builder.ins(), lhs, rhs);
+        self.set(dst, result, INT);
+    }
+
+    fn compare_reals(&mut self, dst: Register, (lhs, rhs): (Register, Register), cc: FloatCC) {
+        let (lhs, rhs) = (self.real(lhs), self.real(rhs));
+        let compared = self.builder.ins().fcmp(cc, lhs, rhs);
+        self.set_bool(dst, compared);
+    }
+
+    fn compare_ints(&mut self, dst: Register, (lhs, rhs): (Register, Register), cc: IntCC) {
+        let (lhs, rhs) = (self.bits(lhs), self.bits(rhs));
+        let compared = self.builder.ins().icmp(cc, lhs, rhs);
+        self.set_bool(dst, compared);
+    }
+
+    /// Values are equal when their kinds are, which also compares references
+    fn equal(&mut self, dst: Register, (lhs, rhs): (Register, Register), negated: bool) {
+        let ((lhs, lhs_tag), (rhs, rhs_tag)) = (self.arg(lhs), self.arg(rhs));
+        let same = self.builder.ins().icmp(IntCC::Equal, lhs, rhs);
+        let same_tag = self.builder.ins().icmp(IntCC::Equal, lhs_tag, rhs_tag);
+        let mut equal = self.builder.ins().band(same, same_tag);
+        if negated {
+            equal = self.builder.ins().bxor_imm(equal, 1);
+        }
+        self.set_bool(dst, equal);
+    }
+
+    /// `dst = f(lhs, rhs)` of truths
+    fn logic(
+        &mut self,
+        dst: Register,
+        (lhs, rhs): (Register, Register),
+        f: impl FnOnce(FuncInstBuilder<'_, 'f>, ir::Value, ir::Value) -> ir::Value,
+    ) {
+        let (lhs, rhs) = (self.truth(lhs), self.truth(rhs));
+        let result = f(self.builder.ins(), lhs, rhs);
+        self.set_bool(dst, result);
+    }
+
+    fn binary(&mut self, op: SemanticBinaryOperator, dst: Register, lhs: Register, rhs: Register) {
+        use SemanticBinaryOperator as Op;
+
+        let operands = (lhs, rhs);
+        match op {
+            Op::RealAdd => self.reals(dst, operands, |ins, lhs, rhs| ins.fadd(lhs, rhs)),
+            Op::RealSub => self.reals(dst, operands, |ins, lhs, rhs| ins.fsub(lhs, rhs)),
+            Op::RealMul => self.reals(dst, operands, |ins, lhs, rhs| ins.fmul(lhs, rhs)),
+            Op::RealDiv => self.reals(dst, operands, |ins, lhs, rhs| ins.fdiv(lhs, rhs)),
+            Op::RealLe => self.compare_reals(dst, operands, FloatCC::LessThanOrEqual),
+            Op::RealLg => self.compare_reals(dst, operands, FloatCC::LessThan),
+            Op::RealGt => self.compare_reals(dst, operands, FloatCC::GreaterThan),
+            Op::RealGe => self.compare_reals(dst, operands, FloatCC::GreaterThanOrEqual),
+            Op::RealEq => self.compare_reals(dst, operands, FloatCC::Equal),
+            Op::RealNeq => self.compare_reals(dst, operands, FloatCC::NotEqual),
+            Op::IntAdd => self.checked(dst, operands, |ins, lhs, rhs| ins.sadd_overflow(lhs, rhs)),
+            Op::IntSub => self.checked(dst, operands, |ins, lhs, rhs| ins.ssub_overflow(lhs, rhs)),
+            Op::IntMul => self.checked(dst, operands, |ins, lhs, rhs| ins.smul_overflow(lhs, rhs)),
+            Op::IntDiv => self.division(dst, operands, |ins, lhs, rhs| ins.sdiv(lhs, rhs)),
+            Op::IntMod => self.division(dst, operands, |ins, lhs, rhs| ins.srem(lhs, rhs)),
+            Op::IntLe => self.compare_ints(dst, operands, IntCC::SignedLessThanOrEqual),
+            Op::IntLg => self.compare_ints(dst, operands, IntCC::SignedLessThan),
+            Op::IntGt => self.compare_ints(dst, operands, IntCC::SignedGreaterThan),
+            Op::IntGe => self.compare_ints(dst, operands, IntCC::SignedGreaterThanOrEqual),
+            Op::IntEq => self.equal(dst, operands, false),
+            Op::IntNeq => self.equal(dst, operands, true),
+            Op::BoolAnd => self.logic(dst, operands, |ins, lhs, rhs| ins.band(lhs, rhs)),
+            Op::BoolOr => self.logic(dst, operands, |ins, lhs, rhs| ins.bor(lhs, rhs)),
+            Op::BoolXor => self.logic(dst, operands, |ins, lhs, rhs| ins.bxor(lhs, rhs)),
+            Op::StringConcat
+            | Op::StringLe
+            | Op::StringLg
+            | Op::StringGt
+            | Op::StringGe
+            | Op::StringEq
+            | Op::StringNeq => unreachable!("Routines with strings are interpreted"),
+        }
+    }
+
+    fn unary(&mut self, op: SemanticUnaryOperator, dst: Register, src: Register) {
+        match op {
+            SemanticUnaryOperator::IntNeg => {
+                let value = self.bits(src);
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN);
+                self.unless(min, self.overflow);
+                let negated = self.builder.ins().ineg(value);
+                self.set(dst, negated, INT);
+            }
+            SemanticUnaryOperator::RealNeg => {
+                let value = self.real(src);
+                let negated = self.builder.ins().fneg(value);
+                self.set_real(dst, negated);
+            }
+            SemanticUnaryOperator::BoolNeg => {
+                let value = self.bits(src);
+                let negated = self.builder.ins().icmp_imm(IntCC::Equal, value, 0);
+                self.set_bool(dst, negated);
+            }
+        }
+    }
+
+    /// Goes to the block starting at `pc`
+    fn jump(&mut self, pc: usize) {
+        let block = self.blocks[&pc];
+        let _: ir::Inst = self.builder.ins().jump(block, &[]);
+    }
+
+    #[expect(clippy::too_many_lines, reason = "It is a single match")]
+    fn instruction(&mut self, pc: usize, instruction: &Instruction) {
+        match *instruction {
+            Instruction::Int { dst, value } => {
+                let value = self.int(value);
+                self.set(dst, value, INT);
+            }
+            Instruction::Real { dst, value } => {
+                let value = self.builder.ins().f64const(value);
+                self.set_real(dst, value);
+            }
+            Instruction::Move { dst, src } => self.mov(dst, src),
+            Instruction::Binary { op, dst, lhs, rhs } => self.binary(op, dst, lhs, rhs),
+            Instruction::Unary { op, dst, src } => self.unary(op, dst, src),
+            Instruction::IntToReal { dst, src } => {
+                let value = self.bits(src);
+                let converted = self.builder.ins().fcvt_from_sint(types::F64, value);
+                self.set_real(dst, converted);
+            }
+            Instruction::RealToInt { dst, src } => {
+                let args = [self.arg(src)];
+                self.call(to_integer, 0, &args, Some(dst));
+            }
+            Instruction::IntToBool { dst, src } => {
+                let args = [self.arg(src)];
+                self.call(native, Builtin::ToBoolean.id().into(), &args, Some(dst));
+            }
+            Instruction::LoadGlobal { dst, index } => {
+                let index = i64::try_from(index).expect("Few globals");
+                self.call(load_global, index, &[], Some(dst));
+            }
+            Instruction::StoreGlobal { index, src } => {
+                let index = i64::try_from(index).expect("Few globals");
+                let args = [self.arg(src)];
+                self.call(store_global, index, &args, None);
+            }
+            Instruction::AllocRecord { dst, type_id } => {
+                self.call(alloc_record, type_id.0.into(), &[], Some(dst));
+            }
+            Instruction::AllocArray { dst, type_id, size } => {
+                let size = self.int(size.cast_signed());
+                let tag = self.int(INT);
+                self.call(alloc_array, type_id.0.into(), &[(size, tag)], Some(dst));
+            }
+            Instruction::ArraySize { dst, array } => {
+                let args = [self.arg(array)];
+                self.call(array_size, 0, &args, Some(dst));
+            }
+            Instruction::LoadField {
+                dst,
+                record,
+                offset,
+            } => {
+                let args = [self.arg(record)];
+                self.call(load_field, offset.cast_signed(), &args, Some(dst));
+            }
+            Instruction::StoreField {
+                record,
+                offset,
+                src,
+            } => {
+                let args = self.args(&[record, src]);
+                self.call(store_field, offset.cast_signed(), &args, None);
+            }
+            Instruction::LoadElement { dst, array, index } => {
+                let args = self.args(&[array, index]);
+                self.call(load_element, 0, &args, Some(dst));
+            }
+            Instruction::StoreElement { array, index, src } => {
+                let args = self.args(&[array, index, src]);
+                self.call(store_element, 0, &args, None);
+            }
+            Instruction::Call {
+                dst,
+                function_label,
+                ref args,
+            } => {
+                let args = self.args(args);
+                self.call(call, function_label.cast_signed(), &args, dst);
+            }
+            Instruction::CallNative { dst, id, ref args }
+                if id == Builtin::Sqrt.id() && args.len() == 1 =>
+            {
+                let value = self.real(args[0]);
+                let root = self.builder.ins().sqrt(value);
+                if let Some(dst) = dst {
+                    self.set_real(dst, root);
+                }
+            }
+            Instruction::CallNative { dst, id, ref args } => {
+                let args = self.args(args);
+                self.call(native, id.into(), &args, dst);
+            }
+            Instruction::Jump { target } => self.jump(target),
+            Instruction::JumpZero { condition, target } => {
+                let condition = self.truth(condition);
+                let (then, otherwise) = (self.blocks[&(pc + 1)], self.blocks[&target]);
+                let _: ir::Inst = self
+                    .builder
+                    .ins()
+                    .brif(condition, then, &[], otherwise, &[]);
+            }
+            Instruction::Return { src } => {
+                let (bits, tag) = match src {
+                    Some(src) => self.arg(src),
+                    None => (self.int(0), self.int(INT)),
+                };
+                let flags = MemFlags::trusted();
+                let _: ir::Inst = self.builder.ins().store(flags, bits, self.result, 0);
+                let _: ir::Inst = self.builder.ins().store(flags, tag, self.result, TAG);
+                let status = self.builder.ins().iconst(types::I32, 0);
+                let _: ir::Inst = self.builder.ins().return_(&[status]);
+            }
+            Instruction::Panic { code } => self.fault(PANIC, code.cast_signed()),
+            Instruction::String { .. } | Instruction::Print { .. } => {
+                unreachable!("Left to the interpreter")
+            }
+        }
+    }
+
+    /// Entry 0 starts with the arguments, the other ones load every register
+    /// and continue at a loop head
+    fn entries(&mut self, routine: &Routine, heads: &[usize]) {
+        let start = self.builder.create_block();
+        let resumes: Vec<_> = heads.iter().map(|_| self.builder.create_block()).collect();
+        let calls: Vec<_> = [start]
+            .iter()
+            .chain(&resumes)
+            .map(|&block| self.builder.func.dfg.block_call(block, &[]))
+            .collect();
+        let table = self
+            .builder
+            .create_jump_table(JumpTableData::new(calls[0], &calls));
+        let entry = self.builder.ins().ireduce(types::I32, self.entry);
+        let _: ir::Inst = self.builder.ins().br_table(entry, table);
+
+        self.builder.switch_to_block(start);
+        self.load_frame(routine.args, routine.registers);
+        self.jump(0);
+        for (&head, block) in heads.iter().zip(resumes) {
+            self.builder.switch_to_block(block);
+            self.load_frame(routine.registers, routine.registers);
+            self.jump(head);
+        }
+    }
+
+    fn translate(mut self, routine: &Routine, heads: &[usize]) {
+        self.entries(routine, heads);
+        let mut open = false;
+        for (pc, instruction) in routine.code.iter().enumerate() {
+            if let Some(&block) = self.blocks.get(&pc) {
+                if open {
+                    let _: ir::Inst = self.builder.ins().jump(block, &[]);
+                }
+                self.builder.switch_to_block(block);
+            }
+            self.instruction(pc, instruction);
+            open = !matches!(
+                instruction,
+                Instruction::Jump { .. }
+                    | Instruction::JumpZero { .. }
+                    | Instruction::Return { .. }
+                    | Instruction::Panic { .. }
+            );
+        }
+
+        self.builder.switch_to_block(self.failed);
+        let status = self.builder.ins().iconst(types::I32, 1);
+        let _: ir::Inst = self.builder.ins().return_(&[status]);
+        self.builder.switch_to_block(self.overflow);
+        self.fault(OVERFLOW, 0);
+        self.builder.switch_to_block(self.division_by_zero);
+        self.fault(DIVISION_BY_ZERO, 0);
+    }
+}
@@ -0,0 +1,465 @@
+use compiler::bytecode::{
+    ArrayRTTI, Bytecode, FunctionCode, FunctionRecord, FunctionTable, Module, RTTI, RTTIElement,
+};
+
+use std::io::Cursor;
+use std::path::Path;
+
+use super::*;
+use crate::Vm;
+use crate::console::Capture;
+use crate::test_support::{interpreted, lowered};
+
+const REALS: TypeId = TypeId(4);
+
+fn r(index: u32) -> Register {
+    Register(index)
+}
+
+fn routine(name: &str, args: u32, registers: u32, code: Vec<Instruction>) -> Routine {
+    Routine {
+        name: name.to_owned(),
+        args,
+        registers,
+        code,
+    }
+}
+
+fn binary(op: SemanticBinaryOperator, dst: u32, lhs: u32, rhs: u32) -> Instruction {
+    Instruction::Binary {
+        op,
+        dst: r(dst),
+        lhs: r(lhs),
+        rhs: r(rhs),
+    }
+}
+
+fn int(dst: u32, value: i64) -> Instruction {
+    Instruction::Int { dst: r(dst), value }
+}
+
+/// Loads `routines` by label, each taking integers. The bytecode only has their labels,
+/// the register code runs instead.
+fn load(routines: Vec<(u64, Routine, TypeId)>, strings: &[&str], threshold: u64) -> Vm {
+    let functions = routines
+        .iter()
+        .map(|(label, routine, result)| FunctionRecord {
+            name: routine.name.clone(),
+            code: FunctionCode::Label(*label),
+            args: vec![TypeId::INTEGER; routine.args as usize],
+            result: *result,
+        })
+        .collect();
+    let mut rtti = RTTI::primitives();
+    rtti.0.push(RTTIElement::Array(ArrayRTTI {
+        id: REALS,
+        element_id: TypeId::REAL,
+    }));
+    let module = Module {
+        name: "test".to_owned(),
+        code: routines
+            .iter()
+            .map(|&(id, ..)| Bytecode::Label { id })
+            .collect(),
+        label_count: u64::try_from(routines.len()).expect("Few routines"),
+        functions: FunctionTable(functions),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti,
+        strings: strings.iter().map(|&s| s.to_owned()).collect(),
+        global_count: 1,
+    };
+    let mut vm = Vm::new();
+    vm.load(module).expect("Module is well-formed");
+    let code = Code(
+        routines
+            .into_iter()
+            .map(|(label, routine, _)| (label, routine))
+            .collect(),
+    );
+    vm.load_registers(code).expect("Code is linked");
+    vm.set_jit_threshold(threshold);
+    vm
+}
+
+/// `routine norm(n : integer) : real is var v : array [100] real;`
+/// `for i in 1 .. n loop v[i] := real(i); end; var s is 0.0;`
+/// `for i in 1 .. n loop s := s + sqrt(v[i] * v[i]); end; @0 := n; return s; end;`
+fn norm() -> Vec<(u64, Routine, TypeId)> {
+    use SemanticBinaryOperator as Op;
+
+    let code = vec![
+        Instruction::AllocArray {
+            dst: r(1),
+            type_id: REALS,
+            size: 100,
+        },
+        int(2, 1),
+        Instruction::Real {
+            dst: r(3),
+            value: 0.0,
+        },
+        int(4, 1),
+        binary(Op::IntLe, 5, 2, 0),
+        Instruction::JumpZero {
+            condition: r(5),
+            target: 10,
+        },
+        Instruction::IntToReal {
+            dst: r(6),
+            src: r(2),
+        },
+        Instruction::StoreElement {
+            array: r(1),
+            index: r(2),
+            src: r(6),
+        },
+        binary(Op::IntAdd, 2, 2, 4),
+        Instruction::Jump { target: 4 },
+        int(2, 1),
+        binary(Op::IntLe, 5, 2, 0),
+        Instruction::JumpZero {
+            condition: r(5),
+            target: 19,
+        },
+        Instruction::LoadElement {
+            dst: r(6),
+            array: r(1),
+            index: r(2),
+        },
+        binary(Op::RealMul, 7, 6, 6),
+        Instruction::CallNative {
+            dst: Some(r(7)),
+            id: Builtin::Sqrt.id(),
+            args: vec![r(7)],
+        },
+        binary(Op::RealAdd, 3, 3, 7),
+        binary(Op::IntAdd, 2, 2, 4),
+        Instruction::Jump { target: 11 },
+        Instruction::StoreGlobal {
+            index: 0,
+            src: r(0),
+        },
+        Instruction::Return { src: Some(r(3)) },
+    ];
+    vec![(0, routine("norm", 1, 8, code), TypeId::REAL)]
+}
+
+/// `routine abs(x : integer) : integer is if x < 0 then return -x; end; return x; end;`
+/// `routine total(n : integer) : integer is var s is 0;`
+/// `for i in -n .. n loop s := s + abs(i) * 2 / 2; end; return s; end;`
+fn abs_total() -> Vec<(u64, Routine, TypeId)> {
+    use SemanticBinaryOperator as Op;
+
+    let abs = vec![
+        int(1, 0),
+        binary(Op::IntLg, 2, 0, 1),
+        Instruction::JumpZero {
+            condition: r(2),
+            target: 5,
+        },
+        Instruction::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            dst: r(0),
+            src: r(0),
+        },
+        Instruction::Return { src: Some(r(0)) },
+        Instruction::Return { src: Some(r(0)) },
+    ];
+    let total = vec![
+        Instruction::Unary {
+            op: SemanticUnaryOperator::IntNeg,
+            dst: r(1),
+            src: r(0),
+        },
+        int(2, 0),
+        int(3, 1),
+        int(6, 2),
+        binary(Op::IntLe, 4, 1, 0),
+        Instruction::JumpZero {
+            condition: r(4),
+            target: 12,
+        },
+        Instruction::Call {
+            dst: Some(r(5)),
+            function_label: 1,
+            args: vec![r(1)],
+        },
+        binary(Op::IntMul, 5, 5, 6),
+        binary(Op::IntDiv, 5, 5, 6),
+        binary(Op::IntAdd, 2, 2, 5),
+        binary(Op::IntAdd, 1, 1, 3),
+        Instruction::Jump { target: 4 },
+        Instruction::Return { src: Some(r(2)) },
+    ];
+    vec![
+        (0, routine("total", 1, 7, total), TypeId::INTEGER),
+        (1, routine("abs", 1, 3, abs), TypeId::INTEGER),
+    ]
+}
+
+#[test]
+fn numeric_kernel() {
+    let mut interpreted = load(norm(), &[], u64::MAX);
+    let mut jitted = load(norm(), &[], 50);
+    for n in [10, 100, 100] {
+        let expected = interpreted.call("norm", &[Value::Int(n)]).ok();
+        assert_eq!(jitted.call("norm", &[Value::Int(n)]).ok(), expected);
+    }
+    assert_eq!(
+        jitted.call("norm", &[Value::Int(100)]).ok(),
+        Some(Value::Real(5050.0))
+    );
+    assert_eq!(jitted.compiled_routines(), 1);
+    assert_eq!(interpreted.compiled_routines(), 0);
+    // The first loop went native halfway through the second call
+    assert!(
+        jitted.executed() < interpreted.executed() / 2,
+        "{} of {}",
+        jitted.executed(),
+        interpreted.executed()
+    );
+}
+
+#[test]
+fn hot_calls() {
+    let mut interpreted = load(abs_total(), &[], u64::MAX);
+    let mut jitted = load(abs_total(), &[], 10);
+    for n in [3, 10, 1000] {
+        let expected = interpreted.call("total", &[Value::Int(n)]).ok();
+        assert_eq!(jitted.call("total", &[Value::Int(n)]).ok(), expected);
+    }
+    assert_eq!(
+        jitted.call("abs", &[Value::Int(-7)]).ok(),
+        Some(Value::Int(7))
+    );
+    assert_eq!(jitted.compiled_routines(), 2);
+
+    // Reloaded code starts over
+    let code = abs_total()
+        .into_iter()
+        .map(|(label, routine, _)| (label, routine))
+        .collect();
+    assert_eq!(jitted.load_registers(Code(code)).err(), None);
+    assert_eq!(jitted.compiled_routines(), 0);
+    assert_eq!(
+        jitted.call("total", &[Value::Int(3)]).ok(),
+        Some(Value::Int(12))
+    );
+    assert_eq!(jitted.compiled_routines(), 0);
+}
+
+#[test]
+fn failures() {
+    for threshold in [u64::MAX, 0] {
+        let mut vm = load(norm(), &[], threshold);
+        let failure = vm.call("norm", &[Value::Int(101)]).err();
+        assert!(
+            matches!(
+                failure,
+                Some(RuntimeError::IndexOutOfBounds {
+                    index: 101,
+                    length: 100
+                })
+            ),
+            "{failure:?}"
+        );
+
+        let mut vm = load(abs_total(), &[], threshold);
+        let failure = vm.call("total", &[Value::Int(i64::MAX)]).err();
+        assert!(
+            matches!(failure, Some(RuntimeError::IntegerOverflow)),
+            "{failure:?}"
+        );
+        let failure = vm.call("abs", &[Value::Int(i64::MIN)]).err();
+        assert!(
+            matches!(failure, Some(RuntimeError::IntegerOverflow)),
+            "{failure:?}"
+        );
+    }
+
+    let quotient = vec![
+        binary(SemanticBinaryOperator::IntDiv, 2, 0, 1),
+        Instruction::JumpZero {
+            condition: r(2),
+            target: 3,
+        },
+        Instruction::Return { src: Some(r(2)) },
+        Instruction::Panic { code: 7 },
+    ];
+    let mut vm = load(
+        vec![(0, routine("quotient", 2, 3, quotient), TypeId::INTEGER)],
+        &[],
+        0,
+    );
+    assert_eq!(
+        vm.call("quotient", &[Value::Int(7), Value::Int(2)]).ok(),
+        Some(Value::Int(3))
+    );
+    assert_eq!(vm.compiled_routines(), 1);
+    let failure = vm.call("quotient", &[Value::Int(7), Value::Int(0)]).err();
+    assert!(
+        matches!(failure, Some(RuntimeError::DivisionByZero)),
+        "{failure:?}"
+    );
+    let failure = vm.call("quotient", &[Value::Int(1), Value::Int(2)]).err();
+    assert!(
+        matches!(failure, Some(RuntimeError::Panic { code: 7 })),
+        "{failure:?}"
+    );
+}
+
+#[test]
+fn strings_are_interpreted() {
+    // `routine greet(n : integer) is for i in 1 .. n loop print "hi"; end; end;`
+    let greet = vec![
+        int(1, 1),
+        binary(SemanticBinaryOperator::IntLe, 2, 1, 0),
+        Instruction::JumpZero {
+            condition: r(2),
+            target: 7,
+        },
+        Instruction::String { dst: r(3), id: 0 },
+        Instruction::Print {
+            type_id: TypeId::STRING,
+            src: r(3),
+        },
+        Instruction::Call {
+            dst: Some(r(1)),
+            function_label: 1,
+            args: vec![r(1)],
+        },
+        Instruction::Jump { target: 1 },
+        Instruction::Return { src: None },
+    ];
+    let next = vec![
+        int(1, 1),
+        binary(SemanticBinaryOperator::IntAdd, 0, 0, 1),
+        Instruction::Return { src: Some(r(0)) },
+    ];
+    let mut vm = load(
+        vec![
+            (0, routine("greet", 1, 4, greet), TypeId::INTEGER),
+            (1, routine("next", 1, 2, next), TypeId::INTEGER),
+        ],
+        &["hi"],
+        0,
+    );
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    assert_eq!(vm.call("greet", &[Value::Int(3)]).ok(), Some(Value::Int(0)));
+    assert_eq!(output.contents(), b"hi\nhi\nhi\n");
+    // Only `next`
+    assert_eq!(vm.compiled_routines(), 1);
+}
+
+#[test]
+fn deep_recursion() {
+    // `routine depth(n : integer) : integer is`
+    // `if n = 0 then return 0; end; return depth(n - 1) + 1; end;`
+    let depth = vec![
+        int(1, 0),
+        binary(SemanticBinaryOperator::IntNeq, 2, 0, 1),
+        Instruction::JumpZero {
+            condition: r(2),
+            target: 7,
+        },
+        int(1, 1),
+        binary(SemanticBinaryOperator::IntSub, 2, 0, 1),
+        Instruction::Call {
+            dst: Some(r(2)),
+            function_label: 0,
+            args: vec![r(2)],
+        },
+        Instruction::Jump { target: 8 },
+        Instruction::Return { src: Some(r(1)) },
+        binary(SemanticBinaryOperator::IntAdd, 2, 2, 1),
+        Instruction::Return { src: Some(r(2)) },
+    ];
+    for threshold in [u64::MAX, 0] {
+        let mut vm = load(
+            vec![(0, routine("depth", 1, 3, depth.clone()), TypeId::INTEGER)],
+            &[],
+            threshold,
+        );
+        assert_eq!(
+            vm.call("depth", &[Value::Int(1000)]).ok(),
+            Some(Value::Int(1000))
+        );
+        let failure = vm.call("depth", &[Value::Int(200_000)]).err();
+        assert!(
+            matches!(failure, Some(RuntimeError::StackOverflow)),
+            "{failure:?}"
+        );
+        let limit = i64::try_from(MAX_DEPTH).expect("Depth fits") - 1;
+        assert_eq!(
+            vm.call("depth", &[Value::Int(limit)]).ok(),
+            Some(Value::Int(limit))
+        );
+    }
+}
+
+/// Runs `main` on the register machine reading `input`, compiling the routines entered
+/// `threshold` times, with what it printed
+fn run_lowered(module: Module, code: Code, input: &str, threshold: u64) -> (Vm, String) {
+    let mut vm = Vm::new();
+    vm.load(module).expect("Module is well-formed");
+    vm.load_registers(code).expect("Code is linked");
+    vm.set_jit_threshold(threshold);
+    vm.set_input(Cursor::new(input.to_owned()));
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    vm.run().expect("Runs");
+    let output = String::from_utf8(output.contents()).expect("Output is UTF-8");
+    (vm, output)
+}
+
+/// `collatz` of `tests/src/while_loops.i` gets hot, the native code computes what the
+/// interpreter does
+#[test]
+fn compiled_hot_routine() {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/src/while_loops.i");
+    let (module, code) = lowered(&source);
+    let expected = interpreted(module.clone(), "");
+    let (mut registers, output) = run_lowered(module.clone(), code.clone(), "", u64::MAX);
+    assert_eq!(output, expected);
+    let (mut jitted, output) = run_lowered(module, code, "", 10);
+    assert_eq!(output, expected);
+    assert_eq!(jitted.compiled_routines(), 1);
+    for n in [1, 27, 97] {
+        let steps = registers.call("collatz", &[Value::Int(n)]).ok();
+        assert_eq!(jitted.call("collatz", &[Value::Int(n)]).ok(), steps);
+    }
+    assert_eq!(
+        jitted.call("collatz", &[Value::Int(27)]).ok(),
+        Some(Value::Int(111))
+    );
+    assert_eq!(registers.compiled_routines(), 0);
+    assert!(
+        jitted.executed() < registers.executed(),
+        "{} of {}",
+        jitted.executed(),
+        registers.executed()
+    );
+}
+
+/// The programs of `tests/run` print the same with every routine compiled as on the stack machine
+#[test]
+fn corpus_runs_like_the_interpreter() {
+    let mut compiled = 0;
+    for corpus::Program {
+        name,
+        source,
+        input,
+        ..
+    } in corpus::programs()
+    {
+        let (module, code) = lowered(&source);
+        let expected = interpreted(module.clone(), &input);
+        let (vm, output) = run_lowered(module, code, &input, 0);
+        assert_eq!(output, expected, "{name}");
+        compiled += vm.compiled_routines();
+    }
+    assert!(compiled > 0);
+}
@@ -0,0 +1,243 @@
+//! Virtual machine running programs produced by the compiler
+//!
+//! ```text
+//! let mut vm = Vm::new();
+//! vm.load(compiler::bytecode::linker::link(modules)?)?;
+//! vm.run()?;
+//! ```
+
+use std::io::{BufRead, Write};
+
+use compiler::bytecode::{FunctionCode, FunctionTable, Module, RTTI};
+use compiler::registers;
+
+use crate::console::Console;
+use crate::host::{HostError, HostFunctions, IntoHostFunction, Signature, accepts};
+pub use crate::interpreter::{MAX_DEPTH, RuntimeError};
+use crate::interpreter::{Machine, from_machine};
+use crate::profile::Profile;
+pub use crate::program::LoadError;
+use crate::program::Program;
+use crate::value::{Heap, Value};
+
+pub mod console;
+pub mod host;
+mod interpreter;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod natives;
+pub mod profile;
+mod program;
+#[cfg(test)]
+mod test_support;
+pub mod value;
+pub mod wasm;
+
+#[derive(Debug, Default)]
+pub struct Vm {
+    heap: Heap,
+    host_functions: HostFunctions,
+    program: Option<Program>,
+    /// Runs instead of the program's bytecode when loaded
+    registers: Option<registers::Code>,
+    globals: Vec<Value>,
+    /// Whether the `INIT` routines of the loaded program ran
+    initialized: bool,
+    console: Console,
+    executed: u64,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit,
+    profile: Option<Profile>,
+}
+
+impl Vm {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Makes `function` callable from `.i` code as `name`
+    pub fn register<Args>(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        function: impl IntoHostFunction<Args>,
+    ) -> Result<(), HostError> {
+        self.host_functions
+            .register(name, signature, function)
+            .map(|_| ())
+    }
+
+    pub fn call_host(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, HostError> {
+        let function = self
+            .host_functions
+            .lookup(name)
+            .and_then(|id| self.host_functions.get(id))
+            .ok_or_else(|| HostError::NotFound {
+                name: name.to_owned(),
+            })?;
+        // Without a program only primitives have types
+        let primitives = RTTI::primitives();
+        let rtti = self
+            .program
+            .as_ref()
+            .map_or(&primitives, |program| &program.module.rtti);
+        function.call(&mut self.heap, rtti, args)
+    }
+
+    /// Where `read_*` built-ins take words from, stdin by default
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.console.input = Box::new(input);
+    }
+
+    /// Where `print` writes lines to, stdout by default, see `console::Capture`
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.console.output = Box::new(output);
+    }
+
+    /// Replaces the previously loaded program, host functions it uses must be registered by now.
+    /// Globals are initialized by the `INIT` routines of the modules before the first call.
+    pub fn load(&mut self, module: Module) -> Result<(), LoadError> {
+        let program = Program::load(module, &self.host_functions, &mut self.heap)?;
+        self.globals = vec![Value::Int(0); program.module.global_count as usize];
+        self.initialized = false;
+        if self.profile.is_some() {
+            self.profile = Some(Profile::new(&program.module.functions));
+        }
+        self.program = Some(program);
+        self.registers = None;
+        #[cfg(feature = "jit")]
+        self.jit.reset();
+        Ok(())
+    }
+
+    /// Runs the routines of the loaded program from `code` instead of its bytecode,
+    /// `code` has to be linked like the program, see `linker::link_registers`
+    pub fn load_registers(&mut self, code: registers::Code) -> Result<(), LoadError> {
+        let program = self.program.as_ref().ok_or(LoadError::NotLoaded)?;
+        program.check_registers(&code)?;
+        self.registers = Some(code);
+        #[cfg(feature = "jit")]
+        self.jit.reset();
+        Ok(())
+    }
+
+    /// Instructions run by all the calls so far, on either machine.
+    /// Native code of the JIT does not count.
+    #[must_use]
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Calls and loop iterations after which a routine of the register code is compiled
+    /// to native code, `jit::THRESHOLD` by default
+    #[cfg(feature = "jit")]
+    pub fn set_jit_threshold(&mut self, threshold: u64) {
+        self.jit.threshold = threshold;
+    }
+
+    /// Routines of the loaded register code running natively by now
+    #[cfg(feature = "jit")]
+    #[must_use]
+    pub fn compiled_routines(&self) -> usize {
+        self.jit.compiled()
+    }
+
+    /// Starts profiling the calls to routines run on the stack machine, or stops it.
+    /// Loading a program starts the profile over.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(|| {
+            let functions = self
+                .program
+                .as_ref()
+                .map(|program| &program.module.functions);
+            Profile::new(functions.unwrap_or(&FunctionTable(Vec::new())))
+        });
+    }
+
+    /// What the calls so far took, if profiling
+    #[must_use]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Calls `main`
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        let _: Value = self.call("main", &[])?;
+        self.console.output.flush()?;
+        Ok(())
+    }
+
+    /// Procedures return integer 0. The first call runs the `INIT` routines of the program.
+    /// Objects the program can no longer reach may be collected while it runs, so references
+    /// kept from earlier calls stay valid only if they are passed in again or stored in globals.
+    pub fn call(&mut self, routine: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        let program = self.program.as_ref().ok_or(RuntimeError::NotLoaded)?;
+        let function = program
+            .routine(routine)
+            .ok_or_else(|| RuntimeError::UnknownRoutine {
+                name: routine.to_owned(),
+            })?;
+        if function.args.len() != args.len() {
+            return Err(RuntimeError::ArgumentCount {
+                routine: routine.to_owned(),
+                expected: function.args.len(),
+                found: args.len(),
+            });
+        }
+        let rtti = &program.module.rtti;
+        for (index, (&expected, &arg)) in function.args.iter().zip(args).enumerate() {
+            if !accepts(expected, arg, &self.heap, rtti) {
+                return Err(RuntimeError::ArgumentType {
+                    routine: routine.to_owned(),
+                    index,
+                    expected,
+                });
+            }
+        }
+        let FunctionCode::Label(label) = function.code else {
+            unreachable!("Only compiled routines are looked up")
+        };
+        let result = function.result;
+        if !self.initialized {
+            let inits: Vec<u64> = program.inits().collect();
+            for init in inits {
+                let _: Value = self.execute(init, &[])?;
+            }
+            self.initialized = true;
+        }
+        Ok(from_machine(self.execute(label, args)?, result))
+    }
+
+    /// Runs the routine at `label` on the register machine if register code is loaded,
+    /// on the stack machine otherwise
+    fn execute(&mut self, label: u64, args: &[Value]) -> Result<Value, RuntimeError> {
+        let program = self.program.as_ref().ok_or(RuntimeError::NotLoaded)?;
+        let mut machine = Machine::new(
+            program,
+            &mut self.heap,
+            &self.host_functions,
+            &mut self.globals,
+            &mut self.console,
+        );
+        #[cfg(feature = "jit")]
+        {
+            machine.jit = Some(&mut self.jit);
+        }
+        let value = if let Some(code) = &self.registers {
+            machine.run_registers(code, label, args)
+        } else {
+            machine.profile = self.profile.as_mut();
+            machine.run(label, args)
+        };
+        self.executed += machine.executed;
+        if let Some(profile) = &mut self.profile {
+            profile.finish();
+        }
+        value
+    }
+}
@@ -0,0 +1,154 @@
+//! Built-in routines, see `compiler::builtins`
+
+use core::cmp::Ordering;
+
+use compiler::builtins::Builtin;
+use compiler::bytecode::RTTI;
+
+use crate::console::Console;
+use crate::interpreter::{RuntimeError, int, real, real_to_int, string, truth};
+use crate::value::{Heap, Object, ObjectRef, Value};
+
+fn object_ref(value: Value) -> Result<ObjectRef, RuntimeError> {
+    match value {
+        Value::Ref(ObjectRef::NULL) => Err(RuntimeError::NullReference),
+        Value::Ref(object) => Ok(object),
+        Value::Int(_) | Value::Real(_) | Value::Bool(_) => {
+            Err(RuntimeError::Malformed("Expected a reference".to_owned()))
+        }
+    }
+}
+
+fn array(heap: &mut Heap, value: Value) -> Result<&mut Vec<Value>, RuntimeError> {
+    match heap.get_mut(object_ref(value)?) {
+        Object::Array(elements) => Ok(elements),
+        Object::Record(_) | Object::String(_) => {
+            Err(RuntimeError::Malformed("Expected an array".to_owned()))
+        }
+    }
+}
+
+fn numeric(
+    value: Value,
+    on_int: impl FnOnce(i64) -> Option<i64>,
+    on_real: impl FnOnce(f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(value) => on_int(value)
+            .map(Value::Int)
+            .ok_or(RuntimeError::IntegerOverflow),
+        Value::Real(value) => Ok(Value::Real(on_real(value))),
+        Value::Bool(_) | Value::Ref(_) => {
+            Err(RuntimeError::Malformed("Expected a number".to_owned()))
+        }
+    }
+}
+
+fn pick(lhs: Value, rhs: Value, wanted: Ordering) -> Result<Value, RuntimeError> {
+    let ordering = match (lhs, rhs) {
+        (Value::Int(lhs), Value::Int(rhs)) => lhs.cmp(&rhs),
+        (Value::Real(lhs), Value::Real(rhs)) => lhs.total_cmp(&rhs),
+        _ => return Err(RuntimeError::Malformed("Expected two numbers".to_owned())),
+    };
+    Ok(if ordering == wanted { lhs } else { rhs })
+}
+
+/// Arguments are in the order of parameters, procedures return integer 0 like compiled ones do.
+/// `rtti` has the types of the objects `equals` compares.
+pub(crate) fn call(
+    builtin: Builtin,
+    heap: &mut Heap,
+    rtti: &RTTI,
+    console: &mut Console,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    let arg = |index: usize| {
+        args.get(index).copied().ok_or_else(|| {
+            RuntimeError::Malformed(format!("Too few arguments for `{}`", builtin.name()))
+        })
+    };
+    let unary = |f: fn(f64) -> f64| Ok(Value::Real(f(real(arg(0)?)?)));
+    let rounding = |f: fn(f64) -> f64| real_to_int(f(real(arg(0)?)?)).map(Value::Int);
+
+    match builtin {
+        Builtin::Sqrt => unary(f64::sqrt),
+        Builtin::Sin => unary(f64::sin),
+        Builtin::Cos => unary(f64::cos),
+        Builtin::Tan => unary(f64::tan),
+        Builtin::Atan => unary(f64::atan),
+        Builtin::Exp => unary(f64::exp),
+        Builtin::Ln => unary(f64::ln),
+        Builtin::Abs => numeric(arg(0)?, i64::checked_abs, f64::abs),
+        Builtin::Floor => rounding(f64::floor),
+        Builtin::Ceil => rounding(f64::ceil),
+        Builtin::Round => rounding(f64::round),
+        Builtin::ToInteger => rounding(f64::trunc),
+        Builtin::Pow => Ok(Value::Real(real(arg(0)?)?.powf(real(arg(1)?)?))),
+        Builtin::Min => pick(arg(0)?, arg(1)?, Ordering::Less),
+        Builtin::Max => pick(arg(0)?, arg(1)?, Ordering::Greater),
+        #[expect(clippy::cast_precision_loss, reason = "It is how conversion works")]
+        Builtin::ToReal => Ok(Value::Real(int(arg(0)?)? as f64)),
+        Builtin::ToBoolean => match int(arg(0)?)? {
+            value @ (0 | 1) => Ok(Value::Int(value)),
+            value => Err(RuntimeError::InvalidConversion(format!(
+                "{value} is not a boolean"
+            ))),
+        },
+        Builtin::Fill => {
+            let value = arg(1)?;
+            array(heap, arg(0)?)?.fill(value);
+            Ok(Value::Int(0))
+        }
+        Builtin::Copy => Ok(heap.deep_copy(arg(0)?)),
+        Builtin::Equals => Ok(Value::Int(heap.deep_eq(arg(0)?, arg(1)?, rtti).into())),
+        Builtin::Sort => {
+            let elements = array(heap, arg(0)?)?;
+            let integers: Result<Vec<_>, _> =
+                elements.iter().map(|&element| int(element)).collect();
+            *elements = if let Ok(mut integers) = integers {
+                integers.sort_unstable();
+                integers.into_iter().map(Value::Int).collect()
+            } else {
+                // The checker lets only arrays of numbers through, mixed ones are malformed
+                let mut reals = elements
+                    .iter()
+                    .map(|&element| real(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                reals.sort_by(f64::total_cmp);
+                reals.into_iter().map(Value::Real).collect()
+            };
+            Ok(Value::Int(0))
+        }
+        Builtin::Assert => {
+            if truth(arg(0)?)? {
+                Ok(Value::Int(0))
+            } else {
+                Err(RuntimeError::AssertionFailed)
+            }
+        }
+        Builtin::ReadInteger => Ok(Value::Int(console.read("an integer")??)),
+        Builtin::ReadReal => Ok(Value::Real(console.read("a real")??)),
+        Builtin::ReadBoolean => Ok(Value::Int(console.read::<bool>("a boolean")??.into())),
+        Builtin::Length => {
+            let length = string(heap, arg(0)?)?.chars().count();
+            Ok(Value::Int(
+                i64::try_from(length).expect("Strings fit into memory"),
+            ))
+        }
+        Builtin::CharAt => {
+            let index = int(arg(1)?)?;
+            let string = string(heap, arg(0)?)?;
+            let found = usize::try_from(index)
+                .ok()
+                .and_then(|index| index.checked_sub(1))
+                .and_then(|offset| string.chars().nth(offset));
+            let Some(found) = found else {
+                return Err(RuntimeError::IndexOutOfBounds {
+                    index,
+                    length: string.chars().count(),
+                });
+            };
+            Ok(Value::Ref(heap.alloc(Object::String(found.to_string()))))
+        }
+    }
+}
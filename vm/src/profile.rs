@@ -0,0 +1,294 @@
+//! Profile of programs run on the stack machine, see `Vm::set_profiling`
+//!
+//! Routines are counted by the label they start at, loops by the `Bytecode::Label` their
+//! jumps back go to. Instructions are attributed to the routines running them along the
+//! whole stack of calls, which is what the collapsed stacks list.
+
+use core::fmt::Write as _;
+use core::time::Duration;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use compiler::bytecode::{FunctionCode, FunctionTable, TypeId};
+
+#[cfg(test)]
+mod tests;
+
+/// What a routine took, over all of its calls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub calls: u64,
+    /// Instructions run by the routine and the routines it called.
+    /// Recursive calls count once, with the outermost one.
+    pub instructions: u64,
+    /// Instructions run by the routine itself
+    pub self_instructions: u64,
+    pub time: Duration,
+    pub self_time: Duration,
+}
+
+/// Node of the tree of calls
+#[derive(Debug)]
+struct Node {
+    /// `None` for the root
+    label: Option<u64>,
+    parent: usize,
+    children: HashMap<u64, usize>,
+    /// Run by the routine at this node itself
+    instructions: u64,
+}
+
+/// Routine running
+#[derive(Debug)]
+struct Active {
+    label: u64,
+    node: usize,
+    entered: Instant,
+    /// `Profile::instructions` when entered
+    instructions: u64,
+    /// Spent in the routines it called
+    callee_time: Duration,
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    /// Labels of routines to their names
+    names: HashMap<u64, String>,
+    routines: BTreeMap<u64, RoutineProfile>,
+    /// Jumps back to a label, by the label of the routine and the one of the loop
+    loops: BTreeMap<(u64, u64), u64>,
+    allocations: HashMap<TypeId, u64>,
+    /// Root first
+    nodes: Vec<Node>,
+    active: Vec<Active>,
+    /// Calls of each routine running now
+    running: HashMap<u64, usize>,
+    /// Instructions run so far
+    instructions: u64,
+}
+
+impl Profile {
+    pub(crate) fn new(functions: &FunctionTable) -> Self {
+        let names = functions
+            .0
+            .iter()
+            .filter_map(|function| match function.code {
+                FunctionCode::Label(label) => Some((label, function.name.clone())),
+                FunctionCode::Native(_) => None,
+            })
+            .collect();
+        Self {
+            names,
+            routines: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            allocations: HashMap::new(),
+            nodes: vec![Node {
+                label: None,
+                parent: 0,
+                children: HashMap::new(),
+                instructions: 0,
+            }],
+            active: Vec::new(),
+            running: HashMap::new(),
+            instructions: 0,
+        }
+    }
+
+    fn name(&self, label: u64) -> String {
+        self.names
+            .get(&label)
+            .cloned()
+            .unwrap_or_else(|| format!("L{label}"))
+    }
+
+    /// Label of the routine called `name`, with or without its module
+    fn label(&self, name: &str) -> Option<u64> {
+        self.names
+            .iter()
+            .find(|(_, routine)| {
+                *routine == name
+                    || routine
+                        .rsplit_once('.')
+                        .is_some_and(|(_, short)| short == name)
+            })
+            .map(|(&label, _)| label)
+    }
+
+    /// `None` if the routine was not called
+    #[must_use]
+    pub fn routine(&self, name: &str) -> Option<&RoutineProfile> {
+        self.routines.get(&self.label(name)?)
+    }
+
+    /// Times the loop at `label` in `routine` went round
+    #[must_use]
+    pub fn trips(&self, routine: &str, label: u64) -> u64 {
+        self.label(routine)
+            .and_then(|routine| self.loops.get(&(routine, label)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Records and arrays of the type allocated, also as default values of others
+    #[must_use]
+    pub fn allocations(&self, type_id: TypeId) -> u64 {
+        self.allocations.get(&type_id).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn instruction(&mut self) {
+        self.instructions += 1;
+        let node = self.active.last().map_or(0, |active| active.node);
+        self.nodes[node].instructions += 1;
+    }
+
+    pub(crate) fn enter(&mut self, label: u64) {
+        let parent = self.active.last().map_or(0, |active| active.node);
+        let node = if let Some(&node) = self.nodes[parent].children.get(&label) {
+            node
+        } else {
+            self.nodes.push(Node {
+                label: Some(label),
+                parent,
+                children: HashMap::new(),
+                instructions: 0,
+            });
+            let node = self.nodes.len() - 1;
+            let _: Option<usize> = self.nodes[parent].children.insert(label, node);
+            node
+        };
+        self.routines.entry(label).or_default().calls += 1;
+        *self.running.entry(label).or_default() += 1;
+        self.active.push(Active {
+            label,
+            node,
+            entered: Instant::now(),
+            instructions: self.instructions,
+            callee_time: Duration::ZERO,
+        });
+    }
+
+    pub(crate) fn leave(&mut self) {
+        let Some(active) = self.active.pop() else {
+            return;
+        };
+        let time = active.entered.elapsed();
+        if let Some(caller) = self.active.last_mut() {
+            caller.callee_time += time;
+        }
+        let routine = self.routines.entry(active.label).or_default();
+        routine.self_time += time.saturating_sub(active.callee_time);
+        let running = self.running.entry(active.label).or_default();
+        *running -= 1;
+        if *running == 0 {
+            routine.time += time;
+            routine.instructions += self.instructions - active.instructions;
+        }
+    }
+
+    /// The loop at `label` went round
+    pub(crate) fn trip(&mut self, label: u64) {
+        if let Some(active) = self.active.last() {
+            *self.loops.entry((active.label, label)).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn allocation(&mut self, type_id: TypeId) {
+        *self.allocations.entry(type_id).or_default() += 1;
+    }
+
+    /// Leaves the routines still running after a failure and sums
+    /// `RoutineProfile::self_instructions` up from the tree of calls
+    pub(crate) fn finish(&mut self) {
+        while !self.active.is_empty() {
+            self.leave();
+        }
+        for routine in self.routines.values_mut() {
+            routine.self_instructions = 0;
+        }
+        for node in &self.nodes {
+            if let Some(label) = node.label {
+                self.routines.entry(label).or_default().self_instructions += node.instructions;
+            }
+        }
+    }
+
+    /// Routines by the instructions they ran themselves, then loops and allocations,
+    /// each table sorted by the largest count
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut s = String::new();
+        let mut routines: Vec<_> = self
+            .routines
+            .iter()
+            .map(|(&label, routine)| (self.name(label), routine))
+            .collect();
+        routines.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| {
+            (rhs.self_instructions, lhs_name).cmp(&(lhs.self_instructions, rhs_name))
+        });
+        writeln!(
+            s,
+            "{:<32} {:>10} {:>14} {:>14} {:>12} {:>12}",
+            "routine", "calls", "instructions", "self", "time", "self time"
+        )
+        .expect("Writing to String won't fail");
+        for (name, routine) in routines {
+            writeln!(
+                s,
+                "{name:<32} {:>10} {:>14} {:>14} {:>12?} {:>12?}",
+                routine.calls,
+                routine.instructions,
+                routine.self_instructions,
+                routine.time,
+                routine.self_time
+            )
+            .expect("Writing to String won't fail");
+        }
+
+        let mut loops: Vec<_> = self
+            .loops
+            .iter()
+            .map(|(&(routine, label), &trips)| (format!("{} L{label}", self.name(routine)), trips))
+            .collect();
+        loops.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| (rhs, lhs_name).cmp(&(lhs, rhs_name)));
+        writeln!(s, "\n{:<32} {:>10}", "loop", "trips").expect("Writing to String won't fail");
+        for (name, trips) in loops {
+            writeln!(s, "{name:<32} {trips:>10}").expect("Writing to String won't fail");
+        }
+
+        let mut allocations: Vec<_> = self
+            .allocations
+            .iter()
+            .map(|(type_id, &count)| (type_id.0, count))
+            .collect();
+        allocations.sort_by(|(lhs_id, lhs), (rhs_id, rhs)| (rhs, lhs_id).cmp(&(lhs, rhs_id)));
+        writeln!(s, "\n{:<32} {:>10}", "allocated type", "objects")
+            .expect("Writing to String won't fail");
+        for (type_id, count) in allocations {
+            writeln!(s, "{:<32} {count:>10}", format!("type {type_id}"))
+                .expect("Writing to String won't fail");
+        }
+        s
+    }
+
+    /// A line `main;outer;inner count` for each stack of calls running instructions,
+    /// with the instructions run at its top, for flame graph tools like `inferno`
+    #[must_use]
+    pub fn collapsed(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.instructions == 0 || node.label.is_none() {
+                continue;
+            }
+            let mut names = Vec::new();
+            let mut at = index;
+            while let Some(label) = self.nodes[at].label {
+                names.push(self.name(label));
+                at = self.nodes[at].parent;
+            }
+            names.reverse();
+            lines.push(format!("{} {}\n", names.join(";"), node.instructions));
+        }
+        lines.sort();
+        lines.concat()
+    }
+}
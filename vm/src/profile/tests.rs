@@ -0,0 +1,275 @@
+use compiler::bytecode::{
+    ArrayRTTI, Bytecode, FunctionRecord, Location, Module, RTTI, RTTIElement, RecordRTTI,
+};
+use compiler::operators::SemanticBinaryOperator;
+
+use std::io;
+use std::path::Path;
+
+use super::*;
+use crate::test_support::linked;
+use crate::value::Value;
+use crate::{RuntimeError, Vm};
+
+const POINT: TypeId = TypeId(4);
+const POINTS: TypeId = TypeId(5);
+
+fn load(name: &str) -> Bytecode {
+    let loc = match name {
+        "i" => Location::Local(0),
+        "s" => Location::Local(1),
+        _ => Location::Argument(0),
+    };
+    Bytecode::Load { loc }
+}
+
+fn store(name: &str) -> Bytecode {
+    let loc = match name {
+        "i" => Location::Local(0),
+        _ => Location::Local(1),
+    };
+    Bytecode::Store { loc }
+}
+
+fn op(op: SemanticBinaryOperator) -> Bytecode {
+    Bytecode::BinOp { op }
+}
+
+/// `routine main() : integer is var t : array [3] point; var s is 0;`
+/// `for i in 1 .. 4 loop s := s + fact(i); end; return s; end;`
+/// `routine fact(n : integer) : integer is if n <= 1 then return 1; end; return n * fact(n - 1); end;`
+fn module() -> Module {
+    use SemanticBinaryOperator as Op;
+
+    let code = vec![
+        Bytecode::Label { id: 0 },
+        Bytecode::Enter { args: 0, locals: 2 },
+        Bytecode::AllocArray {
+            type_id: POINTS,
+            size: 3,
+        },
+        Bytecode::Drop,
+        Bytecode::IntConst { value: 1 },
+        store("i"),
+        Bytecode::Label { id: 1 },
+        load("i"),
+        Bytecode::IntConst { value: 4 },
+        op(Op::IntLe),
+        Bytecode::JumpZero { label: 2 },
+        load("s"),
+        load("i"),
+        Bytecode::Call { function_label: 3 },
+        op(Op::IntAdd),
+        store("s"),
+        load("i"),
+        Bytecode::IntConst { value: 1 },
+        op(Op::IntAdd),
+        store("i"),
+        Bytecode::Jump { label: 1 },
+        Bytecode::Label { id: 2 },
+        load("s"),
+        Bytecode::Ret,
+        Bytecode::Label { id: 3 },
+        Bytecode::Enter { args: 1, locals: 0 },
+        load("n"),
+        Bytecode::IntConst { value: 1 },
+        op(Op::IntLe),
+        Bytecode::JumpZero { label: 4 },
+        Bytecode::IntConst { value: 1 },
+        Bytecode::Ret,
+        Bytecode::Label { id: 4 },
+        load("n"),
+        load("n"),
+        Bytecode::IntConst { value: 1 },
+        op(Op::IntSub),
+        Bytecode::Call { function_label: 3 },
+        op(Op::IntMul),
+        Bytecode::Ret,
+    ];
+    let routine = |name: &str, label, args: &[TypeId]| FunctionRecord {
+        name: format!("test.{name}"),
+        code: FunctionCode::Label(label),
+        args: args.to_vec(),
+        result: TypeId::INTEGER,
+    };
+    let mut rtti = RTTI::primitives();
+    rtti.0.extend([
+        RTTIElement::Record(RecordRTTI {
+            id: POINT,
+            field_ids: vec![TypeId::REAL, TypeId::REAL],
+        }),
+        RTTIElement::Array(ArrayRTTI {
+            id: POINTS,
+            element_id: POINT,
+        }),
+    ]);
+    Module {
+        name: "test".to_owned(),
+        code,
+        label_count: 5,
+        functions: FunctionTable(vec![
+            routine("main", 0, &[]),
+            routine("fact", 3, &[TypeId::INTEGER]),
+        ]),
+        externs: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        rtti,
+        strings: Vec::new(),
+        global_count: 0,
+    }
+}
+
+fn program() -> Vm {
+    let mut vm = Vm::new();
+    vm.load(module()).expect("Module is well-formed");
+    vm
+}
+
+#[test]
+fn counts() {
+    let mut vm = program();
+    assert!(vm.profile().is_none());
+    vm.set_profiling(true);
+    assert_eq!(vm.call("main", &[]).ok(), Some(Value::Int(33)));
+    let profile = vm.profile().expect("Profiling");
+
+    let main = profile.routine("main").expect("Called");
+    let fact = profile.routine("test.fact").expect("Called");
+    assert_eq!((main.calls, fact.calls), (1, 10));
+    assert_eq!(main.instructions, vm.executed());
+    assert_eq!(
+        main.self_instructions + fact.self_instructions,
+        vm.executed()
+    );
+    // Only the outermost calls of the recursion count
+    assert_eq!(fact.instructions, fact.self_instructions);
+    assert!(main.time >= fact.time && main.time >= main.self_time);
+    assert_eq!(profile.trips("main", 1), 4);
+    // Jumps forward are no loops
+    assert_eq!(profile.trips("fact", 4), 0);
+    assert_eq!(profile.allocations(POINTS), 1);
+    assert_eq!(profile.allocations(POINT), 3);
+
+    let report = profile.report();
+    assert!(report.starts_with("routine "), "{report}");
+    assert!(report.contains("\ntest.main L1 "), "{report}");
+    assert!(report.contains("\ntype 4 "), "{report}");
+
+    let collapsed = profile.collapsed();
+    let stacks: Vec<_> = collapsed
+        .lines()
+        .map(|line| line.rsplit_once(' ').expect("Stack and count"))
+        .collect();
+    let fact = "test.main;test.fact";
+    assert_eq!(
+        stacks.iter().map(|(stack, _)| *stack).collect::<Vec<_>>(),
+        [
+            "test.main",
+            fact,
+            &format!("{fact};test.fact"),
+            &format!("{fact};test.fact;test.fact"),
+            &format!("{fact};test.fact;test.fact;test.fact"),
+        ]
+    );
+    let total: u64 = stacks
+        .iter()
+        .map(|(_, count)| count.parse::<u64>().expect("Counts are numbers"))
+        .sum();
+    assert_eq!(total, vm.executed());
+}
+
+#[test]
+fn failures_and_reloading() {
+    let mut vm = program();
+    vm.set_profiling(true);
+    assert!(matches!(
+        vm.call("fact", &[Value::Int(30)]),
+        Err(RuntimeError::IntegerOverflow)
+    ));
+    assert!(matches!(
+        vm.call("fact", &[Value::Int(3)]),
+        Ok(Value::Int(6))
+    ));
+    let profile = vm.profile().expect("Profiling");
+    let fact = profile.routine("fact").expect("Called");
+    assert_eq!(fact.calls, 30 + 3);
+    assert_eq!(fact.instructions, vm.executed());
+    assert!(profile.routine("main").is_none());
+
+    vm.load(module()).expect("Module is well-formed");
+    let profile = vm.profile().expect("Still profiling");
+    assert!(profile.routine("fact").is_none());
+
+    vm.set_profiling(false);
+    assert!(vm.profile().is_none());
+}
+
+/// `tests/src/for_loops.i` sorts an array and counts up and down in its routines
+#[test]
+fn compiled_program() {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/src/for_loops.i");
+    let mut vm = Vm::new();
+    vm.load(linked(&source)).expect("Module is well-formed");
+    vm.set_output(io::sink());
+    vm.set_profiling(true);
+    vm.run().expect("Runs");
+    let profile = vm.profile().expect("Profiling");
+
+    let [main, sort, bubble_sort, count, countdown] = [
+        "main",
+        "sort_and_print_reversed_array",
+        "bubble_sort",
+        "count",
+        "countdown",
+    ]
+    .map(|name| profile.routine(name).expect("Called"));
+    for routine in [main, sort, bubble_sort, count, countdown] {
+        assert_eq!(routine.calls, 1);
+    }
+    assert!(profile.routine("array_length").is_none());
+    assert_eq!(
+        [main, sort, bubble_sort, count, countdown]
+            .iter()
+            .map(|routine| routine.self_instructions)
+            .sum::<u64>(),
+        vm.executed()
+    );
+    assert_eq!(
+        sort.instructions,
+        sort.self_instructions + bubble_sort.instructions
+    );
+    // `countdown` is a tail call, it replaces `main` on the stack
+    assert_eq!(main.instructions + countdown.instructions, vm.executed());
+    // `array [5] integer`, the only type of the program
+    assert_eq!(profile.allocations(TypeId(4)), 1);
+
+    let report = profile.report();
+    let mut trips: Vec<u64> = report
+        .lines()
+        .filter_map(|line| line.strip_prefix("for_loops.bubble_sort L"))
+        .map(|line| {
+            let (_, trips) = line.rsplit_once(' ').expect("Loop and trips");
+            trips.parse().expect("Trips are numbers")
+        })
+        .collect();
+    trips.sort_unstable();
+    // The outer loop and the inner one over the elements before the current one
+    assert_eq!(trips, [5, 1 + 2 + 3 + 4], "{report}");
+
+    let collapsed = profile.collapsed();
+    let stacks: Vec<_> = collapsed
+        .lines()
+        .map(|line| line.rsplit_once(' ').expect("Stack and count").0)
+        .collect();
+    assert_eq!(
+        stacks,
+        [
+            "for_loops.countdown",
+            "for_loops.main",
+            "for_loops.main;for_loops.count",
+            "for_loops.main;for_loops.sort_and_print_reversed_array",
+            "for_loops.main;for_loops.sort_and_print_reversed_array;for_loops.bubble_sort",
+        ]
+    );
+}
@@ -0,0 +1,291 @@
+//! Modules prepared for execution
+
+use core::error::Error;
+use core::fmt;
+use std::collections::HashMap;
+
+use compiler::builtins::Builtin;
+use compiler::bytecode::{Bytecode, FunctionCode, FunctionRecord, INIT, Module};
+use compiler::registers::{self, Instruction};
+
+use crate::host::{HostFunctions, Signature, TypeId};
+use crate::value::{Heap, Object, ObjectRef};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// Modules have to be linked before they are run
+    UnresolvedExtern {
+        name: String,
+    },
+    DuplicateLabel {
+        label: u64,
+    },
+    UndefinedLabel {
+        label: u64,
+    },
+    UnknownNative {
+        id: u32,
+    },
+    UnresolvedHostFunction {
+        name: String,
+    },
+    /// The host function takes or returns other types than the program declares
+    HostSignature {
+        name: String,
+    },
+    UndefinedString {
+        id: u32,
+    },
+    /// Register code comes after the program it runs
+    NotLoaded,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::UnresolvedExtern { name } => {
+                write!(f, "`{name}` of another module is not linked")
+            }
+            LoadError::DuplicateLabel { label } => write!(f, "Label {label} is placed twice"),
+            LoadError::UndefinedLabel { label } => write!(f, "Label {label} is not placed"),
+            LoadError::UnknownNative { id } => write!(f, "No native routine with id {id}"),
+            LoadError::UnresolvedHostFunction { name } => {
+                write!(f, "Host function `{name}` is not registered")
+            }
+            LoadError::HostSignature { name } => write!(
+                f,
+                "Host function `{name}` is registered with another signature than declared"
+            ),
+            LoadError::UndefinedString { id } => write!(f, "No string literal with id {id}"),
+            LoadError::NotLoaded => write!(f, "No program is loaded"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+#[derive(Debug)]
+pub(crate) struct Program {
+    pub(crate) module: Module,
+    /// Label ids to indices of their `Bytecode::Label`s in the code
+    labels: HashMap<u64, usize>,
+    /// `Bytecode::CallNative` ids past the built-ins to indices in `HostFunctions`
+    hosts: HashMap<u32, usize>,
+    /// `Module::strings` allocated on the heap
+    strings: Vec<ObjectRef>,
+}
+
+fn jump_target(instruction: &Bytecode) -> Option<u64> {
+    match *instruction {
+        Bytecode::Jump { label }
+        | Bytecode::JumpZero { label }
+        | Bytecode::JumpNotZero { label }
+        | Bytecode::Call {
+            function_label: label,
+        }
+        | Bytecode::TailCall {
+            function_label: label,
+        } => Some(label),
+        Bytecode::IntConst { .. }
+        | Bytecode::RealConst { .. }
+        | Bytecode::StringConst { .. }
+        | Bytecode::Load { .. }
+        | Bytecode::Store { .. }
+        | Bytecode::AddressOf { .. }
+        | Bytecode::Dup
+        | Bytecode::Drop
+        | Bytecode::Swap
+        | Bytecode::BinOp { .. }
+        | Bytecode::UnOp { .. }
+        | Bytecode::StoreAddress
+        | Bytecode::LoadAddress
+        | Bytecode::AllocRecord { .. }
+        | Bytecode::AllocArray { .. }
+        | Bytecode::ArraySize
+        | Bytecode::ElementAddress
+        | Bytecode::FieldAddress { .. }
+        | Bytecode::Label { .. }
+        | Bytecode::Enter { .. }
+        | Bytecode::Ret
+        | Bytecode::CallNative { .. }
+        | Bytecode::Print { .. }
+        | Bytecode::Panic { .. }
+        | Bytecode::IntToBool
+        | Bytecode::RealToInt
+        | Bytecode::IntToReal => None,
+    }
+}
+
+fn unqualified<'a>(module: &str, name: &'a str) -> &'a str {
+    name.strip_prefix(module)
+        .and_then(|name| name.strip_prefix('.'))
+        .unwrap_or(name)
+}
+
+impl Program {
+    /// Checks that every label, native routine and string literal the code refers to exists,
+    /// allocates the literals
+    pub(crate) fn load(
+        module: Module,
+        host_functions: &HostFunctions,
+        heap: &mut Heap,
+    ) -> Result<Self, LoadError> {
+        if let Some(function) = module.externs.first() {
+            return Err(LoadError::UnresolvedExtern {
+                name: function.name.clone(),
+            });
+        }
+        if let Some(symbol) = module.imports.first() {
+            return Err(LoadError::UnresolvedExtern {
+                name: symbol.name().to_owned(),
+            });
+        }
+
+        let mut labels = HashMap::new();
+        for (pc, instruction) in module.code.iter().enumerate() {
+            if let Bytecode::Label { id } = *instruction
+                && labels.insert(id, pc).is_some()
+            {
+                return Err(LoadError::DuplicateLabel { label: id });
+            }
+        }
+        let functions = module
+            .functions
+            .0
+            .iter()
+            .filter_map(|function| match function.code {
+                FunctionCode::Label(label) => Some(label),
+                FunctionCode::Native(_) => None,
+            });
+        for label in module.code.iter().filter_map(jump_target).chain(functions) {
+            if !labels.contains_key(&label) {
+                return Err(LoadError::UndefinedLabel { label });
+            }
+        }
+
+        let mut hosts = HashMap::new();
+        for function in &module.functions.0 {
+            if let FunctionCode::Native(id) = function.code
+                && Builtin::from_id(id).is_none()
+            {
+                let index = host_functions.lookup(&function.name).ok_or_else(|| {
+                    LoadError::UnresolvedHostFunction {
+                        name: function.name.clone(),
+                    }
+                })?;
+                // Procedures are declared to return integer 0
+                let Signature { args, result } = &host_functions
+                    .get(index)
+                    .expect("Looked up by now")
+                    .signature;
+                if *args != function.args || result.unwrap_or(TypeId::INTEGER) != function.result {
+                    return Err(LoadError::HostSignature {
+                        name: function.name.clone(),
+                    });
+                }
+                let _: Option<usize> = hosts.insert(id, index);
+            }
+        }
+        for instruction in &module.code {
+            if let Bytecode::CallNative { id } = *instruction
+                && Builtin::from_id(id).is_none()
+                && !hosts.contains_key(&id)
+            {
+                return Err(LoadError::UnknownNative { id });
+            }
+            if let Bytecode::StringConst { id } = *instruction
+                && usize::try_from(id).map_or(true, |id| id >= module.strings.len())
+            {
+                return Err(LoadError::UndefinedString { id });
+            }
+        }
+
+        let strings = module
+            .strings
+            .iter()
+            .map(|string| heap.alloc(Object::String(string.clone())))
+            .collect();
+        Ok(Self {
+            module,
+            labels,
+            hosts,
+            strings,
+        })
+    }
+
+    /// Checks that register code has every routine of the program, and that the routines,
+    /// native routines and string literals it refers to exist
+    pub(crate) fn check_registers(&self, code: &registers::Code) -> Result<(), LoadError> {
+        for function in &self.module.functions.0 {
+            if let FunctionCode::Label(label) = function.code
+                && !code.0.contains_key(&label)
+            {
+                return Err(LoadError::UndefinedLabel { label });
+            }
+        }
+        for instruction in code.0.values().flat_map(|routine| &routine.code) {
+            if let Instruction::Call { function_label, .. } = *instruction
+                && !code.0.contains_key(&function_label)
+            {
+                return Err(LoadError::UndefinedLabel {
+                    label: function_label,
+                });
+            }
+            if let Instruction::CallNative { id, .. } = *instruction
+                && Builtin::from_id(id).is_none()
+                && !self.hosts.contains_key(&id)
+            {
+                return Err(LoadError::UnknownNative { id });
+            }
+            if let Instruction::String { id, .. } = *instruction
+                && usize::try_from(id).map_or(true, |id| id >= self.strings.len())
+            {
+                return Err(LoadError::UndefinedString { id });
+            }
+        }
+        Ok(())
+    }
+
+    /// Loaded labels are always placed
+    pub(crate) fn label(&self, label: u64) -> usize {
+        self.labels[&label]
+    }
+
+    /// Loaded ids are always resolved
+    pub(crate) fn host(&self, id: u32) -> usize {
+        self.hosts[&id]
+    }
+
+    /// Literals stay allocated while the program is loaded
+    pub(crate) fn strings(&self) -> &[ObjectRef] {
+        &self.strings
+    }
+
+    /// Loaded ids are always in bounds
+    pub(crate) fn string(&self, id: u32) -> ObjectRef {
+        self.strings[id as usize]
+    }
+
+    /// Routines can be named with or without the module, `main` is the same as `program.main`
+    pub(crate) fn routine(&self, name: &str) -> Option<&FunctionRecord> {
+        let module = self.module.name.as_str();
+        self.module.functions.0.iter().find(|function| {
+            matches!(function.code, FunctionCode::Label(_))
+                && unqualified(module, &function.name) == unqualified(module, name)
+        })
+    }
+
+    /// Labels of the `INIT` routines, in the order the linker put the modules in
+    pub(crate) fn inits(&self) -> impl Iterator<Item = u64> {
+        self.module
+            .functions
+            .0
+            .iter()
+            .filter_map(|function| match function.code {
+                FunctionCode::Label(label) if function.name.rsplit('.').next() == Some(INIT) => {
+                    Some(label)
+                }
+                FunctionCode::Label(_) | FunctionCode::Native(_) => None,
+            })
+    }
+}
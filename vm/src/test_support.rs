@@ -0,0 +1,133 @@
+//! Programs shared by the tests of the backends
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use compiler::bytecode::Module;
+#[cfg(feature = "jit")]
+use compiler::bytecode::linker::link_registers;
+use compiler::bytecode::linker::{link, link_ir};
+use compiler::ir;
+use compiler::modules::{self, SearchPath};
+#[cfg(feature = "jit")]
+use compiler::registers;
+use compiler::{Options, TypedProgram};
+
+use crate::Vm;
+use crate::console::Capture;
+
+/// Checks a single module
+///
+/// # Panics
+///
+/// If it is not a valid program
+pub(crate) fn checked(source: &str) -> TypedProgram {
+    let program = compiler::parse(&compiler::lex(source)).expect("Parses");
+    compiler::check(program, &Options::default()).expect("Type checks")
+}
+
+/// Compiles and links a single module
+///
+/// # Panics
+///
+/// If it is not a valid program
+pub(crate) fn compiled(source: &str) -> Module {
+    link(vec![compiler::compile(&checked(source))]).expect("Links")
+}
+
+/// Checks the module at `path` and the ones it imports, the imported ones first
+///
+/// # Panics
+///
+/// If it is not a valid program
+fn checked_modules(path: &Path) -> Vec<TypedProgram> {
+    let mut checked = Vec::new();
+    for module in modules::load(path, &SearchPath::default()).expect("Modules are there") {
+        let program =
+            compiler::check_module(module.program, &module.name, &checked, &Options::default())
+                .expect("Type checks");
+        checked.push(program);
+    }
+    checked
+}
+
+/// Compiles the module at `path` with the ones it imports and links them
+///
+/// # Panics
+///
+/// If it is not a valid program
+pub(crate) fn linked(path: &Path) -> Module {
+    link(
+        checked_modules(path)
+            .iter()
+            .map(compiler::compile)
+            .collect(),
+    )
+    .expect("Links")
+}
+
+/// Like `linked`, with the linked SSA routines of the modules for the backends of `compiler::ir`
+///
+/// # Panics
+///
+/// If it is not a valid program
+pub(crate) fn built(path: &Path) -> (Module, BTreeMap<u64, ir::Function>) {
+    build(&checked_modules(path))
+}
+
+/// `built` of a single module
+///
+/// # Panics
+///
+/// If it is not a valid program
+pub(crate) fn built_source(source: &str) -> (Module, BTreeMap<u64, ir::Function>) {
+    build(&[checked(source)])
+}
+
+fn build(checked: &[TypedProgram]) -> (Module, BTreeMap<u64, ir::Function>) {
+    let units: Vec<_> = checked.iter().map(compiler::compile).collect();
+    let routines = link_ir(&units, checked.iter().map(ir::build).collect()).expect("Links");
+    (link(units).expect("Links"), routines)
+}
+
+/// Like `linked`, with the linked register code of the modules
+///
+/// # Panics
+///
+/// If it is not a valid program
+#[cfg(feature = "jit")]
+pub(crate) fn lowered(path: &Path) -> (Module, registers::Code) {
+    let checked = checked_modules(path);
+    let units: Vec<_> = checked.iter().map(compiler::compile).collect();
+    let code = checked
+        .iter()
+        .map(|program| compiler::lower_registers(ir::build(program), &Options::default()))
+        .collect();
+    let code = link_registers(&units, code).expect("Links");
+    (link(units).expect("Links"), code)
+}
+
+/// What `main` of the program prints on the stack machine
+///
+/// # Panics
+///
+/// If the program fails
+pub(crate) fn output(module: Module) -> String {
+    interpreted(module, "")
+}
+
+/// What `main` of the program prints on the stack machine reading `input`
+///
+/// # Panics
+///
+/// If the program fails
+pub(crate) fn interpreted(module: Module, input: &str) -> String {
+    let mut vm = Vm::new();
+    vm.load(module).expect("Module is well-formed");
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    vm.set_input(Cursor::new(input.to_owned()));
+    vm.run().expect("Runs");
+    String::from_utf8(output.contents()).expect("UTF-8")
+}
@@ -0,0 +1,519 @@
+//! Values the VM operates on and their conversions from and to Rust types
+
+use core::error::Error;
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+
+use compiler::bytecode::{ArrayRTTI, PrimitiveRTTI, RTTI, RTTIElement, RecordRTTI, TypeId};
+
+#[cfg(test)]
+mod tests;
+
+/// Index of an object in the `Heap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectRef(usize);
+
+impl ObjectRef {
+    /// Recursive record fields refer to no object until they are assigned
+    pub const NULL: ObjectRef = ObjectRef(usize::MAX);
+}
+
+#[cfg(feature = "jit")]
+impl ObjectRef {
+    /// Native code passes references around as their indices
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Self(index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Real(f64),
+    Bool(bool),
+    Ref(ObjectRef),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Real(_) => "real",
+            Value::Bool(_) => "boolean",
+            Value::Ref(_) => "reference",
+        }
+    }
+
+    /// Null references refer to no object
+    fn object(self) -> Option<ObjectRef> {
+        match self {
+            Value::Ref(object) if object == ObjectRef::NULL => None,
+            Value::Ref(object) => Some(object),
+            Value::Int(_) | Value::Real(_) | Value::Bool(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Array(Vec<Value>),
+    Record(Vec<Value>),
+    /// Immutable, operations on strings allocate new ones
+    String(String),
+}
+
+/// Allocations between collections when few objects are alive
+const MIN_THRESHOLD: usize = 1024;
+
+/// Objects unreachable from the roots given to `collect` are freed, their slots are reused
+#[derive(Debug)]
+pub struct Heap {
+    /// `None` are free
+    objects: Vec<Option<Object>>,
+    /// Of the objects allocated by the program, the host does not say
+    types: Vec<Option<TypeId>>,
+    free: Vec<usize>,
+    /// Since the last collection
+    allocated: usize,
+    /// Of `allocated` when the next collection is due
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            types: Vec::new(),
+            free: Vec::new(),
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, object: Object) -> ObjectRef {
+        self.alloc_as(object, None)
+    }
+
+    /// Records and arrays of the type `type_id`, which `deep_eq` compares by
+    pub fn alloc_typed(&mut self, object: Object, type_id: TypeId) -> ObjectRef {
+        self.alloc_as(object, Some(type_id))
+    }
+
+    fn alloc_as(&mut self, object: Object, type_id: Option<TypeId>) -> ObjectRef {
+        self.allocated += 1;
+        if let Some(index) = self.free.pop() {
+            self.objects[index] = Some(object);
+            self.types[index] = type_id;
+            return ObjectRef(index);
+        }
+        self.objects.push(Some(object));
+        self.types.push(type_id);
+        ObjectRef(self.objects.len() - 1)
+    }
+
+    /// # Panics
+    /// If the reference is null or the object was collected,
+    /// references the program can reach never are collected
+    #[must_use]
+    pub fn get(&self, object: ObjectRef) -> &Object {
+        self.objects
+            .get(object.0)
+            .and_then(Option::as_ref)
+            .expect("Null and collected objects are not used")
+    }
+
+    /// # Panics
+    /// If the reference is null or the object was collected,
+    /// references the program can reach never are collected
+    pub fn get_mut(&mut self, object: ObjectRef) -> &mut Object {
+        self.objects
+            .get_mut(object.0)
+            .and_then(Option::as_mut)
+            .expect("Null and collected objects are not used")
+    }
+
+    /// What the object was allocated as by `alloc_typed`
+    #[must_use]
+    pub fn type_of(&self, object: ObjectRef) -> Option<TypeId> {
+        self.types.get(object.0).copied().flatten()
+    }
+
+    /// Objects not collected yet
+    #[must_use]
+    pub fn live(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /// Enough was allocated since the last collection to make another one worth it
+    #[must_use]
+    pub fn should_collect(&self) -> bool {
+        self.allocated >= self.threshold
+    }
+
+    /// Frees the objects `roots` do not refer to, directly or through other objects,
+    /// returns how many were freed. References to freed objects must not be used afterwards.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>) -> usize {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<_> = roots.into_iter().filter_map(Value::object).collect();
+        while let Some(object) = pending.pop() {
+            if core::mem::replace(&mut marked[object.0], true) {
+                continue;
+            }
+            match self.get(object) {
+                Object::Array(values) | Object::Record(values) => {
+                    pending.extend(values.iter().copied().filter_map(Value::object));
+                }
+                Object::String(_) => {}
+            }
+        }
+
+        let mut freed = 0;
+        for (index, (object, marked)) in self.objects.iter_mut().zip(marked).enumerate() {
+            if !marked && object.take().is_some() {
+                self.free.push(index);
+                freed += 1;
+            }
+        }
+        self.allocated = 0;
+        self.threshold = (2 * self.live()).max(MIN_THRESHOLD);
+        freed
+    }
+
+    /// Copies records and arrays with everything they refer to, keeping shared and cyclic
+    /// references shared and cyclic in the copy. Strings are immutable, so they are not copied,
+    /// neither are null references.
+    pub fn deep_copy(&mut self, value: Value) -> Value {
+        self.copy_value(value, &mut HashMap::new())
+    }
+
+    /// `copies` maps copied objects to their copies
+    fn copy_value(&mut self, value: Value, copies: &mut HashMap<ObjectRef, ObjectRef>) -> Value {
+        let Some(object) = value.object() else {
+            return value;
+        };
+        if let Some(&copy) = copies.get(&object) {
+            return Value::Ref(copy);
+        }
+        let (values, copy) = match self.get(object) {
+            Object::String(_) => return value,
+            Object::Array(values) => (values.clone(), Object::Array(Vec::new())),
+            Object::Record(values) => (values.clone(), Object::Record(Vec::new())),
+        };
+        // Allocated before the contents, so that cycles lead to it
+        let copy = self.alloc_as(copy, self.type_of(object));
+        let _: Option<ObjectRef> = copies.insert(object, copy);
+        let values = values
+            .into_iter()
+            .map(|value| self.copy_value(value, copies))
+            .collect();
+        match self.get_mut(copy) {
+            Object::Array(contents) | Object::Record(contents) => *contents = values,
+            Object::String(_) => unreachable!("Allocated as an array or a record"),
+        }
+        Value::Ref(copy)
+    }
+
+    /// Compares records, arrays and strings by contents, recursively, walking the types
+    /// of `rtti` like compiled code does. Reals are equal as numbers, null references
+    /// only to null references. Objects the host allocated have no type,
+    /// their values are compared as they are.
+    #[must_use]
+    pub fn deep_eq(&self, lhs: Value, rhs: Value, rtti: &RTTI) -> bool {
+        match (lhs, rhs) {
+            (Value::Ref(lhs), Value::Ref(rhs)) => {
+                self.eq_objects(lhs, rhs, rtti, &mut HashSet::new())
+            }
+            _ => lhs == rhs,
+        }
+    }
+
+    /// Values of the type `type_id`, `None` if it is unknown
+    fn eq_values(
+        &self,
+        (lhs, rhs): (Value, Value),
+        type_id: Option<TypeId>,
+        rtti: &RTTI,
+        assumed: &mut HashSet<(ObjectRef, ObjectRef)>,
+    ) -> bool {
+        match (type_id.and_then(|type_id| rtti.get(type_id)), lhs, rhs) {
+            (
+                Some(RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::REAL })),
+                Value::Real(lhs),
+                Value::Real(rhs),
+            ) => lhs == rhs,
+            (
+                Some(
+                    RTTIElement::Primitive(PrimitiveRTTI { id: TypeId::STRING })
+                    | RTTIElement::Record(_)
+                    | RTTIElement::Array(_),
+                )
+                | None,
+                Value::Ref(lhs),
+                Value::Ref(rhs),
+            ) => self.eq_objects(lhs, rhs, rtti, assumed),
+            _ => lhs == rhs,
+        }
+    }
+
+    /// Pairs in `assumed` are being compared already, so cycles are taken as equal
+    fn eq_objects(
+        &self,
+        lhs: ObjectRef,
+        rhs: ObjectRef,
+        rtti: &RTTI,
+        assumed: &mut HashSet<(ObjectRef, ObjectRef)>,
+    ) -> bool {
+        if lhs == rhs || !assumed.insert((lhs, rhs)) {
+            return true;
+        }
+        if lhs == ObjectRef::NULL || rhs == ObjectRef::NULL {
+            return false;
+        }
+        let rtti_element = self
+            .type_of(lhs)
+            .or(self.type_of(rhs))
+            .and_then(|type_id| rtti.get(type_id));
+        let (types, lhs, rhs): (&dyn Fn(usize) -> Option<TypeId>, _, _) =
+            match (self.get(lhs), self.get(rhs), rtti_element) {
+                (Object::String(lhs), Object::String(rhs), _) => return lhs == rhs,
+                (
+                    Object::Record(lhs),
+                    Object::Record(rhs),
+                    Some(RTTIElement::Record(RecordRTTI { field_ids, .. })),
+                ) => (&|index| field_ids.get(index).copied(), lhs, rhs),
+                (
+                    Object::Array(lhs),
+                    Object::Array(rhs),
+                    Some(&RTTIElement::Array(ArrayRTTI { element_id, .. })),
+                ) => (&move |_| Some(element_id), lhs, rhs),
+                (Object::Record(lhs), Object::Record(rhs), _)
+                | (Object::Array(lhs), Object::Array(rhs), _) => (&|_| None, lhs, rhs),
+                (Object::String(_) | Object::Array(_) | Object::Record(_), _, _) => return false,
+            };
+        lhs.len() == rhs.len()
+            && lhs
+                .iter()
+                .zip(rhs)
+                .enumerate()
+                .all(|(index, (&lhs, &rhs))| {
+                    self.eq_values((lhs, rhs), types(index), rtti, assumed)
+                })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConversionError {
+    UnexpectedValue {
+        expected: &'static str,
+        found: &'static str,
+    },
+    UnexpectedObject {
+        expected: &'static str,
+    },
+    FieldCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnexpectedValue { expected, found } => {
+                write!(f, "Expected {expected}, found {found}")
+            }
+            ConversionError::UnexpectedObject { expected } => {
+                write!(f, "Expected a reference to {expected}")
+            }
+            ConversionError::FieldCount { expected, found } => {
+                write!(f, "Expected a record with {expected} fields, found {found}")
+            }
+        }
+    }
+}
+
+impl Error for ConversionError {}
+
+/// Rust values which can be passed to the VM
+pub trait ToValue {
+    fn to_value(self, heap: &mut Heap) -> Value;
+}
+
+/// Rust values which can be read from the VM
+pub trait FromValue: Sized {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, ConversionError>;
+}
+
+impl ToValue for Value {
+    fn to_value(self, _heap: &mut Heap) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value, _heap: &Heap) -> Result<Self, ConversionError> {
+        Ok(value)
+    }
+}
+
+macro_rules! primitive {
+    ($($t:ty => $variant:ident($name:literal)),+ $(,)?) => {
+        $(
+            impl ToValue for $t {
+                fn to_value(self, _heap: &mut Heap) -> Value {
+                    Value::$variant(self)
+                }
+            }
+
+            impl FromValue for $t {
+                fn from_value(value: Value, _heap: &Heap) -> Result<Self, ConversionError> {
+                    if let Value::$variant(value) = value {
+                        Ok(value)
+                    } else {
+                        Err(ConversionError::UnexpectedValue {
+                            expected: $name,
+                            found: value.kind(),
+                        })
+                    }
+                }
+            }
+        )+
+    };
+}
+
+primitive! {
+    i64 => Int("integer"),
+    f64 => Real("real"),
+}
+
+impl ToValue for bool {
+    fn to_value(self, _heap: &mut Heap) -> Value {
+        Value::Bool(self)
+    }
+}
+
+/// The VM keeps booleans stored in arrays and records as integers 0 and 1
+impl FromValue for bool {
+    fn from_value(value: Value, _heap: &Heap) -> Result<Self, ConversionError> {
+        match value {
+            Value::Bool(value) => Ok(value),
+            Value::Int(0) => Ok(false),
+            Value::Int(1) => Ok(true),
+            Value::Int(_) | Value::Real(_) | Value::Ref(_) => {
+                Err(ConversionError::UnexpectedValue {
+                    expected: "boolean",
+                    found: value.kind(),
+                })
+            }
+        }
+    }
+}
+
+fn object_ref(value: Value, expected: &'static str) -> Result<ObjectRef, ConversionError> {
+    match value {
+        Value::Ref(ObjectRef::NULL) => Err(ConversionError::UnexpectedObject { expected }),
+        Value::Ref(object) => Ok(object),
+        Value::Int(_) | Value::Real(_) | Value::Bool(_) => Err(ConversionError::UnexpectedValue {
+            expected,
+            found: value.kind(),
+        }),
+    }
+}
+
+impl ToValue for String {
+    fn to_value(self, heap: &mut Heap) -> Value {
+        Value::Ref(heap.alloc(Object::String(self)))
+    }
+}
+
+impl ToValue for &str {
+    fn to_value(self, heap: &mut Heap) -> Value {
+        self.to_owned().to_value(heap)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, ConversionError> {
+        match heap.get(object_ref(value, "string")?) {
+            Object::String(string) => Ok(string.clone()),
+            Object::Array(_) | Object::Record(_) => {
+                Err(ConversionError::UnexpectedObject { expected: "string" })
+            }
+        }
+    }
+}
+
+/// Arrays
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(self, heap: &mut Heap) -> Value {
+        let elements = self
+            .into_iter()
+            .map(|element| element.to_value(heap))
+            .collect();
+        Value::Ref(heap.alloc(Object::Array(elements)))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, ConversionError> {
+        match heap.get(object_ref(value, "array")?) {
+            Object::Array(elements) => elements
+                .iter()
+                .map(|&element| T::from_value(element, heap))
+                .collect(),
+            Object::Record(_) | Object::String(_) => {
+                Err(ConversionError::UnexpectedObject { expected: "array" })
+            }
+        }
+    }
+}
+
+/// Records, fields go in the order of declaration
+macro_rules! record {
+    ($($count:literal => ($($field:ident $value:ident),+)),+ $(,)?) => {
+        $(
+            impl<$($field: ToValue),+> ToValue for ($($field,)+) {
+                fn to_value(self, heap: &mut Heap) -> Value {
+                    let ($($value,)+) = self;
+                    let fields = vec![$($value.to_value(heap)),+];
+                    Value::Ref(heap.alloc(Object::Record(fields)))
+                }
+            }
+
+            impl<$($field: FromValue),+> FromValue for ($($field,)+) {
+                fn from_value(value: Value, heap: &Heap) -> Result<Self, ConversionError> {
+                    let fields = match heap.get(object_ref(value, "record")?) {
+                        Object::Record(fields) => fields,
+                        Object::Array(_) | Object::String(_) => {
+                            return Err(ConversionError::UnexpectedObject { expected: "record" })
+                        }
+                    };
+                    if fields.len() != $count {
+                        return Err(ConversionError::FieldCount {
+                            expected: $count,
+                            found: fields.len(),
+                        });
+                    }
+                    let mut fields = fields.iter();
+                    Ok(($($field::from_value(
+                        *fields.next().expect("Field count is checked"),
+                        heap,
+                    )?,)+))
+                }
+            }
+        )+
+    };
+}
+
+record! {
+    1 => (A a),
+    2 => (A a, B b),
+    3 => (A a, B b, C c),
+    4 => (A a, B b, C c, D d),
+}
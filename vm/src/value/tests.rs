@@ -0,0 +1,146 @@
+use super::*;
+
+/// `type node is record var value : integer; var next : node; end;`, the id of `node`
+const NODE: TypeId = TypeId(4);
+
+/// `RTTI::primitives` and `node`
+fn rtti() -> RTTI {
+    let mut rtti = RTTI::primitives();
+    rtti.0.push(RTTIElement::Record(RecordRTTI {
+        id: NODE,
+        field_ids: vec![TypeId::INTEGER, NODE],
+    }));
+    rtti
+}
+
+/// With a null `next`
+fn node(heap: &mut Heap, value: i64) -> ObjectRef {
+    heap.alloc_typed(
+        Object::Record(vec![Value::Int(value), Value::Ref(ObjectRef::NULL)]),
+        NODE,
+    )
+}
+
+fn link(heap: &mut Heap, from: ObjectRef, to: ObjectRef) {
+    let Object::Record(fields) = heap.get_mut(from) else {
+        unreachable!("Nodes are records")
+    };
+    fields[1] = Value::Ref(to);
+}
+
+#[test]
+fn deep_copy_is_independent() {
+    let mut heap = Heap::default();
+    let points = vec![(1.0_f64, 2.0_f64), (3.0, 4.0)].to_value(&mut heap);
+    let copy = heap.deep_copy(points);
+    assert_ne!(copy, points);
+    assert!(heap.deep_eq(copy, points, &RTTI::primitives()));
+
+    let Value::Ref(copy_ref) = copy else {
+        unreachable!("Copy of an array")
+    };
+    let Object::Array(elements) = heap.get(copy_ref) else {
+        unreachable!("Copy of an array")
+    };
+    let Value::Ref(first) = elements[0] else {
+        unreachable!("Points are records")
+    };
+    let Object::Record(fields) = heap.get_mut(first) else {
+        unreachable!("Points are records")
+    };
+    fields[0] = Value::Real(5.0);
+    assert!(!heap.deep_eq(copy, points, &RTTI::primitives()));
+    assert_eq!(
+        <Vec<(f64, f64)>>::from_value(points, &heap),
+        Ok(vec![(1.0, 2.0), (3.0, 4.0)])
+    );
+}
+
+#[test]
+fn cycles() {
+    let mut heap = Heap::default();
+    let a = node(&mut heap, 1);
+    let b = node(&mut heap, 2);
+    link(&mut heap, a, b);
+    link(&mut heap, b, a);
+
+    let Value::Ref(copy) = heap.deep_copy(Value::Ref(a)) else {
+        unreachable!("Copy of a record")
+    };
+    assert!(heap.deep_eq(Value::Ref(copy), Value::Ref(a), &rtti()));
+    assert!(!heap.deep_eq(Value::Ref(copy), Value::Ref(b), &rtti()));
+    let next = |heap: &Heap, node: ObjectRef| match heap.get(node) {
+        Object::Record(fields) => fields[1],
+        Object::Array(_) | Object::String(_) => unreachable!("Nodes are records"),
+    };
+    let Value::Ref(copied_b) = next(&heap, copy) else {
+        unreachable!("Linked")
+    };
+    assert_ne!(copied_b, b);
+    assert_eq!(next(&heap, copied_b), Value::Ref(copy));
+}
+
+/// Fields are compared by their types, null references only equal null references
+#[test]
+fn null_references() {
+    let mut heap = Heap::default();
+    let (a, b, c) = (node(&mut heap, 1), node(&mut heap, 1), node(&mut heap, 2));
+    assert!(heap.deep_eq(Value::Ref(a), Value::Ref(b), &rtti()));
+    link(&mut heap, b, c);
+    assert!(!heap.deep_eq(Value::Ref(a), Value::Ref(b), &rtti()));
+    assert!(!heap.deep_eq(Value::Ref(b), Value::Ref(a), &rtti()));
+    let copy = heap.deep_copy(Value::Ref(b));
+    assert_eq!(
+        heap.type_of(copy.object().expect("Copy of a record")),
+        Some(NODE)
+    );
+    assert_eq!(
+        heap.deep_copy(Value::Ref(ObjectRef::NULL)),
+        Value::Ref(ObjectRef::NULL)
+    );
+    assert_eq!(
+        heap.collect([Value::Ref(ObjectRef::NULL), Value::Ref(a)]),
+        4
+    );
+}
+
+#[test]
+fn strings_and_kinds() {
+    let mut heap = Heap::default();
+    let hello = "hello".to_value(&mut heap);
+    let other = "hello".to_value(&mut heap);
+    assert!(heap.deep_eq(hello, other, &RTTI::primitives()));
+    assert_eq!(heap.deep_copy(hello), hello);
+
+    let array = vec![1_i64].to_value(&mut heap);
+    let record = (1_i64,).to_value(&mut heap);
+    assert!(!heap.deep_eq(array, record, &RTTI::primitives()));
+    assert!(!heap.deep_eq(
+        Value::Real(f64::NAN),
+        Value::Real(f64::NAN),
+        &RTTI::primitives()
+    ));
+}
+
+#[test]
+fn collection() {
+    let mut heap = Heap::default();
+    let a = node(&mut heap, 1);
+    let b = node(&mut heap, 2);
+    link(&mut heap, a, b);
+    link(&mut heap, b, a);
+    let unreachable = node(&mut heap, 3);
+    link(&mut heap, unreachable, unreachable);
+    let _: Value = "garbage".to_value(&mut heap);
+
+    assert_eq!(heap.collect([Value::Int(7), Value::Ref(b)]), 2);
+    assert_eq!(heap.live(), 2);
+    let copy = heap.deep_copy(Value::Ref(a));
+    assert!(heap.deep_eq(Value::Ref(a), copy, &rtti()));
+    // The copies of both nodes, their slots are reused
+    assert_eq!(heap.collect([Value::Ref(a)]), 2);
+    assert!(node(&mut heap, 4).0 < 6);
+    assert_eq!(heap.live(), 3);
+    assert_eq!(heap.collect([]), 3);
+    assert_eq!(heap.live(), 0);
+}
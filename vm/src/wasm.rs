@@ -0,0 +1,676 @@
+//! Runtime of the WebAssembly modules of `compiler::wasm`, which are run by `wasmi`.
+//!
+//! The host allocates objects in the module's memory, prints them by the table of types at its
+//! start and implements the built-ins like the `vm` does, numeric ones by calling its natives.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+
+use compiler::builtins::Builtin;
+use compiler::bytecode::{RTTI, TypeId};
+use compiler::wasm::{CONTENTS, Fault, HEAP_POINTER, Kind, LENGTH, TYPE_COUNT, TYPE_SIZE, TYPES};
+use wasmi::core::{Pages, TrapCode};
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, StackLimits, Store, Val};
+
+use crate::console::Console;
+use crate::interpreter::{MAX_DEPTH, RuntimeError, format_real, int, real};
+use crate::natives;
+use crate::value::{Heap, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// Bytes of a page of memory
+const PAGE: u32 = 64 * 1024;
+
+/// Data of the store
+struct Host {
+    console: Console,
+    /// Why the program stopped, the trap only tells that it did
+    failure: Option<RuntimeError>,
+}
+
+fn malformed<T>(reason: &str) -> Result<T, RuntimeError> {
+    Err(RuntimeError::Malformed(reason.to_owned()))
+}
+
+/// References stored as the bits of `i64`s
+fn address(bits: i64) -> Result<u32, RuntimeError> {
+    u32::try_from(bits).or_else(|_| malformed("Expected an address"))
+}
+
+fn is_object(kind: Kind) -> bool {
+    matches!(kind, Kind::String | Kind::Record | Kind::Array)
+}
+
+/// Types of the contents of an object
+#[derive(Debug, Clone, Copy)]
+enum Contents {
+    Elements(TypeId),
+    /// Address of their `TypeId`s
+    Fields(u32),
+}
+
+/// Reals are compared like `Value`s are
+#[expect(clippy::float_cmp, reason = "It is how reals compare")]
+fn reals_equal(lhs: i64, rhs: i64) -> bool {
+    f64::from_bits(lhs.cast_unsigned()) == f64::from_bits(rhs.cast_unsigned())
+}
+
+/// Reading the module's memory
+#[derive(Clone, Copy)]
+struct View<'a>(&'a [u8]);
+
+impl<'a> View<'a> {
+    fn bytes(self, address: u32, length: u32) -> Result<&'a [u8], RuntimeError> {
+        let start = address as usize;
+        self.0
+            .get(start..start + length as usize)
+            .map_or_else(|| malformed("Address out of memory"), Ok)
+    }
+
+    fn word(self, address: u32) -> Result<u32, RuntimeError> {
+        let bytes = self.bytes(address, 4)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("Four bytes are taken"),
+        ))
+    }
+
+    fn int(self, address: u32) -> Result<i64, RuntimeError> {
+        let bytes = self.bytes(address, 8)?;
+        Ok(i64::from_le_bytes(
+            bytes.try_into().expect("Eight bytes are taken"),
+        ))
+    }
+
+    /// Kind of the type and the two words after it
+    fn type_of(self, type_id: u32) -> Result<(Kind, u32, u32), RuntimeError> {
+        if type_id >= self.word(TYPE_COUNT)? {
+            return malformed("Unknown type");
+        }
+        let description = TYPES + TYPE_SIZE * type_id;
+        let kind = Kind::from_code(self.word(description)?)
+            .map_or_else(|| malformed("Unknown kind of type"), Ok)?;
+        Ok((
+            kind,
+            self.word(description + 4)?,
+            self.word(description + 8)?,
+        ))
+    }
+
+    /// Number of elements of an array or fields of a record and their types
+    fn contents(self, object: u32) -> Result<(u32, Contents), RuntimeError> {
+        let (kind, first, second) = self.type_of(self.word(object)?)?;
+        match kind {
+            Kind::Array => Ok((
+                self.word(object + LENGTH)?,
+                Contents::Elements(TypeId(first)),
+            )),
+            Kind::Record => Ok((first, Contents::Fields(second))),
+            Kind::Integer | Kind::Real | Kind::Boolean | Kind::String | Kind::Enum => {
+                malformed("Expected an array or a record")
+            }
+        }
+    }
+
+    /// Type of the `index`th element or field
+    fn content_type(self, contents: Contents, index: u32) -> Result<u32, RuntimeError> {
+        match contents {
+            Contents::Elements(type_id) => Ok(type_id.0),
+            Contents::Fields(types) => self.word(types + 4 * index),
+        }
+    }
+
+    fn string(self, object: u32) -> Result<&'a str, RuntimeError> {
+        if self.word(object)? != TypeId::STRING.0 {
+            return malformed("Expected a string");
+        }
+        let bytes = self.bytes(object + CONTENTS, self.word(object + LENGTH)?)?;
+        str::from_utf8(bytes).or_else(|_| malformed("Strings are UTF-8"))
+    }
+
+    /// Like `Machine::format` of the `vm`
+    fn format(self, bits: i64, type_id: u32, out: &mut String) -> Result<(), RuntimeError> {
+        let (kind, first, second) = self.type_of(type_id)?;
+        match kind {
+            Kind::Integer => out.push_str(&bits.to_string()),
+            Kind::Real => out.push_str(&format_real(f64::from_bits(bits.cast_unsigned()))),
+            Kind::Boolean => match bits {
+                0 => out.push_str("false"),
+                1 => out.push_str("true"),
+                _ => return malformed("Expected a boolean"),
+            },
+            Kind::String => out.push_str(self.string(address(bits)?)?),
+            Kind::Enum => {
+                let index = u32::try_from(bits)
+                    .ok()
+                    .filter(|&index| index < first)
+                    .map_or_else(
+                        || malformed(&format!("{bits} is not a variant of the enum")),
+                        Ok,
+                    )?;
+                out.push_str(self.string(self.word(second + 4 * index)?)?);
+            }
+            Kind::Array | Kind::Record => {
+                let object = address(bits)?;
+                let (count, contents) = self.contents(object)?;
+                let (open, close) = if kind == Kind::Array {
+                    ('[', ']')
+                } else {
+                    ('{', '}')
+                };
+                out.push(open);
+                for index in 0..count {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    let value = self.int(object + CONTENTS + 8 * index)?;
+                    self.format(value, self.content_type(contents, index)?, out)?;
+                }
+                out.push(close);
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `Heap::deep_eq`, pairs in `assumed` are being compared already
+    fn equals(
+        self,
+        lhs: u32,
+        rhs: u32,
+        assumed: &mut HashSet<(u32, u32)>,
+    ) -> Result<bool, RuntimeError> {
+        if lhs == rhs || !assumed.insert((lhs, rhs)) {
+            return Ok(true);
+        }
+        if self.word(lhs)? == TypeId::STRING.0 {
+            return Ok(self.string(lhs)? == self.string(rhs)?);
+        }
+        let ((count, contents), (other_count, _)) = (self.contents(lhs)?, self.contents(rhs)?);
+        if count != other_count {
+            return Ok(false);
+        }
+        for index in 0..count {
+            let offset = CONTENTS + 8 * index;
+            let (first, second) = (self.int(lhs + offset)?, self.int(rhs + offset)?);
+            let equal = match self.type_of(self.content_type(contents, index)?)?.0 {
+                Kind::Real => reals_equal(first, second),
+                Kind::Integer | Kind::Boolean | Kind::Enum => first == second,
+                Kind::String | Kind::Record | Kind::Array => {
+                    self.equals(address(first)?, address(second)?, assumed)?
+                }
+            };
+            if !equal {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<wasmi::Memory, RuntimeError> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .map_or_else(|| malformed("The module exports no memory"), Ok)
+}
+
+fn view<'a>(caller: &'a Caller<'_, Host>) -> Result<View<'a>, RuntimeError> {
+    Ok(View(memory(caller)?.data(caller)))
+}
+
+fn set_word(caller: &mut Caller<'_, Host>, address: u32, value: u32) -> Result<(), RuntimeError> {
+    set_bytes(caller, address, &value.to_le_bytes())
+}
+
+fn set_bytes(
+    caller: &mut Caller<'_, Host>,
+    address: u32,
+    bytes: &[u8],
+) -> Result<(), RuntimeError> {
+    let start = address as usize;
+    memory(caller)?
+        .data_mut(caller)
+        .get_mut(start..start + bytes.len())
+        .map_or_else(|| malformed("Address out of memory"), Ok)?
+        .copy_from_slice(bytes);
+    Ok(())
+}
+
+/// An object of `size` bytes of the type, zeroed, growing the memory when it is full
+fn alloc(caller: &mut Caller<'_, Host>, type_id: u32, size: u32) -> Result<u32, RuntimeError> {
+    let out_of_memory = || RuntimeError::Wasm("Out of memory".to_owned());
+    let memory = memory(caller)?;
+    let object = view(caller)?.word(HEAP_POINTER)?;
+    let end = size
+        .checked_next_multiple_of(8)
+        .and_then(|size| object.checked_add(size))
+        .ok_or_else(out_of_memory)?;
+    let available = u32::try_from(memory.data(&*caller).len()).unwrap_or(u32::MAX);
+    if end > available {
+        let pages = Pages::new((end - available).div_ceil(PAGE)).ok_or_else(out_of_memory)?;
+        let _: Pages = memory
+            .grow(&mut *caller, pages)
+            .map_err(|e| RuntimeError::Wasm(e.to_string()))?;
+    }
+    set_word(caller, HEAP_POINTER, end)?;
+    set_word(caller, object, type_id)?;
+    Ok(object)
+}
+
+fn alloc_array(
+    caller: &mut Caller<'_, Host>,
+    type_id: u32,
+    length: u32,
+) -> Result<u32, RuntimeError> {
+    let size = length
+        .checked_mul(8)
+        .and_then(|size| size.checked_add(CONTENTS))
+        .ok_or_else(|| RuntimeError::Wasm("Out of memory".to_owned()))?;
+    let array = alloc(caller, type_id, size)?;
+    set_word(caller, array + LENGTH, length)?;
+    Ok(array)
+}
+
+fn alloc_string(caller: &mut Caller<'_, Host>, string: &str) -> Result<u32, RuntimeError> {
+    let length = u32::try_from(string.len()).or_else(|_| malformed("String is too long"))?;
+    let object = alloc(caller, TypeId::STRING.0, CONTENTS + length)?;
+    set_word(caller, object + LENGTH, length)?;
+    set_bytes(caller, object + CONTENTS, string.as_bytes())?;
+    Ok(object)
+}
+
+/// Like `Machine::default_value` of the `vm`, records of the types in `allocating` are null.
+/// Zeros are the default numbers, booleans and variants.
+fn default_value(
+    caller: &mut Caller<'_, Host>,
+    type_id: u32,
+    allocating: &mut Vec<u32>,
+) -> Result<i64, RuntimeError> {
+    let object = match view(caller)?.type_of(type_id)?.0 {
+        Kind::Integer | Kind::Real | Kind::Boolean | Kind::Enum => return Ok(0),
+        Kind::String => alloc_string(caller, "")?,
+        Kind::Record if allocating.contains(&type_id) => 0,
+        Kind::Record => new_record(caller, type_id, allocating)?,
+        Kind::Array => new_array(caller, type_id, 0, allocating)?,
+    };
+    Ok(object.into())
+}
+
+/// A record of the type with the default fields
+fn new_record(
+    caller: &mut Caller<'_, Host>,
+    type_id: u32,
+    allocating: &mut Vec<u32>,
+) -> Result<u32, RuntimeError> {
+    let (_, fields, types) = view(caller)?.type_of(type_id)?;
+    let record = alloc(caller, type_id, CONTENTS + 8 * fields)?;
+    allocating.push(type_id);
+    for index in 0..fields {
+        let field = view(caller)?.content_type(Contents::Fields(types), index)?;
+        let value = default_value(caller, field, allocating)?;
+        set_bytes(caller, record + CONTENTS + 8 * index, &value.to_le_bytes())?;
+    }
+    let _: Option<u32> = allocating.pop();
+    Ok(record)
+}
+
+/// An array of the type with `length` default elements
+fn new_array(
+    caller: &mut Caller<'_, Host>,
+    type_id: u32,
+    length: u32,
+    allocating: &mut Vec<u32>,
+) -> Result<u32, RuntimeError> {
+    let element = view(caller)?.type_of(type_id)?.1;
+    let array = alloc_array(caller, type_id, length)?;
+    for index in 0..length {
+        let value = default_value(caller, element, allocating)?;
+        set_bytes(caller, array + CONTENTS + 8 * index, &value.to_le_bytes())?;
+    }
+    Ok(array)
+}
+
+/// Like `Heap::deep_copy`, `copies` maps copied objects to their copies
+fn copy(
+    caller: &mut Caller<'_, Host>,
+    object: u32,
+    copies: &mut HashMap<u32, u32>,
+) -> Result<u32, RuntimeError> {
+    if let Some(&copy) = copies.get(&object) {
+        return Ok(copy);
+    }
+    let type_id = view(caller)?.word(object)?;
+    let kind = view(caller)?.type_of(type_id)?.0;
+    let count = match kind {
+        Kind::String => return Ok(object),
+        Kind::Array => view(caller)?.word(object + LENGTH)?,
+        Kind::Record => view(caller)?.type_of(type_id)?.1,
+        Kind::Integer | Kind::Real | Kind::Boolean | Kind::Enum => {
+            return malformed("Expected an object");
+        }
+    };
+    // Allocated before the contents, so that cycles lead to it
+    let target = if kind == Kind::Array {
+        alloc_array(caller, type_id, count)?
+    } else {
+        alloc(caller, type_id, CONTENTS + 8 * count)?
+    };
+    let _: Option<u32> = copies.insert(object, target);
+    for index in 0..count {
+        let offset = CONTENTS + 8 * index;
+        let (value, kind) = {
+            let view = view(caller)?;
+            let (_, contents) = view.contents(object)?;
+            let type_id = view.content_type(contents, index)?;
+            (view.int(object + offset)?, view.type_of(type_id)?.0)
+        };
+        let value = if is_object(kind) {
+            copy(caller, address(value)?, copies)?.into()
+        } else {
+            value
+        };
+        set_bytes(caller, target + offset, &value.to_le_bytes())?;
+    }
+    Ok(target)
+}
+
+/// `sort` of the `vm`: integers by value, reals by `total_cmp`, the other elements stay
+fn sort(caller: &mut Caller<'_, Host>, array: u32) -> Result<(), RuntimeError> {
+    let (elements, kind) = {
+        let view = view(caller)?;
+        let (count, contents) = view.contents(array)?;
+        let elements = (0..count)
+            .map(|index| view.int(array + CONTENTS + 8 * index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let kind = view.type_of(view.content_type(contents, 0)?)?.0;
+        (elements, kind)
+    };
+    let mut elements = elements;
+    match kind {
+        Kind::Real => elements.sort_by(|&lhs, &rhs| {
+            f64::from_bits(lhs.cast_unsigned()).total_cmp(&f64::from_bits(rhs.cast_unsigned()))
+        }),
+        Kind::Integer | Kind::Boolean | Kind::Enum => elements.sort_unstable(),
+        Kind::String | Kind::Record | Kind::Array => return Ok(()),
+    }
+    for (index, element) in (0..).zip(elements) {
+        set_bytes(caller, array + CONTENTS + 8 * index, &element.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Calls the built-in of the `vm` on numbers
+fn native(
+    caller: &mut Caller<'_, Host>,
+    builtin: Builtin,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    natives::call(
+        builtin,
+        &mut Heap::default(),
+        &RTTI::primitives(),
+        &mut caller.data_mut().console,
+        args,
+    )
+}
+
+/// Keeps the failure for `run` to return, the trap stops the program
+fn stop(caller: &mut Caller<'_, Host>, e: RuntimeError) -> wasmi::Error {
+    caller.data_mut().failure = Some(e);
+    wasmi::Error::new("The host stopped the program")
+}
+
+/// Runs the body of a host function
+fn attempt<T>(body: impl FnOnce() -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+    body()
+}
+
+/// Defines `env.$name` as the closure, whose body returns `Result<_, RuntimeError>`
+macro_rules! host {
+    ($linker:ident, $name:expr, |$caller:ident $(, $arg:ident: $t:ty)*| $body:expr) => {
+        let _: &mut Linker<Host> = $linker
+            .func_wrap(
+                "env",
+                $name,
+                move |mut $caller: Caller<'_, Host>, $($arg: $t),*| {
+                    let result = attempt(|| $body);
+                    result.map_err(|e| stop(&mut $caller, e))
+                },
+            )
+            .map_err(|e| RuntimeError::Wasm(e.to_string()))?;
+    };
+}
+
+/// Built-ins on reals giving reals
+fn reals(linker: &mut Linker<Host>) -> Result<(), RuntimeError> {
+    for builtin in [
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Atan,
+        Builtin::Exp,
+        Builtin::Ln,
+    ] {
+        host!(linker, builtin.name(), |caller, value: f64| real(native(
+            &mut caller,
+            builtin,
+            &[Value::Real(value)]
+        )?));
+    }
+    for builtin in [Builtin::Pow, Builtin::Min, Builtin::Max] {
+        host!(linker, builtin.name(), |caller, lhs: f64, rhs: f64| real(
+            native(&mut caller, builtin, &[Value::Real(lhs), Value::Real(rhs)])?
+        ));
+    }
+    for builtin in [Builtin::Round, Builtin::ToInteger] {
+        host!(linker, builtin.name(), |caller, value: f64| int(native(
+            &mut caller,
+            builtin,
+            &[Value::Real(value)]
+        )?));
+    }
+    Ok(())
+}
+
+/// Built-ins on integers and input
+fn numbers(linker: &mut Linker<Host>) -> Result<(), RuntimeError> {
+    for builtin in [Builtin::Abs, Builtin::ToBoolean] {
+        host!(linker, builtin.name(), |caller, value: i64| int(native(
+            &mut caller,
+            builtin,
+            &[Value::Int(value)]
+        )?));
+    }
+    host!(linker, Builtin::Assert.name(), |caller, value: i64| native(
+        &mut caller,
+        Builtin::Assert,
+        &[Value::Int(value)]
+    )
+    .map(drop));
+    for builtin in [Builtin::ReadInteger, Builtin::ReadBoolean] {
+        host!(linker, builtin.name(), |caller| int(native(
+            &mut caller,
+            builtin,
+            &[]
+        )?));
+    }
+    host!(linker, Builtin::ReadReal.name(), |caller| real(native(
+        &mut caller,
+        Builtin::ReadReal,
+        &[]
+    )?));
+    Ok(())
+}
+
+/// Concatenation, comparison and the built-ins on strings
+fn strings(linker: &mut Linker<Host>) -> Result<(), RuntimeError> {
+    host!(linker, "concat", |caller, lhs: i32, rhs: i32| {
+        let view = view(&caller)?;
+        let string = [
+            view.string(lhs.cast_unsigned())?,
+            view.string(rhs.cast_unsigned())?,
+        ]
+        .concat();
+        Ok(alloc_string(&mut caller, &string)?.cast_signed())
+    });
+    host!(linker, "compare", |caller, lhs: i32, rhs: i32| {
+        let view = view(&caller)?;
+        let ordering = view
+            .string(lhs.cast_unsigned())?
+            .cmp(view.string(rhs.cast_unsigned())?);
+        Ok(ordering as i32)
+    });
+    host!(linker, Builtin::Length.name(), |caller, string: i32| {
+        let length = view(&caller)?
+            .string(string.cast_unsigned())?
+            .chars()
+            .count();
+        Ok(i64::try_from(length).expect("Strings fit into memory"))
+    });
+    host!(
+        linker,
+        Builtin::CharAt.name(),
+        |caller, string: i32, index: i64| {
+            let string = view(&caller)?.string(string.cast_unsigned())?;
+            let found = usize::try_from(index)
+                .ok()
+                .and_then(|index| index.checked_sub(1))
+                .and_then(|offset| string.chars().nth(offset));
+            let Some(found) = found else {
+                return Err(RuntimeError::IndexOutOfBounds {
+                    index,
+                    length: string.chars().count(),
+                });
+            };
+            Ok(alloc_string(&mut caller, &found.to_string())?.cast_signed())
+        }
+    );
+    Ok(())
+}
+
+/// Allocation, output, failures and the built-ins on objects
+fn objects(linker: &mut Linker<Host>) -> Result<(), RuntimeError> {
+    host!(linker, "fail", |caller,
+                           fault: i32,
+                           first: i64,
+                           second: i64| {
+        Err::<(), _>(match Fault::from_code(fault) {
+            Some(Fault::IntegerOverflow) => RuntimeError::IntegerOverflow,
+            Some(Fault::DivisionByZero) => RuntimeError::DivisionByZero,
+            Some(Fault::IndexOutOfBounds) => RuntimeError::IndexOutOfBounds {
+                index: first,
+                length: usize::try_from(second).unwrap_or_default(),
+            },
+            Some(Fault::Panic) => RuntimeError::Panic {
+                code: first.cast_unsigned(),
+            },
+            None => RuntimeError::Malformed(format!("Unknown fault {fault}")),
+        })
+    });
+    host!(linker, "record", |caller, type_id: i32| {
+        Ok(new_record(&mut caller, type_id.cast_unsigned(), &mut Vec::new())?.cast_signed())
+    });
+    host!(linker, "array", |caller, type_id: i32, length: i64| {
+        let length = u32::try_from(length).or_else(|_| malformed("Invalid array size"))?;
+        let array = new_array(
+            &mut caller,
+            type_id.cast_unsigned(),
+            length,
+            &mut Vec::new(),
+        )?;
+        Ok(array.cast_signed())
+    });
+    host!(linker, "print", |caller, bits: i64, type_id: i32| {
+        let mut line = String::new();
+        view(&caller)?.format(bits, type_id.cast_unsigned(), &mut line)?;
+        writeln!(caller.data_mut().console.output, "{line}")?;
+        Ok(())
+    });
+    host!(
+        linker,
+        Builtin::Equals.name(),
+        |caller, lhs: i32, rhs: i32| {
+            let equal = view(&caller)?.equals(
+                lhs.cast_unsigned(),
+                rhs.cast_unsigned(),
+                &mut HashSet::new(),
+            )?;
+            Ok(i64::from(equal))
+        }
+    );
+    host!(
+        linker,
+        Builtin::Fill.name(),
+        |caller, array: i32, bits: i64| {
+            let array = array.cast_unsigned();
+            let length = view(&caller)?.word(array + LENGTH)?;
+            for index in 0..length {
+                set_bytes(
+                    &mut caller,
+                    array + CONTENTS + 8 * index,
+                    &bits.to_le_bytes(),
+                )?;
+            }
+            Ok(())
+        }
+    );
+    host!(linker, Builtin::Copy.name(), |caller, object: i32| {
+        Ok(copy(&mut caller, object.cast_unsigned(), &mut HashMap::new())?.cast_signed())
+    });
+    host!(linker, Builtin::Sort.name(), |caller, array: i32| sort(
+        &mut caller,
+        array.cast_unsigned()
+    ));
+    Ok(())
+}
+
+/// Runs `main` of `binary`, a module of `compiler::wasm`, reading the input of `read_*`
+/// built-ins from `input` and printing to `output`. Fails like the `vm` does, with
+/// `RuntimeError::Wasm` when the module can't be run.
+pub fn run(
+    binary: &[u8],
+    input: impl BufRead + 'static,
+    output: impl Write + 'static,
+) -> Result<(), RuntimeError> {
+    let wasm = |e: wasmi::Error| RuntimeError::Wasm(e.to_string());
+    let limits = StackLimits::new(1024, 1 << 24, MAX_DEPTH)
+        .map_err(|e| RuntimeError::Wasm(e.to_string()))?;
+    let engine = Engine::new(Config::default().set_stack_limits(limits));
+    let module = Module::new(&engine, binary).map_err(wasm)?;
+    let console = Console {
+        input: Box::new(input),
+        output: Box::new(output),
+    };
+    let mut store = Store::new(
+        &engine,
+        Host {
+            console,
+            failure: None,
+        },
+    );
+    let mut linker = Linker::new(&engine);
+    reals(&mut linker)?;
+    numbers(&mut linker)?;
+    objects(&mut linker)?;
+    strings(&mut linker)?;
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(wasm)?;
+    let main = instance
+        .get_func(&store, "main")
+        .ok_or_else(|| RuntimeError::Wasm("The module exports no `main`".to_owned()))?;
+    let mut results = vec![Val::I64(0); main.ty(&store).results().len()];
+    let outcome = main.call(&mut store, &[], &mut results);
+    store.data_mut().console.output.flush()?;
+    outcome.map_err(|e| {
+        store.data_mut().failure.take().unwrap_or_else(|| {
+            if e.as_trap_code() == Some(TrapCode::StackOverflow) {
+                RuntimeError::StackOverflow
+            } else {
+                wasm(e)
+            }
+        })
+    })
+}
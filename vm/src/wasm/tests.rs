@@ -0,0 +1,125 @@
+use core::mem;
+use std::io;
+
+use super::*;
+use crate::Vm;
+use crate::console::Capture;
+use crate::test_support::{built, built_source, interpreted};
+
+/// Runs `binary` on `input`, giving the output and the failure
+fn execute(binary: &[u8], input: &str) -> (String, Result<(), RuntimeError>) {
+    let output = Capture::default();
+    let result = run(binary, io::Cursor::new(input.to_owned()), output.clone());
+    let output = String::from_utf8(output.contents()).expect("Output is UTF-8");
+    (output, result)
+}
+
+/// Runs `source` on WebAssembly and on the stack machine, which have to print the same
+/// and fail the same way, with the output and the failure on WebAssembly
+fn failing(source: &str, input: &str) -> (String, RuntimeError) {
+    let (module, routines) = built_source(source);
+    let binary = compiler::wasm::compile(&module, &routines)
+        .expect("There is a main")
+        .binary();
+    let (output, result) = execute(&binary, input);
+    let error = result.expect_err("Fails on WebAssembly");
+
+    let mut vm = Vm::new();
+    vm.load(module).expect("Module is well-formed");
+    let interpreted = Capture::default();
+    vm.set_output(interpreted.clone());
+    vm.set_input(io::Cursor::new(input.to_owned()));
+    let expected = vm.run().expect_err("Fails on the stack machine too");
+    assert_eq!(
+        String::from_utf8(interpreted.contents()).expect("Output is UTF-8"),
+        output
+    );
+    assert_eq!(
+        mem::discriminant(&error),
+        mem::discriminant(&expected),
+        "{error} instead of {expected}"
+    );
+    (output, error)
+}
+
+#[test]
+fn bad_input() {
+    let (output, error) = failing(
+        "routine main() is print read_integer(); print read_integer(); end;",
+        "  100\nnope",
+    );
+    assert_eq!(output, "100\n");
+    assert!(matches!(error, RuntimeError::Input(_)), "{error:?}");
+}
+
+#[test]
+fn out_of_bounds() {
+    let (output, error) = failing(
+        "routine main() is var a : array [3] integer; a[3] := 7; print a[3]; print a[4]; end;",
+        "",
+    );
+    assert_eq!(output, "7\n");
+    assert_eq!(error.to_string(), "Index 4 is out of bounds 1..=3");
+
+    let (output, error) = failing(
+        r#"routine main() is var s is "Hello, " + "wörld"; print s[12]; print s[0]; end;"#,
+        "",
+    );
+    assert_eq!(output, "d\n");
+    assert_eq!(error.to_string(), "Index 0 is out of bounds 1..=12");
+}
+
+#[test]
+fn integer_overflow() {
+    let (output, error) = failing(
+        "routine main() is var max is 9223372036854775807; print -max; print max + 1; end;",
+        "",
+    );
+    assert_eq!(output, "-9223372036854775807\n");
+    assert!(matches!(error, RuntimeError::IntegerOverflow));
+}
+
+#[test]
+fn division_by_zero_and_failed_assertions() {
+    let (_, error) = failing("routine main() is var z is 0; print 7 % z; end;", "");
+    assert!(matches!(error, RuntimeError::DivisionByZero));
+    let (_, error) = failing("routine main() is assert(1 > 2); end;", "");
+    assert!(matches!(error, RuntimeError::AssertionFailed), "{error:?}");
+}
+
+/// Not a tail call, which would loop forever
+#[test]
+fn stack_overflow() {
+    let (_, error) = failing(
+        "routine deep(n : integer) : integer => deep(n + 1) + 1;
+         routine main() is print deep(0); end;",
+        "",
+    );
+    assert!(matches!(error, RuntimeError::StackOverflow), "{error:?}");
+}
+
+#[test]
+fn not_a_module() {
+    let result = run(b"\0asm", io::empty(), io::sink());
+    assert!(matches!(result, Err(RuntimeError::Wasm(_))));
+}
+
+/// The programs of `tests/run` print the same on WebAssembly as on the stack machine
+#[test]
+fn corpus_runs_like_the_interpreter() {
+    for corpus::Program {
+        name,
+        source,
+        input,
+        ..
+    } in corpus::programs()
+    {
+        let (module, routines) = built(&source);
+        let binary = compiler::wasm::compile(&module, &routines)
+            .expect("There is a main")
+            .binary();
+        let (output, result) = execute(&binary, &input);
+        assert!(result.is_ok(), "{name}: {result:?}");
+        assert_eq!(output, interpreted(module, &input), "{name}");
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
 #[derive(Parser)]
@@ -11,6 +13,29 @@ use clap::Parser;
 pub(crate) enum Task {
     /// Update test cases listed in lexer src based on tests/ dir content
     UpdateLexerTests,
+    /// Run programs from tests/src and compare their output with tests/run,
+    /// feeding them tests/input as stdin, at every optimization level on both machines
+    /// and compiled to C and WebAssembly, and to x86-64 on Linux.
+    /// With the `jit` feature, also with every routine compiled by the JIT.
+    RunTests {
+        /// Only run them on the stack machine, comparing the output at every optimization level
+        /// with the one at -O0, and list how many times each peephole rule applied
+        #[arg(long)]
+        levels: bool,
+    },
+    /// Compare instructions executed and wall time of the stack and register machines
+    /// on each program of run-tests and in total
+    Bench,
+    /// Run a program of run-tests at -O2 on the stack machine and list the calls, instructions
+    /// and time of its routines, the trips of its loops and the objects it allocated
+    Profile {
+        /// Name of the program in tests/src, without `.i`
+        name: String,
+        /// Write the stacks of calls with the instructions they ran to this file,
+        /// in the collapsed format of flame graph tools
+        #[arg(long)]
+        collapsed: Option<PathBuf>,
+    },
 }
 
 impl Task {
@@ -1,12 +1,22 @@
 use core::fmt;
+use core::time::Duration;
 use std::{
     collections::HashSet,
-    env, fs,
+    env,
+    fs::{self, File},
+    io::{self, BufReader},
     path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
 };
 
 use anyhow::{Context as _, Error, anyhow, ensure};
+use compiler::OptLevel;
+use compiler::bytecode::linker::{link, link_ir, link_registers};
+use compiler::modules::{self, SearchPath};
+use compiler::optimizer::Statistics;
 use culpa::throws;
+use vm::{Vm, console::Capture};
 
 #[derive(Debug)]
 struct TestDirContents {
@@ -151,6 +161,385 @@ fn update_lexer_tests() {
     fs::write(&path, s).with_context(|| format!("Failed to write back to {}", path.display()))?
 }
 
+/// Machine of the `vm` crate running the programs, native code built with `cc`
+/// from C of `compiler::cgen` or assembly of `compiler::x86_64`, or the module of
+/// `compiler::wasm` run by `vm::wasm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Stack,
+    Registers,
+    /// The register machine compiling every routine to native code, needs the `jit` feature
+    Jit,
+    C,
+    X86_64,
+    Wasm,
+}
+
+struct Run {
+    output: String,
+    /// Instructions run by the machine, 0 for native code and WebAssembly
+    executed: u64,
+    elapsed: Duration,
+    /// Flat report and collapsed stacks of `vm::profile::Profile`, when profiling
+    profile: Option<(String, String)>,
+    /// Of the peephole optimizer over the bytecode of all the modules
+    statistics: Statistics,
+}
+
+/// Compiles `source` with the modules it imports at `opt_level` for `backend` and runs it,
+/// `profiling` on the stack machine
+#[throws]
+fn run_program(
+    source: &Path,
+    input: Option<&Path>,
+    opt_level: OptLevel,
+    backend: Backend,
+    profiling: bool,
+) -> Run {
+    let options = compiler::Options {
+        opt_level,
+        ..compiler::Options::default()
+    };
+    let mut units = Vec::new();
+    let mut registers = Vec::new();
+    let mut routines = Vec::new();
+    let mut checked = Vec::new();
+    let mut statistics = Statistics::default();
+    for module in modules::load(source, &SearchPath::default())? {
+        let program = compiler::check_module(module.program, &module.name, &checked, &options)
+            .with_context(|| format!("Failed to check {}", module.path.display()))?;
+        let mut unit = compiler::compile(&program);
+        statistics.merge(&compiler::optimize(&mut unit, &options));
+        units.push(unit);
+        if matches!(backend, Backend::Registers | Backend::Jit) {
+            let mut routines = compiler::ir::build(&program);
+            let _inlined = compiler::inline(&mut routines, &options);
+            registers.push(compiler::lower_registers(routines, &options));
+        }
+        if matches!(backend, Backend::C | Backend::X86_64 | Backend::Wasm) {
+            let mut functions = compiler::ir::build(&program);
+            let _inlined = compiler::inline(&mut functions, &options);
+            if opt_level >= OptLevel::O2 {
+                functions
+                    .values_mut()
+                    .for_each(compiler::ir::passes::optimize);
+            }
+            routines.push(functions);
+        }
+        checked.push(program);
+    }
+    if backend == Backend::Wasm {
+        let routines = link_ir(&units, routines)?;
+        let binary = compiler::wasm::compile(&link(units)?, &routines)?.binary();
+        return Run {
+            statistics,
+            ..run_wasm(&binary, input)?
+        };
+    }
+    if matches!(backend, Backend::C | Backend::X86_64) {
+        let routines = link_ir(&units, routines)?;
+        let program = link(units)?;
+        let dir = env::temp_dir().join(format!("xtask-{}", std::process::id()));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let executable = dir.join("program");
+        if backend == Backend::C {
+            let source = compiler::cgen::translate(&program, &routines)?;
+            build_c(&source, &dir, &executable)?;
+        } else {
+            let assembly = compiler::x86_64::assemble(&program, &routines)?;
+            compiler::x86_64::build_executable(&assembly, &executable)?;
+        }
+        let run = run_native(&executable, input);
+        fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+        return Run { statistics, ..run? };
+    }
+
+    let mut vm = Vm::new();
+    match input {
+        Some(input) => vm.set_input(BufReader::new(
+            File::open(input).with_context(|| format!("Failed to open {}", input.display()))?,
+        )),
+        None => vm.set_input(io::empty()),
+    }
+    let output = Capture::default();
+    vm.set_output(output.clone());
+    let code = match backend {
+        Backend::Stack | Backend::C | Backend::X86_64 | Backend::Wasm => None,
+        Backend::Registers | Backend::Jit => Some(link_registers(&units, registers)?),
+    };
+    vm.load(link(units)?)?;
+    if let Some(code) = code {
+        vm.load_registers(code)?;
+    }
+    #[cfg(feature = "jit")]
+    if backend == Backend::Jit {
+        vm.set_jit_threshold(0);
+    }
+    vm.set_profiling(profiling);
+    let start = Instant::now();
+    vm.run()?;
+    Run {
+        elapsed: start.elapsed(),
+        executed: vm.executed(),
+        output: String::from_utf8(output.contents()).context("Output is not UTF-8")?,
+        profile: vm
+            .profile()
+            .map(|profile| (profile.report(), profile.collapsed())),
+        statistics,
+    }
+}
+
+/// Runs the WebAssembly module `binary` with `vm::wasm`
+#[throws]
+fn run_wasm(binary: &[u8], input: Option<&Path>) -> Run {
+    let output = Capture::default();
+    let start = Instant::now();
+    match input {
+        Some(input) => vm::wasm::run(
+            binary,
+            BufReader::new(
+                File::open(input).with_context(|| format!("Failed to open {}", input.display()))?,
+            ),
+            output.clone(),
+        ),
+        None => vm::wasm::run(binary, io::empty(), output.clone()),
+    }?;
+    Run {
+        elapsed: start.elapsed(),
+        executed: 0,
+        output: String::from_utf8(output.contents()).context("Output is not UTF-8")?,
+        profile: None,
+        statistics: Statistics::default(),
+    }
+}
+
+/// Builds `executable` from the C translation of a program with `cc`
+#[throws]
+fn build_c(source: &str, dir: &Path, executable: &Path) {
+    let c = dir.join("program.c");
+    fs::write(&c, source).with_context(|| format!("Failed to write {}", c.display()))?;
+    let compiled = Command::new("cc")
+        .args(["-std=c11", "-O2", "-o"])
+        .arg(executable)
+        .arg(&c)
+        .arg("-lm")
+        .output()
+        .context("Failed to run cc")?;
+    ensure!(
+        compiled.status.success(),
+        "cc failed:\n{}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+}
+
+#[throws]
+fn run_native(executable: &Path, input: Option<&Path>) -> Run {
+    let stdin = match input {
+        Some(input) => Stdio::from(
+            File::open(input).with_context(|| format!("Failed to open {}", input.display()))?,
+        ),
+        None => Stdio::null(),
+    };
+    let start = Instant::now();
+    let output = Command::new(executable)
+        .stdin(stdin)
+        .output()
+        .context("Failed to run the compiled program")?;
+    let elapsed = start.elapsed();
+    ensure!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Run {
+        elapsed,
+        executed: 0,
+        output: String::from_utf8(output.stdout).context("Output is not UTF-8")?,
+        profile: None,
+        statistics: Statistics::default(),
+    }
+}
+
+/// Names of the programs with an expected output, sorted
+#[throws]
+fn run_tests_list() -> (TestDirContents, Vec<String>) {
+    let expected =
+        list_tests(tests_dir()?.join("run")).context("Failed to get a list of run tests")?;
+    debug_assert_eq!(expected.extension, "stdout");
+    let mut names: Vec<_> = expected.names.iter().cloned().collect();
+    names.sort();
+    (expected, names)
+}
+
+#[throws]
+fn run_tests() {
+    let (expected, names) = run_tests_list()?;
+
+    let mut failed = Vec::new();
+    for name in names {
+        let source = tests_dir()?.join("src").join(format!("{name}.i"));
+        let input = tests_dir()?.join("input").join(format!("{name}.txt"));
+        let expected_output = fs::read_to_string(expected.name_to_path(&name))
+            .with_context(|| format!("Failed to read expected output of {name}"))?;
+        // Every optimization level and backend has to give the same output
+        let native = cfg!(all(target_arch = "x86_64", target_os = "linux"));
+        for backend in [
+            Backend::Stack,
+            Backend::Registers,
+            Backend::Jit,
+            Backend::C,
+            Backend::X86_64,
+            Backend::Wasm,
+        ] {
+            if backend == Backend::X86_64 && !native
+                || backend == Backend::Jit && !cfg!(feature = "jit")
+            {
+                continue;
+            }
+            for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                let case = format!("{name} -{opt_level:?} {backend:?}");
+                match run_program(
+                    &source,
+                    input.is_file().then_some(input.as_path()),
+                    opt_level,
+                    backend,
+                    false,
+                ) {
+                    Ok(run) if run.output == expected_output => println!("ok {case}"),
+                    Ok(run) => {
+                        println!("FAILED {case}, output:\n{}", run.output);
+                        failed.push(case);
+                    }
+                    Err(e) => {
+                        println!("FAILED {case}: {e:#}");
+                        failed.push(case);
+                    }
+                }
+            }
+        }
+    }
+    ensure!(failed.is_empty(), "Failed programs: {failed:?}");
+}
+
+/// Runs the programs of `run_tests` on the stack machine at every optimization level,
+/// each has to print what it prints at `-O0`
+#[throws]
+fn compare_levels() {
+    let (expected, names) = run_tests_list()?;
+
+    let mut failed = Vec::new();
+    let mut statistics = [OptLevel::O1, OptLevel::O2].map(|level| (level, Statistics::default()));
+    for name in names {
+        let source = tests_dir()?.join("src").join(format!("{name}.i"));
+        let input = tests_dir()?.join("input").join(format!("{name}.txt"));
+        let run = |opt_level| {
+            run_program(
+                &source,
+                input.is_file().then_some(input.as_path()),
+                opt_level,
+                Backend::Stack,
+                false,
+            )
+            .with_context(|| format!("Failed to run {name} at -{opt_level:?}"))
+        };
+        let unoptimized = run(OptLevel::O0)?;
+        let expected_output = fs::read_to_string(expected.name_to_path(&name))
+            .with_context(|| format!("Failed to read expected output of {name}"))?;
+        ensure!(
+            unoptimized.output == expected_output,
+            "{name} at -O0 prints:\n{}",
+            unoptimized.output
+        );
+        for (level, total) in &mut statistics {
+            let case = format!("{name} -{level:?}");
+            match run(*level) {
+                Ok(run) if run.output == unoptimized.output => {
+                    println!("ok {case}");
+                    total.merge(&run.statistics);
+                }
+                Ok(run) => {
+                    println!("FAILED {case}, output:\n{}", run.output);
+                    failed.push(case);
+                }
+                Err(e) => {
+                    println!("FAILED {case}: {e:#}");
+                    failed.push(case);
+                }
+            }
+        }
+    }
+    for (level, total) in statistics {
+        print!("\nRules applied at -{level:?}:\n{total}");
+    }
+    ensure!(failed.is_empty(), "Output differs from -O0: {failed:?}");
+}
+
+/// Runs the programs of `run_tests` at `-O2` on both machines,
+/// listing the instructions each one executed and how long it took
+#[throws]
+fn bench() {
+    let (_, names) = run_tests_list()?;
+    println!(
+        "{:<24} {:>12} {:>12} {:>12} {:>12}",
+        "program", "stack", "time", "registers", "time"
+    );
+    let mut total = (0, Duration::ZERO, 0, Duration::ZERO);
+    for name in names {
+        let source = tests_dir()?.join("src").join(format!("{name}.i"));
+        let input = tests_dir()?.join("input").join(format!("{name}.txt"));
+        let run = |backend| {
+            run_program(
+                &source,
+                input.is_file().then_some(input.as_path()),
+                OptLevel::O2,
+                backend,
+                false,
+            )
+            .with_context(|| format!("Failed to run {name} on the {backend:?} machine"))
+        };
+        let stack = run(Backend::Stack)?;
+        let registers = run(Backend::Registers)?;
+        ensure!(
+            stack.output == registers.output,
+            "The machines disagree on the output of {name}"
+        );
+        println!(
+            "{name:<24} {:>12} {:>12?} {:>12} {:>12?}",
+            stack.executed, stack.elapsed, registers.executed, registers.elapsed
+        );
+        total.0 += stack.executed;
+        total.1 += stack.elapsed;
+        total.2 += registers.executed;
+        total.3 += registers.elapsed;
+    }
+    println!(
+        "{:<24} {:>12} {:>12?} {:>12} {:>12?}",
+        "total", total.0, total.1, total.2, total.3
+    );
+}
+
+/// Runs the program of `run_tests` called `name` at `-O2` on the stack machine, printing
+/// its profile and writing the collapsed stacks to `collapsed`
+#[throws]
+fn profile(name: &str, collapsed: Option<&Path>) {
+    let source = tests_dir()?.join("src").join(format!("{name}.i"));
+    let input = tests_dir()?.join("input").join(format!("{name}.txt"));
+    let run = run_program(
+        &source,
+        input.is_file().then_some(input.as_path()),
+        OptLevel::O2,
+        Backend::Stack,
+        true,
+    )
+    .with_context(|| format!("Failed to run {name}"))?;
+    let (report, stacks) = run.profile.context("The machine was not profiling")?;
+    print!("{report}");
+    println!("\n{} instructions in {:?}", run.executed, run.elapsed);
+    if let Some(path) = collapsed {
+        fs::write(path, stacks).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+}
+
 mod cli;
 
 #[throws]
@@ -159,5 +548,9 @@ fn main() {
         cli::Task::UpdateLexerTests => {
             update_lexer_tests().context("Failed to update lexer test cases")?
         }
+        cli::Task::RunTests { levels: false } => run_tests()?,
+        cli::Task::RunTests { levels: true } => compare_levels()?,
+        cli::Task::Bench => bench()?,
+        cli::Task::Profile { name, collapsed } => profile(&name, collapsed.as_deref())?,
     }
 }
@@ -62,3 +62,14 @@ fn lexer_has_all_test_cases() {
         )
     }
 }
+
+#[test]
+#[throws]
+fn inputs_have_expected_outputs() {
+    let inputs =
+        list_tests(tests_dir()?.join("input")).context("Failed to get a list of inputs")?;
+    assert_eq!(inputs.extension, "txt");
+    let outputs =
+        list_tests(tests_dir()?.join("run")).context("Failed to get a list of outputs")?;
+    check_has_all_tests(&outputs, &inputs)?;
+}